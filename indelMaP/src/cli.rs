@@ -1,30 +1,119 @@
-use clap::Parser;
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::CategoryStrategy;
+use parsimony::parsimony_alignment::recoding::Recoding;
+use parsimony::parsimony_alignment::sequence_weights::SequenceWeighting;
+use parsimony::parsimony_alignment::tree_input::RootingMethod;
+use parsimony::parsimony_alignment::{DataType, ScoreRows, TieBreak};
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub(super) struct Cli {
-    /// Sequence file in fasta format
-    #[arg(short, long, value_name = "SEQ_FILE")]
-    pub(super) seq_file: PathBuf,
+    #[command(subcommand)]
+    pub(super) command: Command,
 
-    /// Tree file in newick format
-    #[arg(short, long, value_name = "TREE_FILE")]
-    pub(super) tree_file: PathBuf,
+    /// Configuration file in TOML format with the long option names of the subcommand as keys,
+    /// options given on the command line override the values in the file
+    #[arg(long, global = true, value_name = "CONFIG_FILE")]
+    pub(super) config: Option<PathBuf>,
 
-    /// Tree file in newick format
-    #[arg(short, long, value_name = "OUTPUT_MSA_FILE")]
-    pub(super) output_msa_file: Option<PathBuf>,
+    /// Increase the logging verbosity, -v for debug and -vv for trace messages
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub(super) verbose: u8,
+
+    /// Decrease the logging verbosity, -q for warnings only and -qq for errors only
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub(super) quiet: u8,
+
+    /// Write the log to this file instead of standard error
+    #[arg(long, global = true, value_name = "LOG_FILE")]
+    pub(super) log_file: Option<PathBuf>,
+}
+
+impl Cli {
+    pub(super) fn log_level(&self) -> LevelFilter {
+        match (self.verbose, self.quiet) {
+            (0, 0) => LevelFilter::Info,
+            (1, _) => LevelFilter::Debug,
+            (_, 0) => LevelFilter::Trace,
+            (_, 1) => LevelFilter::Warn,
+            (_, 2) => LevelFilter::Error,
+            _ => LevelFilter::Off,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub(super) enum Command {
+    /// Align the sequences on the tree
+    #[command(args_override_self = true)]
+    Align(AlignArgs),
+    /// Score an existing MSA on the tree
+    #[command(args_override_self = true)]
+    Score(ScoreArgs),
+    /// Reconstruct the ancestral sequences of an existing MSA on the tree
+    #[command(args_override_self = true)]
+    Ancestors(AncestorsArgs),
+    /// Add new sequences to an existing MSA without changing its columns
+    #[command(args_override_self = true)]
+    Add(AddArgs),
+    /// Align two MSAs to each other without changing the columns of either
+    #[command(args_override_self = true)]
+    Merge(MergeArgs),
+    /// Bootstrap an existing MSA by resampling its columns and report the support of the tree
+    #[command(args_override_self = true)]
+    Bootstrap(BootstrapArgs),
+    /// Align every gene of a directory or a manifest, writing an MSA per gene and a summary table.
+    /// The genes share a scoring built from all their sequences and branch lengths, the run
+    /// fails if any gene could not be aligned
+    #[command(args_override_self = true)]
+    Batch(BatchArgs),
+    /// Compare an MSA to a reference MSA with the sum-of-pairs and total-column scores
+    Compare(CompareArgs),
+    /// Check the sequences, the tree and the model and estimate the memory and time of the
+    /// alignment without aligning
+    #[command(args_override_self = true)]
+    Check(CheckArgs),
+    /// Simulate sequences with indels on the tree (not available yet)
+    Simulate,
+}
 
-    /// Sequence evolution model
-    #[arg(short, long, value_name = "MODEL", rename_all = "UPPER")]
-    pub(super) model: String,
+/// Options of the scoring that are shared between the subcommands.
+#[derive(Args, Clone)]
+pub(super) struct ModelArgs {
+    /// Named defaults for the model, the gap penalties and the branch length categories of a
+    /// kind of data, options given in the config file or on the command line override them
+    #[arg(long, value_enum, value_name = "PRESET")]
+    pub(super) preset: Option<GapPreset>,
 
-    /// Sequence evolution model parameters, e.g. alpha and beta for k80 and
-    /// f_t f_c f_a f_g r_tc r_ta r_tg r_ca r_cg r_ag for GTR (in this specific order)
+    /// Sequence evolution model, or one of the BLOSUM62, BLOSUM45 and PAM250 scoring matrices
+    /// for proteins. The +F suffix, e.g. WAG+F, uses the frequencies of the input sequences
+    #[arg(
+        short,
+        long,
+        value_name = "MODEL",
+        rename_all = "UPPER",
+        required_unless_present = "cost_matrix"
+    )]
+    pub(super) model: Option<String>,
+
+    /// Substitution cost matrix in EMBOSS or PAML format to use instead of the model costs,
+    /// the gap penalties are then absolute costs rather than multiples of the average cost
+    #[arg(long, value_name = "COST_MATRIX", conflicts_with_all = ["model", "model_params"])]
+    pub(super) cost_matrix: Option<PathBuf>,
+
+    /// Sequence evolution model parameters, e.g. alpha and beta for k80,
+    /// f_t f_c f_a f_g r_tc r_ta r_tg r_ca r_cg r_ag for GTR, [f_t f_c f_a f_g] kappa for HKY85
+    /// and [f_t f_c f_a f_g] k_tc k_ag for TN93 (in this specific order)
     #[arg(short = 'p', long, value_name = "MODEL_PARAMS", num_args = 0..)]
     pub(super) model_params: Vec<f64>,
 
+    /// Use the frequencies of the input sequences (+F) for a protein model or for GTR, HKY85 or
+    /// TN93, the DNA model parameters are then only the rates
+    #[arg(long, conflicts_with = "cost_matrix")]
+    pub(super) empirical_freqs: bool,
+
     /// Gap opening penalty
     #[arg(short = 'g', long, default_value_t = 2.5)]
     pub(super) go: f64,
@@ -33,7 +122,1736 @@ pub(super) struct Cli {
     #[arg(short = 'e', long, default_value_t = 0.5)]
     pub(super) ge: f64,
 
-    /// Number of percentile categories to use for branch length approximation
+    /// Comma-separated slopes of the gap opening and extension penalties in the branch length,
+    /// the penalties of a branch of length t are then go + OPEN_SLOPE * t and ge + EXT_SLOPE * t
+    /// instead of multiples of its average substitution cost
+    #[arg(
+        long,
+        value_name = "OPEN_SLOPE,EXT_SLOPE",
+        value_delimiter = ',',
+        num_args = 2,
+        conflicts_with = "cost_matrix"
+    )]
+    pub(super) gap_slopes: Option<Vec<f64>>,
+
+    /// Shape of the gamma distribution of the rates across sites, the costs are then derived
+    /// from the substitution probabilities averaged over the discrete gamma rate categories
+    #[arg(long, value_name = "ALPHA", conflicts_with = "cost_matrix")]
+    pub(super) gamma: Option<f64>,
+
+    /// Number of discrete gamma rate categories
+    #[arg(long, value_name = "N", default_value_t = 4, requires = "gamma")]
+    pub(super) gamma_categories: usize,
+
+    /// Score every alignment column in the cheapest of the discrete gamma rate categories
+    /// instead of with the costs averaged over the categories
+    #[arg(long, requires = "gamma")]
+    pub(super) site_rates: bool,
+
+    /// Partition file with a line `[TYPE] MODEL [PARAMS...], NAME = RANGES` per partition, e.g.
+    /// `HKY85 2.0, gene1_pos3 = 3-900\3`, the residues at the 1-based positions of the ranges
+    /// are scored with the model of the partition and the other residues with --model.
+    /// Partitions of different data types (DNA, RNA or PROT, inferred if not given) are aligned
+    /// separately and their MSAs concatenated in the order of the file, they need aligned input
+    /// whose columns are all in a partition
+    #[arg(
+        long,
+        value_name = "PARTITION_FILE",
+        conflicts_with_all = ["cost_matrix", "site_rates"]
+    )]
+    pub(super) partitions: Option<PathBuf>,
+
+    /// Directory for keeping the cost matrices computed from the substitution models between
+    /// runs, by default indelMaP in the user cache directory
+    #[arg(long, value_name = "CACHE_DIR", conflicts_with = "no_cache")]
+    pub(super) cache_dir: Option<PathBuf>,
+
+    /// Always compute the cost matrices of the substitution models instead of reading and
+    /// storing them in the cache directory
+    #[arg(long)]
+    pub(super) no_cache: bool,
+
+    /// Number of percentile categories to use for branch length approximation, 0 computes exact
+    /// costs for every distinct branch length of the tree
     #[arg(short, long, default_value_t = 4)]
     pub(super) categories: u32,
+
+    /// How to choose the branch lengths of the categories from the branch lengths of the tree
+    #[arg(long, value_enum, default_value_t = CategoryPolicy::Percentiles)]
+    pub(super) category_strategy: CategoryPolicy,
+
+    /// Comma-separated branch lengths to compute the cost matrices for instead of the percentile
+    /// categories, every branch is scored with the closest one
+    #[arg(
+        long,
+        value_name = "LENGTHS",
+        value_delimiter = ',',
+        conflicts_with_all = ["categories", "cost_matrix"]
+    )]
+    pub(super) category_breakpoints: Vec<f64>,
+
+    /// Leading and trailing gaps against the longer of two aligned profiles cost nothing
+    /// (semi-global alignment), for sequences that are fragments such as partial genes
+    #[arg(long)]
+    pub(super) free_end_gaps: bool,
+
+    /// Treatment of soft-masked (lowercase) residues: keep scores them as uppercase, ignore
+    /// leaves them out of the score and downweight scales the gap penalties against them by
+    /// the soft mask weight
+    #[arg(long, value_enum, default_value_t = SoftMaskPolicy::Keep)]
+    pub(super) soft_mask: SoftMaskPolicy,
+
+    /// Multiplier of the gap penalties against soft-masked residues with --soft-mask downweight
+    #[arg(long, value_name = "WEIGHT", default_value_t = 0.5)]
+    pub(super) soft_mask_weight: f64,
+
+    /// Weighting of the sequences when combining the profiles of two subtrees, thg scales the
+    /// costs on each side by the Thompson-Higgins-Gibson weights of its sequences so that
+    /// over-represented clades do not dominate the gap placement
+    #[arg(long, value_enum, default_value_t = SequenceWeightScheme::None)]
+    pub(super) sequence_weights: SequenceWeightScheme,
+
+    /// Gap penalty as a function of the gap length: affine charges the extension cost for every
+    /// residue after the first, log charges the opening cost plus the extension cost times the
+    /// gap log scale times the log of the length, approximately as every cell of the DP only
+    /// extends its best gap
+    #[arg(long, value_enum, default_value_t = GapFunctionOption::Affine)]
+    pub(super) gap_function: GapFunctionOption,
+
+    /// Scale of the logarithmic gap penalty with --gap-function log
+    #[arg(long, value_name = "SCALE", default_value_t = 1.0)]
+    pub(super) gap_log_scale: f64,
+
+    /// Character-state recoding applied before scoring, the output MSA keeps the original characters
+    #[arg(long, value_enum, default_value_t = RecodingScheme::None)]
+    pub(super) recoding: RecodingScheme,
+
+    /// Type of the sequences, auto infers it from the characters which can mistake short or
+    /// unusual protein sequences for DNA
+    #[arg(long, value_enum, default_value_t = DataTypeOption::Auto)]
+    pub(super) data_type: DataTypeOption,
+}
+
+impl ModelArgs {
+    /// Name of the model without the +F suffix.
+    pub(super) fn model_name(&self) -> &str {
+        let name = self.model.as_deref().unwrap_or_default();
+        name.strip_suffix("+F")
+            .or_else(|| name.strip_suffix("+f"))
+            .unwrap_or(name)
+    }
+
+    /// Whether the frequencies of the model are estimated from the sequences, with
+    /// --empirical-freqs or the +F suffix of the model name.
+    pub(super) fn empirical_freqs(&self) -> bool {
+        self.empirical_freqs
+            || self
+                .model
+                .as_deref()
+                .is_some_and(|name| name.ends_with("+F") || name.ends_with("+f"))
+    }
+}
+
+/// Options for reading the input tree that are shared between the subcommands.
+#[derive(Args)]
+pub(super) struct TreeArgs {
+    /// Where to root an unrooted input tree, i.e. one with three subtrees at the top level
+    #[arg(long, value_enum, default_value_t = RootingPolicy::Midpoint)]
+    pub(super) rooting: RootingPolicy,
+
+    /// Comma-separated ids of the sequences to reroot the tree on, they must form a clade
+    #[arg(long, value_name = "TAXON", value_delimiter = ',')]
+    pub(super) outgroup: Vec<String>,
+
+    /// Drop the leaves of the tree without a sequence and the sequences that are not in the
+    /// tree instead of failing, the dropped taxa are reported in the log
+    #[arg(long)]
+    pub(super) prune_missing: bool,
+
+    /// Ignore the case when pairing the leaf labels of the tree with the sequence ids
+    #[arg(long)]
+    pub(super) ignore_case: bool,
+
+    /// Ignore all whitespace when pairing the leaf labels with the sequence ids, the whole
+    /// FASTA header is used instead of the id up to the first space
+    #[arg(long)]
+    pub(super) ignore_whitespace: bool,
+
+    /// Cut the leaf labels at their first space, like the sequence ids
+    #[arg(long)]
+    pub(super) truncate_names: bool,
+
+    /// Regular expression whose matches are removed from the leaf labels and the sequence ids
+    /// before pairing them, e.g. '^[a-z]+\|' for database prefixes
+    #[arg(long, value_name = "REGEX")]
+    pub(super) strip_names: Option<String>,
+}
+
+#[derive(Args)]
+pub(super) struct BatchArgs {
+    /// Directory with a fasta file per gene, all the files with a .fasta, .fa, .fas, .fna or .faa
+    /// extension are aligned
+    #[arg(long, value_name = "SEQ_DIR", required_unless_present = "manifest")]
+    pub(super) seq_dir: Option<PathBuf>,
+
+    /// Tab-separated file with a line per gene: its name, its fasta file and optionally its tree
+    /// file, relative paths are relative to the directory of the manifest
+    #[arg(long, value_name = "MANIFEST_FILE", conflicts_with = "seq_dir")]
+    pub(super) manifest: Option<PathBuf>,
+
+    /// Tree file in newick format for the genes without a tree of their own, a guide tree is
+    /// estimated for them if not provided
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Directory for the MSA of every gene, named after the gene, and the summary table
+    #[arg(short, long, value_name = "OUTPUT_DIR", default_value = "msas")]
+    pub(super) output_dir: PathBuf,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+
+    /// Seed for the random tie-breaking in the traceback, a random seed is drawn if not provided
+    #[arg(long, value_name = "SEED")]
+    pub(super) seed: Option<u64>,
+
+    /// Number of genes aligned in parallel, 0 uses all available cores
+    #[arg(long, value_name = "THREADS", default_value_t = 1)]
+    pub(super) threads: usize,
+}
+
+#[derive(Args)]
+pub(super) struct AlignArgs {
+    /// Sequence file in fasta format, use - to read the sequences from standard input
+    #[arg(short, long, value_name = "SEQ_FILE")]
+    pub(super) seq_file: PathBuf,
+
+    /// Tree file in newick format, if not provided a guide tree is estimated from the sequences
+    /// and written next to the output MSA
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Give the sequences with an id that is already taken a numbered suffix, e.g. A_2, instead
+    /// of failing, the original and new ids are written next to the output MSA. The leaves of a
+    /// tree file must use the new ids
+    #[arg(long)]
+    pub(super) auto_rename: bool,
+
+    /// Handling of DNA sequences that share more k-mers with the longest sequence on the
+    /// opposite strand: keep aligns them as they are without checking, fail reports them and
+    /// stops and correct aligns their reverse complement
+    #[arg(long, value_enum, default_value_t = ReverseStrandPolicy::Keep)]
+    pub(super) reverse_strand: ReverseStrandPolicy,
+
+    /// Output MSA file in fasta format, use - to write the MSA to standard output
+    #[arg(short, long, value_name = "OUTPUT_MSA_FILE")]
+    pub(super) output_msa_file: Option<PathBuf>,
+
+    /// Format of the output MSA, maf writes a single alignment block with the source
+    /// coordinates taken from sequence ids with a range such as chr1:1001-2000
+    #[arg(long, value_enum, default_value_t = MsaFormat::Fasta)]
+    pub(super) output_format: MsaFormat,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+
+    /// Seed for the random tie-breaking in the traceback, a random seed is drawn if not provided
+    #[arg(long, value_name = "SEED")]
+    pub(super) seed: Option<u64>,
+
+    /// Policy for choosing between equally scoring alignment steps, all but random are deterministic
+    #[arg(long, value_enum, default_value_t = TieBreakPolicy::Random)]
+    pub(super) tie_break: TieBreakPolicy,
+
+    /// Number of alternative co-optimal alignments to sample by repeating the traceback, each is
+    /// written to a separate file next to the output MSA
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub(super) samples: usize,
+
+    /// Write the fraction of the sampled alignments that reproduce every column of the output
+    /// MSA next to it, columns with a low confidence depend on arbitrary traceback choices
+    #[arg(long, requires = "samples")]
+    pub(super) column_confidence: bool,
+
+    /// Number of next best distinct alignments at the root to write, each is written to a
+    /// separate file next to the output MSA
+    #[arg(long, value_name = "K", default_value_t = 0)]
+    pub(super) suboptimal: usize,
+
+    /// Directory to write the MSA of the clade below every internal node to, as aligned at that
+    /// node, the files are named after the label of the node in the ancestral outputs, e.g.
+    /// N3.fasta
+    #[arg(long, value_name = "CLADE_MSA_DIR")]
+    pub(super) clade_msa_dir: Option<PathBuf>,
+
+    /// Number of alignment runs with different traceback seeds, the MSA with the lowest total
+    /// score is kept and the seeds and scores of all runs, with the kept one marked, are written
+    /// next to it; the runs share --threads
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        conflicts_with_all = ["samples", "suboptimal", "checkpoint"]
+    )]
+    pub(super) restarts: usize,
+
+    /// Number of threads for aligning independent subtrees in parallel, 0 uses all available
+    /// cores; sampling and suboptimal alignments run on a single thread
+    #[arg(
+        long,
+        value_name = "THREADS",
+        default_value_t = 1,
+        conflicts_with_all = ["samples", "suboptimal"]
+    )]
+    pub(super) threads: usize,
+
+    /// Write counters of the work done by the alignment, e.g. the DP cells filled and the tie
+    /// breaks, as tab-separated values to this file, for performance debugging
+    #[arg(long, value_name = "METRICS_FILE")]
+    pub(super) metrics: Option<PathBuf>,
+
+    /// Fill the alignment matrices by anti-diagonals with the cells of a diagonal computed in
+    /// parallel, speeds up the alignment of long sequences
+    #[arg(long)]
+    pub(super) wavefront: bool,
+
+    /// Whether to keep all the rows of the score matrices of a node, auto keeps only two rows for
+    /// the nodes with very long sequences. The directions are always kept for every cell, so
+    /// two rows cut the memory by about four fifths but it stays quadratic
+    #[arg(long, value_enum, default_value_t = ScoreRowsPolicy::Auto)]
+    pub(super) score_rows: ScoreRowsPolicy,
+
+    /// Limit on the memory of the alignment matrices of a node, e.g. 8G or 512M, larger nodes
+    /// keep only two rows of scores and the run stops with an error if that does not fit either
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub(super) max_memory: Option<usize>,
+
+    /// Only consider alignments within this many diagonals of the main diagonal, widened by the
+    /// length difference of the aligned profiles, fast for near-identical sequences
+    #[arg(long, value_name = "W")]
+    pub(super) band: Option<usize>,
+
+    /// Stop extending alignments that score more than X above the best cell of their
+    /// anti-diagonal, fast for similar sequences but the optimal alignment may be cut off
+    #[arg(long, value_name = "X", conflicts_with = "band")]
+    pub(super) x_drop: Option<f64>,
+
+    /// Align long sequences in chunks between exact matches of at least this many sites that
+    /// occur once in both aligned profiles, only the regions between these anchors are filled
+    #[arg(long, value_name = "K", conflicts_with_all = ["samples", "suboptimal"])]
+    pub(super) anchor_length: Option<usize>,
+
+    /// File of residue pairs that must be aligned, a line per pair with the id of a sequence, the
+    /// 1-based position of the residue in it and the same for the second sequence, e.g. A 12 B 10
+    #[arg(
+        long,
+        value_name = "CONSTRAINTS_FILE",
+        conflicts_with = "refine_iterations"
+    )]
+    pub(super) constraints: Option<PathBuf>,
+
+    /// FASTA alignment of some of the sequences whose columns are kept, in the A2M convention:
+    /// uppercase residues and - gaps form the fixed columns, lowercase residues and . gaps are
+    /// aligned freely with the remaining sequences
+    #[arg(
+        long,
+        value_name = "SEED_FILE",
+        conflicts_with_all = ["constraints", "refine_iterations"]
+    )]
+    pub(super) seed_alignment: Option<PathBuf>,
+
+    /// File of per-site gap penalty multipliers, a line per sequence with its id followed by a
+    /// multiplier for every residue, gaps against a site cost its multiplier times the usual cost
+    #[arg(long, value_name = "GAP_PROFILE_FILE")]
+    pub(super) gap_profile: Option<PathBuf>,
+
+    /// Scale the gap penalties of protein sequences by their windowed Kyte-Doolittle hydropathy,
+    /// making gaps cheaper in hydrophilic loops and dearer in the hydrophobic core
+    #[arg(long, conflicts_with = "gap_profile")]
+    pub(super) hydropathy_gaps: bool,
+
+    /// FASTA file of protein secondary structure strings with the ids of the sequences, e.g.
+    /// HHHCCEEE, gaps in helices and strands cost the helix or strand gap weight times the usual
+    /// cost
+    #[arg(
+        long,
+        value_name = "STRUCTURE_FILE",
+        conflicts_with_all = ["gap_profile", "hydropathy_gaps"]
+    )]
+    pub(super) secondary_structure: Option<PathBuf>,
+
+    /// Predict the secondary structure of the protein sequences with a simple Chou-Fasman
+    /// assignment and penalize gaps in helices and strands as with --secondary-structure
+    #[arg(
+        long,
+        conflicts_with_all = ["gap_profile", "hydropathy_gaps", "secondary_structure"]
+    )]
+    pub(super) predict_structure: bool,
+
+    /// Multiplier of the gap penalties inside helices
+    #[arg(long, value_name = "WEIGHT", default_value_t = 2.0)]
+    pub(super) helix_gap_weight: f64,
+
+    /// Multiplier of the gap penalties inside strands
+    #[arg(long, value_name = "WEIGHT", default_value_t = 1.5)]
+    pub(super) strand_gap_weight: f64,
+
+    /// BED-like file of regions to exclude from the scoring, e.g. low quality or repeat segments,
+    /// a line per region with the sequence id, the 0-based start and the exclusive end
+    #[arg(long, value_name = "MASK_FILE")]
+    pub(super) region_mask: Option<PathBuf>,
+
+    /// Multiplier of the gap penalties against masked regions, matches with them cost nothing
+    #[arg(
+        long,
+        value_name = "WEIGHT",
+        default_value_t = 0.5,
+        requires = "region_mask"
+    )]
+    pub(super) mask_gap_weight: f64,
+
+    /// File to which every aligned node is written as the alignment proceeds, so that an
+    /// interrupted run can be continued with --resume
+    #[arg(
+        long,
+        value_name = "CHECKPOINT_FILE",
+        conflicts_with_all = ["threads", "samples", "suboptimal"]
+    )]
+    pub(super) checkpoint: Option<PathBuf>,
+
+    /// Continue from the nodes already aligned in the checkpoint file
+    #[arg(long, requires = "checkpoint")]
+    pub(super) resume: bool,
+
+    /// Align coding DNA codon by codon so that gaps never break the reading frame, the cost
+    /// between two codons is the sum of the model costs of their nucleotides
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "samples",
+            "suboptimal",
+            "checkpoint",
+            "threads",
+            "recoding",
+            "cost_matrix",
+            "gap_profile",
+            "hydropathy_gaps",
+            "secondary_structure",
+            "predict_structure",
+            "constraints",
+            "seed_alignment",
+            "region_mask",
+            "refine_iterations",
+            "nni_rounds",
+            "restarts",
+            "column_scores",
+            "traceback_paths",
+            "dump_matrices",
+            "two_pass",
+            "reestimate_branch_lengths",
+            "normalize_gaps"
+        ]
+    )]
+    pub(super) codon: bool,
+
+    /// Align coding DNA as peptides: the sequences are translated with the standard genetic
+    /// code, aligned with the protein model and the nucleotides are threaded back through the
+    /// peptide alignment
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "codon",
+            "partitions",
+            "samples",
+            "suboptimal",
+            "checkpoint",
+            "threads",
+            "recoding",
+            "cost_matrix",
+            "gap_profile",
+            "hydropathy_gaps",
+            "secondary_structure",
+            "predict_structure",
+            "constraints",
+            "seed_alignment",
+            "region_mask",
+            "refine_iterations",
+            "nni_rounds",
+            "restarts",
+            "column_scores",
+            "traceback_paths",
+            "dump_matrices",
+            "two_pass",
+            "reestimate_branch_lengths",
+            "normalize_gaps"
+        ]
+    )]
+    pub(super) translate: bool,
+
+    /// Number of refinement iterations after the progressive alignment, each realigns every
+    /// subtree against the rest of the tree and keeps the changes that lower the total score
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub(super) refine_iterations: usize,
+
+    /// Rounds of nearest-neighbour interchange tree search before the alignment, keeping the
+    /// interchanges that lower the score, the improved tree is written next to the output MSA
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub(super) nni_rounds: usize,
+
+    /// Realign on a tree re-estimated by neighbour joining on the distances of the first MSA,
+    /// which reduces the bias of the guide tree, the new tree is written next to the output MSA
+    #[arg(
+        long,
+        conflicts_with_all = ["samples", "suboptimal", "restarts", "checkpoint"]
+    )]
+    pub(super) two_pass: bool,
+
+    /// Re-estimate the branch lengths of the tree under the substitution model from the output
+    /// MSA and its parsimony ancestral sequences, taken as observed rather than summed over as
+    /// in a full likelihood, the tree is written next to the output MSA
+    #[arg(long, conflicts_with = "cost_matrix")]
+    pub(super) reestimate_branch_lengths: bool,
+
+    /// Write the score of every MSA column, summed over the internal nodes of the tree, next to
+    /// the output MSA
+    #[arg(long)]
+    pub(super) column_scores: bool,
+
+    /// Write the path of the alignment at every internal node through the DP matrices next to
+    /// the output MSA, a line per step with its cell, matrix and cost, to see why the gaps were
+    /// placed where they are. The paths are read back from the output MSA, so they cannot be
+    /// combined with refinement or gap normalisation, which move the gaps after the DP
+    #[arg(long, conflicts_with_all = ["refine_iterations", "normalize_gaps"])]
+    pub(super) traceback_paths: bool,
+
+    /// Write the M, X and Y score matrices of the DP at an internal node next to the output MSA,
+    /// the node is numbered as in the labels of the ancestral outputs, e.g. 3 for N3
+    #[arg(long, value_name = "NODE")]
+    pub(super) dump_matrices: Option<usize>,
+
+    /// Format of the matrices written with --dump-matrices
+    #[arg(long, value_enum, default_value_t = MatrixFormat::Tsv, requires = "dump_matrices")]
+    pub(super) matrix_format: MatrixFormat,
+
+    /// Shift the gaps of the output MSA as far left as possible without changing its columns, so
+    /// that equivalent gap placements are reported the same across runs
+    #[arg(long)]
+    pub(super) normalize_gaps: bool,
+
+    /// With --normalize-gaps, also move gaps inside homopolymer runs to the left end of the run
+    #[arg(long, requires = "normalize_gaps")]
+    pub(super) homopolymer_gaps: bool,
+
+    /// Remove the columns of the output MSA in which more than this fraction of the rows have a
+    /// gap, the original index of every kept column is written next to the output MSA. Not
+    /// available with the maf output, whose coordinates need the complete rows
+    #[arg(long, value_name = "FRACTION")]
+    pub(super) trim_gap_fraction: Option<f64>,
+
+    /// Write a consensus sequence of the output MSA next to it, columns in which most rows have
+    /// a gap are left out
+    #[arg(long, value_enum)]
+    pub(super) consensus: Option<ConsensusPolicy>,
+
+    /// Fraction of the residues of a column that the most frequent one needs for the majority
+    /// consensus, columns below it get N or X
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.5)]
+    pub(super) consensus_threshold: f64,
+
+    /// Also write the consensus of the clade MSA of every internal node, named after the label of
+    /// the node in the ancestral outputs
+    #[arg(long, requires = "consensus")]
+    pub(super) node_consensus: bool,
+}
+
+#[derive(Args)]
+pub(super) struct ScoreArgs {
+    /// MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// Tree file in newick format
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+}
+
+#[derive(Args)]
+pub(super) struct AncestorsArgs {
+    /// MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// Tree file in newick format
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Output file for the ancestral sequences in fasta format, aligned to the MSA, use - to
+    /// write them to standard output
+    #[arg(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        default_value = "ancestors.fasta"
+    )]
+    pub(super) output_file: PathBuf,
+
+    /// Also write the inferred gap presence (1) or absence (0) of every internal node in every
+    /// MSA column to this file as tab-separated values
+    #[arg(long, value_name = "GAP_MATRIX_FILE")]
+    pub(super) gap_matrix: Option<PathBuf>,
+
+    /// Output file for the tree with its internal nodes labeled N1, N2, ... as in the ancestral
+    /// outputs, by default written next to the ancestral sequences, e.g.
+    /// ancestors.labeled_tree.newick
+    #[arg(long, value_name = "LABELED_TREE_FILE")]
+    pub(super) labeled_tree: Option<PathBuf>,
+
+    /// Also write the reconstructed indels, each an insertion or a deletion on the branch above
+    /// a node, to this file as tab-separated values
+    #[arg(long, value_name = "INDEL_EVENTS_FILE")]
+    pub(super) indel_events: Option<PathBuf>,
+
+    /// Print a summary of the reconstructed indels to standard output: the counts, the
+    /// distribution of the lengths and the number of indels per unit of length of every branch
+    #[arg(long)]
+    pub(super) indel_stats: bool,
+
+    /// Also write the summary of the reconstructed indels to this file as JSON
+    #[arg(long, value_name = "INDEL_STATS_FILE")]
+    pub(super) indel_stats_json: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+}
+
+#[derive(Args)]
+pub(super) struct AddArgs {
+    /// Reference MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// File with the new sequences in fasta format, use - to read them from standard input
+    #[arg(short, long, value_name = "SEQ_FILE")]
+    pub(super) seq_file: PathBuf,
+
+    /// Tree file in newick format with both the reference and the new sequences, every new
+    /// sequence is aligned to the reference MSA at the place it is attached to the tree
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Output file for the MSA with the new sequences in fasta format, use - to write it to
+    /// standard output
+    #[arg(short, long, value_name = "OUTPUT_FILE", default_value = "added.fasta")]
+    pub(super) output_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+}
+
+#[derive(Args)]
+pub(super) struct MergeArgs {
+    /// First MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// Second MSA file in fasta format
+    #[arg(short = 'b', long, value_name = "MSA_FILE")]
+    pub(super) other_msa_file: PathBuf,
+
+    /// Tree file in newick format with the sequences of both MSAs, the root of the tree must
+    /// split them into the two MSAs
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Output file for the merged MSA in fasta format, use - to write it to standard output
+    #[arg(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        default_value = "merged.fasta"
+    )]
+    pub(super) output_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum RecodingScheme {
+    /// No recoding
+    None,
+    /// Purine/pyrimidine coding for DNA
+    Ry,
+    /// Dayhoff 6-state coding for proteins
+    Dayhoff6,
+}
+
+impl From<RecodingScheme> for Recoding {
+    fn from(scheme: RecodingScheme) -> Self {
+        match scheme {
+            RecodingScheme::None => Recoding::None,
+            RecodingScheme::Ry => Recoding::RY,
+            RecodingScheme::Dayhoff6 => Recoding::Dayhoff6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum GapPreset {
+    /// WAG costs with slightly higher gap opening penalties for proteins
+    ProteinDefault,
+    /// HKY85 costs with expensive gaps for protein-coding DNA, where indels are rare
+    DnaCoding,
+    /// HKY85 costs with cheaper gaps for non-coding DNA such as introns and intergenic regions
+    DnaNoncoding,
+    /// TN93 costs with more branch length categories for ribosomal RNA genes
+    Rrna,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum GapFunctionOption {
+    /// Opening cost plus a fixed cost per extension
+    Affine,
+    /// Opening cost plus a cost growing with the log of the length
+    Log,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum SequenceWeightScheme {
+    /// Every subtree counts the same
+    None,
+    /// Tree-derived weights of Thompson, Higgins and Gibson
+    Thg,
+}
+
+impl From<SequenceWeightScheme> for SequenceWeighting {
+    fn from(scheme: SequenceWeightScheme) -> Self {
+        match scheme {
+            SequenceWeightScheme::None => SequenceWeighting::None,
+            SequenceWeightScheme::Thg => SequenceWeighting::Thg,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum DataTypeOption {
+    /// Infer the type from the sequence characters
+    Auto,
+    /// Nucleotide sequences
+    Dna,
+    /// Nucleotide sequences with U instead of T
+    Rna,
+    /// Amino acid sequences
+    Protein,
+}
+
+impl From<DataTypeOption> for DataType {
+    fn from(option: DataTypeOption) -> Self {
+        match option {
+            DataTypeOption::Auto => DataType::Auto,
+            DataTypeOption::Dna => DataType::Dna,
+            DataTypeOption::Rna => DataType::Rna,
+            DataTypeOption::Protein => DataType::Protein,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum CategoryPolicy {
+    /// Equal-frequency percentiles of the branch lengths
+    Percentiles,
+    /// Cluster means of k-means on the branch lengths, for skewed branch length distributions
+    KMeans,
+}
+
+impl From<CategoryPolicy> for CategoryStrategy {
+    fn from(policy: CategoryPolicy) -> Self {
+        match policy {
+            CategoryPolicy::Percentiles => CategoryStrategy::Percentiles,
+            CategoryPolicy::KMeans => CategoryStrategy::KMeans,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum MsaFormat {
+    Fasta,
+    /// Multiple Alignment Format of the genome browsers
+    Maf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum MatrixFormat {
+    /// Tab-separated values, a line per row
+    Tsv,
+    /// NumPy array of 64-bit floats
+    Npy,
+}
+
+impl MatrixFormat {
+    pub(super) fn extension(&self) -> &'static str {
+        match self {
+            MatrixFormat::Tsv => "tsv",
+            MatrixFormat::Npy => "npy",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum ReverseStrandPolicy {
+    /// Align the sequences as they are
+    Keep,
+    /// Stop with the list of the reversed sequences
+    Fail,
+    /// Replace the reversed sequences by their reverse complement
+    Correct,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum SoftMaskPolicy {
+    /// Score lowercase residues as uppercase
+    Keep,
+    /// Leave lowercase residues out of the score
+    Ignore,
+    /// Make gaps against lowercase residues cheaper
+    Downweight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum TieBreakPolicy {
+    /// Prefer matching the two sequences
+    Match,
+    /// Prefer a gap in the first sequence
+    Gapx,
+    /// Prefer a gap in the second sequence
+    Gapy,
+    /// Choose randomly, reproducible with --seed
+    Random,
+}
+
+impl From<TieBreakPolicy> for TieBreak {
+    fn from(policy: TieBreakPolicy) -> Self {
+        match policy {
+            TieBreakPolicy::Match => TieBreak::Match,
+            TieBreakPolicy::Gapx => TieBreak::GapInX,
+            TieBreakPolicy::Gapy => TieBreak::GapInY,
+            TieBreakPolicy::Random => TieBreak::Random,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum ConsensusPolicy {
+    /// The most frequent residue of every column, if it reaches --consensus-threshold
+    Majority,
+    /// The IUPAC ambiguity code of all the residues of every column
+    Ambiguity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum ScoreRowsPolicy {
+    /// Keep two rows of scores only for the nodes with very large matrices
+    Auto,
+    /// Always keep all the rows of scores
+    All,
+    /// Always keep only two rows of scores
+    Two,
+}
+
+impl From<ScoreRowsPolicy> for ScoreRows {
+    fn from(policy: ScoreRowsPolicy) -> Self {
+        match policy {
+            ScoreRowsPolicy::Auto => ScoreRows::Auto,
+            ScoreRowsPolicy::All => ScoreRows::All,
+            ScoreRowsPolicy::Two => ScoreRows::Two,
+        }
+    }
+}
+
+/// Parses a number of bytes with an optional K, M, G or T suffix for powers of 1024.
+fn parse_memory_size(size: &str) -> Result<usize, String> {
+    let upper = size.trim().to_ascii_uppercase();
+    let number = upper.strip_suffix('B').unwrap_or(&upper);
+    let (number, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1usize << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a memory size such as 512M or 8G", size))?;
+    if value <= 0.0 {
+        return Err(format!("The memory size must be positive, got '{}'", size));
+    }
+    Ok((value * multiplier as f64) as usize)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(super) enum RootingPolicy {
+    /// Root at the midpoint of the longest leaf to leaf path
+    Midpoint,
+    /// Root at the middle of the longest branch
+    LongestBranch,
+}
+
+impl From<RootingPolicy> for RootingMethod {
+    fn from(policy: RootingPolicy) -> Self {
+        match policy {
+            RootingPolicy::Midpoint => RootingMethod::Midpoint,
+            RootingPolicy::LongestBranch => RootingMethod::LongestBranch,
+        }
+    }
+}
+
+#[derive(Args)]
+pub(super) struct BootstrapArgs {
+    /// MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// Tree file in newick format
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    /// Number of bootstrap replicates
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub(super) replicates: usize,
+
+    /// Seed of the column resampling, a random seed is drawn if not provided
+    #[arg(long, value_name = "SEED")]
+    pub(super) seed: Option<u64>,
+
+    /// Level of the confidence interval of the total score
+    #[arg(long, value_name = "LEVEL", default_value_t = 0.95)]
+    pub(super) confidence: f64,
+
+    /// Output file for the tree in newick format with the support of every branch, as the
+    /// percentage of the replicates supporting it, in the node labels
+    #[arg(
+        short,
+        long,
+        value_name = "OUTPUT_FILE",
+        default_value = "bootstrap.newick"
+    )]
+    pub(super) output_file: PathBuf,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+}
+
+#[derive(Args)]
+pub(super) struct CompareArgs {
+    /// MSA file in fasta format, use - to read the MSA from standard input
+    #[arg(short = 'a', long, value_name = "MSA_FILE")]
+    pub(super) msa_file: PathBuf,
+
+    /// Reference MSA file in fasta format with the same sequences
+    #[arg(short, long, value_name = "REFERENCE_FILE")]
+    pub(super) reference_file: PathBuf,
+}
+
+#[derive(Args)]
+pub(super) struct CheckArgs {
+    /// Sequence file in fasta format, use - to read the sequences from standard input
+    #[arg(short, long, value_name = "SEQ_FILE")]
+    pub(super) seq_file: PathBuf,
+
+    /// Tree file in newick format, if not provided the guide tree is estimated as for the
+    /// alignment
+    #[arg(short, long, value_name = "TREE_FILE")]
+    pub(super) tree_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub(super) tree: TreeArgs,
+
+    #[command(flatten)]
+    pub(super) model: ModelArgs,
+
+    /// Whether to keep all the rows of the score matrices of a node, as for the alignment
+    #[arg(long, value_enum, default_value_t = ScoreRowsPolicy::Auto)]
+    pub(super) score_rows: ScoreRowsPolicy,
+
+    /// Limit on the memory of the alignment matrices of a node, e.g. 8G or 512M, the check fails
+    /// if the alignment cannot stay within it
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory_size)]
+    pub(super) max_memory: Option<usize>,
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::{
+        parse_memory_size, CategoryPolicy, Cli, Command, ConsensusPolicy, GapFunctionOption,
+        MatrixFormat, MsaFormat, ReverseStrandPolicy, RootingPolicy, SequenceWeightScheme,
+        SoftMaskPolicy,
+    };
+    use clap::Parser;
+    use log::LevelFilter;
+    use std::path::PathBuf;
+
+    fn parse(flags: &[&str]) -> Cli {
+        let args = [
+            "indelMaP",
+            "align",
+            "-s",
+            "seqs.fasta",
+            "-t",
+            "tree.newick",
+            "-m",
+            "JC69",
+        ];
+        Cli::try_parse_from(args.iter().chain(flags)).unwrap()
+    }
+
+    #[test]
+    fn verbosity_flags() {
+        assert_eq!(parse(&[]).log_level(), LevelFilter::Info);
+        assert_eq!(parse(&["-v"]).log_level(), LevelFilter::Debug);
+        assert_eq!(parse(&["-vv"]).log_level(), LevelFilter::Trace);
+        assert_eq!(parse(&["-vvv"]).log_level(), LevelFilter::Trace);
+        assert_eq!(parse(&["-q"]).log_level(), LevelFilter::Warn);
+        assert_eq!(parse(&["-qq"]).log_level(), LevelFilter::Error);
+        assert_eq!(parse(&["-qqq"]).log_level(), LevelFilter::Off);
+        assert!(Cli::try_parse_from([
+            "indelMaP", "align", "-s", "s.fasta", "-t", "t.newick", "-m", "JC69", "-v", "-q"
+        ])
+        .is_err());
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "-q",
+            "score",
+            "-a",
+            "msa.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+        ])
+        .unwrap();
+        assert_eq!(cli.log_level(), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn subcommands() {
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "score",
+            "-a",
+            "msa.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+            "--go",
+            "3",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Score(args) => {
+                assert_eq!(args.model.model.as_deref(), Some("WAG"));
+                assert_eq!(args.model.go, 3.0);
+            }
+            _ => panic!("Expected the score subcommand"),
+        }
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "ancestors",
+            "-a",
+            "msa.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Command::Ancestors(_)));
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "add",
+            "-a",
+            "msa.fasta",
+            "-s",
+            "new.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Add(args) => {
+                assert_eq!(args.msa_file, PathBuf::from("msa.fasta"));
+                assert_eq!(args.seq_file, PathBuf::from("new.fasta"));
+                assert_eq!(args.output_file, PathBuf::from("added.fasta"));
+            }
+            _ => panic!("Expected the add subcommand"),
+        }
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "merge",
+            "-a",
+            "first.fasta",
+            "-b",
+            "second.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Merge(args) => {
+                assert_eq!(args.other_msa_file, PathBuf::from("second.fasta"));
+                assert_eq!(args.output_file, PathBuf::from("merged.fasta"));
+            }
+            _ => panic!("Expected the merge subcommand"),
+        }
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "bootstrap",
+            "-a",
+            "msa.fasta",
+            "-t",
+            "t.newick",
+            "-m",
+            "WAG",
+            "--replicates",
+            "20",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Bootstrap(args) => {
+                assert_eq!(args.replicates, 20);
+                assert_eq!(args.seed, None);
+                assert_eq!(args.confidence, 0.95);
+                assert_eq!(args.output_file, PathBuf::from("bootstrap.newick"));
+            }
+            _ => panic!("Expected the bootstrap subcommand"),
+        }
+        let cli =
+            Cli::try_parse_from(["indelMaP", "compare", "-a", "msa.fasta", "-r", "ref.fasta"])
+                .unwrap();
+        match cli.command {
+            Command::Compare(args) => {
+                assert_eq!(args.msa_file, PathBuf::from("msa.fasta"));
+                assert_eq!(args.reference_file, PathBuf::from("ref.fasta"));
+            }
+            _ => panic!("Expected the compare subcommand"),
+        }
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "check",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--max-memory",
+            "1G",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Check(args) => {
+                assert_eq!(args.tree_file, None);
+                assert_eq!(args.max_memory, Some(1 << 30));
+            }
+            _ => panic!("Expected the check subcommand"),
+        }
+        match parse(&["--rooting", "longest-branch"]).command {
+            Command::Align(args) => assert_eq!(args.tree.rooting, RootingPolicy::LongestBranch),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--gamma", "0.5"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.gamma, Some(0.5));
+                assert_eq!(args.model.gamma_categories, 4);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--gamma-categories",
+            "8"
+        ])
+        .is_err());
+        match parse(&["--gamma", "0.5", "--site-rates"]).command {
+            Command::Align(args) => assert!(args.model.site_rates),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--partitions", "genes.txt"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.partitions, Some(PathBuf::from("genes.txt")))
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--gamma",
+            "0.5",
+            "--site-rates",
+            "--partitions",
+            "genes.txt"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--site-rates"
+        ])
+        .is_err());
+        match parse(&["--category-breakpoints", "0.05,0.2,0.8"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.category_breakpoints, vec![0.05, 0.2, 0.8])
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&[
+            "--refine-iterations",
+            "3",
+            "--nni-rounds",
+            "5",
+            "--column-scores",
+            "--trim-gap-fraction",
+            "0.9",
+        ])
+        .command
+        {
+            Command::Align(args) => {
+                assert_eq!(args.refine_iterations, 3);
+                assert_eq!(args.nni_rounds, 5);
+                assert!(args.column_scores);
+                assert_eq!(args.trim_gap_fraction, Some(0.9));
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "JC69",
+            "--codon",
+            "--refine-iterations",
+            "2"
+        ])
+        .is_err());
+        match parse(&["--translate"]).command {
+            Command::Align(args) => assert!(args.translate),
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--translate",
+            "--codon"
+        ])
+        .is_err());
+        // the passes after the alignment read the expanded nucleotide MSA
+        for coding in ["--codon", "--translate"] {
+            for flags in [
+                &["--refine-iterations", "2"][..],
+                &["--column-scores"],
+                &["--traceback-paths"],
+                &["--dump-matrices", "1"],
+                &["--reestimate-branch-lengths"],
+            ] {
+                let args = ["indelMaP", "align", "-s", "s.fasta", "-m", "JC69", coding];
+                assert!(Cli::try_parse_from(args.iter().chain(flags)).is_err());
+            }
+        }
+        match parse(&["--restarts", "8", "--threads", "4"]).command {
+            Command::Align(args) => assert_eq!(args.restarts, 8),
+            _ => panic!("Expected the align subcommand"),
+        }
+        // every run mode takes only the options it uses instead of silently dropping the others
+        for flags in [
+            &["--samples", "2", "--threads", "4"][..],
+            &["--suboptimal", "2", "--threads", "4"],
+            &["--checkpoint", "nodes.ckpt", "--threads", "4"],
+            &["--checkpoint", "nodes.ckpt", "--samples", "2"],
+            &["--restarts", "4", "--checkpoint", "nodes.ckpt"],
+            &["--codon", "--samples", "2"],
+            &["--codon", "--suboptimal", "2"],
+            &["--codon", "--restarts", "4"],
+            &["--codon", "--checkpoint", "nodes.ckpt"],
+            &["--codon", "--threads", "4"],
+            &["--translate", "--samples", "2"],
+            &["--translate", "--threads", "4"],
+        ] {
+            let args = ["indelMaP", "align", "-s", "s.fasta", "-m", "JC69"];
+            assert!(Cli::try_parse_from(args.iter().chain(flags)).is_err());
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "JC69",
+            "--restarts",
+            "4",
+            "--samples",
+            "2"
+        ])
+        .is_err());
+        match parse(&["--gap-slopes", "-1.5,0.5"]).command {
+            Command::Align(args) => assert_eq!(args.model.gap_slopes, Some(vec![-1.5, 0.5])),
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "--cost-matrix",
+            "costs.txt",
+            "--gap-slopes",
+            "1,1"
+        ])
+        .is_err());
+        match parse(&["--free-end-gaps"]).command {
+            Command::Align(args) => assert!(args.model.free_end_gaps),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--output-format", "maf"]).command {
+            Command::Align(args) => assert_eq!(args.output_format, MsaFormat::Maf),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&[]).command {
+            Command::Align(args) => assert_eq!(args.reverse_strand, ReverseStrandPolicy::Keep),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--reverse-strand", "correct"]).command {
+            Command::Align(args) => assert_eq!(args.reverse_strand, ReverseStrandPolicy::Correct),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--dump-matrices", "3", "--matrix-format", "npy"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.dump_matrices, Some(3));
+                assert_eq!(args.matrix_format, MatrixFormat::Npy);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--two-pass"]).command {
+            Command::Align(args) => assert!(args.two_pass),
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "JC69",
+            "--two-pass",
+            "--restarts",
+            "4"
+        ])
+        .is_err());
+        // the second pass estimates the tree from the nucleotides, not the coding alignment
+        for coding in ["--codon", "--translate"] {
+            assert!(Cli::try_parse_from([
+                "indelMaP",
+                "align",
+                "-s",
+                "s.fasta",
+                "-m",
+                "JC69",
+                coding,
+                "--two-pass"
+            ])
+            .is_err());
+        }
+        match parse(&["--reestimate-branch-lengths"]).command {
+            Command::Align(args) => assert!(args.reestimate_branch_lengths),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--traceback-paths"]).command {
+            Command::Align(args) => assert!(args.traceback_paths),
+            _ => panic!("Expected the align subcommand"),
+        }
+        for flags in [&["--refine-iterations", "2"][..], &["--normalize-gaps"]] {
+            assert!(Cli::try_parse_from(
+                [
+                    "indelMaP",
+                    "align",
+                    "-s",
+                    "s.fasta",
+                    "-m",
+                    "JC69",
+                    "--traceback-paths"
+                ]
+                .iter()
+                .chain(flags)
+            )
+            .is_err());
+        }
+        match parse(&["--soft-mask", "downweight", "--soft-mask-weight", "0.2"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.soft_mask, SoftMaskPolicy::Downweight);
+                assert_eq!(args.model.soft_mask_weight, 0.2);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--gap-function", "log", "--gap-log-scale", "2"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.gap_function, GapFunctionOption::Log);
+                assert_eq!(args.model.gap_log_scale, 2.0);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--anchor-length", "32"]).command {
+            Command::Align(args) => assert_eq!(args.anchor_length, Some(32)),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--constraints", "anchors.txt"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.constraints, Some(PathBuf::from("anchors.txt")))
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--constraints", "anchors.txt", "--samples", "3"]).command {
+            Command::Align(args) => assert_eq!(args.samples, 3),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--seed-alignment", "seed.fasta", "--suboptimal", "2"]).command {
+            Command::Align(args) => assert_eq!(args.suboptimal, 2),
+            _ => panic!("Expected the align subcommand"),
+        }
+        for mode in ["--samples", "--suboptimal"] {
+            assert!(Cli::try_parse_from([
+                "indelMaP",
+                "align",
+                "-s",
+                "s.fasta",
+                "-m",
+                "WAG",
+                "--anchor-length",
+                "32",
+                mode,
+                "3"
+            ])
+            .is_err());
+        }
+        match parse(&["--seed-alignment", "seed.fasta"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.seed_alignment, Some(PathBuf::from("seed.fasta")))
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--seed-alignment",
+            "seed.fasta",
+            "--constraints",
+            "anchors.txt"
+        ])
+        .is_err());
+        match parse(&["--region-mask", "repeats.bed", "--mask-gap-weight", "0.2"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.region_mask, Some(PathBuf::from("repeats.bed")));
+                assert_eq!(args.mask_gap_weight, 0.2);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--mask-gap-weight",
+            "0.2"
+        ])
+        .is_err());
+        match parse(&["--normalize-gaps", "--homopolymer-gaps"]).command {
+            Command::Align(args) => {
+                assert!(args.normalize_gaps);
+                assert!(args.homopolymer_gaps);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--homopolymer-gaps"
+        ])
+        .is_err());
+        match parse(&["--consensus", "majority", "--consensus-threshold", "0.7"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.consensus, Some(ConsensusPolicy::Majority));
+                assert_eq!(args.consensus_threshold, 0.7);
+                assert!(!args.node_consensus);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--node-consensus"
+        ])
+        .is_err());
+        match parse(&["--samples", "10", "--column-confidence"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.samples, 10);
+                assert!(args.column_confidence);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--column-confidence"
+        ])
+        .is_err());
+        match parse(&["--cache-dir", "costs"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.cache_dir, Some(PathBuf::from("costs")));
+                assert!(!args.model.no_cache);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--no-cache"]).command {
+            Command::Align(args) => assert!(args.model.no_cache),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--empirical-freqs", "-p", "2.0"]).command {
+            Command::Align(args) => {
+                assert!(args.model.empirical_freqs());
+                assert_eq!(args.model.model_params, vec![2.0]);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["-m", "WAG+F"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.model_name(), "WAG");
+                assert!(args.model.empirical_freqs());
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--no-cache",
+            "--cache-dir",
+            "costs"
+        ])
+        .is_err());
+        match parse(&["--x-drop", "20"]).command {
+            Command::Align(args) => assert_eq!(args.x_drop, Some(20.0)),
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP", "align", "-s", "s.fasta", "-m", "WAG", "--x-drop", "20", "--band", "5"
+        ])
+        .is_err());
+        match parse(&["--metrics", "run.tsv"]).command {
+            Command::Align(args) => assert_eq!(args.metrics, Some(PathBuf::from("run.tsv"))),
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--sequence-weights", "thg"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.sequence_weights, SequenceWeightScheme::Thg)
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--gap-profile", "weights.txt"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.gap_profile, Some(PathBuf::from("weights.txt")));
+                assert!(!args.hydropathy_gaps);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--gap-profile",
+            "weights.txt",
+            "--hydropathy-gaps"
+        ])
+        .is_err());
+        match parse(&[
+            "--secondary-structure",
+            "ss.fasta",
+            "--helix-gap-weight",
+            "3",
+        ])
+        .command
+        {
+            Command::Align(args) => {
+                assert_eq!(args.secondary_structure, Some(PathBuf::from("ss.fasta")));
+                assert_eq!(args.helix_gap_weight, 3.0);
+                assert_eq!(args.strand_gap_weight, 1.5);
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "align",
+            "-s",
+            "s.fasta",
+            "-m",
+            "WAG",
+            "--secondary-structure",
+            "ss.fasta",
+            "--predict-structure"
+        ])
+        .is_err());
+        match parse(&["--category-strategy", "k-means"]).command {
+            Command::Align(args) => {
+                assert_eq!(args.model.category_strategy, CategoryPolicy::KMeans)
+            }
+            _ => panic!("Expected the align subcommand"),
+        }
+        match parse(&["--outgroup", "A,B"]).command {
+            Command::Align(args) => assert_eq!(args.tree.outgroup, vec!["A", "B"]),
+            _ => panic!("Expected the align subcommand"),
+        }
+        assert!(Cli::try_parse_from(["indelMaP", "-s", "s.fasta", "-t", "t.newick"]).is_err());
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "score",
+            "-a",
+            "msa.fasta",
+            "-t",
+            "t.newick",
+            "--cost-matrix",
+            "costs.txt",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Score(args) => {
+                assert_eq!(args.model.model, None);
+                assert_eq!(args.model.cost_matrix, Some(PathBuf::from("costs.txt")));
+            }
+            _ => panic!("Expected the score subcommand"),
+        }
+        assert!(
+            Cli::try_parse_from(["indelMaP", "score", "-a", "msa.fasta", "-t", "t.newick"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from([
+            "indelMaP", "score", "-s", "s.fasta", "-t", "t.newick", "-m", "WAG"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn memory_sizes() {
+        assert_eq!(parse_memory_size("1024"), Ok(1024));
+        assert_eq!(parse_memory_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_memory_size("8g"), Ok(8 << 30));
+        assert_eq!(parse_memory_size("1.5GB"), Ok(3 << 29));
+        assert!(parse_memory_size("lots").is_err());
+        assert!(parse_memory_size("0").is_err());
+        match parse(&["--max-memory", "2K"]).command {
+            Command::Align(args) => assert_eq!(args.max_memory, Some(2048)),
+            _ => panic!("Expected the align subcommand"),
+        }
+    }
+
+    #[test]
+    fn batch_inputs() {
+        let cli = Cli::try_parse_from([
+            "indelMaP",
+            "batch",
+            "--seq-dir",
+            "genes",
+            "-m",
+            "JC69",
+            "--threads",
+            "4",
+        ])
+        .unwrap();
+        match cli.command {
+            Command::Batch(args) => {
+                assert_eq!(args.seq_dir, Some(PathBuf::from("genes")));
+                assert_eq!(args.output_dir, PathBuf::from("msas"));
+                assert_eq!(args.threads, 4);
+            }
+            _ => panic!("Expected the batch subcommand"),
+        }
+        assert!(Cli::try_parse_from(["indelMaP", "batch", "-m", "JC69"]).is_err());
+        assert!(Cli::try_parse_from([
+            "indelMaP",
+            "batch",
+            "--seq-dir",
+            "genes",
+            "--manifest",
+            "genes.tsv",
+            "-m",
+            "JC69"
+        ])
+        .is_err());
+    }
 }
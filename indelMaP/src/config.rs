@@ -0,0 +1,164 @@
+use crate::cli::Cli;
+use anyhow::{bail, Error};
+use clap::CommandFactory;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, Error>;
+
+const CONFIG_FLAG: &str = "--config";
+
+/// Inserts the options from the configuration file given with --config right after the
+/// subcommand, in front of the other command line arguments, so that the flags given on the
+/// command line override the file values.
+pub(crate) fn expand_config(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let mut config_path = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == CONFIG_FLAG {
+            config_path = iter.next().map(|path| path.to_string_lossy().into_owned());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.to_string());
+        }
+    }
+    let Some(config_path) = config_path else {
+        return Ok(args);
+    };
+    let Some((position, subcommand)) = subcommand_position(&args) else {
+        return Ok(args);
+    };
+    let config = match fs::read_to_string(Path::new(&config_path)) {
+        Ok(config) => config,
+        Err(error) => bail!("Cannot read config file {}: {}", config_path, error),
+    };
+    let mut expanded = args[..=position].to_vec();
+    expanded.extend(
+        config_args(&config, &subcommand)?
+            .into_iter()
+            .map(OsString::from),
+    );
+    expanded.extend(args[position + 1..].iter().cloned());
+    Ok(expanded)
+}
+
+/// Position and name of the subcommand among the command line arguments.
+pub(crate) fn subcommand_position(args: &[OsString]) -> Option<(usize, String)> {
+    let command = Cli::command();
+    args.iter().enumerate().skip(1).find_map(|(i, arg)| {
+        let arg = arg.to_string_lossy();
+        command
+            .get_subcommands()
+            .find(|subcommand| subcommand.get_name() == arg)
+            .map(|subcommand| (i, subcommand.get_name().to_string()))
+    })
+}
+
+/// Converts the TOML configuration to command line arguments. The keys are the long option
+/// names, with either dashes or underscores, and paths are relative to the working directory.
+pub(crate) fn config_args(config: &str, subcommand: &str) -> Result<Vec<String>> {
+    let table: toml::Table = config.parse()?;
+    let command = Cli::command();
+    let Some(subcommand) = command.find_subcommand(subcommand) else {
+        bail!("Unknown subcommand {}.", subcommand);
+    };
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let name = key.replace('_', "-");
+        let known = command
+            .get_arguments()
+            .chain(subcommand.get_arguments())
+            .any(|arg| arg.get_long() == Some(name.as_str()));
+        if !known || name == "config" {
+            bail!("Unknown option {} in the config file.", key);
+        }
+        let flag = format!("--{}", name);
+        match value {
+            toml::Value::Boolean(true) => args.push(flag),
+            toml::Value::Boolean(false) => {}
+            toml::Value::Array(values) => {
+                args.push(flag);
+                for value in values {
+                    args.push(config_value(key, value)?);
+                }
+            }
+            value => {
+                args.push(flag);
+                args.push(config_value(key, value)?);
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn config_value(key: &str, value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        _ => bail!("Unsupported value for option {} in the config file.", key),
+    })
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::config_args;
+    use crate::cli::{Cli, Command};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    const CONFIG: &str = r#"
+seq_file = "seqs.fasta"
+tree-file = "tree.newick"
+model = "K80"
+model_params = [2.0, 1.0]
+go = 3.0
+categories = 8
+"#;
+
+    #[test]
+    fn config_to_args() {
+        assert_eq!(
+            config_args(CONFIG, "align").unwrap(),
+            vec![
+                "--seq-file",
+                "seqs.fasta",
+                "--tree-file",
+                "tree.newick",
+                "--model",
+                "K80",
+                "--model-params",
+                "2",
+                "1",
+                "--go",
+                "3",
+                "--categories",
+                "8"
+            ]
+        );
+        assert!(config_args("unknown = 1", "align").is_err());
+        assert!(config_args("seq_file = \"seqs.fasta\"", "score").is_err());
+        assert!(config_args("model = { name = \"K80\" }", "align").is_err());
+        assert_eq!(
+            config_args("quiet = true", "score").unwrap(),
+            vec!["--quiet"]
+        );
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let mut args = vec!["indelMaP".to_string(), "align".to_string()];
+        args.extend(config_args(CONFIG, "align").unwrap());
+        args.extend(["--go", "1.5", "-m", "HKY"].map(String::from));
+        let Command::Align(args) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("Expected the align subcommand");
+        };
+        assert_eq!(args.seq_file, PathBuf::from("seqs.fasta"));
+        assert_eq!(args.model.model.as_deref(), Some("HKY"));
+        assert_eq!(args.model.model_params, vec![2.0, 1.0]);
+        assert_eq!(args.model.go, 1.5);
+        assert_eq!(args.model.ge, 0.5);
+        assert_eq!(args.model.categories, 8);
+    }
+}
@@ -0,0 +1,1164 @@
+use anyhow::{bail, Error};
+use bio::io::fasta;
+use log::{info, warn};
+use parsimony::parsimony_alignment::guide_tree::{
+    estimate_guide_tree, internal_node_label, tree_to_labeled_newick, tree_to_newick,
+    tree_to_newick_w_support,
+};
+use parsimony::parsimony_alignment::indels::{IndelEvent, IndelKind, IndelStats};
+use parsimony::parsimony_alignment::metrics::MetricsSnapshot;
+use parsimony::parsimony_alignment::msa_scoring::TracebackStep;
+use parsimony::parsimony_alignment::tree_input::{
+    prune_missing_taxa, tree_from_newick, TreeInputOptions,
+};
+use phylo::io;
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::tree::{NodeIdx, Tree};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Path used on the command line to refer to standard input or standard output.
+pub(crate) const STDIO_PATH: &str = "-";
+
+pub(crate) fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == STDIO_PATH
+}
+
+/// Source of an aligned sequence in a MAF block: the source name, the 0-based start, the number
+/// of residues and the size of the source.
+#[derive(Debug, PartialEq)]
+pub(crate) struct MafSource {
+    pub(crate) name: String,
+    pub(crate) start: usize,
+    pub(crate) size: usize,
+    pub(crate) src_size: usize,
+}
+
+/// Source of a sequence with `residues` residues. An id with a range, e.g. chr1:1001-2000 as
+/// written by samtools faidx, gives the source name and the 1-based inclusive coordinates of the
+/// sequence, the end of the range stands in for the unknown size of the source. Other ids are
+/// taken as whole sources.
+pub(crate) fn maf_source(id: &str, residues: usize) -> MafSource {
+    let range = id.rsplit_once(':').and_then(|(name, range)| {
+        let (start, end) = range.split_once('-')?;
+        let start: usize = start.replace(',', "").parse().ok()?;
+        let end: usize = end.replace(',', "").parse().ok()?;
+        (!name.is_empty() && start >= 1 && end >= start).then_some((name, start, end))
+    });
+    match range {
+        Some((name, start, end)) => {
+            if end - start + 1 != residues {
+                warn!(
+                    "The range of sequence {} covers {} positions but the sequence has {} residues.",
+                    id,
+                    end - start + 1,
+                    residues
+                );
+            }
+            MafSource {
+                name: name.to_string(),
+                start: start - 1,
+                size: residues,
+                src_size: end.max(start - 1 + residues),
+            }
+        }
+        None => MafSource {
+            name: id.to_string(),
+            start: 0,
+            size: residues,
+            src_size: residues,
+        },
+    }
+}
+
+/// The MSA as a MAF file with a single alignment block of the given score, all the sequences
+/// are on the plus strand, see `maf_source` for their coordinates.
+pub(crate) fn msa_to_maf(msa: &[fasta::Record], score: f64) -> String {
+    let sources: Vec<MafSource> = msa
+        .iter()
+        .map(|record| {
+            let residues = record.seq().iter().filter(|&&c| c != b'-').count();
+            maf_source(record.id(), residues)
+        })
+        .collect();
+    let name_width = sources.iter().map(|source| source.name.len()).max();
+    let name_width = name_width.unwrap_or_default();
+    let mut maf = String::from("##maf version=1 program=indelMaP\n\n");
+    maf.push_str(&format!("a score={}\n", score));
+    for (record, source) in msa.iter().zip(&sources) {
+        maf.push_str(&format!(
+            "s {:<width$} {} {} + {} {}\n",
+            source.name,
+            source.start,
+            source.size,
+            source.src_size,
+            String::from_utf8_lossy(record.seq()),
+            width = name_width
+        ));
+    }
+    maf.push('\n');
+    maf
+}
+
+/// Writes the MSA as a MAF file, see `msa_to_maf`.
+pub(crate) fn write_maf(msa: &[fasta::Record], score: f64, path: &Path) -> Result<()> {
+    let maf = msa_to_maf(msa, score);
+    if is_stdio(path) {
+        info!("Writing MSA in MAF format to standard output");
+        print!("{}", maf);
+    } else {
+        info!("Writing MSA in MAF format to {}.", path.display());
+        std::fs::write(path, maf)?;
+    }
+    Ok(())
+}
+
+/// Sequence ids changed to make them unique, pairs of the original and the new id.
+pub(crate) type RenamedIds = Vec<(String, String)>;
+
+/// Reads the sequences and the tree, the tree is matched to the sequences, rooted and resolved to
+/// a binary tree according to the tree input options.
+pub(crate) fn read_phylo_info(
+    seq_file: PathBuf,
+    tree_file: PathBuf,
+    tree_options: &TreeInputOptions,
+) -> Result<PhyloInfo> {
+    Ok(read_phylo_info_renamed(seq_file, tree_file, tree_options, false)?.0)
+}
+
+/// Reads the sequences and the tree as `read_phylo_info`, with `auto_rename` duplicate sequence
+/// ids are made unique instead of failing, see `make_ids_unique`. The leaves of the tree must
+/// then carry the new ids.
+pub(crate) fn read_phylo_info_renamed(
+    seq_file: PathBuf,
+    tree_file: PathBuf,
+    tree_options: &TreeInputOptions,
+    auto_rename: bool,
+) -> Result<(PhyloInfo, RenamedIds)> {
+    let newick = read_newick(&tree_file)?;
+    let prepare = tree_options.needs_preparing(&newick)?;
+    let (sequences, renamed) = read_sequences_renamed(&seq_file, auto_rename)?;
+    let info = phylo_info_w_tree(sequences, tree_file, newick, prepare, tree_options)?;
+    Ok((info, renamed))
+}
+
+/// Reads the sequences of two files, e.g. a reference MSA and new sequences to add to it,
+/// together with a tree over all of them. Returns the sequences of the first file followed by
+/// the ones of the second on the tree, and the ids of the sequences of the second file.
+pub(crate) fn read_phylo_info_from_two_files(
+    first_file: PathBuf,
+    second_file: PathBuf,
+    tree_file: PathBuf,
+    tree_options: &TreeInputOptions,
+) -> Result<(PhyloInfo, HashSet<String>)> {
+    if is_stdio(&first_file) && is_stdio(&second_file) {
+        bail!("Only one of the two sequence files can be read from standard input.");
+    }
+    let newick = read_newick(&tree_file)?;
+    let prepare = tree_options.needs_preparing(&newick)?;
+    let mut sequences = read_sequences(&first_file)?;
+    let second_sequences = read_sequences(&second_file)?;
+    let second_ids: HashSet<String> = second_sequences
+        .iter()
+        .map(|record| record.id().to_string())
+        .collect();
+    if let Some(record) = sequences
+        .iter()
+        .find(|record| second_ids.contains(record.id()))
+    {
+        bail!("Sequence {} is in both sequence files.", record.id());
+    }
+    sequences.extend(second_sequences);
+    let info = phylo_info_w_tree(sequences, tree_file, newick, prepare, tree_options)?;
+    Ok((info, second_ids))
+}
+
+fn read_newick(tree_file: &Path) -> Result<String> {
+    if is_stdio(tree_file) {
+        bail!("The tree cannot be read from standard input, please provide a tree file.");
+    }
+    Ok(std::fs::read_to_string(tree_file)?)
+}
+
+fn phylo_info_w_tree(
+    sequences: Vec<fasta::Record>,
+    tree_file: PathBuf,
+    newick: String,
+    prepare: bool,
+    tree_options: &TreeInputOptions,
+) -> Result<PhyloInfo> {
+    if prepare {
+        let (newick, sequences) = if tree_options.prune_missing {
+            prune_missing_taxa(&newick, &sequences, &tree_options.matching)?
+        } else {
+            (newick, sequences)
+        };
+        let tree = tree_from_newick(&newick, &sequences, tree_options)?;
+        return Ok(phyloinfo_from_sequences_tree(&sequences, tree)?);
+    }
+    info!("Reading trees from file {}", tree_file.display());
+    let mut trees = io::read_newick_from_file(tree_file)?;
+    if trees.is_empty() {
+        bail!("No trees found in the tree file.");
+    }
+    Ok(phyloinfo_from_sequences_tree(&sequences, trees.remove(0))?)
+}
+
+/// Reads the sequences and estimates a guide tree for them, used when no tree file is given.
+/// The guide tree is rerooted on the outgroup if one is given.
+pub(crate) fn read_phylo_info_w_guide_tree(
+    seq_file: PathBuf,
+    tree_options: &TreeInputOptions,
+) -> Result<PhyloInfo> {
+    Ok(read_phylo_info_w_guide_tree_renamed(seq_file, tree_options, false)?.0)
+}
+
+/// Reads the sequences and estimates a guide tree as `read_phylo_info_w_guide_tree`, with
+/// `auto_rename` duplicate sequence ids are made unique instead of failing.
+pub(crate) fn read_phylo_info_w_guide_tree_renamed(
+    seq_file: PathBuf,
+    tree_options: &TreeInputOptions,
+    auto_rename: bool,
+) -> Result<(PhyloInfo, RenamedIds)> {
+    let (sequences, renamed) = read_sequences_renamed(&seq_file, auto_rename)?;
+    let tree = guide_tree(&sequences, tree_options)?;
+    Ok((phyloinfo_from_sequences_tree(&sequences, tree)?, renamed))
+}
+
+/// Estimates the guide tree of the sequences, rerooted on the outgroup if one is given.
+pub(crate) fn guide_tree(
+    sequences: &[fasta::Record],
+    tree_options: &TreeInputOptions,
+) -> Result<Tree> {
+    reroot_on_outgroup(estimate_guide_tree(sequences)?, sequences, tree_options)
+}
+
+/// Reroots an estimated tree on the outgroup of the tree options, if one is given.
+pub(crate) fn reroot_on_outgroup(
+    tree: Tree,
+    sequences: &[fasta::Record],
+    tree_options: &TreeInputOptions,
+) -> Result<Tree> {
+    if tree_options.outgroup.is_empty() {
+        return Ok(tree);
+    }
+    Ok(tree_from_newick(
+        &tree_to_newick(&tree, sequences),
+        sequences,
+        tree_options,
+    )?)
+}
+
+/// Reads the sequences of a fasta file, fails if two sequences have the same id.
+pub(crate) fn read_sequences(seq_file: &Path) -> Result<Vec<fasta::Record>> {
+    Ok(read_sequences_renamed(seq_file, false)?.0)
+}
+
+/// Reads the sequences of a fasta file, with `auto_rename` duplicate ids are made unique
+/// instead of failing.
+pub(crate) fn read_sequences_renamed(
+    seq_file: &Path,
+    auto_rename: bool,
+) -> Result<(Vec<fasta::Record>, RenamedIds)> {
+    let mut sequences = read_records(seq_file)?;
+    if auto_rename {
+        let renamed = make_ids_unique(&mut sequences);
+        for (original, new) in &renamed {
+            warn!("Renamed a duplicate of sequence {} to {}.", original, new);
+        }
+        return Ok((sequences, renamed));
+    }
+    let mut ids = HashSet::new();
+    if let Some(record) = sequences.iter().find(|record| !ids.insert(record.id())) {
+        bail!(
+            "Sequence id {} appears more than once in {}, use --auto-rename to make the ids unique.",
+            record.id(),
+            seq_file.display()
+        );
+    }
+    Ok((sequences, Vec::new()))
+}
+
+/// Gives every repeated sequence id a numbered suffix, the second A becomes A_2, the third A_3
+/// and so on, skipping the names that are already taken. The first occurrence keeps its id.
+pub(crate) fn make_ids_unique(sequences: &mut [fasta::Record]) -> RenamedIds {
+    let mut taken: HashSet<String> = sequences
+        .iter()
+        .map(|record| record.id().to_string())
+        .collect();
+    let mut seen = HashSet::new();
+    let mut renamed = Vec::new();
+    for record in sequences.iter_mut() {
+        if seen.insert(record.id().to_string()) {
+            continue;
+        }
+        let new_id = (2..)
+            .map(|n| format!("{}_{}", record.id(), n))
+            .find(|id| !taken.contains(id))
+            .unwrap();
+        taken.insert(new_id.clone());
+        renamed.push((record.id().to_string(), new_id.clone()));
+        *record = fasta::Record::with_attrs(&new_id, record.desc(), record.seq());
+    }
+    renamed
+}
+
+/// Writes the original and the new id of every renamed sequence as tab-separated values.
+pub(crate) fn write_renamed_ids(renamed: &RenamedIds, path: &Path) -> Result<()> {
+    info!(
+        "Writing the ids of {} renamed sequences to {}.",
+        renamed.len(),
+        path.display()
+    );
+    let mut table = String::from("original_id\trenamed_id\n");
+    for (original, new) in renamed {
+        table.push_str(&format!("{}\t{}\n", original, new));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+fn read_records(seq_file: &Path) -> Result<Vec<fasta::Record>> {
+    let sequences = if is_stdio(seq_file) {
+        info!("Reading sequences from standard input");
+        fasta::Reader::new(std::io::stdin())
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?
+    } else {
+        info!("Reading sequences from file {}", seq_file.display());
+        fasta::Reader::from_file(seq_file)?
+            .records()
+            .collect::<std::result::Result<Vec<_>, _>>()?
+    };
+    info!("{} sequence(s) read successfully", sequences.len());
+    Ok(sequences)
+}
+
+/// Writes the estimated guide tree in newick format.
+pub(crate) fn write_guide_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    info!("Writing the estimated guide tree to {}.", path.display());
+    write_tree(info, path)
+}
+
+/// Writes the tree re-estimated from the MSA of the first pass in newick format.
+pub(crate) fn write_two_pass_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    info!(
+        "Writing the tree re-estimated from the first pass to {}.",
+        path.display()
+    );
+    write_tree(info, path)
+}
+
+/// Writes the tree with the branch lengths re-estimated from the MSA in newick format.
+pub(crate) fn write_branch_length_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    info!(
+        "Writing the tree with the re-estimated branch lengths to {}.",
+        path.display()
+    );
+    write_tree(info, path)
+}
+
+/// Writes the tree found by the tree search in newick format.
+pub(crate) fn write_nni_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    info!(
+        "Writing the tree found by the tree search to {}.",
+        path.display()
+    );
+    write_tree(info, path)
+}
+
+/// Writes the tree with the bootstrap support of its branches as node labels.
+pub(crate) fn write_support_tree(
+    info: &PhyloInfo,
+    support: &[Option<f64>],
+    path: &Path,
+) -> Result<()> {
+    info!(
+        "Writing the tree with branch support to {}.",
+        path.display()
+    );
+    let newick = tree_to_newick_w_support(&info.tree, &info.sequences, support);
+    std::fs::write(path, newick + "\n")?;
+    Ok(())
+}
+
+/// Writes the tree with the internal nodes labeled as in the ancestral outputs.
+pub(crate) fn write_labeled_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    info!(
+        "Writing the tree with labeled internal nodes to {}.",
+        path.display()
+    );
+    std::fs::write(
+        path,
+        tree_to_labeled_newick(&info.tree, &info.sequences) + "\n",
+    )?;
+    Ok(())
+}
+
+fn write_tree(info: &PhyloInfo, path: &Path) -> Result<()> {
+    std::fs::write(path, tree_to_newick(&info.tree, &info.sequences) + "\n")?;
+    Ok(())
+}
+
+/// Writes the seed and the total score of every restart as tab-separated values, the `best`
+/// column marks the kept restart with 1.
+pub(crate) fn write_restart_scores(scores: &[(u64, f64)], best: usize, path: &Path) -> Result<()> {
+    info!("Writing the scores of the restarts to {}.", path.display());
+    let mut table = String::from("restart\tseed\tscore\tbest\n");
+    for (restart, (seed, score)) in scores.iter().enumerate() {
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            restart,
+            seed,
+            score,
+            u8::from(restart == best)
+        ));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Writes the score of every MSA column as tab-separated values, with the columns numbered
+/// from 1.
+pub(crate) fn write_column_scores(scores: &[f64], path: &Path) -> Result<()> {
+    info!(
+        "Writing the scores of the MSA columns to {}.",
+        path.display()
+    );
+    let mut table = String::from("column\tscore\n");
+    for (column, score) in scores.iter().enumerate() {
+        table.push_str(&format!("{}\t{}\n", column + 1, score));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Writes the traceback path of every internal node, see `traceback_paths_table`.
+pub(crate) fn write_traceback_paths(paths: &[Vec<TracebackStep>], path: &Path) -> Result<()> {
+    info!("Writing the traceback paths to {}.", path.display());
+    std::fs::write(path, traceback_paths_table(paths))?;
+    Ok(())
+}
+
+/// Traceback path of every internal node as tab-separated values, a line per step with the
+/// node labeled as in the ancestral outputs and the columns numbered from 1.
+pub(crate) fn traceback_paths_table(paths: &[Vec<TracebackStep>]) -> String {
+    let mut table = String::from("node\tstep\ti\tj\tstate\tgap_open\tcost\tcolumn\n");
+    for (node, steps) in paths.iter().enumerate() {
+        for (idx, step) in steps.iter().enumerate() {
+            table.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                internal_node_label(node),
+                idx + 1,
+                step.cell.0,
+                step.cell.1,
+                step.state,
+                step.gap_open,
+                step.cost,
+                step.column + 1
+            ));
+        }
+    }
+    table
+}
+
+/// Writes a score matrix of the DP as tab-separated values, a line per row of the matrix.
+/// Unreachable cells are written as inf.
+pub(crate) fn write_matrix_tsv(matrix: &[Vec<f64>], path: &Path) -> Result<()> {
+    let mut table = String::new();
+    for row in matrix {
+        let values: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        table.push_str(&values.join("\t"));
+        table.push('\n');
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Writes a score matrix of the DP as a NumPy .npy file of little-endian 64-bit floats.
+pub(crate) fn write_matrix_npy(matrix: &[Vec<f64>], path: &Path) -> Result<()> {
+    std::fs::write(path, npy_bytes(matrix))?;
+    Ok(())
+}
+
+/// Version 1.0 of the .npy format: magic string, version, header length, header dictionary
+/// padded with spaces to a multiple of 64 bytes and the values in row-major order.
+fn npy_bytes(matrix: &[Vec<f64>]) -> Vec<u8> {
+    let (rows, cols) = (matrix.len(), matrix.first().map_or(0, Vec::len));
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, {}), }}",
+        rows, cols
+    );
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    for value in matrix.iter().flatten() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+/// Writes the counters of the alignment run as tab-separated values, a line per counter.
+pub(crate) fn write_metrics(metrics: &MetricsSnapshot, path: &Path) -> Result<()> {
+    info!("Writing the run metrics to {}.", path.display());
+    let mut table = String::from("metric\tvalue\n");
+    for (name, value) in metrics.entries() {
+        table.push_str(&format!("{}\t{}\n", name, value));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Writes the fraction of the sampled alignments that reproduce every MSA column as
+/// tab-separated values, with the columns numbered from 1.
+pub(crate) fn write_column_confidence(confidence: &[f64], path: &Path) -> Result<()> {
+    info!(
+        "Writing the confidence of the MSA columns to {}.",
+        path.display()
+    );
+    let mut table = String::from("column\tconfidence\n");
+    for (column, confidence) in confidence.iter().enumerate() {
+        table.push_str(&format!("{}\t{}\n", column + 1, confidence));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Writes a line per internal node with a 1 for every MSA column with a gap at the node and a 0
+/// for the others, the columns are numbered from 1 in the header.
+pub(crate) fn write_gap_matrix(gaps: &[Vec<bool>], path: &Path) -> Result<()> {
+    info!(
+        "Writing the ancestral gaps of {} internal nodes to {}.",
+        gaps.len(),
+        path.display()
+    );
+    let columns = gaps.first().map_or(0, Vec::len);
+    let mut table = String::from("node");
+    for column in 0..columns {
+        table.push_str(&format!("\t{}", column + 1));
+    }
+    table.push('\n');
+    for (node, gaps) in gaps.iter().enumerate() {
+        table.push_str(&internal_node_label(node));
+        for &gap in gaps {
+            table.push_str(if gap { "\t1" } else { "\t0" });
+        }
+        table.push('\n');
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+/// Name of a node in the outputs, the sequence id of a leaf and the label of an internal node as
+/// in the ancestral sequences and the labeled tree.
+pub(crate) fn node_name(info: &PhyloInfo, node: NodeIdx) -> String {
+    match node {
+        NodeIdx::Internal(idx) => internal_node_label(idx),
+        NodeIdx::Leaf(idx) => info.sequences[idx].id().to_string(),
+    }
+}
+
+/// Writes every indel with the node below its branch, its kind and its first and last MSA
+/// column, numbered from 1.
+pub(crate) fn write_indel_events(
+    events: &[IndelEvent],
+    info: &PhyloInfo,
+    path: &Path,
+) -> Result<()> {
+    info!(
+        "Writing {} indel events to {}.",
+        events.len(),
+        path.display()
+    );
+    let mut table = String::from("node\tkind\tstart\tend\tlength\n");
+    for event in events {
+        let kind = indel_kind_name(event.kind);
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            node_name(info, event.node),
+            kind,
+            event.start + 1,
+            event.end,
+            event.length
+        ));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+fn indel_kind_name(kind: IndelKind) -> &'static str {
+    match kind {
+        IndelKind::Insertion => "insertion",
+        IndelKind::Deletion => "deletion",
+    }
+}
+
+/// Summary of the indels as three tab-separated tables: the counts and mean lengths of the
+/// insertions and deletions, the number of indels of every length and the indels of every
+/// branch. Undefined means and rates are NA.
+pub(crate) fn indel_stats_table(stats: &IndelStats, info: &PhyloInfo) -> String {
+    let optional = |value: Option<f64>| value.map_or("NA".to_string(), |value| value.to_string());
+    let mut table = String::from("kind\tcount\tmean_length\n");
+    for (kind, count) in [
+        (IndelKind::Insertion, stats.insertions),
+        (IndelKind::Deletion, stats.deletions),
+    ] {
+        table.push_str(&format!(
+            "{}\t{}\t{}\n",
+            indel_kind_name(kind),
+            count,
+            optional(stats.mean_length(kind))
+        ));
+    }
+    table.push_str("\nlength\tinsertions\tdeletions\n");
+    for counts in &stats.lengths {
+        table.push_str(&format!(
+            "{}\t{}\t{}\n",
+            counts.length, counts.insertions, counts.deletions
+        ));
+    }
+    table.push_str("\nnode\tbranch_length\tinsertions\tdeletions\trate\n");
+    for branch in &stats.branches {
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            node_name(info, branch.node),
+            branch.branch_length,
+            branch.insertions,
+            branch.deletions,
+            optional(branch.rate)
+        ));
+    }
+    table
+}
+
+/// Summary of the indels as JSON with the same fields as `indel_stats_table`, undefined means
+/// and rates are null.
+pub(crate) fn indel_stats_json(stats: &IndelStats, info: &PhyloInfo) -> String {
+    let lengths: Vec<Value> = stats
+        .lengths
+        .iter()
+        .map(|counts| {
+            json!({
+                "length": counts.length,
+                "insertions": counts.insertions,
+                "deletions": counts.deletions,
+            })
+        })
+        .collect();
+    let branches: Vec<Value> = stats
+        .branches
+        .iter()
+        .map(|branch| {
+            json!({
+                "node": node_name(info, branch.node),
+                "branch_length": branch.branch_length,
+                "insertions": branch.insertions,
+                "deletions": branch.deletions,
+                "rate": branch.rate,
+            })
+        })
+        .collect();
+    let summary = json!({
+        "insertions": stats.insertions,
+        "deletions": stats.deletions,
+        "mean_insertion_length": stats.mean_length(IndelKind::Insertion),
+        "mean_deletion_length": stats.mean_length(IndelKind::Deletion),
+        "lengths": lengths,
+        "branches": branches,
+    });
+    format!("{:#}\n", summary)
+}
+
+pub(crate) fn write_indel_stats_json(
+    stats: &IndelStats,
+    info: &PhyloInfo,
+    path: &Path,
+) -> Result<()> {
+    info!("Writing the indel summary to {}.", path.display());
+    std::fs::write(path, indel_stats_json(stats, info))?;
+    Ok(())
+}
+
+/// Writes the index of every column of the trimmed MSA in the original MSA as tab-separated
+/// values, with the columns numbered from 1.
+pub(crate) fn write_trim_map(kept: &[usize], path: &Path) -> Result<()> {
+    info!(
+        "Writing the columns kept by the trimming to {}.",
+        path.display()
+    );
+    let mut table = String::from("trimmed_column\toriginal_column\n");
+    for (column, original) in kept.iter().enumerate() {
+        table.push_str(&format!("{}\t{}\n", column + 1, original + 1));
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+pub(crate) fn write_msa(msa: &[fasta::Record], path: PathBuf) -> Result<()> {
+    if !is_stdio(&path) {
+        return Ok(io::write_sequences_to_file(msa, path)?);
+    }
+    info!("Writing MSA to standard output");
+    let mut writer = fasta::Writer::new(std::io::stdout());
+    for record in msa {
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Path of the `sample`-th sampled alignment, e.g. msa.fasta -> msa.sample1.fasta.
+pub(crate) fn sample_path(output: &Path, sample: usize) -> PathBuf {
+    numbered_path(output, "sample", sample)
+}
+
+/// Path of the `rank`-th suboptimal alignment, e.g. msa.fasta -> msa.subopt1.fasta.
+pub(crate) fn suboptimal_path(output: &Path, rank: usize) -> PathBuf {
+    numbered_path(output, "subopt", rank)
+}
+
+/// Path of the scores of the restarts, e.g. msa.fasta -> msa.restarts.tsv.
+pub(crate) fn restart_scores_path(output: &Path) -> PathBuf {
+    tsv_path(output, "restarts")
+}
+
+/// Path of the scores of the MSA columns, e.g. msa.fasta -> msa.columns.tsv.
+pub(crate) fn column_scores_path(output: &Path) -> PathBuf {
+    tsv_path(output, "columns")
+}
+
+/// Path of the traceback paths of the internal nodes, e.g. msa.fasta -> msa.paths.tsv.
+pub(crate) fn traceback_paths_path(output: &Path) -> PathBuf {
+    tsv_path(output, "paths")
+}
+
+/// Path of the DP score matrix of a state at an internal node, e.g. msa.fasta and the match
+/// state of N3 -> msa.N3.M.tsv.
+pub(crate) fn matrix_path(output: &Path, node: &str, state: &str, extension: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    output.with_file_name(format!("{}.{}.{}.{}", stem, node, state, extension))
+}
+
+/// Path of the confidence of the MSA columns, e.g. msa.fasta -> msa.confidence.tsv.
+pub(crate) fn column_confidence_path(output: &Path) -> PathBuf {
+    tsv_path(output, "confidence")
+}
+
+/// Path of the mapping of the renamed sequence ids, e.g. msa.fasta -> msa.renamed_ids.tsv.
+pub(crate) fn renamed_ids_path(output: &Path) -> PathBuf {
+    tsv_path(output, "renamed_ids")
+}
+
+/// Path of the column map of the trimmed MSA, e.g. msa.fasta -> msa.trim_map.tsv.
+pub(crate) fn trim_map_path(output: &Path) -> PathBuf {
+    tsv_path(output, "trim_map")
+}
+
+/// Path of the consensus of the output MSA, e.g. msa.fasta -> msa.consensus.fasta.
+pub(crate) fn consensus_path(output: &Path) -> PathBuf {
+    labeled_path(output, "consensus")
+}
+
+/// Path of the consensus sequences of the internal nodes, e.g.
+/// msa.fasta -> msa.node_consensus.fasta.
+pub(crate) fn node_consensus_path(output: &Path) -> PathBuf {
+    labeled_path(output, "node_consensus")
+}
+
+/// Path of the estimated guide tree, e.g. msa.fasta -> msa.guide_tree.newick.
+pub(crate) fn guide_tree_path(output: &Path) -> PathBuf {
+    tree_path(output, "guide_tree")
+}
+
+/// Path of the tree of the second pass, e.g. msa.fasta -> msa.two_pass_tree.newick.
+pub(crate) fn two_pass_tree_path(output: &Path) -> PathBuf {
+    tree_path(output, "two_pass_tree")
+}
+
+/// Path of the tree with the re-estimated branch lengths, e.g. msa.fasta ->
+/// msa.branch_lengths.newick.
+pub(crate) fn branch_length_tree_path(output: &Path) -> PathBuf {
+    tree_path(output, "branch_lengths")
+}
+
+/// Path of the tree found by the tree search, e.g. msa.fasta -> msa.nni_tree.newick.
+pub(crate) fn nni_tree_path(output: &Path) -> PathBuf {
+    tree_path(output, "nni_tree")
+}
+
+/// Path of the tree labeled as the ancestral outputs, e.g. anc.fasta -> anc.labeled_tree.newick.
+pub(crate) fn labeled_tree_path(output: &Path) -> PathBuf {
+    tree_path(output, "labeled_tree")
+}
+
+/// Gene of a batch run, its name is also the name of its output MSA.
+pub(crate) struct BatchGene {
+    pub(crate) name: String,
+    pub(crate) seq_file: PathBuf,
+    pub(crate) tree_file: Option<PathBuf>,
+}
+
+/// Number of sequences, number of MSA columns and score of an aligned gene of a batch run, or
+/// the reason why it could not be aligned.
+pub(crate) type GeneOutcome = std::result::Result<(usize, usize, f64), String>;
+
+const FASTA_EXTENSIONS: [&str; 5] = ["fasta", "fa", "fas", "fna", "faa"];
+
+/// Genes of a batch run from the fasta files in a directory, named after the file names without
+/// the extension and sorted by name.
+pub(crate) fn genes_in_dir(dir: &Path) -> Result<Vec<BatchGene>> {
+    let mut genes = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_fasta = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .is_some_and(|ext| FASTA_EXTENSIONS.contains(&ext.as_str()));
+        if path.is_file() && is_fasta {
+            genes.push(BatchGene {
+                name: path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                seq_file: path,
+                tree_file: None,
+            });
+        }
+    }
+    if genes.is_empty() {
+        bail!("No fasta files found in {}.", dir.display());
+    }
+    genes.sort_by(|a, b| a.name.cmp(&b.name));
+    check_gene_names(&genes)?;
+    info!("Found {} genes in {}.", genes.len(), dir.display());
+    Ok(genes)
+}
+
+/// Genes of a batch run from a manifest, see `parse_manifest`. Relative paths in the manifest
+/// are relative to its directory.
+pub(crate) fn read_manifest(manifest: &Path) -> Result<Vec<BatchGene>> {
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    let genes = parse_manifest(&std::fs::read_to_string(manifest)?, base)?;
+    info!("Read {} genes from {}.", genes.len(), manifest.display());
+    Ok(genes)
+}
+
+/// Parses a manifest with a tab-separated line per gene: its name, its fasta file and
+/// optionally its tree file. Empty lines and lines starting with # are skipped.
+fn parse_manifest(manifest: &str, base: &Path) -> Result<Vec<BatchGene>> {
+    let mut genes = Vec::new();
+    for (number, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let (name, seq_file, tree_file) = match fields[..] {
+            [name, seq_file] => (name, seq_file, None),
+            [name, seq_file, tree_file] => (name, seq_file, Some(tree_file)),
+            _ => bail!(
+                "Line {} of the manifest must hold a gene name, a sequence file and optionally a tree file.",
+                number + 1
+            ),
+        };
+        genes.push(BatchGene {
+            name: name.to_string(),
+            seq_file: base.join(seq_file),
+            tree_file: tree_file
+                .filter(|tree_file| !tree_file.is_empty())
+                .map(|tree_file| base.join(tree_file)),
+        });
+    }
+    if genes.is_empty() {
+        bail!("The manifest does not list any genes.");
+    }
+    check_gene_names(&genes)?;
+    Ok(genes)
+}
+
+fn check_gene_names(genes: &[BatchGene]) -> Result<()> {
+    let mut names = HashSet::new();
+    for gene in genes {
+        if !names.insert(gene.name.as_str()) {
+            bail!("Gene {} appears more than once in the batch.", gene.name);
+        }
+    }
+    Ok(())
+}
+
+/// Writes a line per gene of a batch run with its number of sequences, number of MSA columns
+/// and score, or the error for the genes that could not be aligned.
+pub(crate) fn write_batch_summary(
+    genes: &[BatchGene],
+    outcomes: &[GeneOutcome],
+    path: &Path,
+) -> Result<()> {
+    info!("Writing the summary of the batch to {}.", path.display());
+    let mut table = String::from("gene\tsequences\tcolumns\tscore\terror\n");
+    for (gene, outcome) in genes.iter().zip(outcomes) {
+        match outcome {
+            Ok((sequences, columns, score)) => table.push_str(&format!(
+                "{}\t{}\t{}\t{}\t\n",
+                gene.name, sequences, columns, score
+            )),
+            Err(error) => table.push_str(&format!(
+                "{}\t\t\t\t{}\n",
+                gene.name,
+                error.replace(['\t', '\n'], " ")
+            )),
+        }
+    }
+    std::fs::write(path, table)?;
+    Ok(())
+}
+
+fn tsv_path(output: &Path, label: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    output.with_file_name(format!("{}.{}.tsv", stem, label))
+}
+
+fn tree_path(output: &Path, label: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    output.with_file_name(format!("{}.{}.newick", stem, label))
+}
+
+fn labeled_path(output: &Path, label: &str) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match output.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, label, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, label),
+    };
+    output.with_file_name(file_name)
+}
+
+fn numbered_path(output: &Path, label: &str, number: usize) -> PathBuf {
+    let stem = output
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file_name = match output.extension() {
+        Some(ext) => format!("{}.{}{}.{}", stem, label, number, ext.to_string_lossy()),
+        None => format!("{}.{}{}", stem, label, number),
+    };
+    output.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod files_tests {
+    use super::{
+        consensus_path, guide_tree_path, indel_stats_json, indel_stats_table, is_stdio,
+        labeled_tree_path, maf_source, make_ids_unique, matrix_path, msa_to_maf, nni_tree_path,
+        npy_bytes, parse_manifest, restart_scores_path, sample_path, suboptimal_path,
+        traceback_paths_table,
+    };
+    use bio::io::fasta::Record;
+    use parsimony::parsimony_alignment::indels::{IndelEvent, IndelKind, IndelStats};
+    use parsimony::parsimony_alignment::metrics::MetricsSnapshot;
+    use parsimony::parsimony_alignment::msa_scoring::TracebackStep;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Leaf as L, Tree};
+    use serde_json::Value;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn sample_paths() {
+        assert_eq!(
+            sample_path(&PathBuf::from("out/msa.fasta"), 2),
+            PathBuf::from("out/msa.sample2.fasta")
+        );
+        assert_eq!(
+            sample_path(&PathBuf::from("msa"), 10),
+            PathBuf::from("msa.sample10")
+        );
+        assert_eq!(
+            suboptimal_path(&PathBuf::from("msa.fa"), 1),
+            PathBuf::from("msa.subopt1.fa")
+        );
+        assert_eq!(
+            consensus_path(&PathBuf::from("out/msa.fasta")),
+            PathBuf::from("out/msa.consensus.fasta")
+        );
+        assert_eq!(
+            guide_tree_path(&PathBuf::from("out/msa.fasta")),
+            PathBuf::from("out/msa.guide_tree.newick")
+        );
+        assert_eq!(
+            nni_tree_path(&PathBuf::from("msa.fasta")),
+            PathBuf::from("msa.nni_tree.newick")
+        );
+        assert_eq!(
+            labeled_tree_path(&PathBuf::from("out/anc.fasta")),
+            PathBuf::from("out/anc.labeled_tree.newick")
+        );
+        assert_eq!(
+            restart_scores_path(&PathBuf::from("out/msa.fasta")),
+            PathBuf::from("out/msa.restarts.tsv")
+        );
+        assert_eq!(
+            matrix_path(&PathBuf::from("out/msa.fasta"), "N3", "M", "npy"),
+            PathBuf::from("out/msa.N3.M.npy")
+        );
+    }
+
+    #[test]
+    fn npy_matrix() {
+        let bytes = npy_bytes(&[vec![0.0, 1.5], vec![f64::INFINITY, 2.0]]);
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'shape': (2, 2)") && header.ends_with('\n'));
+        assert_eq!(bytes.len(), 10 + header_len + 4 * 8);
+        assert_eq!(
+            &bytes[10 + header_len + 8..10 + header_len + 16],
+            &1.5f64.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn stdio_paths() {
+        assert!(is_stdio(&PathBuf::from("-")));
+        assert!(!is_stdio(&PathBuf::from("msa.fasta")));
+    }
+
+    #[test]
+    fn unique_ids() {
+        let mut sequences: Vec<Record> = ["A", "B", "A", "A_2", "A"]
+            .iter()
+            .map(|id| Record::with_attrs(id, Some("desc"), b"ACGT"))
+            .collect();
+        let renamed = make_ids_unique(&mut sequences);
+        let ids: Vec<&str> = sequences.iter().map(|record| record.id()).collect();
+        assert_eq!(ids, vec!["A", "B", "A_3", "A_2", "A_4"]);
+        assert_eq!(
+            renamed,
+            vec![
+                ("A".to_string(), "A_3".to_string()),
+                ("A".to_string(), "A_4".to_string())
+            ]
+        );
+        assert_eq!(sequences[2].desc(), Some("desc"));
+        assert!(make_ids_unique(&mut sequences).is_empty());
+    }
+
+    #[test]
+    fn maf_output() {
+        assert_eq!(
+            maf_source("chr1:1,001-1,004", 4),
+            super::MafSource {
+                name: "chr1".to_string(),
+                start: 1000,
+                size: 4,
+                src_size: 1004,
+            }
+        );
+        assert_eq!(maf_source("seq:A", 3).name, "seq:A");
+        let msa = [
+            Record::with_attrs("hg38.chr1:11-14", None, b"AC-GT"),
+            Record::with_attrs("mm10", None, b"ACTG-"),
+        ];
+        assert_eq!(
+            msa_to_maf(&msa, 2.5),
+            "##maf version=1 program=indelMaP\n\na score=2.5\n\
+             s hg38.chr1 10 4 + 14 AC-GT\n\
+             s mm10      0 4 + 4 ACTG-\n\n"
+        );
+    }
+
+    #[test]
+    fn manifest() {
+        let genes = parse_manifest(
+            "# gene\tsequences\ttree\nrbcL\trbcL.fasta\trbcL.newick\n\nmatK\t/data/matK.fa\n",
+            Path::new("genes"),
+        )
+        .unwrap();
+        assert_eq!(genes.len(), 2);
+        assert_eq!(genes[0].name, "rbcL");
+        assert_eq!(genes[0].seq_file, PathBuf::from("genes/rbcL.fasta"));
+        assert_eq!(genes[0].tree_file, Some(PathBuf::from("genes/rbcL.newick")));
+        assert_eq!(genes[1].seq_file, PathBuf::from("/data/matK.fa"));
+        assert_eq!(genes[1].tree_file, None);
+        assert!(parse_manifest("rbcL\n", Path::new("")).is_err());
+        assert!(parse_manifest("a\ta.fa\na\tb.fa\n", Path::new("")).is_err());
+        assert!(parse_manifest("# nothing\n", Path::new("")).is_err());
+    }
+
+    #[test]
+    fn traceback_paths_output() {
+        let step = |cell, state, gap_open, cost, column| TracebackStep {
+            cell,
+            state,
+            gap_open,
+            cost,
+            column,
+        };
+        let paths = [
+            vec![
+                step((1, 1), 'M', false, 0.0, 0),
+                step((1, 2), 'Y', true, 2.5, 2),
+            ],
+            vec![step((1, 0), 'F', false, 0.0, 1)],
+        ];
+        assert_eq!(
+            traceback_paths_table(&paths),
+            "node\tstep\ti\tj\tstate\tgap_open\tcost\tcolumn\n\
+             N1\t1\t1\t1\tM\tfalse\t0\t1\nN1\t2\t1\t2\tY\ttrue\t2.5\t3\n\
+             N2\t1\t1\t0\tF\tfalse\t0\t2\n"
+        );
+        assert_eq!(traceback_paths_table(&[]).lines().count(), 1);
+    }
+
+    #[test]
+    fn indel_stats_outputs() {
+        let msa = [
+            Record::with_attrs("A", None, b"AC"),
+            Record::with_attrs("B", None, b"A-"),
+        ];
+        let mut tree = Tree::new(&msa).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.5, 0.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&msa, tree).unwrap();
+        let events = [IndelEvent {
+            node: L(0),
+            kind: IndelKind::Insertion,
+            start: 1,
+            end: 2,
+            length: 1,
+        }];
+        let stats = IndelStats::new(&events, &info.tree);
+        assert_eq!(
+            indel_stats_table(&stats, &info),
+            "kind\tcount\tmean_length\ninsertion\t1\t1\ndeletion\t0\tNA\n\n\
+             length\tinsertions\tdeletions\n1\t1\t0\n\n\
+             node\tbranch_length\tinsertions\tdeletions\trate\nA\t0.5\t1\t0\t2\nB\t0\t0\t0\tNA\n"
+        );
+        let json: Value = serde_json::from_str(&indel_stats_json(&stats, &info)).unwrap();
+        assert_eq!(json["insertions"], 1);
+        assert_eq!(json["mean_insertion_length"], 1.0);
+        assert!(json["mean_deletion_length"].is_null());
+        assert_eq!(json["lengths"][0]["insertions"], 1);
+        assert_eq!(json["branches"][0]["rate"], 2.0);
+        assert_eq!(json["branches"][1]["node"], "B");
+        assert!(json["branches"][1]["rate"].is_null());
+    }
+}
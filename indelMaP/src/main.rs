@@ -1,133 +1,1358 @@
 #![allow(non_snake_case)]
-use crate::cli::Cli;
-use anyhow::Error;
+use crate::cli::{
+    AddArgs, AlignArgs, AncestorsArgs, BatchArgs, BootstrapArgs, CategoryPolicy, CheckArgs, Cli,
+    Command, CompareArgs, ConsensusPolicy, GapFunctionOption, MatrixFormat, MergeArgs, ModelArgs,
+    MsaFormat, ReverseStrandPolicy, ScoreArgs, SoftMaskPolicy, TreeArgs,
+};
+use crate::files::{BatchGene, GeneOutcome};
+use crate::metadata::RunMetadata;
+use anyhow::{bail, Error};
+use bio::io::fasta::Record;
 use clap::Parser;
-use log::{error, info, LevelFilter};
-use parsimony::parsimony_alignment::pars_align_on_tree;
-use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
+use log::{error, info, warn, LevelFilter};
+use parsimony::parsimony_alignment::aligner::IndelMapAligner;
+use parsimony::parsimony_alignment::batch::{batch_branch_lengths, pars_align_batch, AlignmentJob};
+use parsimony::parsimony_alignment::bootstrap::pars_bootstrap_msa;
+use parsimony::parsimony_alignment::branch_lengths::{pars_branch_lengths, BranchLengthModel};
+use parsimony::parsimony_alignment::checkpoint::pars_align_on_tree_w_checkpoint;
+use parsimony::parsimony_alignment::codon::{
+    codon_alphabet, codon_phylo_info, codon_scoring, expand_codon_msa, translated_phylo_info,
+};
+use parsimony::parsimony_alignment::consensus::{consensus_sequence, ConsensusRule};
+use parsimony::parsimony_alignment::constraints::AnchorConstraints;
+use parsimony::parsimony_alignment::gap_normalization::normalize_gaps;
+use parsimony::parsimony_alignment::gap_profiles::GapProfiles;
+use parsimony::parsimony_alignment::guide_tree::{estimate_tree_from_msa, internal_node_label};
+use parsimony::parsimony_alignment::indels::{pars_indel_events, IndelStats};
+use parsimony::parsimony_alignment::inspection::{pars_node_matrices, DpState};
+use parsimony::parsimony_alignment::metrics::RunMetrics;
+use parsimony::parsimony_alignment::msa_comparison::{column_confidence, compare_msas};
+use parsimony::parsimony_alignment::msa_scoring::{
+    pars_ancestors_msa, pars_ancestral_gaps, pars_column_scores, pars_score_msa,
+    pars_traceback_paths,
+};
+use parsimony::parsimony_alignment::pairwise::pars_align_sequences;
+use parsimony::parsimony_alignment::parallel::pars_align_on_tree_parallel;
+use parsimony::parsimony_alignment::parsimony_costs::cost_cache::CostCache;
+use parsimony::parsimony_alignment::parsimony_costs::dna_models::{
+    empirical_frequencies, params_w_frequencies,
+};
+use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_matrix::{
+    is_score_matrix, ParsimonyCostsMatrix,
+};
+use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+    breakpoint_times, scoring_times, GammaRates, GapMultipliers,
+};
 use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
-    DNAParsCosts, ProteinParsCosts,
+    DNAParsCosts, ParsimonyCostsWModel, ProteinParsCosts,
+};
+use parsimony::parsimony_alignment::parsimony_costs::protein_models::empirical_aminoacid_frequencies;
+use parsimony::parsimony_alignment::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
+use parsimony::parsimony_alignment::partitions::{concatenate_alignments, Partitions};
+use parsimony::parsimony_alignment::profile_alignment::{pars_add_to_msa, pars_merge_msas};
+use parsimony::parsimony_alignment::recoding::Recoding;
+use parsimony::parsimony_alignment::refinement::pars_refine_msa;
+use parsimony::parsimony_alignment::region_masks::RegionMasks;
+use parsimony::parsimony_alignment::restarts::{
+    pars_restarts_on_tree, restart_seed, summarize_scores,
 };
-use phylo::alignment::{compile_alignment_representation, Alignment};
-use phylo::io;
-use phylo::phylo_info::{phyloinfo_from_files, PhyloInfo};
-use phylo::sequences::{get_sequence_type, SequenceType};
-use phylo::tree::{get_percentiles_rounded, NodeIdx};
+use parsimony::parsimony_alignment::sampling::pars_sample_on_tree;
+use parsimony::parsimony_alignment::sequence_weights::SequenceWeighting;
+use parsimony::parsimony_alignment::strand::{reverse_complement, reverse_strand_sequences};
+use parsimony::parsimony_alignment::suboptimal::{pars_suboptimal_on_tree, NodeAlternatives};
+use parsimony::parsimony_alignment::tree_input::{NameMatching, TreeInputOptions};
+use parsimony::parsimony_alignment::tree_search::pars_nni_search;
+use parsimony::parsimony_alignment::trimming::trim_gappy_columns;
+use parsimony::parsimony_alignment::{
+    estimate_tree_dp, format_bytes, pars_align_on_tree_w_options, AlignmentOptions,
+    AlignmentResult, DataType, GapFunction, ScoreRows, SoftMask, TieBreak,
+};
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::sequences::{SequenceType, AMINOACIDS_STR, NUCLEOTIDES_STR};
+use phylo::tree::NodeIdx;
 use phylo::Rounding;
-use pretty_env_logger::env_logger::Builder;
-use std::path::PathBuf;
+use pretty_env_logger::env_logger::{Builder, Target};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::result::Result::Ok;
+use std::sync::Arc;
+use std::time::Instant;
 
 mod cli;
+mod config;
+mod files;
+mod metadata;
+mod presets;
+mod progress;
+mod warnings;
 
 type Result<T> = std::result::Result<T, Error>;
 
-pub fn indel_map_align_dna(
-    info: &PhyloInfo,
-    model_name: String,
-    model_params: Vec<f64>,
+fn dna_scoring(
+    model_name: &str,
+    model_params: &[f64],
     gap_mult: &GapMultipliers,
-    categories: u32,
-) -> Result<(Vec<Alignment>, Vec<f64>)> {
-    indel_map_align_dna_rounded(
-        info,
-        model_name,
-        model_params,
-        gap_mult,
-        categories,
-        &Rounding::none(),
-    )
+    times: &[f64],
+    rounding: &Rounding,
+    gamma: &GammaRates,
+    cache: Option<&CostCache>,
+) -> Result<DNAParsCosts> {
+    Ok(match cache {
+        Some(cache) => DNAParsCosts::new_cached(
+            model_name,
+            model_params,
+            gap_mult,
+            times,
+            false,
+            rounding,
+            gamma,
+            cache,
+        )?,
+        None => DNAParsCosts::new_w_gamma(
+            model_name,
+            model_params,
+            gap_mult,
+            times,
+            false,
+            rounding,
+            gamma,
+        )?,
+    })
 }
 
-pub(crate) fn indel_map_align_dna_rounded(
-    info: &PhyloInfo,
-    model_name: String,
-    model_params: Vec<f64>,
+fn protein_scoring(
+    model_name: &str,
     gap_mult: &GapMultipliers,
-    categories: u32,
+    times: &[f64],
     rounding: &Rounding,
-) -> Result<(Vec<Alignment>, Vec<f64>)> {
-    let times = get_percentiles_rounded(&info.tree.get_all_branch_lengths(), categories, rounding);
-    let scoring = DNAParsCosts::new(
-        &model_name,
-        &model_params,
-        gap_mult,
-        &times,
-        false,
-        rounding,
-    )?;
-    Ok(pars_align_on_tree(&scoring, info))
+    gamma: &GammaRates,
+    cache: Option<&CostCache>,
+) -> Result<ProteinParsCosts> {
+    Ok(match cache {
+        Some(cache) => ProteinParsCosts::new_cached(
+            model_name, gap_mult, times, false, rounding, gamma, cache,
+        )?,
+        None => ProteinParsCosts::new_w_gamma(model_name, gap_mult, times, false, rounding, gamma)?,
+    })
 }
 
-pub fn indel_map_align_protein(
+/// Cache of the model cost matrices, in the cache directory of the options or the default one,
+/// none with --no-cache or if there is no default directory.
+fn cost_cache(model: &ModelArgs) -> Option<CostCache> {
+    if model.no_cache {
+        return None;
+    }
+    model
+        .cache_dir
+        .clone()
+        .or_else(CostCache::default_dir)
+        .map(CostCache::new)
+}
+
+/// Scoring for the given sequence type, for runs that need the scoring beyond a single
+/// alignment on the tree. A cost matrix replaces the model costs and keeps the gap penalties as
+/// absolute costs, the BLOSUM and PAM matrices are used for all branch lengths.
+pub(crate) fn model_scoring(
     info: &PhyloInfo,
-    model_name: String,
-    _: Vec<f64>,
-    gap_mult: &GapMultipliers,
-    categories: u32,
-) -> Result<(Vec<Alignment>, Vec<f64>)> {
-    indel_map_align_protein_rounded(
-        info,
-        model_name,
-        vec![],
-        gap_mult,
-        categories,
-        &Rounding::none(),
+    sequence_type: &SequenceType,
+    model: &ModelArgs,
+    partitions: Option<&Partitions>,
+) -> Result<Box<dyn ParsimonyCosts + Sync>> {
+    sequences_scoring(
+        &info.sequences,
+        &info.tree.get_all_branch_lengths(),
+        sequence_type,
+        model,
+        partitions,
     )
 }
 
-pub(crate) fn indel_map_align_protein_rounded(
+/// Scoring like `model_scoring` for the `sequences` of trees with the branch lengths
+/// `branch_lengths`, e.g. of the genes of a batch that share their scoring.
+fn sequences_scoring(
+    sequences: &[Record],
+    branch_lengths: &[f64],
+    sequence_type: &SequenceType,
+    model: &ModelArgs,
+    partitions: Option<&Partitions>,
+) -> Result<Box<dyn ParsimonyCosts + Sync>> {
+    if let Some(path) = &model.cost_matrix {
+        let scoring = ParsimonyCostsMatrix::from_file(path, model.go, model.ge)?;
+        scoring.check_symbols(match sequence_type {
+            SequenceType::DNA => NUCLEOTIDES_STR.as_bytes(),
+            SequenceType::Protein => AMINOACIDS_STR.as_bytes(),
+        })?;
+        return Ok(Box::new(scoring));
+    }
+    let model_name = model.model_name();
+    let gap_mult = gap_multipliers(model);
+    if is_score_matrix(model_name) {
+        if model.empirical_freqs() {
+            bail!("Empirical frequencies need a substitution model, not a scoring matrix.");
+        }
+        if partitions.is_some() {
+            bail!("Partitions need a substitution model, not a scoring matrix.");
+        }
+        if !matches!(sequence_type, SequenceType::Protein) {
+            bail!(
+                "The {} scoring matrix is only for protein sequences.",
+                model_name
+            );
+        }
+        return Ok(Box::new(ParsimonyCostsMatrix::from_score_matrix(
+            model_name, &gap_mult,
+        )?));
+    }
+    let rounding = Rounding::none();
+    let times = model_times(branch_lengths, model)?;
+    // With site rate categories the costs of a branch are those of rate 1 and every category
+    // has its own matrices, which are not cached.
+    let (gamma, site_rates, cache) = if model.site_rates {
+        (GammaRates::none(), Some(gamma_rates(model)?), None)
+    } else {
+        (gamma_rates(model)?, None, cost_cache(model))
+    };
+    Ok(match sequence_type {
+        SequenceType::DNA => {
+            let scoring = |model_name: &str, rates: &[f64]| -> Result<DNAParsCosts> {
+                dna_scoring(
+                    model_name,
+                    &dna_params(sequences, model_name, rates, model.empirical_freqs())?,
+                    &gap_mult,
+                    &times,
+                    &rounding,
+                    &gamma,
+                    cache.as_ref(),
+                )
+            };
+            Box::new(with_site_categories(
+                scoring(model_name, &model.model_params)?,
+                site_rates.as_ref(),
+                partitions,
+                scoring,
+            )?)
+        }
+        SequenceType::Protein if model.empirical_freqs() => {
+            let freqs = empirical_aminoacid_frequencies(sequences);
+            info!("Empirical amino acid frequencies: {:?}", freqs);
+            let scoring = |model_name: &str, params: &[f64]| -> Result<ProteinParsCosts> {
+                check_protein_params(model_name, params)?;
+                Ok(ProteinParsCosts::new_w_frequencies(
+                    model_name, &freqs, &gap_mult, &times, false, &rounding, &gamma,
+                )?)
+            };
+            Box::new(with_site_categories(
+                scoring(model_name, &[])?,
+                site_rates.as_ref(),
+                partitions,
+                scoring,
+            )?)
+        }
+        SequenceType::Protein => {
+            let scoring = |model_name: &str, params: &[f64]| -> Result<ProteinParsCosts> {
+                check_protein_params(model_name, params)?;
+                protein_scoring(
+                    model_name,
+                    &gap_mult,
+                    &times,
+                    &rounding,
+                    &gamma,
+                    cache.as_ref(),
+                )
+            };
+            Box::new(with_site_categories(
+                scoring(model_name, &[])?,
+                site_rates.as_ref(),
+                partitions,
+                scoring,
+            )?)
+        }
+    })
+}
+
+/// The protein models have no parameters, those given for a protein partition are an error
+/// rather than being ignored.
+fn check_protein_params(model_name: &str, params: &[f64]) -> Result<()> {
+    if !params.is_empty() {
+        bail!(
+            "The {} protein model has no parameters, got {:?}.",
+            model_name,
+            params
+        );
+    }
+    Ok(())
+}
+
+/// Adds the site categories of the options to the scoring, the site rate categories or the
+/// partitions, whose scorings `partition_scoring` builds from their model and parameters.
+fn with_site_categories<const N: usize>(
+    scoring: ParsimonyCostsWModel<N>,
+    site_rates: Option<&GammaRates>,
+    partitions: Option<&Partitions>,
+    partition_scoring: impl Fn(&str, &[f64]) -> Result<ParsimonyCostsWModel<N>>,
+) -> Result<ParsimonyCostsWModel<N>> {
+    let scoring = match site_rates {
+        Some(rates) => scoring.with_site_rates(rates),
+        None => scoring,
+    };
+    let Some(partitions) = partitions else {
+        return Ok(scoring);
+    };
+    let partition_scorings = partitions
+        .iter()
+        .map(|partition| partition_scoring(&partition.model, &partition.params))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(scoring.with_partitions(partition_scorings)?)
+}
+
+/// Aligns the partitions of different data types separately on the tree, each with the scoring
+/// of its model for its data type. The positions of the partition file are columns of the input,
+/// so the sequences must be aligned and every column must be in a partition. Returns the
+/// sequences of the concatenated partitions and their alignment, whose MSA has the columns of
+/// the partitions in the order of the partition file.
+fn align_mixed_partitions(
     info: &PhyloInfo,
-    model_name: String,
-    _: Vec<f64>,
-    gap_mult: &GapMultipliers,
-    categories: u32,
-    rounding: &Rounding,
-) -> Result<(Vec<Alignment>, Vec<f64>)> {
-    let times = get_percentiles_rounded(&info.tree.get_all_branch_lengths(), categories, rounding);
-    let scoring = ProteinParsCosts::new(&model_name, gap_mult, &times, false, rounding)?;
-    Ok(pars_align_on_tree(&scoring, info))
+    model: &ModelArgs,
+    options: &AlignmentOptions,
+) -> Result<(PhyloInfo, AlignmentResult)> {
+    let Some(partitions) = &options.partitions else {
+        bail!("There are no partitions to align.");
+    };
+    let align_block = |sequences: &[Record], data_type: DataType, block_model: ModelArgs| {
+        let block_info = phyloinfo_from_sequences_tree(sequences, info.tree.clone())?;
+        let sequence_type = data_type.sequence_type(sequences);
+        let scoring = model_scoring(&block_info, &sequence_type, &block_model, None)?;
+        let block_options = AlignmentOptions {
+            data_type,
+            partitions: None,
+            ..options.clone()
+        };
+        let result = pars_align_on_tree_w_options(scoring.as_ref(), &block_info, &block_options)?;
+        Ok::<_, Error>((block_info, result))
+    };
+    let columns = info.sequences[0].seq().len();
+    if let Some(record) = info
+        .sequences
+        .iter()
+        .find(|record| record.seq().len() != columns)
+    {
+        bail!(
+            "Partitions of different data types need aligned sequences, as their positions are columns of the input, but {} has {} columns instead of {}.",
+            record.id(),
+            record.seq().len(),
+            columns
+        );
+    }
+    let uncovered = partitions.unpartitioned_sequences(&info.sequences[..1])[0]
+        .seq()
+        .len();
+    if uncovered > 0 {
+        bail!(
+            "{} columns of the input are in none of the partitions, partitions of different data types must cover all the columns.",
+            uncovered
+        );
+    }
+    if !partitions.keeps_positions() {
+        warn!("The partitions are not in the order of their positions, the columns of the MSA follow the order of the partition file.");
+    }
+    let mut blocks = Vec::new();
+    for partition in partitions.iter() {
+        info!(
+            "Aligning the partition {} with the {} model.",
+            partition.name, partition.model
+        );
+        blocks.push(align_block(
+            &partition.sequences(&info.sequences),
+            partition.data_type,
+            ModelArgs {
+                model: Some(partition.model.clone()),
+                model_params: partition.params.clone(),
+                partitions: None,
+                ..model.clone()
+            },
+        )?);
+    }
+    Ok(concatenate_alignments(&blocks)?)
+}
+
+/// Partitions of the sequences from the partition file of the options.
+fn model_partitions(model: &ModelArgs) -> Result<Option<Partitions>> {
+    Ok(model
+        .partitions
+        .as_deref()
+        .map(Partitions::from_file)
+        .transpose()?)
+}
+
+/// Branch lengths to create the cost matrices for, the given breakpoints or otherwise the
+/// percentile categories of the branch lengths of the tree.
+fn model_times(branch_lengths: &[f64], model: &ModelArgs) -> Result<Vec<f64>> {
+    if model.category_breakpoints.is_empty() {
+        Ok(scoring_times(
+            branch_lengths,
+            model.categories,
+            model.category_strategy.into(),
+            &Rounding::none(),
+        ))
+    } else {
+        Ok(breakpoint_times(&model.category_breakpoints)?)
+    }
+}
+
+/// Parameters of the DNA model, with --empirical-freqs the frequencies of the sequences come
+/// in front of the given rates.
+fn model_params(info: &PhyloInfo, model: &ModelArgs) -> Result<Vec<f64>> {
+    dna_params(
+        &info.sequences,
+        model.model_name(),
+        &model.model_params,
+        model.empirical_freqs(),
+    )
+}
+
+/// Parameters of the DNA model `model_name`, `rates` with the frequencies of the sequences in
+/// front of them for empirical frequencies.
+fn dna_params(
+    sequences: &[Record],
+    model_name: &str,
+    rates: &[f64],
+    empirical_freqs: bool,
+) -> Result<Vec<f64>> {
+    if !empirical_freqs {
+        return Ok(rates.to_vec());
+    }
+    let freqs = empirical_frequencies(sequences);
+    info!("Empirical nucleotide frequencies (T, C, A, G): {:?}", freqs);
+    Ok(params_w_frequencies(model_name, rates, freqs)?)
+}
+
+/// Substitution model of the run for re-estimating the branch lengths, with the frequencies of
+/// the sequences for empirical frequencies and the gamma rate categories.
+fn branch_length_model(
+    info: &PhyloInfo,
+    sequence_type: &SequenceType,
+    model: &ModelArgs,
+) -> Result<BranchLengthModel> {
+    let gamma = gamma_rates(model)?;
+    Ok(match sequence_type {
+        SequenceType::DNA => {
+            BranchLengthModel::dna(model.model_name(), &model_params(info, model)?, &gamma)?
+        }
+        SequenceType::Protein => {
+            let freqs = model
+                .empirical_freqs()
+                .then(|| empirical_aminoacid_frequencies(&info.sequences));
+            BranchLengthModel::protein(model.model_name(), freqs.as_ref(), &gamma)?
+        }
+    })
+}
+
+/// Gap multipliers of the options, affine in the branch length if gap slopes are given.
+fn gap_multipliers(model: &ModelArgs) -> GapMultipliers {
+    let gap_mult = GapMultipliers::new(model.go, model.ge);
+    match model.gap_slopes.as_deref() {
+        Some(&[open_slope, ext_slope]) => gap_mult.with_branch_slopes(open_slope, ext_slope),
+        _ => gap_mult,
+    }
+}
+
+fn gamma_rates(model: &ModelArgs) -> Result<GammaRates> {
+    match model.gamma {
+        Some(alpha) => Ok(GammaRates::new(alpha, model.gamma_categories)?),
+        None => Ok(GammaRates::none()),
+    }
+}
+
+/// Per-site gap penalty multipliers from the gap profile file, or the hydropathy or secondary
+/// structure of the protein sequences.
+fn gap_profiles(
+    args: &AlignArgs,
+    info: &PhyloInfo,
+    sequence_type: &SequenceType,
+) -> Result<Option<GapProfiles>> {
+    if let Some(path) = &args.gap_profile {
+        let profiles = GapProfiles::from_file(path)?;
+        profiles.check_sequences(&info.sequences)?;
+        return Ok(Some(profiles));
+    }
+    if args.hydropathy_gaps {
+        if !matches!(sequence_type, SequenceType::Protein) {
+            bail!("The hydropathy gap penalties are only for protein sequences.");
+        }
+        return Ok(Some(GapProfiles::from_hydropathy(&info.sequences)));
+    }
+    if args.secondary_structure.is_none() && !args.predict_structure {
+        return Ok(None);
+    }
+    if !matches!(sequence_type, SequenceType::Protein) {
+        bail!("The secondary structure gap penalties are only for protein sequences.");
+    }
+    if args.helix_gap_weight < 0.0 || args.strand_gap_weight < 0.0 {
+        bail!("The helix and strand gap weights must not be negative.");
+    }
+    let (helix, strand) = (args.helix_gap_weight, args.strand_gap_weight);
+    let profiles = match &args.secondary_structure {
+        Some(path) => GapProfiles::from_secondary_structure_file(path, helix, strand)?,
+        None => GapProfiles::from_predicted_structure(&info.sequences, helix, strand),
+    };
+    profiles.check_sequences(&info.sequences)?;
+    Ok(Some(profiles))
+}
+
+/// Writes the MSAs that use the next best alternatives at the root instead of the optimal
+/// alignment, the alignments below the root are the same as in the output MSA.
+fn write_suboptimal(
+    info: &PhyloInfo,
+    result: &AlignmentResult,
+    alternatives: NodeAlternatives,
+    out_msa_path: &Path,
+    k: usize,
+) -> Result<()> {
+    let root = Into::<usize>::into(info.tree.root);
+    let below_root = result.score() - result.scores[root];
+    let optimal = &result.alignments[root];
+    let suboptimal = alternatives
+        .into_iter()
+        .filter(|(alignment, _)| {
+            alignment.map_x != optimal.map_x || alignment.map_y != optimal.map_y
+        })
+        .take(k);
+    let mut suboptimal_result = result.clone();
+    for (rank, (alignment, score)) in suboptimal.enumerate() {
+        let path = files::suboptimal_path(out_msa_path, rank + 1);
+        info!(
+            "Writing suboptimal alignment {} with score {} to {}.",
+            rank + 1,
+            below_root + score,
+            path.display()
+        );
+        suboptimal_result.alignments[root] = alignment;
+        files::write_msa(&suboptimal_result.msa(info), path)?;
+    }
+    Ok(())
+}
+
+/// Writes the MSA of the clade below every internal node to the directory. The codon MSAs of a
+/// codon alignment are expanded to nucleotides.
+fn write_clade_msas(
+    info: &PhyloInfo,
+    codon_info: Option<&PhyloInfo>,
+    result: &AlignmentResult,
+    dir: &Path,
+) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    info!(
+        "Writing the MSAs of the {} clades to {}.",
+        result.alignments.len(),
+        dir.display()
+    );
+    for node in 0..result.alignments.len() {
+        let msa = match codon_info {
+            Some(codon_info) => {
+                expand_codon_msa(&result.clade_msa(codon_info, node), &info.sequences)?
+            }
+            None => result.clade_msa(info, node),
+        };
+        files::write_msa(
+            &msa,
+            dir.join(format!("{}.fasta", internal_node_label(node))),
+        )?;
+    }
+    Ok(())
+}
+
+/// Consensus sequences of the clade MSAs of the internal nodes, named after the labels of the
+/// nodes. The codon MSAs of a codon alignment are expanded to nucleotides.
+fn node_consensus(
+    info: &PhyloInfo,
+    codon_info: Option<&PhyloInfo>,
+    result: &AlignmentResult,
+    rule: ConsensusRule,
+    sequence_type: &SequenceType,
+) -> Result<Vec<Record>> {
+    (0..result.alignments.len())
+        .map(|node| {
+            let msa = match codon_info {
+                Some(codon_info) => {
+                    expand_codon_msa(&result.clade_msa(codon_info, node), &info.sequences)?
+                }
+                None => result.clade_msa(info, node),
+            };
+            Ok(Record::with_attrs(
+                &internal_node_label(node),
+                None,
+                &consensus_sequence(&msa, rule, sequence_type),
+            ))
+        })
+        .collect()
 }
 
 fn main() -> Result<()> {
-    Builder::new()
-        .filter_level(LevelFilter::Info)
-        .format_timestamp_secs()
-        .format_module_path(false)
-        .init();
+    let cli = Cli::try_parse_from(presets::expand_preset(config::expand_config(
+        std::env::args_os().collect(),
+    )?))?;
+    let target = match &cli.log_file {
+        Some(path) => Target::Pipe(Box::new(File::create(path)?)),
+        None => Target::Stderr,
+    };
+    warnings::init_logger(
+        Builder::new()
+            .filter_level(cli.log_level())
+            .target(target)
+            .format_timestamp_secs()
+            .format_module_path(false),
+    )?;
     info!("IndelMaP run started");
-    let cli = Cli::try_parse()?;
     info!("Successfully parsed the command line parameters");
-    let info = phyloinfo_from_files(cli.seq_file, cli.tree_file);
+    // the per node log messages already show the progress when they go to the terminal
+    let show_progress = cli.log_file.is_some() || cli.log_level() <= LevelFilter::Warn;
+    match cli.command {
+        Command::Align(args) => run_align(args, show_progress),
+        Command::Score(args) => run_score(args),
+        Command::Ancestors(args) => run_ancestors(args),
+        Command::Add(args) => run_add(args),
+        Command::Merge(args) => run_merge(args),
+        Command::Bootstrap(args) => run_bootstrap(args),
+        Command::Batch(args) => run_batch(args),
+        Command::Compare(args) => run_compare(args),
+        Command::Check(args) => run_check(args),
+        Command::Simulate => bail!("Simulation is not available yet."),
+    }
+}
+
+fn scoring_options(model: &ModelArgs, sequence_type: &SequenceType) -> Result<AlignmentOptions> {
+    let options = AlignmentOptions {
+        recoding: Recoding::from(model.recoding),
+        data_type: DataType::from(model.data_type),
+        free_end_gaps: model.free_end_gaps,
+        soft_mask: match model.soft_mask {
+            SoftMaskPolicy::Keep => SoftMask::Keep,
+            SoftMaskPolicy::Ignore => SoftMask::Ignore,
+            SoftMaskPolicy::Downweight => SoftMask::Downweight(model.soft_mask_weight),
+        },
+        sequence_weighting: SequenceWeighting::from(model.sequence_weights),
+        gap_function: match model.gap_function {
+            GapFunctionOption::Affine => GapFunction::Affine,
+            GapFunctionOption::Log => GapFunction::Logarithmic(model.gap_log_scale),
+        },
+        partitions: model_partitions(model)?,
+        ..Default::default()
+    };
+    if model.soft_mask_weight < 0.0 {
+        bail!("The soft mask weight must not be negative.");
+    }
+    if model.gap_log_scale < 0.0 {
+        bail!("The gap log scale must not be negative.");
+    }
+    if !options.recoding.supports(sequence_type) {
+        bail!(
+            "The {:?} recoding cannot be applied to {} data.",
+            options.recoding,
+            match sequence_type {
+                SequenceType::DNA => "DNA",
+                SequenceType::Protein => "protein",
+            }
+        );
+    }
+    Ok(options)
+}
+
+fn tree_input_options(tree: &TreeArgs) -> Result<TreeInputOptions> {
+    let mut matching = NameMatching {
+        ignore_case: tree.ignore_case,
+        ignore_whitespace: tree.ignore_whitespace,
+        truncate_at_space: tree.truncate_names,
+        ..Default::default()
+    };
+    if let Some(pattern) = &tree.strip_names {
+        matching = matching.with_strip_pattern(pattern)?;
+    }
+    Ok(TreeInputOptions {
+        rooting: tree.rooting.into(),
+        outgroup: tree.outgroup.clone(),
+        prune_missing: tree.prune_missing,
+        matching,
+    })
+}
+
+fn run_score(args: ScoreArgs) -> Result<()> {
+    let info = files::read_phylo_info(
+        args.msa_file,
+        args.tree_file,
+        &tree_input_options(&args.tree)?,
+    )?;
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let options = scoring_options(&args.model, &sequence_type)?;
+    let scoring = model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    let (_, scores) = pars_score_msa(scoring.as_ref(), &info, &options)?;
+    for (idx, score) in scores.iter().enumerate() {
+        info!("Score at internal node {}: {}", idx, score);
+    }
+    println!("{}", scores.iter().sum::<f64>());
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    Ok(())
+}
+
+fn run_ancestors(args: AncestorsArgs) -> Result<()> {
+    if args.indel_stats && files::is_stdio(&args.output_file) {
+        bail!("The indel summary cannot be printed together with the ancestral sequences, write them to an output file.");
+    }
+    let info = files::read_phylo_info(
+        args.msa_file,
+        args.tree_file,
+        &tree_input_options(&args.tree)?,
+    )?;
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let options = scoring_options(&args.model, &sequence_type)?;
+    let scoring = model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    let ancestors = pars_ancestors_msa(scoring.as_ref(), &info, &options)?;
+    info!(
+        "Writing {} ancestral sequences to {}.",
+        ancestors.len(),
+        args.output_file.display()
+    );
+    let labeled_tree = match args.labeled_tree {
+        Some(path) => Some(path),
+        None if files::is_stdio(&args.output_file) => None,
+        None => Some(files::labeled_tree_path(&args.output_file)),
+    };
+    files::write_msa(&ancestors, args.output_file)?;
+    if let Some(path) = &labeled_tree {
+        files::write_labeled_tree(&info, path)?;
+    }
+    if let Some(path) = &args.gap_matrix {
+        let gaps = pars_ancestral_gaps(scoring.as_ref(), &info, &options)?;
+        files::write_gap_matrix(&gaps, path)?;
+    }
+    if args.indel_events.is_some() || args.indel_stats || args.indel_stats_json.is_some() {
+        let events = pars_indel_events(scoring.as_ref(), &info, &options)?;
+        if let Some(path) = &args.indel_events {
+            files::write_indel_events(&events, &info, path)?;
+        }
+        let stats = IndelStats::new(&events, &info.tree);
+        if args.indel_stats {
+            print!("{}", files::indel_stats_table(&stats, &info));
+        }
+        if let Some(path) = &args.indel_stats_json {
+            files::write_indel_stats_json(&stats, &info, path)?;
+        }
+    }
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    Ok(())
+}
+
+fn run_add(args: AddArgs) -> Result<()> {
+    let (info, new_ids) = files::read_phylo_info_from_two_files(
+        args.msa_file,
+        args.seq_file,
+        args.tree_file,
+        &tree_input_options(&args.tree)?,
+    )?;
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let options = scoring_options(&args.model, &sequence_type)?;
+    let scoring = model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    let new_ids = new_ids.iter().map(String::as_str).collect();
+    let msa = pars_add_to_msa(scoring.as_ref(), &info, &new_ids, &options)?;
+    info!(
+        "Writing the extended MSA to {}.",
+        args.output_file.display()
+    );
+    files::write_msa(&msa, args.output_file)?;
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    Ok(())
+}
+
+fn run_merge(args: MergeArgs) -> Result<()> {
+    let (info, y_ids) = files::read_phylo_info_from_two_files(
+        args.msa_file,
+        args.other_msa_file,
+        args.tree_file,
+        &tree_input_options(&args.tree)?,
+    )?;
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let options = scoring_options(&args.model, &sequence_type)?;
+    let scoring = model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    let y_ids = y_ids.iter().map(String::as_str).collect();
+    let (msa, score) = pars_merge_msas(scoring.as_ref(), &info, &y_ids, &options)?;
+    info!("Writing the merged MSA to {}.", args.output_file.display());
+    files::write_msa(&msa, args.output_file)?;
+    println!("{}", score);
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    Ok(())
+}
+
+fn run_bootstrap(args: BootstrapArgs) -> Result<()> {
+    if !(args.confidence > 0.0 && args.confidence <= 1.0) {
+        bail!("The confidence level must be between 0 and 1.");
+    }
+    let info = files::read_phylo_info(
+        args.msa_file,
+        args.tree_file,
+        &tree_input_options(&args.tree)?,
+    )?;
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let options = scoring_options(&args.model, &sequence_type)?;
+    let scoring = model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    let seed = args.seed.unwrap_or_else(rand::random);
+    info!("Resampling the columns with seed {}.", seed);
+    let bootstrap = pars_bootstrap_msa(scoring.as_ref(), &info, args.replicates, seed, &options)?;
+    let (lower, upper) = bootstrap.confidence_interval(args.confidence);
+    info!(
+        "Score {} with {}% confidence interval [{}, {}] over {} replicates.",
+        bootstrap.score,
+        args.confidence * 100.0,
+        lower,
+        upper,
+        args.replicates
+    );
+    files::write_support_tree(&info, &bootstrap.support, &args.output_file)?;
+    println!("{}\t{}\t{}", bootstrap.score, lower, upper);
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    Ok(())
+}
+
+fn run_batch(args: BatchArgs) -> Result<()> {
+    let genes = match (&args.seq_dir, &args.manifest) {
+        (Some(dir), _) => files::genes_in_dir(dir)?,
+        (None, Some(manifest)) => files::read_manifest(manifest)?,
+        (None, None) => bail!("Either a sequence directory or a manifest is needed."),
+    };
+    std::fs::create_dir_all(&args.output_dir)?;
+    let tree_options = tree_input_options(&args.tree)?;
+    let seed = args.seed.unwrap_or_else(rand::random);
+    let infos: Vec<Result<PhyloInfo>> = genes
+        .iter()
+        .map(|gene| read_gene(gene, &args, &tree_options))
+        .collect();
+    let read: Vec<&PhyloInfo> = infos.iter().flatten().collect();
+    let data_type = DataType::from(args.model.data_type);
+    let is_dna: Vec<bool> = read
+        .iter()
+        .map(|info| matches!(data_type.sequence_type(&info.sequences), SequenceType::DNA))
+        .collect();
+    if is_dna.windows(2).any(|pair| pair[0] != pair[1]) {
+        bail!("The genes of a batch share their scoring and must have the same data type, set it with --data-type.");
+    }
+    let sequences: Vec<Record> = read
+        .iter()
+        .flat_map(|info| info.sequences.iter().cloned())
+        .collect();
+    let sequence_type = data_type.sequence_type(&sequences);
+    let options = AlignmentOptions {
+        seed: Some(seed),
+        ..scoring_options(&args.model, &sequence_type)?
+    };
+    let jobs: Vec<AlignmentJob> = read
+        .iter()
+        .map(|info| (info.sequences.clone(), info.tree.clone()))
+        .collect();
+    let scoring = sequences_scoring(
+        &sequences,
+        &batch_branch_lengths(&jobs),
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    )?;
+    info!("Aligning {} genes with seed {}.", genes.len(), seed);
+    let mut results = pars_align_batch(scoring.as_ref(), jobs, &options, args.threads)?.into_iter();
+    let outcomes: Vec<GeneOutcome> = genes
+        .iter()
+        .zip(&infos)
+        .map(|(gene, info)| {
+            let outcome = match info {
+                Ok(info) => results
+                    .next()
+                    .expect("every read gene has a result")
+                    .map_err(Error::from)
+                    .and_then(|result| write_gene(gene, info, &result, &args)),
+                Err(error) => Err(anyhow::anyhow!("{}", error)),
+            };
+            outcome.map_err(|error| {
+                error!("Gene {} could not be aligned: {}", gene.name, error);
+                error.to_string()
+            })
+        })
+        .collect();
+    files::write_batch_summary(&genes, &outcomes, &args.output_dir.join("summary.tsv"))?;
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    let failed = outcomes.iter().filter(|outcome| outcome.is_err()).count();
+    if failed > 0 {
+        bail!(
+            "{} of {} genes could not be aligned, see the summary table.",
+            failed,
+            genes.len()
+        );
+    }
+    Ok(())
+}
+
+/// Reads the sequences of a gene of a batch run with its own tree, the tree of the batch or a
+/// guide tree.
+fn read_gene(
+    gene: &BatchGene,
+    args: &BatchArgs,
+    tree_options: &TreeInputOptions,
+) -> Result<PhyloInfo> {
+    match gene.tree_file.as_ref().or(args.tree_file.as_ref()) {
+        Some(tree_file) => {
+            files::read_phylo_info(gene.seq_file.clone(), tree_file.clone(), tree_options)
+        }
+        None => files::read_phylo_info_w_guide_tree(gene.seq_file.clone(), tree_options),
+    }
+}
+
+/// Writes the MSA of an aligned gene of a batch run to the output directory.
+fn write_gene(
+    gene: &BatchGene,
+    info: &PhyloInfo,
+    result: &AlignmentResult,
+    args: &BatchArgs,
+) -> Result<(usize, usize, f64)> {
+    info!("Aligned gene {} with score {}.", gene.name, result.score());
+    let msa = result.msa(info);
+    let columns = msa.first().map_or(0, |record| record.seq().len());
+    files::write_msa(&msa, args.output_dir.join(format!("{}.fasta", gene.name)))?;
+    Ok((msa.len(), columns, result.score()))
+}
+
+fn run_compare(args: CompareArgs) -> Result<()> {
+    let msa = files::read_sequences(&args.msa_file)?;
+    let reference = files::read_sequences(&args.reference_file)?;
+    let comparison = compare_msas(&msa, &reference)?;
+    info!(
+        "Sum-of-pairs score {}, total-column score {}.",
+        comparison.sum_of_pairs, comparison.total_column
+    );
+    println!("{}\t{}", comparison.sum_of_pairs, comparison.total_column);
+    Ok(())
+}
+
+/// Length to which the sequences are cut for timing the DP fill in the check.
+const CALIBRATION_LENGTH: usize = 1000;
+
+/// Characters of the sequences that are neither residues, ambiguity codes nor gaps of the
+/// sequence type, in the order of their first appearance.
+fn unexpected_characters(record: &Record, sequence_type: &SequenceType) -> Vec<char> {
+    let expected = match sequence_type {
+        SequenceType::DNA => format!("{}URYSWKMBDHVN-", NUCLEOTIDES_STR),
+        SequenceType::Protein => format!("{}BZJX*-", AMINOACIDS_STR),
+    };
+    let mut unexpected = Vec::new();
+    for c in record.seq().to_ascii_uppercase() {
+        let c = c as char;
+        if !expected.contains(c) && !unexpected.contains(&c) {
+            unexpected.push(c);
+        }
+    }
+    unexpected
+}
+
+/// Cells per second of the DP fill, timed on the alignment of the first two sequences cut to
+/// `CALIBRATION_LENGTH` residues.
+fn calibrate_dp_speed(
+    info: &PhyloInfo,
+    scoring: &dyn ParsimonyCosts,
+    options: &AlignmentOptions,
+) -> f64 {
+    let residues = |record: &Record| {
+        let seq: Vec<u8> = record
+            .seq()
+            .iter()
+            .copied()
+            .filter(|&c| c != b'-')
+            .take(CALIBRATION_LENGTH)
+            .collect();
+        Record::with_attrs(record.id(), None, &seq)
+    };
+    let (x, y) = (residues(&info.sequences[0]), residues(&info.sequences[1]));
+    let start = Instant::now();
+    // the calibration sequences are short enough for any memory limit
+    let _ = pars_align_sequences(
+        &x,
+        &y,
+        scoring.get_branch_costs(info.tree.leaves[0].blen),
+        options,
+    );
+    let cells = (x.seq().len() + 1) * (y.seq().len() + 1);
+    cells as f64 / start.elapsed().as_secs_f64().max(1e-6)
+}
+
+fn format_seconds(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{:.1} s", seconds)
+    } else if seconds < 3600.0 {
+        format!("{:.1} min", seconds / 60.0)
+    } else {
+        format!("{:.1} h", seconds / 3600.0)
+    }
+}
+
+fn run_check(args: CheckArgs) -> Result<()> {
+    let tree_options = tree_input_options(&args.tree)?;
+    let info = match args.tree_file {
+        Some(tree_file) => files::read_phylo_info(args.seq_file, tree_file, &tree_options)?,
+        None => files::read_phylo_info_w_guide_tree(args.seq_file, &tree_options)?,
+    };
+    info!(
+        "The {} sequences match the leaves of the tree.",
+        info.sequences.len()
+    );
+    let mut problems = Vec::new();
+
+    let sequence_type = DataType::from(args.model.data_type).sequence_type(&info.sequences);
+    let kind = match sequence_type {
+        SequenceType::DNA => "DNA",
+        SequenceType::Protein => "protein",
+    };
+    for record in &info.sequences {
+        if record.seq().iter().all(|&c| c == b'-') {
+            problems.push(format!("Sequence {} is empty.", record.id()));
+        }
+        let unexpected = unexpected_characters(record, &sequence_type);
+        if !unexpected.is_empty() {
+            problems.push(format!(
+                "Sequence {} has characters that are not {}: {}.",
+                record.id(),
+                kind,
+                unexpected.iter().collect::<String>()
+            ));
+        }
+    }
+
+    let tree = &info.tree;
+    let mut zero_branches = 0;
+    for &node in tree.postorder.iter().filter(|&&node| node != tree.root) {
+        let blen = match node {
+            NodeIdx::Internal(idx) => tree.internals[idx].blen,
+            NodeIdx::Leaf(idx) => tree.leaves[idx].blen,
+        };
+        if blen.is_nan() || blen < 0.0 {
+            problems.push(format!(
+                "The branch above {} has length {}.",
+                files::node_name(&info, node),
+                blen
+            ));
+        } else if blen == 0.0 {
+            zero_branches += 1;
+        }
+    }
+    if zero_branches > 0 {
+        warn!("The tree has {} branches of length zero.", zero_branches);
+    }
+
+    let options = AlignmentOptions {
+        score_rows: ScoreRows::from(args.score_rows),
+        max_memory: args.max_memory,
+        ..scoring_options(&args.model, &sequence_type)?
+    };
+    let scoring = match model_scoring(
+        &info,
+        &sequence_type,
+        &args.model,
+        options.partitions.as_ref(),
+    ) {
+        Ok(scoring) => Some(scoring),
+        Err(error) => {
+            problems.push(format!("The scoring cannot be set up: {}", error));
+            None
+        }
+    };
+
+    // every branch has the same site categories
+    let site_categories = scoring
+        .as_ref()
+        .map_or(0, |scoring| scoring.get_branch_costs(1.0).site_categories());
+    let estimate = estimate_tree_dp(&info, &options, site_categories);
+    if let Some(max_memory) = options.max_memory {
+        if estimate.lower.peak_memory > max_memory {
+            problems.push(format!(
+                "The largest node needs at least {} for the DP matrices, more than the limit of {}.",
+                format_bytes(estimate.lower.peak_memory),
+                format_bytes(max_memory)
+            ));
+        } else if estimate.upper.peak_memory > max_memory {
+            warn!(
+                "The largest node may need up to {} for the DP matrices, more than the limit of {}.",
+                format_bytes(estimate.upper.peak_memory),
+                format_bytes(max_memory)
+            );
+        }
+    }
+    println!("sequences\t{}", info.sequences.len());
+    println!("data_type\t{}", kind);
+    println!(
+        "dp_cells\t{}-{}",
+        estimate.lower.cells, estimate.upper.cells
+    );
+    println!(
+        "peak_memory\t{}-{}",
+        format_bytes(estimate.lower.peak_memory),
+        format_bytes(estimate.upper.peak_memory)
+    );
+    if let (Some(scoring), true) = (&scoring, info.sequences.len() > 1) {
+        let speed = calibrate_dp_speed(&info, scoring.as_ref(), &options);
+        println!(
+            "time\t{}-{}",
+            format_seconds(estimate.lower.cells as f64 / speed),
+            format_seconds(estimate.upper.cells as f64 / speed)
+        );
+    }
+
+    warnings::log_warning_summary(&warnings::summarise_warnings());
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        bail!("The check found {} problem(s).", problems.len());
+    }
+    info!("The input is ready for the alignment.");
+    Ok(())
+}
+
+/// Shortest exact match that is used as an anchor of the chunked alignment.
+const MIN_ANCHOR_LENGTH: usize = 8;
+
+fn run_align(args: AlignArgs, show_progress: bool) -> Result<()> {
+    let estimate_tree = args.tree_file.is_none();
+    let tree_options = tree_input_options(&args.tree)?;
+    let info = match args.tree_file {
+        Some(tree_file) => files::read_phylo_info_renamed(
+            args.seq_file,
+            tree_file,
+            &tree_options,
+            args.auto_rename,
+        ),
+        None => files::read_phylo_info_w_guide_tree_renamed(
+            args.seq_file,
+            &tree_options,
+            args.auto_rename,
+        ),
+    };
     match info {
-        Ok(info) => {
-            let (alignment, scores) = match get_sequence_type(&info.sequences) {
-                SequenceType::DNA => {
-                    info!("Working on DNA data -- please ensure that data type is inferred correctly.");
-                    indel_map_align_dna(
-                        &info,
-                        cli.model,
-                        cli.model_params,
-                        &GapMultipliers::new(cli.go, cli.ge),
-                        cli.categories,
-                    )?
-                }
-                SequenceType::Protein => {
-                    info!("Working on protein data -- please ensure that data type is inferred correctly.");
-                    indel_map_align_protein(
-                        &info,
-                        cli.model,
-                        cli.model_params,
-                        &GapMultipliers::new(cli.go, cli.ge),
-                        cli.categories,
-                    )?
+        Ok((mut info, renamed)) => {
+            let data_type = DataType::from(args.model.data_type);
+            let sequence_type = data_type.sequence_type(&info.sequences);
+            let kind = match sequence_type {
+                SequenceType::DNA => "DNA",
+                SequenceType::Protein => "protein",
+            };
+            if data_type == DataType::Auto {
+                info!(
+                    "Working on {} data inferred from the sequences, use --data-type to override it.",
+                    kind
+                );
+            } else {
+                info!("Working on {} data.", kind);
+            }
+            let reversed = match (&sequence_type, args.reverse_strand) {
+                (SequenceType::DNA, ReverseStrandPolicy::Fail | ReverseStrandPolicy::Correct) => {
+                    reverse_strand_sequences(&info.sequences)
                 }
+                _ => Vec::new(),
             };
-            info!(
-                "Final alignment score is: \n{:?}",
-                scores.iter().sum::<f64>()
-            );
-            let out_msa_path = match cli.output_msa_file {
+            if !reversed.is_empty() {
+                let ids: Vec<&str> = reversed.iter().map(|&i| info.sequences[i].id()).collect();
+                if args.reverse_strand == ReverseStrandPolicy::Fail {
+                    bail!(
+                        "{} sequence(s) look reverse complemented relative to the others: {}. Use --reverse-strand correct to align their reverse complement.",
+                        ids.len(),
+                        ids.join(", ")
+                    );
+                }
+                warn!(
+                    "Aligning the reverse complement of {} sequence(s) from the opposite strand: {}.",
+                    ids.len(),
+                    ids.join(", ")
+                );
+                let mut sequences = info.sequences.clone();
+                for &idx in &reversed {
+                    sequences[idx] = reverse_complement(&sequences[idx]);
+                }
+                let tree = if estimate_tree {
+                    files::guide_tree(&sequences, &tree_options)?
+                } else {
+                    info.tree.clone()
+                };
+                info = phyloinfo_from_sequences_tree(&sequences, tree)?;
+            }
+            if args.anchor_length.is_some_and(|k| k < MIN_ANCHOR_LENGTH) {
+                bail!(
+                    "The anchor length must be at least {}, shorter anchors match by chance.",
+                    MIN_ANCHOR_LENGTH
+                );
+            }
+            if args.x_drop.is_some_and(|x| x.is_nan() || x < 0.0) {
+                bail!("The X-drop must be a non-negative number.");
+            }
+            if args.model.site_rates && args.model.gamma_categories > u8::MAX as usize + 1 {
+                bail!("Site rate categories need at most 256 gamma rate categories.");
+            }
+            let seed = args.seed.unwrap_or_else(rand::random);
+            let mut options = AlignmentOptions {
+                seed: Some(seed),
+                tie_break: TieBreak::from(args.tie_break),
+                wavefront: args.wavefront,
+                score_rows: ScoreRows::from(args.score_rows),
+                max_memory: args.max_memory,
+                band: args.band,
+                x_drop: args.x_drop,
+                anchor_length: args.anchor_length,
+                ..scoring_options(&args.model, &sequence_type)?
+            };
+            options.gap_profiles = gap_profiles(&args, &info, &sequence_type)?;
+            if args.metrics.is_some() {
+                options.metrics = Some(Arc::new(RunMetrics::new()));
+            }
+            if let Some(path) = &args.constraints {
+                let constraints = AnchorConstraints::from_file(path)?;
+                constraints.check_sequences(&info.sequences)?;
+                info!(
+                    "Enforcing {} anchor constraints.",
+                    constraints.pairs().len()
+                );
+                options.constraints = Some(constraints);
+            }
+            if let Some(path) = &args.region_mask {
+                let masks = RegionMasks::from_bed_file(path, args.mask_gap_weight)?;
+                masks.check_sequences(&info.sequences)?;
+                options.region_masks = Some(masks);
+            }
+            if let Some(path) = &args.seed_alignment {
+                let constraints = AnchorConstraints::from_seed_file(path, &info.sequences)?;
+                info!(
+                    "Keeping the seed alignment columns with {} aligned residue pairs and {} gaps.",
+                    constraints.pairs().len(),
+                    constraints.gaps().len()
+                );
+                options.constraints = Some(constraints);
+            }
+            let progress_bar = progress::node_progress_bar();
+            if show_progress {
+                options.progress = Some(progress::progress_callback(&progress_bar));
+            }
+            let mut metadata = RunMetadata::new();
+            match (&args.model.model, &args.model.cost_matrix) {
+                (_, Some(path)) => metadata.add("cost_matrix", path.display()),
+                (Some(model), None) => metadata.add("model", model),
+                (None, None) => {}
+            }
+            if let Some(preset) = args.model.preset {
+                metadata.add("preset", format!("{:?}", preset));
+            }
+            if !args.model.category_breakpoints.is_empty() {
+                metadata.add(
+                    "category_breakpoints",
+                    format!("{:?}", args.model.category_breakpoints),
+                );
+            }
+            if let Some(slopes) = &args.model.gap_slopes {
+                metadata.add("gap_slopes", format!("{:?}", slopes));
+            }
+            if let Some(path) = &args.gap_profile {
+                metadata.add("gap_profile", path.display());
+            }
+            if args.hydropathy_gaps {
+                metadata.add("hydropathy_gaps", true);
+            }
+            if let Some(path) = &args.secondary_structure {
+                metadata.add("secondary_structure", path.display());
+            }
+            if args.predict_structure {
+                metadata.add("predict_structure", true);
+            }
+            if args.secondary_structure.is_some() || args.predict_structure {
+                metadata.add("helix_gap_weight", args.helix_gap_weight);
+                metadata.add("strand_gap_weight", args.strand_gap_weight);
+            }
+            if let Some(alpha) = args.model.gamma {
+                metadata.add("gamma", alpha);
+                metadata.add("gamma_categories", args.model.gamma_categories);
+            }
+            if args.model.site_rates {
+                metadata.add("site_rates", true);
+            }
+            if let Some(path) = &args.model.partitions {
+                metadata.add("partitions", path.display());
+            }
+            if args.model.empirical_freqs() {
+                metadata.add("empirical_freqs", true);
+            }
+            if options.free_end_gaps {
+                metadata.add("free_end_gaps", true);
+            }
+            if options.soft_mask != SoftMask::Keep {
+                metadata.add("soft_mask", format!("{:?}", options.soft_mask));
+            }
+            if options.gap_function != GapFunction::Affine {
+                metadata.add("gap_function", format!("{:?}", options.gap_function));
+            }
+            if options.sequence_weighting != SequenceWeighting::None {
+                metadata.add(
+                    "sequence_weighting",
+                    format!("{:?}", options.sequence_weighting),
+                );
+            }
+            metadata.add("seed", seed);
+            metadata.add("tie_break", format!("{:?}", options.tie_break));
+            metadata.add("recoding", format!("{:?}", options.recoding));
+            metadata.add("data_type", format!("{:?}", options.data_type));
+            metadata.add("wavefront", options.wavefront);
+            metadata.add("score_rows", format!("{:?}", options.score_rows));
+            if let Some(max_memory) = options.max_memory {
+                metadata.add("max_memory", max_memory);
+            }
+            if let Some(band) = options.band {
+                metadata.add("band", band);
+            }
+            if let Some(x_drop) = options.x_drop {
+                metadata.add("x_drop", x_drop);
+            }
+            if let Some(anchor_length) = options.anchor_length {
+                metadata.add("anchor_length", anchor_length);
+            }
+            if let Some(path) = &args.constraints {
+                metadata.add("constraints", path.display());
+            }
+            if let Some(path) = &args.seed_alignment {
+                metadata.add("seed_alignment", path.display());
+            }
+            if let Some(path) = &args.region_mask {
+                metadata.add("region_mask", path.display());
+                metadata.add("mask_gap_weight", args.mask_gap_weight);
+            }
+            if !args.tree.outgroup.is_empty() {
+                metadata.add("outgroup", args.tree.outgroup.join(","));
+            }
+            if !reversed.is_empty() {
+                metadata.add("reverse_complemented", reversed.len());
+            }
+            let out_msa_path = match args.output_msa_file {
                 Some(path) => path,
                 None => {
                     let path = PathBuf::from("msa.fasta");
@@ -138,10 +1363,504 @@ fn main() -> Result<()> {
                     path
                 }
             };
-            io::write_sequences_to_file(
-                &compile_alignment_representation(&info, &alignment, None::<NodeIdx>),
-                out_msa_path,
-            )?;
+            if !renamed.is_empty() {
+                if files::is_stdio(&out_msa_path) {
+                    bail!("Renamed sequences can only be written to an output file, the id mapping is written next to it.");
+                }
+                files::write_renamed_ids(&renamed, &files::renamed_ids_path(&out_msa_path))?;
+                metadata.add("renamed_sequences", renamed.len());
+            }
+            if estimate_tree {
+                if files::is_stdio(&out_msa_path) {
+                    warn!("The estimated guide tree is not written when the MSA goes to standard output.");
+                } else {
+                    files::write_guide_tree(&info, &files::guide_tree_path(&out_msa_path))?;
+                }
+                metadata.add("tree", "estimated");
+            }
+            let mixed_types = options
+                .partitions
+                .as_ref()
+                .is_some_and(|partitions| partitions.mixed_types(&info.sequences, &sequence_type));
+            if mixed_types
+                && (args.nni_rounds > 0
+                    || args.samples > 0
+                    || args.suboptimal > 0
+                    || args.restarts > 1
+                    || args.checkpoint.is_some()
+                    || args.refine_iterations > 0
+                    || args.column_scores
+                    || args.traceback_paths
+                    || args.dump_matrices.is_some()
+                    || args.two_pass
+                    || args.reestimate_branch_lengths
+                    || args.clade_msa_dir.is_some()
+                    || args.node_consensus)
+            {
+                bail!("Partitions of different data types are only aligned separately and concatenated, without tree search, sampling, restarts, checkpoints, refinement, column scores, traceback paths, DP matrices, two passes, branch lengths or clade MSAs.");
+            }
+            if args.nni_rounds > 0 {
+                let scoring = model_scoring(
+                    &info,
+                    &sequence_type,
+                    &args.model,
+                    options.partitions.as_ref(),
+                )?;
+                let (tree, score) =
+                    pars_nni_search(scoring.as_ref(), &info, args.nni_rounds, &options)?;
+                info!("Tree search done, the tree has score {}.", score);
+                info = phyloinfo_from_sequences_tree(&info.sequences, tree)?;
+                if files::is_stdio(&out_msa_path) {
+                    warn!("The tree found by the tree search is not written when the MSA goes to standard output.");
+                } else {
+                    files::write_nni_tree(&info, &files::nni_tree_path(&out_msa_path))?;
+                }
+                metadata.add("nni_rounds", args.nni_rounds);
+            }
+            if args.samples > 0 && files::is_stdio(&out_msa_path) {
+                bail!("Sampled alignments can only be written to an output file, not to standard output.");
+            }
+            if args.suboptimal > 0 && files::is_stdio(&out_msa_path) {
+                bail!("Suboptimal alignments can only be written to an output file, not to standard output.");
+            }
+            if args.restarts > 1 && files::is_stdio(&out_msa_path) {
+                bail!("Restarts can only be written to an output file, not to standard output.");
+            }
+            if let Some(fraction) = args.trim_gap_fraction {
+                if !(0.0..=1.0).contains(&fraction) {
+                    bail!("The gap fraction for trimming must be between 0 and 1.");
+                }
+                if files::is_stdio(&out_msa_path) {
+                    bail!("Trimming can only be used with an output file, the column map is written next to it.");
+                }
+            }
+            if args.consensus.is_some() {
+                if !(args.consensus_threshold > 0.0 && args.consensus_threshold <= 1.0) {
+                    bail!("The consensus threshold must be above 0 and at most 1.");
+                }
+                if files::is_stdio(&out_msa_path) {
+                    bail!("The consensus can only be written next to an output file, not to standard output.");
+                }
+            }
+            if args.column_scores && files::is_stdio(&out_msa_path) {
+                bail!("Column scores can only be written next to an output file, not to standard output.");
+            }
+            if args.traceback_paths && files::is_stdio(&out_msa_path) {
+                bail!("Traceback paths can only be written next to an output file, not to standard output.");
+            }
+            if let Some(node) = args.dump_matrices {
+                if node == 0 || node > info.tree.internals.len() {
+                    bail!(
+                        "The node of the DP matrices must be between 1 and {}, the number of internal nodes.",
+                        info.tree.internals.len()
+                    );
+                }
+                if files::is_stdio(&out_msa_path) {
+                    bail!("DP matrices can only be written next to an output file, not to standard output.");
+                }
+            }
+            if args.reestimate_branch_lengths {
+                if args.model.model.as_deref().map_or(true, is_score_matrix) {
+                    bail!("Re-estimating the branch lengths needs a substitution model, set it with -m.");
+                }
+                if files::is_stdio(&out_msa_path) {
+                    bail!("The re-estimated tree can only be written next to an output file, not to standard output.");
+                }
+            }
+            if args.trim_gap_fraction.is_some() && matches!(args.output_format, MsaFormat::Maf) {
+                bail!(
+                    "The MAF coordinates need the complete rows, write the trimmed MSA as fasta."
+                );
+            }
+            if args.column_confidence && args.samples == 0 {
+                bail!("The column confidence needs sampled alignments, set --samples.");
+            }
+            if args.samples > 0 && args.suboptimal > 0 {
+                bail!("--samples and --suboptimal cannot be used together.");
+            }
+            let model = args.model;
+            let mut codon_info = None;
+            let mut partition_info = None;
+            let mut sample_msas = Vec::new();
+            let result = if args.codon {
+                if !matches!(sequence_type, SequenceType::DNA) {
+                    bail!("The codon alignment needs DNA sequences.");
+                }
+                if model.site_rates || model.partitions.is_some() {
+                    bail!(
+                        "Site rate categories and partitions are not supported for the codon alignment."
+                    );
+                }
+                let gap_mult = gap_multipliers(&model);
+                let dna = dna_scoring(
+                    model.model_name(),
+                    &model_params(&info, &model)?,
+                    &gap_mult,
+                    &model_times(&info.tree.get_all_branch_lengths(), &model)?,
+                    &Rounding::none(),
+                    &gamma_rates(&model)?,
+                    cost_cache(&model).as_ref(),
+                )?;
+                let scoring = codon_scoring(&dna, &gap_mult)?;
+                let encoded = codon_phylo_info(&info)?;
+                options.alphabet = Some(codon_alphabet());
+                metadata.add("codon", true);
+                let result = pars_align_on_tree_w_options(&scoring, &encoded, &options)?;
+                codon_info = Some(encoded);
+                result
+            } else if args.translate {
+                if !matches!(sequence_type, SequenceType::DNA) {
+                    bail!("The translated alignment needs coding DNA sequences.");
+                }
+                let translated = translated_phylo_info(&info)?;
+                options.data_type = DataType::Protein;
+                let scoring = model_scoring(
+                    &translated,
+                    &SequenceType::Protein,
+                    &model,
+                    options.partitions.as_ref(),
+                )?;
+                metadata.add("translate", true);
+                let result = pars_align_on_tree_w_options(scoring.as_ref(), &translated, &options)?;
+                codon_info = Some(translated);
+                result
+            } else if mixed_types {
+                let (concatenated, result) = align_mixed_partitions(&info, &model, &options)?;
+                metadata.add("mixed_partitions", true);
+                partition_info = Some(concatenated);
+                result
+            } else if args.samples > 0 {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                let mut samples =
+                    pars_sample_on_tree(scoring.as_ref(), &info, &options, args.samples + 1)?;
+                for (sample, result) in samples.iter().enumerate().skip(1) {
+                    let path = files::sample_path(&out_msa_path, sample);
+                    info!(
+                        "Writing sampled alignment {} with score {} to {}.",
+                        sample,
+                        result.score(),
+                        path.display()
+                    );
+                    let sample_msa = result.msa(&info);
+                    files::write_msa(&sample_msa, path)?;
+                    if args.column_confidence {
+                        sample_msas.push(sample_msa);
+                    }
+                }
+                metadata.add("samples", args.samples);
+                samples.swap_remove(0)
+            } else if args.suboptimal > 0 {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                let (result, alternatives) = pars_suboptimal_on_tree(
+                    scoring.as_ref(),
+                    &info,
+                    &options,
+                    args.suboptimal + 1,
+                )?;
+                write_suboptimal(&info, &result, alternatives, &out_msa_path, args.suboptimal)?;
+                metadata.add("suboptimal", args.suboptimal);
+                result
+            } else if args.restarts > 1 {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                let mut restarts = pars_restarts_on_tree(
+                    scoring.as_ref(),
+                    &info,
+                    &options,
+                    args.restarts,
+                    args.threads,
+                )?;
+                let totals: Vec<(u64, f64)> = restarts
+                    .iter()
+                    .enumerate()
+                    .map(|(restart, result)| (restart_seed(seed, restart), result.score()))
+                    .collect();
+                let scores: Vec<f64> = totals.iter().map(|(_, score)| *score).collect();
+                let summary = summarize_scores(&scores).unwrap();
+                let best = summary.best;
+                files::write_restart_scores(
+                    &totals,
+                    best,
+                    &files::restart_scores_path(&out_msa_path),
+                )?;
+                info!(
+                    "Keeping restart {} with seed {} and score {}, the {} restarts scored {} to {} with mean {} and standard deviation {}.",
+                    best,
+                    totals[best].0,
+                    summary.min,
+                    args.restarts,
+                    summary.min,
+                    summary.max,
+                    summary.mean,
+                    summary.sd
+                );
+                metadata.add("restarts", args.restarts);
+                metadata.add("best_restart_seed", totals[best].0);
+                metadata.add("restart_score_mean", summary.mean);
+                metadata.add("restart_score_sd", summary.sd);
+                metadata.add("restart_score_max", summary.max);
+                restarts.swap_remove(best)
+            } else if let Some(checkpoint) = &args.checkpoint {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                pars_align_on_tree_w_checkpoint(
+                    scoring.as_ref(),
+                    &info,
+                    &options,
+                    checkpoint,
+                    args.resume,
+                )?
+            } else if args.threads != 1 {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                metadata.add("threads", args.threads);
+                pars_align_on_tree_parallel(scoring.as_ref(), &info, &options, args.threads)?
+            } else if model.cost_matrix.is_some()
+                || model.gamma.is_some()
+                || model.empirical_freqs()
+                || model.partitions.is_some()
+                || model.category_strategy != CategoryPolicy::Percentiles
+                || !model.category_breakpoints.is_empty()
+                || model.model.as_deref().is_some_and(is_score_matrix)
+            {
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                pars_align_on_tree_w_options(scoring.as_ref(), &info, &options)?
+            } else {
+                IndelMapAligner::builder()
+                    .model(
+                        model.model.as_deref().unwrap_or_default(),
+                        model.model_params.clone(),
+                    )
+                    .gap_multipliers(gap_multipliers(&model))
+                    .categories(model.categories)
+                    .options(options.clone())
+                    .build()?
+                    .align(&info)?
+            };
+            let result = if args.two_pass {
+                let first_score = result.score();
+                let first_msa = result.msa(&info);
+                let rows_by_id: HashMap<&str, &Record> =
+                    first_msa.iter().map(|row| (row.id(), row)).collect();
+                // the leaves of the new tree follow the order of the sequences
+                let rows: Vec<Record> = info
+                    .sequences
+                    .iter()
+                    .map(|record| rows_by_id[record.id()].clone())
+                    .collect();
+                let tree = files::reroot_on_outgroup(
+                    estimate_tree_from_msa(&rows)?,
+                    &info.sequences,
+                    &tree_options,
+                )?;
+                info = phyloinfo_from_sequences_tree(&info.sequences, tree)?;
+                if files::is_stdio(&out_msa_path) {
+                    warn!("The tree of the second pass is not written when the MSA goes to standard output.");
+                } else {
+                    files::write_two_pass_tree(&info, &files::two_pass_tree_path(&out_msa_path))?;
+                }
+                let scoring =
+                    model_scoring(&info, &sequence_type, &model, options.partitions.as_ref())?;
+                let result = if args.threads != 1 {
+                    pars_align_on_tree_parallel(scoring.as_ref(), &info, &options, args.threads)?
+                } else {
+                    pars_align_on_tree_w_options(scoring.as_ref(), &info, &options)?
+                };
+                info!(
+                    "The first pass scored {}, the second pass on the re-estimated tree scored {}.",
+                    first_score,
+                    result.score()
+                );
+                metadata.add("two_pass", true);
+                metadata.add("first_pass_score", first_score);
+                metadata.add("second_pass_score", result.score());
+                result
+            } else {
+                result
+            };
+            // built on the final tree, which the second pass replaces
+            let refine_scoring = match args.refine_iterations {
+                0 => None,
+                _ => Some(model_scoring(
+                    &info,
+                    &sequence_type,
+                    &model,
+                    options.partitions.as_ref(),
+                )?),
+            };
+            let column_scoring = if args.column_scores
+                || args.traceback_paths
+                || args.dump_matrices.is_some()
+                || args.reestimate_branch_lengths
+            {
+                Some(model_scoring(
+                    &info,
+                    &sequence_type,
+                    &model,
+                    options.partitions.as_ref(),
+                )?)
+            } else {
+                None
+            };
+            progress_bar.finish_and_clear();
+            info!("Final alignment score is: \n{:?}", result.score());
+            if let Some(dir) = &args.clade_msa_dir {
+                write_clade_msas(&info, codon_info.as_ref(), &result, dir)?;
+            }
+            let mut msa = match (&codon_info, &partition_info) {
+                (Some(codon_info), _) => {
+                    expand_codon_msa(&result.msa(codon_info), &info.sequences)?
+                }
+                (None, Some(partition_info)) => result.msa(partition_info),
+                (None, None) => result.msa(&info),
+            };
+            let mut score = result.score();
+            if let Some(scoring) = refine_scoring {
+                let msa_info = phyloinfo_from_sequences_tree(&msa, info.tree.clone())?;
+                (msa, score) = pars_refine_msa(
+                    scoring.as_ref(),
+                    &msa_info,
+                    args.refine_iterations,
+                    &options,
+                )?;
+                info!("Refined alignment score is: {}", score);
+                metadata.add("refine_iterations", args.refine_iterations);
+            }
+            if args.normalize_gaps {
+                msa = normalize_gaps(&msa, args.homopolymer_gaps);
+                metadata.add("normalize_gaps", true);
+                if args.homopolymer_gaps {
+                    metadata.add("homopolymer_gaps", true);
+                }
+            }
+            if let Some(scoring) = column_scoring {
+                let msa_info = phyloinfo_from_sequences_tree(&msa, info.tree.clone())?;
+                if args.column_scores {
+                    let column_scores = pars_column_scores(scoring.as_ref(), &msa_info, &options)?;
+                    files::write_column_scores(
+                        &column_scores,
+                        &files::column_scores_path(&out_msa_path),
+                    )?;
+                    metadata.add("column_scores", true);
+                }
+                if args.traceback_paths {
+                    let paths = pars_traceback_paths(scoring.as_ref(), &msa_info, &options)?;
+                    files::write_traceback_paths(
+                        &paths,
+                        &files::traceback_paths_path(&out_msa_path),
+                    )?;
+                    metadata.add("traceback_paths", true);
+                }
+                if let Some(node) = args.dump_matrices {
+                    let matrices =
+                        pars_node_matrices(scoring.as_ref(), &msa_info, &options, node - 1)?;
+                    let label = internal_node_label(node - 1);
+                    for (state, name) in DpState::ALL.into_iter().zip(["M", "X", "Y"]) {
+                        let path = files::matrix_path(
+                            &out_msa_path,
+                            &label,
+                            name,
+                            args.matrix_format.extension(),
+                        );
+                        match args.matrix_format {
+                            MatrixFormat::Tsv => {
+                                files::write_matrix_tsv(matrices.scores(state), &path)?
+                            }
+                            MatrixFormat::Npy => {
+                                files::write_matrix_npy(matrices.scores(state), &path)?
+                            }
+                        }
+                    }
+                    info!(
+                        "Wrote the DP matrices of {} with the alignment score {}.",
+                        label,
+                        matrices.alignment_score()
+                    );
+                    metadata.add("dump_matrices", label);
+                }
+                if args.reestimate_branch_lengths {
+                    let branch_model = branch_length_model(&msa_info, &sequence_type, &model)?;
+                    let tree =
+                        pars_branch_lengths(scoring.as_ref(), &msa_info, &options, &branch_model)?;
+                    files::write_branch_length_tree(
+                        &phyloinfo_from_sequences_tree(&msa, tree)?,
+                        &files::branch_length_tree_path(&out_msa_path),
+                    )?;
+                    metadata.add("reestimate_branch_lengths", true);
+                }
+            }
+            // the samples are compared with the columns of the untrimmed MSA, the kept alignment
+            // is not one of them
+            let mut confidence = None;
+            if args.column_confidence {
+                if args.normalize_gaps {
+                    for sample_msa in &mut sample_msas {
+                        *sample_msa = normalize_gaps(sample_msa, args.homopolymer_gaps);
+                    }
+                }
+                confidence = Some(column_confidence(&msa, &sample_msas)?);
+            }
+            if let Some(fraction) = args.trim_gap_fraction {
+                let columns = msa.first().map_or(0, |record| record.seq().len());
+                let (trimmed, kept) = trim_gappy_columns(&msa, fraction);
+                info!(
+                    "Trimming removed {} of the {} columns with a gap fraction above {}.",
+                    columns - kept.len(),
+                    columns,
+                    fraction
+                );
+                files::write_trim_map(&kept, &files::trim_map_path(&out_msa_path))?;
+                if let Some(confidence) = &mut confidence {
+                    *confidence = kept.iter().map(|&column| confidence[column]).collect();
+                }
+                msa = trimmed;
+                metadata.add("trim_gap_fraction", fraction);
+            }
+            if let Some(confidence) = confidence {
+                files::write_column_confidence(
+                    &confidence,
+                    &files::column_confidence_path(&out_msa_path),
+                )?;
+                metadata.add("column_confidence", true);
+            }
+            if let Some(policy) = args.consensus {
+                let rule = match policy {
+                    ConsensusPolicy::Majority => ConsensusRule::Majority(args.consensus_threshold),
+                    ConsensusPolicy::Ambiguity => ConsensusRule::Ambiguity,
+                };
+                let consensus = Record::with_attrs(
+                    "consensus",
+                    None,
+                    &consensus_sequence(&msa, rule, &sequence_type),
+                );
+                files::write_msa(&[consensus], files::consensus_path(&out_msa_path))?;
+                metadata.add("consensus", format!("{:?}", rule));
+                if args.node_consensus {
+                    let consensus =
+                        node_consensus(&info, codon_info.as_ref(), &result, rule, &sequence_type)?;
+                    files::write_msa(&consensus, files::node_consensus_path(&out_msa_path))?;
+                    metadata.add("node_consensus", true);
+                }
+            }
+            match args.output_format {
+                MsaFormat::Fasta => files::write_msa(&msa, out_msa_path.clone())?,
+                MsaFormat::Maf => files::write_maf(&msa, score, &out_msa_path)?,
+            }
+            metadata.add("output_format", format!("{:?}", args.output_format));
+            metadata.add("score", score);
+            if let (Some(path), Some(metrics)) = (&args.metrics, &options.metrics) {
+                files::write_metrics(&metrics.snapshot(), path)?;
+                metadata.add("metrics", path.display());
+            }
+            let warnings = warnings::summarise_warnings();
+            warnings::log_warning_summary(&warnings);
+            if !files::is_stdio(&out_msa_path) {
+                metadata.set_warnings(&warnings);
+                metadata::write_run_metadata(&metadata, &out_msa_path)?;
+            }
             info!("IndelMAP alignment done, quitting.");
         }
         Err(error) => {
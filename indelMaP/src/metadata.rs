@@ -0,0 +1,134 @@
+use crate::warnings::WarningCategory;
+use anyhow::Error;
+use log::info;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Run information that is written as a JSON sidecar file next to the output MSA.
+#[derive(Debug, Default)]
+pub(crate) struct RunMetadata {
+    fields: Vec<(String, String)>,
+    warnings: Vec<WarningCategory>,
+}
+
+impl RunMetadata {
+    pub(crate) fn new() -> Self {
+        let mut metadata = RunMetadata::default();
+        metadata.add("version", env!("CARGO_PKG_VERSION"));
+        metadata.add(
+            "command",
+            std::env::args().collect::<Vec<String>>().join(" "),
+        );
+        metadata
+    }
+
+    pub(crate) fn add(&mut self, key: &str, value: impl Display) {
+        self.fields.push((key.to_string(), value.to_string()));
+    }
+
+    pub(crate) fn set_warnings(&mut self, warnings: &[WarningCategory]) {
+        self.warnings = warnings.to_vec();
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let mut lines: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": \"{}\"", escape(key), escape(value)))
+            .collect();
+        let warnings: Vec<String> = self
+            .warnings
+            .iter()
+            .map(|w| {
+                format!(
+                    "    {{\"category\": \"{}\", \"count\": {}, \"example\": \"{}\"}}",
+                    escape(&w.category),
+                    w.count,
+                    escape(&w.example)
+                )
+            })
+            .collect();
+        lines.push(if warnings.is_empty() {
+            "  \"warnings\": []".to_string()
+        } else {
+            format!("  \"warnings\": [\n{}\n  ]", warnings.join(",\n"))
+        });
+        format!("{{\n{}\n}}\n", lines.join(",\n"))
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json())?;
+        Ok(())
+    }
+}
+
+pub(crate) fn write_run_metadata(metadata: &RunMetadata, output: &Path) -> Result<()> {
+    let path = sidecar_path(output);
+    info!("Writing run metadata to {}.", path.display());
+    metadata.write(&path)
+}
+
+/// Sidecar path for an output file, e.g. msa.fasta -> msa.fasta.meta.json.
+pub(crate) fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".meta.json");
+    PathBuf::from(path)
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod metadata_tests {
+    use super::{escape, sidecar_path, RunMetadata};
+    use crate::warnings::WarningCategory;
+    use std::path::PathBuf;
+
+    #[test]
+    fn json_output() {
+        let mut metadata = RunMetadata::default();
+        metadata.add("seed", 42);
+        metadata.add("model", "W\"AG");
+        assert_eq!(
+            metadata.to_json(),
+            "{\n  \"seed\": \"42\",\n  \"model\": \"W\\\"AG\",\n  \"warnings\": []\n}\n"
+        );
+        metadata.set_warnings(&[WarningCategory {
+            category: "ambiguity".to_string(),
+            count: 3,
+            example: "a\tb".to_string(),
+        }]);
+        assert!(metadata
+            .to_json()
+            .contains("{\"category\": \"ambiguity\", \"count\": 3, \"example\": \"a\\tb\"}"));
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape("a\\b\nc"), "a\\\\b\\nc");
+        assert_eq!(escape("\u{1}"), "\\u0001");
+    }
+
+    #[test]
+    fn sidecar_paths() {
+        assert_eq!(
+            sidecar_path(&PathBuf::from("out/msa.fasta")),
+            PathBuf::from("out/msa.fasta.meta.json")
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::cli::{Cli, GapPreset};
+use crate::config::subcommand_position;
+use clap::{CommandFactory, ValueEnum};
+use std::ffi::OsString;
+
+const PRESET_FLAG: &str = "--preset";
+
+impl GapPreset {
+    /// Model options of the preset, the model and its parameters come first.
+    fn model_args(self) -> &'static [&'static str] {
+        match self {
+            GapPreset::ProteinDefault => &["--model", "WAG"],
+            GapPreset::DnaCoding => &["--model", "HKY85", "--model-params", "4.0"],
+            GapPreset::DnaNoncoding => &["--model", "HKY85", "--model-params", "2.0"],
+            GapPreset::Rrna => &["--model", "TN93", "--model-params", "3.0", "2.0"],
+        }
+    }
+
+    fn gap_args(self) -> &'static [&'static str] {
+        match self {
+            GapPreset::ProteinDefault => &["--go", "3.0", "--ge", "0.5", "--categories", "4"],
+            GapPreset::DnaCoding => &["--go", "4.0", "--ge", "1.0", "--categories", "4"],
+            GapPreset::DnaNoncoding => &["--go", "2.0", "--ge", "0.5", "--categories", "4"],
+            GapPreset::Rrna => &["--go", "3.0", "--ge", "0.75", "--categories", "8"],
+        }
+    }
+}
+
+/// Inserts the options of the preset given with --preset right after the subcommand, in front
+/// of the config file options and the other command line arguments, which override them. The
+/// model of the preset and its parameters are left out when a model or a cost matrix is given,
+/// the parameters of one model do not fit another.
+pub(crate) fn expand_preset(args: Vec<OsString>) -> Vec<OsString> {
+    let mut preset = None;
+    let mut model_given = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == PRESET_FLAG {
+            preset = iter.next().map(|name| name.to_string_lossy().into_owned());
+        } else if let Some(name) = arg.strip_prefix("--preset=") {
+            preset = Some(name.to_string());
+        } else if ["-m", "--model", "--cost-matrix"].contains(&arg.as_ref())
+            || arg.starts_with("--model=")
+            || arg.starts_with("--cost-matrix=")
+        {
+            model_given = true;
+        }
+    }
+    // unknown presets are left for clap to report
+    let Some(preset) = preset.and_then(|name| GapPreset::from_str(&name, false).ok()) else {
+        return args;
+    };
+    let Some((position, subcommand)) = subcommand_position(&args) else {
+        return args;
+    };
+    let has_preset = Cli::command()
+        .find_subcommand(&subcommand)
+        .is_some_and(|subcommand| {
+            subcommand
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some("preset"))
+        });
+    if !has_preset {
+        return args;
+    }
+    let mut expanded = args[..=position].to_vec();
+    if !model_given {
+        expanded.extend(preset.model_args().iter().map(OsString::from));
+    }
+    expanded.extend(preset.gap_args().iter().map(OsString::from));
+    expanded.extend(args[position + 1..].iter().cloned());
+    expanded
+}
+
+#[cfg(test)]
+mod presets_tests {
+    use super::expand_preset;
+    use crate::cli::{Cli, Command, GapPreset};
+    use clap::Parser;
+    use std::ffi::OsString;
+
+    fn parse(args: &[&str]) -> Cli {
+        let args = ["indelMaP", "align", "-s", "seqs.fasta"]
+            .iter()
+            .chain(args)
+            .map(OsString::from)
+            .collect();
+        Cli::try_parse_from(expand_preset(args)).unwrap()
+    }
+
+    #[test]
+    fn preset_sets_defaults() {
+        let Command::Align(args) = parse(&["--preset", "dna-coding"]).command else {
+            panic!("Expected the align subcommand");
+        };
+        assert_eq!(args.model.preset, Some(GapPreset::DnaCoding));
+        assert_eq!(args.model.model.as_deref(), Some("HKY85"));
+        assert_eq!(args.model.model_params, vec![4.0]);
+        assert_eq!(args.model.go, 4.0);
+        assert_eq!(args.model.ge, 1.0);
+        assert_eq!(args.model.categories, 4);
+    }
+
+    #[test]
+    fn command_line_overrides_preset() {
+        let Command::Align(args) = parse(&["--preset=rrna", "--go", "1.5", "-m", "JC69"]).command
+        else {
+            panic!("Expected the align subcommand");
+        };
+        assert_eq!(args.model.model.as_deref(), Some("JC69"));
+        assert!(args.model.model_params.is_empty());
+        assert_eq!(args.model.go, 1.5);
+        assert_eq!(args.model.ge, 0.75);
+        assert_eq!(args.model.categories, 8);
+
+        let args: Vec<OsString> = ["indelMaP", "align", "-s", "s.fasta", "--preset", "unknown"]
+            .map(OsString::from)
+            .to_vec();
+        assert_eq!(expand_preset(args.clone()), args);
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use parsimony::parsimony_alignment::ProgressCallback;
+
+/// Progress bar over the tree nodes, drawn on standard error. The bar is hidden when standard
+/// error is not a terminal.
+pub(crate) fn node_progress_bar() -> ProgressBar {
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} nodes, elapsed {elapsed}, ETA {eta}")
+            .unwrap(),
+    );
+    bar
+}
+
+pub(crate) fn progress_callback(bar: &ProgressBar) -> ProgressCallback {
+    let bar = bar.clone();
+    ProgressCallback::new(move |processed, total| {
+        bar.set_length(total as u64);
+        bar.set_position(processed as u64);
+    })
+}
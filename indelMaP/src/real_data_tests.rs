@@ -1,4 +1,4 @@
-use crate::indel_map_align_protein_rounded;
+use parsimony::parsimony_alignment::aligner::IndelMapAligner;
 use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
 use phylo::phylo_info::phyloinfo_from_files;
 use phylo::Rounding;
@@ -11,14 +11,14 @@ fn align_HIV_example_wag() {
         PathBuf::from("./data/HIV_subset.nwk"),
     )
     .unwrap();
-    let (_, scores) = indel_map_align_protein_rounded(
-        &info,
-        "WAG".to_string(),
-        vec![],
-        &GapMultipliers::new(2.5, 0.5),
-        4,
-        &Rounding::four(),
-    )
-    .unwrap();
-    assert_eq!(scores.iter().sum::<f64>(), 350.64988524999995);
+    let result = IndelMapAligner::builder()
+        .model("WAG", vec![])
+        .gap_multipliers(GapMultipliers::new(2.5, 0.5))
+        .categories(4)
+        .rounding(Rounding::four())
+        .build()
+        .unwrap()
+        .align(&info)
+        .unwrap();
+    assert_eq!(result.score(), 350.64988524999995);
 }
@@ -0,0 +1,120 @@
+use anyhow::Error;
+use log::{warn, Level, Log, Metadata, Record};
+use pretty_env_logger::env_logger::{Builder, Logger};
+use std::sync::Mutex;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Target of the summary messages, these are not collected again.
+const SUMMARY_TARGET: &str = "summary";
+
+static WARNINGS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+/// Wraps the logger and keeps all the warnings issued during the run, the log target of a
+/// warning is used as its category.
+struct WarningCollector {
+    inner: Logger,
+}
+
+impl Log for WarningCollector {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Warn && record.target() != SUMMARY_TARGET {
+            WARNINGS
+                .lock()
+                .unwrap()
+                .push((record.target().to_string(), record.args().to_string()));
+        }
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+pub(crate) fn init_logger(builder: &mut Builder) -> Result<()> {
+    let logger = builder.build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(WarningCollector { inner: logger }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WarningCategory {
+    pub(crate) category: String,
+    pub(crate) count: usize,
+    pub(crate) example: String,
+}
+
+/// Groups the warnings collected so far by category, in the order of their first occurrence.
+pub(crate) fn summarise_warnings() -> Vec<WarningCategory> {
+    group_warnings(&WARNINGS.lock().unwrap())
+}
+
+fn group_warnings(warnings: &[(String, String)]) -> Vec<WarningCategory> {
+    let mut summary = Vec::<WarningCategory>::new();
+    for (category, message) in warnings {
+        match summary.iter_mut().find(|c| &c.category == category) {
+            Some(entry) => entry.count += 1,
+            None => summary.push(WarningCategory {
+                category: category.clone(),
+                count: 1,
+                example: message.clone(),
+            }),
+        }
+    }
+    summary
+}
+
+pub(crate) fn log_warning_summary(summary: &[WarningCategory]) {
+    if summary.is_empty() {
+        return;
+    }
+    warn!(
+        target: SUMMARY_TARGET,
+        "The run produced {} warning(s):",
+        summary.iter().map(|c| c.count).sum::<usize>()
+    );
+    for entry in summary {
+        warn!(
+            target: SUMMARY_TARGET,
+            "{}: {} time(s), e.g. \"{}\"", entry.category, entry.count, entry.example
+        );
+    }
+}
+
+#[cfg(test)]
+mod warnings_tests {
+    use super::{group_warnings, WarningCategory};
+
+    #[test]
+    fn group_by_category() {
+        let warnings = [
+            ("ambiguity", "first"),
+            ("pruning", "second"),
+            ("ambiguity", "third"),
+        ]
+        .map(|(c, m)| (c.to_string(), m.to_string()));
+        assert_eq!(
+            group_warnings(&warnings),
+            vec![
+                WarningCategory {
+                    category: "ambiguity".to_string(),
+                    count: 2,
+                    example: "first".to_string()
+                },
+                WarningCategory {
+                    category: "pruning".to_string(),
+                    count: 1,
+                    example: "second".to_string()
+                },
+            ]
+        );
+        assert!(group_warnings(&[]).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! WebAssembly bindings of the IndelMaP alignment. Sequences and MSAs are passed as FASTA strings
+//! and trees as newick strings, errors are thrown as JavaScript errors.
+
+use bio::io::fasta::{Reader, Record, Writer};
+use parsimony::parsimony_alignment::aligner::IndelMapAligner;
+use parsimony::parsimony_alignment::guide_tree::estimate_guide_tree;
+use parsimony::parsimony_alignment::msa_scoring::pars_score_msa;
+use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
+use parsimony::parsimony_alignment::tree_input::{tree_from_newick, TreeInputOptions};
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use wasm_bindgen::prelude::*;
+
+fn js_error(error: impl std::fmt::Display) -> JsError {
+    JsError::new(&error.to_string())
+}
+
+fn read_fasta(fasta: &str) -> Result<Vec<Record>, JsError> {
+    Reader::new(fasta.as_bytes())
+        .records()
+        .map(|record| record.map_err(js_error))
+        .collect()
+}
+
+fn write_fasta(records: &[Record]) -> Result<String, JsError> {
+    let mut writer = Writer::new(Vec::new());
+    for record in records {
+        writer.write_record(record).map_err(js_error)?;
+    }
+    let bytes = writer.into_inner().map_err(js_error)?;
+    String::from_utf8(bytes).map_err(js_error)
+}
+
+fn phylo_info(fasta: &str, newick: Option<String>) -> Result<PhyloInfo, JsError> {
+    let sequences = read_fasta(fasta)?;
+    let tree = match newick {
+        Some(newick) => tree_from_newick(&newick, &sequences, &TreeInputOptions::default()),
+        None => estimate_guide_tree(&sequences),
+    }
+    .map_err(js_error)?;
+    phyloinfo_from_sequences_tree(&sequences, tree).map_err(js_error)
+}
+
+fn aligner(
+    model: &str,
+    params: Vec<f64>,
+    gap_open: f64,
+    gap_ext: f64,
+    seed: Option<u32>,
+) -> Result<IndelMapAligner, JsError> {
+    let mut builder = IndelMapAligner::builder()
+        .model(model, params)
+        .gap_multipliers(GapMultipliers::new(gap_open, gap_ext));
+    if let Some(seed) = seed {
+        builder = builder.seed(seed as u64);
+    }
+    builder.build().map_err(js_error)
+}
+
+/// Aligned sequences in FASTA format and the score of the alignment.
+#[wasm_bindgen]
+pub struct Alignment {
+    fasta: String,
+    score: f64,
+}
+
+#[wasm_bindgen]
+impl Alignment {
+    #[wasm_bindgen(getter)]
+    pub fn fasta(&self) -> String {
+        self.fasta.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+}
+
+/// Aligns the sequences of the FASTA string on the newick tree, the guide tree is estimated from
+/// the sequences if no tree is given.
+#[wasm_bindgen]
+pub fn align(
+    fasta: &str,
+    newick: Option<String>,
+    model: &str,
+    params: Vec<f64>,
+    gap_open: f64,
+    gap_ext: f64,
+    seed: Option<u32>,
+) -> Result<Alignment, JsError> {
+    let info = phylo_info(fasta, newick)?;
+    let result = aligner(model, params, gap_open, gap_ext, seed)?
+        .align(&info)
+        .map_err(js_error)?;
+    Ok(Alignment {
+        fasta: write_fasta(&result.msa(&info))?,
+        score: result.score(),
+    })
+}
+
+/// Scores an existing MSA in FASTA format on the newick tree, returns the sum of the scores of
+/// the internal nodes.
+#[wasm_bindgen(js_name = scoreMsa)]
+pub fn score_msa(
+    fasta: &str,
+    newick: String,
+    model: &str,
+    params: Vec<f64>,
+    gap_open: f64,
+    gap_ext: f64,
+) -> Result<f64, JsError> {
+    let info = phylo_info(fasta, Some(newick))?;
+    let aligner = aligner(model, params, gap_open, gap_ext, None)?;
+    let scoring = aligner.scoring(&info).map_err(js_error)?;
+    let (_, scores) =
+        pars_score_msa(scoring.as_ref(), &info, aligner.options()).map_err(js_error)?;
+    Ok(scores.iter().sum())
+}
@@ -0,0 +1,85 @@
+use thiserror::Error;
+
+/// Errors of the parsimony crate, grouped by cause so that callers can match on them. The
+/// message of every variant is the full description of the problem.
+#[derive(Debug, Error)]
+pub enum ParsimonyError {
+    /// The named substitution model or scoring matrix is not known.
+    #[error("{0}")]
+    UnknownModel(String),
+    /// A model parameter or an option is out of range or inconsistent.
+    #[error("{0}")]
+    InvalidParameters(String),
+    /// The symbols of a scoring do not fit the alphabet of the sequences.
+    #[error("{0}")]
+    AlphabetMismatch(String),
+    /// The tree and the sequences do not have the same taxa.
+    #[error("{0}")]
+    TreeSequenceMismatch(String),
+    /// Two inputs that should hold the same sequences do not, e.g. an MSA and its reference.
+    #[error("{0}")]
+    SequenceMismatch(String),
+    /// The sequences are not a valid MSA or cannot be scored as one.
+    #[error("{0}")]
+    InvalidAlignment(String),
+    /// The tree does not have the structure that is needed.
+    #[error("{0}")]
+    InvalidTree(String),
+    #[error("{0}")]
+    TooFewSequences(String),
+    /// A malformed input such as a newick string, a cost matrix or a gap profile file.
+    #[error("{0}")]
+    InvalidInput(String),
+    /// The DP matrices of a node would need more memory than the set limit.
+    #[error("{0}")]
+    MemoryLimit(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[error(transparent)]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    /// Error reported by the `phylo` crate, e.g. when reading the sequences or the tree.
+    #[error(transparent)]
+    Phylo(#[from] anyhow::Error),
+}
+
+#[cfg(test)]
+mod errors_tests {
+    use super::ParsimonyError;
+    use crate::parsimony_alignment::alphabet::Alphabet;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::GammaRates;
+    use crate::parsimony_alignment::tree_input::{tree_from_newick, TreeInputOptions};
+    use assert_matches::assert_matches;
+    use bio::io::fasta::Record;
+
+    #[test]
+    fn errors_by_cause() {
+        assert_matches!(
+            GammaRates::new(-1.0, 4),
+            Err(ParsimonyError::InvalidParameters(_))
+        );
+        assert_matches!(
+            Alphabet::new(b"AC-"),
+            Err(ParsimonyError::InvalidParameters(_))
+        );
+        let sequences = [
+            Record::with_attrs("A", None, b"AC"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"AC"),
+        ];
+        let options = TreeInputOptions::default();
+        assert_matches!(
+            tree_from_newick("((A:1,B:1):1,D:1);", &sequences, &options).err(),
+            Some(ParsimonyError::TreeSequenceMismatch(_))
+        );
+        let error = tree_from_newick("((A:1,B:1):1,C:x);", &sequences, &options)
+            .err()
+            .unwrap();
+        assert_matches!(error, ParsimonyError::InvalidInput(_));
+        assert_eq!(
+            error.to_string(),
+            "Invalid branch length \"x\" in the newick tree."
+        );
+    }
+}
@@ -1,8 +1,9 @@
-use anyhow::Error;
-
+pub mod errors;
 pub mod parsimony_alignment;
 
-type Result<T> = std::result::Result<T, Error>;
+pub use errors::ParsimonyError;
+
+pub type Result<T> = std::result::Result<T, ParsimonyError>;
 
 #[allow(non_camel_case_types)]
 type f64_h = ordered_float::OrderedFloat<f64>;
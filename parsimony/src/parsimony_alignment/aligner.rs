@@ -0,0 +1,280 @@
+use phylo::phylo_info::PhyloInfo;
+use phylo::sequences::SequenceType;
+use phylo::Rounding;
+
+use super::parallel::pars_align_on_tree_parallel;
+use super::parsimony_costs::parsimony_costs_model::{
+    scoring_times, CategoryStrategy, DNAParsCosts, GapMultipliers, ProteinParsCosts,
+};
+use super::parsimony_costs::ParsimonyCosts;
+use super::{pars_align_on_tree_w_options, AlignmentOptions, AlignmentResult, TieBreak};
+use crate::{ParsimonyError, Result};
+
+/// Aligns sequences on their tree with a scoring from a substitution model. The cost matrices
+/// are created for the branch lengths of every aligned tree, so one aligner can be reused for
+/// many families.
+///
+/// ```ignore
+/// let aligner = IndelMapAligner::builder()
+///     .model("HKY85", vec![2.0])
+///     .gap_multipliers(GapMultipliers::new(2.5, 0.5))
+///     .seed(7)
+///     .threads(4)
+///     .build()?;
+/// let result = aligner.align(&info)?;
+/// ```
+pub struct IndelMapAligner {
+    model_name: String,
+    model_params: Vec<f64>,
+    gap_mult: GapMultipliers,
+    categories: u32,
+    category_strategy: CategoryStrategy,
+    rounding: Rounding,
+    threads: usize,
+    options: AlignmentOptions,
+}
+
+/// Collects the configuration of an `IndelMapAligner`. Everything but the model has the same
+/// defaults as the command line: gap multipliers 2.5 and 0.5, four percentile categories, no
+/// rounding, a single thread and random tie-breaking without a seed.
+pub struct IndelMapAlignerBuilder {
+    model_name: Option<String>,
+    model_params: Vec<f64>,
+    gap_mult: GapMultipliers,
+    categories: u32,
+    category_strategy: CategoryStrategy,
+    rounding: Rounding,
+    threads: usize,
+    options: AlignmentOptions,
+}
+
+impl Default for IndelMapAlignerBuilder {
+    fn default() -> Self {
+        IndelMapAlignerBuilder {
+            model_name: None,
+            model_params: Vec::new(),
+            gap_mult: GapMultipliers::new(2.5, 0.5),
+            categories: 4,
+            category_strategy: CategoryStrategy::Percentiles,
+            rounding: Rounding::none(),
+            threads: 1,
+            options: AlignmentOptions::default(),
+        }
+    }
+}
+
+impl IndelMapAlignerBuilder {
+    /// Substitution model and its parameters, the parameters are ignored for protein models.
+    pub fn model(self, model_name: &str, model_params: Vec<f64>) -> Self {
+        IndelMapAlignerBuilder {
+            model_name: Some(model_name.to_string()),
+            model_params,
+            ..self
+        }
+    }
+
+    pub fn gap_multipliers(self, gap_mult: GapMultipliers) -> Self {
+        IndelMapAlignerBuilder { gap_mult, ..self }
+    }
+
+    /// Number of branch length categories to create cost matrices for, 0 uses every distinct
+    /// branch length of the tree.
+    pub fn categories(self, categories: u32) -> Self {
+        IndelMapAlignerBuilder { categories, ..self }
+    }
+
+    pub fn category_strategy(self, category_strategy: CategoryStrategy) -> Self {
+        IndelMapAlignerBuilder {
+            category_strategy,
+            ..self
+        }
+    }
+
+    /// Rounding of the branch length categories and of the cost matrix entries.
+    pub fn rounding(self, rounding: Rounding) -> Self {
+        IndelMapAlignerBuilder { rounding, ..self }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    /// Number of threads for aligning independent subtrees in parallel, 0 chooses the number
+    /// automatically.
+    pub fn threads(self, threads: usize) -> Self {
+        IndelMapAlignerBuilder { threads, ..self }
+    }
+
+    pub fn tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.options.tie_break = tie_break;
+        self
+    }
+
+    /// Replaces all the alignment options, including the seed and the tie-breaking set before.
+    pub fn options(self, options: AlignmentOptions) -> Self {
+        IndelMapAlignerBuilder { options, ..self }
+    }
+
+    pub fn build(self) -> Result<IndelMapAligner> {
+        let Some(model_name) = self.model_name else {
+            return Err(ParsimonyError::InvalidParameters(
+                "The aligner needs a substitution model.".to_string(),
+            ));
+        };
+        if self.gap_mult.open < 0.0 || self.gap_mult.ext < 0.0 {
+            return Err(ParsimonyError::InvalidParameters(
+                "The gap multipliers must not be negative.".to_string(),
+            ));
+        }
+        Ok(IndelMapAligner {
+            model_name,
+            model_params: self.model_params,
+            gap_mult: self.gap_mult,
+            categories: self.categories,
+            category_strategy: self.category_strategy,
+            rounding: self.rounding,
+            threads: self.threads,
+            options: self.options,
+        })
+    }
+}
+
+impl IndelMapAligner {
+    pub fn builder() -> IndelMapAlignerBuilder {
+        IndelMapAlignerBuilder::default()
+    }
+
+    pub fn options(&self) -> &AlignmentOptions {
+        &self.options
+    }
+
+    /// Scoring for the sequences and the branch lengths of `info`, DNA or protein depending on
+    /// the data type of the options.
+    pub fn scoring(&self, info: &PhyloInfo) -> Result<Box<dyn ParsimonyCosts + Sync>> {
+        let times = scoring_times(
+            &info.tree.get_all_branch_lengths(),
+            self.categories,
+            self.category_strategy,
+            &self.rounding,
+        );
+        Ok(
+            match self.options.data_type.sequence_type(&info.sequences) {
+                SequenceType::DNA => Box::new(DNAParsCosts::new(
+                    &self.model_name,
+                    &self.model_params,
+                    &self.gap_mult,
+                    &times,
+                    false,
+                    &self.rounding,
+                )?),
+                SequenceType::Protein => Box::new(ProteinParsCosts::new(
+                    &self.model_name,
+                    &self.gap_mult,
+                    &times,
+                    false,
+                    &self.rounding,
+                )?),
+            },
+        )
+    }
+
+    pub fn align(&self, info: &PhyloInfo) -> Result<AlignmentResult> {
+        let scoring = self.scoring(info)?;
+        if self.threads == 1 {
+            pars_align_on_tree_w_options(scoring.as_ref(), info, &self.options)
+        } else {
+            pars_align_on_tree_parallel(scoring.as_ref(), info, &self.options, self.threads)
+        }
+    }
+}
+
+#[cfg(test)]
+mod aligner_tests {
+    use super::IndelMapAligner;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+        scoring_times, CategoryStrategy, DNAParsCosts, GapMultipliers,
+    };
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions, TieBreak};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+    use phylo::Rounding;
+
+    #[test]
+    fn builder_matches_positional_setup() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+            Record::with_attrs("D", None, b"GACTT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.1, 0.3);
+        tree.add_parent(1, L(2), L(3), 0.2, 0.5);
+        tree.add_parent(2, I(0), I(1), 0.4, 0.6);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+
+        let aligner = IndelMapAligner::builder()
+            .model("JC69", vec![])
+            .gap_multipliers(GapMultipliers::new(2.0, 0.5))
+            .categories(2)
+            .seed(11)
+            .tie_break(TieBreak::Match)
+            .build()
+            .unwrap();
+        assert_eq!(aligner.options().seed, Some(11));
+        let result = aligner.align(&info).unwrap();
+
+        let times = scoring_times(
+            &info.tree.get_all_branch_lengths(),
+            2,
+            CategoryStrategy::Percentiles,
+            &Rounding::none(),
+        );
+        let scoring = DNAParsCosts::new(
+            "JC69",
+            &[],
+            &GapMultipliers::new(2.0, 0.5),
+            &times,
+            false,
+            &Rounding::none(),
+        )
+        .unwrap();
+        let options = AlignmentOptions {
+            seed: Some(11),
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+        let expected = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(result.scores, expected.scores);
+        for (alignment, expected) in result.alignments.iter().zip(expected.alignments.iter()) {
+            assert_eq!(alignment.map_x, expected.map_x);
+            assert_eq!(alignment.map_y, expected.map_y);
+        }
+
+        let parallel = IndelMapAligner::builder()
+            .model("JC69", vec![])
+            .gap_multipliers(GapMultipliers::new(2.0, 0.5))
+            .categories(2)
+            .options(options)
+            .threads(2)
+            .build()
+            .unwrap()
+            .align(&info)
+            .unwrap();
+        assert_eq!(parallel.scores, expected.scores);
+    }
+
+    #[test]
+    fn builder_needs_model() {
+        assert!(IndelMapAligner::builder().seed(1).build().is_err());
+        assert!(IndelMapAligner::builder()
+            .model("JC69", vec![])
+            .gap_multipliers(GapMultipliers::new(-1.0, 0.5))
+            .build()
+            .is_err());
+    }
+}
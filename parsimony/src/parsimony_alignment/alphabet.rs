@@ -0,0 +1,107 @@
+use bio::io::fasta::Record;
+
+use super::parsimony_sets::{gap_set, ParsimonySet};
+use crate::{ParsimonyError, Result};
+
+/// User-defined alphabet, e.g. a reduced amino acid alphabet or a structural alphabet. The
+/// position of a symbol is its row and column in the cost matrices of
+/// `ParsimonyCostsWModel::from_cost_matrices`. The symbols are case-sensitive, `-` is the gap
+/// and any character that is not in the alphabet stands for all the symbols.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alphabet {
+    symbols: Vec<u8>,
+}
+
+impl Alphabet {
+    pub fn new(symbols: &[u8]) -> Result<Self> {
+        if symbols.is_empty() {
+            return Err(ParsimonyError::InvalidParameters(
+                "The alphabet needs at least one symbol.".to_string(),
+            ));
+        }
+        if symbols.contains(&b'-') {
+            return Err(ParsimonyError::InvalidParameters(
+                "The gap character - cannot be a symbol of the alphabet.".to_string(),
+            ));
+        }
+        // the index tables of the substitution models only cover the bytes below 255
+        if symbols.contains(&u8::MAX) {
+            return Err(ParsimonyError::InvalidParameters(
+                "The byte 255 cannot be a symbol of the alphabet.".to_string(),
+            ));
+        }
+        for (i, symbol) in symbols.iter().enumerate() {
+            if symbols[..i].contains(symbol) {
+                return Err(ParsimonyError::InvalidParameters(format!(
+                    "The symbol {} appears twice in the alphabet.",
+                    *symbol as char
+                )));
+            }
+        }
+        Ok(Alphabet {
+            symbols: symbols.to_vec(),
+        })
+    }
+
+    pub fn symbols(&self) -> &[u8] {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Index table from characters to the positions of the symbols, in the form used by the
+    /// substitution models. Characters outside the alphabet map to the first symbol, they never
+    /// reach the costs because their sets are made of symbols.
+    pub fn index(&self) -> [i32; 255] {
+        let mut index = [0; 255];
+        for (i, &symbol) in self.symbols.iter().enumerate() {
+            if let Some(entry) = index.get_mut(symbol as usize) {
+                *entry = i as i32;
+            }
+        }
+        index
+    }
+
+    /// Parsimony set of a character, see the alphabet description for the rules.
+    pub(crate) fn set(&self, char: u8) -> ParsimonySet {
+        if char == b'-' {
+            gap_set()
+        } else if self.symbols.contains(&char) {
+            ParsimonySet::from_iter([char])
+        } else {
+            ParsimonySet::from_iter(self.symbols.iter().copied())
+        }
+    }
+
+    pub(crate) fn parsimony_sets(&self, record: &Record) -> Vec<ParsimonySet> {
+        record.seq().iter().map(|&c| self.set(c)).collect()
+    }
+}
+
+#[cfg(test)]
+mod alphabet_tests {
+    use super::Alphabet;
+    use crate::parsimony_alignment::parsimony_sets::{gap_set, make_parsimony_set};
+
+    #[test]
+    fn alphabet_sets() {
+        let alphabet = Alphabet::new(b"abcZ").unwrap();
+        assert_eq!(alphabet.len(), 4);
+        assert_eq!(alphabet.index()[b'Z' as usize], 3);
+        assert_eq!(alphabet.set(b'b'), make_parsimony_set([b'b']));
+        assert_eq!(alphabet.set(b'-'), gap_set());
+        assert_eq!(alphabet.set(b'B'), make_parsimony_set(*b"abcZ"));
+        assert!(Alphabet::new(b"").is_err());
+        assert!(Alphabet::new(b"ab-").is_err());
+        assert!(Alphabet::new(b"aba").is_err());
+        assert!(Alphabet::new(&[b'a', u8::MAX]).is_err());
+        let alphabet = Alphabet::new(b"ab").unwrap();
+        assert_eq!(alphabet.set(u8::MAX), make_parsimony_set(*b"ab"));
+    }
+}
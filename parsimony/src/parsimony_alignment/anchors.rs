@@ -0,0 +1,266 @@
+use log::info;
+use phylo::alignment::Alignment;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::constraints::NodeConstraints;
+use super::parsimony_costs::BranchParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::parsimony_matrices::match_sites;
+use super::{pars_align_dp, AlignmentOptions};
+
+/// Exactly matching stretch of two profiles, the sites `x..x + length` of x and `y..y + length`
+/// of y.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Anchor {
+    pub x: usize,
+    pub y: usize,
+    pub length: usize,
+}
+
+/// Character of a profile site that is certain, i.e. not a possible gap and with a single
+/// character in its set.
+fn certain_char(site: &ParsimonySiteInfo) -> Option<u8> {
+    if site.no_gap() && site.set.len() == 1 {
+        site.set.iter().next()
+    } else {
+        None
+    }
+}
+
+/// Starts of the k-mers of certain characters that occur exactly once in the profile.
+fn unique_kmers(chars: &[Option<u8>], k: usize) -> HashMap<Vec<u8>, usize> {
+    let mut kmers = HashMap::<Vec<u8>, Option<usize>>::new();
+    for (start, window) in chars.windows(k).enumerate() {
+        let Some(kmer) = window.iter().copied().collect::<Option<Vec<u8>>>() else {
+            continue;
+        };
+        kmers
+            .entry(kmer)
+            .and_modify(|position| *position = None)
+            .or_insert(Some(start));
+    }
+    kmers
+        .into_iter()
+        .filter_map(|(kmer, position)| position.map(|position| (kmer, position)))
+        .collect()
+}
+
+/// Collinear chain of exact anchors between two profiles. The seeds are the k-mers of certain
+/// characters that occur exactly once in each profile, the longest chain of seeds that is
+/// increasing in both profiles is kept and overlapping seeds on the same diagonal are merged.
+/// Seeds that overlap the previous anchor on another diagonal are dropped.
+pub fn find_anchors(
+    x_info: &[ParsimonySiteInfo],
+    y_info: &[ParsimonySiteInfo],
+    k: usize,
+) -> Vec<Anchor> {
+    if k == 0 || x_info.len() < k || y_info.len() < k {
+        return Vec::new();
+    }
+    let x_chars: Vec<Option<u8>> = x_info.iter().map(certain_char).collect();
+    let y_chars: Vec<Option<u8>> = y_info.iter().map(certain_char).collect();
+    let y_kmers = unique_kmers(&y_chars, k);
+    let mut seeds: Vec<(usize, usize)> = unique_kmers(&x_chars, k)
+        .into_iter()
+        .filter_map(|(kmer, x)| y_kmers.get(&kmer).map(|&y| (x, y)))
+        .collect();
+    seeds.sort_unstable();
+
+    // longest strictly increasing subsequence of the y positions
+    let mut tails = Vec::<usize>::new();
+    let mut previous = vec![None; seeds.len()];
+    for (idx, &(_, y)) in seeds.iter().enumerate() {
+        let pos = tails.partition_point(|&tail| seeds[tail].1 < y);
+        if pos > 0 {
+            previous[idx] = Some(tails[pos - 1]);
+        }
+        if pos == tails.len() {
+            tails.push(idx);
+        } else {
+            tails[pos] = idx;
+        }
+    }
+    let mut chain = Vec::with_capacity(tails.len());
+    let mut idx = tails.last().copied();
+    while let Some(seed) = idx {
+        chain.push(seeds[seed]);
+        idx = previous[seed];
+    }
+    chain.reverse();
+
+    let mut anchors = Vec::<Anchor>::new();
+    for (x, y) in chain {
+        match anchors.last_mut() {
+            Some(last) if x < last.x + last.length || y < last.y + last.length => {
+                if x - last.x == y - last.y {
+                    last.length = x + k - last.x;
+                }
+            }
+            _ => anchors.push(Anchor { x, y, length: k }),
+        }
+    }
+    anchors
+}
+
+/// Aligns two profiles by anchoring them at exact matches of at least `k` sites and filling the
+/// DP matrices only for the regions between the anchors, which are then stitched together with
+/// the anchors matched site by site. The memory and time needed are those of the largest
+/// region, so long similar sequences can be aligned, but the result is only optimal if the
+/// anchors are part of the optimal alignment. Free end gaps only apply at the ends of the whole
+/// alignment. Without anchors the profiles are aligned in one go.
+pub(crate) fn pars_align_anchored(
+    x_info: &[ParsimonySiteInfo],
+    x_scoring: &dyn BranchParsimonyCosts,
+    y_info: &[ParsimonySiteInfo],
+    y_scoring: &dyn BranchParsimonyCosts,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
+    options: &AlignmentOptions,
+    k: usize,
+) -> (Vec<ParsimonySiteInfo>, Alignment, f64) {
+    let anchors = find_anchors(x_info, y_info, k);
+    if anchors.is_empty() {
+        return pars_align_dp(
+            x_info,
+            x_scoring,
+            y_info,
+            y_scoring,
+            rng,
+            options,
+            options.free_end_gaps,
+            &NodeConstraints::default(),
+        );
+    }
+    info!(
+        "Found {} anchors covering {} of {} and {} sites.",
+        anchors.len(),
+        anchors.iter().map(|anchor| anchor.length).sum::<usize>(),
+        x_info.len(),
+        y_info.len()
+    );
+    // the regions share the random generator of the node
+    let rng = Arc::new(Mutex::new(rng));
+    let mut node_info = Vec::with_capacity(x_info.len() + y_info.len());
+    let mut alignment = Alignment::empty();
+    let mut score = 0.0;
+    let (mut x_start, mut y_start) = (0, 0);
+    let last = Anchor {
+        x: x_info.len(),
+        y: y_info.len(),
+        length: 0,
+    };
+    for (idx, anchor) in anchors.iter().chain([&last]).enumerate() {
+        let (x_region, y_region) = (&x_info[x_start..anchor.x], &y_info[y_start..anchor.y]);
+        if !x_region.is_empty() || !y_region.is_empty() {
+            let rng = rng.clone();
+            let free_end_gaps = options.free_end_gaps && (idx == 0 || idx == anchors.len());
+            let (region_info, region_alignment, region_score) = pars_align_dp(
+                x_region,
+                x_scoring,
+                y_region,
+                y_scoring,
+                move |l| (*rng.lock().unwrap())(l),
+                options,
+                free_end_gaps,
+                &NodeConstraints::default(),
+            );
+            node_info.extend(region_info);
+            alignment.map_x.extend(
+                region_alignment
+                    .map_x
+                    .iter()
+                    .map(|x| x.map(|x| x + x_start)),
+            );
+            alignment.map_y.extend(
+                region_alignment
+                    .map_y
+                    .iter()
+                    .map(|y| y.map(|y| y + y_start)),
+            );
+            score += region_score;
+        }
+        for offset in 0..anchor.length {
+            let (x, y) = (anchor.x + offset, anchor.y + offset);
+            let (site, site_score) = match_sites(&x_info[x], x_scoring, &y_info[y], y_scoring);
+            node_info.push(site);
+            alignment.map_x.push(Some(x));
+            alignment.map_y.push(Some(y));
+            score += site_score;
+        }
+        (x_start, y_start) = (anchor.x + anchor.length, anchor.y + anchor.length);
+    }
+    (node_info, alignment, score)
+}
+
+#[cfg(test)]
+mod anchors_tests {
+    use bio::io::fasta::Record;
+    use phylo::sequences::SequenceType;
+
+    use super::{find_anchors, pars_align_anchored, Anchor, NodeConstraints};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
+    use crate::parsimony_alignment::parsimony_info::ParsimonySiteInfo;
+    use crate::parsimony_alignment::{leaf_site_info, pars_align_dp, AlignmentOptions, TieBreak};
+
+    fn info(seq: &[u8]) -> Vec<ParsimonySiteInfo> {
+        leaf_site_info(
+            &Record::with_attrs("A", None, seq),
+            &SequenceType::DNA,
+            &AlignmentOptions::default(),
+        )
+    }
+
+    #[test]
+    fn anchors() {
+        let x = info(b"ACGTACCTTGCAGGATCCA");
+        let y = info(b"TTACGTACCTTGCATTGGATCCA");
+        assert_eq!(
+            find_anchors(&x, &y, 5),
+            vec![
+                Anchor {
+                    x: 0,
+                    y: 2,
+                    length: 12
+                },
+                Anchor {
+                    x: 12,
+                    y: 16,
+                    length: 7
+                },
+            ]
+        );
+        // repeated k-mers are not used as anchors
+        assert_eq!(find_anchors(&info(b"ACGACG"), &info(b"ACGACG"), 3), vec![]);
+        assert_eq!(find_anchors(&x, &y, 0), vec![]);
+        assert_eq!(find_anchors(&x, &y, 30), vec![]);
+    }
+
+    #[test]
+    fn anchored_alignment() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+        let options = AlignmentOptions {
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+        let x = info(b"ACGTACCTTGCAGGATCCA");
+        let y = info(b"TTACGTACCTTGCATTGGATCCA");
+        let costs = scoring.get_branch_costs(1.0);
+        let (node_info, alignment, score) =
+            pars_align_anchored(&x, costs, &y, costs, |_| 0, &options, 5);
+        let (dp_info, dp_alignment, dp_score) = pars_align_dp(
+            &x,
+            costs,
+            &y,
+            costs,
+            |_| 0,
+            &options,
+            false,
+            &NodeConstraints::default(),
+        );
+        assert_eq!(score, dp_score);
+        assert_eq!(alignment.map_x, dp_alignment.map_x);
+        assert_eq!(alignment.map_y, dp_alignment.map_y);
+        assert_eq!(node_info, dp_info);
+    }
+}
@@ -0,0 +1,128 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::phylo_info::phyloinfo_from_sequences_tree;
+use phylo::tree::Tree;
+use rayon::prelude::*;
+
+use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
+use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions, AlignmentResult};
+use crate::Result;
+
+/// A single family to be aligned in a batch: the unaligned sequences and their tree.
+pub type AlignmentJob = (Vec<Record>, Tree);
+
+/// Collects the branch lengths of all the trees in the batch, e.g. for choosing the branch
+/// length categories of a scoring that is shared between all the jobs.
+pub fn batch_branch_lengths(jobs: &[AlignmentJob]) -> Vec<f64> {
+    jobs.iter()
+        .flat_map(|(_, tree)| tree.get_all_branch_lengths())
+        .collect()
+}
+
+/// Aligns many independent families on a single thread pool. The scoring (and therefore the
+/// cost matrices) is shared between all the jobs, so it is only generated once for the batch.
+/// Results are returned in the order of the jobs, a failing job does not stop the others.
+/// With `threads` set to 0 the number of threads is chosen automatically.
+pub fn pars_align_batch(
+    scoring: &(dyn ParsimonyCosts + Sync),
+    jobs: Vec<AlignmentJob>,
+    options: &AlignmentOptions,
+    threads: usize,
+) -> Result<Vec<Result<AlignmentResult>>> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    info!(
+        "Aligning a batch of {} families on {} threads.",
+        jobs.len(),
+        pool.current_num_threads()
+    );
+    Ok(pool.install(|| {
+        jobs.into_par_iter()
+            .map(|(sequences, tree)| -> Result<AlignmentResult> {
+                let info = phyloinfo_from_sequences_tree(&sequences, tree)?;
+                pars_align_on_tree_w_options(scoring, &info, options)
+            })
+            .collect()
+    }))
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use super::{batch_branch_lengths, pars_align_batch, AlignmentJob};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn cherry_job(seq_a: &[u8], seq_b: &[u8], blen: f64) -> AlignmentJob {
+        let sequences = vec![
+            Record::with_attrs("A", None, seq_a),
+            Record::with_attrs("B", None, seq_b),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), blen, blen);
+        tree.complete = true;
+        tree.create_postorder();
+        (sequences, tree)
+    }
+
+    fn quartet_job() -> AlignmentJob {
+        let sequences = vec![
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"A"),
+            Record::with_attrs("D", None, b"GA"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.5, 0.5);
+        tree.add_parent(1, L(2), L(3), 0.5, 0.5);
+        tree.add_parent(2, I(0), I(1), 0.5, 0.5);
+        tree.complete = true;
+        tree.create_postorder();
+        (sequences, tree)
+    }
+
+    #[test]
+    fn batch_matches_single_runs() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(7),
+            ..Default::default()
+        };
+        let jobs = || {
+            vec![
+                cherry_job(b"AACT", b"AC", 1.0),
+                quartet_job(),
+                cherry_job(b"ACGTACGT", b"ACGACGT", 0.1),
+            ]
+        };
+        assert_eq!(batch_branch_lengths(&jobs()).len(), 2 + 6 + 2);
+        let results = pars_align_batch(&scoring, jobs(), &options, 2).unwrap();
+        assert_eq!(results.len(), 3);
+        for ((sequences, tree), result) in jobs().into_iter().zip(results) {
+            let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+            let expected = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+            let result = result.unwrap();
+            assert_eq!(result.scores, expected.scores);
+            for (alignment, expected) in result.alignments.iter().zip(expected.alignments.iter()) {
+                assert_eq!(alignment.map_x, expected.map_x);
+                assert_eq!(alignment.map_y, expected.map_y);
+            }
+        }
+    }
+
+    #[test]
+    fn batch_reports_failing_jobs() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let (_, tree) = quartet_job();
+        let jobs = vec![
+            cherry_job(b"AACT", b"AC", 1.0),
+            (vec![Record::with_attrs("X", None, b"ACGT")], tree),
+        ];
+        let results = pars_align_batch(&scoring, jobs, &AlignmentOptions::default(), 1).unwrap();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+}
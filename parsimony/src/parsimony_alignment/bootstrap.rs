@@ -0,0 +1,193 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::tree::{NodeIdx::Internal as Int, Tree};
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use super::msa_scoring::pars_score_msa;
+use super::parsimony_costs::ParsimonyCosts;
+use super::tree_search::Topology;
+use super::AlignmentOptions;
+use crate::{ParsimonyError, Result};
+
+/// Margin by which an interchanged tree has to score worse for a replicate to support a branch,
+/// so that rounding errors do not count as support.
+const SUPPORT_MARGIN: f64 = 1e-9;
+
+/// Scores of the bootstrap replicates of an MSA and the support of the branches of its tree.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BootstrapSupport {
+    /// Total score of the MSA on the tree.
+    pub score: f64,
+    /// Total scores of the replicates on the tree.
+    pub replicate_scores: Vec<f64>,
+    /// Fraction of the replicates that support the branch above every internal node, `None` for
+    /// the root and for the branches without nearest-neighbour interchanges.
+    pub support: Vec<Option<f64>>,
+}
+
+impl BootstrapSupport {
+    /// Interval of the replicate scores that holds the central `level` fraction of them, from
+    /// the nearest-rank percentiles.
+    pub fn confidence_interval(&self, level: f64) -> (f64, f64) {
+        let mut scores = self.replicate_scores.clone();
+        scores.sort_by(f64::total_cmp);
+        let last = scores.len() - 1;
+        let rank = |quantile: f64| (quantile * last as f64).round() as usize;
+        (
+            scores[rank((1.0 - level) / 2.0)],
+            scores[rank((1.0 + level) / 2.0)],
+        )
+    }
+}
+
+fn msa_score(
+    scoring: &dyn ParsimonyCosts,
+    sequences: &[Record],
+    tree: &Tree,
+    options: &AlignmentOptions,
+) -> Result<f64> {
+    let info = phyloinfo_from_sequences_tree(sequences, tree.clone())?;
+    Ok(pars_score_msa(scoring, &info, options)?.1.iter().sum())
+}
+
+/// Bootstraps an MSA on the tree by resampling its columns with replacement. Every replicate is
+/// scored on the tree, and a replicate supports the branch above an internal node if both
+/// nearest-neighbour interchanges around the branch give a higher score for the replicate. The
+/// two branches below the root form a single branch of the unrooted tree and get the same
+/// support. Replicate r draws its columns with the seed `seed + r`, the replicates are scored
+/// concurrently.
+pub fn pars_bootstrap_msa(
+    scoring: &(dyn ParsimonyCosts + Sync),
+    info: &PhyloInfo,
+    replicates: usize,
+    seed: u64,
+    options: &AlignmentOptions,
+) -> Result<BootstrapSupport> {
+    let tree = &info.tree;
+    let sequences = &info.sequences;
+    let columns = sequences.first().map_or(0, |record| record.seq().len());
+    if columns == 0 || replicates == 0 {
+        return Err(ParsimonyError::InvalidParameters(
+            "The bootstrap needs an MSA with columns and at least one replicate.".to_string(),
+        ));
+    }
+    let score = msa_score(scoring, sequences, tree, options)?;
+    let topology = Topology::new(tree);
+    let neighbours = (0..tree.internals.len())
+        .map(|idx| {
+            topology
+                .interchanges(idx)
+                .into_iter()
+                .map(|(a, b)| {
+                    let mut neighbour = topology.clone();
+                    neighbour.swap(a, b);
+                    neighbour.build_tree(sequences)
+                })
+                .collect::<Result<Vec<Tree>>>()
+        })
+        .collect::<Result<Vec<Vec<Tree>>>>()?;
+    info!(
+        "Bootstrapping the MSA with score {} with {} replicates.",
+        score, replicates
+    );
+
+    let results = (0..replicates)
+        .into_par_iter()
+        .map(|replicate| {
+            let mut rng = StdRng::seed_from_u64(seed.wrapping_add(replicate as u64));
+            let sampled: Vec<usize> = (0..columns).map(|_| rng.gen_range(0..columns)).collect();
+            let resampled: Vec<Record> = sequences
+                .iter()
+                .map(|record| {
+                    let row: Vec<u8> = sampled.iter().map(|&column| record.seq()[column]).collect();
+                    Record::with_attrs(record.id(), record.desc(), &row)
+                })
+                .collect();
+            let replicate_score = msa_score(scoring, &resampled, tree, options)?;
+            let mut supported = Vec::with_capacity(neighbours.len());
+            for trees in &neighbours {
+                let mut branch_supported = true;
+                for neighbour in trees {
+                    let neighbour_score = msa_score(scoring, &resampled, neighbour, options)?;
+                    branch_supported &= neighbour_score > replicate_score + SUPPORT_MARGIN;
+                }
+                supported.push(branch_supported);
+            }
+            Ok((replicate_score, supported))
+        })
+        .collect::<Result<Vec<(f64, Vec<bool>)>>>()?;
+
+    let mut support: Vec<Option<f64>> = neighbours
+        .iter()
+        .enumerate()
+        .map(|(idx, trees)| {
+            (!trees.is_empty()).then(|| {
+                let count = results
+                    .iter()
+                    .filter(|(_, supported)| supported[idx])
+                    .count();
+                count as f64 / replicates as f64
+            })
+        })
+        .collect();
+    let root = Into::<usize>::into(tree.root);
+    for child in &tree.internals[root].children {
+        if let Int(child) = child {
+            support[*child] = support[root];
+        }
+    }
+    support[root] = None;
+    Ok(BootstrapSupport {
+        score,
+        replicate_scores: results.into_iter().map(|(score, _)| score).collect(),
+        support,
+    })
+}
+
+#[cfg(test)]
+mod bootstrap_tests {
+    use super::{pars_bootstrap_msa, BootstrapSupport};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn bootstrap_clear_clades() {
+        let sequences = [
+            Record::with_attrs("A1", None, b"AAAAAAAA"),
+            Record::with_attrs("A2", None, b"AAAAAAAA"),
+            Record::with_attrs("B1", None, b"CCGGTTCC"),
+            Record::with_attrs("B2", None, b"CCGGTTCC"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let bootstrap =
+            pars_bootstrap_msa(&scoring, &info, 20, 3, &AlignmentOptions::default()).unwrap();
+        assert_eq!(bootstrap.score, 8.0);
+        assert_eq!(bootstrap.replicate_scores, vec![8.0; 20]);
+        assert_eq!(bootstrap.support, vec![Some(1.0), Some(1.0), None]);
+        assert_eq!(bootstrap.confidence_interval(0.95), (8.0, 8.0));
+    }
+
+    #[test]
+    fn confidence_interval() {
+        let bootstrap = BootstrapSupport {
+            score: 0.0,
+            replicate_scores: (0..=100).rev().map(f64::from).collect(),
+            support: Vec::new(),
+        };
+        assert_eq!(bootstrap.confidence_interval(0.9), (5.0, 95.0));
+        assert_eq!(bootstrap.confidence_interval(1.0), (0.0, 100.0));
+    }
+}
@@ -0,0 +1,261 @@
+use log::info;
+use nalgebra::{DMatrix, DVector, SymmetricEigen};
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+
+use super::msa_scoring::pars_ancestors_msa;
+use super::parsimony_costs::dna_models::MODEL_NUCLEOTIDES;
+use super::parsimony_costs::parsimony_costs_model::{
+    dna_rate_matrix, protein_rate_matrix, rate_matrix_frequencies, GammaRates,
+};
+use super::parsimony_costs::protein_models::MODEL_AMINOACIDS;
+use super::parsimony_costs::ParsimonyCosts;
+use super::AlignmentOptions;
+use crate::Result;
+
+/// Range of the estimated branch lengths, branches without substitutions get the shortest one.
+const MIN_BRANCH_LENGTH: f64 = 1e-6;
+const MAX_BRANCH_LENGTH: f64 = 10.0;
+
+/// Iterations of the golden section search, enough to pin the branch length down to a few
+/// parts per million of the range of its log.
+const SEARCH_ITERATIONS: usize = 64;
+
+/// Substitution model under which the branch lengths are re-estimated: the eigendecomposition
+/// of the rate matrix of the model, the order of its states and the rates of the site
+/// categories. With the equilibrium frequencies pi, q = D^-1/2 U diag(eigenvalues) U^T D^1/2
+/// for D = diag(pi), so that exp(q t) only needs the exponentials of the eigenvalues.
+#[derive(Clone, Debug)]
+pub struct BranchLengthModel {
+    left: DMatrix<f64>,
+    eigenvalues: DVector<f64>,
+    right: DMatrix<f64>,
+    chars: &'static [u8],
+    rates: Vec<f64>,
+}
+
+impl BranchLengthModel {
+    /// Named DNA model with its parameters, as for `DNAParsCosts::new_w_gamma`.
+    pub fn dna(model_name: &str, model_params: &[f64], gamma: &GammaRates) -> Result<Self> {
+        Ok(Self::from_rate_matrix(
+            &dna_rate_matrix(model_name, model_params)?,
+            MODEL_NUCLEOTIDES,
+            gamma,
+        ))
+    }
+
+    /// Named protein model, with its equilibrium frequencies replaced by `freqs` for +F.
+    pub fn protein(
+        model_name: &str,
+        freqs: Option<&[f64; 20]>,
+        gamma: &GammaRates,
+    ) -> Result<Self> {
+        Ok(Self::from_rate_matrix(
+            &protein_rate_matrix(model_name, freqs)?,
+            MODEL_AMINOACIDS,
+            gamma,
+        ))
+    }
+
+    /// Decomposes the reversible rate matrix `q` through the symmetric D^1/2 q D^-1/2.
+    fn from_rate_matrix(q: &DMatrix<f64>, chars: &'static [u8], gamma: &GammaRates) -> Self {
+        let n = q.nrows();
+        let sqrt_freqs: Vec<f64> = rate_matrix_frequencies(q)
+            .iter()
+            .map(|freq| freq.sqrt())
+            .collect();
+        let symmetric = DMatrix::from_fn(n, n, |i, j| sqrt_freqs[i] * q[(i, j)] / sqrt_freqs[j]);
+        let SymmetricEigen {
+            eigenvectors,
+            eigenvalues,
+        } = SymmetricEigen::new(symmetric);
+        BranchLengthModel {
+            left: DMatrix::from_fn(n, n, |i, k| eigenvectors[(i, k)] / sqrt_freqs[i]),
+            eigenvalues,
+            right: DMatrix::from_fn(n, n, |k, j| eigenvectors[(j, k)] * sqrt_freqs[j]),
+            chars,
+            rates: gamma.rates().to_vec(),
+        }
+    }
+
+    fn state(&self, residue: u8) -> Option<usize> {
+        let residue = match residue.to_ascii_uppercase() {
+            b'U' if self.chars == MODEL_NUCLEOTIDES => b'T',
+            residue => residue,
+        };
+        self.chars.iter().position(|&c| c == residue)
+    }
+
+    /// Substitution probabilities over a branch of length `time`, averaged over the rate
+    /// categories.
+    fn probabilities(&self, time: f64) -> DMatrix<f64> {
+        // the categories share the eigenvectors, only the exponentials are averaged
+        let exps = self.eigenvalues.map(|eigenvalue| {
+            self.rates
+                .iter()
+                .map(|rate| (eigenvalue * rate * time).exp())
+                .sum::<f64>()
+                / self.rates.len() as f64
+        });
+        &self.left * DMatrix::from_diagonal(&exps) * &self.right
+    }
+
+    /// Log-likelihood of the counted substitutions over a branch of length `time`.
+    fn log_likelihood(&self, counts: &DMatrix<f64>, time: f64) -> f64 {
+        let p = self.probabilities(time);
+        counts
+            .iter()
+            .zip(p.iter())
+            .filter(|(&count, _)| count > 0.0)
+            .map(|(count, prob)| count * prob.max(f64::MIN_POSITIVE).ln())
+            .sum()
+    }
+
+    /// Maximum likelihood branch length for the counts of the substitutions from the states of
+    /// the rows to the states of the columns, found by a golden section search on the log of
+    /// the branch length.
+    fn estimate(&self, counts: &DMatrix<f64>) -> f64 {
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (MIN_BRANCH_LENGTH.ln(), MAX_BRANCH_LENGTH.ln());
+        let value = |log_time: f64| self.log_likelihood(counts, log_time.exp());
+        let mut a = high - ratio * (high - low);
+        let mut b = low + ratio * (high - low);
+        let (mut value_a, mut value_b) = (value(a), value(b));
+        for _ in 0..SEARCH_ITERATIONS {
+            if value_a >= value_b {
+                high = b;
+                (b, value_b) = (a, value_a);
+                a = high - ratio * (high - low);
+                value_a = value(a);
+            } else {
+                low = a;
+                (a, value_a) = (b, value_b);
+                b = low + ratio * (high - low);
+                value_b = value(b);
+            }
+        }
+        ((low + high) / 2.0).exp()
+    }
+}
+
+/// Re-estimates the branch lengths of the tree from an MSA on it under the substitution model.
+/// This is a plug-in approximation rather than the maximum likelihood tree: the ancestral
+/// sequences are reconstructed as in `pars_ancestors_msa` and taken as observed instead of
+/// being summed over by Felsenstein pruning, and every branch gets the maximum likelihood
+/// length for the residues of its two ends on its own. The columns with a gap or an ambiguous
+/// residue at either end are left out. Branches without such columns keep their length, the
+/// root keeps its own.
+pub fn pars_branch_lengths(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    model: &BranchLengthModel,
+) -> Result<Tree> {
+    info!("Re-estimating the branch lengths from the MSA.");
+    let ancestors = pars_ancestors_msa(scoring, info, options)?;
+    let mut tree = info.tree.clone();
+    let n = model.chars.len();
+    for (parent, node) in info.tree.internals.iter().enumerate() {
+        for &child in &node.children {
+            let child_seq = match child {
+                Int(idx) => ancestors[idx].seq(),
+                Leaf(idx) => info.sequences[idx].seq(),
+            };
+            let mut counts = DMatrix::<f64>::zeros(n, n);
+            for (&from, &to) in ancestors[parent].seq().iter().zip(child_seq) {
+                if let (Some(from), Some(to)) = (model.state(from), model.state(to)) {
+                    counts[(from, to)] += 1.0;
+                }
+            }
+            if counts.sum() == 0.0 {
+                continue;
+            }
+            let blen = model.estimate(&counts);
+            match child {
+                Int(idx) => tree.internals[idx].blen = blen,
+                Leaf(idx) => tree.leaves[idx].blen = blen,
+            }
+        }
+    }
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod branch_lengths_tests {
+    use super::{pars_branch_lengths, BranchLengthModel, MIN_BRANCH_LENGTH};
+    use crate::parsimony_alignment::parsimony_costs::dna_models::MODEL_NUCLEOTIDES;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+        dna_rate_matrix, GammaRates,
+    };
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use nalgebra::{DMatrix, DVector};
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn jc69() -> BranchLengthModel {
+        let q = DMatrix::from_fn(4, 4, |i, j| if i == j { -1.0 } else { 1.0 / 3.0 });
+        BranchLengthModel::from_rate_matrix(&q, MODEL_NUCLEOTIDES, &GammaRates::none())
+    }
+
+    #[test]
+    fn jc69_distance() {
+        let model = jc69();
+        let mut counts = DMatrix::from_diagonal_element(4, 4, 22.5);
+        counts[(0, 1)] = 5.0;
+        counts[(2, 3)] = 5.0;
+        // p = 0.1 gives the JC69 distance -3/4 ln(1 - 4/3 p)
+        let expected = -0.75 * (1.0 - 4.0 / 3.0 * 0.1f64).ln();
+        assert_relative_eq!(model.estimate(&counts), expected, epsilon = 1e-5);
+        let same = DMatrix::from_diagonal_element(4, 4, 10.0);
+        assert_relative_eq!(model.estimate(&same), MIN_BRANCH_LENGTH, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn decomposed_probabilities() {
+        let q = dna_rate_matrix("hky85", &[0.1, 0.2, 0.3, 0.4, 2.0]).unwrap();
+        let gamma = GammaRates::new(0.5, 4).unwrap();
+        let model = BranchLengthModel::from_rate_matrix(&q, MODEL_NUCLEOTIDES, &gamma);
+        for time in [0.01, 0.3, 2.0] {
+            let expected = gamma
+                .rates()
+                .iter()
+                .fold(DMatrix::zeros(4, 4), |sum, rate| {
+                    sum + (&q * (rate * time)).exp()
+                })
+                / 4.0;
+            assert_relative_eq!(model.probabilities(time), expected, epsilon = 1e-10);
+        }
+        assert_relative_eq!(
+            model.probabilities(1e3).row(0).transpose(),
+            DVector::from_row_slice(&[0.1, 0.2, 0.3, 0.4]),
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn reestimated_tree() {
+        let msa = [
+            Record::with_attrs("A", None, b"ACGTACGTAC"),
+            Record::with_attrs("B", None, b"ACGTACGTAC"),
+            Record::with_attrs("C", None, b"ACGAACGTTC"),
+            Record::with_attrs("D", None, b"TCGAACCTTC"),
+        ];
+        let mut tree = Tree::new(&msa).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.3, 0.3);
+        tree.add_parent(1, L(2), L(3), 0.3, 0.3);
+        tree.add_parent(2, I(0), I(1), 0.3, 0.3);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&msa, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let tree =
+            pars_branch_lengths(&scoring, &info, &AlignmentOptions::default(), &jc69()).unwrap();
+        assert_relative_eq!(tree.leaves[0].blen, MIN_BRANCH_LENGTH, epsilon = 1e-6);
+        assert_relative_eq!(tree.leaves[1].blen, MIN_BRANCH_LENGTH, epsilon = 1e-6);
+        assert!(tree.leaves[3].blen > tree.leaves[2].blen);
+        assert!(tree.leaves[3].blen > 0.1);
+    }
+}
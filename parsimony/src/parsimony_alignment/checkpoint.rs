@@ -0,0 +1,457 @@
+use log::{info, warn};
+use phylo::alignment::{Alignment, Mapping};
+use phylo::phylo_info::PhyloInfo;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use super::parsimony_costs::cost_cache::fnv1a;
+use super::parsimony_costs::ParsimonyCosts;
+use super::parsimony_info::{ParsimonySiteInfo, SiteFlag};
+use super::{align_info_on_tree, leaf_site_info, AlignmentOptions, AlignmentResult, NodeStore};
+use crate::{ParsimonyError, Result};
+
+const HEADER: &str = "# indelMaP checkpoint";
+
+/// Result of an internal node that was aligned before the run was interrupted.
+struct NodeResult {
+    info: Vec<ParsimonySiteInfo>,
+    alignment: Alignment,
+    score: f64,
+}
+
+/// The header ties a checkpoint to the tree size, the sequence ids and a fingerprint of the
+/// settings that change the alignment of a node.
+fn header(scoring: &dyn ParsimonyCosts, info: &PhyloInfo, options: &AlignmentOptions) -> String {
+    let ids: Vec<&str> = info.sequences.iter().map(|record| record.id()).collect();
+    format!(
+        "{}\t{}\t{}\t{:016x}",
+        HEADER,
+        info.tree.internals.len(),
+        ids.join(","),
+        fingerprint(scoring, info, options)
+    )
+}
+
+/// Hash of the options that change the alignment of a node, of the leaf profiles, which carry
+/// the data type, recoding, alphabet, masks, gap profiles and partitions, and of the costs of
+/// every branch of the tree for the characters of the leaves.
+fn fingerprint(scoring: &dyn ParsimonyCosts, info: &PhyloInfo, options: &AlignmentOptions) -> u64 {
+    let sequence_type = options.data_type.sequence_type(&info.sequences);
+    let mut settings = format!(
+        "{:?}",
+        (
+            options.seed,
+            options.tie_break,
+            options.wavefront,
+            options.band,
+            options.x_drop,
+            options.free_end_gaps,
+            options.sequence_weighting,
+            options.gap_function,
+            options.anchor_length,
+            &options.constraints,
+        )
+    );
+    let mut symbols = BTreeSet::new();
+    for record in &info.sequences {
+        let leaf_info = leaf_site_info(record, &sequence_type, options);
+        symbols.extend(leaf_info.iter().flat_map(|site| site.set.iter()));
+        settings.push('\n');
+        settings.push_str(&encode_info(&leaf_info));
+    }
+    let branches = info.tree.leaves.iter().map(|node| node.blen);
+    for blen in branches.chain(info.tree.internals.iter().map(|node| node.blen)) {
+        let costs = scoring.get_branch_costs(blen);
+        settings.push_str(&format!(
+            "\n{:?}",
+            (
+                blen,
+                costs.gap_open_cost(),
+                costs.gap_ext_cost(),
+                costs.avg_cost(),
+                costs.site_categories()
+            )
+        ));
+        for &i in &symbols {
+            for &j in &symbols {
+                settings.push_str(&format!(" {:?}", costs.match_cost(i, j)));
+            }
+        }
+    }
+    fnv1a(settings.as_bytes())
+}
+
+fn encode_mapping(mapping: &Mapping) -> String {
+    let sites: Vec<String> = mapping
+        .iter()
+        .map(|site| site.map_or("-".to_string(), |idx| idx.to_string()))
+        .collect();
+    sites.join(",")
+}
+
+fn parse_mapping(field: &str) -> Option<Mapping> {
+    if field.is_empty() {
+        return Some(Mapping::new());
+    }
+    field
+        .split(',')
+        .map(|site| match site {
+            "-" => Some(None),
+            idx => idx.parse().ok().map(Some),
+        })
+        .collect()
+}
+
+fn encode_info(info: &[ParsimonySiteInfo]) -> String {
+    let sites: Vec<String> = info
+        .iter()
+        .map(|site| {
+            let flag = match site.flag {
+                SiteFlag::GapFixed => 'F',
+                SiteFlag::GapOpen => 'O',
+                SiteFlag::GapExt => 'E',
+                SiteFlag::NoGap => 'N',
+            };
+            let set: Vec<u8> = site.set.iter().collect();
+            let set = String::from_utf8_lossy(&set);
+            let mut site_str = format!("{}{}", flag, set);
+            if site.gap_weight != 1.0 {
+                site_str.push_str(&format!("@{}", site.gap_weight));
+            }
+            if let Some(category) = site.site_category {
+                site_str.push_str(&format!("#{}", category));
+            }
+            site_str
+        })
+        .collect();
+    sites.join(",")
+}
+
+fn parse_info(field: &str) -> Option<Vec<ParsimonySiteInfo>> {
+    if field.is_empty() {
+        return Some(Vec::new());
+    }
+    field
+        .split(',')
+        .map(|site| {
+            let flag = match site.as_bytes().first()? {
+                b'F' => SiteFlag::GapFixed,
+                b'O' => SiteFlag::GapOpen,
+                b'E' => SiteFlag::GapExt,
+                b'N' => SiteFlag::NoGap,
+                _ => return None,
+            };
+            let (site, site_category) = match site.rsplit_once('#') {
+                Some((site, category)) => (site, Some(category.parse().ok()?)),
+                None => (site, None),
+            };
+            let (set, gap_weight) = match site.rsplit_once('@') {
+                Some((set, gap_weight)) => (set, gap_weight.parse().ok()?),
+                None => (site, 1.0),
+            };
+            Some(
+                ParsimonySiteInfo::new(set.bytes().skip(1), flag)
+                    .with_gap_weight(gap_weight)
+                    .with_site_category(site_category),
+            )
+        })
+        .collect()
+}
+
+fn encode_node(
+    idx: usize,
+    info: &[ParsimonySiteInfo],
+    alignment: &Alignment,
+    score: f64,
+) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}",
+        idx,
+        score,
+        encode_mapping(&alignment.map_x),
+        encode_mapping(&alignment.map_y),
+        encode_info(info)
+    )
+}
+
+fn parse_node(line: &str) -> Option<(usize, NodeResult)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 5 {
+        return None;
+    }
+    let alignment = Alignment::new(parse_mapping(fields[2])?, parse_mapping(fields[3])?);
+    let info = parse_info(fields[4])?;
+    if alignment.map_x.len() != info.len() || alignment.map_y.len() != info.len() {
+        return None;
+    }
+    Some((
+        fields[0].parse().ok()?,
+        NodeResult {
+            score: fields[1].parse().ok()?,
+            alignment,
+            info,
+        },
+    ))
+}
+
+/// Whether the mapping places the sites of a child profile of `len` sites in order.
+fn maps_sites(mapping: &Mapping, len: usize) -> bool {
+    mapping.iter().flatten().copied().eq(0..len)
+}
+
+/// Reads the nodes completed in a previous run, stopping at the first entry that cannot be
+/// parsed or that is not terminated by a newline, e.g. a line that was cut off when the run was
+/// interrupted.
+fn load_checkpoint(
+    path: &Path,
+    header: &str,
+    internals: usize,
+) -> Result<HashMap<usize, NodeResult>> {
+    let content = fs::read_to_string(path)?;
+    let mut lines = content.split_inclusive('\n');
+    if lines.next().map(|line| line.trim_end_matches('\n')) != Some(header) {
+        return Err(ParsimonyError::TreeSequenceMismatch(format!(
+            "The checkpoint {} was written for a different tree, sequences or settings.",
+            path.display()
+        )));
+    }
+    let mut done = HashMap::new();
+    for line in lines {
+        match line.strip_suffix('\n').and_then(parse_node) {
+            Some((idx, node)) if idx < internals => {
+                done.insert(idx, node);
+            }
+            _ => {
+                warn!(
+                    "Ignoring an incomplete entry at the end of the checkpoint {}.",
+                    path.display()
+                );
+                break;
+            }
+        }
+    }
+    Ok(done)
+}
+
+/// Rewrites the checkpoint with the header and the nodes that are already done. The file is
+/// replaced in one step so that an interruption cannot lose the previous checkpoint.
+fn write_checkpoint(
+    path: &Path,
+    header: &str,
+    internals: usize,
+    done: &HashMap<usize, NodeResult>,
+) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    writeln!(writer, "{}", header)?;
+    for idx in 0..internals {
+        if let Some(node) = done.get(&idx) {
+            writeln!(
+                writer,
+                "{}",
+                encode_node(idx, &node.info, &node.alignment, node.score)
+            )?;
+        }
+    }
+    writer.flush()?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Nodes read from the checkpoint and the file that the newly aligned nodes are appended to.
+struct Checkpoint<'a> {
+    path: &'a Path,
+    done: HashMap<usize, NodeResult>,
+    writer: BufWriter<File>,
+}
+
+impl NodeStore for Checkpoint<'_> {
+    fn load(
+        &mut self,
+        idx: usize,
+        x_info: &[ParsimonySiteInfo],
+        y_info: &[ParsimonySiteInfo],
+    ) -> Option<(Vec<ParsimonySiteInfo>, Alignment, f64)> {
+        let node = self.done.remove(&idx)?;
+        if !maps_sites(&node.alignment.map_x, x_info.len())
+            || !maps_sites(&node.alignment.map_y, y_info.len())
+        {
+            warn!(
+                "The entry of internal node {} in the checkpoint {} does not match its children, aligning it again.",
+                idx,
+                self.path.display()
+            );
+            return None;
+        }
+        Some((node.info, node.alignment, node.score))
+    }
+
+    fn store(
+        &mut self,
+        idx: usize,
+        info: &[ParsimonySiteInfo],
+        alignment: &Alignment,
+        score: f64,
+    ) -> Result<()> {
+        writeln!(self.writer, "{}", encode_node(idx, info, alignment, score))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Aligns the sequences on the tree like `pars_align_on_tree_w_options` and appends every
+/// aligned internal node to the `checkpoint` file. With `resume` the nodes found in an existing
+/// checkpoint are taken from it instead of being aligned again, a checkpoint written with other
+/// sequences, costs or options is rejected. Every node has its own random generator, so a
+/// resumed run gives the same result as an uninterrupted one.
+pub fn pars_align_on_tree_w_checkpoint(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    checkpoint: &Path,
+    resume: bool,
+) -> Result<AlignmentResult> {
+    let header = header(scoring, info, options);
+    let internals = info.tree.internals.len();
+    let done = if resume && checkpoint.exists() {
+        let done = load_checkpoint(checkpoint, &header, internals)?;
+        info!(
+            "Resuming from {} of {} internal nodes in {}.",
+            done.len(),
+            internals,
+            checkpoint.display()
+        );
+        done
+    } else {
+        HashMap::new()
+    };
+    write_checkpoint(checkpoint, &header, internals, &done)?;
+    let mut store = Checkpoint {
+        path: checkpoint,
+        done,
+        writer: BufWriter::new(OpenOptions::new().append(true).open(checkpoint)?),
+    };
+    align_info_on_tree(scoring, info, options, Some(&mut store))
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use super::pars_align_on_tree_w_checkpoint;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+    use std::fs;
+
+    #[test]
+    fn resume_after_interruption() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+            Record::with_attrs("D", None, b"GACTT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(3),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("indelMaP_resume_after_interruption.checkpoint");
+
+        let result =
+            pars_align_on_tree_w_checkpoint(&scoring, &info, &options, &path, false).unwrap();
+        let expected = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(result.scores, expected.scores);
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 4);
+
+        // keep the first node and a cut off second one
+        let lines: Vec<&str> = content.lines().collect();
+        let interrupted = format!("{}\n{}\n{}", lines[0], lines[1], &lines[2][..4]);
+        fs::write(&path, interrupted).unwrap();
+        let resumed =
+            pars_align_on_tree_w_checkpoint(&scoring, &info, &options, &path, true).unwrap();
+        assert_eq!(resumed.scores, result.scores);
+        for (resumed, alignment) in resumed.alignments.iter().zip(&expected.alignments) {
+            assert_eq!(resumed.map_x, alignment.map_x);
+            assert_eq!(resumed.map_y, alignment.map_y);
+        }
+        assert_eq!(result.alignments.len(), resumed.alignments.len());
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        let other = phyloinfo_from_sequences_tree(&sequences[..2], {
+            let mut tree = Tree::new(&sequences[..2]).unwrap();
+            tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+            tree.complete = true;
+            tree.create_postorder();
+            tree
+        })
+        .unwrap();
+        assert!(pars_align_on_tree_w_checkpoint(&scoring, &other, &options, &path, true).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_rejects_truncated_entries_and_other_settings() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(3),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join("indelMaP_resume_truncated.checkpoint");
+        pars_align_on_tree_w_checkpoint(&scoring, &info, &options, &path, false).unwrap();
+        let expected = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+
+        // a complete line whose info field lost its last site
+        let lines: Vec<&str> = content.lines().collect();
+        let cut = lines[2].rfind(',').unwrap();
+        fs::write(
+            &path,
+            format!("{}\n{}\n{}\n", lines[0], lines[1], &lines[2][..cut]),
+        )
+        .unwrap();
+        let resumed =
+            pars_align_on_tree_w_checkpoint(&scoring, &info, &options, &path, true).unwrap();
+        assert_eq!(resumed.scores, expected.scores);
+        for (resumed, alignment) in resumed.alignments.iter().zip(&expected.alignments) {
+            assert_eq!(resumed.map_x, alignment.map_x);
+            assert_eq!(resumed.map_y, alignment.map_y);
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        let other_scoring = ParsimonyCostsSimple::new(1.0, 3.0, 0.5);
+        assert!(
+            pars_align_on_tree_w_checkpoint(&other_scoring, &info, &options, &path, true).is_err()
+        );
+        let other_options = AlignmentOptions {
+            seed: Some(4),
+            ..Default::default()
+        };
+        assert!(
+            pars_align_on_tree_w_checkpoint(&scoring, &info, &other_options, &path, true).is_err()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
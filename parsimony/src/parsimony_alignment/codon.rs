@@ -0,0 +1,286 @@
+use bio::io::fasta::Record;
+use nalgebra::DMatrix;
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use std::collections::HashMap;
+
+use super::alphabet::Alphabet;
+use super::parsimony_costs::parsimony_costs_model::{
+    DNAParsCosts, GapMultipliers, ParsimonyCostsWModel,
+};
+use super::parsimony_costs::ParsimonyCosts;
+use crate::{ParsimonyError, Result};
+
+pub type CodonParsCosts = ParsimonyCostsWModel<64>;
+
+const NUCLEOTIDES: &[u8; 4] = b"ACGT";
+
+/// The 64 codons are encoded as the bytes from `CODON_BASE` on, so that they do not clash with
+/// the gap or the ambiguous codon symbol.
+const CODON_BASE: u8 = 128;
+
+/// Symbol of a codon with an ambiguous or unknown nucleotide, stands for all the codons.
+const AMBIGUOUS_CODON: u8 = b'X';
+
+/// Amino acids of the codons in the standard genetic code, in the order of the codon symbols,
+/// with * for the stop codons.
+const STANDARD_CODE: &[u8; 64] =
+    b"KNKNTTTTRSRSIIMIQHQHPPPPRRRRLLLLEDEDAAAAGGGGVVVV*Y*YSSSS*CWCLFLF";
+
+fn nucleotide_index(nucleotide: u8) -> Option<usize> {
+    match nucleotide.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' | b'U' => Some(3),
+        _ => None,
+    }
+}
+
+fn codon_symbol(codon: &[u8]) -> u8 {
+    codon
+        .iter()
+        .try_fold(0, |acc, &c| Some(acc * 4 + nucleotide_index(c)?))
+        .map_or(AMBIGUOUS_CODON, |idx| CODON_BASE + idx as u8)
+}
+
+/// Alphabet of the encoded codons, to be set in the alignment options of a codon alignment.
+pub fn codon_alphabet() -> Alphabet {
+    let symbols: Vec<u8> = (0..64).map(|idx| CODON_BASE + idx).collect();
+    Alphabet::new(&symbols).unwrap()
+}
+
+/// Encodes every codon of a coding sequence as a single symbol of the codon alphabet, gaps in the
+/// input are dropped. The sequence length must be a multiple of three.
+pub fn encode_codons(record: &Record) -> Result<Record> {
+    let residues: Vec<u8> = record
+        .seq()
+        .iter()
+        .copied()
+        .filter(|&c| c != b'-')
+        .collect();
+    if residues.len() % 3 != 0 {
+        return Err(ParsimonyError::InvalidInput(format!(
+            "Sequence {} has length {} which is not a multiple of three.",
+            record.id(),
+            residues.len()
+        )));
+    }
+    let codons: Vec<u8> = residues.chunks(3).map(codon_symbol).collect();
+    Ok(Record::with_attrs(record.id(), record.desc(), &codons))
+}
+
+/// Sequences and tree with the sequences encoded as codons.
+pub fn codon_phylo_info(info: &PhyloInfo) -> Result<PhyloInfo> {
+    let sequences = info
+        .sequences
+        .iter()
+        .map(encode_codons)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(phyloinfo_from_sequences_tree(
+        &sequences,
+        info.tree.clone(),
+    )?)
+}
+
+/// Translates a coding sequence with the standard genetic code, gaps in the input are dropped.
+/// Codons with an ambiguous nucleotide and stop codons translate to X, which matches any amino
+/// acid. The sequence length must be a multiple of three.
+pub fn translate(record: &Record) -> Result<Record> {
+    let codons = encode_codons(record)?;
+    let peptide: Vec<u8> = codons
+        .seq()
+        .iter()
+        .map(|&symbol| match symbol {
+            AMBIGUOUS_CODON => b'X',
+            symbol => match STANDARD_CODE[(symbol - CODON_BASE) as usize] {
+                b'*' => b'X',
+                amino_acid => amino_acid,
+            },
+        })
+        .collect();
+    Ok(Record::with_attrs(record.id(), record.desc(), &peptide))
+}
+
+/// Sequences and tree with the sequences translated to peptides, for aligning coding DNA with
+/// protein costs. `expand_codon_msa` threads the nucleotides back through the peptide MSA.
+pub fn translated_phylo_info(info: &PhyloInfo) -> Result<PhyloInfo> {
+    let sequences = info
+        .sequences
+        .iter()
+        .map(translate)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(phyloinfo_from_sequences_tree(
+        &sequences,
+        info.tree.clone(),
+    )?)
+}
+
+/// Codon scoring derived from a DNA scoring, for every branch length the cost between two
+/// codons is the sum of the costs between their nucleotides at the three positions. The gap
+/// costs come from the average codon cost, so a codon gap costs about three nucleotide gaps.
+pub fn codon_scoring(dna: &DNAParsCosts, gap_mult: &GapMultipliers) -> Result<CodonParsCosts> {
+    let matrices: Vec<(f64, DMatrix<f64>)> = dna
+        .times()
+        .iter()
+        .map(|&time| {
+            let costs = dna.get_branch_costs(time);
+            let matrix = DMatrix::from_fn(64, 64, |i, j| {
+                (0..3)
+                    .map(|pos| {
+                        let shift = 2 * (2 - pos);
+                        costs.match_cost(
+                            NUCLEOTIDES[(i >> shift) & 3],
+                            NUCLEOTIDES[(j >> shift) & 3],
+                        )
+                    })
+                    .sum()
+            });
+            (time, matrix)
+        })
+        .collect();
+    CodonParsCosts::from_cost_matrices(&codon_alphabet(), &matrices, gap_mult)
+}
+
+/// Turns an MSA of encoded codons or of translated peptides back into nucleotides, every codon
+/// or amino acid is replaced by the three nucleotides of the original sequence and every gap by
+/// three gaps.
+pub fn expand_codon_msa(codon_msa: &[Record], sequences: &[Record]) -> Result<Vec<Record>> {
+    let originals: HashMap<&str, &Record> = sequences
+        .iter()
+        .map(|record| (record.id(), record))
+        .collect();
+    codon_msa
+        .iter()
+        .map(|aligned| {
+            let Some(original) = originals.get(aligned.id()) else {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "No original sequence for the aligned codons of {}.",
+                    aligned.id()
+                )));
+            };
+            let residues: Vec<u8> = original
+                .seq()
+                .iter()
+                .copied()
+                .filter(|&c| c != b'-')
+                .collect();
+            let mut codons = residues.chunks(3);
+            let mut seq = Vec::with_capacity(aligned.seq().len() * 3);
+            for &symbol in aligned.seq() {
+                match symbol {
+                    b'-' => seq.extend_from_slice(b"---"),
+                    _ => match codons.next() {
+                        Some(codon) => seq.extend_from_slice(codon),
+                        None => {
+                            return Err(ParsimonyError::SequenceMismatch(format!(
+                                "The aligned codons of {} do not fit the sequence.",
+                                aligned.id()
+                            )))
+                        }
+                    },
+                }
+            }
+            Ok(Record::with_attrs(aligned.id(), aligned.desc(), &seq))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod codon_tests {
+    use super::{codon_alphabet, codon_scoring, encode_codons, expand_codon_msa};
+    use super::{codon_phylo_info, translate, translated_phylo_info, AMBIGUOUS_CODON, CODON_BASE};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+        DNAParsCosts, GapMultipliers, ProteinParsCosts,
+    };
+    use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Leaf as L, Tree};
+    use phylo::Rounding;
+
+    #[test]
+    fn codon_encoding() {
+        let encoded = encode_codons(&Record::with_attrs("A", None, b"AAA-TTTaNg")).unwrap();
+        assert_eq!(
+            encoded.seq(),
+            &[CODON_BASE, CODON_BASE + 63, AMBIGUOUS_CODON]
+        );
+        assert!(encode_codons(&Record::with_attrs("A", None, b"AAAT")).is_err());
+        assert_eq!(codon_alphabet().len(), 64);
+    }
+
+    #[test]
+    fn codon_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ATGAAACCC"),
+            Record::with_attrs("B", None, b"ATGCCC"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.5, 0.5);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let gap_mult = GapMultipliers::new(2.5, 0.5);
+        let dna =
+            DNAParsCosts::new("JC69", &[], &gap_mult, &[0.5], false, &Rounding::none()).unwrap();
+        let scoring = codon_scoring(&dna, &gap_mult).unwrap();
+        let dna_costs = dna.get_branch_costs(0.5);
+        let codon_costs = scoring.get_branch_costs(0.5);
+        assert_eq!(
+            codon_costs.match_cost(CODON_BASE, CODON_BASE + 1),
+            dna_costs.match_cost(b'A', b'C') + 2.0 * dna_costs.match_cost(b'A', b'A')
+        );
+
+        let codon_info = codon_phylo_info(&info).unwrap();
+        let options = AlignmentOptions {
+            alphabet: Some(codon_alphabet()),
+            ..Default::default()
+        };
+        let codon_msa = pars_align_on_tree_w_options(&scoring, &codon_info, &options)
+            .unwrap()
+            .msa(&codon_info);
+        let msa = expand_codon_msa(&codon_msa, &info.sequences).unwrap();
+        let b = msa.iter().find(|record| record.id() == "B").unwrap();
+        assert_eq!(b.seq(), b"ATG---CCC");
+    }
+
+    #[test]
+    fn translation() {
+        let peptide = translate(&Record::with_attrs("A", None, b"ATGTGG-TTCtaaGCNugg")).unwrap();
+        assert_eq!(peptide.seq(), b"MWFXXW");
+        assert!(translate(&Record::with_attrs("A", None, b"ATGT")).is_err());
+    }
+
+    #[test]
+    fn translated_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ATGAAACCCTGG"),
+            Record::with_attrs("B", None, b"ATGCCGTGG"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.5, 0.5);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ProteinParsCosts::new(
+            "WAG",
+            &GapMultipliers::new(2.5, 0.5),
+            &[0.5],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap();
+        let translated = translated_phylo_info(&info).unwrap();
+        let peptides: Vec<&[u8]> = translated.sequences.iter().map(|r| r.seq()).collect();
+        assert_eq!(peptides, [&b"MKPW"[..], &b"MPW"[..]]);
+        let peptide_msa =
+            pars_align_on_tree_w_options(&scoring, &translated, &AlignmentOptions::default())
+                .unwrap()
+                .msa(&translated);
+        let msa = expand_codon_msa(&peptide_msa, &info.sequences).unwrap();
+        // the synonymous CCC and CCG are aligned and the gap keeps the codons together
+        let b = msa.iter().find(|record| record.id() == "B").unwrap();
+        assert_eq!(b.seq(), b"ATG---CCGTGG");
+    }
+}
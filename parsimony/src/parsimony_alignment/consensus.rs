@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+
+use bio::io::fasta::Record;
+use phylo::sequences::SequenceType;
+
+use super::msa_scoring::GAP;
+use super::parsimony_sets::{get_dna_set, get_protein_set, ParsimonySet};
+
+/// How the character of a consensus column is chosen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsensusRule {
+    /// The most frequent residue of the column if at least this fraction of the residues in the
+    /// column are that residue, the fully ambiguous character N or X if not.
+    Majority(f64),
+    /// The ambiguity code of all the residues in the column, e.g. R for a column of A and G.
+    Ambiguity,
+}
+
+/// IUPAC codes of the sets of nucleotides, the members in alphabetical order.
+const DNA_CODES: [(&str, u8); 15] = [
+    ("A", b'A'),
+    ("C", b'C'),
+    ("G", b'G'),
+    ("T", b'T'),
+    ("AG", b'R'),
+    ("CT", b'Y'),
+    ("CG", b'S'),
+    ("AT", b'W'),
+    ("GT", b'K'),
+    ("AC", b'M'),
+    ("CGT", b'B'),
+    ("AGT", b'D'),
+    ("ACT", b'H'),
+    ("ACG", b'V'),
+    ("ACGT", b'N'),
+];
+
+/// Consensus of the MSA. Columns in which most of the rows have a gap are left out, the other
+/// columns get the character picked by `rule` from their residues, case is ignored.
+pub fn consensus_sequence(
+    msa: &[Record],
+    rule: ConsensusRule,
+    sequence_type: &SequenceType,
+) -> Vec<u8> {
+    let columns = msa.first().map_or(0, |record| record.seq().len());
+    (0..columns)
+        .filter_map(|column| {
+            let residues: Vec<u8> = msa
+                .iter()
+                .map(|record| record.seq()[column].to_ascii_uppercase())
+                .filter(|&c| c != GAP)
+                .collect();
+            if 2 * residues.len() <= msa.len() {
+                return None;
+            }
+            Some(match rule {
+                ConsensusRule::Majority(threshold) => {
+                    majority_char(&residues, threshold, sequence_type)
+                }
+                ConsensusRule::Ambiguity => ambiguity_char(&residues, sequence_type),
+            })
+        })
+        .collect()
+}
+
+fn unknown_char(sequence_type: &SequenceType) -> u8 {
+    match sequence_type {
+        SequenceType::DNA => b'N',
+        SequenceType::Protein => b'X',
+    }
+}
+
+fn majority_char(residues: &[u8], threshold: f64, sequence_type: &SequenceType) -> u8 {
+    let mut counts = BTreeMap::<u8, usize>::new();
+    for &residue in residues {
+        *counts.entry(residue).or_default() += 1;
+    }
+    // the first of the most frequent residues, so ties are broken alphabetically
+    let (residue, count) = counts
+        .into_iter()
+        .rev()
+        .max_by_key(|&(_, count)| count)
+        .unwrap();
+    if count as f64 >= threshold * residues.len() as f64 {
+        residue
+    } else {
+        unknown_char(sequence_type)
+    }
+}
+
+fn ambiguity_char(residues: &[u8], sequence_type: &SequenceType) -> u8 {
+    let set: ParsimonySet = residues
+        .iter()
+        .flat_map(|residue| match sequence_type {
+            SequenceType::DNA => get_dna_set(residue),
+            SequenceType::Protein => get_protein_set(residue),
+        })
+        .collect();
+    let mut members: Vec<u8> = set.into_iter().collect();
+    members.sort_unstable();
+    match sequence_type {
+        SequenceType::DNA => DNA_CODES
+            .iter()
+            .find(|(code_members, _)| code_members.as_bytes() == members)
+            .map_or(b'N', |&(_, code)| code),
+        SequenceType::Protein => match &members[..] {
+            [residue] => *residue,
+            b"DN" => b'B',
+            b"EQ" => b'Z',
+            b"IL" => b'J',
+            _ => b'X',
+        },
+    }
+}
+
+#[cfg(test)]
+mod consensus_tests {
+    use super::{consensus_sequence, ConsensusRule};
+    use bio::io::fasta::Record;
+    use phylo::sequences::SequenceType;
+
+    fn msa(rows: &[&[u8]]) -> Vec<Record> {
+        rows.iter()
+            .enumerate()
+            .map(|(idx, row)| Record::with_attrs(&format!("S{}", idx), None, row))
+            .collect()
+    }
+
+    #[test]
+    fn majority_consensus() {
+        let dna = msa(&[b"AC-GTA", b"AC-GCA", b"ATTGC-", b"ac-GG-"]);
+        assert_eq!(
+            consensus_sequence(&dna, ConsensusRule::Majority(0.5), &SequenceType::DNA),
+            b"ACGC".to_vec()
+        );
+        assert_eq!(
+            consensus_sequence(&dna, ConsensusRule::Majority(0.75), &SequenceType::DNA),
+            b"ACGN".to_vec()
+        );
+        let protein = msa(&[b"MKL", b"MRL", b"MKI"]);
+        assert_eq!(
+            consensus_sequence(
+                &protein,
+                ConsensusRule::Majority(0.9),
+                &SequenceType::Protein
+            ),
+            b"MXX".to_vec()
+        );
+    }
+
+    #[test]
+    fn ambiguity_consensus() {
+        let dna = msa(&[b"AC-GTA", b"AC-GCA", b"ATTGC-", b"ac-GG-"]);
+        assert_eq!(
+            consensus_sequence(&dna, ConsensusRule::Ambiguity, &SequenceType::DNA),
+            b"AYGB".to_vec()
+        );
+        assert_eq!(
+            consensus_sequence(
+                &msa(&[b"R", b"C"]),
+                ConsensusRule::Ambiguity,
+                &SequenceType::DNA
+            ),
+            b"V".to_vec()
+        );
+        let protein = msa(&[b"MDEIW", b"MNQLK", b"MNQLA"]);
+        assert_eq!(
+            consensus_sequence(&protein, ConsensusRule::Ambiguity, &SequenceType::Protein),
+            b"MBZJX".to_vec()
+        );
+        assert!(consensus_sequence(&[], ConsensusRule::Ambiguity, &SequenceType::DNA).is_empty());
+    }
+}
@@ -0,0 +1,713 @@
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+#[cfg(not(target_arch = "wasm32"))]
+use bio::io::fasta;
+use bio::io::fasta::Record;
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+
+use super::parsimony_info::ParsimonySiteInfo;
+use crate::{ParsimonyError, Result};
+
+/// Two residues that must end up in the same column of the MSA, given by the sequence ids and
+/// the 0-based positions of the residues in the ungapped sequences.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResiduePair {
+    pub first: (String, usize),
+    pub second: (String, usize),
+}
+
+/// A residue that must not share a column of the MSA with any residue of the sequence `other`,
+/// i.e. `other` has a gap in the column of the residue.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResidueGap {
+    pub residue: (String, usize),
+    pub other: String,
+}
+
+/// Curated anchors that the alignment must respect. Every constraint is enforced at the node
+/// where the subtrees of its two sequences are joined, the DP of that node only allows
+/// alignments that match the sites holding two paired residues and that do not match the site
+/// of a gapped residue with a site holding a residue of the other sequence.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AnchorConstraints {
+    pairs: Vec<ResiduePair>,
+    gaps: Vec<ResidueGap>,
+}
+
+impl AnchorConstraints {
+    pub fn new(pairs: Vec<ResiduePair>) -> Self {
+        AnchorConstraints {
+            pairs,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Constraints that keep the aligned columns of a seed alignment of some of the sequences.
+    /// As in the A2M format, uppercase residues and - gaps make up the columns that are kept,
+    /// the uppercase residues of such a column are aligned with each other and with none of the
+    /// residues of the sequences that have a - in it. Lowercase residues and . gaps are not
+    /// aligned and placed freely, so a seed can fix just a trusted region of the sequences. The
+    /// ungapped rows of the seed must be the input sequences with the same ids, up to case.
+    pub fn from_seed_alignment(seed: &[Record], sequences: &[Record]) -> Result<Self> {
+        let Some(width) = seed.first().map(|record| record.seq().len()) else {
+            return Ok(Self::default());
+        };
+        for record in seed {
+            if record.seq().len() != width {
+                return Err(ParsimonyError::InvalidAlignment(format!(
+                    "The seed alignment row of {} has {} columns instead of {}.",
+                    record.id(),
+                    record.seq().len(),
+                    width
+                )));
+            }
+            let Some(sequence) = sequences
+                .iter()
+                .find(|sequence| sequence.id() == record.id())
+            else {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "The seed alignment has a row for {} but there is no such sequence.",
+                    record.id()
+                )));
+            };
+            let residues = |seq: &[u8]| -> Vec<u8> {
+                seq.iter()
+                    .filter(|&&c| c != b'-' && c != b'.')
+                    .map(u8::to_ascii_uppercase)
+                    .collect()
+            };
+            if residues(record.seq()) != residues(sequence.seq()) {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "The seed alignment row of {} does not match its sequence.",
+                    record.id()
+                )));
+            }
+        }
+
+        let mut constraints = Self::default();
+        let mut positions = vec![0; seed.len()];
+        for column in 0..width {
+            let mut aligned = Vec::new();
+            let mut gapped = Vec::new();
+            for (row, record) in seed.iter().enumerate() {
+                match record.seq()[column] {
+                    b'-' => gapped.push(record.id()),
+                    b'.' => {}
+                    c => {
+                        if c.is_ascii_uppercase() {
+                            aligned.push((record.id(), positions[row]));
+                        }
+                        positions[row] += 1;
+                    }
+                }
+            }
+            for (idx, &(first, first_pos)) in aligned.iter().enumerate() {
+                for &(second, second_pos) in &aligned[idx + 1..] {
+                    constraints.pairs.push(ResiduePair {
+                        first: (first.to_string(), first_pos),
+                        second: (second.to_string(), second_pos),
+                    });
+                }
+                for &other in &gapped {
+                    constraints.gaps.push(ResidueGap {
+                        residue: (first.to_string(), first_pos),
+                        other: other.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(constraints)
+    }
+
+    /// Reads the seed alignment from a FASTA file, see `from_seed_alignment`. Not available on
+    /// wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_seed_file(path: &Path, sequences: &[Record]) -> Result<Self> {
+        info!("Reading the seed alignment from {}.", path.display());
+        let seed = fasta::Reader::from_file(path)
+            .map_err(|error| ParsimonyError::InvalidInput(error.to_string()))?
+            .records()
+            .collect::<std::io::Result<Vec<Record>>>()?;
+        Self::from_seed_alignment(&seed, sequences)
+    }
+    /// Reads the constraints from a file with a line per pair: the id of the first sequence, the
+    /// 1-based position of its residue, the id of the second sequence and the position of its
+    /// residue, separated by whitespace. Empty lines and lines starting with # are skipped. Not
+    /// available on wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        info!("Reading the anchor constraints from {}.", path.display());
+        let mut pairs = Vec::new();
+        for (line_no, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || fields[0].starts_with('#') {
+                continue;
+            }
+            let position = |field: &str| field.parse::<usize>().ok().filter(|&pos| pos > 0);
+            match fields[..] {
+                [first, first_pos, second, second_pos] => {
+                    match (position(first_pos), position(second_pos)) {
+                        (Some(first_pos), Some(second_pos)) => pairs.push(ResiduePair {
+                            first: (first.to_string(), first_pos - 1),
+                            second: (second.to_string(), second_pos - 1),
+                        }),
+                        _ => {
+                            return Err(ParsimonyError::InvalidInput(format!(
+                                "Line {} of the constraints file has a residue position that is not a positive number.",
+                                line_no + 1
+                            )))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(ParsimonyError::InvalidInput(format!(
+                        "Line {} of the constraints file does not have the four fields SEQ_A POS_A SEQ_B POS_B.",
+                        line_no + 1
+                    )))
+                }
+            }
+        }
+        Ok(Self::new(pairs))
+    }
+
+    pub fn pairs(&self) -> &[ResiduePair] {
+        &self.pairs
+    }
+
+    pub fn gaps(&self) -> &[ResidueGap] {
+        &self.gaps
+    }
+
+    /// Fails if a constraint names a sequence that does not exist, a residue past the end of its
+    /// sequence or two residues of the same sequence.
+    pub fn check_sequences(&self, sequences: &[Record]) -> Result<()> {
+        let residues = |id: &str| -> Result<usize> {
+            let Some(record) = sequences.iter().find(|record| record.id() == id) else {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "There is an anchor constraint for {} but no such sequence.",
+                    id
+                )));
+            };
+            Ok(record.seq().iter().filter(|&&c| c != b'-').count())
+        };
+        let check_residue = |(id, pos): &(String, usize)| -> Result<()> {
+            let residues = residues(id)?;
+            if *pos >= residues {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "The anchor constraint for residue {} of {} is past the end of the sequence with {} residues.",
+                    pos + 1,
+                    id,
+                    residues
+                )));
+            }
+            Ok(())
+        };
+        for pair in &self.pairs {
+            check_residue(&pair.first)?;
+            check_residue(&pair.second)?;
+            if pair.first.0 == pair.second.0 && pair.first.1 != pair.second.1 {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The anchor constraint between residues {} and {} of {} cannot be met.",
+                    pair.first.1 + 1,
+                    pair.second.1 + 1,
+                    pair.first.0
+                )));
+            }
+        }
+        for gap in &self.gaps {
+            check_residue(&gap.residue)?;
+            residues(&gap.other)?;
+        }
+        Ok(())
+    }
+}
+
+/// Cells of the DP matrices of a node that the constraints fix, see
+/// `ParsimonyAlignmentMatrices::fill_matrices_w_matches`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct NodeConstraints {
+    /// Cells that every alignment has to pass in the match state.
+    pub(crate) forced: Vec<(usize, usize)>,
+    /// Cells that no alignment may pass in the match state, as disjoint column ranges of a row
+    /// sorted by row and first column.
+    pub(crate) forbidden: Vec<(usize, Range<usize>)>,
+}
+
+impl NodeConstraints {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.forced.is_empty() && self.forbidden.is_empty()
+    }
+
+    pub(crate) fn is_forbidden(&self, i: usize, j: usize) -> bool {
+        let next = self
+            .forbidden
+            .partition_point(|(row, columns)| (*row, columns.start) <= (i, j));
+        next > 0 && {
+            let (row, columns) = &self.forbidden[next - 1];
+            *row == i && columns.contains(&j)
+        }
+    }
+}
+
+/// Sorts the forbidden column ranges and merges the overlapping or adjacent ranges of a row.
+fn merge_ranges(mut ranges: Vec<(usize, Range<usize>)>) -> Vec<(usize, Range<usize>)> {
+    ranges.sort_unstable_by_key(|(row, columns)| (*row, columns.start));
+    let mut merged: Vec<(usize, Range<usize>)> = Vec::with_capacity(ranges.len());
+    for (row, columns) in ranges {
+        match merged.last_mut() {
+            Some((last_row, last)) if *last_row == row && columns.start <= last.end => {
+                last.end = last.end.max(columns.end);
+            }
+            _ => merged.push((row, columns)),
+        }
+    }
+    merged
+}
+
+/// Runs of consecutive sites, shifted by one to the columns of the DP matrices.
+fn site_runs(sites: &[usize]) -> Vec<Range<usize>> {
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for &site in sites {
+        match runs.last_mut() {
+            Some(run) if run.end == site + 1 => run.end += 1,
+            _ => runs.push(site + 1..site + 2),
+        }
+    }
+    runs
+}
+
+/// Constraints resolved to the leaves of a tree, with the parents of the nodes to follow the
+/// residues up to the node where they are joined.
+pub(crate) struct TreeConstraints {
+    pairs: Vec<((usize, usize), (usize, usize))>,
+    gaps: Vec<((usize, usize), usize)>,
+    /// Leaves that appear in a constraint.
+    leaves: Vec<usize>,
+    internal_parents: Vec<Option<usize>>,
+    leaf_parents: Vec<Option<usize>>,
+}
+
+impl TreeConstraints {
+    pub(crate) fn new(constraints: &AnchorConstraints, info: &PhyloInfo) -> Result<Self> {
+        constraints.check_sequences(&info.sequences)?;
+        let leaf_ids: HashMap<&str, usize> = info
+            .sequences
+            .iter()
+            .enumerate()
+            .map(|(idx, record)| (record.id(), idx))
+            .collect();
+        let residue = |(id, pos): &(String, usize)| (leaf_ids[id.as_str()], *pos);
+        let pairs: Vec<_> = constraints
+            .pairs
+            .iter()
+            .filter(|pair| pair.first.0 != pair.second.0)
+            .map(|pair| (residue(&pair.first), residue(&pair.second)))
+            .collect();
+        let gaps: Vec<_> = constraints
+            .gaps
+            .iter()
+            .filter(|gap| gap.residue.0 != gap.other)
+            .map(|gap| (residue(&gap.residue), leaf_ids[gap.other.as_str()]))
+            .collect();
+        let mut leaves: Vec<usize> = pairs
+            .iter()
+            .flat_map(|&(first, second)| [first.0, second.0])
+            .chain(gaps.iter().flat_map(|&(residue, other)| [residue.0, other]))
+            .collect();
+        leaves.sort_unstable();
+        leaves.dedup();
+        let mut internal_parents = vec![None; info.tree.internals.len()];
+        let mut leaf_parents = vec![None; info.tree.leaves.len()];
+        for (idx, node) in info.tree.internals.iter().enumerate() {
+            for &child in &node.children {
+                match child {
+                    Int(child) => internal_parents[child] = Some(idx),
+                    Leaf(child) => leaf_parents[child] = Some(idx),
+                }
+            }
+        }
+        Ok(TreeConstraints {
+            pairs,
+            gaps,
+            leaves,
+            internal_parents,
+            leaf_parents,
+        })
+    }
+
+    fn parent(&self, node: NodeIdx) -> Option<usize> {
+        match node {
+            Int(idx) => self.internal_parents[idx],
+            Leaf(idx) => self.leaf_parents[idx],
+        }
+    }
+
+    /// Child of the internal node `node` that `leaf` is below, with the sites of the profile of
+    /// that child that hold the residues of the leaf. The sites are followed up from the leaf
+    /// through the alignments of the nodes in between.
+    fn residue_sites(
+        &self,
+        tree: &Tree,
+        node: usize,
+        leaf: usize,
+        residues: usize,
+        alignment_of: &dyn Fn(usize) -> &Alignment,
+    ) -> Option<(usize, Vec<usize>)> {
+        let mut path = Vec::new();
+        let mut current = Leaf(leaf);
+        loop {
+            let parent = self.parent(current)?;
+            if parent == node {
+                break;
+            }
+            path.push((parent, current == tree.internals[parent].children[0]));
+            current = Int(parent);
+        }
+        let child = tree.internals[node]
+            .children
+            .iter()
+            .position(|&child| child == current)?;
+        let mut sites: Vec<usize> = (0..residues).collect();
+        for (parent, is_x) in path {
+            let alignment = alignment_of(parent);
+            let map = if is_x {
+                &alignment.map_x
+            } else {
+                &alignment.map_y
+            };
+            let mut columns = vec![0; map.len()];
+            for (column, site) in map.iter().enumerate() {
+                if let Some(site) = site {
+                    columns[*site] = column;
+                }
+            }
+            sites.iter_mut().for_each(|site| *site = columns[*site]);
+        }
+        Some((child, sites))
+    }
+
+    /// Cells of the DP matrices of the internal node `node` fixed by the constraints joined at
+    /// it, the cell (i, j) stands for the sites i - 1 of x and j - 1 of y. The forced cells are
+    /// increasing in both coordinates. Fails if the constraints contradict each other or a
+    /// constrained residue ended up in a fixed gap site below the node.
+    pub(crate) fn node_constraints(
+        &self,
+        info: &PhyloInfo,
+        node: usize,
+        x_info: &[ParsimonySiteInfo],
+        y_info: &[ParsimonySiteInfo],
+        alignment_of: &dyn Fn(usize) -> &Alignment,
+    ) -> Result<NodeConstraints> {
+        let below: HashMap<usize, (usize, Vec<usize>)> = self
+            .leaves
+            .iter()
+            .filter_map(|&leaf| {
+                let residues = info.sequences[leaf].seq().len();
+                self.residue_sites(&info.tree, node, leaf, residues, alignment_of)
+                    .map(|sites| (leaf, sites))
+            })
+            .collect();
+        let cell = |(first_child, first_site): (usize, usize), second_site: usize| {
+            if first_child == 0 {
+                (first_site + 1, second_site + 1)
+            } else {
+                (second_site + 1, first_site + 1)
+            }
+        };
+
+        let mut constraints = NodeConstraints::default();
+        for &(first, second) in &self.pairs {
+            let (Some((first_child, first_sites)), Some((second_child, second_sites))) =
+                (below.get(&first.0), below.get(&second.0))
+            else {
+                continue;
+            };
+            if first_child == second_child {
+                continue;
+            }
+            let (i, j) = cell((*first_child, first_sites[first.1]), second_sites[second.1]);
+            if x_info[i - 1].is_fixed() || y_info[j - 1].is_fixed() {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The anchor constraint between residue {} of {} and residue {} of {} cannot be met.",
+                    first.1 + 1,
+                    info.sequences[first.0].id(),
+                    second.1 + 1,
+                    info.sequences[second.0].id()
+                )));
+            }
+            constraints.forced.push((i, j));
+        }
+        constraints.forced.sort_unstable();
+        constraints.forced.dedup();
+        if constraints
+            .forced
+            .windows(2)
+            .any(|pair| pair[0].0 >= pair[1].0 || pair[0].1 >= pair[1].1)
+        {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "The anchor constraints joined at internal node {} cross each other.",
+                node
+            )));
+        }
+
+        for &(residue, other) in &self.gaps {
+            let (Some((residue_child, residue_sites)), Some((other_child, other_sites))) =
+                (below.get(&residue.0), below.get(&other))
+            else {
+                continue;
+            };
+            if residue_child == other_child {
+                continue;
+            }
+            let site = residue_sites[residue.1];
+            if *residue_child == 0 {
+                constraints.forbidden.extend(
+                    site_runs(other_sites)
+                        .into_iter()
+                        .map(|columns| (site + 1, columns)),
+                );
+            } else {
+                constraints.forbidden.extend(
+                    other_sites
+                        .iter()
+                        .map(|&other_site| (other_site + 1, site + 1..site + 2)),
+                );
+            }
+        }
+        constraints.forbidden = merge_ranges(std::mem::take(&mut constraints.forbidden));
+        if constraints
+            .forced
+            .iter()
+            .any(|&(i, j)| constraints.is_forbidden(i, j))
+        {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "The anchor constraints joined at internal node {} contradict each other.",
+                node
+            )));
+        }
+        Ok(constraints)
+    }
+}
+
+#[cfg(test)]
+mod constraints_tests {
+    use super::{
+        merge_ranges, site_runs, AnchorConstraints, NodeConstraints, ResidueGap, ResiduePair,
+    };
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions, TieBreak};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+    use std::fs;
+
+    fn pair(first: &str, first_pos: usize, second: &str, second_pos: usize) -> ResiduePair {
+        ResiduePair {
+            first: (first.to_string(), first_pos),
+            second: (second.to_string(), second_pos),
+        }
+    }
+
+    fn gap(id: &str, pos: usize, other: &str) -> ResidueGap {
+        ResidueGap {
+            residue: (id.to_string(), pos),
+            other: other.to_string(),
+        }
+    }
+
+    /// Three sequences on the tree ((A, B), C).
+    fn phylo_info(sequences: &[Record; 3]) -> PhyloInfo {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        phyloinfo_from_sequences_tree(sequences, tree).unwrap()
+    }
+
+    /// Column of the MSA that holds the residue `pos` of the sequence `id`.
+    fn column(msa: &[Record], id: &str, pos: usize) -> usize {
+        let record = msa.iter().find(|record| record.id() == id).unwrap();
+        record
+            .seq()
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c != b'-')
+            .nth(pos)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn constraints_from_file() {
+        let path = std::env::temp_dir().join("indelMaP_constraints_from_file.txt");
+        fs::write(&path, "# curated anchors\nA 4 B 2\n\nB\t2 C 2\n").unwrap();
+        let constraints = AnchorConstraints::from_file(&path).unwrap();
+        assert_eq!(
+            constraints.pairs(),
+            &[pair("A", 3, "B", 1), pair("B", 1, "C", 1)]
+        );
+        fs::write(&path, "A 4 B\n").unwrap();
+        assert!(AnchorConstraints::from_file(&path).is_err());
+        fs::write(&path, "A 0 B 2\n").unwrap();
+        assert!(AnchorConstraints::from_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"AGT"),
+        ];
+        assert!(constraints.check_sequences(&sequences).is_ok());
+        assert!(constraints.check_sequences(&sequences[..2]).is_err());
+        assert!(AnchorConstraints::new(vec![pair("B", 2, "C", 0)])
+            .check_sequences(&sequences)
+            .is_err());
+        assert!(AnchorConstraints::new(vec![pair("A", 0, "A", 1)])
+            .check_sequences(&sequences)
+            .is_err());
+    }
+
+    #[test]
+    fn constrained_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"AGT"),
+        ];
+        let info = phylo_info(&sequences);
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+        let options = AlignmentOptions {
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+
+        let msa = pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .msa(&info);
+        assert_eq!(column(&msa, "B", 1), column(&msa, "A", 1));
+
+        // the C of B under the T of A, and the G of C in the same column, which is only known
+        // through the alignment of A and B
+        let options = AlignmentOptions {
+            constraints: Some(AnchorConstraints::new(vec![
+                pair("A", 3, "B", 1),
+                pair("C", 1, "B", 1),
+            ])),
+            ..options
+        };
+        let msa = pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .msa(&info);
+        assert_eq!(column(&msa, "B", 1), column(&msa, "A", 3));
+        assert_eq!(column(&msa, "C", 1), column(&msa, "A", 3));
+
+        let options = AlignmentOptions {
+            constraints: Some(AnchorConstraints::new(vec![
+                pair("A", 0, "B", 1),
+                pair("A", 3, "B", 0),
+            ])),
+            ..options
+        };
+        assert!(pars_align_on_tree_w_options(&scoring, &info, &options).is_err());
+    }
+
+    #[test]
+    fn seed_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGTT"),
+            Record::with_attrs("B", None, b"AGT"),
+            Record::with_attrs("C", None, b"ACT"),
+        ];
+        let seed = [
+            Record::with_attrs("A", None, b"ACGTt"),
+            Record::with_attrs("B", None, b"A-GT."),
+        ];
+        let constraints = AnchorConstraints::from_seed_alignment(&seed, &sequences).unwrap();
+        assert_eq!(
+            constraints.pairs(),
+            &[
+                pair("A", 0, "B", 0),
+                pair("A", 2, "B", 1),
+                pair("A", 3, "B", 2)
+            ]
+        );
+        assert_eq!(constraints.gaps(), &[gap("A", 1, "B")]);
+        assert!(constraints.check_sequences(&sequences).is_ok());
+
+        let seed = [
+            Record::with_attrs("A", None, b"ACGTT"),
+            Record::with_attrs("B", None, b"A-GT"),
+        ];
+        assert!(AnchorConstraints::from_seed_alignment(&seed, &sequences).is_err());
+        let seed = [Record::with_attrs("D", None, b"ACGTT")];
+        assert!(AnchorConstraints::from_seed_alignment(&seed, &sequences).is_err());
+        let seed = [Record::with_attrs("B", None, b"A-CT")];
+        assert!(AnchorConstraints::from_seed_alignment(&seed, &sequences).is_err());
+        assert_eq!(
+            AnchorConstraints::from_seed_alignment(&[], &sequences).unwrap(),
+            AnchorConstraints::default()
+        );
+    }
+
+    #[test]
+    fn seed_constrained_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"AT"),
+            Record::with_attrs("C", None, b"AGT"),
+        ];
+        let info = phylo_info(&sequences);
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+        let options = AlignmentOptions {
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+        let msa = pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .msa(&info);
+        assert_eq!(column(&msa, "B", 0), column(&msa, "A", 0));
+
+        // the lowercase a of B is placed freely, but not under the A or the G of A
+        let seed = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"-a-T"),
+        ];
+        let options = AlignmentOptions {
+            constraints: Some(AnchorConstraints::from_seed_alignment(&seed, &sequences).unwrap()),
+            ..options
+        };
+        let msa = pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .msa(&info);
+        assert_ne!(column(&msa, "B", 0), column(&msa, "A", 0));
+        assert_ne!(column(&msa, "B", 0), column(&msa, "A", 2));
+        assert_eq!(column(&msa, "B", 1), column(&msa, "A", 3));
+    }
+
+    #[test]
+    fn forbidden_ranges() {
+        assert_eq!(site_runs(&[0, 1, 2, 5, 7, 8]), vec![1..4, 6..7, 8..10]);
+        let constraints = NodeConstraints {
+            forced: Vec::new(),
+            forbidden: merge_ranges(vec![(2, 4..6), (1, 3..4), (2, 1..3), (2, 5..8), (1, 3..4)]),
+        };
+        assert_eq!(constraints.forbidden, vec![(1, 3..4), (2, 1..3), (2, 4..8)]);
+        assert!(constraints.is_forbidden(1, 3));
+        assert!(!constraints.is_forbidden(1, 4));
+        assert!(!constraints.is_forbidden(2, 0));
+        assert!(constraints.is_forbidden(2, 2));
+        assert!(!constraints.is_forbidden(2, 3));
+        assert!(constraints.is_forbidden(2, 7));
+        assert!(!constraints.is_forbidden(2, 8));
+        assert!(!constraints.is_forbidden(3, 4));
+    }
+}
@@ -0,0 +1,166 @@
+use bio::io::fasta::Record;
+
+use super::msa_scoring::GAP;
+
+/// Shifts the gaps of the MSA as far left as possible without changing the alignment, so that
+/// equivalent placements of a gap come out the same. A gap moves left by one column when the
+/// residues before it can take the last column of the gap instead, together in all the rows
+/// with the gap. The other rows must read the same in both columns, so the MSA keeps the same
+/// columns. With `homopolymers` a gap inside a run of the same residue of its row, e.g. AA-A, is
+/// also moved to the left end of the run, which places the indels of homopolymer errors
+/// canonically but can change the columns. Columns left with gaps only are removed.
+pub fn normalize_gaps(msa: &[Record], homopolymers: bool) -> Vec<Record> {
+    let mut rows: Vec<Vec<u8>> = msa.iter().map(|record| record.seq().to_vec()).collect();
+    let mut shifted = true;
+    while shifted {
+        shifted = false;
+        for row in 0..rows.len() {
+            shifted |= shift_row_gaps(&mut rows, row, homopolymers);
+        }
+    }
+    let columns = rows.first().map_or(0, Vec::len);
+    let kept: Vec<usize> = (0..columns)
+        .filter(|&column| rows.iter().any(|row| row[column] != GAP))
+        .collect();
+    msa.iter()
+        .zip(rows)
+        .map(|(record, row)| {
+            let row: Vec<u8> = kept.iter().map(|&column| row[column]).collect();
+            Record::with_attrs(record.id(), record.desc(), &row)
+        })
+        .collect()
+}
+
+/// Shifts the gaps of one row left while they can move, returns whether any did.
+fn shift_row_gaps(rows: &mut [Vec<u8>], row: usize, homopolymers: bool) -> bool {
+    let mut shifted = false;
+    let mut start = 0;
+    while start < rows[row].len() {
+        if rows[row][start] != GAP {
+            start += 1;
+            continue;
+        }
+        let end = start + rows[row][start..].iter().take_while(|&&c| c == GAP).count();
+        let moved = if start == 0 {
+            Vec::new()
+        } else {
+            shifting_rows(rows, row, start, end, homopolymers)
+        };
+        if moved.is_empty() {
+            start = end;
+            continue;
+        }
+        for &moved in &moved {
+            rows[moved].swap(start - 1, end - 1);
+        }
+        shifted = true;
+        // the shifted gap can be joined by the gap before it, so it is looked at again
+        start = rows[row][..start - 1]
+            .iter()
+            .rposition(|&c| c != GAP)
+            .map_or(0, |residue| residue + 1);
+    }
+    shifted
+}
+
+/// Rows whose residue before the gap `start..end` of `row` moves to the last column of the gap.
+/// These are all the rows with the same residue and gap columns, the MSA keeps its columns if
+/// the other rows read the same in the two columns. Otherwise only `row` is shifted if the gap
+/// is inside a homopolymer and `homopolymers` is set, and no row if not.
+fn shifting_rows(
+    rows: &[Vec<u8>],
+    row: usize,
+    start: usize,
+    end: usize,
+    homopolymers: bool,
+) -> Vec<usize> {
+    let (sharing, others): (Vec<usize>, Vec<usize>) = (0..rows.len()).partition(|&other| {
+        rows[other][start - 1] != GAP && rows[other][start..end].iter().all(|&c| c == GAP)
+    });
+    if others
+        .iter()
+        .all(|&other| rows[other][start - 1] == rows[other][end - 1])
+    {
+        return sharing;
+    }
+    let residue = rows[row][start - 1];
+    if homopolymers
+        && rows[row]
+            .get(end)
+            .is_some_and(|next| next.eq_ignore_ascii_case(&residue))
+    {
+        return vec![row];
+    }
+    Vec::new()
+}
+
+#[cfg(test)]
+mod gap_normalization_tests {
+    use super::normalize_gaps;
+    use bio::io::fasta::Record;
+
+    fn rows(msa: &[Record]) -> Vec<&[u8]> {
+        msa.iter().map(|record| record.seq()).collect()
+    }
+
+    #[test]
+    fn left_shift_equivalent_gaps() {
+        let msa = [
+            Record::with_attrs("A", None, b"ACCCT"),
+            Record::with_attrs("B", None, b"ACC-T"),
+            Record::with_attrs("C", None, b"ACC-T"),
+        ];
+        let normalized = normalize_gaps(&msa, false);
+        assert_eq!(rows(&normalized), vec![&b"ACCCT"[..], b"A-CCT", b"A-CCT"]);
+        assert_eq!(normalized[1].id(), "B");
+        assert_eq!(rows(&normalize_gaps(&normalized, false)), rows(&normalized));
+
+        // C can only move with a gap of B in the same columns
+        let msa = [
+            Record::with_attrs("A", None, b"ACCCT"),
+            Record::with_attrs("B", None, b"ACC-T"),
+            Record::with_attrs("C", None, b"AC-CT"),
+        ];
+        assert_eq!(
+            rows(&normalize_gaps(&msa, false)),
+            vec![&b"ACCCT"[..], b"AC-CT", b"A-CCT"]
+        );
+
+        // the residues of the other rows differ, so the gap stays
+        let msa = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"AC-T"),
+        ];
+        assert_eq!(rows(&normalize_gaps(&msa, false)), rows(&msa));
+    }
+
+    #[test]
+    fn shift_to_homopolymer_boundary() {
+        let msa = [
+            Record::with_attrs("A", None, b"GAAGAT"),
+            Record::with_attrs("B", None, b"GAA-AT"),
+        ];
+        assert_eq!(rows(&normalize_gaps(&msa, false)), rows(&msa));
+        assert_eq!(
+            rows(&normalize_gaps(&msa, true)),
+            vec![&b"GAAGAT"[..], b"G-AAAT"]
+        );
+    }
+
+    #[test]
+    fn remove_emptied_columns() {
+        let msa = [
+            Record::with_attrs("A", None, b"AA--C"),
+            Record::with_attrs("B", None, b"--AAC"),
+        ];
+        assert_eq!(
+            rows(&normalize_gaps(&msa, true)),
+            vec![&b"AA--C"[..], b"--AAC"]
+        );
+        let msa = [
+            Record::with_attrs("A", None, b"A-AC"),
+            Record::with_attrs("B", None, b"-GTC"),
+        ];
+        assert_eq!(rows(&normalize_gaps(&msa, true)), vec![&b"AAC"[..], b"GTC"]);
+    }
+}
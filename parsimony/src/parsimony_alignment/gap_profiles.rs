@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+#[cfg(not(target_arch = "wasm32"))]
+use bio::io::fasta;
+use bio::io::fasta::Record;
+use log::info;
+
+use crate::{ParsimonyError, Result};
+
+/// Window of residues over which the hydropathy is averaged.
+pub const HYDROPATHY_WINDOW: usize = 7;
+
+/// Window of residues over which the Chou-Fasman propensities are averaged.
+pub const STRUCTURE_WINDOW: usize = 7;
+
+/// Per-site multipliers of the gap penalties of the input sequences. Opening or extending a gap
+/// against a site costs its multiplier times the usual gap cost, so multipliers above one
+/// discourage gaps, e.g. inside conserved cores. The sites of an aligned profile keep the
+/// largest multiplier of the sites in their column.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GapProfiles {
+    weights: HashMap<String, Vec<f64>>,
+}
+
+impl GapProfiles {
+    pub fn new(weights: HashMap<String, Vec<f64>>) -> Result<Self> {
+        for (id, profile) in &weights {
+            if let Some(weight) = profile
+                .iter()
+                .find(|&&weight| weight < 0.0 || !weight.is_finite())
+            {
+                return Err(ParsimonyError::InvalidParameters(format!(
+                    "The gap penalty multipliers of {} must be non-negative, got {}.",
+                    id, weight
+                )));
+            }
+        }
+        Ok(GapProfiles { weights })
+    }
+
+    /// Reads the multipliers from a file with a line per sequence, the sequence id followed by
+    /// a multiplier for every residue, all separated by whitespace. Empty lines and lines
+    /// starting with # are skipped. Not available on wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        info!("Reading the gap penalty profiles from {}.", path.display());
+        let mut weights = HashMap::new();
+        for (line_no, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let mut fields = line.split_whitespace();
+            let Some(id) = fields.next().filter(|id| !id.starts_with('#')) else {
+                continue;
+            };
+            let profile = fields
+                .map(|field| field.parse::<f64>())
+                .collect::<std::result::Result<Vec<f64>, _>>();
+            let Ok(profile) = profile else {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "Line {} of the gap profile file has a multiplier that is not a number.",
+                    line_no + 1
+                )));
+            };
+            if weights.insert(id.to_string(), profile).is_some() {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The gap profile file has more than one line for {}.",
+                    id
+                )));
+            }
+        }
+        Self::new(weights)
+    }
+
+    /// Multipliers from the Kyte-Doolittle hydropathy of protein sequences averaged over a
+    /// window of `HYDROPATHY_WINDOW` residues. The hydropathy h ranges from -4.5 to 4.5 and gives
+    /// the multiplier 1 + h / 9, so gaps are cheaper in hydrophilic stretches, which tend to be
+    /// exposed loops, and dearer in the hydrophobic core.
+    pub fn from_hydropathy(sequences: &[Record]) -> Self {
+        let weights = sequences
+            .iter()
+            .map(|record| {
+                let hydropathy: Vec<f64> =
+                    record.seq().iter().map(|&c| kyte_doolittle(c)).collect();
+                let profile = (0..hydropathy.len())
+                    .map(|site| {
+                        let start = site.saturating_sub(HYDROPATHY_WINDOW / 2);
+                        let end = (site + HYDROPATHY_WINDOW / 2 + 1).min(hydropathy.len());
+                        let window = &hydropathy[start..end];
+                        1.0 + window.iter().sum::<f64>() / window.len() as f64 / 9.0
+                    })
+                    .collect();
+                (record.id().to_string(), profile)
+            })
+            .collect();
+        GapProfiles { weights }
+    }
+
+    /// Multipliers from secondary structure strings with the ids of the sequences they belong
+    /// to: sites in helices (H, G or I) get `helix`, sites in strands (E or B) get `strand` and
+    /// all other sites keep the usual costs. Gaps are thus pushed out of the structured
+    /// elements into the loops between them.
+    pub fn from_secondary_structure(structures: &[Record], helix: f64, strand: f64) -> Self {
+        let weights = structures
+            .iter()
+            .map(|record| {
+                let profile = record
+                    .seq()
+                    .iter()
+                    .map(|&state| match state.to_ascii_uppercase() {
+                        b'H' | b'G' | b'I' => helix,
+                        b'E' | b'B' => strand,
+                        _ => 1.0,
+                    })
+                    .collect();
+                (record.id().to_string(), profile)
+            })
+            .collect();
+        GapProfiles { weights }
+    }
+
+    /// Reads the secondary structure strings from a FASTA file, see `from_secondary_structure`.
+    /// Not available on wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_secondary_structure_file(path: &Path, helix: f64, strand: f64) -> Result<Self> {
+        info!("Reading the secondary structures from {}.", path.display());
+        let structures = fasta::Reader::from_file(path)
+            .map_err(|error| ParsimonyError::InvalidInput(error.to_string()))?
+            .records()
+            .collect::<std::io::Result<Vec<Record>>>()?;
+        Self::new(Self::from_secondary_structure(&structures, helix, strand).weights)
+    }
+
+    /// Multipliers from the secondary structures predicted by `predict_secondary_structure`, for
+    /// protein sequences without a known structure.
+    pub fn from_predicted_structure(sequences: &[Record], helix: f64, strand: f64) -> Self {
+        let structures: Vec<Record> = sequences
+            .iter()
+            .map(|record| {
+                Record::with_attrs(
+                    record.id(),
+                    None,
+                    &predict_secondary_structure(record.seq()),
+                )
+            })
+            .collect();
+        Self::from_secondary_structure(&structures, helix, strand)
+    }
+
+    /// Fails if a profile has no sequence or does not have a multiplier for every residue of
+    /// its sequence. Sequences without a profile keep the usual gap costs.
+    pub fn check_sequences(&self, sequences: &[Record]) -> Result<()> {
+        for (id, profile) in &self.weights {
+            let Some(record) = sequences.iter().find(|record| record.id() == id) else {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "There is a gap profile for {} but no such sequence.",
+                    id
+                )));
+            };
+            if profile.len() != record.seq().len() {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "The gap profile of {} has {} multipliers but the sequence has {} residues.",
+                    id,
+                    profile.len(),
+                    record.seq().len()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn weights(&self, id: &str) -> Option<&[f64]> {
+        self.weights.get(id).map(|profile| &profile[..])
+    }
+}
+
+/// Simplified Chou-Fasman prediction of the secondary structure of a protein sequence, H for
+/// helix, E for strand and C for coil. The helix and strand propensities are averaged over a
+/// window of `STRUCTURE_WINDOW` residues, a site is in a helix if the helix average is at least
+/// 1.03 and not below the strand average, otherwise it is in a strand if the strand average is
+/// at least 1.05. Much less accurate than dedicated predictors, but enough to keep gaps out of
+/// the clearest structured stretches.
+pub fn predict_secondary_structure(sequence: &[u8]) -> Vec<u8> {
+    let propensities: Vec<(f64, f64)> = sequence.iter().map(|&c| chou_fasman(c)).collect();
+    (0..propensities.len())
+        .map(|site| {
+            let start = site.saturating_sub(STRUCTURE_WINDOW / 2);
+            let end = (site + STRUCTURE_WINDOW / 2 + 1).min(propensities.len());
+            let window = &propensities[start..end];
+            let helix = window.iter().map(|p| p.0).sum::<f64>() / window.len() as f64;
+            let strand = window.iter().map(|p| p.1).sum::<f64>() / window.len() as f64;
+            if helix >= 1.03 && helix >= strand {
+                b'H'
+            } else if strand >= 1.05 {
+                b'E'
+            } else {
+                b'C'
+            }
+        })
+        .collect()
+}
+
+/// Helix and strand propensities of Chou and Fasman (1978).
+fn chou_fasman(residue: u8) -> (f64, f64) {
+    match residue.to_ascii_uppercase() {
+        b'A' => (1.42, 0.83),
+        b'R' => (0.98, 0.93),
+        b'N' => (0.67, 0.89),
+        b'D' => (1.01, 0.54),
+        b'C' => (0.70, 1.19),
+        b'Q' => (1.11, 1.10),
+        b'E' => (1.51, 0.37),
+        b'G' => (0.57, 0.75),
+        b'H' => (1.00, 0.87),
+        b'I' => (1.08, 1.60),
+        b'L' => (1.21, 1.30),
+        b'K' => (1.14, 0.74),
+        b'M' => (1.45, 1.05),
+        b'F' => (1.13, 1.38),
+        b'P' => (0.57, 0.55),
+        b'S' => (0.77, 0.75),
+        b'T' => (0.83, 1.19),
+        b'W' => (1.08, 1.37),
+        b'Y' => (0.69, 1.47),
+        b'V' => (1.06, 1.70),
+        _ => (1.0, 1.0),
+    }
+}
+
+fn kyte_doolittle(residue: u8) -> f64 {
+    match residue.to_ascii_uppercase() {
+        b'A' => 1.8,
+        b'R' => -4.5,
+        b'N' => -3.5,
+        b'D' => -3.5,
+        b'C' => 2.5,
+        b'Q' => -3.5,
+        b'E' => -3.5,
+        b'G' => -0.4,
+        b'H' => -3.2,
+        b'I' => 4.5,
+        b'L' => 3.8,
+        b'K' => -3.9,
+        b'M' => 1.9,
+        b'F' => 2.8,
+        b'P' => -1.6,
+        b'S' => -0.8,
+        b'T' => -0.7,
+        b'W' => -0.9,
+        b'Y' => -1.3,
+        b'V' => 4.2,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod gap_profiles_tests {
+    use super::{predict_secondary_structure, GapProfiles};
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn profiles_from_file() {
+        let path = std::env::temp_dir().join("indelMaP_profiles_from_file.txt");
+        fs::write(&path, "# gap penalty multipliers\nA 1 2 0.5\n\nB\t1.5 1\n").unwrap();
+        let profiles = GapProfiles::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(profiles.weights("A"), Some(&[1.0, 2.0, 0.5][..]));
+        assert_eq!(profiles.weights("B"), Some(&[1.5, 1.0][..]));
+        assert_eq!(profiles.weights("C"), None);
+        let sequences = [
+            Record::with_attrs("A", None, b"ACG"),
+            Record::with_attrs("B", None, b"AC"),
+        ];
+        assert!(profiles.check_sequences(&sequences).is_ok());
+        assert!(profiles.check_sequences(&sequences[..1]).is_err());
+        let sequences = [
+            Record::with_attrs("A", None, b"ACG"),
+            Record::with_attrs("B", None, b"ACG"),
+        ];
+        assert!(profiles.check_sequences(&sequences).is_err());
+        assert!(GapProfiles::new(HashMap::from([("A".to_string(), vec![-1.0])])).is_err());
+    }
+
+    #[test]
+    fn hydropathy_profiles() {
+        let sequences = [Record::with_attrs("A", None, b"IIIIIIIIIIKKKKKKKKKK")];
+        let profiles = GapProfiles::from_hydropathy(&sequences);
+        let weights = profiles.weights("A").unwrap();
+        assert_eq!(weights.len(), 20);
+        assert_relative_eq!(weights[0], 1.5);
+        assert_relative_eq!(weights[19], 1.0 - 3.9 / 9.0);
+        assert!(weights.windows(2).all(|pair| pair[0] >= pair[1]));
+    }
+
+    #[test]
+    fn secondary_structure_profiles() {
+        let structures = [Record::with_attrs("A", None, b"CHHhGEEB-C")];
+        let profiles = GapProfiles::from_secondary_structure(&structures, 3.0, 2.0);
+        assert_eq!(
+            profiles.weights("A"),
+            Some(&[1.0, 3.0, 3.0, 3.0, 3.0, 2.0, 2.0, 2.0, 1.0, 1.0][..])
+        );
+
+        let path = std::env::temp_dir().join("indelMaP_secondary_structure.fasta");
+        fs::write(&path, ">A\nCHHE\n>B\nEEC\n").unwrap();
+        let profiles = GapProfiles::from_secondary_structure_file(&path, 3.0, 2.0).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(profiles.weights("A"), Some(&[1.0, 3.0, 3.0, 2.0][..]));
+        assert_eq!(profiles.weights("B"), Some(&[2.0, 2.0, 1.0][..]));
+        assert!(GapProfiles::from_secondary_structure_file(&path, 3.0, 2.0).is_err());
+    }
+
+    #[test]
+    fn predicted_structure() {
+        assert_eq!(
+            predict_secondary_structure(b"EEEEAAAAMMMM"),
+            b"HHHHHHHHHHHH"
+        );
+        assert_eq!(
+            predict_secondary_structure(b"VVVVIIIIYYYY"),
+            b"EEEEEEEEEEEE"
+        );
+        assert_eq!(
+            predict_secondary_structure(b"GGGGPPPPNNNN"),
+            b"CCCCCCCCCCCC"
+        );
+        let sequences = [Record::with_attrs("A", None, b"EEEEAAAAGGGGPPPP")];
+        let profiles = GapProfiles::from_predicted_structure(&sequences, 3.0, 2.0);
+        let weights = profiles.weights("A").unwrap();
+        assert_eq!(weights[0], 3.0);
+        assert_eq!(weights[15], 1.0);
+    }
+}
@@ -0,0 +1,473 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::sequences::{get_sequence_type, SequenceType};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+use std::collections::HashMap;
+
+use crate::{ParsimonyError, Result};
+
+/// Unrooted tree from neighbour joining, the first nodes are the leaves in sequence order and
+/// every node keeps its neighbours with the lengths of the connecting branches.
+pub(crate) type Adjacency = Vec<Vec<(usize, f64)>>;
+
+fn kmer_counts(sequence: &[u8], k: usize) -> HashMap<&[u8], usize> {
+    let mut counts = HashMap::new();
+    for kmer in sequence.windows(k) {
+        *counts.entry(kmer).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Fraction of the k-mers of the shorter sequence that are not shared with the other one.
+fn kmer_distances(sequences: &[Record]) -> Vec<Vec<f64>> {
+    let k = match get_sequence_type(sequences) {
+        SequenceType::DNA => 4,
+        SequenceType::Protein => 2,
+    };
+    let residues: Vec<Vec<u8>> = sequences
+        .iter()
+        .map(|record| {
+            record
+                .seq()
+                .iter()
+                .filter(|&&c| c != b'-')
+                .map(|c| c.to_ascii_uppercase())
+                .collect()
+        })
+        .collect();
+    let counts: Vec<_> = residues.iter().map(|seq| kmer_counts(seq, k)).collect();
+    let mut distances = vec![vec![0.0; sequences.len()]; sequences.len()];
+    for a in 0..sequences.len() {
+        for b in (a + 1)..sequences.len() {
+            let kmers = residues[a]
+                .len()
+                .min(residues[b].len())
+                .saturating_sub(k - 1);
+            let distance = if kmers == 0 {
+                1.0
+            } else {
+                let shared: usize = counts[a]
+                    .iter()
+                    .filter_map(|(kmer, count)| Some(*count.min(counts[b].get(kmer)?)))
+                    .sum();
+                1.0 - shared as f64 / kmers as f64
+            };
+            distances[a][b] = distance;
+            distances[b][a] = distance;
+        }
+    }
+    distances
+}
+
+/// Fraction of the columns with residues in both rows where the residues differ, the columns
+/// with a gap in either row are left out. Rows without shared columns are at distance 1.
+fn msa_distances(msa: &[Record]) -> Vec<Vec<f64>> {
+    let mut distances = vec![vec![0.0; msa.len()]; msa.len()];
+    for a in 0..msa.len() {
+        for b in (a + 1)..msa.len() {
+            let (compared, differing) = msa[a]
+                .seq()
+                .iter()
+                .zip(msa[b].seq())
+                .filter(|(&x, &y)| x != b'-' && y != b'-')
+                .fold((0, 0), |(compared, differing), (x, y)| {
+                    let differs = !x.eq_ignore_ascii_case(y);
+                    (compared + 1, differing + differs as usize)
+                });
+            let distance = if compared == 0 {
+                1.0
+            } else {
+                differing as f64 / compared as f64
+            };
+            distances[a][b] = distance;
+            distances[b][a] = distance;
+        }
+    }
+    distances
+}
+
+fn neighbour_joining(mut distances: Vec<Vec<f64>>) -> Adjacency {
+    let mut adjacency: Adjacency = vec![Vec::new(); distances.len()];
+    let mut active: Vec<usize> = (0..distances.len()).collect();
+    while active.len() > 2 {
+        let r = active.len();
+        let totals: Vec<f64> = distances.iter().map(|row| row.iter().sum()).collect();
+        let (mut best, mut best_q) = ((0, 1), f64::INFINITY);
+        for a in 0..r {
+            for b in (a + 1)..r {
+                let q = (r - 2) as f64 * distances[a][b] - totals[a] - totals[b];
+                if q < best_q {
+                    (best, best_q) = ((a, b), q);
+                }
+            }
+        }
+        let (a, b) = best;
+        let a_len = (distances[a][b] / 2.0 + (totals[a] - totals[b]) / (2.0 * (r - 2) as f64))
+            .clamp(0.0, distances[a][b]);
+        let b_len = distances[a][b] - a_len;
+        let node = adjacency.len();
+        adjacency.push(vec![(active[a], a_len), (active[b], b_len)]);
+        adjacency[active[a]].push((node, a_len));
+        adjacency[active[b]].push((node, b_len));
+        // the joined node takes the place of a, b is dropped
+        for c in 0..r {
+            if c != a && c != b {
+                let distance =
+                    ((distances[a][c] + distances[b][c] - distances[a][b]) / 2.0).max(0.0);
+                distances[a][c] = distance;
+                distances[c][a] = distance;
+            }
+        }
+        distances[a][a] = 0.0;
+        active[a] = node;
+        active.remove(b);
+        distances.remove(b);
+        for row in distances.iter_mut() {
+            row.remove(b);
+        }
+    }
+    let (a, b) = (active[0], active[1]);
+    adjacency[a].push((b, distances[0][1]));
+    adjacency[b].push((a, distances[0][1]));
+    adjacency
+}
+
+/// Distances from `start` to every node and the previous node on the path to it.
+fn paths_from(adjacency: &Adjacency, start: usize) -> (Vec<f64>, Vec<usize>) {
+    let mut distance = vec![f64::INFINITY; adjacency.len()];
+    let mut previous = vec![start; adjacency.len()];
+    let mut stack = vec![start];
+    distance[start] = 0.0;
+    while let Some(node) = stack.pop() {
+        for &(next, blen) in &adjacency[node] {
+            if distance[next].is_infinite() {
+                distance[next] = distance[node] + blen;
+                previous[next] = node;
+                stack.push(next);
+            }
+        }
+    }
+    (distance, previous)
+}
+
+/// Branch holding the midpoint of the longest leaf to leaf path, with the distance of the
+/// midpoint from each of its ends.
+pub(crate) fn midpoint_branch(
+    adjacency: &Adjacency,
+    leaves: usize,
+) -> ((usize, f64), (usize, f64)) {
+    let (mut from, mut to, mut longest) = (0, 1, -1.0);
+    for leaf in 0..leaves {
+        let (distance, _) = paths_from(adjacency, leaf);
+        for other in (leaf + 1)..leaves {
+            if distance[other] > longest {
+                (from, to, longest) = (leaf, other, distance[other]);
+            }
+        }
+    }
+    let (distance, previous) = paths_from(adjacency, from);
+    let half = longest / 2.0;
+    let mut node = to;
+    while distance[previous[node]] > half {
+        node = previous[node];
+    }
+    let parent = previous[node];
+    (
+        (parent, half - distance[parent]),
+        (node, distance[node] - half),
+    )
+}
+
+pub(crate) fn add_subtree(
+    tree: &mut Tree,
+    adjacency: &Adjacency,
+    leaves: usize,
+    node: usize,
+    parent: usize,
+    next_internal: &mut usize,
+) -> NodeIdx {
+    if node < leaves {
+        return Leaf(node);
+    }
+    let children: Vec<(NodeIdx, f64)> = adjacency[node]
+        .iter()
+        .filter(|(child, _)| *child != parent)
+        .map(|&(child, blen)| {
+            (
+                add_subtree(tree, adjacency, leaves, child, node, next_internal),
+                blen,
+            )
+        })
+        .collect();
+    let idx = *next_internal;
+    *next_internal += 1;
+    tree.add_parent(
+        idx,
+        children[0].0,
+        children[1].0,
+        children[0].1,
+        children[1].1,
+    );
+    Int(idx)
+}
+
+/// Estimates a guide tree for unaligned sequences by neighbour joining on k-mer distances,
+/// rooted at the midpoint of the longest leaf to leaf path.
+pub fn estimate_guide_tree(sequences: &[Record]) -> Result<Tree> {
+    if sequences.len() < 2 {
+        return Err(ParsimonyError::TooFewSequences(
+            "At least two sequences are needed to estimate a guide tree.".to_string(),
+        ));
+    }
+    info!(
+        "Estimating a guide tree for {} sequences by neighbour joining on k-mer distances.",
+        sequences.len()
+    );
+    let adjacency = neighbour_joining(kmer_distances(sequences));
+    let root_branch = midpoint_branch(&adjacency, sequences.len());
+    rooted_tree(sequences, &adjacency, root_branch)
+}
+
+/// Re-estimates the tree from an MSA by neighbour joining on the p-distances of the rows,
+/// rooted at the midpoint of the longest leaf to leaf path. The leaves are in the order of the
+/// rows, which must all have the same length.
+pub fn estimate_tree_from_msa(msa: &[Record]) -> Result<Tree> {
+    if msa.len() < 2 {
+        return Err(ParsimonyError::TooFewSequences(
+            "At least two sequences are needed to estimate a tree.".to_string(),
+        ));
+    }
+    let columns = msa[0].seq().len();
+    if let Some(record) = msa.iter().find(|record| record.seq().len() != columns) {
+        return Err(ParsimonyError::InvalidAlignment(format!(
+            "Sequence {} has length {} instead of {}, the sequences are not aligned.",
+            record.id(),
+            record.seq().len(),
+            columns
+        )));
+    }
+    info!(
+        "Estimating a tree for {} aligned sequences by neighbour joining on p-distances.",
+        msa.len()
+    );
+    let adjacency = neighbour_joining(msa_distances(msa));
+    let root_branch = midpoint_branch(&adjacency, msa.len());
+    rooted_tree(msa, &adjacency, root_branch)
+}
+
+/// Builds the tree rooted on the given branch of an unrooted tree, the root splits the branch
+/// into the two given lengths. The first nodes of the unrooted tree are the leaves in sequence
+/// order and all the others must have three neighbours.
+pub(crate) fn rooted_tree(
+    sequences: &[Record],
+    adjacency: &Adjacency,
+    ((left, left_len), (right, right_len)): ((usize, f64), (usize, f64)),
+) -> Result<Tree> {
+    let leaves = sequences.len();
+    let mut tree = Tree::new(sequences)?;
+    let mut next_internal = 0;
+    let left_idx = add_subtree(
+        &mut tree,
+        adjacency,
+        leaves,
+        left,
+        right,
+        &mut next_internal,
+    );
+    let right_idx = add_subtree(
+        &mut tree,
+        adjacency,
+        leaves,
+        right,
+        left,
+        &mut next_internal,
+    );
+    tree.add_parent(next_internal, left_idx, right_idx, left_len, right_len);
+    tree.complete = true;
+    tree.create_postorder();
+    Ok(tree)
+}
+
+/// Newick representation of the tree with the leaves named by the ids of the sequences.
+pub fn tree_to_newick(tree: &Tree, sequences: &[Record]) -> String {
+    tree_to_newick_w_support(tree, sequences, &vec![None; tree.internals.len()])
+}
+
+/// Newick representation of the tree with the support of every internal node, a fraction
+/// between 0 and 1, written as a percentage in the label of the node.
+pub fn tree_to_newick_w_support(
+    tree: &Tree,
+    sequences: &[Record],
+    support: &[Option<f64>],
+) -> String {
+    let labels: Vec<String> = support
+        .iter()
+        .map(|support| {
+            support
+                .map(|support| format!("{}", (support * 100.0).round()))
+                .unwrap_or_default()
+        })
+        .collect();
+    newick_w_labels(tree, sequences, &labels, false)
+}
+
+/// Label of the internal node with index `idx` in the ancestral outputs, N1 for the first one.
+pub fn internal_node_label(idx: usize) -> String {
+    format!("N{}", idx + 1)
+}
+
+/// Newick representation of the tree with every internal node, the root included, labeled as
+/// in the ancestral outputs, see `internal_node_label`.
+pub fn tree_to_labeled_newick(tree: &Tree, sequences: &[Record]) -> String {
+    let labels: Vec<String> = (0..tree.internals.len()).map(internal_node_label).collect();
+    newick_w_labels(tree, sequences, &labels, true)
+}
+
+fn newick_w_labels(
+    tree: &Tree,
+    sequences: &[Record],
+    labels: &[String],
+    label_root: bool,
+) -> String {
+    fn subtree(tree: &Tree, sequences: &[Record], labels: &[String], node: NodeIdx) -> String {
+        match node {
+            Leaf(idx) => format!("{}:{}", sequences[idx].id(), tree.leaves[idx].blen),
+            Int(idx) => {
+                let children: Vec<String> = tree.internals[idx]
+                    .children
+                    .iter()
+                    .map(|&child| subtree(tree, sequences, labels, child))
+                    .collect();
+                format!(
+                    "({}){}:{}",
+                    children.join(","),
+                    labels[idx],
+                    tree.internals[idx].blen
+                )
+            }
+        }
+    }
+    let root = Into::<usize>::into(tree.root);
+    let children: Vec<String> = tree.internals[root]
+        .children
+        .iter()
+        .map(|&child| subtree(tree, sequences, labels, child))
+        .collect();
+    let root_label = if label_root {
+        labels[root].as_str()
+    } else {
+        ""
+    };
+    format!("({}){};", children.join(","), root_label)
+}
+
+#[cfg(test)]
+mod guide_tree_tests {
+    use super::{estimate_guide_tree, kmer_distances, tree_to_labeled_newick};
+    use super::{estimate_tree_from_msa, msa_distances};
+    use super::{tree_to_newick, tree_to_newick_w_support};
+    use bio::io::fasta::Record;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn sequences() -> Vec<Record> {
+        vec![
+            Record::with_attrs("A", None, b"ACGTACGTACGTTTGA"),
+            Record::with_attrs("B", None, b"ACGTACGTACGTTTGC"),
+            Record::with_attrs("C", None, b"TTGGCCAATTGGCCAA"),
+            Record::with_attrs("D", None, b"TTGGCCAATTGGCCTA"),
+        ]
+    }
+
+    #[test]
+    fn distances() {
+        let distances = kmer_distances(&sequences());
+        assert_eq!(distances[0][0], 0.0);
+        assert_eq!(distances[0][1], distances[1][0]);
+        assert!(distances[0][1] < distances[0][2]);
+        assert!(distances[2][3] < distances[1][3]);
+    }
+
+    #[test]
+    fn guide_tree_groups_closest_sequences() {
+        let sequences = sequences();
+        let tree = estimate_guide_tree(&sequences).unwrap();
+        assert_eq!(tree.internals.len(), 3);
+        assert_eq!(tree.postorder.len(), 7);
+        let mut cherries: Vec<Vec<usize>> = tree
+            .internals
+            .iter()
+            .filter_map(|node| {
+                node.children
+                    .iter()
+                    .map(|child| match child {
+                        L(idx) => Some(*idx),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<usize>>>()
+            })
+            .collect();
+        for cherry in cherries.iter_mut() {
+            cherry.sort();
+        }
+        cherries.sort();
+        assert_eq!(cherries, vec![vec![0, 1], vec![2, 3]]);
+        let newick = tree_to_newick(&tree, &sequences);
+        assert!(newick.starts_with("((") && newick.ends_with(");"));
+        for id in ["A:", "B:", "C:", "D:"] {
+            assert!(newick.contains(id));
+        }
+        assert!(estimate_guide_tree(&sequences[..1]).is_err());
+    }
+
+    #[test]
+    fn tree_from_msa() {
+        let msa = vec![
+            Record::with_attrs("A", None, b"ACGTACGT-A"),
+            Record::with_attrs("B", None, b"ACGTACGTTA"),
+            Record::with_attrs("C", None, b"TCGAACCT-A"),
+            Record::with_attrs("D", None, b"TCGAACCATA"),
+        ];
+        let distances = msa_distances(&msa);
+        assert_eq!(distances[0][1], 0.0);
+        assert_eq!(distances[0][2], 3.0 / 9.0);
+        assert_eq!(distances[2][3], 1.0 / 9.0);
+        let tree = estimate_tree_from_msa(&msa).unwrap();
+        assert_eq!(tree.internals.len(), 3);
+        let mut cherries: Vec<Vec<usize>> = tree
+            .internals
+            .iter()
+            .filter_map(|node| match node.children[..] {
+                [L(a), L(b)] => Some(vec![a.min(b), a.max(b)]),
+                _ => None,
+            })
+            .collect();
+        cherries.sort();
+        assert_eq!(cherries, vec![vec![0, 1], vec![2, 3]]);
+        assert!(estimate_tree_from_msa(&msa[..1]).is_err());
+        let unaligned = [msa[0].clone(), Record::with_attrs("E", None, b"ACGT")];
+        assert!(estimate_tree_from_msa(&unaligned).is_err());
+    }
+
+    #[test]
+    fn newick_w_support() {
+        let sequences = sequences();
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 2.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 0.5, 0.5);
+        tree.complete = true;
+        tree.create_postorder();
+        assert_eq!(
+            tree_to_newick_w_support(&tree, &sequences, &[Some(0.876), Some(1.0), None]),
+            "((A:1,B:2)88:0.5,(C:1,D:1)100:0.5);"
+        );
+        assert_eq!(
+            tree_to_newick(&tree, &sequences),
+            "((A:1,B:2):0.5,(C:1,D:1):0.5);"
+        );
+        assert_eq!(
+            tree_to_labeled_newick(&tree, &sequences),
+            "((A:1,B:2)N1:0.5,(C:1,D:1)N2:0.5)N3;"
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use log::info;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+use std::collections::BTreeMap;
+
+use super::msa_scoring::{pars_ancestral_gaps, GAP};
+use super::parsimony_costs::ParsimonyCosts;
+use super::AlignmentOptions;
+use crate::Result;
+
+/// Direction of an indel on the branch above a node. Residues that the node has and its parent
+/// does not were inserted on the branch, residues of the parent that the node lacks were deleted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IndelKind {
+    Insertion,
+    Deletion,
+}
+
+/// Indel on the branch above `node` over the MSA columns `start..end`. Columns that are gaps at
+/// both ends of the branch do not interrupt an indel, `length` only counts the columns with a
+/// residue at one of the ends.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndelEvent {
+    pub node: NodeIdx,
+    pub kind: IndelKind,
+    pub start: usize,
+    pub end: usize,
+    pub length: usize,
+}
+
+/// Reconstructs the indels of an MSA on every branch of the tree from the inferred gaps of the
+/// internal nodes, see `pars_ancestral_gaps`. The events are ordered by the postorder of the
+/// nodes below the branches and by their first column.
+pub fn pars_indel_events(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<Vec<IndelEvent>> {
+    info!("Reconstructing the indel events of the MSA.");
+    let tree = &info.tree;
+    let ancestral = pars_ancestral_gaps(scoring, info, options)?;
+    let mut parents = vec![None; tree.internals.len() + tree.leaves.len()];
+    for (idx, node) in tree.internals.iter().enumerate() {
+        for &child in &node.children {
+            parents[node_position(tree.internals.len(), child)] = Some(idx);
+        }
+    }
+    let mut events = Vec::new();
+    for &node in &tree.postorder {
+        let Some(parent) = parents[node_position(tree.internals.len(), node)] else {
+            continue;
+        };
+        let gaps: Vec<bool> = match node {
+            Int(idx) => ancestral[idx].clone(),
+            Leaf(idx) => info.sequences[idx]
+                .seq()
+                .iter()
+                .map(|&c| c == GAP)
+                .collect(),
+        };
+        branch_events(node, &ancestral[parent], &gaps, &mut events);
+    }
+    info!("Found {} indel events.", events.len());
+    Ok(events)
+}
+
+/// Number of insertions and deletions of one length.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndelLengthCount {
+    pub length: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// Indels on the branch above `node`. The rate is the number of indels per unit of branch
+/// length, it is not defined for branches of length zero.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BranchIndels {
+    pub node: NodeIdx,
+    pub branch_length: f64,
+    pub insertions: usize,
+    pub deletions: usize,
+    pub rate: Option<f64>,
+}
+
+/// Summary of the indel events of an MSA: the totals, the distribution of the lengths in
+/// increasing order and the indels of every branch in postorder.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndelStats {
+    pub insertions: usize,
+    pub deletions: usize,
+    pub lengths: Vec<IndelLengthCount>,
+    pub branches: Vec<BranchIndels>,
+}
+
+impl IndelStats {
+    /// Summarises the events reconstructed on `tree`, see `pars_indel_events`.
+    pub fn new(events: &[IndelEvent], tree: &Tree) -> Self {
+        let mut lengths = BTreeMap::<usize, (usize, usize)>::new();
+        let mut per_node = vec![(0, 0); tree.internals.len() + tree.leaves.len()];
+        for event in events {
+            let length = lengths.entry(event.length).or_default();
+            let node = &mut per_node[node_position(tree.internals.len(), event.node)];
+            match event.kind {
+                IndelKind::Insertion => {
+                    length.0 += 1;
+                    node.0 += 1;
+                }
+                IndelKind::Deletion => {
+                    length.1 += 1;
+                    node.1 += 1;
+                }
+            }
+        }
+        let branches = tree
+            .postorder
+            .iter()
+            .filter(|&&node| node != tree.root)
+            .map(|&node| {
+                let (insertions, deletions) = per_node[node_position(tree.internals.len(), node)];
+                let branch_length = match node {
+                    Int(idx) => tree.internals[idx].blen,
+                    Leaf(idx) => tree.leaves[idx].blen,
+                };
+                BranchIndels {
+                    node,
+                    branch_length,
+                    insertions,
+                    deletions,
+                    rate: (branch_length > 0.0)
+                        .then(|| (insertions + deletions) as f64 / branch_length),
+                }
+            })
+            .collect();
+        IndelStats {
+            insertions: lengths.values().map(|counts| counts.0).sum(),
+            deletions: lengths.values().map(|counts| counts.1).sum(),
+            lengths: lengths
+                .into_iter()
+                .map(|(length, (insertions, deletions))| IndelLengthCount {
+                    length,
+                    insertions,
+                    deletions,
+                })
+                .collect(),
+            branches,
+        }
+    }
+
+    /// Mean length of the insertions or of the deletions, not defined if there are none.
+    pub fn mean_length(&self, kind: IndelKind) -> Option<f64> {
+        let (count, total) = self
+            .lengths
+            .iter()
+            .map(|counts| match kind {
+                IndelKind::Insertion => (counts.insertions, counts.insertions * counts.length),
+                IndelKind::Deletion => (counts.deletions, counts.deletions * counts.length),
+            })
+            .fold((0, 0), |(count, total), (n, l)| (count + n, total + l));
+        (count > 0).then(|| total as f64 / count as f64)
+    }
+}
+
+fn node_position(internal_count: usize, node: NodeIdx) -> usize {
+    match node {
+        Int(idx) => idx,
+        Leaf(idx) => internal_count + idx,
+    }
+}
+
+fn branch_events(node: NodeIdx, parent_gaps: &[bool], gaps: &[bool], events: &mut Vec<IndelEvent>) {
+    let mut current: Option<IndelEvent> = None;
+    for (column, (&parent_gap, &gap)) in parent_gaps.iter().zip(gaps).enumerate() {
+        let kind = match (parent_gap, gap) {
+            (true, true) => continue,
+            (false, false) => None,
+            (true, false) => Some(IndelKind::Insertion),
+            (false, true) => Some(IndelKind::Deletion),
+        };
+        if let (Some(event), Some(kind)) = (current.as_mut(), kind) {
+            if event.kind == kind {
+                event.end = column + 1;
+                event.length += 1;
+                continue;
+            }
+        }
+        events.extend(current.take());
+        current = kind.map(|kind| IndelEvent {
+            node,
+            kind,
+            start: column,
+            end: column + 1,
+            length: 1,
+        });
+    }
+    events.extend(current);
+}
+
+#[cfg(test)]
+mod indels_tests {
+    use super::{branch_events, pars_indel_events, IndelEvent, IndelKind, IndelStats};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn events_on_a_branch() {
+        let parent = [false, false, true, true, false, true, false];
+        let child = [false, true, false, true, false, true, true];
+        let mut events = Vec::new();
+        branch_events(L(0), &parent, &child, &mut events);
+        assert_eq!(
+            events,
+            vec![
+                IndelEvent {
+                    node: L(0),
+                    kind: IndelKind::Deletion,
+                    start: 1,
+                    end: 2,
+                    length: 1,
+                },
+                IndelEvent {
+                    node: L(0),
+                    kind: IndelKind::Insertion,
+                    start: 2,
+                    end: 3,
+                    length: 1,
+                },
+                IndelEvent {
+                    node: L(0),
+                    kind: IndelKind::Deletion,
+                    start: 6,
+                    end: 7,
+                    length: 1,
+                },
+            ]
+        );
+        let mut events = Vec::new();
+        branch_events(
+            I(1),
+            &[false, true, false],
+            &[true, true, true],
+            &mut events,
+        );
+        assert_eq!(events[0].start, 0);
+        assert_eq!(events[0].end, 3);
+        assert_eq!(events[0].length, 2);
+    }
+
+    #[test]
+    fn deletion_in_a_leaf() {
+        let msa = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+            Record::with_attrs("C", None, b"AGCT"),
+            Record::with_attrs("D", None, b"AGCT"),
+        ];
+        let mut tree = Tree::new(&msa).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&msa, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let events = pars_indel_events(&scoring, &info, &AlignmentOptions::default()).unwrap();
+        assert_eq!(
+            events,
+            vec![IndelEvent {
+                node: L(1),
+                kind: IndelKind::Deletion,
+                start: 3,
+                end: 4,
+                length: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let sequences = [
+            Record::with_attrs("A", None, b"A"),
+            Record::with_attrs("B", None, b"A"),
+            Record::with_attrs("C", None, b"A"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 0.5, 0.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 2.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let event = |node, kind, length| IndelEvent {
+            node,
+            kind,
+            start: 0,
+            end: length,
+            length,
+        };
+        let events = [
+            event(L(0), IndelKind::Insertion, 2),
+            event(L(0), IndelKind::Deletion, 1),
+            event(L(2), IndelKind::Deletion, 1),
+            event(I(0), IndelKind::Deletion, 3),
+        ];
+        let stats = IndelStats::new(&events, &tree);
+        assert_eq!(stats.insertions, 1);
+        assert_eq!(stats.deletions, 3);
+        let lengths: Vec<_> = stats
+            .lengths
+            .iter()
+            .map(|counts| (counts.length, counts.insertions, counts.deletions))
+            .collect();
+        assert_eq!(lengths, vec![(1, 0, 2), (2, 1, 0), (3, 0, 1)]);
+        assert_eq!(stats.mean_length(IndelKind::Insertion), Some(2.0));
+        assert_eq!(stats.mean_length(IndelKind::Deletion), Some(5.0 / 3.0));
+        assert_eq!(stats.branches.len(), 4);
+        let rate = |node| {
+            stats
+                .branches
+                .iter()
+                .find(|branch| branch.node == node)
+                .unwrap()
+                .rate
+        };
+        assert_eq!(rate(L(0)), Some(4.0));
+        assert_eq!(rate(L(1)), None);
+        assert_eq!(rate(I(0)), Some(1.0));
+        assert_eq!(rate(L(2)), Some(0.5));
+    }
+}
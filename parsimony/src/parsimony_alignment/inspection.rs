@@ -0,0 +1,265 @@
+//! Read-only views of the DP matrices of the alignment at a node, for teaching tools and custom
+//! diagnostics around the alignment algorithm. Only built with the `inspect` feature.
+
+use phylo::phylo_info::PhyloInfo;
+use std::iter::zip;
+
+use super::msa_scoring::msa_on_tree;
+use super::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
+use super::parsimony_matrices::ParsimonyAlignmentMatrices;
+use super::sequence_weights::{child_cost_factors, WeightedBranchCosts};
+use super::Direction::{self, GapInX, GapInY, Matc};
+use super::{check_dp_memory, child_info, node_rng, AlignmentOptions, DpExtraMatrices};
+use crate::{ParsimonyError, Result};
+
+/// State of the DP, the kind of the last column of the alignments that end in a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DpState {
+    /// Sites of both children are matched.
+    Match,
+    /// A site of the first child is aligned to a gap.
+    GapInY,
+    /// A site of the second child is aligned to a gap.
+    GapInX,
+}
+
+impl DpState {
+    /// The three states in the order of the matrices.
+    pub const ALL: [DpState; 3] = [DpState::Match, DpState::GapInY, DpState::GapInX];
+
+    fn index(self) -> usize {
+        match self {
+            DpState::Match => 0,
+            DpState::GapInY => 1,
+            DpState::GapInX => 2,
+        }
+    }
+}
+
+impl From<Direction> for DpState {
+    fn from(direction: Direction) -> Self {
+        match direction {
+            Matc => DpState::Match,
+            GapInY => DpState::GapInY,
+            GapInX => DpState::GapInX,
+        }
+    }
+}
+
+impl From<DpState> for Direction {
+    fn from(state: DpState) -> Self {
+        match state {
+            DpState::Match => Matc,
+            DpState::GapInY => GapInY,
+            DpState::GapInX => GapInX,
+        }
+    }
+}
+
+/// Copy of the filled DP matrices of the alignment of the two children of a node. The cell
+/// (i, j) holds the alignments of the first i sites of the first child with the first j sites
+/// of the second one, every state has its own score and direction matrix.
+#[derive(Clone, Debug)]
+pub struct DpMatrices {
+    scores: [Vec<Vec<f64>>; 3],
+    directions: [Vec<Vec<DpState>>; 3],
+    optimal: [Vec<Vec<Vec<DpState>>>; 3],
+    path: Vec<(DpState, usize, usize)>,
+    score: f64,
+}
+
+impl DpMatrices {
+    fn new(
+        pars_mats: &ParsimonyAlignmentMatrices,
+        path: Vec<(DpState, usize, usize)>,
+        score: f64,
+    ) -> Self {
+        let matrix = |state: DpState| {
+            (0..pars_mats.rows)
+                .map(|i| {
+                    (0..pars_mats.cols)
+                        .map(|j| pars_mats.state_score(state.into(), i, j))
+                        .collect()
+                })
+                .collect()
+        };
+        let directions = |state: DpState| {
+            (0..pars_mats.rows)
+                .map(|i| {
+                    (0..pars_mats.cols)
+                        .map(|j| pars_mats.stored_direction(state.into(), i, j).into())
+                        .collect()
+                })
+                .collect()
+        };
+        let optimal = |state: DpState| {
+            (0..pars_mats.rows)
+                .map(|i| {
+                    (0..pars_mats.cols)
+                        .map(|j| {
+                            pars_mats
+                                .tied_directions(state.into(), i, j)
+                                .iter()
+                                .map(|&direction| direction.into())
+                                .collect()
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        DpMatrices {
+            scores: DpState::ALL.map(matrix),
+            directions: DpState::ALL.map(directions),
+            optimal: DpState::ALL.map(optimal),
+            path,
+            score,
+        }
+    }
+
+    /// Number of rows, one more than the number of sites of the first child.
+    pub fn rows(&self) -> usize {
+        self.scores[0].len()
+    }
+
+    /// Number of columns, one more than the number of sites of the second child.
+    pub fn cols(&self) -> usize {
+        self.scores[0].first().map_or(0, Vec::len)
+    }
+
+    /// Score matrix of a state.
+    pub fn scores(&self, state: DpState) -> &[Vec<f64>] {
+        &self.scores[state.index()]
+    }
+
+    /// Best score of the alignments that end in the cell in the given state.
+    pub fn score(&self, state: DpState, i: usize, j: usize) -> f64 {
+        self.scores[state.index()][i][j]
+    }
+
+    /// State of the previous cell that the traceback takes from the cell, the choice made
+    /// during the fill.
+    pub fn direction(&self, state: DpState, i: usize, j: usize) -> DpState {
+        self.directions[state.index()][i][j]
+    }
+
+    /// All the states of the previous cell that reach the best score of the cell, more than
+    /// one where the tie-breaking had to choose.
+    pub fn optimal_directions(&self, state: DpState, i: usize, j: usize) -> &[DpState] {
+        &self.optimal[state.index()][i][j]
+    }
+
+    /// Cells of the chosen alignment with their states, from the top left to the bottom right.
+    /// Fixed gap sites are not part of the path, the matrices skip them.
+    pub fn path(&self) -> &[(DpState, usize, usize)] {
+        &self.path
+    }
+
+    /// Score of the chosen alignment.
+    pub fn alignment_score(&self) -> f64 {
+        self.score
+    }
+}
+
+/// DP matrices of the alignment at the internal node with index `node` for an existing MSA on
+/// the tree. The profiles of the two children are the ones induced by the MSA, see
+/// `pars_score_msa`, and the matrices are filled without constraints, band or X-drop, so they
+/// show the best alignment of the children and how it compares to the others.
+pub fn pars_node_matrices(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    node: usize,
+) -> Result<DpMatrices> {
+    let tree = &info.tree;
+    if node >= tree.internals.len() {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "Node {} does not exist, the tree has {} internal nodes.",
+            node,
+            tree.internals.len()
+        )));
+    }
+    let msa = msa_on_tree(scoring, info, options, false)?;
+    let factors = child_cost_factors(tree, options.sequence_weighting);
+    let children = &tree.internals[node].children;
+    let (x_info, x_branch) = child_info(tree, children[0], &msa.internal_info, &msa.leaf_info);
+    let (y_info, y_branch) = child_info(tree, children[1], &msa.internal_info, &msa.leaf_info);
+    let x_scor = WeightedBranchCosts::new(scoring.get_branch_costs(x_branch), factors[node].0);
+    let y_scor = WeightedBranchCosts::new(scoring.get_branch_costs(y_branch), factors[node].1);
+    let extra = DpExtraMatrices::new(options, x_scor.site_categories());
+    check_dp_memory(x_info.len(), y_info.len(), options, extra, true)?;
+    let mut pars_mats = ParsimonyAlignmentMatrices::new(
+        x_info.len() + 1,
+        y_info.len() + 1,
+        node_rng(options, node),
+    )
+    .with_tie_break(options.tie_break)
+    .with_free_end_gaps(options.free_end_gaps)
+    .with_gap_function(options.gap_function);
+    pars_mats.fill_matrices(x_info, &x_scor, y_info, &y_scor);
+    let (_, alignment, score) = pars_mats.traceback(x_info, y_info);
+    let (mut i, mut j) = (0, 0);
+    let path = zip(&alignment.map_x, &alignment.map_y)
+        .filter_map(|(x, y)| match (x, y) {
+            (Some(_), Some(_)) => {
+                (i, j) = (i + 1, j + 1);
+                Some((DpState::Match, i, j))
+            }
+            (Some(_), None) => {
+                i += 1;
+                (!x_info[i - 1].is_fixed()).then_some((DpState::GapInY, i, j))
+            }
+            (None, Some(_)) => {
+                j += 1;
+                (!y_info[j - 1].is_fixed()).then_some((DpState::GapInX, i, j))
+            }
+            (None, None) => unreachable!(),
+        })
+        .collect();
+    Ok(DpMatrices::new(&pars_mats, path, score))
+}
+
+#[cfg(test)]
+mod inspection_tests {
+    use super::{pars_node_matrices, DpState};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn node_matrices() {
+        let msa = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+        ];
+        let mut tree = Tree::new(&msa).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&msa, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let matrices = pars_node_matrices(&scoring, &info, &options, 0).unwrap();
+        assert_eq!((matrices.rows(), matrices.cols()), (5, 4));
+        assert_eq!(matrices.score(DpState::Match, 0, 0), 0.0);
+        assert_eq!(matrices.score(DpState::Match, 3, 3), 0.0);
+        assert_eq!(matrices.alignment_score(), 2.0);
+        assert_eq!(
+            matrices.path(),
+            [
+                (DpState::Match, 1, 1),
+                (DpState::Match, 2, 2),
+                (DpState::Match, 3, 3),
+                (DpState::GapInY, 4, 3)
+            ]
+        );
+        assert_eq!(matrices.direction(DpState::GapInY, 4, 3), DpState::Match);
+        assert_eq!(
+            matrices.optimal_directions(DpState::GapInY, 4, 3),
+            [DpState::Match]
+        );
+        assert_eq!(matrices.scores(DpState::GapInX).len(), 5);
+        assert!(pars_node_matrices(&scoring, &info, &options, 1).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+/// Counters of the work done by the alignments that share them, for finding out where the time
+/// and memory of a run on a user dataset go. The counters are updated concurrently by the
+/// parallel alignment and the restarts, `snapshot` reads their current values.
+#[derive(Debug, Default)]
+pub struct RunMetrics {
+    cells_filled: AtomicU64,
+    cost_lookups: AtomicU64,
+    cost_cache_hits: AtomicU64,
+    tie_breaks: AtomicU64,
+    dp_allocations: AtomicU64,
+    dp_bytes: AtomicU64,
+}
+
+/// Values of the `RunMetrics` counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// DP cells whose scores were computed, cells of fixed sites and cells masked by a band are
+    /// not counted.
+    pub cells_filled: u64,
+    /// Lookups of the costs of a branch.
+    pub cost_lookups: u64,
+    /// Lookups that found costs computed for that exact branch length, the others used the
+    /// costs of the closest precomputed branch length.
+    pub cost_cache_hits: u64,
+    /// Choices between equally scoring directions in the fill and the traceback.
+    pub tie_breaks: u64,
+    /// Allocations of a set of DP matrices, more than one per node with anchored alignment.
+    pub dp_allocations: u64,
+    /// Estimated size of all the allocated DP matrices in bytes.
+    pub dp_bytes: u64,
+}
+
+impl RunMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            cells_filled: self.cells_filled.load(Relaxed),
+            cost_lookups: self.cost_lookups.load(Relaxed),
+            cost_cache_hits: self.cost_cache_hits.load(Relaxed),
+            tie_breaks: self.tie_breaks.load(Relaxed),
+            dp_allocations: self.dp_allocations.load(Relaxed),
+            dp_bytes: self.dp_bytes.load(Relaxed),
+        }
+    }
+
+    pub(crate) fn record_cost_lookup(&self, cache_hit: bool) {
+        self.cost_lookups.fetch_add(1, Relaxed);
+        if cache_hit {
+            self.cost_cache_hits.fetch_add(1, Relaxed);
+        }
+    }
+
+    /// Records one fill of the DP matrices and its traceback.
+    pub(crate) fn record_dp(&self, bytes: usize, cells_filled: u64, tie_breaks: u64) {
+        self.dp_allocations.fetch_add(1, Relaxed);
+        self.dp_bytes.fetch_add(bytes as u64, Relaxed);
+        self.cells_filled.fetch_add(cells_filled, Relaxed);
+        self.tie_breaks.fetch_add(tie_breaks, Relaxed);
+    }
+}
+
+impl MetricsSnapshot {
+    /// Names and values of the counters, in the order of the fields.
+    pub fn entries(&self) -> [(&'static str, u64); 6] {
+        [
+            ("cells_filled", self.cells_filled),
+            ("cost_lookups", self.cost_lookups),
+            ("cost_cache_hits", self.cost_cache_hits),
+            ("tie_breaks", self.tie_breaks),
+            ("dp_allocations", self.dp_allocations),
+            ("dp_bytes", self.dp_bytes),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::{MetricsSnapshot, RunMetrics};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{
+        pars_align_on_tree_w_options, AlignmentObserver, AlignmentOptions, ObserverHandle,
+    };
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Leaf as L, Tree};
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Option<MetricsSnapshot>>>);
+
+    impl AlignmentObserver for Recorder {
+        fn run_metrics(&self, metrics: &MetricsSnapshot) {
+            *self.0.lock().unwrap() = Some(*metrics);
+        }
+    }
+
+    #[test]
+    fn count_dp_work() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let metrics = Arc::new(RunMetrics::new());
+        let reported = Arc::new(Mutex::new(None));
+        let options = AlignmentOptions {
+            seed: Some(3),
+            metrics: Some(metrics.clone()),
+            observer: Some(ObserverHandle::new(Recorder(reported.clone()))),
+            ..Default::default()
+        };
+        pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.cells_filled, 7 * 3);
+        assert_eq!(snapshot.cost_lookups, 2);
+        assert_eq!(snapshot.cost_cache_hits, 2);
+        assert_eq!(snapshot.dp_allocations, 1);
+        assert!(snapshot.dp_bytes > 0);
+        assert_eq!(*reported.lock().unwrap(), Some(snapshot));
+        assert_eq!(snapshot.entries()[0], ("cells_filled", 21));
+
+        pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(metrics.snapshot().dp_allocations, 2);
+    }
+}
@@ -1,18 +1,71 @@
+use self::alphabet::Alphabet;
+use self::constraints::{AnchorConstraints, NodeConstraints, TreeConstraints};
+use self::gap_profiles::GapProfiles;
+use self::metrics::{MetricsSnapshot, RunMetrics};
 use self::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
-use self::parsimony_info::ParsimonySiteInfo;
+use self::parsimony_info::{ParsimonySiteInfo, SiteFlag};
 use self::parsimony_matrices::ParsimonyAlignmentMatrices;
-use self::parsimony_sets::get_parsimony_sets;
-use log::{debug, info};
-use phylo::alignment::Alignment;
+use self::parsimony_sets::{get_dna_set, get_parsimony_sets, get_protein_set, ParsimonySet};
+use self::partitions::Partitions;
+use self::recoding::Recoding;
+use self::region_masks::RegionMasks;
+use self::sequence_weights::{child_cost_factors, SequenceWeighting, WeightedBranchCosts};
+use crate::{ParsimonyError, Result};
+use bio::io::fasta::Record;
+use log::{debug, info, warn};
+use phylo::alignment::{compile_alignment_representation, Alignment};
 use phylo::phylo_info::PhyloInfo;
-use phylo::sequences::get_sequence_type;
-use phylo::tree::{NodeIdx::Internal as Int, NodeIdx::Leaf};
+use phylo::sequences::{get_sequence_type, SequenceType};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
 use rand::prelude::*;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+// std::time::Instant panics on wasm32, web_time uses the clock of the browser there
+use web_time::Instant;
 
+pub mod aligner;
+pub mod alphabet;
+pub mod anchors;
+pub mod batch;
+pub mod bootstrap;
+pub mod branch_lengths;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod checkpoint;
+pub mod codon;
+pub mod consensus;
+pub mod constraints;
+pub mod gap_normalization;
+pub mod gap_profiles;
+pub mod guide_tree;
+pub mod indels;
+#[cfg(feature = "inspect")]
+pub mod inspection;
+pub mod metrics;
+pub mod msa_comparison;
+pub mod msa_scoring;
+pub mod pairwise;
+pub mod parallel;
 pub mod parsimony_costs;
 pub mod parsimony_info;
 pub mod parsimony_matrices;
-pub(crate) mod parsimony_sets;
+pub mod parsimony_sets;
+pub mod partitions;
+pub mod profile_alignment;
+pub mod recoding;
+pub mod refinement;
+pub mod region_masks;
+pub mod restarts;
+pub mod sampling;
+pub mod sequence_weights;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod strand;
+pub mod streaming;
+pub mod suboptimal;
+pub mod tree_input;
+pub mod tree_search;
+pub mod trimming;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum Direction {
@@ -21,18 +74,464 @@ pub(crate) enum Direction {
     GapInX,
 }
 
+/// Whether the fill keeps all the rows of the score matrices of a node or only the two rows it
+/// needs at a time. The traceback only follows the stored directions, which take a byte per
+/// state and cell, so dropping the scores cuts the memory of a node by about four fifths. The
+/// direction and tie matrices keep a cell for every pair of sites, the memory stays quadratic
+/// in the lengths of the profiles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScoreRows {
+    /// Keep two rows of scores for the nodes with more than `TWO_SCORE_ROWS_CELLS` cells.
+    #[default]
+    Auto,
+    All,
+    Two,
+}
+
+/// Number of cells above which `ScoreRows::Auto` keeps two rows of scores, at 24 bytes per
+/// cell for the scores this is around 400MB.
+pub const TWO_SCORE_ROWS_CELLS: usize = 1 << 24;
+
+impl ScoreRows {
+    pub(crate) fn two_rows(&self, rows: usize, cols: usize) -> bool {
+        match self {
+            ScoreRows::Auto => rows.saturating_mul(cols) > TWO_SCORE_ROWS_CELLS,
+            ScoreRows::All => false,
+            ScoreRows::Two => true,
+        }
+    }
+}
+
+/// Matrices of the DP that are only allocated for some of the options.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DpExtraMatrices {
+    /// Length of the gap ending in every cell of the two gap states, four bytes each, for the
+    /// gap functions that are not affine.
+    pub gap_lengths: bool,
+    /// Site category of the match in every cell, a byte each, for the scorings with site
+    /// categories.
+    pub match_categories: bool,
+}
+
+impl DpExtraMatrices {
+    /// Extra matrices for aligning with `options` and a scoring with `site_categories` site
+    /// categories, see `BranchParsimonyCosts::site_categories`.
+    pub fn new(options: &AlignmentOptions, site_categories: usize) -> Self {
+        DpExtraMatrices {
+            gap_lengths: options.gap_function != GapFunction::Affine,
+            match_categories: site_categories > 0,
+        }
+    }
+}
+
+/// Estimated memory in bytes of the DP matrices for aligning profiles of lengths `x_len` and
+/// `y_len`. Every cell holds a direction and a tie mask of a byte and a score of eight bytes for
+/// each of the three states, plus the `extra` matrices. With `two_score_rows` only two rows of
+/// the scores and the gap lengths are kept.
+pub fn estimate_dp_memory(
+    x_len: usize,
+    y_len: usize,
+    two_score_rows: bool,
+    extra: DpExtraMatrices,
+) -> usize {
+    let (rows, cols) = (x_len + 1, y_len + 1);
+    let score_rows = if two_score_rows { rows.min(2) } else { rows };
+    let row_bytes = if extra.gap_lengths { 32 } else { 24 };
+    let cell_bytes = if extra.match_categories { 7 } else { 6 };
+    rows.saturating_mul(cols)
+        .saturating_mul(cell_bytes)
+        .saturating_add(score_rows.saturating_mul(cols).saturating_mul(row_bytes))
+}
+
+/// Human readable size, e.g. 1.5 GB.
+pub fn format_bytes(bytes: usize) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+// Nodes whose full matrices exceed `max_memory` keep two rows of scores, unless all the rows
+// were asked for explicitly.
+fn node_two_score_rows(
+    x_len: usize,
+    y_len: usize,
+    options: &AlignmentOptions,
+    extra: DpExtraMatrices,
+) -> bool {
+    let two_score_rows = options.score_rows.two_rows(x_len + 1, y_len + 1);
+    match options.max_memory {
+        Some(max_memory) if !two_score_rows && options.score_rows == ScoreRows::Auto => {
+            estimate_dp_memory(x_len, y_len, false, extra) > max_memory
+        }
+        _ => two_score_rows,
+    }
+}
+
+// Whether the fill of a node goes by anti-diagonals, which keeps all the scores, see
+// `ParsimonyAlignmentMatrices::fill_matrices`.
+fn fills_by_diagonals(options: &AlignmentOptions) -> bool {
+    options.band.is_none() && (options.wavefront || options.x_drop.is_some())
+}
+
+// Memory of the DP matrices of a node with the fill chosen by the options, and whether that is
+// the fill with two score rows. With `all_scores` the caller reads the scores of every cell
+// after the fill, e.g. for the k best tracebacks, so none of them are dropped.
+fn node_dp_memory(
+    x_len: usize,
+    y_len: usize,
+    options: &AlignmentOptions,
+    extra: DpExtraMatrices,
+    all_scores: bool,
+) -> (usize, bool) {
+    let two_score_rows = !all_scores
+        && !fills_by_diagonals(options)
+        && node_two_score_rows(x_len, y_len, options, extra);
+    (
+        estimate_dp_memory(x_len, y_len, two_score_rows, extra),
+        two_score_rows,
+    )
+}
+
+/// Number of DP cells over all the internal nodes and memory of the DP matrices of the largest
+/// node for aligning on a tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DpCost {
+    pub cells: usize,
+    pub peak_memory: usize,
+}
+
+/// Range of the cost of aligning on a tree before knowing the lengths of the ancestral
+/// profiles. The lower bound takes every ancestor as long as its longer child, as for closely
+/// related sequences, and the upper bound as long as its two children together, as if nothing
+/// could be matched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DpEstimate {
+    pub lower: DpCost,
+    pub upper: DpCost,
+}
+
+/// Estimates the DP cells and memory of aligning the sequences of `info` on its tree with the
+/// memory settings of `options` and a scoring with `site_categories` site categories, see
+/// `DpEstimate`.
+pub fn estimate_tree_dp(
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    site_categories: usize,
+) -> DpEstimate {
+    let tree = &info.tree;
+    let extra = DpExtraMatrices::new(options, site_categories);
+    let leaf_lens: Vec<usize> = info
+        .sequences
+        .iter()
+        .map(|record| record.seq().iter().filter(|&&c| c != b'-').count())
+        .collect();
+    let bound = |ancestor_len: fn(usize, usize) -> usize| {
+        let mut lengths = vec![0; tree.internals.len()];
+        let mut cost = DpCost::default();
+        for &node in &tree.postorder {
+            let Int(idx) = node else {
+                continue;
+            };
+            let length = |child: NodeIdx| match child {
+                Int(child) => lengths[child],
+                Leaf(child) => leaf_lens[child],
+            };
+            let children = &tree.internals[idx].children;
+            let (x_len, y_len) = (length(children[0]), length(children[1]));
+            cost.cells = cost
+                .cells
+                .saturating_add((x_len + 1).saturating_mul(y_len + 1));
+            cost.peak_memory = cost
+                .peak_memory
+                .max(node_dp_memory(x_len, y_len, options, extra, false).0);
+            lengths[idx] = ancestor_len(x_len, y_len);
+        }
+        cost
+    };
+    DpEstimate {
+        lower: bound(usize::max),
+        upper: bound(|x_len, y_len| x_len + y_len),
+    }
+}
+
+/// Fails if the DP matrices for aligning profiles of lengths `x_len` and `y_len` need more than
+/// `options.max_memory` even after switching to two rows of scores, see `node_dp_memory` for
+/// `all_scores`.
+pub(crate) fn check_dp_memory(
+    x_len: usize,
+    y_len: usize,
+    options: &AlignmentOptions,
+    extra: DpExtraMatrices,
+    all_scores: bool,
+) -> Result<()> {
+    let Some(max_memory) = options.max_memory else {
+        return Ok(());
+    };
+    let (needed, two_score_rows) = node_dp_memory(x_len, y_len, options, extra, all_scores);
+    if needed > max_memory {
+        return Err(ParsimonyError::MemoryLimit(format!(
+            "Aligning profiles of length {} and {} needs about {} for the DP matrices{}, more than the limit of {}.",
+            x_len,
+            y_len,
+            format_bytes(needed),
+            if two_score_rows { " with two rows of scores" } else { "" },
+            format_bytes(max_memory)
+        )));
+    }
+    if two_score_rows && !options.score_rows.two_rows(x_len + 1, y_len + 1) {
+        info!(
+            "Keeping two rows of scores for profiles of length {} and {} to stay within {}.",
+            x_len,
+            y_len,
+            format_bytes(max_memory)
+        );
+    }
+    Ok(())
+}
+
+/// Type of the sequences, either inferred from the characters or given explicitly so that short
+/// or unusual protein sequences are not taken for DNA. RNA is aligned as DNA with U read as T.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DataType {
+    #[default]
+    Auto,
+    Dna,
+    Rna,
+    Protein,
+}
+
+impl DataType {
+    /// Sequence type used for the parsimony sets and the scoring of the sequences.
+    pub fn sequence_type(&self, sequences: &[Record]) -> SequenceType {
+        match self {
+            DataType::Auto => get_sequence_type(sequences),
+            DataType::Dna | DataType::Rna => SequenceType::DNA,
+            DataType::Protein => SequenceType::Protein,
+        }
+    }
+}
+
+/// Treatment of soft-masked residues, the lowercase letters that masking tools use for repeats
+/// and low complexity regions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SoftMask {
+    /// Lowercase residues are scored as the uppercase ones.
+    #[default]
+    Keep,
+    /// Lowercase residues stand for any character and gaps against them cost nothing, so they
+    /// add nothing to the score.
+    Ignore,
+    /// Gaps against lowercase residues cost this fraction of the usual penalty, the multiplier
+    /// is applied on top of the gap profiles.
+    Downweight(f64),
+}
+
+/// Penalty of a gap as a function of its length.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GapFunction {
+    /// A gap of length L costs the opening cost plus L - 1 times the extension cost.
+    #[default]
+    Affine,
+    /// A gap of length L costs the opening cost plus the extension cost times the given scale
+    /// times ln(L). Each residue adds less than the one before, which fits the long tail of the
+    /// observed indel lengths better than the affine costs. The DP only keeps the length of the
+    /// best gap ending in every cell, so the costs of extending the other gaps are not
+    /// considered and the alignment approximates the optimum under these concave costs.
+    Logarithmic(f64),
+}
+
+impl GapFunction {
+    /// Multiplier of the extension cost for growing a gap to `length` residues, at least two.
+    pub(crate) fn ext_multiplier(&self, length: u32) -> f64 {
+        match self {
+            GapFunction::Affine => 1.0,
+            GapFunction::Logarithmic(scale) => scale * (length as f64 / (length - 1) as f64).ln(),
+        }
+    }
+}
+
+/// Policy for choosing between equally scoring directions in the DP matrices and the
+/// traceback. All the policies except `Random` are fully deterministic, the named direction is
+/// preferred whenever it is among the optimal ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TieBreak {
+    #[default]
+    Random,
+    Match,
+    GapInX,
+    GapInY,
+}
+
 fn rng_len(l: usize) -> usize {
     random::<usize>() % l
 }
 
+fn seeded_rng_len(seed: u64) -> impl FnMut(usize) -> usize + Send {
+    let mut rng = StdRng::seed_from_u64(seed);
+    move |l| rng.gen_range(0..l)
+}
+
+// Every internal node gets its own generator so that the random choices at a node do not
+// depend on the order in which the nodes are processed.
+fn node_seed(seed: u64, node_idx: usize) -> u64 {
+    seed ^ (node_idx as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+fn node_rng(options: &AlignmentOptions, node_idx: usize) -> Box<dyn FnMut(usize) -> usize + Send> {
+    match options.seed {
+        Some(seed) => Box::new(seeded_rng_len(node_seed(seed, node_idx))),
+        None => Box::new(rng_len),
+    }
+}
+
 fn pars_align_w_rng(
     x_info: &[ParsimonySiteInfo],
     x_scoring: &dyn BranchParsimonyCosts,
     y_info: &[ParsimonySiteInfo],
     y_scoring: &dyn BranchParsimonyCosts,
-    rng: fn(usize) -> usize,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
 ) -> (Vec<ParsimonySiteInfo>, Alignment, f64) {
-    let mut pars_mats = ParsimonyAlignmentMatrices::new(x_info.len() + 1, y_info.len() + 1, rng);
+    pars_align_dp(
+        x_info,
+        x_scoring,
+        y_info,
+        y_scoring,
+        rng,
+        &AlignmentOptions::default(),
+        false,
+        &NodeConstraints::default(),
+    )
+}
+
+fn pars_align_w_options(
+    x_info: &[ParsimonySiteInfo],
+    x_scoring: &dyn BranchParsimonyCosts,
+    y_info: &[ParsimonySiteInfo],
+    y_scoring: &dyn BranchParsimonyCosts,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
+    options: &AlignmentOptions,
+) -> Result<(Vec<ParsimonySiteInfo>, Alignment, f64)> {
+    pars_align_w_matches(
+        x_info,
+        x_scoring,
+        y_info,
+        y_scoring,
+        rng,
+        options,
+        &NodeConstraints::default(),
+    )
+}
+
+/// Aligns two profiles so that the sites of the forced DP cells are matched and those of the
+/// forbidden cells are not, see `ParsimonyAlignmentMatrices::fill_matrices_w_matches`. The
+/// anchored alignment is only used without constraints. Fails if the DP matrices need more
+/// than `options.max_memory`.
+fn pars_align_w_matches(
+    x_info: &[ParsimonySiteInfo],
+    x_scoring: &dyn BranchParsimonyCosts,
+    y_info: &[ParsimonySiteInfo],
+    y_scoring: &dyn BranchParsimonyCosts,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
+    options: &AlignmentOptions,
+    constraints: &NodeConstraints,
+) -> Result<(Vec<ParsimonySiteInfo>, Alignment, f64)> {
+    let extra = DpExtraMatrices::new(options, x_scoring.site_categories());
+    check_dp_memory(x_info.len(), y_info.len(), options, extra, false)?;
+    Ok(match options.anchor_length {
+        Some(k) if constraints.is_empty() => {
+            anchors::pars_align_anchored(x_info, x_scoring, y_info, y_scoring, rng, options, k)
+        }
+        _ => pars_align_dp(
+            x_info,
+            x_scoring,
+            y_info,
+            y_scoring,
+            rng,
+            options,
+            options.free_end_gaps,
+            constraints,
+        ),
+    })
+}
+
+// DP matrices of a node with the fill settings of the options. The score rows and the free end
+// gaps are up to the caller, the anchored alignment only has free end gaps at the outer ends.
+fn node_matrices(
+    x_len: usize,
+    y_len: usize,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
+    options: &AlignmentOptions,
+    two_score_rows: bool,
+    free_end_gaps: bool,
+) -> ParsimonyAlignmentMatrices {
+    ParsimonyAlignmentMatrices::new(x_len + 1, y_len + 1, rng)
+        .with_tie_break(options.tie_break)
+        .with_wavefront(options.wavefront)
+        .with_two_score_rows(two_score_rows)
+        .with_band(options.band)
+        .with_x_drop(options.x_drop)
+        .with_free_end_gaps(free_end_gaps)
+        .with_gap_function(options.gap_function)
+}
+
+// Adds a filled node to the metrics of the options.
+fn record_node_dp(
+    options: &AlignmentOptions,
+    pars_mats: &mut ParsimonyAlignmentMatrices,
+    memory: usize,
+) {
+    if let Some(metrics) = &options.metrics {
+        metrics.record_dp(
+            memory,
+            pars_mats.cells_filled,
+            *pars_mats.tie_breaks.get_mut(),
+        );
+    }
+}
+
+// Fails for the options that do not align a node with a single fill of its DP matrices, which
+// `what` needs to trace them back again.
+fn check_single_fill(options: &AlignmentOptions, what: &str) -> Result<()> {
+    if options.anchor_length.is_some() {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "{} cannot be combined with anchors, they split the DP matrices of a node.",
+            what
+        )));
+    }
+    Ok(())
+}
+
+/// Aligns two profiles with a single fill of the DP matrices.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn pars_align_dp(
+    x_info: &[ParsimonySiteInfo],
+    x_scoring: &dyn BranchParsimonyCosts,
+    y_info: &[ParsimonySiteInfo],
+    y_scoring: &dyn BranchParsimonyCosts,
+    rng: impl FnMut(usize) -> usize + Send + 'static,
+    options: &AlignmentOptions,
+    free_end_gaps: bool,
+    constraints: &NodeConstraints,
+) -> (Vec<ParsimonySiteInfo>, Alignment, f64) {
+    let extra = DpExtraMatrices::new(options, x_scoring.site_categories());
+    let mut pars_mats = node_matrices(
+        x_info.len(),
+        y_info.len(),
+        rng,
+        options,
+        node_two_score_rows(x_info.len(), y_info.len(), options, extra),
+        free_end_gaps,
+    );
     debug!(
         "x_scoring: {} {} {}",
         x_scoring.avg_cost(),
@@ -45,29 +544,388 @@ fn pars_align_w_rng(
         y_scoring.gap_open_cost(),
         y_scoring.gap_ext_cost()
     );
-    pars_mats.fill_matrices(x_info, x_scoring, y_info, y_scoring);
-    pars_mats.traceback(x_info, y_info)
+    pars_mats.fill_matrices_w_matches(x_info, x_scoring, y_info, y_scoring, constraints);
+    let (node_info, alignment, score) = pars_mats.traceback(x_info, y_info);
+    let (memory, _) = node_dp_memory(x_info.len(), y_info.len(), options, extra, false);
+    record_node_dp(options, &mut pars_mats, memory);
+    if pars_mats.touches_band_edge(&alignment) {
+        warn!(
+            target: "band",
+            "The alignment of profiles of length {} and {} reaches the edge of the band, a wider band may give a better alignment.",
+            x_info.len(),
+            y_info.len()
+        );
+    }
+    (node_info, alignment, score)
 }
 
-fn pars_align(
-    x_info: &[ParsimonySiteInfo],
-    x_scoring: &dyn BranchParsimonyCosts,
-    y_info: &[ParsimonySiteInfo],
-    y_scoring: &dyn BranchParsimonyCosts,
-) -> (Vec<ParsimonySiteInfo>, Alignment, f64) {
-    pars_align_w_rng(x_info, x_scoring, y_info, y_scoring, rng_len)
+/// Callback that is notified after every processed tree node with the number of processed
+/// nodes and the total number of nodes, e.g. to drive a progress bar.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(usize, usize) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(usize, usize) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    pub(crate) fn report(&self, processed: usize, total: usize) {
+        (self.0)(processed, total)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProgressCallback")
+    }
+}
+
+/// Hooks for following an alignment on the tree, e.g. to report the progress in a GUI or a
+/// pipeline without parsing the log. Only the internal nodes are reported, with the time that
+/// their alignment took. All the methods do nothing by default.
+pub trait AlignmentObserver: Send + Sync {
+    fn node_started(&self, _node: NodeIdx) {}
+
+    fn node_finished(&self, _node: NodeIdx, _score: f64, _elapsed: Duration) {}
+
+    fn run_finished(&self, _score: f64, _elapsed: Duration) {}
+
+    /// Called after `run_finished` with the counters of `AlignmentOptions::metrics`, if set.
+    fn run_metrics(&self, _metrics: &MetricsSnapshot) {}
+}
+
+/// Shared `AlignmentObserver` of the alignment options.
+#[derive(Clone)]
+pub struct ObserverHandle(Arc<dyn AlignmentObserver>);
+
+impl ObserverHandle {
+    pub fn new(observer: impl AlignmentObserver + 'static) -> Self {
+        ObserverHandle(Arc::new(observer))
+    }
+}
+
+impl fmt::Debug for ObserverHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObserverHandle")
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AlignmentOptions {
+    pub recoding: Recoding,
+    /// Seed for the random choices between equally scoring traceback directions, if unset the
+    /// choices are not reproducible between runs.
+    pub seed: Option<u64>,
+    pub tie_break: TieBreak,
+    pub progress: Option<ProgressCallback>,
+    pub observer: Option<ObserverHandle>,
+    /// Fill the alignment matrices of a node by anti-diagonals, computing the cells of a
+    /// diagonal in parallel. Worthwhile for long sequences.
+    pub wavefront: bool,
+    pub score_rows: ScoreRows,
+    /// Only fill the cells within this many diagonals of the main diagonal, widened by the length
+    /// difference of the aligned profiles. Fast for near-identical sequences, but the optimal
+    /// alignment may fall outside of the band.
+    pub band: Option<usize>,
+    /// Stop extending the cells of an anti-diagonal that score more than this above the best cell
+    /// of the diagonal, the matrices are then filled one diagonal at a time. Ignored with a band
+    /// or alignment constraints. Fast for similar sequences, but the optimal alignment may be
+    /// cut off.
+    pub x_drop: Option<f64>,
+    pub data_type: DataType,
+    /// Custom alphabet for the leaf parsimony sets, replaces the DNA or protein sets of the
+    /// data type. Used together with a scoring built for the same alphabet.
+    pub alphabet: Option<Alphabet>,
+    /// Per-site multipliers of the gap penalties of the input sequences.
+    pub gap_profiles: Option<GapProfiles>,
+    /// Leading and trailing gaps against the longer of two aligned profiles cost nothing, so
+    /// that fragments such as partial genes align within the longer sequences.
+    pub free_end_gaps: bool,
+    /// Limit in bytes on the DP matrices of a node. Nodes above it keep only two rows of
+    /// scores if `score_rows` allows it, the alignment on the tree fails if that is still
+    /// too much.
+    pub max_memory: Option<usize>,
+    pub soft_mask: SoftMask,
+    pub sequence_weighting: SequenceWeighting,
+    pub gap_function: GapFunction,
+    /// Align the profiles of every node in chunks between exact anchors of at least this many
+    /// sites, see `anchors::pars_align_anchored`. For long, similar sequences that do not fit
+    /// into a single DP.
+    pub anchor_length: Option<usize>,
+    /// Residue pairs that must be aligned in the MSA of the tree.
+    pub constraints: Option<AnchorConstraints>,
+    /// Regions of the input sequences that are excluded from the scoring.
+    pub region_masks: Option<RegionMasks>,
+    /// Partitions of the input sequences whose residues are scored with the costs of their own
+    /// model, see `ParsimonyCostsWModel::with_partitions`. Used together with a scoring built
+    /// for the same partitions.
+    pub partitions: Option<Partitions>,
+    /// Counters of the DP work and the cost lookups, shared by all the alignments run with
+    /// these options.
+    pub metrics: Option<Arc<RunMetrics>>,
+}
+
+/// Counts the lookup of the costs of a branch in the metrics of the options.
+pub(crate) fn record_cost_lookup(
+    scoring: &dyn ParsimonyCosts,
+    branch_length: f64,
+    options: &AlignmentOptions,
+) {
+    if let Some(metrics) = &options.metrics {
+        metrics.record_cost_lookup(scoring.has_exact_costs(branch_length));
+    }
+}
+
+/// Reports the end of an alignment on the tree to the observer of the options.
+pub(crate) fn report_run_finished(options: &AlignmentOptions, score: f64, elapsed: Duration) {
+    if let Some(observer) = &options.observer {
+        observer.0.run_finished(score, elapsed);
+        if let Some(metrics) = &options.metrics {
+            observer.0.run_metrics(&metrics.snapshot());
+        }
+    }
+}
+
+pub(crate) fn leaf_site_info(
+    record: &Record,
+    sequence_type: &SequenceType,
+    options: &AlignmentOptions,
+) -> Vec<ParsimonySiteInfo> {
+    let masked: Vec<bool> = record.seq().iter().map(u8::is_ascii_lowercase).collect();
+    let recoded = match options.recoding {
+        Recoding::None => None,
+        recoding => Some(recoding.recode_record(record)),
+    };
+    let record = recoded.as_ref().unwrap_or(record);
+    let pars_sets = match &options.alphabet {
+        Some(alphabet) => alphabet.parsimony_sets(record),
+        None => get_parsimony_sets(record, sequence_type),
+    };
+    let mut leaf_info: Vec<ParsimonySiteInfo> = pars_sets
+        .into_iter()
+        .map(|set| ParsimonySiteInfo::from_set(set, SiteFlag::NoGap))
+        .collect();
+    if let Some(weights) = options
+        .gap_profiles
+        .as_ref()
+        .and_then(|profiles| profiles.weights(record.id()))
+        .filter(|weights| weights.len() == leaf_info.len())
+    {
+        for (site, &weight) in leaf_info.iter_mut().zip(weights) {
+            site.gap_weight = weight;
+        }
+    }
+    let ambiguous = leaf_info.iter().filter(|site| site.set.len() > 1).count();
+    if ambiguous > 0 {
+        warn!(
+            target: "ambiguity",
+            "Sequence {} has {} ambiguous character(s), these are scored as the set of possible residues.",
+            record.id(),
+            ambiguous
+        );
+    }
+    apply_soft_mask(&mut leaf_info, &masked, sequence_type, options);
+    if let Some(masks) = &options.region_masks {
+        let any = any_set(sequence_type, options);
+        let masked = masks.masked_sites(record.id(), leaf_info.len());
+        for (site, _) in leaf_info
+            .iter_mut()
+            .zip(masked)
+            .filter(|(_, masked)| *masked)
+        {
+            site.set = any.clone();
+            site.gap_weight *= masks.gap_weight();
+        }
+    }
+    if let Some(partitions) = &options.partitions {
+        let site_partitions = partitions.site_partitions(leaf_info.len());
+        for (site, partition) in leaf_info.iter_mut().zip(site_partitions) {
+            site.site_category = Some(partition);
+        }
+    }
+    leaf_info
+}
+
+/// Set of all the characters, which matches any character at no cost.
+fn any_set(sequence_type: &SequenceType, options: &AlignmentOptions) -> ParsimonySet {
+    match (&options.alphabet, sequence_type) {
+        (Some(alphabet), _) => alphabet.symbols().iter().copied().collect(),
+        (None, SequenceType::DNA) => get_dna_set(&b'N'),
+        (None, SequenceType::Protein) => get_protein_set(&b'X'),
+    }
+}
+
+fn apply_soft_mask(
+    leaf_info: &mut [ParsimonySiteInfo],
+    masked: &[bool],
+    sequence_type: &SequenceType,
+    options: &AlignmentOptions,
+) {
+    let masked_sites = leaf_info
+        .iter_mut()
+        .zip(masked)
+        .filter(|(_, &masked)| masked)
+        .map(|(site, _)| site);
+    match options.soft_mask {
+        SoftMask::Keep => {}
+        SoftMask::Ignore => {
+            let any = any_set(sequence_type, options);
+            for site in masked_sites {
+                site.set = any.clone();
+                site.gap_weight = 0.0;
+            }
+        }
+        SoftMask::Downweight(weight) => {
+            for site in masked_sites {
+                site.gap_weight *= weight;
+            }
+        }
+    }
+}
+
+pub(crate) fn child_info<'a>(
+    tree: &Tree,
+    child: NodeIdx,
+    internal_info: &'a [Vec<ParsimonySiteInfo>],
+    leaf_info: &'a [Vec<ParsimonySiteInfo>],
+) -> (&'a [ParsimonySiteInfo], f64) {
+    match child {
+        Int(idx) => (&internal_info[idx], tree.internals[idx].blen),
+        Leaf(idx) => (&leaf_info[idx], tree.leaves[idx].blen),
+    }
+}
+
+/// Result of aligning the sequences on the tree, the pairwise alignments of the progressive
+/// alignment together with their scores.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AlignmentResult {
+    /// Alignment of the profiles of the two children of every internal node, indexed like the
+    /// internal nodes of the tree.
+    #[cfg_attr(feature = "serde", serde(with = "serialization::alignments"))]
+    pub alignments: Vec<Alignment>,
+    /// Parsimony score of the alignment at every internal node.
+    pub scores: Vec<f64>,
+    /// Order in which the nodes were processed, every node comes after its children.
+    #[cfg_attr(feature = "serde", serde(with = "serialization::nodes"))]
+    pub order: Vec<NodeIdx>,
+}
+
+impl AlignmentResult {
+    pub(crate) fn new(alignments: Vec<Alignment>, scores: Vec<f64>, order: Vec<NodeIdx>) -> Self {
+        AlignmentResult {
+            alignments,
+            scores,
+            order,
+        }
+    }
+
+    /// Total parsimony score, the sum of the scores of all the internal nodes.
+    pub fn score(&self) -> f64 {
+        self.scores.iter().sum()
+    }
+
+    /// MSA of the sequences in `info`, which must be the sequences and the tree that were
+    /// aligned. The rows are in the order of the leaves of the tree.
+    pub fn msa(&self, info: &PhyloInfo) -> Vec<Record> {
+        compile_alignment_representation(info, &self.alignments, None::<NodeIdx>)
+    }
+
+    /// MSA of the sequences of the clade below the internal node `node` as it was aligned at
+    /// that node, before the alignments further up the tree added gap columns to it.
+    pub fn clade_msa(&self, info: &PhyloInfo, node: usize) -> Vec<Record> {
+        compile_alignment_representation(info, &self.alignments, Some(Int(node)))
+    }
 }
 
 pub fn pars_align_on_tree(
     scoring: &dyn ParsimonyCosts,
     info: &PhyloInfo,
-) -> (Vec<Alignment>, Vec<f64>) {
+) -> Result<AlignmentResult> {
+    pars_align_on_tree_w_options(scoring, info, &AlignmentOptions::default())
+}
+
+pub fn pars_align_on_tree_w_options(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<AlignmentResult> {
+    align_info_on_tree(scoring, info, options, None)
+}
+
+/// Progressive alignment of the sequences of `info` on its tree, see `align_on_tree`.
+pub(crate) fn align_info_on_tree(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    store: Option<&mut dyn NodeStore>,
+) -> Result<AlignmentResult> {
+    let sequence_type = &options.data_type.sequence_type(&info.sequences);
+    let constraints = options
+        .constraints
+        .as_ref()
+        .map(|constraints| TreeConstraints::new(constraints, info))
+        .transpose()?;
+    align_on_tree(
+        scoring,
+        &info.tree,
+        |idx| leaf_site_info(&info.sequences[idx], sequence_type, options),
+        constraints.as_ref().map(|constraints| (constraints, info)),
+        options,
+        store,
+    )
+}
+
+/// Results of the internal nodes kept outside of `align_on_tree`, e.g. the checkpoint of an
+/// interrupted run. A node that `load` returns is not aligned again, every node that is aligned
+/// is handed to `store`.
+pub(crate) trait NodeStore {
+    fn load(
+        &mut self,
+        idx: usize,
+        x_info: &[ParsimonySiteInfo],
+        y_info: &[ParsimonySiteInfo],
+    ) -> Option<(Vec<ParsimonySiteInfo>, Alignment, f64)>;
+
+    fn store(
+        &mut self,
+        idx: usize,
+        info: &[ParsimonySiteInfo],
+        alignment: &Alignment,
+        score: f64,
+    ) -> Result<()>;
+}
+
+/// Progressive alignment on the tree with the leaf profiles given by `leaf_profile`, which is
+/// called once per leaf when the postorder reaches it. The internal nodes found in `store` are
+/// taken from it.
+pub(crate) fn align_on_tree(
+    scoring: &dyn ParsimonyCosts,
+    tree: &Tree,
+    mut leaf_profile: impl FnMut(usize) -> Vec<ParsimonySiteInfo>,
+    constraints: Option<(&TreeConstraints, &PhyloInfo)>,
+    options: &AlignmentOptions,
+    mut store: Option<&mut dyn NodeStore>,
+) -> Result<AlignmentResult> {
     info!("Starting the IndelMAP alignment.");
+    if options.recoding != Recoding::None {
+        info!(
+            "Sequences are recoded with the {:?} scheme before scoring.",
+            options.recoding
+        );
+    }
+    match options.tie_break {
+        TieBreak::Random => {
+            if let Some(seed) = options.seed {
+                info!("Using seed {} for the traceback choices.", seed);
+            }
+        }
+        tie_break => info!("Using the deterministic {:?} tie-breaking.", tie_break),
+    }
 
-    let tree = &info.tree;
-    let sequences = &info.sequences;
-    let sequence_type = &get_sequence_type(&info.sequences);
+    let start = Instant::now();
     let order = &tree.postorder;
+    let factors = child_cost_factors(tree, options.sequence_weighting);
 
     debug_assert_eq!(tree.internals.len() + tree.leaves.len(), order.len());
 
@@ -76,7 +934,7 @@ pub fn pars_align_on_tree(
     let mut alignments = vec![Alignment::empty(); tree.internals.len()];
     let mut scores = vec![0.0; tree.internals.len()];
 
-    for &node_idx in order {
+    for (processed, &node_idx) in order.iter().enumerate() {
         info!(
             "Processing {}{}.",
             node_idx,
@@ -84,48 +942,98 @@ pub fn pars_align_on_tree(
         );
         match node_idx {
             Int(idx) => {
-                let (x_info, x_branch) = match tree.internals[idx].children[0] {
-                    Int(idx) => (&internal_info[idx], tree.internals[idx].blen),
-                    Leaf(idx) => (&leaf_info[idx], tree.leaves[idx].blen),
-                };
-                debug!("x_info: {:?}", x_info);
-                let (y_info, y_branch) = match tree.internals[idx].children[1] {
-                    Int(idx) => (&internal_info[idx], tree.internals[idx].blen),
-                    Leaf(idx) => (&leaf_info[idx], tree.leaves[idx].blen),
-                };
-                debug!("y_info: {:?}", y_info);
-                info!(
-                    "Aligning sequences at nodes: \n1. {}{} with branch length {} \n2. {}{} with branch length {}",
+                let (x_info, x_branch) = child_info(
+                    tree,
                     tree.internals[idx].children[0],
-                    tree.get_node_id_string(&tree.internals[idx].children[0]),
-                    x_branch,
-                    tree.internals[idx].children[1],
-                    tree.get_node_id_string(&tree.internals[idx].children[1]),
-                    y_branch
+                    &internal_info,
+                    &leaf_info,
                 );
-                let (info, alignment, score) = pars_align(
-                    x_info,
-                    scoring.get_branch_costs(x_branch),
-                    y_info,
-                    scoring.get_branch_costs(y_branch),
+                debug!("x_info: {:?}", x_info);
+                let (y_info, y_branch) = child_info(
+                    tree,
+                    tree.internals[idx].children[1],
+                    &internal_info,
+                    &leaf_info,
                 );
-                internal_info[idx] = info;
-                alignments[idx] = alignment;
-                scores[idx] = score;
-                info!("Alignment complete with score {}.\n", score);
+                debug!("y_info: {:?}", y_info);
+                if let Some((info, alignment, score)) = store
+                    .as_mut()
+                    .and_then(|store| store.load(idx, x_info, y_info))
+                {
+                    info!("Resumed the alignment with score {}.\n", score);
+                    internal_info[idx] = info;
+                    alignments[idx] = alignment;
+                    scores[idx] = score;
+                } else {
+                    info!(
+                        "Aligning sequences at nodes: \n1. {}{} with branch length {} \n2. {}{} with branch length {}",
+                        tree.internals[idx].children[0],
+                        tree.get_node_id_string(&tree.internals[idx].children[0]),
+                        x_branch,
+                        tree.internals[idx].children[1],
+                        tree.get_node_id_string(&tree.internals[idx].children[1]),
+                        y_branch
+                    );
+                    record_cost_lookup(scoring, x_branch, options);
+                    record_cost_lookup(scoring, y_branch, options);
+                    let (x_scoring, y_scoring) = (
+                        &WeightedBranchCosts::new(
+                            scoring.get_branch_costs(x_branch),
+                            factors[idx].0,
+                        ),
+                        &WeightedBranchCosts::new(
+                            scoring.get_branch_costs(y_branch),
+                            factors[idx].1,
+                        ),
+                    );
+                    let node_constraints = match constraints {
+                        Some((constraints, info)) => {
+                            constraints.node_constraints(info, idx, x_info, y_info, &|node| {
+                                &alignments[node]
+                            })?
+                        }
+                        None => NodeConstraints::default(),
+                    };
+                    let node_start = Instant::now();
+                    if let Some(observer) = &options.observer {
+                        observer.0.node_started(node_idx);
+                    }
+                    let (info, alignment, score) = pars_align_w_matches(
+                        x_info,
+                        x_scoring,
+                        y_info,
+                        y_scoring,
+                        node_rng(options, idx),
+                        options,
+                        &node_constraints,
+                    )?;
+                    if let Some(store) = store.as_mut() {
+                        store.store(idx, &info, &alignment, score)?;
+                    }
+                    internal_info[idx] = info;
+                    alignments[idx] = alignment;
+                    scores[idx] = score;
+                    if let Some(observer) = &options.observer {
+                        observer
+                            .0
+                            .node_finished(node_idx, score, node_start.elapsed());
+                    }
+                    info!("Alignment complete with score {}.\n", score);
+                }
             }
             Leaf(idx) => {
-                let pars_sets = get_parsimony_sets(&sequences[idx], sequence_type);
-                leaf_info[idx] = pars_sets
-                    .into_iter()
-                    .map(ParsimonySiteInfo::new_leaf)
-                    .collect();
+                leaf_info[idx] = leaf_profile(idx);
                 info!("Processed leaf node.\n");
             }
         }
+        if let Some(progress) = &options.progress {
+            progress.report(processed + 1, order.len());
+        }
     }
     info!("Finished IndelMAP alignment.");
-    (alignments, scores)
+    let result = AlignmentResult::new(alignments, scores, order.clone());
+    report_run_finished(options, result.score(), start.elapsed());
+    Ok(result)
 }
 
 #[cfg(test)]
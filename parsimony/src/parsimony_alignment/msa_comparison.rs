@@ -0,0 +1,228 @@
+use bio::io::fasta::Record;
+use std::collections::{HashMap, HashSet};
+
+use super::msa_scoring::GAP;
+use crate::{ParsimonyError, Result};
+
+/// Agreement of an MSA with a reference MSA of the same sequences.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MsaComparison {
+    /// Fraction of the residue pairs aligned in the reference that are also aligned in the MSA.
+    pub sum_of_pairs: f64,
+    /// Fraction of the reference columns with at least two residues that appear unchanged as a
+    /// column of the MSA.
+    pub total_column: f64,
+}
+
+/// Column of every residue of the row, in the order of the residues.
+fn residue_columns(row: &[u8]) -> Vec<usize> {
+    row.iter()
+        .enumerate()
+        .filter(|(_, &c)| c != GAP)
+        .map(|(column, _)| column)
+        .collect()
+}
+
+fn ungapped(row: &[u8]) -> Vec<u8> {
+    row.iter()
+        .filter(|&&c| c != GAP)
+        .map(u8::to_ascii_uppercase)
+        .collect()
+}
+
+/// Compares an MSA to a reference MSA with the sum-of-pairs and total-column scores used by
+/// benchmarks such as BAliBASE. The rows are matched by their ids, the order of the rows does not
+/// matter, and both MSAs must contain the same sequences up to gaps and case.
+pub fn compare_msas(msa: &[Record], reference: &[Record]) -> Result<MsaComparison> {
+    let rows: HashMap<&str, &Record> = msa.iter().map(|record| (record.id(), record)).collect();
+    if rows.len() != msa.len() || msa.len() != reference.len() {
+        return Err(ParsimonyError::SequenceMismatch(
+            "The MSA and the reference must contain the same sequences once each.".to_string(),
+        ));
+    }
+    let mut msa_columns = Vec::with_capacity(reference.len());
+    for record in reference {
+        let Some(row) = rows.get(record.id()) else {
+            return Err(ParsimonyError::SequenceMismatch(format!(
+                "Sequence {} of the reference is not in the MSA.",
+                record.id()
+            )));
+        };
+        if ungapped(row.seq()) != ungapped(record.seq()) {
+            return Err(ParsimonyError::SequenceMismatch(format!(
+                "Sequence {} differs between the MSA and the reference.",
+                record.id()
+            )));
+        }
+        msa_columns.push(residue_columns(row.seq()));
+    }
+
+    // the residues of every reference column as (row, MSA column) pairs
+    let length = reference.first().map_or(0, |record| record.seq().len());
+    let mut columns = vec![Vec::<(usize, usize)>::new(); length];
+    for (row, record) in reference.iter().enumerate() {
+        if record.seq().len() != length {
+            return Err(ParsimonyError::InvalidAlignment(
+                "The rows of the reference have different lengths, it is not an MSA.".to_string(),
+            ));
+        }
+        for (residue, column) in residue_columns(record.seq()).into_iter().enumerate() {
+            columns[column].push((row, msa_columns[row][residue]));
+        }
+    }
+    let mut residues_in_msa_column = HashMap::<usize, usize>::new();
+    for row in &msa_columns {
+        for &column in row {
+            *residues_in_msa_column.entry(column).or_default() += 1;
+        }
+    }
+
+    let (mut pairs, mut shared_pairs) = (0, 0);
+    let (mut scored_columns, mut shared_columns) = (0, 0);
+    for residues in columns.iter().filter(|residues| residues.len() > 1) {
+        let mut by_msa_column = HashMap::<usize, usize>::new();
+        for &(_, column) in residues {
+            *by_msa_column.entry(column).or_default() += 1;
+        }
+        pairs += residues.len() * (residues.len() - 1) / 2;
+        shared_pairs += by_msa_column
+            .values()
+            .map(|count| count * (count - 1) / 2)
+            .sum::<usize>();
+        scored_columns += 1;
+        let (&column, _) = by_msa_column.iter().next().unwrap();
+        if by_msa_column.len() == 1 && residues_in_msa_column[&column] == residues.len() {
+            shared_columns += 1;
+        }
+    }
+    if scored_columns == 0 {
+        return Err(ParsimonyError::InvalidAlignment(
+            "The reference has no column with two or more residues to compare to.".to_string(),
+        ));
+    }
+    Ok(MsaComparison {
+        sum_of_pairs: shared_pairs as f64 / pairs as f64,
+        total_column: shared_columns as f64 / scored_columns as f64,
+    })
+}
+
+/// Residues of every column of the MSA as sorted (row, residue) pairs, the rows are numbered by
+/// `rows` and the residues by their position in the row.
+fn column_residues(msa: &[Record], rows: &[usize]) -> Result<Vec<Vec<(usize, usize)>>> {
+    let length = msa.first().map_or(0, |record| record.seq().len());
+    let mut columns = vec![Vec::<(usize, usize)>::new(); length];
+    for (record, &row) in msa.iter().zip(rows) {
+        if record.seq().len() != length {
+            return Err(ParsimonyError::InvalidAlignment(
+                "The rows have different lengths, it is not an MSA.".to_string(),
+            ));
+        }
+        for (residue, column) in residue_columns(record.seq()).into_iter().enumerate() {
+            columns[column].push((row, residue));
+        }
+    }
+    columns
+        .iter_mut()
+        .for_each(|residues| residues.sort_unstable());
+    Ok(columns)
+}
+
+/// Fraction of the sampled MSAs in which every column of the MSA appears unchanged, i.e. with
+/// the same residues and no others. Columns that most samples reproduce are stable, the others
+/// depend on arbitrary choices between co-optimal alignments. The rows of the samples are
+/// matched to the rows of the MSA by their ids.
+pub fn column_confidence(msa: &[Record], samples: &[Vec<Record>]) -> Result<Vec<f64>> {
+    if samples.is_empty() {
+        return Err(ParsimonyError::InvalidParameters(
+            "The column confidence needs at least one sampled MSA.".to_string(),
+        ));
+    }
+    let rows: HashMap<&str, usize> = msa
+        .iter()
+        .enumerate()
+        .map(|(row, record)| (record.id(), row))
+        .collect();
+    let columns = column_residues(msa, &(0..msa.len()).collect::<Vec<_>>())?;
+    let mut reproduced = vec![0; columns.len()];
+    for sample in samples {
+        let order = sample
+            .iter()
+            .map(|record| rows.get(record.id()).copied())
+            .collect::<Option<Vec<usize>>>();
+        let Some(order) = order.filter(|order| order.len() == msa.len()) else {
+            return Err(ParsimonyError::SequenceMismatch(
+                "The sampled MSAs must contain the sequences of the MSA once each.".to_string(),
+            ));
+        };
+        let sample_columns: HashSet<Vec<(usize, usize)>> =
+            column_residues(sample, &order)?.into_iter().collect();
+        for (count, residues) in reproduced.iter_mut().zip(&columns) {
+            if sample_columns.contains(residues) {
+                *count += 1;
+            }
+        }
+    }
+    Ok(reproduced
+        .into_iter()
+        .map(|count| count as f64 / samples.len() as f64)
+        .collect())
+}
+
+#[cfg(test)]
+mod msa_comparison_tests {
+    use super::{column_confidence, compare_msas};
+    use bio::io::fasta::Record;
+
+    fn reference() -> Vec<Record> {
+        vec![
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"AAC-G"),
+            Record::with_attrs("C", None, b"A-CTG"),
+        ]
+    }
+
+    #[test]
+    fn compare_to_itself() {
+        let comparison = compare_msas(&reference(), &reference()).unwrap();
+        assert_eq!(comparison.sum_of_pairs, 1.0);
+        assert_eq!(comparison.total_column, 1.0);
+    }
+
+    #[test]
+    fn compare_shifted_rows() {
+        let msa = [
+            Record::with_attrs("C", None, b"AC-TG"),
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"AAC-G"),
+        ];
+        // the C of the third row moves to the second column, which loses two of the nine
+        // reference pairs and changes the second and third column
+        let comparison = compare_msas(&msa, &reference()).unwrap();
+        assert_eq!(comparison.sum_of_pairs, 7.0 / 9.0);
+        assert_eq!(comparison.total_column, 3.0 / 5.0);
+    }
+
+    #[test]
+    fn compare_different_sequences() {
+        let mut msa = reference();
+        msa[1] = Record::with_attrs("B", None, b"AAC-T");
+        assert!(compare_msas(&msa, &reference()).is_err());
+        assert!(compare_msas(&reference()[1..], &reference()).is_err());
+    }
+
+    #[test]
+    fn confidence_of_columns() {
+        let shifted = vec![
+            Record::with_attrs("C", None, b"AC-TG"),
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"AAC-G"),
+        ];
+        assert_eq!(
+            column_confidence(&reference(), &[reference(), shifted]).unwrap(),
+            vec![1.0, 0.5, 0.5, 1.0, 1.0]
+        );
+        assert!(column_confidence(&reference(), &[]).is_err());
+        assert!(column_confidence(&reference(), &[reference()[1..].to_vec()]).is_err());
+    }
+}
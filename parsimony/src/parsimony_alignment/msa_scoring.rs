@@ -0,0 +1,524 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf};
+use std::collections::{HashMap, HashSet};
+
+use super::guide_tree::internal_node_label;
+use super::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
+use super::parsimony_info::{ParsimonySiteInfo, SiteFlag};
+use super::parsimony_matrices::ParsimonyAlignmentMatrices;
+use super::sequence_weights::{child_cost_factors, WeightedBranchCosts};
+use super::Direction::{self, GapInX, GapInY, Matc};
+use super::{check_dp_memory, child_info, leaf_site_info, AlignmentOptions, DpExtraMatrices};
+use crate::{ParsimonyError, Result};
+
+pub(crate) const GAP: u8 = b'-';
+
+/// Site infos of all the nodes for an MSA on the tree, together with the MSA column of every
+/// site.
+pub(crate) struct MsaOnTree {
+    pub(crate) leaf_info: Vec<Vec<ParsimonySiteInfo>>,
+    pub(crate) leaf_columns: Vec<Vec<usize>>,
+    pub(crate) internal_info: Vec<Vec<ParsimonySiteInfo>>,
+    pub(crate) internal_columns: Vec<Vec<usize>>,
+    pub(crate) alignments: Vec<Alignment>,
+    pub(crate) scores: Vec<f64>,
+    /// Cost of every site of the internal nodes, empty unless requested from `msa_on_tree`.
+    pub(crate) site_costs: Vec<Vec<f64>>,
+    pub(crate) columns: usize,
+}
+
+/// Scores the MSA of `info` on its tree, the cost of every site is only traced back with
+/// `with_site_costs`.
+pub(crate) fn msa_on_tree(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    with_site_costs: bool,
+) -> Result<MsaOnTree> {
+    let tree = &info.tree;
+    let sequences = &info.sequences;
+    let columns = sequences.first().map_or(0, |record| record.seq().len());
+    if let Some(record) = sequences.iter().find(|r| r.seq().len() != columns) {
+        return Err(ParsimonyError::InvalidAlignment(format!(
+            "Sequence {} has length {} instead of {}, the sequences are not aligned.",
+            record.id(),
+            record.seq().len(),
+            columns
+        )));
+    }
+    let sequence_type = options.data_type.sequence_type(sequences);
+    let mut leaf_info = Vec::with_capacity(sequences.len());
+    let mut leaf_columns = Vec::with_capacity(sequences.len());
+    for record in sequences {
+        let (site_columns, residues): (Vec<usize>, Vec<u8>) = record
+            .seq()
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c != GAP)
+            .map(|(column, &c)| (column, c))
+            .unzip();
+        let ungapped = Record::with_attrs(record.id(), record.desc(), &residues);
+        leaf_info.push(leaf_site_info(&ungapped, &sequence_type, options));
+        leaf_columns.push(site_columns);
+    }
+
+    let mut internal_info = vec![Vec::<ParsimonySiteInfo>::new(); tree.internals.len()];
+    let mut internal_columns = vec![Vec::<usize>::new(); tree.internals.len()];
+    let mut alignments = vec![Alignment::empty(); tree.internals.len()];
+    let mut scores = vec![0.0; tree.internals.len()];
+    let mut site_costs = vec![Vec::<f64>::new(); tree.internals.len()];
+    let factors = child_cost_factors(tree, options.sequence_weighting);
+    for &node_idx in &tree.postorder {
+        let idx = match node_idx {
+            Int(idx) => idx,
+            Leaf(_) => continue,
+        };
+        let children = &tree.internals[idx].children;
+        let (x_info, x_branch) = child_info(tree, children[0], &internal_info, &leaf_info);
+        let (y_info, y_branch) = child_info(tree, children[1], &internal_info, &leaf_info);
+        let x_columns = node_columns(children[0], &internal_columns, &leaf_columns);
+        let y_columns = node_columns(children[1], &internal_columns, &leaf_columns);
+        let path = msa_path(x_info, x_columns, y_info, y_columns);
+
+        let x_scor = WeightedBranchCosts::new(scoring.get_branch_costs(x_branch), factors[idx].0);
+        let y_scor = WeightedBranchCosts::new(scoring.get_branch_costs(y_branch), factors[idx].1);
+        let extra = DpExtraMatrices::new(options, x_scor.site_categories());
+        check_dp_memory(x_info.len(), y_info.len(), options, extra, true)?;
+        let mut pars_mats =
+            ParsimonyAlignmentMatrices::new(x_info.len() + 1, y_info.len() + 1, |_| 0)
+                .with_free_end_gaps(options.free_end_gaps)
+                .with_gap_function(options.gap_function);
+        pars_mats.fill_matrices_constrained(
+            x_info,
+            &x_scor,
+            y_info,
+            &y_scor,
+            Some(&|state: Direction, i: usize, j: usize| path.contains(&(state as u8, i, j))),
+        );
+        let (node_info, alignment, score) = pars_mats.traceback(x_info, y_info);
+        if !score.is_finite() {
+            return Err(ParsimonyError::InvalidAlignment(format!(
+                "The MSA cannot be scored at {}{}.",
+                node_idx,
+                tree.get_node_id_string(&node_idx)
+            )));
+        }
+        internal_columns[idx] = alignment
+            .map_x
+            .iter()
+            .zip(alignment.map_y.iter())
+            .map(|(x, y)| match (x, y) {
+                (Some(i), _) => x_columns[*i],
+                (None, Some(j)) => y_columns[*j],
+                (None, None) => unreachable!(),
+            })
+            .collect();
+        if with_site_costs {
+            site_costs[idx] = pars_mats.site_costs(x_info, y_info, &alignment);
+        }
+        internal_info[idx] = node_info;
+        alignments[idx] = alignment;
+        scores[idx] = score;
+    }
+    Ok(MsaOnTree {
+        leaf_info,
+        leaf_columns,
+        internal_info,
+        internal_columns,
+        alignments,
+        scores,
+        site_costs,
+        columns,
+    })
+}
+
+fn node_columns<'a>(
+    node_idx: NodeIdx,
+    internal_columns: &'a [Vec<usize>],
+    leaf_columns: &'a [Vec<usize>],
+) -> &'a [usize] {
+    match node_idx {
+        Int(idx) => &internal_columns[idx],
+        Leaf(idx) => &leaf_columns[idx],
+    }
+}
+
+/// The cells, with their states, that the alignment of the two children given by the MSA goes
+/// through. Fixed gap sites are skipped, the matrices handle those the same way for every
+/// alignment.
+fn msa_path(
+    x_info: &[ParsimonySiteInfo],
+    x_columns: &[usize],
+    y_info: &[ParsimonySiteInfo],
+    y_columns: &[usize],
+) -> HashSet<(u8, usize, usize)> {
+    let sites_by_column = |info: &[ParsimonySiteInfo], columns: &[usize]| {
+        info.iter()
+            .zip(columns)
+            .enumerate()
+            .filter(|(_, (site, _))| !site.is_fixed())
+            .map(|(site, (_, &column))| (column, site))
+            .collect::<HashMap<usize, usize>>()
+    };
+    let x_sites = sites_by_column(x_info, x_columns);
+    let y_sites = sites_by_column(y_info, y_columns);
+    let mut columns: Vec<usize> = x_sites.keys().chain(y_sites.keys()).copied().collect();
+    columns.sort_unstable();
+    columns.dedup();
+
+    let (mut i, mut j) = (0, 0);
+    let mut path = HashSet::with_capacity(columns.len());
+    for column in columns {
+        let state = match (x_sites.get(&column), y_sites.get(&column)) {
+            (Some(&x), Some(&y)) => {
+                (i, j) = (x + 1, y + 1);
+                Matc
+            }
+            (Some(&x), None) => {
+                i = x + 1;
+                GapInY
+            }
+            (None, Some(&y)) => {
+                j = y + 1;
+                GapInX
+            }
+            (None, None) => unreachable!(),
+        };
+        path.insert((state as u8, i, j));
+    }
+    path
+}
+
+/// Scores an existing MSA on the tree. The sequences in `info` are the rows of the MSA, the
+/// alignment at every internal node is the one induced by the MSA and it is scored in the same
+/// way as the alignments found by `pars_align_on_tree`.
+pub fn pars_score_msa(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<(Vec<Alignment>, Vec<f64>)> {
+    info!("Scoring the MSA on the tree.");
+    let msa = msa_on_tree(scoring, info, options, false)?;
+    Ok((msa.alignments, msa.scores))
+}
+
+/// Scores of the columns of an existing MSA on the tree, the cost of every column summed over
+/// all the internal nodes. The alignment at every node is scored as in `pars_score_msa`, so the
+/// column scores add up to the total score of the MSA.
+pub fn pars_column_scores(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<Vec<f64>> {
+    info!("Scoring the columns of the MSA on the tree.");
+    let msa = msa_on_tree(scoring, info, options, true)?;
+    let mut column_scores = vec![0.0; msa.columns];
+    for (costs, columns) in msa.site_costs.iter().zip(msa.internal_columns.iter()) {
+        for (cost, &column) in costs.iter().zip(columns) {
+            column_scores[column] += cost;
+        }
+    }
+    Ok(column_scores)
+}
+
+/// Step of the path that the alignment at an internal node takes through the DP matrices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TracebackStep {
+    /// Cell the step ends in, the number of sites of the first and of the second child that
+    /// are aligned after the step.
+    pub cell: (usize, usize),
+    /// Matrix of the step: M for two matched sites, X for a site of the first child against a
+    /// gap, Y for a site of the second child against a gap and F for a fixed gap site, which
+    /// the matrices skip.
+    pub state: char,
+    /// Whether the step opens a new gap, false for extensions and for all the other states.
+    pub gap_open: bool,
+    /// Cost of the step, the difference between the scores of its cell and of the previous one.
+    pub cost: f64,
+    /// MSA column of the site created by the step.
+    pub column: usize,
+}
+
+/// Paths through the DP matrices of the alignments at all the internal nodes for an existing
+/// MSA on the tree, indexed like the internal nodes. The steps run from the top left to the
+/// bottom right cell and their costs add up to the score of the node, see `pars_score_msa`.
+pub fn pars_traceback_paths(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<Vec<Vec<TracebackStep>>> {
+    info!("Recording the traceback paths of the MSA on the tree.");
+    let tree = &info.tree;
+    let msa = msa_on_tree(scoring, info, options, true)?;
+    let mut paths = vec![Vec::new(); tree.internals.len()];
+    for (idx, path) in paths.iter_mut().enumerate() {
+        let children = &tree.internals[idx].children;
+        let (x_info, _) = child_info(tree, children[0], &msa.internal_info, &msa.leaf_info);
+        let (y_info, _) = child_info(tree, children[1], &msa.internal_info, &msa.leaf_info);
+        let alignment = &msa.alignments[idx];
+        let (mut i, mut j) = (0, 0);
+        for (site, (x, y)) in alignment.map_x.iter().zip(&alignment.map_y).enumerate() {
+            let state = match (x, y) {
+                (Some(_), Some(_)) => {
+                    (i, j) = (i + 1, j + 1);
+                    'M'
+                }
+                (Some(_), None) => {
+                    i += 1;
+                    if x_info[i - 1].is_fixed() {
+                        'F'
+                    } else {
+                        'X'
+                    }
+                }
+                (None, Some(_)) => {
+                    j += 1;
+                    if y_info[j - 1].is_fixed() {
+                        'F'
+                    } else {
+                        'Y'
+                    }
+                }
+                (None, None) => unreachable!(),
+            };
+            path.push(TracebackStep {
+                cell: (i, j),
+                state,
+                gap_open: msa.internal_info[idx][site].flag == SiteFlag::GapOpen,
+                cost: msa.site_costs[idx][site],
+                column: msa.internal_columns[idx][site],
+            });
+        }
+    }
+    Ok(paths)
+}
+
+/// Reconstructs the ancestral sequences of all the internal nodes for an existing MSA on the
+/// tree, aligned to the MSA. Sites where the node has a fixed gap are gaps, sites with a
+/// possible gap are gaps when the parent has a gap in that column (at the root they are always
+/// gaps). Residues are taken from the parent where the parsimony set allows it, otherwise the
+/// smallest residue of the set is used.
+pub fn pars_ancestors_msa(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<Vec<Record>> {
+    info!("Reconstructing the ancestral sequences for the MSA.");
+    let tree = &info.tree;
+    let msa = msa_on_tree(scoring, info, options, false)?;
+    let mut parents = vec![None; tree.internals.len()];
+    for (idx, node) in tree.internals.iter().enumerate() {
+        for child in &node.children {
+            if let Int(child) = child {
+                parents[*child] = Some(idx);
+            }
+        }
+    }
+    let mut ancestors = vec![Vec::<u8>::new(); tree.internals.len()];
+    for &node_idx in tree.postorder.iter().rev() {
+        let idx = match node_idx {
+            Int(idx) => idx,
+            Leaf(_) => continue,
+        };
+        let mut sequence = vec![GAP; msa.columns];
+        for (site, &column) in msa.internal_info[idx]
+            .iter()
+            .zip(msa.internal_columns[idx].iter())
+        {
+            let parent_char = parents[idx].map(|parent: usize| ancestors[parent][column]);
+            sequence[column] = ancestral_char(site, parent_char);
+        }
+        ancestors[idx] = sequence;
+    }
+    Ok(ancestors
+        .iter()
+        .enumerate()
+        .map(|(idx, sequence)| Record::with_attrs(&internal_node_label(idx), None, sequence))
+        .collect())
+}
+
+/// Inferred presence of a gap at every internal node in every column of the MSA, true for a gap.
+/// The gaps follow the flags of the node as in `pars_ancestors_msa`, and the columns without
+/// residues below the node are gaps as well.
+pub fn pars_ancestral_gaps(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<Vec<Vec<bool>>> {
+    Ok(pars_ancestors_msa(scoring, info, options)?
+        .iter()
+        .map(|ancestor| ancestor.seq().iter().map(|&c| c == GAP).collect())
+        .collect())
+}
+
+fn ancestral_char(site: &ParsimonySiteInfo, parent_char: Option<u8>) -> u8 {
+    if site.is_fixed() || (site.is_possible() && parent_char.map_or(true, |c| c == GAP)) {
+        return GAP;
+    }
+    match parent_char {
+        Some(c) if site.set.contains(c) => c,
+        _ => site.set.iter().min().unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod msa_scoring_tests {
+    use super::pars_traceback_paths;
+    use super::{pars_ancestors_msa, pars_ancestral_gaps, pars_column_scores, pars_score_msa};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions, TieBreak};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    #[test]
+    fn score_own_alignment() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+            Record::with_attrs("D", None, b"GACTT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&sequences, tree(&sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+        let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        let msa = result.msa(&info);
+
+        let msa_info = phyloinfo_from_sequences_tree(&msa, tree(&msa)).unwrap();
+        let (msa_alignments, msa_scores) = pars_score_msa(&scoring, &msa_info, &options).unwrap();
+        assert_eq!(msa_scores, result.scores);
+        for (alignment, msa_alignment) in result.alignments.iter().zip(msa_alignments.iter()) {
+            assert_eq!(alignment.map_x, msa_alignment.map_x);
+            assert_eq!(alignment.map_y, msa_alignment.map_y);
+        }
+    }
+
+    #[test]
+    fn score_worse_alignment() {
+        let optimal = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+            Record::with_attrs("C", None, b"AACT"),
+            Record::with_attrs("D", None, b"AAC-"),
+        ];
+        let shifted = [
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"-AAC-"),
+            Record::with_attrs("C", None, b"AACT-"),
+            Record::with_attrs("D", None, b"-AAC-"),
+        ];
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let score = |msa: &[Record]| -> f64 {
+            let info = phyloinfo_from_sequences_tree(msa, tree(msa)).unwrap();
+            pars_score_msa(&scoring, &info, &options)
+                .unwrap()
+                .1
+                .iter()
+                .sum()
+        };
+        assert!(score(&optimal) < score(&shifted));
+    }
+
+    #[test]
+    fn column_scores() {
+        let msa = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+            Record::with_attrs("C", None, b"AGCT"),
+            Record::with_attrs("D", None, b"AGCT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&msa, tree(&msa)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let column_scores = pars_column_scores(&scoring, &info, &options).unwrap();
+        assert_eq!(column_scores, vec![0.0, 1.0, 0.0, 2.0]);
+        let (_, scores) = pars_score_msa(&scoring, &info, &options).unwrap();
+        assert_eq!(
+            column_scores.iter().sum::<f64>(),
+            scores.iter().sum::<f64>()
+        );
+    }
+
+    #[test]
+    fn traceback_paths() {
+        let msa = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+            Record::with_attrs("C", None, b"AGCT"),
+            Record::with_attrs("D", None, b"AGCT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&msa, tree(&msa)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let paths = pars_traceback_paths(&scoring, &info, &options).unwrap();
+        assert_eq!(paths.len(), 3);
+        let states: String = paths[0].iter().map(|step| step.state).collect();
+        assert_eq!(states, "MMMX");
+        let last = paths[0].last().unwrap();
+        assert_eq!(
+            (last.cell, last.gap_open, last.cost, last.column),
+            ((4, 3), true, 2.0, 3)
+        );
+        let (_, scores) = pars_score_msa(&scoring, &info, &options).unwrap();
+        for (path, score) in paths.iter().zip(scores) {
+            assert_eq!(path.iter().map(|step| step.cost).sum::<f64>(), score);
+        }
+    }
+
+    #[test]
+    fn unaligned_sequences() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"AACT"),
+            Record::with_attrs("D", None, b"AACT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&sequences, tree(&sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        assert!(pars_score_msa(&scoring, &info, &AlignmentOptions::default()).is_err());
+    }
+
+    #[test]
+    fn ancestors() {
+        let msa = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AAC-"),
+            Record::with_attrs("C", None, b"AGCT"),
+            Record::with_attrs("D", None, b"AGCT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&msa, tree(&msa)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let ancestors = pars_ancestors_msa(&scoring, &info, &AlignmentOptions::default()).unwrap();
+        assert_eq!(ancestors.len(), 3);
+        assert_eq!(ancestors[1].seq(), b"AGCT");
+        for ancestor in &ancestors {
+            assert_eq!(ancestor.seq().len(), 4);
+        }
+
+        let gaps = pars_ancestral_gaps(&scoring, &info, &AlignmentOptions::default()).unwrap();
+        assert_eq!(gaps.len(), 3);
+        assert_eq!(gaps[1], vec![false; 4]);
+        for (gaps, ancestor) in gaps.iter().zip(&ancestors) {
+            let expected: Vec<bool> = ancestor.seq().iter().map(|&c| c == b'-').collect();
+            assert_eq!(gaps, &expected);
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use bio::io::fasta::Record;
+use phylo::alignment::Alignment;
+
+use super::msa_scoring::GAP;
+use super::parsimony_costs::BranchParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::{leaf_site_info, node_rng, pars_align_w_options, AlignmentOptions};
+use crate::Result;
+
+/// Indel-aware parsimony alignment of two sequences or profiles.
+#[derive(Clone, Debug)]
+pub struct PairwiseAlignment {
+    /// Mapping of the columns of the alignment to the sites of x and y.
+    pub alignment: Alignment,
+    pub score: f64,
+    /// Parsimony sets and gap flags of the common ancestor, one per column of the alignment.
+    pub ancestor: Vec<ParsimonySiteInfo>,
+}
+
+impl PairwiseAlignment {
+    /// Rows of x and y in the alignment, `x` and `y` must be the aligned sequences.
+    pub fn rows(&self, x: &[u8], y: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let row = |map: &[Option<usize>], seq: &[u8]| {
+            map.iter()
+                .map(|site| site.map_or(GAP, |site| seq[site]))
+                .collect()
+        };
+        (row(&self.alignment.map_x, x), row(&self.alignment.map_y, y))
+    }
+}
+
+/// Profile of a sequence to align with `pars_align_profiles`, read in the same way as the
+/// leaves of a tree alignment with the data type, alphabet, recoding and gap profiles of
+/// `options`.
+pub fn sequence_profile(record: &Record, options: &AlignmentOptions) -> Vec<ParsimonySiteInfo> {
+    let sequence_type = options
+        .data_type
+        .sequence_type(std::slice::from_ref(record));
+    leaf_site_info(record, &sequence_type, options)
+}
+
+/// Aligns the profiles of x and y, which are joined in a common ancestor by branches with the
+/// costs `x_scoring` and `y_scoring`. Ties are broken as set in `options`, with `options.seed`
+/// the alignment is reproducible. Fails if the DP matrices need more than
+/// `options.max_memory`.
+pub fn pars_align_profiles(
+    x_info: &[ParsimonySiteInfo],
+    x_scoring: &dyn BranchParsimonyCosts,
+    y_info: &[ParsimonySiteInfo],
+    y_scoring: &dyn BranchParsimonyCosts,
+    options: &AlignmentOptions,
+) -> Result<PairwiseAlignment> {
+    let (ancestor, alignment, score) = pars_align_w_options(
+        x_info,
+        x_scoring,
+        y_info,
+        y_scoring,
+        node_rng(options, 0),
+        options,
+    )?;
+    Ok(PairwiseAlignment {
+        alignment,
+        score,
+        ancestor,
+    })
+}
+
+/// Aligns the sequences x and y, `scoring` holds the costs of the branch from each of them to
+/// their common ancestor. The sequences are read in the same way as the leaves of a tree
+/// alignment, so the data type, alphabet, recoding and gap profiles of `options` apply.
+pub fn pars_align_sequences(
+    x: &Record,
+    y: &Record,
+    scoring: &dyn BranchParsimonyCosts,
+    options: &AlignmentOptions,
+) -> Result<PairwiseAlignment> {
+    let sequence_type = options.data_type.sequence_type(&[x.clone(), y.clone()]);
+    pars_align_profiles(
+        &leaf_site_info(x, &sequence_type, options),
+        scoring,
+        &leaf_site_info(y, &sequence_type, options),
+        scoring,
+        options,
+    )
+}
+
+#[cfg(test)]
+mod pairwise_tests {
+    use super::{pars_align_profiles, pars_align_sequences, sequence_profile};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+
+    #[test]
+    fn align_two_sequences() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let x = Record::with_attrs("A", None, b"AACT");
+        let y = Record::with_attrs("B", None, b"AC");
+        let options = AlignmentOptions {
+            seed: Some(3),
+            ..Default::default()
+        };
+
+        let pairwise =
+            pars_align_sequences(&x, &y, scoring.get_branch_costs(1.0), &options).unwrap();
+        assert_eq!(pairwise.score, 3.5);
+        assert_eq!(pairwise.ancestor.len(), 4);
+        let (row_x, row_y) = pairwise.rows(x.seq(), y.seq());
+        assert_eq!(row_x, b"AACT");
+        assert!(row_y == b"AC--" || row_y == b"A--C");
+
+        let other = pars_align_sequences(&x, &y, scoring.get_branch_costs(1.0), &options).unwrap();
+        assert_eq!(other.alignment.map_y, pairwise.alignment.map_y);
+    }
+
+    #[test]
+    fn align_profile_to_sequence() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(3),
+            ..Default::default()
+        };
+        let x = sequence_profile(&Record::with_attrs("A", None, b"ACGT"), &options);
+        let y = sequence_profile(&Record::with_attrs("B", None, b"ACGT"), &options);
+        let z = sequence_profile(&Record::with_attrs("C", None, b"ACG"), &options);
+
+        let xy = pars_align_profiles(
+            &x,
+            scoring.get_branch_costs(1.0),
+            &y,
+            scoring.get_branch_costs(1.0),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(xy.score, 0.0);
+        assert_eq!(xy.ancestor.len(), 4);
+        let xyz = pars_align_profiles(
+            &xy.ancestor,
+            scoring.get_branch_costs(1.0),
+            &z,
+            scoring.get_branch_costs(1.0),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(xyz.alignment.map_x.len(), 4);
+        assert_eq!(xyz.alignment.map_y.iter().flatten().count(), 3);
+    }
+}
@@ -0,0 +1,238 @@
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use web_time::Instant;
+
+use super::constraints::{NodeConstraints, TreeConstraints};
+use super::parsimony_costs::ParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::sequence_weights::{child_cost_factors, WeightedBranchCosts};
+use super::{
+    leaf_site_info, node_rng, pars_align_w_matches, record_cost_lookup, report_run_finished,
+    AlignmentOptions, AlignmentResult,
+};
+use crate::{ParsimonyError, Result};
+
+/// Shared state of a parallel alignment on the tree. An internal node is aligned as soon as both
+/// of its children are done, `pending` counts the children of every node that are not done yet.
+struct Schedule<'a> {
+    scoring: &'a (dyn ParsimonyCosts + Sync),
+    info: &'a PhyloInfo,
+    options: &'a AlignmentOptions,
+    /// Cost multipliers of the children of every internal node from the sequence weighting.
+    factors: Vec<(f64, f64)>,
+    constraints: Option<TreeConstraints>,
+    leaf_info: Vec<Vec<ParsimonySiteInfo>>,
+    internal_info: Vec<OnceLock<Vec<ParsimonySiteInfo>>>,
+    results: Vec<OnceLock<(Alignment, f64)>>,
+    parents: Vec<Option<usize>>,
+    pending: Vec<AtomicUsize>,
+    processed: AtomicUsize,
+    /// First error of a node, the nodes above it are not aligned.
+    failed: OnceLock<ParsimonyError>,
+}
+
+impl<'a> Schedule<'a> {
+    fn report_processed(&self) {
+        let processed = self.processed.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(progress) = &self.options.progress {
+            progress.report(processed, self.info.tree.postorder.len());
+        }
+    }
+
+    fn child_info(&self, child: NodeIdx) -> (&[ParsimonySiteInfo], f64) {
+        let tree = &self.info.tree;
+        match child {
+            Int(idx) => (
+                self.internal_info[idx].get().unwrap(),
+                tree.internals[idx].blen,
+            ),
+            Leaf(idx) => (&self.leaf_info[idx], tree.leaves[idx].blen),
+        }
+    }
+
+    fn align_node(&'a self, scope: &rayon::Scope<'a>, idx: usize) {
+        let children = &self.info.tree.internals[idx].children;
+        let (x_info, x_branch) = self.child_info(children[0]);
+        let (y_info, y_branch) = self.child_info(children[1]);
+        let node_constraints = match &self.constraints {
+            Some(constraints) => {
+                match constraints.node_constraints(self.info, idx, x_info, y_info, &|node| {
+                    &self.results[node].get().unwrap().0
+                }) {
+                    Ok(node_constraints) => node_constraints,
+                    Err(error) => {
+                        let _ = self.failed.set(error);
+                        return;
+                    }
+                }
+            }
+            None => NodeConstraints::default(),
+        };
+        let start = Instant::now();
+        if let Some(observer) = &self.options.observer {
+            observer.0.node_started(Int(idx));
+        }
+        record_cost_lookup(self.scoring, x_branch, self.options);
+        record_cost_lookup(self.scoring, y_branch, self.options);
+        let (node_info, alignment, score) = match pars_align_w_matches(
+            x_info,
+            &WeightedBranchCosts::new(self.scoring.get_branch_costs(x_branch), self.factors[idx].0),
+            y_info,
+            &WeightedBranchCosts::new(self.scoring.get_branch_costs(y_branch), self.factors[idx].1),
+            node_rng(self.options, idx),
+            self.options,
+            &node_constraints,
+        ) {
+            Ok(aligned) => aligned,
+            Err(error) => {
+                let _ = self.failed.set(error);
+                return;
+            }
+        };
+        info!("Aligned internal node {} with score {}.", idx, score);
+        if let Some(observer) = &self.options.observer {
+            observer.0.node_finished(Int(idx), score, start.elapsed());
+        }
+        self.internal_info[idx].set(node_info).unwrap();
+        self.results[idx].set((alignment, score)).unwrap();
+        self.report_processed();
+        if let Some(parent) = self.parents[idx] {
+            if self.pending[parent].fetch_sub(1, Ordering::SeqCst) == 1 {
+                scope.spawn(move |scope| self.align_node(scope, parent));
+            }
+        }
+    }
+}
+
+/// Aligns the sequences on the tree in the same way as `pars_align_on_tree_w_options`, but the
+/// internal nodes with disjoint subtrees are aligned concurrently on a thread pool. With
+/// `threads` set to 0 the number of threads is chosen automatically. Every node has its own
+/// random generator, so the result does not depend on the number of threads.
+pub fn pars_align_on_tree_parallel(
+    scoring: &(dyn ParsimonyCosts + Sync),
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    threads: usize,
+) -> Result<AlignmentResult> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let start = Instant::now();
+    info!(
+        "Starting the IndelMAP alignment on {} threads.",
+        pool.current_num_threads()
+    );
+    let tree = &info.tree;
+    let internal_count = tree.internals.len();
+    let mut parents = vec![None; internal_count];
+    let mut pending: Vec<AtomicUsize> = (0..internal_count).map(|_| AtomicUsize::new(0)).collect();
+    for (idx, node) in tree.internals.iter().enumerate() {
+        for child in &node.children {
+            if let Int(child) = child {
+                parents[*child] = Some(idx);
+                *pending[idx].get_mut() += 1;
+            }
+        }
+    }
+    let mut schedule = Schedule {
+        scoring,
+        info,
+        options,
+        factors: child_cost_factors(tree, options.sequence_weighting),
+        constraints: options
+            .constraints
+            .as_ref()
+            .map(|constraints| TreeConstraints::new(constraints, info))
+            .transpose()?,
+        leaf_info: Vec::new(),
+        internal_info: (0..internal_count).map(|_| OnceLock::new()).collect(),
+        results: (0..internal_count).map(|_| OnceLock::new()).collect(),
+        parents,
+        pending,
+        processed: AtomicUsize::new(0),
+        failed: OnceLock::new(),
+    };
+    let sequence_type = options.data_type.sequence_type(&info.sequences);
+    schedule.leaf_info = pool.install(|| {
+        info.sequences
+            .par_iter()
+            .map(|record| {
+                let leaf_info = leaf_site_info(record, &sequence_type, options);
+                schedule.report_processed();
+                leaf_info
+            })
+            .collect()
+    });
+
+    let shared = &schedule;
+    pool.scope(|scope| {
+        for idx in 0..internal_count {
+            if shared.pending[idx].load(Ordering::SeqCst) == 0 {
+                scope.spawn(move |scope| shared.align_node(scope, idx));
+            }
+        }
+    });
+    if let Some(error) = schedule.failed.take() {
+        return Err(error);
+    }
+    info!("Finished IndelMAP alignment.");
+    let (alignments, scores) = schedule
+        .results
+        .iter()
+        .map(|result| result.get().unwrap().clone())
+        .unzip();
+    let result = AlignmentResult::new(alignments, scores, tree.postorder.clone());
+    report_run_finished(options, result.score(), start.elapsed());
+    Ok(result)
+}
+
+#[cfg(test)]
+mod parallel_tests {
+    use super::pars_align_on_tree_parallel;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+            Record::with_attrs("D", None, b"GACTT"),
+            Record::with_attrs("E", None, b"ACTTA"),
+            Record::with_attrs("F", None, b"GACT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, L(4), L(5), 1.0, 1.0);
+        tree.add_parent(3, I(0), I(1), 1.0, 1.0);
+        tree.add_parent(4, I(3), I(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(11),
+            ..Default::default()
+        };
+
+        let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        for threads in [1, 2, 4] {
+            let parallel = pars_align_on_tree_parallel(&scoring, &info, &options, threads).unwrap();
+            assert_eq!(parallel.scores, result.scores);
+            for (alignment, parallel) in result.alignments.iter().zip(parallel.alignments.iter()) {
+                assert_eq!(alignment.map_x, parallel.map_x);
+                assert_eq!(alignment.map_y, parallel.map_y);
+            }
+        }
+    }
+}
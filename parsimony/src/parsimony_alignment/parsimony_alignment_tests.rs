@@ -1,15 +1,27 @@
 use super::parsimony_info::SiteFlag::{self, GapOpen, NoGap};
 use crate::parsimony_alignment::parsimony_costs::{
-    parsimony_costs_simple::ParsimonyCostsSimple, ParsimonyCosts,
+    parsimony_costs_model::{DNAParsCosts, GapMultipliers},
+    parsimony_costs_simple::ParsimonyCostsSimple,
+    ParsimonyCosts,
 };
+use crate::parsimony_alignment::partitions::{Partition, Partitions};
 use crate::parsimony_alignment::{
-    pars_align_on_tree, pars_align_w_rng, parsimony_info::ParsimonySiteInfo,
-    parsimony_sets::get_parsimony_sets,
+    estimate_dp_memory, estimate_tree_dp, gap_profiles::GapProfiles, pars_align_on_tree,
+    pars_align_on_tree_w_options, pars_align_w_rng, parsimony_info::ParsimonySiteInfo,
+    parsimony_sets::get_parsimony_sets, region_masks::RegionMasks, AlignmentObserver,
+    AlignmentOptions, AlignmentResult, DataType, DpExtraMatrices, ObserverHandle, ProgressCallback,
+    ScoreRows, SoftMask, TieBreak,
 };
+use crate::ParsimonyError;
+use assert_matches::assert_matches;
 use bio::io::fasta::Record;
 use phylo::phylo_info::phyloinfo_from_sequences_tree;
 use phylo::sequences::SequenceType;
-use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+use phylo::Rounding;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 macro_rules! align {
     (@collect -) => { None };
@@ -100,9 +112,9 @@ pub(crate) fn align_two_on_tree() {
 
     let scoring = ParsimonyCostsSimple::new(mismatch_cost, gap_open_cost, gap_ext_cost);
 
-    let (alignment_vec, score) = pars_align_on_tree(&scoring, &info);
-    assert_eq!(score[Into::<usize>::into(info.tree.root)], 3.5);
-    let alignment = &alignment_vec[Into::<usize>::into(info.tree.root)];
+    let result = pars_align_on_tree(&scoring, &info).unwrap();
+    assert_eq!(result.scores[Into::<usize>::into(info.tree.root)], 3.5);
+    let alignment = &result.alignments[Into::<usize>::into(info.tree.root)];
     assert_eq!(alignment.map_x.len(), 4);
     assert_eq!(alignment.map_y.len(), 4);
 }
@@ -225,7 +237,11 @@ pub(crate) fn align_four_on_tree() {
 
     let scoring = ParsimonyCostsSimple::new(c, a, b);
 
-    let (alignment_vec, score) = pars_align_on_tree(&scoring, &info);
+    let AlignmentResult {
+        alignments: alignment_vec,
+        scores: score,
+        ..
+    } = pars_align_on_tree(&scoring, &info).unwrap();
     // first cherry
     assert_eq!(score[0], 3.5);
     assert_eq!(alignment_vec[0].map_x.len(), 4);
@@ -240,3 +256,471 @@ pub(crate) fn align_four_on_tree() {
         assert!(alignment_vec[2].map_x.len() == 4 || alignment_vec[2].map_x.len() == 5);
     }
 }
+
+#[test]
+pub(crate) fn align_on_tree_seeded_reproducible() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACTGAC"),
+        Record::with_attrs("B", None, b"ACG"),
+        Record::with_attrs("C", None, b"AGTA"),
+        Record::with_attrs("D", None, b"GACTT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+    tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let options = AlignmentOptions {
+        seed: Some(42),
+        ..Default::default()
+    };
+
+    let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+    for _ in 0..10 {
+        let other = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(result.scores, other.scores);
+        for (alignment, other) in result.alignments.iter().zip(other.alignments.iter()) {
+            assert_eq!(alignment.map_x, other.map_x);
+            assert_eq!(alignment.map_y, other.map_y);
+        }
+    }
+}
+
+#[test]
+pub(crate) fn alignment_result_msa() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACT"),
+        Record::with_attrs("B", None, b"AC"),
+        Record::with_attrs("C", None, b"AACT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+
+    let result = pars_align_on_tree(&scoring, &info).unwrap();
+    assert_eq!(result.order, info.tree.postorder);
+    assert_eq!(result.score(), result.scores.iter().sum::<f64>());
+    let msa = result.msa(&info);
+    assert_eq!(msa.len(), 3);
+    for (record, row) in sequences.iter().zip(msa.iter()) {
+        assert_eq!(row.seq().len(), 4);
+        let ungapped: Vec<u8> = row.seq().iter().copied().filter(|&c| c != b'-').collect();
+        assert_eq!(ungapped, record.seq());
+    }
+    let clade = result.clade_msa(&info, 0);
+    assert_eq!(clade.len(), 2);
+    assert_eq!(clade[0].id(), "A");
+    assert_eq!(clade[1].id(), "B");
+    assert_eq!(clade[0].seq().len(), result.alignments[0].map_x.len());
+    assert_eq!(result.clade_msa(&info, 1), msa);
+}
+
+#[test]
+pub(crate) fn align_on_tree_reports_progress() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACT"),
+        Record::with_attrs("B", None, b"AC"),
+        Record::with_attrs("C", None, b"A"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let progress = {
+        let reported = reported.clone();
+        ProgressCallback::new(move |processed, total| {
+            reported.lock().unwrap().push((processed, total))
+        })
+    };
+    let options = AlignmentOptions {
+        progress: Some(progress),
+        ..Default::default()
+    };
+
+    pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+    assert_eq!(
+        *reported.lock().unwrap(),
+        vec![(1, 5), (2, 5), (3, 5), (4, 5), (5, 5)]
+    );
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Started(NodeIdx),
+    Finished(NodeIdx, f64),
+    RunFinished(f64),
+}
+
+#[derive(Default)]
+struct RecordingObserver(Arc<Mutex<Vec<Event>>>);
+
+impl AlignmentObserver for RecordingObserver {
+    fn node_started(&self, node: NodeIdx) {
+        self.0.lock().unwrap().push(Event::Started(node));
+    }
+
+    fn node_finished(&self, node: NodeIdx, score: f64, _: Duration) {
+        self.0.lock().unwrap().push(Event::Finished(node, score));
+    }
+
+    fn run_finished(&self, score: f64, _: Duration) {
+        self.0.lock().unwrap().push(Event::RunFinished(score));
+    }
+}
+
+#[test]
+pub(crate) fn align_on_tree_notifies_observer() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACT"),
+        Record::with_attrs("B", None, b"AC"),
+        Record::with_attrs("C", None, b"A"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let observer = RecordingObserver::default();
+    let events = observer.0.clone();
+    let options = AlignmentOptions {
+        observer: Some(ObserverHandle::new(observer)),
+        ..Default::default()
+    };
+
+    let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            Event::Started(I(0)),
+            Event::Finished(I(0), result.scores[0]),
+            Event::Started(I(1)),
+            Event::Finished(I(1), result.scores[1]),
+            Event::RunFinished(result.score()),
+        ]
+    );
+}
+
+#[test]
+pub(crate) fn align_on_tree_deterministic_tie_break() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACT"),
+        Record::with_attrs("B", None, b"AC"),
+        Record::with_attrs("C", None, b"A"),
+        Record::with_attrs("D", None, b"GA"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+    tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    for tie_break in [TieBreak::Match, TieBreak::GapInX, TieBreak::GapInY] {
+        let options = AlignmentOptions {
+            tie_break,
+            ..Default::default()
+        };
+        let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        for _ in 0..10 {
+            let other = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+            assert_eq!(result.scores, other.scores);
+            for (alignment, other) in result.alignments.iter().zip(other.alignments.iter()) {
+                assert_eq!(alignment.map_x, other.map_x);
+                assert_eq!(alignment.map_y, other.map_y);
+            }
+        }
+    }
+}
+
+#[test]
+pub(crate) fn data_type_override() {
+    let sequences = [
+        Record::with_attrs("A", None, b"ACGT"),
+        Record::with_attrs("B", None, b"ACGA"),
+    ];
+    assert!(matches!(
+        DataType::Auto.sequence_type(&sequences),
+        SequenceType::DNA
+    ));
+    assert!(matches!(
+        DataType::Protein.sequence_type(&sequences),
+        SequenceType::Protein
+    ));
+    let rna = [
+        Record::with_attrs("A", None, b"ACGU"),
+        Record::with_attrs("B", None, b"ACGT"),
+    ];
+    assert!(matches!(
+        DataType::Rna.sequence_type(&rna),
+        SequenceType::DNA
+    ));
+    let mut tree = Tree::new(&rna).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&rna, tree).unwrap();
+    let options = AlignmentOptions {
+        data_type: DataType::Rna,
+        ..Default::default()
+    };
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+    assert_eq!(result.scores, vec![0.0]);
+}
+
+#[test]
+pub(crate) fn align_w_gap_profiles() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AAT"),
+        Record::with_attrs("B", None, b"AT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let align_w_profile = |id: &str, weights: Vec<f64>| {
+        let options = AlignmentOptions {
+            gap_profiles: Some(
+                GapProfiles::new(HashMap::from([(id.to_string(), weights)])).unwrap(),
+            ),
+            tie_break: TieBreak::Match,
+            ..Default::default()
+        };
+        pars_align_on_tree_w_options(&scoring, &info, &options).unwrap()
+    };
+    // the gap goes against the site with the lower multiplier
+    let result = align_w_profile("A", vec![3.0, 1.0, 1.0]);
+    assert_eq!(result.alignments[0].map_y, align!(0 - 1));
+    assert_eq!(result.scores, vec![2.0]);
+    let result = align_w_profile("A", vec![1.0, 3.0, 1.0]);
+    assert_eq!(result.alignments[0].map_y, align!(- 0 1));
+    assert_eq!(result.scores, vec![2.0]);
+    assert_eq!(align_w_profile("A", vec![2.0, 2.0, 2.0]).scores, vec![4.0]);
+    // the multipliers of the other sequence do not apply to gaps against A
+    assert_eq!(align_w_profile("B", vec![2.0, 2.0]).scores, vec![2.0]);
+}
+
+#[test]
+pub(crate) fn align_w_soft_mask() {
+    let sequences = [
+        Record::with_attrs("A", None, b"ggAT"),
+        Record::with_attrs("B", None, b"CAT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let score = |soft_mask| {
+        let options = AlignmentOptions {
+            soft_mask,
+            ..Default::default()
+        };
+        pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .scores
+    };
+    assert_eq!(score(SoftMask::Keep), vec![3.0]);
+    assert_eq!(score(SoftMask::Ignore), vec![0.0]);
+    assert_eq!(score(SoftMask::Downweight(0.5)), vec![2.0]);
+}
+
+#[test]
+pub(crate) fn align_w_region_masks() {
+    let sequences = [
+        Record::with_attrs("A", None, b"GGAT"),
+        Record::with_attrs("B", None, b"CAT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let score = |gap_weight| {
+        let regions = HashMap::from([("A".to_string(), vec![0..2])]);
+        let options = AlignmentOptions {
+            region_masks: Some(RegionMasks::new(regions, gap_weight).unwrap()),
+            ..Default::default()
+        };
+        pars_align_on_tree_w_options(&scoring, &info, &options)
+            .unwrap()
+            .scores
+    };
+    // the masked GG match the C for free and the gap against them is cheaper
+    assert_eq!(score(1.0), vec![2.0]);
+    assert_eq!(score(0.5), vec![1.0]);
+    assert_eq!(score(0.0), vec![0.0]);
+}
+
+#[test]
+pub(crate) fn align_w_partitions() {
+    let sequences = [
+        Record::with_attrs("A", None, b"ACGTTG"),
+        Record::with_attrs("B", None, b"AGGTCG"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = |model: &str, params: &[f64]| {
+        DNAParsCosts::new(
+            model,
+            params,
+            &GapMultipliers::new(2.5, 0.5),
+            &[1.0],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap()
+    };
+    let score = |scoring: &dyn ParsimonyCosts, partitions: Option<Partitions>| {
+        let options = AlignmentOptions {
+            partitions,
+            ..Default::default()
+        };
+        pars_align_on_tree_w_options(scoring, &info, &options)
+            .unwrap()
+            .scores[0]
+    };
+    let partitions = |ranges| {
+        Partitions::new(vec![Partition {
+            name: "all".to_string(),
+            data_type: DataType::Auto,
+            model: "HKY85".to_string(),
+            params: vec![4.0],
+            ranges,
+        }])
+        .unwrap()
+    };
+    let jc69 = score(&scoring("JC69", &[]), None);
+    let hky = score(&scoring("HKY85", &[4.0]), None);
+    assert_ne!(jc69, hky);
+    // a partition over all the sites scores them with its model, the ignored partitions of a
+    // scoring without them change nothing
+    let partitioned = scoring("JC69", &[])
+        .with_partitions(vec![scoring("HKY85", &[4.0])])
+        .unwrap();
+    assert_eq!(score(&partitioned, Some(partitions(vec![(0..6, 1)]))), hky);
+    assert_eq!(
+        score(&scoring("JC69", &[]), Some(partitions(vec![(0..6, 1)]))),
+        jc69
+    );
+    // sites outside of the partitions keep the costs of the scoring
+    assert_eq!(score(&partitioned, Some(partitions(vec![(6..7, 1)]))), jc69);
+}
+
+#[test]
+pub(crate) fn tree_dp_estimate() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACT"),
+        Record::with_attrs("B", None, b"A-C"),
+        Record::with_attrs("C", None, b"ACGT"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let estimate = estimate_tree_dp(&info, &AlignmentOptions::default(), 0);
+    assert_eq!(estimate.lower.cells, 15 + 25);
+    assert_eq!(
+        estimate.lower.peak_memory,
+        estimate_dp_memory(4, 4, false, Default::default())
+    );
+    assert_eq!(estimate.upper.cells, 15 + 35);
+    assert_eq!(
+        estimate.upper.peak_memory,
+        estimate_dp_memory(6, 4, false, Default::default())
+    );
+    let low = AlignmentOptions {
+        score_rows: ScoreRows::Two,
+        ..Default::default()
+    };
+    assert_eq!(
+        estimate_tree_dp(&info, &low, 0).upper.peak_memory,
+        estimate_dp_memory(6, 4, true, Default::default())
+    );
+}
+
+#[test]
+pub(crate) fn align_within_max_memory() {
+    let sequences = [
+        Record::with_attrs("A", None, b"AACTGAC"),
+        Record::with_attrs("B", None, b"ACG"),
+    ];
+    let mut tree = Tree::new(&sequences).unwrap();
+    tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+    tree.complete = true;
+    tree.create_postorder();
+    let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    assert_eq!(estimate_dp_memory(7, 3, false, Default::default()), 960);
+    assert_eq!(estimate_dp_memory(7, 3, true, Default::default()), 384);
+    let gap_lengths = DpExtraMatrices {
+        gap_lengths: true,
+        ..Default::default()
+    };
+    assert_eq!(estimate_dp_memory(7, 3, false, gap_lengths), 1216);
+    assert_eq!(estimate_dp_memory(7, 3, true, gap_lengths), 448);
+    let match_categories = DpExtraMatrices {
+        match_categories: true,
+        ..Default::default()
+    };
+    // a byte for the site category of the match in every one of the 32 cells
+    assert_eq!(estimate_dp_memory(7, 3, false, match_categories), 992);
+    assert_eq!(estimate_dp_memory(7, 3, true, match_categories), 416);
+
+    let low = AlignmentOptions {
+        seed: Some(5),
+        score_rows: ScoreRows::Two,
+        ..Default::default()
+    };
+    let expected = pars_align_on_tree_w_options(&scoring, &info, &low).unwrap();
+    // the full matrices do not fit, so the node switches to two rows of scores
+    let limited = AlignmentOptions {
+        seed: Some(5),
+        max_memory: Some(500),
+        ..Default::default()
+    };
+    let result = pars_align_on_tree_w_options(&scoring, &info, &limited).unwrap();
+    assert_eq!(result.scores, expected.scores);
+    assert_eq!(result.alignments[0].map_x, expected.alignments[0].map_x);
+    assert_eq!(result.alignments[0].map_y, expected.alignments[0].map_y);
+
+    let full = AlignmentOptions {
+        score_rows: ScoreRows::All,
+        ..limited.clone()
+    };
+    assert_matches!(
+        pars_align_on_tree_w_options(&scoring, &info, &full).err(),
+        Some(ParsimonyError::MemoryLimit(_))
+    );
+    let tiny = AlignmentOptions {
+        max_memory: Some(100),
+        ..limited
+    };
+    assert_matches!(
+        pars_align_on_tree_w_options(&scoring, &info, &tiny).err(),
+        Some(ParsimonyError::MemoryLimit(_))
+    );
+}
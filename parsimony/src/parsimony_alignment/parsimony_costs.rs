@@ -1,13 +1,64 @@
+use super::parsimony_sets::ParsimonySet;
+use crate::cmp_f64;
+
 pub trait ParsimonyCosts {
     fn get_branch_costs(&self, branch_length: f64) -> &dyn BranchParsimonyCosts;
+
+    /// Whether `get_branch_costs` has costs computed for exactly this branch length rather than
+    /// those of the closest of the precomputed branch lengths.
+    fn has_exact_costs(&self, _branch_length: f64) -> bool {
+        true
+    }
 }
 
-pub trait BranchParsimonyCosts {
+pub trait BranchParsimonyCosts: Sync {
     fn match_cost(&self, i: u8, j: u8) -> f64;
     fn gap_open_cost(&self) -> f64;
     fn gap_ext_cost(&self) -> f64;
     fn avg_cost(&self) -> f64;
+
+    /// Costs of matching every character of `ancestors`, in iteration order, to the cheapest
+    /// character of `set`.
+    fn min_match_costs(&self, ancestors: &ParsimonySet, set: &ParsimonySet) -> Vec<f64> {
+        ancestors
+            .iter()
+            .map(|ancestor| {
+                set.iter()
+                    .map(|child| self.match_cost(ancestor, child))
+                    .min_by(cmp_f64())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// Number of site categories with their own costs, the partitions or the site rate
+    /// categories, zero if every site is scored with the same costs.
+    fn site_categories(&self) -> usize {
+        0
+    }
+
+    /// Like `min_match_costs`, with the substitution costs of the sites in the site category
+    /// `category`. The costs are written into `costs`, so that one buffer serves all the
+    /// categories of a cell.
+    fn category_min_match_costs(
+        &self,
+        _category: usize,
+        ancestors: &ParsimonySet,
+        set: &ParsimonySet,
+        costs: &mut Vec<f64>,
+    ) {
+        costs.clear();
+        costs.extend(self.min_match_costs(ancestors, set));
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cost_cache;
+pub mod dna_models;
+mod gamma;
+pub mod parsimony_costs_matrix;
 pub mod parsimony_costs_model;
 pub mod parsimony_costs_simple;
+pub mod protein_models;
+mod score_matrices;
+mod simd;
@@ -0,0 +1,274 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use nalgebra::DMatrix;
+use phylo::Rounding;
+
+/// Directory in which the cost matrices computed from substitution models are kept between
+/// runs, one file per model setup and branch length. The file names are hashes of everything
+/// the matrix depends on, so a changed setup never reads a stale matrix, and the cache can be
+/// cleared at any time by removing the directory. Problems with the cache are logged and the
+/// matrices are then computed as usual.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostCache {
+    dir: PathBuf,
+}
+
+/// Everything that the cost matrices of a substitution model depend on apart from the branch
+/// length.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CostKey(String);
+
+/// Cost matrix of one branch length as stored in the cache, without the gap costs which are
+/// derived from the average cost.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CachedCosts {
+    pub(crate) index: [i32; 255],
+    pub(crate) avg_cost: f64,
+    pub(crate) costs: DMatrix<f64>,
+}
+
+impl CostKey {
+    pub(crate) fn new(
+        model_name: &str,
+        model_params: &[f64],
+        rates: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+    ) -> Self {
+        CostKey(format!(
+            "model={} params={} rates={} zero_diag={} round={} digits={}",
+            model_name.to_uppercase(),
+            hex_values(model_params),
+            hex_values(rates),
+            zero_diag,
+            rounding.round,
+            rounding.digits
+        ))
+    }
+
+    fn with_time(&self, time: f64) -> String {
+        format!("{} time={:016x}", self.0, time.to_bits())
+    }
+}
+
+impl CostCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        CostCache { dir: dir.into() }
+    }
+
+    /// The indelMaP directory in $XDG_CACHE_HOME or otherwise in $HOME/.cache, `None` if neither
+    /// is set.
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .filter(|dir| !dir.is_empty())
+                    .map(|home| PathBuf::from(home).join(".cache"))
+            })
+            .map(|dir| dir.join("indelMaP"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}.costs", fnv1a(key.as_bytes())))
+    }
+
+    /// The cached costs of the branch length, `None` if there are none or they are unreadable or
+    /// not `n`x`n`.
+    pub(crate) fn load(&self, key: &CostKey, time: f64, n: usize) -> Option<CachedCosts> {
+        let key = key.with_time(time);
+        let path = self.entry_path(&key);
+        let content = fs::read_to_string(&path).ok()?;
+        let entry = parse_entry(&content, &key, n);
+        match &entry {
+            Some(_) => debug!("Read the costs for time {} from {}.", time, path.display()),
+            None => warn!(
+                "Ignoring the unreadable cached costs in {}, they are computed again.",
+                path.display()
+            ),
+        }
+        entry
+    }
+
+    pub(crate) fn store(&self, key: &CostKey, time: f64, entry: &CachedCosts) {
+        let key = key.with_time(time);
+        let path = self.entry_path(&key);
+        let content = format!(
+            "{}\n{}\n{:016x}\n{}\n",
+            key,
+            entry
+                .index
+                .iter()
+                .map(i32::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+            entry.avg_cost.to_bits(),
+            hex_values(entry.costs.as_slice())
+        );
+        // written to a temporary file first so that concurrent runs never read half an entry
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp, content))
+            .and_then(|_| fs::rename(&temp, &path));
+        match written {
+            Ok(()) => debug!("Cached the costs for time {} in {}.", time, path.display()),
+            Err(error) => {
+                let _ = fs::remove_file(&temp);
+                warn!(
+                    "Could not cache the costs in {}: {}.",
+                    self.dir.display(),
+                    error
+                );
+            }
+        }
+    }
+}
+
+fn parse_entry(content: &str, key: &str, n: usize) -> Option<CachedCosts> {
+    let mut lines = content.lines();
+    // a different key means a hash collision
+    if lines.next()? != key {
+        return None;
+    }
+    let index: Vec<i32> = lines
+        .next()?
+        .split(' ')
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+    let avg_cost = parse_hex(lines.next()?)?;
+    let costs: Vec<f64> = lines
+        .next()?
+        .split(' ')
+        .map(parse_hex)
+        .collect::<Option<_>>()?;
+    if costs.len() != n * n {
+        return None;
+    }
+    Some(CachedCosts {
+        index: index.try_into().ok()?,
+        avg_cost,
+        costs: DMatrix::from_column_slice(n, n, &costs),
+    })
+}
+
+/// Exact text form of the values, the hexadecimal bits of every value.
+fn hex_values(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|value| format!("{:016x}", value.to_bits()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_hex(value: &str) -> Option<f64> {
+    u64::from_str_radix(value, 16).ok().map(f64::from_bits)
+}
+
+/// 64-bit FNV-1a hash, unlike the hasher of the standard library it is the same for every
+/// build, which the names of the cache files and the checkpoint headers rely on.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod cost_cache_tests {
+    use super::{CostCache, CostKey};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+        DNAParsCosts, GammaRates, GapMultipliers, ProteinParsCosts,
+    };
+    use phylo::Rounding;
+    use std::fs;
+
+    #[test]
+    fn cached_costs_match_computed() {
+        let dir = std::env::temp_dir().join("indelMaP_cached_costs_match_computed");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = CostCache::new(&dir);
+        let gap_mult = GapMultipliers::new(2.5, 0.5);
+        let times = [0.1, 0.5];
+        let computed =
+            ProteinParsCosts::new("WAG", &gap_mult, &times, false, &Rounding::none()).unwrap();
+        let stored = ProteinParsCosts::new_cached(
+            "WAG",
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(stored, computed);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        // the gap costs are not part of the cached entries
+        let other_gaps = GapMultipliers::new(4.0, 1.0);
+        let read = ProteinParsCosts::new_cached(
+            "WAG",
+            &other_gaps,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(
+            read,
+            ProteinParsCosts::new("WAG", &other_gaps, &times, false, &Rounding::none()).unwrap()
+        );
+
+        // unreadable entries are computed again and replaced
+        for entry in fs::read_dir(&dir).unwrap() {
+            fs::write(entry.unwrap().path(), "garbage").unwrap();
+        }
+        let dna = DNAParsCosts::new_cached(
+            "k80",
+            &[],
+            &gap_mult,
+            &times,
+            true,
+            &Rounding::none(),
+            &GammaRates::none(),
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(
+            dna,
+            DNAParsCosts::new("k80", &[], &gap_mult, &times, true, &Rounding::none()).unwrap()
+        );
+        let recomputed = ProteinParsCosts::new_cached(
+            "WAG",
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+            &cache,
+        )
+        .unwrap();
+        assert_eq!(recomputed, computed);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keys_differ_by_setup() {
+        let rounding = Rounding::none();
+        let key = CostKey::new("wag", &[], &[1.0], false, &rounding);
+        assert_eq!(key, CostKey::new("WAG", &[], &[1.0], false, &rounding));
+        assert_ne!(key, CostKey::new("WAG", &[], &[1.0], true, &rounding));
+        assert_ne!(key, CostKey::new("WAG", &[], &[0.5, 1.5], false, &rounding));
+        assert_ne!(key.with_time(0.1), key.with_time(0.2));
+    }
+}
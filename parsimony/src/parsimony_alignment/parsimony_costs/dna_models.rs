@@ -0,0 +1,246 @@
+use bio::io::fasta::Record;
+use nalgebra::DMatrix;
+
+use super::parsimony_costs_model::normalised_rate_matrix;
+use crate::{ParsimonyError, Result};
+
+/// Nucleotide order of the frequencies and the rate matrices of the models.
+pub(crate) const MODEL_NUCLEOTIDES: &[u8] = b"TCAG";
+
+/// Parameters of the models that are defined here rather than in the phylo crate: the optional
+/// frequencies f_t f_c f_a f_g followed by kappa for HKY85, or by the pyrimidine and purine
+/// transition rates k_tc k_ag for TN93. Without frequencies they are equal.
+fn model_parameters(name: &str, params: &[f64]) -> Result<Option<([f64; 4], f64, f64)>> {
+    let rates = match name.to_uppercase().as_str() {
+        "HKY85" => 1,
+        "TN93" => 2,
+        _ => return Ok(None),
+    };
+    let (freqs, rate_params) = if params.len() == rates {
+        ([0.25; 4], params)
+    } else if params.len() == rates + 4 {
+        let freqs = [params[0], params[1], params[2], params[3]];
+        if freqs.iter().any(|&freq| freq <= 0.0) {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "The {} frequencies must be positive, got {:?}.",
+                name, freqs
+            )));
+        }
+        if (freqs.iter().sum::<f64>() - 1.0).abs() > 1e-6 {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "The {} frequencies must sum to one, got {:?}.",
+                name, freqs
+            )));
+        }
+        (freqs, &params[4..])
+    } else {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "The {} model takes {} or {} parameters, got {}.",
+            name,
+            rates,
+            rates + 4,
+            params.len()
+        )));
+    };
+    if rate_params.iter().any(|&rate| rate <= 0.0) {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "The {} rates must be positive, got {:?}.",
+            name, rate_params
+        )));
+    }
+    let (k_tc, k_ag) = match rate_params {
+        [kappa] => (*kappa, *kappa),
+        [k_tc, k_ag] => (*k_tc, *k_ag),
+        _ => unreachable!(),
+    };
+    Ok(Some((freqs, k_tc, k_ag)))
+}
+
+/// Rate matrix with the transitions T<->C and A<->G scaled by `k_tc` and `k_ag`.
+fn rate_matrix(freqs: &[f64; 4], k_tc: f64, k_ag: f64) -> DMatrix<f64> {
+    let exchangeabilities = DMatrix::from_fn(4, 4, |i, j| match (i.min(j), i.max(j)) {
+        (0, 1) => k_tc,
+        (2, 3) => k_ag,
+        _ => 1.0,
+    });
+    normalised_rate_matrix(&exchangeabilities, freqs)
+}
+
+/// Rate matrix of the named model with the nucleotides in the order of `MODEL_NUCLEOTIDES`,
+/// or `None` if the model is not defined here. Fails on invalid parameters.
+pub(crate) fn dna_model_rate_matrix(name: &str, params: &[f64]) -> Result<Option<DMatrix<f64>>> {
+    Ok(model_parameters(name, params)?.map(|(freqs, k_tc, k_ag)| rate_matrix(&freqs, k_tc, k_ag)))
+}
+
+/// Number of rate parameters of the models that take the nucleotide frequencies in front of
+/// their rates, or `None` for the models with equal frequencies.
+fn frequency_model_rates(name: &str) -> Option<usize> {
+    match name.to_uppercase().as_str() {
+        "GTR" => Some(6),
+        "HKY85" => Some(1),
+        "TN93" => Some(2),
+        _ => None,
+    }
+}
+
+/// Frequencies of the nucleotides in the order of `MODEL_NUCLEOTIDES` counted over the
+/// sequences (+F), ambiguous characters and gaps are skipped. Every nucleotide starts with a
+/// pseudocount of one so that none of the frequencies is zero.
+pub fn empirical_frequencies(sequences: &[Record]) -> [f64; 4] {
+    let mut counts = [1.0; 4];
+    for residue in sequences.iter().flat_map(|record| record.seq()) {
+        let residue = residue.to_ascii_uppercase();
+        let residue = if residue == b'U' { b'T' } else { residue };
+        if let Some(idx) = MODEL_NUCLEOTIDES.iter().position(|&c| c == residue) {
+            counts[idx] += 1.0;
+        }
+    }
+    let total: f64 = counts.iter().sum();
+    counts.map(|count| count / total)
+}
+
+/// Parameters of the named model with `freqs` in front of the rate parameters `rates`. Fails
+/// for the models with equal frequencies and for a wrong number of rates.
+pub fn params_w_frequencies(name: &str, rates: &[f64], freqs: [f64; 4]) -> Result<Vec<f64>> {
+    let Some(rate_count) = frequency_model_rates(name) else {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "The {} model has equal nucleotide frequencies, empirical frequencies need GTR, HKY85 \
+             or TN93.",
+            name
+        )));
+    };
+    if rates.len() != rate_count {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "With empirical frequencies the {} model takes {} rate parameters, got {}.",
+            name,
+            rate_count,
+            rates.len()
+        )));
+    }
+    Ok(freqs.iter().chain(rates).copied().collect())
+}
+
+#[cfg(test)]
+mod dna_models_tests {
+    use super::{
+        dna_model_rate_matrix, empirical_frequencies, model_parameters, params_w_frequencies,
+    };
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::rate_matrix_costs;
+    use crate::Result;
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use nalgebra::DMatrix;
+    use phylo::Rounding;
+
+    /// Cost matrices of the named model for the branch lengths `times` and the rate categories
+    /// `rates`, or `None` if the model is not defined here. Fails on invalid parameters.
+    fn dna_model_costs(
+        name: &str,
+        params: &[f64],
+        times: &[f64],
+        rates: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+    ) -> Result<Option<Vec<(f64, DMatrix<f64>)>>> {
+        Ok(dna_model_rate_matrix(name, params)?.map(|q| {
+            rate_matrix_costs(&q, times, rates, zero_diag, rounding)
+                .into_iter()
+                .map(|(time, (costs, _))| (time, costs))
+                .collect()
+        }))
+    }
+
+    #[test]
+    fn parameter_validation() {
+        assert_eq!(
+            model_parameters("hky85", &[2.0]).unwrap(),
+            Some(([0.25; 4], 2.0, 2.0))
+        );
+        assert_eq!(
+            model_parameters("TN93", &[0.1, 0.2, 0.3, 0.4, 3.0, 1.5]).unwrap(),
+            Some(([0.1, 0.2, 0.3, 0.4], 3.0, 1.5))
+        );
+        assert_eq!(model_parameters("K80", &[2.0]).unwrap(), None);
+        assert!(model_parameters("HKY85", &[]).is_err());
+        assert!(model_parameters("HKY85", &[2.0, 1.0]).is_err());
+        assert!(model_parameters("HKY85", &[-2.0]).is_err());
+        assert!(model_parameters("HKY85", &[0.1, 0.2, 0.3, 0.3, 2.0]).is_err());
+        assert!(model_parameters("TN93", &[0.0, 0.3, 0.3, 0.4, 2.0, 1.0]).is_err());
+        assert!(model_parameters("TN93", &[2.0]).is_err());
+    }
+
+    #[test]
+    fn frequencies_from_sequences() {
+        let sequences = [
+            Record::with_attrs("a", None, b"TTCA-GN"),
+            Record::with_attrs("b", None, b"ttuA"),
+        ];
+        assert_eq!(
+            empirical_frequencies(&sequences),
+            [6.0 / 13.0, 2.0 / 13.0, 3.0 / 13.0, 2.0 / 13.0]
+        );
+        assert_eq!(empirical_frequencies(&[]), [0.25; 4]);
+
+        let freqs = [0.4, 0.1, 0.3, 0.2];
+        let params = params_w_frequencies("hky85", &[2.0], freqs).unwrap();
+        assert_eq!(params, vec![0.4, 0.1, 0.3, 0.2, 2.0]);
+        assert_eq!(
+            model_parameters("HKY85", &params).unwrap(),
+            Some((freqs, 2.0, 2.0))
+        );
+        assert_eq!(
+            params_w_frequencies("GTR", &[1.0; 6], freqs).unwrap().len(),
+            10
+        );
+        assert!(params_w_frequencies("JC69", &[], freqs).is_err());
+        assert!(params_w_frequencies("TN93", &[0.4, 0.1, 0.3, 0.2, 2.0, 1.0], freqs).is_err());
+    }
+
+    #[test]
+    fn derived_costs() {
+        // with kappa one and equal frequencies HKY85 is JC69
+        let costs = dna_model_costs(
+            "HKY85",
+            &[1.0],
+            &[0.1, 0.7],
+            &[1.0],
+            false,
+            &Rounding::zero(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(costs[0].1.mean(), 2.25);
+        assert_eq!(costs[1].1.mean(), 1.75);
+
+        let hky = dna_model_costs("HKY85", &[4.0], &[0.2], &[1.0], false, &Rounding::none())
+            .unwrap()
+            .unwrap();
+        let tn93 = dna_model_costs(
+            "TN93",
+            &[4.0, 4.0],
+            &[0.2],
+            &[1.0],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_relative_eq!(hky[0].1, tn93[0].1, epsilon = 1e-10);
+        let costs = &hky[0].1;
+        // transitions are cheaper than transversions
+        assert!(costs[(0, 1)] < costs[(0, 2)]);
+        assert!(costs[(2, 3)] < costs[(1, 3)]);
+
+        let tn93 = dna_model_costs("TN93", &[8.0, 2.0], &[0.2], &[1.0], true, &Rounding::none())
+            .unwrap()
+            .unwrap();
+        let costs = &tn93[0].1;
+        assert!(costs[(0, 1)] < costs[(2, 3)]);
+        assert_eq!(costs.diagonal().sum(), 0.0);
+        assert!(
+            dna_model_costs("JC69", &[], &[0.2], &[1.0], false, &Rounding::none())
+                .unwrap()
+                .is_none()
+        );
+    }
+}
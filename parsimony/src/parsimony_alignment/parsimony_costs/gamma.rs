@@ -0,0 +1,157 @@
+/// Natural log of the gamma function, Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    #[allow(clippy::excessive_precision)]
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Regularized lower incomplete gamma function P(a, x), by its series for small x and by the
+/// continued fraction of the upper function otherwise.
+fn incomplete_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x.is_infinite() {
+        return 1.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..10_000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term < sum * 1e-15 {
+                break;
+            }
+        }
+        (sum * scale).min(1.0)
+    } else {
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut fraction = d;
+        for n in 1..10_000 {
+            let an = -(n as f64) * (n as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            fraction *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (1.0 - scale * fraction).max(0.0)
+    }
+}
+
+/// Quantile of the gamma distribution with shape and rate `alpha`, i.e. with mean one.
+fn gamma_quantile(alpha: f64, p: f64) -> f64 {
+    let cdf = |x: f64| incomplete_gamma(alpha, alpha * x);
+    let mut high = 1.0;
+    while cdf(high) < p {
+        high *= 2.0;
+    }
+    let mut low = 0.0;
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if cdf(mid) < p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Rates of `categories` equally probable discrete gamma categories with shape `alpha`, each
+/// the mean rate of its category as in Yang (1994), so that the rates average to one.
+pub(crate) fn discrete_gamma_rates(alpha: f64, categories: usize) -> Vec<f64> {
+    let k = categories as f64;
+    let bounds: Vec<f64> = (0..=categories)
+        .map(|i| match i {
+            0 => 0.0,
+            i if i == categories => f64::INFINITY,
+            i => gamma_quantile(alpha, i as f64 / k),
+        })
+        .collect();
+    bounds
+        .windows(2)
+        .map(|bound| {
+            k * (incomplete_gamma(alpha + 1.0, alpha * bound[1])
+                - incomplete_gamma(alpha + 1.0, alpha * bound[0]))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod gamma_tests {
+    use super::{discrete_gamma_rates, incomplete_gamma, ln_gamma};
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn gamma_functions() {
+        assert_relative_eq!(ln_gamma(5.0), 24f64.ln(), epsilon = 1e-12);
+        assert_relative_eq!(
+            ln_gamma(0.5),
+            std::f64::consts::PI.sqrt().ln(),
+            epsilon = 1e-12
+        );
+        assert_relative_eq!(
+            incomplete_gamma(1.0, 2.0),
+            1.0 - (-2f64).exp(),
+            epsilon = 1e-12
+        );
+        assert_relative_eq!(
+            incomplete_gamma(1.0, 0.1),
+            1.0 - (-0.1f64).exp(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn yang_rates() {
+        let rates = discrete_gamma_rates(0.5, 4);
+        for (rate, expected) in rates.iter().zip([0.0334, 0.2519, 0.8203, 2.8944]) {
+            assert_relative_eq!(*rate, expected, epsilon = 1e-4);
+        }
+        for (alpha, categories) in [(0.2, 4), (1.0, 8), (5.0, 3)] {
+            let rates = discrete_gamma_rates(alpha, categories);
+            assert_eq!(rates.len(), categories);
+            assert_relative_eq!(rates.iter().sum::<f64>(), categories as f64, epsilon = 1e-8);
+            assert!(rates.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        assert_eq!(discrete_gamma_rates(0.5, 1), vec![1.0]);
+    }
+}
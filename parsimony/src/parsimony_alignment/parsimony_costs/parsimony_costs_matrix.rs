@@ -0,0 +1,354 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use log::info;
+use nalgebra::DMatrix;
+
+use super::parsimony_costs_model::GapMultipliers;
+use super::score_matrices::{score_matrix, SCORE_MATRIX_AMINOACIDS, SCORE_MATRIX_NAMES};
+use crate::parsimony_alignment::{BranchParsimonyCosts, ParsimonyCosts};
+use crate::{ParsimonyError, Result};
+
+/// Amino acid order of the lower triangular PAML matrices.
+const PAML_AMINOACIDS: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+/// Nucleotide order of the lower triangular PAML matrices.
+const PAML_NUCLEOTIDES: &[u8] = b"TCAG";
+
+/// Scoring from a user-supplied cost matrix with fixed gap costs, the same costs are used for
+/// every branch length.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParsimonyCostsMatrix {
+    costs: BranchCostsMatrix,
+}
+
+impl ParsimonyCostsMatrix {
+    /// Costs between the `symbols`, the rows and columns of `costs` follow the order of the
+    /// symbols. Lowercase symbols are matched like uppercase ones.
+    pub fn new(symbols: &[u8], costs: DMatrix<f64>, gap_open: f64, gap_ext: f64) -> Result<Self> {
+        if costs.nrows() != symbols.len() || costs.ncols() != symbols.len() {
+            return Err(ParsimonyError::AlphabetMismatch(format!(
+                "The cost matrix is {}x{} but there are {} symbols.",
+                costs.nrows(),
+                costs.ncols(),
+                symbols.len()
+            )));
+        }
+        let mut index = [usize::MAX; 256];
+        for (i, symbol) in symbols.iter().enumerate() {
+            index[symbol.to_ascii_uppercase() as usize] = i;
+        }
+        Ok(ParsimonyCostsMatrix {
+            costs: BranchCostsMatrix {
+                symbols: symbols.to_ascii_uppercase(),
+                index,
+                avg_cost: costs.mean(),
+                max_cost: costs.max(),
+                gap_open,
+                gap_ext,
+                costs,
+            },
+        })
+    }
+
+    /// Reads a cost matrix in EMBOSS or PAML format, see `parse_cost_matrix`. Not available on
+    /// wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path, gap_open: f64, gap_ext: f64) -> Result<Self> {
+        info!("Reading the cost matrix from {}.", path.display());
+        let (symbols, costs) = parse_cost_matrix(&fs::read_to_string(path)?)?;
+        info!(
+            "Read a cost matrix for the symbols {}.",
+            String::from_utf8_lossy(&symbols)
+        );
+        Self::new(&symbols, costs, gap_open, gap_ext)
+    }
+
+    /// Costs from one of the classic protein scoring matrices BLOSUM62, BLOSUM45 or PAM250. A
+    /// similarity score s(i, j) becomes the cost (s(i, i) + s(j, j)) / 2 - s(i, j), so identical
+    /// residues cost nothing, and like for the substitution models the gap costs are the gap
+    /// multipliers times the average cost. The matrices do not depend on the branch length, so
+    /// the gap costs cannot follow it either.
+    pub fn from_score_matrix(name: &str, gap_mult: &GapMultipliers) -> Result<Self> {
+        if gap_mult.branch_slopes.is_some() {
+            return Err(ParsimonyError::InvalidParameters(
+                "Branch length dependent gap costs need a substitution model.".to_string(),
+            ));
+        }
+        let Some(scores) = score_matrix(name) else {
+            return Err(ParsimonyError::UnknownModel(format!(
+                "Unknown scoring matrix {}, the available ones are {}.",
+                name,
+                SCORE_MATRIX_NAMES.join(", ")
+            )));
+        };
+        let costs = DMatrix::from_fn(20, 20, |i, j| {
+            (scores[i][i] + scores[j][j]) as f64 / 2.0 - scores[i][j] as f64
+        });
+        let avg_cost = costs.mean();
+        info!(
+            "Setting up the parsimony scoring from the {} matrix with average cost {}.",
+            name.to_uppercase(),
+            avg_cost
+        );
+        Self::new(
+            SCORE_MATRIX_AMINOACIDS,
+            costs,
+            gap_mult.open * avg_cost,
+            gap_mult.ext * avg_cost,
+        )
+    }
+
+    /// Fails if any of the `symbols`, e.g. the residues of the data type, has no costs.
+    pub fn check_symbols(&self, symbols: &[u8]) -> Result<()> {
+        let missing: Vec<char> = symbols
+            .iter()
+            .filter(|&&symbol| self.costs.index[symbol.to_ascii_uppercase() as usize] == usize::MAX)
+            .map(|&symbol| symbol as char)
+            .collect();
+        if !missing.is_empty() {
+            return Err(ParsimonyError::AlphabetMismatch(format!(
+                "The cost matrix has no costs for {}.",
+                missing.iter().collect::<String>()
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn symbols(&self) -> &[u8] {
+        &self.costs.symbols
+    }
+}
+
+impl ParsimonyCosts for ParsimonyCostsMatrix {
+    fn get_branch_costs(&self, _: f64) -> &dyn BranchParsimonyCosts {
+        &self.costs
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct BranchCostsMatrix {
+    symbols: Vec<u8>,
+    index: [usize; 256],
+    avg_cost: f64,
+    max_cost: f64,
+    gap_open: f64,
+    gap_ext: f64,
+    costs: DMatrix<f64>,
+}
+
+impl BranchParsimonyCosts for BranchCostsMatrix {
+    /// Symbols without costs, which `check_symbols` rules out for the residues of the data
+    /// type, cost as much as the most expensive substitution.
+    fn match_cost(&self, i: u8, j: u8) -> f64 {
+        match (self.index[i as usize], self.index[j as usize]) {
+            (i, j) if i != usize::MAX && j != usize::MAX => self.costs[(i, j)],
+            _ => self.max_cost,
+        }
+    }
+
+    fn gap_ext_cost(&self) -> f64 {
+        self.gap_ext
+    }
+
+    fn gap_open_cost(&self) -> f64 {
+        self.gap_open
+    }
+
+    fn avg_cost(&self) -> f64 {
+        self.avg_cost
+    }
+}
+
+/// Whether `name` is one of the built-in protein scoring matrices rather than a substitution
+/// model.
+pub fn is_score_matrix(name: &str) -> bool {
+    score_matrix(name).is_some()
+}
+
+fn parse_number(token: &str) -> Result<f64> {
+    match token.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(ParsimonyError::InvalidInput(format!(
+            "Invalid entry \"{}\" in the cost matrix.",
+            token
+        ))),
+    }
+}
+
+/// Parses a cost matrix, either a square EMBOSS matrix with a header line of symbols and every
+/// row starting with its symbol, or a PAML lower triangle without the diagonal for the 20 amino
+/// acids or 4 nucleotides in the PAML order, with a zero diagonal. Lines starting with # are
+/// comments. The equilibrium frequencies that may follow the PAML triangle are checked but not
+/// used, anything after them is ignored.
+pub(crate) fn parse_cost_matrix(text: &str) -> Result<(Vec<u8>, DMatrix<f64>)> {
+    let lines: Vec<Vec<&str>> = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .filter(|tokens| !tokens.is_empty())
+        .collect();
+    let Some(first) = lines.first() else {
+        return Err(ParsimonyError::InvalidInput(
+            "The cost matrix is empty.".to_string(),
+        ));
+    };
+    if first.iter().all(|token| token.parse::<f64>().is_err()) {
+        let symbols: Vec<u8> = first
+            .iter()
+            .map(|token| match token.as_bytes() {
+                [symbol] => Ok(*symbol),
+                _ => Err(ParsimonyError::InvalidInput(format!(
+                    "Invalid symbol \"{}\" in the cost matrix header.",
+                    token
+                ))),
+            })
+            .collect::<Result<_>>()?;
+        let n = symbols.len();
+        if lines.len() != n + 1 {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "The cost matrix has {} rows for {} symbols.",
+                lines.len() - 1,
+                n
+            )));
+        }
+        let mut costs = DMatrix::zeros(n, n);
+        for row in &lines[1..] {
+            let Some(i) = symbols.iter().position(|s| row[0].as_bytes() == [*s]) else {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The cost matrix row {} is not in the header.",
+                    row[0]
+                )));
+            };
+            if row.len() != n + 1 {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The cost matrix row {} has {} entries instead of {}.",
+                    row[0],
+                    row.len() - 1,
+                    n
+                )));
+            }
+            for (j, token) in row[1..].iter().enumerate() {
+                costs[(i, j)] = parse_number(token)?;
+            }
+        }
+        return Ok((symbols, costs));
+    }
+    // The frequency line has as many entries as the next row of the triangle would, so it is
+    // only told apart by the size of the matrix.
+    let rows = lines
+        .iter()
+        .enumerate()
+        .take_while(|(i, tokens)| tokens.len() == i + 1)
+        .count();
+    let (symbols, rows) = match rows {
+        19.. => (PAML_AMINOACIDS, 19),
+        3 | 4 => (PAML_NUCLEOTIDES, 3),
+        rows => {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "A lower triangular cost matrix must be for 20 amino acids or 4 nucleotides, \
+                 not {}.",
+                rows + 1
+            )))
+        }
+    };
+    let freqs: Vec<&str> = lines[rows..].iter().flatten().copied().collect();
+    if !freqs.is_empty() {
+        if freqs.len() < rows + 1 {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "The cost matrix has {} equilibrium frequencies instead of {}.",
+                freqs.len(),
+                rows + 1
+            )));
+        }
+        for token in &freqs[..rows + 1] {
+            parse_number(token)?;
+        }
+    }
+    let mut costs = DMatrix::zeros(rows + 1, rows + 1);
+    for (i, tokens) in lines[..rows].iter().enumerate() {
+        for (j, token) in tokens.iter().enumerate() {
+            let cost = parse_number(token)?;
+            costs[(i + 1, j)] = cost;
+            costs[(j, i + 1)] = cost;
+        }
+    }
+    Ok((symbols.to_vec(), costs))
+}
+
+#[cfg(test)]
+mod parsimony_costs_matrix_test {
+    use super::{is_score_matrix, parse_cost_matrix, ParsimonyCostsMatrix};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
+    use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
+    use phylo::sequences::AMINOACIDS_STR;
+
+    #[test]
+    fn emboss_matrix() {
+        let text =
+            "# costs\n   A  C  G  T\nA  0  2  1  2\nC  2  0  2  1\nT  2  1  2  0\nG  1  2  0  2\n";
+        let (symbols, costs) = parse_cost_matrix(text).unwrap();
+        assert_eq!(symbols, b"ACGT");
+        assert_eq!(costs[(3, 1)], 1.0);
+        assert_eq!(costs[(2, 0)], 1.0);
+        let scoring = ParsimonyCostsMatrix::new(&symbols, costs, 3.0, 1.0).unwrap();
+        let branch = scoring.get_branch_costs(0.7);
+        assert_eq!(branch.match_cost(b'a', b'g'), 1.0);
+        assert_eq!(branch.match_cost(b'T', b'A'), 2.0);
+        assert_eq!(branch.gap_open_cost(), 3.0);
+        assert_eq!(branch.avg_cost(), 1.25);
+        assert!(scoring.check_symbols(b"ACGT").is_ok());
+        assert!(scoring.check_symbols(b"ACGU").is_err());
+        assert_eq!(branch.match_cost(b'U', b'A'), 2.0);
+        assert_eq!(branch.match_cost(255, 255), 2.0);
+
+        assert!(parse_cost_matrix("A C\nA 0 1\n").is_err());
+        assert!(parse_cost_matrix("A C\nA 0 1\nC 1 x\n").is_err());
+        assert!(parse_cost_matrix("").is_err());
+    }
+
+    #[test]
+    fn blosum_matrix() {
+        let scoring =
+            ParsimonyCostsMatrix::from_score_matrix("blosum62", &GapMultipliers::new(2.0, 0.5))
+                .unwrap();
+        let branch = scoring.get_branch_costs(1.0);
+        assert_eq!(branch.match_cost(b'W', b'W'), 0.0);
+        assert_eq!(branch.match_cost(b'I', b'V'), 1.0);
+        assert_eq!(branch.match_cost(b'W', b'G'), 10.5);
+        assert_eq!(branch.match_cost(b'Y', b'F'), branch.match_cost(b'F', b'Y'));
+        assert_eq!(branch.gap_open_cost(), 2.0 * branch.avg_cost());
+        assert!(scoring.check_symbols(AMINOACIDS_STR.as_bytes()).is_ok());
+        assert!(is_score_matrix("PAM250"));
+        assert!(!is_score_matrix("WAG"));
+        assert!(
+            ParsimonyCostsMatrix::from_score_matrix("PAM30", &GapMultipliers::new(2.0, 0.5))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn paml_matrix() {
+        let text = "1\n2 3\n4 5 6\n\n0.25 0.25 0.25 0.25\n";
+        let (symbols, costs) = parse_cost_matrix(text).unwrap();
+        assert_eq!(symbols, b"TCAG");
+        assert_eq!(costs[(1, 0)], 1.0);
+        assert_eq!(costs[(0, 1)], 1.0);
+        assert_eq!(costs[(3, 2)], 6.0);
+        assert_eq!(costs[(2, 2)], 0.0);
+        assert_eq!(costs.nrows(), 4);
+        assert_eq!(parse_cost_matrix("1\n2 3\n4 5 6\n").unwrap().1, costs);
+        assert!(parse_cost_matrix("1\n2 3\n").is_err());
+        assert!(parse_cost_matrix("1\n2 3\n4 5 6\n0.5 0.5\n").is_err());
+
+        let triangle: String = (1..20)
+            .map(|i| vec!["1"; i].join(" ") + "\n")
+            .collect::<String>();
+        let freqs = vec!["0.05"; 20].join(" ");
+        let (symbols, costs) = parse_cost_matrix(&(triangle + &freqs + "\nWAG\n")).unwrap();
+        assert_eq!(symbols.len(), 20);
+        assert_eq!(costs[(19, 18)], 1.0);
+        assert_eq!(costs[(19, 19)], 0.0);
+    }
+}
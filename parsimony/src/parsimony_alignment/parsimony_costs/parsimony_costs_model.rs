@@ -1,4 +1,6 @@
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
 
 use log::{debug, info};
 use nalgebra::{Const, DMatrix, DimMin};
@@ -10,30 +12,122 @@ use phylo::substitution_models::{
     protein_models::{aminoacid_index, ProteinSubstModel},
     SubstitutionModel,
 };
+use phylo::tree::get_percentiles_rounded;
 use phylo::Rounding;
 
+#[cfg(not(target_arch = "wasm32"))]
+use super::cost_cache::{CachedCosts, CostCache, CostKey};
+use super::dna_models::{dna_model_rate_matrix, MODEL_NUCLEOTIDES};
+use super::gamma::discrete_gamma_rates;
+use super::protein_models::{
+    protein_model_rate_matrix, protein_model_rate_matrix_w_freqs, MODEL_AMINOACIDS,
+};
+use super::simd;
+use crate::parsimony_alignment::alphabet::Alphabet;
+use crate::parsimony_alignment::parsimony_sets::{
+    Bits, ParsimonySet, AMINOACID_BITS, NUCLEOTIDE_BITS,
+};
 use crate::parsimony_alignment::{BranchParsimonyCosts, ParsimonyCosts};
-use crate::{cmp_f64, f64_h, Result};
+use crate::{cmp_f64, f64_h, ParsimonyError, Result};
 
 type CostMatrix = DMatrix<f64>;
 
-#[derive(Clone, Debug, PartialEq)]
+/// Computes the costs of a branch length when they are first looked up.
+type BranchCostsGenerator<const N: usize> = Arc<dyn Fn(f64) -> BranchCostsWModel<N> + Send + Sync>;
+
+/// Scoring with a cost matrix for each of a set of branch lengths. The matrices of a
+/// substitution model are only computed when a branch first uses them, so that setups with
+/// many categories of which few are used start faster and keep less in memory.
+#[derive(Clone)]
 pub struct ParsimonyCostsWModel<const N: usize> {
     times: Vec<f64>,
-    costs: HashMap<OrderedFloat<f64>, BranchCostsWModel<N>>,
+    costs: HashMap<OrderedFloat<f64>, OnceLock<BranchCostsWModel<N>>>,
+    generator: Option<BranchCostsGenerator<N>>,
 }
 
 pub type DNAParsCosts = ParsimonyCostsWModel<4>;
 pub type ProteinParsCosts = ParsimonyCostsWModel<20>;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct GapMultipliers {
     pub(crate) open: f64,
     pub(crate) ext: f64,
+    pub(crate) branch_slopes: Option<(f64, f64)>,
 }
 
 impl GapMultipliers {
     pub fn new(open: f64, ext: f64) -> Self {
-        GapMultipliers { open, ext }
+        GapMultipliers {
+            open,
+            ext,
+            branch_slopes: None,
+        }
+    }
+
+    /// Makes the gap costs affine in the branch length t instead of proportional to the average
+    /// substitution cost: `open + open_slope * t` and `ext + ext_slope * t`, but at least zero.
+    pub fn with_branch_slopes(self, open_slope: f64, ext_slope: f64) -> Self {
+        GapMultipliers {
+            branch_slopes: Some((open_slope, ext_slope)),
+            ..self
+        }
+    }
+
+    /// Gap opening and extension costs of a branch of length `time` with the average
+    /// substitution cost `avg_cost`.
+    pub(crate) fn costs(&self, time: f64, avg_cost: f64) -> (f64, f64) {
+        match self.branch_slopes {
+            Some((open_slope, ext_slope)) => (
+                (self.open + open_slope * time).max(0.0),
+                (self.ext + ext_slope * time).max(0.0),
+            ),
+            None => (self.open * avg_cost, self.ext * avg_cost),
+        }
+    }
+}
+
+/// Among-site rate variation of the substitution model. The costs of a branch come from the
+/// substitution probabilities averaged over equally probable rate categories.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GammaRates {
+    rates: Vec<f64>,
+}
+
+impl GammaRates {
+    /// Discrete gamma rate categories with shape `alpha`, the rate of a category is its mean.
+    pub fn new(alpha: f64, categories: usize) -> Result<Self> {
+        if alpha <= 0.0 || !alpha.is_finite() {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "The gamma shape must be positive, got {}.",
+                alpha
+            )));
+        }
+        if categories == 0 {
+            return Err(ParsimonyError::InvalidParameters(
+                "At least one gamma rate category is needed.".to_string(),
+            ));
+        }
+        let rates = discrete_gamma_rates(alpha, categories);
+        info!(
+            "Using {} gamma rate categories with shape {}, the rates are {:?}.",
+            categories, alpha, rates
+        );
+        Ok(GammaRates { rates })
+    }
+
+    /// A single category with rate one, i.e. no rate variation.
+    pub fn none() -> Self {
+        GammaRates { rates: vec![1.0] }
+    }
+
+    pub fn rates(&self) -> &[f64] {
+        &self.rates
+    }
+}
+
+impl Default for GammaRates {
+    fn default() -> Self {
+        Self::none()
     }
 }
 
@@ -45,6 +139,26 @@ impl DNAParsCosts {
         times: &[f64],
         zero_diag: bool,
         rounding: &Rounding,
+    ) -> Result<Self> {
+        Self::new_w_gamma(
+            model_name,
+            model_params,
+            gap_mult,
+            times,
+            zero_diag,
+            rounding,
+            &GammaRates::none(),
+        )
+    }
+
+    pub fn new_w_gamma(
+        model_name: &str,
+        model_params: &[f64],
+        gap_mult: &GapMultipliers,
+        times: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
     ) -> Result<Self> {
         info!(
             "Setting up the parsimony scoring from the {} substitution model.",
@@ -58,23 +172,55 @@ impl DNAParsCosts {
             "The scoring matrix entries will {}be rounded to the closest integer value.",
             if rounding.round { "" } else { "not " }
         );
+        info!(
+            "Scoring matrices from the {} substitution model for {:?} branch lengths are computed on first use.",
+            model_name, times
+        );
+        if let Some(q) = dna_model_rate_matrix(model_name, model_params)? {
+            let index = Alphabet::new(MODEL_NUCLEOTIDES)?.index();
+            return Ok(Self::from_rate_matrix(
+                index, q, times, gap_mult, zero_diag, rounding, gamma,
+            ));
+        }
         let model = DNASubstModel::new(model_name, model_params, false)?;
-        let costs = generate_costs(
-            &model,
+        Ok(Self::from_model(
+            model,
+            nucleotide_index(),
             times,
             gap_mult,
-            nucleotide_index(),
             zero_diag,
             rounding,
-        );
-        info!(
-            "Created scoring matrices from the {} substitution model for {:?} branch lengths.",
-            model_name, times
-        );
-        Ok(DNAParsCosts {
-            times: sort_times(times),
-            costs,
-        })
+            gamma,
+        ))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DNAParsCosts {
+    /// Like `new_w_gamma`, but the cost matrix of a branch length is read from the cache on its
+    /// first use, or computed and then stored in the cache if it is not there.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_cached(
+        model_name: &str,
+        model_params: &[f64],
+        gap_mult: &GapMultipliers,
+        times: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
+        cache: &CostCache,
+    ) -> Result<Self> {
+        let key = CostKey::new(model_name, model_params, gamma.rates(), zero_diag, rounding);
+        let scoring = Self::new_w_gamma(
+            model_name,
+            model_params,
+            gap_mult,
+            times,
+            zero_diag,
+            rounding,
+            gamma,
+        )?;
+        Ok(scoring.with_cache(cache, key, gap_mult))
     }
 }
 
@@ -85,32 +231,312 @@ impl ProteinParsCosts {
         times: &[f64],
         zero_diag: bool,
         rounding: &Rounding,
+    ) -> Result<Self> {
+        Self::new_w_gamma(
+            model_name,
+            gap_mult,
+            times,
+            zero_diag,
+            rounding,
+            &GammaRates::none(),
+        )
+    }
+
+    pub fn new_w_gamma(
+        model_name: &str,
+        gap_mult: &GapMultipliers,
+        times: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
     ) -> Result<Self> {
         info!(
             "Setting up the parsimony scoring from the {} substitution model.",
             model_name
         );
+        info!(
+            "Scoring matrices from the {} substitution model for {:?} branch lengths are computed on first use.",
+            model_name, times
+        );
+        if let Some(q) = protein_model_rate_matrix(model_name) {
+            let index = Alphabet::new(MODEL_AMINOACIDS)?.index();
+            return Ok(Self::from_rate_matrix(
+                index, q, times, gap_mult, zero_diag, rounding, gamma,
+            ));
+        }
         let model = ProteinSubstModel::new(model_name, &[], false)?;
-        let costs = generate_costs(
-            &model,
+        Ok(Self::from_model(
+            model,
+            aminoacid_index(),
             times,
             gap_mult,
-            aminoacid_index(),
             zero_diag,
             rounding,
+            gamma,
+        ))
+    }
+
+    /// Like `new_w_gamma`, but the equilibrium frequencies of the model are replaced by `freqs`
+    /// in the order of `MODEL_AMINOACIDS`, e.g. the empirical frequencies of the sequences for
+    /// WAG+F or LG+F. The costs are not cached, they depend on the data set.
+    pub fn new_w_frequencies(
+        model_name: &str,
+        freqs: &[f64; 20],
+        gap_mult: &GapMultipliers,
+        times: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
+    ) -> Result<Self> {
+        if freqs.iter().any(|&freq| freq <= 0.0) || (freqs.iter().sum::<f64>() - 1.0).abs() > 1e-4 {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "The amino acid frequencies must be positive and sum to one, got {:?}.",
+                freqs
+            )));
+        }
+        info!(
+            "Setting up the parsimony scoring from the {}+F substitution model.",
+            model_name
         );
+        let q = match protein_model_rate_matrix_w_freqs(model_name, freqs) {
+            Some(q) => q,
+            None => rate_matrix_w_freqs(
+                &ProteinSubstModel::new(model_name, &[], false)?,
+                MODEL_AMINOACIDS,
+                freqs,
+            ),
+        };
+        let index = Alphabet::new(MODEL_AMINOACIDS)?.index();
+        Ok(Self::from_rate_matrix(
+            index, q, times, gap_mult, zero_diag, rounding, gamma,
+        ))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ProteinParsCosts {
+    /// Like `new_w_gamma`, see `DNAParsCosts::new_cached`.
+    pub fn new_cached(
+        model_name: &str,
+        gap_mult: &GapMultipliers,
+        times: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
+        cache: &CostCache,
+    ) -> Result<Self> {
+        let key = CostKey::new(model_name, &[], gamma.rates(), zero_diag, rounding);
+        let scoring = Self::new_w_gamma(model_name, gap_mult, times, zero_diag, rounding, gamma)?;
+        Ok(scoring.with_cache(cache, key, gap_mult))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<const N: usize> ParsimonyCostsWModel<N> {
+    /// Looks up the costs of a branch length in the cache before computing them, and stores the
+    /// computed costs in it. The gap costs are not cached, they come from `gap_mult` and the
+    /// average costs.
+    fn with_cache(self, cache: &CostCache, key: CostKey, gap_mult: &GapMultipliers) -> Self {
+        let Some(generator) = self.generator.clone() else {
+            return self;
+        };
         info!(
-            "Created scoring matrices from the {} substitution model for {:?} branch lengths.",
-            model_name, times
+            "Using the cost matrices cached in {}.",
+            cache.dir().display()
         );
-        debug!("The scoring matrices are: {:?}", costs);
-        Ok(ProteinParsCosts {
-            times: sort_times(times),
+        let (cache, gap_mult) = (cache.clone(), gap_mult.clone());
+        Self::lazy(&self.times, move |time| {
+            if let Some(entry) = cache.load(&key, time, N) {
+                return BranchCostsWModel::new(
+                    entry.index,
+                    time,
+                    entry.avg_cost,
+                    entry.costs,
+                    &gap_mult,
+                );
+            }
+            let branch_costs = generator(time);
+            let entry = CachedCosts {
+                index: branch_costs.index,
+                avg_cost: branch_costs.avg_cost,
+                costs: branch_costs.costs.clone(),
+            };
+            cache.store(&key, time, &entry);
+            branch_costs
+        })
+    }
+}
+
+impl<const N: usize> ParsimonyCostsWModel<N> {
+    /// Scoring for a custom alphabet from user-defined cost matrices, one `N`x`N` matrix per
+    /// branch length with the rows and columns in the order of the alphabet symbols. Like for
+    /// the substitution models the gap costs are the gap multipliers times the average entry of
+    /// the matrix.
+    pub fn from_cost_matrices(
+        alphabet: &Alphabet,
+        matrices: &[(f64, DMatrix<f64>)],
+        gap_mult: &GapMultipliers,
+    ) -> Result<Self> {
+        if alphabet.len() != N {
+            return Err(ParsimonyError::AlphabetMismatch(format!(
+                "The alphabet has {} symbols but the scoring is for {}.",
+                alphabet.len(),
+                N
+            )));
+        }
+        if matrices.is_empty() {
+            return Err(ParsimonyError::InvalidParameters(
+                "At least one cost matrix is needed.".to_string(),
+            ));
+        }
+        for (time, matrix) in matrices {
+            if matrix.nrows() != N || matrix.ncols() != N {
+                return Err(ParsimonyError::AlphabetMismatch(format!(
+                    "The cost matrix for branch length {} is {}x{} instead of {}x{}.",
+                    time,
+                    matrix.nrows(),
+                    matrix.ncols(),
+                    N,
+                    N
+                )));
+            }
+        }
+        let scoring = Self::from_indexed_matrices(alphabet.index(), matrices, gap_mult);
+        info!(
+            "Created scoring matrices for a custom alphabet of {} symbols for {:?} branch lengths.",
+            N,
+            scoring.times()
+        );
+        Ok(scoring)
+    }
+
+    fn from_indexed_matrices(
+        index: [i32; 255],
+        matrices: &[(f64, DMatrix<f64>)],
+        gap_mult: &GapMultipliers,
+    ) -> Self {
+        let costs = matrices
+            .iter()
+            .map(|(time, matrix)| {
+                let branch_costs =
+                    BranchCostsWModel::new(index, *time, matrix.mean(), matrix.clone(), gap_mult);
+                (f64_h::from(*time), OnceLock::from(branch_costs))
+            })
+            .collect();
+        let times: Vec<f64> = matrices.iter().map(|(time, _)| *time).collect();
+        ParsimonyCostsWModel {
+            times: sort_times(&times),
             costs,
+            generator: None,
+        }
+    }
+
+    /// Scoring for the branch lengths `times` whose costs are computed by `generator` when they
+    /// are first looked up.
+    fn lazy(
+        times: &[f64],
+        generator: impl Fn(f64) -> BranchCostsWModel<N> + Send + Sync + 'static,
+    ) -> Self {
+        ParsimonyCostsWModel {
+            times: sort_times(times),
+            costs: times
+                .iter()
+                .map(|&time| (f64_h::from(time), OnceLock::new()))
+                .collect(),
+            generator: Some(Arc::new(generator)),
+        }
+    }
+
+    /// Lazy scoring from the rate matrix `q` of a model defined in this crate, see
+    /// `rate_matrix_costs`.
+    fn from_rate_matrix(
+        index: [i32; 255],
+        q: CostMatrix,
+        times: &[f64],
+        gap_mult: &GapMultipliers,
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
+    ) -> Self {
+        let (gap_mult, rounding, rates) = (gap_mult.clone(), rounding.clone(), gamma.clone());
+        Self::lazy(times, move |time| {
+            let (_, (costs, avg_cost)) =
+                rate_matrix_costs(&q, &[time], rates.rates(), zero_diag, &rounding)
+                    .pop()
+                    .unwrap();
+            BranchCostsWModel::new(index, time, avg_cost, costs, &gap_mult)
         })
     }
+
+    /// Lazy scoring from a substitution model of the phylo crate.
+    fn from_model(
+        model: SubstitutionModel<N>,
+        index: [i32; 255],
+        times: &[f64],
+        gap_mult: &GapMultipliers,
+        zero_diag: bool,
+        rounding: &Rounding,
+        gamma: &GammaRates,
+    ) -> Self
+    where
+        Const<N>: DimMin<Const<N>, Output = Const<N>>,
+        SubstitutionModel<N>: Send + Sync + 'static,
+    {
+        let (gap_mult, rounding, rates) = (gap_mult.clone(), rounding.clone(), gamma.clone());
+        Self::lazy(times, move |time| {
+            model_branch_costs(
+                &model,
+                time,
+                &gap_mult,
+                index,
+                zero_diag,
+                &rounding,
+                rates.rates(),
+            )
+        })
+    }
+
+    /// Costs of the precomputed branch length `time`, computed now if this is their first use.
+    fn branch_costs(&self, time: f64) -> &BranchCostsWModel<N> {
+        self.costs[&f64_h::from(time)].get_or_init(|| {
+            debug!("Computing the scoring matrix for time {}.", time);
+            let generator = self
+                .generator
+                .as_ref()
+                .expect("scorings without a generator have all their costs");
+            generator(time)
+        })
+    }
+}
+
+impl<const N: usize> fmt::Debug for ParsimonyCostsWModel<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let computed: Vec<(f64, &BranchCostsWModel<N>)> = self
+            .times
+            .iter()
+            .filter_map(|&time| Some((time, self.costs[&f64_h::from(time)].get()?)))
+            .collect();
+        f.debug_struct("ParsimonyCostsWModel")
+            .field("times", &self.times)
+            .field("computed", &computed)
+            .finish()
+    }
+}
+
+/// Scorings are equal if they have the same costs for the same branch lengths, comparing them
+/// computes all their costs.
+impl<const N: usize> PartialEq for ParsimonyCostsWModel<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.times == other.times
+            && self
+                .times
+                .iter()
+                .all(|&time| self.branch_costs(time) == other.branch_costs(time))
+    }
 }
 
+/// Costs of the model for all the branch lengths at once.
+#[cfg(test)]
 fn generate_costs<const N: usize>(
     model: &SubstitutionModel<N>,
     times: &[f64],
@@ -118,39 +544,360 @@ fn generate_costs<const N: usize>(
     index: [i32; 255],
     zero_diag: bool,
     rounding: &Rounding,
+    rates: &[f64],
 ) -> HashMap<OrderedFloat<f64>, BranchCostsWModel<N>>
 where
     Const<N>: DimMin<Const<N>, Output = Const<N>>,
 {
-    model
-        .generate_scorings(times, zero_diag, rounding)
+    times
+        .iter()
+        .map(|&time| {
+            (
+                f64_h::from(time),
+                model_branch_costs(model, time, gap_mult, index, zero_diag, rounding, rates),
+            )
+        })
+        .collect()
+}
+
+/// Costs of the model for the branch length `time`.
+fn model_branch_costs<const N: usize>(
+    model: &SubstitutionModel<N>,
+    time: f64,
+    gap_mult: &GapMultipliers,
+    index: [i32; 255],
+    zero_diag: bool,
+    rounding: &Rounding,
+    rates: &[f64],
+) -> BranchCostsWModel<N>
+where
+    Const<N>: DimMin<Const<N>, Output = Const<N>>,
+{
+    let (_, (branch_costs, avg_cost)) = if rates == [1.0] {
+        model
+            .generate_scorings(&[time], zero_diag, rounding)
+            .into_iter()
+            .next()
+            .unwrap()
+    } else {
+        rate_category_scorings(model, &[time], rates, zero_diag, rounding)
+            .pop()
+            .unwrap()
+    };
+    let branch_costs = BranchCostsWModel::new(index, time, avg_cost, branch_costs, gap_mult);
+    debug!("Average cost for time {} is {}", time, avg_cost);
+    debug!(
+        "Gap open cost for time {} is {}",
+        time, branch_costs.gap_open
+    );
+    debug!("Gap ext cost for time {} is {}", time, branch_costs.gap_ext);
+    branch_costs
+}
+
+/// Scorings of the model with the substitution probabilities averaged over the rate categories,
+/// a category with rate r has the probabilities of the branch length r * t. The probabilities
+/// are recovered from the unrounded costs of the model.
+fn rate_category_scorings<const N: usize>(
+    model: &SubstitutionModel<N>,
+    times: &[f64],
+    rates: &[f64],
+    zero_diag: bool,
+    rounding: &Rounding,
+) -> Vec<(OrderedFloat<f64>, (CostMatrix, f64))>
+where
+    Const<N>: DimMin<Const<N>, Output = Const<N>>,
+{
+    let scaled_times: Vec<f64> = rates
+        .iter()
+        .flat_map(|rate| times.iter().map(move |time| rate * time))
+        .collect();
+    let probabilities: HashMap<OrderedFloat<f64>, CostMatrix> = model
+        .generate_scorings(&scaled_times, false, &Rounding::none())
         .into_iter()
-        .map(|(key, (branch_costs, avg_cost))| {
-            debug!("Average cost for time {} is {}", key, avg_cost);
-            debug!(
-                "Gap open cost for time {} is {}",
-                key,
-                gap_mult.open * avg_cost
-            );
-            debug!(
-                "Gap ext cost for time {} is {}",
-                key,
-                gap_mult.ext * avg_cost
-            );
+        .map(|(key, (costs, _))| (key, costs.map(|cost| (-cost).exp())))
+        .collect();
+    times
+        .iter()
+        .map(|&time| {
+            let p = rates.iter().fold(CostMatrix::zeros(N, N), |sum, rate| {
+                sum + &probabilities[&f64_h::from(rate * time)]
+            }) / rates.len() as f64;
             (
-                key,
-                BranchCostsWModel {
-                    index,
-                    avg_cost,
-                    gap_open: gap_mult.open * avg_cost,
-                    gap_ext: gap_mult.ext * avg_cost,
-                    costs: branch_costs,
-                },
+                f64_h::from(time),
+                probability_costs(&p, zero_diag, rounding),
             )
         })
         .collect()
 }
 
+/// Costs as the negative log of the substitution probabilities `p`, rounded and with a zero
+/// diagonal as requested, together with their average cost. Like for the models of the phylo
+/// crate the average includes the costs of the diagonal before it is zeroed.
+fn probability_costs(p: &CostMatrix, zero_diag: bool, rounding: &Rounding) -> (CostMatrix, f64) {
+    let mut costs = p.map(|p| -p.ln());
+    if rounding.round {
+        let factor = 10f64.powi(rounding.digits as i32);
+        costs = costs.map(|cost| (cost * factor).round() / factor);
+    }
+    let avg_cost = costs.mean();
+    if zero_diag {
+        costs.fill_diagonal(0.0);
+    }
+    (costs, avg_cost)
+}
+
+/// Rate matrix from symmetric exchangeabilities and equilibrium frequencies, normalised to one
+/// expected substitution per unit of time.
+pub(crate) fn normalised_rate_matrix(exchangeabilities: &CostMatrix, freqs: &[f64]) -> CostMatrix {
+    let n = freqs.len();
+    let mut q = DMatrix::from_fn(n, n, |i, j| {
+        if i == j {
+            0.0
+        } else {
+            exchangeabilities[(i, j)] * freqs[j]
+        }
+    });
+    for i in 0..n {
+        q[(i, i)] = -q.row(i).sum();
+    }
+    let rate = -(0..n).map(|i| freqs[i] * q[(i, i)]).sum::<f64>();
+    q / rate
+}
+
+/// Rate matrix of a reversible model of the phylo crate with its equilibrium frequencies
+/// replaced by `freqs`, with the states in the order of `chars`. The exchangeabilities are
+/// recovered from the rates, q_ij / q_ji = pi_j / pi_i gives the model frequencies up to a
+/// constant factor, which the normalisation cancels.
+fn rate_matrix_w_freqs<const N: usize>(
+    model: &SubstitutionModel<N>,
+    chars: &[u8],
+    freqs: &[f64],
+) -> CostMatrix
+where
+    Const<N>: DimMin<Const<N>, Output = Const<N>>,
+{
+    let rate = |i: usize, j: usize| model.get_rate(chars[i], chars[j]);
+    let model_freqs: Vec<f64> = (0..N)
+        .map(|j| if j == 0 { 1.0 } else { rate(0, j) / rate(j, 0) })
+        .collect();
+    let exchangeabilities = DMatrix::from_fn(N, N, |i, j| {
+        if i == j {
+            0.0
+        } else {
+            rate(i, j) / model_freqs[j]
+        }
+    });
+    normalised_rate_matrix(&exchangeabilities, freqs)
+}
+
+/// Rate matrix of a reversible model of the phylo crate with its own equilibrium frequencies,
+/// recovered from the rates as in `rate_matrix_w_freqs`.
+fn model_rate_matrix<const N: usize>(model: &SubstitutionModel<N>, chars: &[u8]) -> CostMatrix
+where
+    Const<N>: DimMin<Const<N>, Output = Const<N>>,
+{
+    let rate = |i: usize, j: usize| model.get_rate(chars[i], chars[j]);
+    let freqs: Vec<f64> = (0..N)
+        .map(|j| if j == 0 { 1.0 } else { rate(0, j) / rate(j, 0) })
+        .collect();
+    let total: f64 = freqs.iter().sum();
+    let freqs: Vec<f64> = freqs.iter().map(|freq| freq / total).collect();
+    rate_matrix_w_freqs(model, chars, &freqs)
+}
+
+/// Equilibrium frequencies of a reversible rate matrix, recovered from q_0j / q_j0 = pi_j / pi_0.
+pub(crate) fn rate_matrix_frequencies(q: &CostMatrix) -> Vec<f64> {
+    let freqs: Vec<f64> = (0..q.nrows())
+        .map(|j| if j == 0 { 1.0 } else { q[(0, j)] / q[(j, 0)] })
+        .collect();
+    let total: f64 = freqs.iter().sum();
+    freqs.iter().map(|freq| freq / total).collect()
+}
+
+/// Rate matrix of the named DNA model with the nucleotides in the order of
+/// `MODEL_NUCLEOTIDES`, normalised to one expected substitution per unit of time.
+pub(crate) fn dna_rate_matrix(model_name: &str, model_params: &[f64]) -> Result<CostMatrix> {
+    match dna_model_rate_matrix(model_name, model_params)? {
+        Some(q) => Ok(q),
+        None => Ok(model_rate_matrix(
+            &DNASubstModel::new(model_name, model_params, false)?,
+            MODEL_NUCLEOTIDES,
+        )),
+    }
+}
+
+/// Rate matrix of the named protein model with the amino acids in the order of
+/// `MODEL_AMINOACIDS`, with the equilibrium frequencies replaced by `freqs` if given.
+pub(crate) fn protein_rate_matrix(
+    model_name: &str,
+    freqs: Option<&[f64; 20]>,
+) -> Result<CostMatrix> {
+    let q = match freqs {
+        Some(freqs) => protein_model_rate_matrix_w_freqs(model_name, freqs),
+        None => protein_model_rate_matrix(model_name),
+    };
+    match (q, freqs) {
+        (Some(q), _) => Ok(q),
+        (None, Some(freqs)) => Ok(rate_matrix_w_freqs(
+            &ProteinSubstModel::new(model_name, &[], false)?,
+            MODEL_AMINOACIDS,
+            freqs,
+        )),
+        (None, None) => Ok(model_rate_matrix(
+            &ProteinSubstModel::new(model_name, &[], false)?,
+            MODEL_AMINOACIDS,
+        )),
+    }
+}
+
+/// Cost matrices of the rate matrix `q` for the branch lengths `times`, with the substitution
+/// probabilities averaged over the rate categories `rates`, each with its average cost. Like for
+/// the models of the phylo crate the cost of a substitution is the negative log of its
+/// probability over the branch.
+pub(crate) fn rate_matrix_costs(
+    q: &CostMatrix,
+    times: &[f64],
+    rates: &[f64],
+    zero_diag: bool,
+    rounding: &Rounding,
+) -> Vec<(f64, (CostMatrix, f64))> {
+    times
+        .iter()
+        .map(|&time| {
+            let p = rates
+                .iter()
+                .fold(CostMatrix::zeros(q.nrows(), q.ncols()), |sum, rate| {
+                    sum + (q * (rate * time)).exp()
+                })
+                / rates.len() as f64;
+            (time, probability_costs(&p, zero_diag, rounding))
+        })
+        .collect()
+}
+
+/// How the branch lengths of the cost matrices are chosen from the branch lengths of the tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CategoryStrategy {
+    /// Equal-frequency percentiles of the branch lengths.
+    #[default]
+    Percentiles,
+    /// Means of the clusters found by k-means, better suited to skewed distributions where the
+    /// percentiles crowd into the bulk of short branches.
+    KMeans,
+}
+
+/// Branch lengths to create the cost matrices for: `categories` representatives of
+/// `branch_lengths` chosen by `strategy`, or with 0 categories every distinct positive branch
+/// length so that every branch gets its exact costs. Shorter branches then use the shortest
+/// length.
+pub fn scoring_times(
+    branch_lengths: &[f64],
+    categories: u32,
+    strategy: CategoryStrategy,
+    rounding: &Rounding,
+) -> Vec<f64> {
+    if categories > 0 {
+        return match strategy {
+            CategoryStrategy::Percentiles => {
+                get_percentiles_rounded(branch_lengths, categories, rounding)
+            }
+            CategoryStrategy::KMeans => kmeans_times(branch_lengths, categories, rounding),
+        };
+    }
+    let mut times: Vec<f64> = branch_lengths
+        .iter()
+        .copied()
+        .filter(|&length| length > 0.0)
+        .collect();
+    times.sort_by(cmp_f64());
+    times.dedup();
+    if times.is_empty() {
+        times.push(1.0);
+    }
+    info!(
+        "Using exact costs for {} distinct branch lengths out of {}.",
+        times.len(),
+        branch_lengths.len()
+    );
+    times
+}
+
+/// Cluster means of one-dimensional k-means on the branch lengths, started from the
+/// equal-frequency percentiles so that the result is deterministic. Empty clusters are dropped.
+fn kmeans_times(branch_lengths: &[f64], categories: u32, rounding: &Rounding) -> Vec<f64> {
+    let lengths = sort_times(branch_lengths);
+    if lengths.is_empty() {
+        return vec![1.0];
+    }
+    let k = categories as usize;
+    let mut centres: Vec<f64> = (0..k)
+        .map(|i| lengths[(2 * i + 1) * lengths.len() / (2 * k)])
+        .collect();
+    centres.dedup();
+    for _ in 0..100 {
+        let mut sums = vec![0.0; centres.len()];
+        let mut counts = vec![0usize; centres.len()];
+        for &length in &lengths {
+            let closest = (0..centres.len())
+                .min_by(|&a, &b| {
+                    cmp_f64()(&(centres[a] - length).abs(), &(centres[b] - length).abs())
+                })
+                .unwrap();
+            sums[closest] += length;
+            counts[closest] += 1;
+        }
+        let updated: Vec<f64> = sums
+            .iter()
+            .zip(&counts)
+            .filter(|(_, &count)| count > 0)
+            .map(|(sum, &count)| sum / count as f64)
+            .collect();
+        if updated == centres {
+            break;
+        }
+        centres = updated;
+    }
+    if rounding.round {
+        let factor = 10f64.powi(rounding.digits as i32);
+        centres = centres
+            .iter()
+            .map(|centre| (centre * factor).round() / factor)
+            .collect();
+        centres.dedup();
+    }
+    info!(
+        "Chose the branch lengths {:?} by k-means for {} categories.",
+        centres, categories
+    );
+    centres
+}
+
+/// Branch lengths given by the user to create the cost matrices for, sorted and without
+/// duplicates. Fails on lengths that are not positive.
+pub fn breakpoint_times(breakpoints: &[f64]) -> Result<Vec<f64>> {
+    if let Some(length) = breakpoints
+        .iter()
+        .find(|&&length| length <= 0.0 || !length.is_finite())
+    {
+        return Err(ParsimonyError::InvalidParameters(format!(
+            "The category branch lengths must be positive, got {}.",
+            length
+        )));
+    }
+    let mut times = sort_times(breakpoints);
+    times.dedup();
+    if times.is_empty() {
+        return Err(ParsimonyError::InvalidParameters(
+            "At least one category branch length is needed.".to_string(),
+        ));
+    }
+    info!(
+        "Using the cost matrices for the branch lengths {:?}.",
+        times
+    );
+    Ok(times)
+}
+
 fn sort_times(times: &[f64]) -> Vec<f64> {
     let mut sorted_times = Vec::from(times);
     sorted_times.sort_by(cmp_f64());
@@ -158,6 +905,69 @@ fn sort_times(times: &[f64]) -> Vec<f64> {
 }
 
 impl<const N: usize> ParsimonyCostsWModel<N> {
+    /// Scores every site of the alignment in one of the site rate categories `rates` instead of
+    /// with the same costs: a branch of length t has a cost matrix for each length t * rate,
+    /// and each matched column takes the category of the cheapest one. The gap costs stay those
+    /// of the branch. Sites that are never matched keep the costs of the branch. Only scorings
+    /// that compute their costs on first use can add categories, a single rate changes nothing.
+    pub fn with_site_rates(self, rates: &GammaRates) -> Self {
+        let Some(generator) = self.generator.clone() else {
+            return self;
+        };
+        if rates.rates().len() < 2 {
+            return self;
+        }
+        info!(
+            "Scoring the sites in {} rate categories with the rates {:?}.",
+            rates.rates().len(),
+            rates.rates()
+        );
+        let rates = rates.rates().to_vec();
+        Self::lazy(&self.times, move |time| {
+            let mut costs = generator(time);
+            costs.site_costs = rates.iter().map(|rate| generator(time * rate)).collect();
+            costs
+        })
+    }
+
+    /// Scores the sites of each partition of the sequences with the costs of its own scoring in
+    /// `partitions`, indexed by the site category of the site. The gap costs and the costs of
+    /// the sites outside of the partitions stay those of this scoring, a branch takes the costs
+    /// of each partition for the closest of its branch lengths.
+    pub fn with_partitions(self, partitions: Vec<Self>) -> Result<Self> {
+        if partitions.len() > u8::MAX as usize {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "At most {} partitions are supported, got {}.",
+                u8::MAX,
+                partitions.len()
+            )));
+        }
+        if partitions.is_empty() {
+            return Ok(self);
+        }
+        info!("Scoring the sites of {} partitions.", partitions.len());
+        let times = self.times.clone();
+        Ok(Self::lazy(&times, move |time| {
+            let mut costs = self.branch_costs(time).clone();
+            costs.site_costs = partitions
+                .iter()
+                .map(|partition| {
+                    let costs = partition.branch_costs(partition.find_closest_branch_length(time));
+                    BranchCostsWModel {
+                        site_costs: Vec::new(),
+                        ..costs.clone()
+                    }
+                })
+                .collect();
+            costs
+        }))
+    }
+
+    /// Branch lengths the scoring has cost matrices for, in increasing order.
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
     fn find_closest_branch_length(&self, target: f64) -> f64 {
         debug!("Getting scoring for time {}", target);
         let time = match self
@@ -176,7 +986,11 @@ impl<const N: usize> ParsimonyCostsWModel<N> {
 
 impl<const N: usize> ParsimonyCosts for ParsimonyCostsWModel<N> {
     fn get_branch_costs(&self, branch_length: f64) -> &dyn BranchParsimonyCosts {
-        &self.costs[&f64_h::from(self.find_closest_branch_length(branch_length))]
+        self.branch_costs(self.find_closest_branch_length(branch_length))
+    }
+
+    fn has_exact_costs(&self, branch_length: f64) -> bool {
+        self.costs.contains_key(&f64_h::from(branch_length))
     }
 }
 
@@ -187,6 +1001,123 @@ pub(crate) struct BranchCostsWModel<const N: usize> {
     gap_open: f64,
     gap_ext: f64,
     costs: CostMatrix,
+    mask_costs: MaskMinCosts<N>,
+    /// Costs of each site category, the partitions or the site rate categories.
+    site_costs: Vec<BranchCostsWModel<N>>,
+}
+
+/// Bits of the amino acid masks per table of `MaskMinCosts`.
+const CHUNK_BITS: usize = 7;
+
+/// Minimum costs of the sets of nucleotides and of amino acids to every character, in model
+/// index order, looked up by the masks of the sets instead of going through their members. The
+/// amino acid masks are split into chunks of `CHUNK_BITS` bits with a table each, which keeps
+/// the tables small while a set takes at most three lookups.
+#[derive(Clone, PartialEq)]
+struct MaskMinCosts<const N: usize> {
+    nucleotides: Vec<[f64; N]>,
+    aminoacids: Vec<Vec<[f64; N]>>,
+}
+
+impl<const N: usize> fmt::Debug for MaskMinCosts<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MaskMinCosts").finish_non_exhaustive()
+    }
+}
+
+impl<const N: usize> MaskMinCosts<N> {
+    fn new(index: &[i32; 255], costs: &CostMatrix) -> Self {
+        let costs = costs.as_slice();
+        // characters outside of the model never match at a finite cost
+        let column = |char: u8| -> [f64; N] {
+            match usize::try_from(index[char as usize]) {
+                Ok(col) if col < N => std::array::from_fn(|row| costs[col * N + row]),
+                _ => [f64::INFINITY; N],
+            }
+        };
+        MaskMinCosts {
+            nucleotides: mask_table(&NUCLEOTIDE_BITS, &column),
+            aminoacids: AMINOACID_BITS
+                .chunks(CHUNK_BITS)
+                .map(|chars| mask_table(chars, &column))
+                .collect(),
+        }
+    }
+}
+
+/// Minimum costs of every subset of `chars`, indexed by the mask of the subset. Every set is
+/// the set without its lowest bit plus one character, so every entry takes a single minimum.
+fn mask_table<const N: usize>(chars: &[u8], column: &impl Fn(u8) -> [f64; N]) -> Vec<[f64; N]> {
+    let mut table = vec![[f64::INFINITY; N]; 1 << chars.len()];
+    for mask in 1..table.len() {
+        let rest = table[mask & (mask - 1)];
+        let char_costs = column(chars[mask.trailing_zeros() as usize]);
+        table[mask] = std::array::from_fn(|row| rest[row].min(char_costs[row]));
+    }
+    table
+}
+
+impl<const N: usize> BranchCostsWModel<N> {
+    fn new(
+        index: [i32; 255],
+        time: f64,
+        avg_cost: f64,
+        costs: CostMatrix,
+        gap_mult: &GapMultipliers,
+    ) -> Self {
+        let (gap_open, gap_ext) = gap_mult.costs(time, avg_cost);
+        let mask_costs = MaskMinCosts::new(&index, &costs);
+        BranchCostsWModel {
+            index,
+            avg_cost,
+            gap_open,
+            gap_ext,
+            costs,
+            mask_costs,
+            site_costs: Vec::new(),
+        }
+    }
+
+    /// Minimum cost of every character of the alphabet, in model index order, to the characters
+    /// of `set`. The cost matrix is stored by columns, so every character of the set contributes
+    /// one contiguous column.
+    fn set_min_costs(&self, set: &ParsimonySet) -> [f64; N] {
+        let mut min_costs = [f64::INFINITY; N];
+        match set.bits() {
+            Bits::Nucleotides(mask) => return self.mask_costs.nucleotides[*mask as usize],
+            Bits::Aminoacids(mask) => {
+                for (chunk, table) in self.mask_costs.aminoacids.iter().enumerate() {
+                    let chunk_mask = (mask >> (chunk * CHUNK_BITS)) as usize & (table.len() - 1);
+                    if chunk_mask != 0 {
+                        simd::min_assign(&mut min_costs, &table[chunk_mask]);
+                    }
+                }
+            }
+            Bits::Bytes(_) => {
+                let costs = self.costs.as_slice();
+                for child in set {
+                    let col = self.index[child as usize] as usize;
+                    simd::min_assign(&mut min_costs, &costs[col * N..(col + 1) * N]);
+                }
+            }
+        }
+        min_costs
+    }
+
+    fn write_min_match_costs(
+        &self,
+        ancestors: &ParsimonySet,
+        set: &ParsimonySet,
+        costs: &mut Vec<f64>,
+    ) {
+        let min_costs = self.set_min_costs(set);
+        costs.clear();
+        costs.extend(
+            ancestors
+                .iter()
+                .map(|ancestor| min_costs[self.index[ancestor as usize] as usize]),
+        );
+    }
 }
 
 impl<const N: usize> BranchParsimonyCosts for BranchCostsWModel<N> {
@@ -208,31 +1139,122 @@ impl<const N: usize> BranchParsimonyCosts for BranchCostsWModel<N> {
     fn avg_cost(&self) -> f64 {
         self.avg_cost
     }
+
+    fn min_match_costs(&self, ancestors: &ParsimonySet, set: &ParsimonySet) -> Vec<f64> {
+        let mut costs = Vec::with_capacity(ancestors.len());
+        self.write_min_match_costs(ancestors, set, &mut costs);
+        costs
+    }
+
+    fn site_categories(&self) -> usize {
+        self.site_costs.len()
+    }
+
+    fn category_min_match_costs(
+        &self,
+        category: usize,
+        ancestors: &ParsimonySet,
+        set: &ParsimonySet,
+        costs: &mut Vec<f64>,
+    ) {
+        self.site_costs
+            .get(category)
+            .unwrap_or(self)
+            .write_min_match_costs(ancestors, set, costs);
+    }
 }
 
 #[cfg(test)]
 mod parsimony_costs_model_test {
-    use super::generate_costs;
+    use super::{
+        breakpoint_times, dna_rate_matrix, generate_costs, model_rate_matrix, protein_rate_matrix,
+        rate_matrix_frequencies, rate_matrix_w_freqs, scoring_times, CategoryStrategy,
+        ParsimonyCostsWModel, MODEL_AMINOACIDS,
+    };
+    use crate::parsimony_alignment::parsimony_costs::protein_models::protein_model_rate_matrix;
     use crate::{
         f64_h,
+        parsimony_alignment::alphabet::Alphabet,
         parsimony_alignment::parsimony_costs::{
-            parsimony_costs_model::{DNAParsCosts, GapMultipliers, ProteinParsCosts},
+            parsimony_costs_model::{DNAParsCosts, GammaRates, GapMultipliers, ProteinParsCosts},
             ParsimonyCosts,
         },
+        parsimony_alignment::parsimony_sets::{make_parsimony_set, ParsimonySet},
+        parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions},
     };
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use nalgebra::DMatrix;
     use phylo::evolutionary_models::EvolutionaryModel;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
     use phylo::substitution_models::{
         dna_models::DNASubstModel,
         protein_models::{self, ProteinSubstModel},
     };
+    use phylo::tree::{NodeIdx::Leaf as L, Tree};
     use phylo::Rounding;
 
+    fn assert_normalised(q: &DMatrix<f64>, freqs: &[f64]) {
+        for i in 0..q.nrows() {
+            assert_relative_eq!(q.row(i).sum(), 0.0, epsilon = 1e-10);
+        }
+        let rate: f64 = (0..q.nrows()).map(|i| -freqs[i] * q[(i, i)]).sum();
+        assert_relative_eq!(rate, 1.0, epsilon = 1e-10);
+    }
+
+    #[test]
+    fn dna_rate_matrices() {
+        let jc69 = dna_rate_matrix("jc69", &[]).unwrap();
+        let expected = DMatrix::from_fn(4, 4, |i, j| if i == j { -1.0 } else { 1.0 / 3.0 });
+        assert_relative_eq!(jc69, expected, epsilon = 1e-10);
+        let freqs = [0.1, 0.2, 0.3, 0.4];
+        let hky = dna_rate_matrix("hky85", &[0.1, 0.2, 0.3, 0.4, 2.0]).unwrap();
+        let recovered = rate_matrix_frequencies(&hky);
+        assert_relative_eq!(recovered.as_slice(), &freqs[..], epsilon = 1e-10);
+        assert_normalised(&hky, &freqs);
+        // T<->C is a transition, T<->A a transversion
+        assert_relative_eq!(hky[(0, 1)] / hky[(0, 2)], 2.0 * 0.2 / 0.3, epsilon = 1e-10);
+        assert!(dna_rate_matrix("hky85", &[0.5, 0.5, 0.5, 0.5, 2.0]).is_err());
+    }
+
+    #[test]
+    fn protein_rate_matrices() {
+        let total: f64 = (1..=20).map(f64::from).sum();
+        let freqs: [f64; 20] = std::array::from_fn(|i| (i + 1) as f64 / total);
+        for model in ["wag", "lg"] {
+            let q = protein_rate_matrix(model, Some(&freqs)).unwrap();
+            let recovered = rate_matrix_frequencies(&q);
+            assert_relative_eq!(recovered.as_slice(), &freqs[..], epsilon = 1e-10);
+            assert_normalised(&q, &freqs);
+        }
+        assert_eq!(
+            protein_rate_matrix("lg", None).unwrap(),
+            protein_model_rate_matrix("lg").unwrap()
+        );
+    }
+
+    #[test]
+    fn model_rate_matrix_frequencies() {
+        let model = ProteinSubstModel::new("wag", &[], false).unwrap();
+        let q = model_rate_matrix(&model, MODEL_AMINOACIDS);
+        assert_eq!(q, protein_rate_matrix("wag", None).unwrap());
+        let freqs = rate_matrix_frequencies(&q);
+        assert_relative_eq!(freqs.iter().sum::<f64>(), 1.0, epsilon = 1e-10);
+        assert_normalised(&q, &freqs);
+        // the recovered frequencies are the equilibrium of q and give it back with the
+        // exchangeabilities of the model
+        let equilibrium = DMatrix::from_row_slice(1, 20, &freqs) * &q;
+        assert_relative_eq!(equilibrium.norm(), 0.0, epsilon = 1e-10);
+        assert_relative_eq!(
+            rate_matrix_w_freqs(&model, MODEL_AMINOACIDS, &freqs),
+            q,
+            epsilon = 1e-10
+        );
+    }
+
     #[test]
     fn protein_branch_scoring() {
-        let gap_mult = GapMultipliers {
-            open: 2.5,
-            ext: 0.5,
-        };
+        let gap_mult = GapMultipliers::new(2.5, 0.5);
         let avg_01 = 5.7675;
         let avg_07 = 4.0075;
         let times = [0.1, 0.7];
@@ -244,6 +1266,7 @@ mod parsimony_costs_model_test {
             protein_models::aminoacid_index(),
             false,
             &Rounding::zero(),
+            &[1.0],
         );
         let branch_costs = costs.get(&f64_h::from(0.1)).unwrap();
         assert_eq!(branch_costs.costs.mean(), avg_01);
@@ -262,6 +1285,7 @@ mod parsimony_costs_model_test {
             protein_models::aminoacid_index(),
             true,
             &Rounding::zero(),
+            &[1.0],
         );
         let branch_costs = costs.get(&f64_h::from(0.1)).unwrap();
         assert_eq!(branch_costs.costs.mean(), avg_01);
@@ -302,10 +1326,7 @@ mod parsimony_costs_model_test {
 
     #[test]
     fn protein_branch_scoring_nearest() {
-        let gap_mult = GapMultipliers {
-            open: 2.0,
-            ext: 0.1,
-        };
+        let gap_mult = GapMultipliers::new(2.0, 0.1);
         let avg_01 = 5.7675;
         let avg_05 = 4.2825;
         let times = [0.1, 0.5];
@@ -333,10 +1354,7 @@ mod parsimony_costs_model_test {
 
     #[test]
     fn dna_branch_scoring() {
-        let gap_mult = GapMultipliers {
-            open: 2.5,
-            ext: 0.5,
-        };
+        let gap_mult = GapMultipliers::new(2.5, 0.5);
         let avg_01 = 2.25;
         let avg_07 = 1.75;
         let times = [0.1, 0.7];
@@ -348,6 +1366,7 @@ mod parsimony_costs_model_test {
             protein_models::aminoacid_index(),
             false,
             &Rounding::zero(),
+            &[1.0],
         );
         let branch_costs = costs.get(&f64_h::from(0.1)).unwrap();
         assert_eq!(branch_costs.costs.mean(), avg_01);
@@ -365,6 +1384,7 @@ mod parsimony_costs_model_test {
             protein_models::aminoacid_index(),
             true,
             &Rounding::zero(),
+            &[1.0],
         );
         let branch_costs = costs.get(&f64_h::from(0.1)).unwrap();
         assert_eq!(branch_costs.costs.mean(), avg_01);
@@ -376,10 +1396,7 @@ mod parsimony_costs_model_test {
 
     #[test]
     fn dna_branch_scoring_nearest() {
-        let gap_mult = GapMultipliers {
-            open: 3.0,
-            ext: 0.75,
-        };
+        let gap_mult = GapMultipliers::new(3.0, 0.75);
         let avg_01 = 2.25;
         let avg_07 = 1.75;
         let times = [0.1, 0.7];
@@ -401,4 +1418,429 @@ mod parsimony_costs_model_test {
         let scores_05 = model.get_branch_costs(0.5);
         assert_eq!(scores_05.avg_cost(), avg_07);
     }
+
+    #[test]
+    fn lg_and_jtt_scoring() {
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        for name in ["LG", "jtt"] {
+            let model =
+                ProteinParsCosts::new(name, &gap_mult, &[0.1, 0.5], false, &Rounding::zero())
+                    .unwrap();
+            assert_eq!(model.times(), &[0.1, 0.5]);
+            let short = model.get_branch_costs(0.1);
+            let long = model.get_branch_costs(0.5);
+            assert!(short.avg_cost() > long.avg_cost());
+            assert_eq!(short.gap_open_cost(), short.avg_cost() * 2.0);
+            assert!(short.match_cost(b'A', b'A') < short.match_cost(b'A', b'W'));
+            assert_eq!(short.match_cost(b'W', b'Y').fract(), 0.0);
+        }
+    }
+
+    #[test]
+    fn empirical_frequency_scoring() {
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        let times = [0.1, 0.5];
+        let lg = ProteinParsCosts::new("LG", &gap_mult, &times, false, &Rounding::none()).unwrap();
+        let lg_freqs = [
+            0.079066, 0.055941, 0.041977, 0.053052, 0.012937, 0.040767, 0.071586, 0.057337,
+            0.022355, 0.062157, 0.099081, 0.064600, 0.022951, 0.042302, 0.044040, 0.061197,
+            0.053287, 0.012066, 0.034155, 0.069147,
+        ];
+        let lg_f = ProteinParsCosts::new_w_frequencies(
+            "lg",
+            &lg_freqs,
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+        )
+        .unwrap();
+        assert_relative_eq!(
+            lg.get_branch_costs(0.1).match_cost(b'A', b'R'),
+            lg_f.get_branch_costs(0.1).match_cost(b'A', b'R'),
+            epsilon = 1e-4
+        );
+
+        let mut freqs = [0.04; 20];
+        freqs[0] = 0.24;
+        let wag_f = ProteinParsCosts::new_w_frequencies(
+            "WAG",
+            &freqs,
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+        )
+        .unwrap();
+        let (short, long) = (wag_f.get_branch_costs(0.1), wag_f.get_branch_costs(0.5));
+        assert!(short.avg_cost().is_finite());
+        assert!(short.avg_cost() > long.avg_cost());
+        assert!(short.match_cost(b'A', b'A') < short.match_cost(b'A', b'W'));
+
+        freqs[0] = 0.3;
+        assert!(ProteinParsCosts::new_w_frequencies(
+            "WAG",
+            &freqs,
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::none(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn exact_branch_costs() {
+        let lengths = [0.3, 0.1, 0.0, 0.3, 0.7, 0.1, 0.25];
+        let times = scoring_times(
+            &lengths,
+            0,
+            CategoryStrategy::Percentiles,
+            &Rounding::none(),
+        );
+        assert_eq!(times, vec![0.1, 0.25, 0.3, 0.7]);
+        assert_eq!(
+            scoring_times(&[0.0, 0.0], 0, CategoryStrategy::KMeans, &Rounding::none()),
+            vec![1.0]
+        );
+        assert_eq!(
+            breakpoint_times(&[0.8, 0.05, 0.2, 0.05]).unwrap(),
+            vec![0.05, 0.2, 0.8]
+        );
+        assert!(breakpoint_times(&[0.1, 0.0]).is_err());
+        assert!(breakpoint_times(&[]).is_err());
+        let model = ProteinParsCosts::new(
+            "wag",
+            &GapMultipliers::new(2.0, 0.5),
+            &times,
+            false,
+            &Rounding::none(),
+        )
+        .unwrap();
+        assert_eq!(model.times(), &times[..]);
+        let costs_025 = model.get_branch_costs(0.25);
+        let costs_03 = model.get_branch_costs(0.3);
+        assert_ne!(costs_025.avg_cost(), costs_03.avg_cost());
+        assert_eq!(
+            model.get_branch_costs(0.0).avg_cost(),
+            model.get_branch_costs(0.1).avg_cost()
+        );
+    }
+
+    #[test]
+    fn branch_slope_gap_costs() {
+        let gap_mult = GapMultipliers::new(1.0, 0.5).with_branch_slopes(-2.0, 1.0);
+        assert_eq!(gap_mult.costs(0.25, 3.0), (0.5, 0.75));
+        assert_eq!(gap_mult.costs(1.0, 3.0), (0.0, 1.5));
+        assert_eq!(GapMultipliers::new(1.0, 0.5).costs(0.25, 3.0), (3.0, 1.5));
+
+        let times = [0.1, 0.5];
+        let model =
+            DNAParsCosts::new("JC69", &[], &gap_mult, &times, false, &Rounding::none()).unwrap();
+        let costs_01 = model.get_branch_costs(0.1);
+        assert_relative_eq!(costs_01.gap_open_cost(), 0.8);
+        assert_relative_eq!(costs_01.gap_ext_cost(), 0.6);
+        let costs_05 = model.get_branch_costs(0.5);
+        assert_relative_eq!(costs_05.gap_open_cost(), 0.0);
+        assert_relative_eq!(costs_05.gap_ext_cost(), 1.0);
+    }
+
+    #[test]
+    fn kmeans_branch_lengths() {
+        let lengths = [0.01, 0.02, 0.01, 0.03, 0.02, 0.01, 0.02, 0.5, 0.6, 2.0, 2.2];
+        let times = scoring_times(&lengths, 3, CategoryStrategy::KMeans, &Rounding::none());
+        assert_eq!(times.len(), 3);
+        assert_relative_eq!(times[0], 0.12 / 7.0);
+        assert_relative_eq!(times[1], 0.55);
+        assert_relative_eq!(times[2], 2.1);
+        let times = scoring_times(&lengths, 3, CategoryStrategy::KMeans, &Rounding::zero());
+        assert_eq!(times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(
+            scoring_times(&[0.5; 4], 3, CategoryStrategy::KMeans, &Rounding::none()),
+            vec![0.5]
+        );
+    }
+
+    #[test]
+    fn zero_diagonal_avg_cost() {
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        let gamma = GammaRates::new(0.5, 4).unwrap();
+        let scorings = |zero_diag| {
+            let wag = ProteinParsCosts::new_w_gamma(
+                "wag",
+                &gap_mult,
+                &[0.3],
+                zero_diag,
+                &Rounding::none(),
+                &gamma,
+            )
+            .unwrap();
+            let hky = DNAParsCosts::new_w_gamma(
+                "HKY85",
+                &[2.0],
+                &gap_mult,
+                &[0.3],
+                zero_diag,
+                &Rounding::none(),
+                &gamma,
+            )
+            .unwrap();
+            (wag, hky)
+        };
+        let (wag, hky) = scorings(false);
+        let (zero_wag, zero_hky) = scorings(true);
+        for (costs, zero_costs) in [
+            (wag.get_branch_costs(0.3), zero_wag.get_branch_costs(0.3)),
+            (hky.get_branch_costs(0.3), zero_hky.get_branch_costs(0.3)),
+        ] {
+            assert!(costs.match_cost(b'A', b'A') > 0.0);
+            assert_eq!(zero_costs.match_cost(b'A', b'A'), 0.0);
+            // the average is taken before the diagonal is zeroed
+            assert_eq!(zero_costs.avg_cost(), costs.avg_cost());
+        }
+    }
+
+    #[test]
+    fn gamma_rate_costs() {
+        assert!(GammaRates::new(0.0, 4).is_err());
+        assert!(GammaRates::new(0.5, 0).is_err());
+        assert_eq!(GammaRates::new(0.5, 1).unwrap(), GammaRates::none());
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        let times = [0.1, 0.7];
+        let plain =
+            ProteinParsCosts::new("wag", &gap_mult, &times, false, &Rounding::none()).unwrap();
+        let gamma = ProteinParsCosts::new_w_gamma(
+            "wag",
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::new(0.5, 4).unwrap(),
+        )
+        .unwrap();
+        let flat = ProteinParsCosts::new_w_gamma(
+            "wag",
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::new(1e3, 4).unwrap(),
+        )
+        .unwrap();
+        for time in times {
+            let (plain, gamma, flat) = (
+                plain.get_branch_costs(time),
+                gamma.get_branch_costs(time),
+                flat.get_branch_costs(time),
+            );
+            assert_ne!(plain.avg_cost(), gamma.avg_cost());
+            assert_relative_eq!(plain.avg_cost(), flat.avg_cost(), epsilon = 1e-2);
+            // rate variation makes a site more likely to stay unchanged
+            assert!(gamma.match_cost(b'A', b'A') < plain.match_cost(b'A', b'A'));
+        }
+
+        let hky = DNAParsCosts::new_w_gamma(
+            "HKY85",
+            &[2.0],
+            &gap_mult,
+            &times,
+            false,
+            &Rounding::none(),
+            &GammaRates::new(0.5, 4).unwrap(),
+        )
+        .unwrap();
+        let plain = DNAParsCosts::new("HKY85", &[2.0], &gap_mult, &times, false, &Rounding::none())
+            .unwrap();
+        assert!(
+            hky.get_branch_costs(0.7).match_cost(b'T', b'T')
+                < plain.get_branch_costs(0.7).match_cost(b'T', b'T')
+        );
+    }
+
+    #[test]
+    fn site_rate_category_costs() {
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        let plain =
+            ProteinParsCosts::new("wag", &gap_mult, &[0.5], false, &Rounding::none()).unwrap();
+        let one_rate = ProteinParsCosts::new("wag", &gap_mult, &[0.5], false, &Rounding::none())
+            .unwrap()
+            .with_site_rates(&GammaRates::none());
+        assert_eq!(one_rate.get_branch_costs(0.5).site_categories(), 0);
+        let site_rates = ProteinParsCosts::new("wag", &gap_mult, &[0.5], false, &Rounding::none())
+            .unwrap()
+            .with_site_rates(&GammaRates::new(0.5, 4).unwrap());
+        let (plain, costs) = (
+            plain.get_branch_costs(0.5),
+            site_rates.get_branch_costs(0.5),
+        );
+        assert_eq!(costs.site_categories(), 4);
+        // the branch keeps the costs and gap costs of rate 1
+        assert_eq!(costs.match_cost(b'A', b'W'), plain.match_cost(b'A', b'W'));
+        assert_eq!(costs.gap_open_cost(), plain.gap_open_cost());
+        let (ancestors, set) = (make_parsimony_set(*b"A"), make_parsimony_set(*b"A"));
+        let mut buffer = Vec::new();
+        let self_costs: Vec<f64> = (0..4)
+            .map(|category| {
+                costs.category_min_match_costs(category, &ancestors, &set, &mut buffer);
+                buffer[0]
+            })
+            .collect();
+        // slow categories make a site more likely to stay unchanged
+        assert!(self_costs.windows(2).all(|w| w[0] < w[1]));
+        costs.category_min_match_costs(4, &ancestors, &set, &mut buffer);
+        assert_eq!(buffer, costs.min_match_costs(&ancestors, &set));
+    }
+
+    #[test]
+    fn min_match_costs_protein() {
+        let model = ProteinParsCosts::new(
+            "wag",
+            &GapMultipliers::new(2.5, 0.5),
+            &[0.1],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap();
+        let costs = model.get_branch_costs(0.1);
+        let ancestors = make_parsimony_set(*b"AWKL");
+        // amino acid masks over one and several tables and a nucleotide mask
+        let sets = [
+            make_parsimony_set(*b"CDLYV"),
+            ParsimonySet::aminoacids(*b"ARNDCQE"),
+            ParsimonySet::aminoacids(*b"T"),
+            make_parsimony_set(*b"ACG"),
+        ];
+        for set in sets {
+            let min_costs = costs.min_match_costs(&ancestors, &set);
+            for (ancestor, min_cost) in ancestors.iter().zip(min_costs) {
+                let expected = set
+                    .iter()
+                    .map(|child| costs.match_cost(ancestor, child))
+                    .fold(f64::INFINITY, f64::min);
+                assert_eq!(min_cost, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn min_match_costs_dna() {
+        let model = DNAParsCosts::new(
+            "k80",
+            &[],
+            &GapMultipliers::new(2.5, 0.5),
+            &[0.3],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap();
+        let costs = model.get_branch_costs(0.3);
+        for ancestors in [*b"AC", *b"GT"] {
+            let ancestors = make_parsimony_set(ancestors);
+            for set in [&b"A"[..], b"CT", b"ACG", b"ACGT"] {
+                let set = make_parsimony_set(set.iter().copied());
+                let min_costs = costs.min_match_costs(&ancestors, &set);
+                for (ancestor, min_cost) in ancestors.iter().zip(min_costs) {
+                    let expected = set
+                        .iter()
+                        .map(|child| costs.match_cost(ancestor, child))
+                        .fold(f64::INFINITY, f64::min);
+                    assert_eq!(min_cost, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn custom_alphabet_costs() {
+        let alphabet = Alphabet::new(b"xyz").unwrap();
+        let matrix = DMatrix::from_row_slice(3, 3, &[0.0, 1.0, 4.0, 1.0, 0.0, 4.0, 4.0, 4.0, 0.0]);
+        let gap_mult = GapMultipliers::new(2.0, 0.5);
+        let scoring =
+            ParsimonyCostsWModel::<3>::from_cost_matrices(&alphabet, &[(1.0, matrix)], &gap_mult)
+                .unwrap();
+        let costs = scoring.get_branch_costs(0.3);
+        assert_eq!(costs.match_cost(b'x', b'y'), 1.0);
+        assert_eq!(costs.match_cost(b'z', b'y'), 4.0);
+        assert_eq!(costs.avg_cost(), 2.0);
+        assert_eq!(costs.gap_open_cost(), 4.0);
+        assert_eq!(
+            costs.min_match_costs(&make_parsimony_set([b'z']), &alphabet.set(b'?')),
+            vec![0.0]
+        );
+        assert!(ParsimonyCostsWModel::<4>::from_cost_matrices(&alphabet, &[], &gap_mult).is_err());
+        assert!(ParsimonyCostsWModel::<3>::from_cost_matrices(
+            &alphabet,
+            &[(1.0, DMatrix::zeros(2, 2))],
+            &gap_mult
+        )
+        .is_err());
+
+        let sequences = [
+            Record::with_attrs("A", None, b"xxzy"),
+            Record::with_attrs("B", None, b"xyzy"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let options = AlignmentOptions {
+            alphabet: Some(alphabet),
+            ..Default::default()
+        };
+        let result = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(result.scores, vec![1.0]);
+        assert_eq!(result.alignments[0].map_x.len(), 4);
+    }
+
+    #[test]
+    fn costs_computed_on_first_use() {
+        let gap_mult = GapMultipliers::new(2.5, 0.5);
+        let times = [0.1, 0.5, 1.0];
+        let computed = |scoring: &ProteinParsCosts| {
+            scoring
+                .costs
+                .values()
+                .filter(|costs| costs.get().is_some())
+                .count()
+        };
+        for model in ["wag", "lg"] {
+            let scoring =
+                ProteinParsCosts::new(model, &gap_mult, &times, false, &Rounding::none()).unwrap();
+            assert_eq!(computed(&scoring), 0);
+            let branch = scoring.get_branch_costs(0.45);
+            assert_eq!(computed(&scoring), 1);
+            let single =
+                ProteinParsCosts::new(model, &gap_mult, &[0.5], false, &Rounding::none()).unwrap();
+            assert_eq!(
+                branch.match_cost(b'A', b'R'),
+                single.get_branch_costs(0.5).match_cost(b'A', b'R')
+            );
+            assert_eq!(
+                branch.gap_open_cost(),
+                single.get_branch_costs(0.5).gap_open_cost()
+            );
+            scoring.get_branch_costs(0.5);
+            assert_eq!(computed(&scoring), 1);
+        }
+
+        let model = ProteinSubstModel::new("wag", &[], false).unwrap();
+        let eager = generate_costs(
+            &model,
+            &times,
+            &gap_mult,
+            protein_models::aminoacid_index(),
+            false,
+            &Rounding::none(),
+            &[1.0],
+        );
+        let lazy =
+            ProteinParsCosts::new("wag", &gap_mult, &times, false, &Rounding::none()).unwrap();
+        for time in times {
+            assert_eq!(lazy.branch_costs(time), &eager[&f64_h::from(time)]);
+        }
+    }
 }
@@ -0,0 +1,212 @@
+use bio::io::fasta::Record;
+use nalgebra::DMatrix;
+
+use super::parsimony_costs_model::normalised_rate_matrix;
+
+/// Amino acid order of the exchangeabilities and frequencies of the models.
+pub(crate) const MODEL_AMINOACIDS: &[u8] = b"ARNDCQEGHILKMFPSTWYV";
+
+/// Exchangeabilities of the LG model (Le and Gascuel 2008), lower triangle by rows.
+#[rustfmt::skip]
+const LG_RATES: [f64; 190] = [
+    0.425093,
+    0.276818, 0.751878,
+    0.395144, 0.123954, 5.076149,
+    2.489084, 0.534551, 0.528768, 0.062556,
+    0.969894, 2.807908, 1.695752, 0.523386, 0.084808,
+    1.038545, 0.363970, 0.541712, 5.243870, 0.003499, 4.128591,
+    2.066040, 0.390192, 1.437645, 0.844926, 0.569265, 0.267959, 0.348847,
+    0.358858, 2.426601, 4.509238, 0.927114, 0.640543, 4.813505, 0.423881, 0.311484,
+    0.149830, 0.126991, 0.191503, 0.010690, 0.320627, 0.072854, 0.044265, 0.008705, 0.108882,
+    0.395337, 0.301848, 0.068427, 0.015076, 0.594007, 0.582457, 0.069673, 0.044261, 0.366317, 4.145067,
+    0.536518, 6.326067, 2.145078, 0.282959, 0.013266, 3.234294, 1.807177, 0.296636, 0.697264, 0.159069, 0.137500,
+    1.124035, 0.484133, 0.371004, 0.025548, 0.893680, 1.672569, 0.173735, 0.139538, 0.442472, 4.273607, 6.312358, 0.656604,
+    0.253701, 0.052722, 0.089525, 0.017416, 1.105251, 0.035855, 0.018811, 0.089586, 0.682139, 1.112727, 2.592692, 0.023918, 1.798853,
+    1.177651, 0.332533, 0.161787, 0.394456, 0.075382, 0.624294, 0.419409, 0.196961, 0.508851, 0.078281, 0.249060, 0.390322, 0.099849, 0.094464,
+    4.727182, 0.858151, 4.008358, 1.240275, 2.784478, 1.223828, 0.611973, 1.739990, 0.990012, 0.064105, 0.182287, 0.748683, 0.346960, 0.361819, 1.338132,
+    2.139501, 0.578987, 2.000679, 0.425860, 1.143480, 1.080136, 0.604545, 0.129836, 0.584262, 1.033739, 0.302936, 1.136863, 2.020366, 0.165001, 0.571468, 6.472279,
+    0.180717, 0.593607, 0.045376, 0.029890, 0.670128, 0.236199, 0.077852, 0.268491, 0.597054, 0.111660, 0.619632, 0.049906, 0.696175, 2.457121, 0.095131, 0.248862, 0.140825,
+    0.218959, 0.314440, 0.612025, 0.135107, 1.165532, 0.257336, 0.120037, 0.054679, 5.306834, 0.232523, 0.299648, 0.131932, 0.481306, 7.803902, 0.089613, 0.400547, 0.245841, 3.151815,
+    2.547870, 0.170887, 0.083688, 0.037967, 1.959291, 0.210332, 0.245034, 0.076701, 0.119013, 10.649107, 1.702745, 0.185202, 1.898718, 0.654683, 0.296501, 0.098369, 2.188158, 0.189510, 0.249313,
+];
+
+/// Equilibrium frequencies of the LG model.
+#[rustfmt::skip]
+const LG_FREQS: [f64; 20] = [
+    0.079066, 0.055941, 0.041977, 0.053052, 0.012937, 0.040767, 0.071586, 0.057337, 0.022355, 0.062157,
+    0.099081, 0.064600, 0.022951, 0.042302, 0.044040, 0.061197, 0.053287, 0.012066, 0.034155, 0.069147,
+];
+
+/// Exchangeabilities of the JTT model (Jones et al. 1992) as in the PAML jones.dat file,
+/// lower triangle by rows.
+#[rustfmt::skip]
+const JTT_RATES: [f64; 190] = [
+    58.0,
+    54.0, 45.0,
+    81.0, 16.0, 528.0,
+    56.0, 113.0, 34.0, 10.0,
+    57.0, 310.0, 86.0, 49.0, 9.0,
+    105.0, 29.0, 58.0, 767.0, 5.0, 323.0,
+    179.0, 137.0, 81.0, 130.0, 59.0, 26.0, 119.0,
+    27.0, 328.0, 391.0, 112.0, 69.0, 597.0, 26.0, 23.0,
+    36.0, 22.0, 47.0, 11.0, 17.0, 9.0, 12.0, 6.0, 16.0,
+    30.0, 38.0, 12.0, 7.0, 23.0, 72.0, 9.0, 6.0, 56.0, 229.0,
+    35.0, 646.0, 263.0, 26.0, 7.0, 292.0, 181.0, 27.0, 45.0, 21.0, 14.0,
+    54.0, 44.0, 30.0, 15.0, 31.0, 43.0, 18.0, 14.0, 33.0, 479.0, 388.0, 65.0,
+    15.0, 5.0, 10.0, 4.0, 78.0, 4.0, 5.0, 5.0, 40.0, 89.0, 248.0, 4.0, 43.0,
+    194.0, 74.0, 15.0, 15.0, 14.0, 164.0, 18.0, 24.0, 115.0, 10.0, 102.0, 21.0, 16.0, 17.0,
+    378.0, 101.0, 503.0, 59.0, 223.0, 53.0, 30.0, 201.0, 73.0, 40.0, 59.0, 47.0, 29.0, 92.0, 285.0,
+    475.0, 64.0, 232.0, 38.0, 42.0, 51.0, 32.0, 33.0, 46.0, 245.0, 25.0, 103.0, 226.0, 12.0, 118.0, 477.0,
+    9.0, 126.0, 8.0, 4.0, 115.0, 18.0, 10.0, 55.0, 8.0, 9.0, 52.0, 10.0, 24.0, 53.0, 6.0, 35.0, 12.0,
+    11.0, 20.0, 70.0, 46.0, 209.0, 24.0, 7.0, 8.0, 573.0, 32.0, 24.0, 8.0, 18.0, 536.0, 10.0, 63.0, 21.0, 71.0,
+    298.0, 17.0, 16.0, 31.0, 62.0, 20.0, 45.0, 47.0, 11.0, 961.0, 180.0, 14.0, 323.0, 62.0, 23.0, 38.0, 112.0, 25.0, 16.0,
+];
+
+/// Equilibrium frequencies of the JTT model.
+#[rustfmt::skip]
+const JTT_FREQS: [f64; 20] = [
+    0.076748, 0.051691, 0.042645, 0.051544, 0.019803, 0.040752, 0.061830, 0.073152, 0.022944, 0.053761,
+    0.091904, 0.058676, 0.023826, 0.040126, 0.050901, 0.068765, 0.058565, 0.014261, 0.032102, 0.066005,
+];
+
+fn model_parameters(name: &str) -> Option<(&'static [f64; 190], &'static [f64; 20])> {
+    match name.to_uppercase().as_str() {
+        "LG" => Some((&LG_RATES, &LG_FREQS)),
+        "JTT" => Some((&JTT_RATES, &JTT_FREQS)),
+        _ => None,
+    }
+}
+
+/// Rate matrix of the model normalised to one expected substitution per unit of time.
+fn rate_matrix(rates: &[f64; 190], freqs: &[f64; 20]) -> DMatrix<f64> {
+    let mut exchangeabilities = DMatrix::zeros(20, 20);
+    let mut rates = rates.iter();
+    for i in 1..20 {
+        for j in 0..i {
+            exchangeabilities[(i, j)] = *rates.next().unwrap();
+            exchangeabilities[(j, i)] = exchangeabilities[(i, j)];
+        }
+    }
+    normalised_rate_matrix(&exchangeabilities, freqs)
+}
+
+/// Rate matrix of the named model with the amino acids in the order of `MODEL_AMINOACIDS`, or
+/// `None` if the model is not defined here.
+pub(crate) fn protein_model_rate_matrix(name: &str) -> Option<DMatrix<f64>> {
+    let (exchangeabilities, freqs) = model_parameters(name)?;
+    Some(rate_matrix(exchangeabilities, freqs))
+}
+
+/// Rate matrix of the named model with its equilibrium frequencies replaced by `freqs`, or
+/// `None` if the model is not defined here.
+pub(crate) fn protein_model_rate_matrix_w_freqs(
+    name: &str,
+    freqs: &[f64; 20],
+) -> Option<DMatrix<f64>> {
+    let (exchangeabilities, _) = model_parameters(name)?;
+    Some(rate_matrix(exchangeabilities, freqs))
+}
+
+/// Frequencies of the amino acids in the order of `MODEL_AMINOACIDS` counted over the
+/// sequences (+F), ambiguous characters and gaps are skipped. Every amino acid starts with a
+/// pseudocount of one so that none of the frequencies is zero.
+pub fn empirical_aminoacid_frequencies(sequences: &[Record]) -> [f64; 20] {
+    let mut counts = [1.0; 20];
+    for residue in sequences.iter().flat_map(|record| record.seq()) {
+        let residue = residue.to_ascii_uppercase();
+        if let Some(idx) = MODEL_AMINOACIDS.iter().position(|&c| c == residue) {
+            counts[idx] += 1.0;
+        }
+    }
+    let total: f64 = counts.iter().sum();
+    counts.map(|count| count / total)
+}
+
+#[cfg(test)]
+mod protein_models_tests {
+    use super::{
+        empirical_aminoacid_frequencies, model_parameters, protein_model_rate_matrix,
+        protein_model_rate_matrix_w_freqs, rate_matrix, MODEL_AMINOACIDS,
+    };
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::rate_matrix_costs;
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use nalgebra::DMatrix;
+    use phylo::Rounding;
+
+    /// Cost matrices of the named model for the branch lengths `times` and the rate categories
+    /// `rates`, or `None` if the model is not defined here.
+    fn protein_model_costs(
+        name: &str,
+        times: &[f64],
+        rates: &[f64],
+        zero_diag: bool,
+        rounding: &Rounding,
+    ) -> Option<Vec<(f64, DMatrix<f64>)>> {
+        protein_model_rate_matrix(name).map(|q| {
+            rate_matrix_costs(&q, times, rates, zero_diag, rounding)
+                .into_iter()
+                .map(|(time, (costs, _))| (time, costs))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn normalised_rates() {
+        for name in ["LG", "JTT"] {
+            let (rates, freqs) = model_parameters(name).unwrap();
+            assert_relative_eq!(freqs.iter().sum::<f64>(), 1.0, epsilon = 1e-5);
+            let q = rate_matrix(rates, freqs);
+            for i in 0..20 {
+                assert_relative_eq!(q.row(i).sum(), 0.0, epsilon = 1e-12);
+            }
+            let rate: f64 = (0..20).map(|i| -freqs[i] * q[(i, i)]).sum();
+            assert_relative_eq!(rate, 1.0, epsilon = 1e-12);
+        }
+        assert!(model_parameters("wag").is_none());
+    }
+
+    #[test]
+    fn model_costs() {
+        let costs =
+            protein_model_costs("lg", &[0.1, 0.7], &[1.0], false, &Rounding::none()).unwrap();
+        assert_eq!(costs.len(), 2);
+        let (short, long) = (&costs[0].1, &costs[1].1);
+        assert!(short.mean() > long.mean());
+        for i in 0..20 {
+            assert!(short[(i, i)] < long[(i, i)]);
+        }
+        let costs = protein_model_costs("JTT", &[0.5], &[1.0], true, &Rounding::zero()).unwrap();
+        assert_eq!(costs[0].1.diagonal().sum(), 0.0);
+        assert!(costs[0].1.iter().all(|cost| cost.fract() == 0.0));
+        assert!(protein_model_costs("WAG", &[0.5], &[1.0], false, &Rounding::none()).is_none());
+    }
+
+    #[test]
+    fn empirical_frequencies() {
+        let sequences = [
+            Record::with_attrs("a", None, b"AAR-X"),
+            Record::with_attrs("b", None, b"av"),
+        ];
+        let freqs = empirical_aminoacid_frequencies(&sequences);
+        assert_eq!(freqs[0], 4.0 / 25.0);
+        assert_eq!(freqs[1], 2.0 / 25.0);
+        assert_eq!(freqs[2], 1.0 / 25.0);
+        assert_eq!(MODEL_AMINOACIDS[19], b'V');
+        assert_eq!(freqs[19], 2.0 / 25.0);
+
+        // with the model frequencies +F gives back the model
+        let (_, lg_freqs) = model_parameters("LG").unwrap();
+        assert_relative_eq!(
+            protein_model_rate_matrix_w_freqs("lg", lg_freqs).unwrap(),
+            protein_model_rate_matrix("lg").unwrap(),
+            epsilon = 1e-12
+        );
+        let q = protein_model_rate_matrix_w_freqs("JTT", &freqs).unwrap();
+        let rate: f64 = (0..20).map(|i| -freqs[i] * q[(i, i)]).sum();
+        assert_relative_eq!(rate, 1.0, epsilon = 1e-12);
+        // detailed balance with the new frequencies
+        assert_relative_eq!(freqs[0] * q[(0, 1)], freqs[1] * q[(1, 0)], epsilon = 1e-12);
+        assert!(protein_model_rate_matrix_w_freqs("WAG", &freqs).is_none());
+    }
+}
@@ -0,0 +1,55 @@
+/// Replaces every entry of `acc` by its minimum with the corresponding entry of `costs`. Uses AVX
+/// instructions to compare four costs at a time when the CPU supports them.
+pub(super) fn min_assign(acc: &mut [f64], costs: &[f64]) {
+    debug_assert_eq!(acc.len(), costs.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx") {
+            // SAFETY: support for AVX was checked at runtime just above.
+            unsafe { min_assign_avx(acc, costs) };
+            return;
+        }
+    }
+    min_assign_scalar(acc, costs);
+}
+
+fn min_assign_scalar(acc: &mut [f64], costs: &[f64]) {
+    for (acc, &cost) in acc.iter_mut().zip(costs) {
+        *acc = acc.min(cost);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+unsafe fn min_assign_avx(acc: &mut [f64], costs: &[f64]) {
+    use std::arch::x86_64::{_mm256_loadu_pd, _mm256_min_pd, _mm256_storeu_pd};
+    let vectorised = acc.len().min(costs.len()) / 4 * 4;
+    for k in (0..vectorised).step_by(4) {
+        let acc_lanes = _mm256_loadu_pd(acc.as_ptr().add(k));
+        let cost_lanes = _mm256_loadu_pd(costs.as_ptr().add(k));
+        _mm256_storeu_pd(
+            acc.as_mut_ptr().add(k),
+            _mm256_min_pd(acc_lanes, cost_lanes),
+        );
+    }
+    min_assign_scalar(&mut acc[vectorised..], &costs[vectorised..]);
+}
+
+#[cfg(test)]
+mod simd_tests {
+    use super::{min_assign, min_assign_scalar};
+
+    #[test]
+    fn min_assign_matches_scalar() {
+        for len in [1, 4, 7, 20] {
+            let costs: Vec<f64> = (0..len).map(|k| ((k * 7) % 5) as f64 * 0.5).collect();
+            let start: Vec<f64> = (0..len).map(|k| ((k * 3) % 4) as f64 * 0.75).collect();
+            let mut expected = start.clone();
+            min_assign_scalar(&mut expected, &costs);
+            let mut acc = start;
+            min_assign(&mut acc, &costs);
+            assert_eq!(acc, expected);
+            assert!(acc.iter().zip(&costs).all(|(acc, cost)| acc <= cost));
+        }
+    }
+}
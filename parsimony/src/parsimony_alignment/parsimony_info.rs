@@ -11,10 +11,25 @@ pub(crate) enum SiteFlag {
     NoGap,
 }
 
+/// Site of a sequence or of an ancestral profile, the set of possible characters and whether the
+/// site can be a gap.
 #[derive(Clone, PartialEq)]
-pub(crate) struct ParsimonySiteInfo {
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        into = "super::serialization::SiteInfoRepr",
+        from = "super::serialization::SiteInfoRepr"
+    )
+)]
+pub struct ParsimonySiteInfo {
     pub(crate) set: ParsimonySet,
     pub(super) flag: SiteFlag,
+    /// Multiplier of the costs of the gaps against this site.
+    pub(crate) gap_weight: f64,
+    /// Site category whose costs score the site, its partition or the rate category it was
+    /// matched in. None until the site is matched with site rate categories.
+    pub(crate) site_category: Option<u8>,
 }
 
 impl Debug for ParsimonySiteInfo {
@@ -22,7 +37,7 @@ impl Debug for ParsimonySiteInfo {
         write!(
             f,
             "{:?}: {:?}",
-            self.set.iter().map(|&a| a as char).collect::<Vec<char>>(),
+            self.set.iter().map(|a| a as char).collect::<Vec<char>>(),
             self.flag
         )
         .unwrap();
@@ -32,15 +47,48 @@ impl Debug for ParsimonySiteInfo {
 
 impl ParsimonySiteInfo {
     pub(crate) fn new(set: impl IntoIterator<Item = u8>, gap_flag: SiteFlag) -> ParsimonySiteInfo {
+        ParsimonySiteInfo::from_set(make_parsimony_set(set), gap_flag)
+    }
+
+    /// Site with the set as it is, keeping its representation.
+    pub(crate) fn from_set(set: ParsimonySet, gap_flag: SiteFlag) -> ParsimonySiteInfo {
         ParsimonySiteInfo {
-            set: make_parsimony_set(set),
+            set,
             flag: gap_flag,
+            gap_weight: 1.0,
+            site_category: None,
         }
     }
-    pub(crate) fn new_leaf(set: impl IntoIterator<Item = u8>) -> ParsimonySiteInfo {
+
+    pub fn with_gap_weight(mut self, gap_weight: f64) -> ParsimonySiteInfo {
+        self.gap_weight = gap_weight;
+        self
+    }
+
+    /// Site of a sequence with the given possible characters.
+    pub fn new_leaf(set: impl IntoIterator<Item = u8>) -> ParsimonySiteInfo {
         ParsimonySiteInfo::new(set, SiteFlag::NoGap)
     }
 
+    /// Possible characters at the site.
+    pub fn set(&self) -> &ParsimonySet {
+        &self.set
+    }
+
+    pub fn gap_weight(&self) -> f64 {
+        self.gap_weight
+    }
+
+    pub(crate) fn with_site_category(mut self, site_category: Option<u8>) -> ParsimonySiteInfo {
+        self.site_category = site_category;
+        self
+    }
+
+    /// Partition or site rate category of the site, if it has one.
+    pub fn site_category(&self) -> Option<u8> {
+        self.site_category
+    }
+
     pub(crate) fn is_fixed(&self) -> bool {
         self.flag == SiteFlag::GapFixed
     }
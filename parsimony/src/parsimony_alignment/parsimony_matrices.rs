@@ -1,17 +1,27 @@
+use super::constraints::NodeConstraints;
 use super::parsimony_costs::BranchParsimonyCosts as BranchCosts;
 use super::parsimony_info::SiteFlag::{self, GapExt, GapFixed, GapOpen, NoGap};
 use super::parsimony_sets::{gap_set, ParsimonySet};
 use super::{
     parsimony_info::ParsimonySiteInfo as SiteInfo,
     Direction::{self, GapInX, GapInY, Matc},
+    GapFunction, TieBreak,
 };
 use crate::cmp_f64;
 use crate::parsimony_alignment::parsimony_sets::print_parsimony_set;
 use log::debug;
 use phylo::alignment::{Alignment, Mapping};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::f64::INFINITY as INF;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Mutex;
 use std::{fmt, iter::zip};
 
+/// Scores of the three states. With two rows only the first two rows are allocated, the
+/// others stay empty until the fill moves a row it no longer needs to them.
+#[derive(Default)]
 pub(super) struct ScoreMatrices {
     pub(super) m: Vec<Vec<f64>>,
     pub(super) x: Vec<Vec<f64>>,
@@ -19,13 +29,45 @@ pub(super) struct ScoreMatrices {
 }
 
 impl ScoreMatrices {
-    pub(super) fn new(len1: usize, len2: usize) -> ScoreMatrices {
+    pub(super) fn new(len1: usize, len2: usize, two_rows: bool) -> ScoreMatrices {
         ScoreMatrices {
-            m: vec![vec![0.0; len2]; len1],
-            x: vec![vec![0.0; len2]; len1],
-            y: vec![vec![0.0; len2]; len1],
+            m: matrix_rows(len1, len2, two_rows, 0.0),
+            x: matrix_rows(len1, len2, two_rows, 0.0),
+            y: matrix_rows(len1, len2, two_rows, 0.0),
         }
     }
+
+    /// Moves the row `i - 1`, which the fill no longer reads once row `i` is done, to row
+    /// `i + 1`. After the last row only that row is left.
+    fn reuse_row(&mut self, i: usize) {
+        for matrix in [&mut self.m, &mut self.x, &mut self.y] {
+            reuse_row(matrix, i, 0.0);
+        }
+    }
+}
+
+/// Rows of a matrix, with two rows only the first two rows are allocated.
+fn matrix_rows<T: Clone>(len1: usize, len2: usize, two_rows: bool, value: T) -> Vec<Vec<T>> {
+    let allocated = if two_rows { len1.min(2) } else { len1 };
+    (0..len1)
+        .map(|i| {
+            if i < allocated {
+                vec![value.clone(); len2]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Moves the row `i - 1` of a matrix with two rows to row `i + 1` reset to `value`, or drops
+/// it after the last row.
+fn reuse_row<T: Copy>(matrix: &mut [Vec<T>], i: usize, value: T) {
+    let mut row = std::mem::take(&mut matrix[i - 1]);
+    if let Some(next) = matrix.get_mut(i + 1) {
+        row.fill(value);
+        *next = row;
+    }
 }
 
 pub(super) struct TracebackMatrices {
@@ -44,13 +86,65 @@ impl TracebackMatrices {
     }
 }
 
+/// Bitmasks of all the optimal directions for every cell, using the same encoding as the
+/// direction picker (001 match, 010 gap in y, 100 gap in x).
+pub(super) struct TieMatrices {
+    pub(super) m: Vec<Vec<u8>>,
+    pub(super) x: Vec<Vec<u8>>,
+    pub(super) y: Vec<Vec<u8>>,
+}
+
+impl TieMatrices {
+    pub(super) fn new(len1: usize, len2: usize) -> TieMatrices {
+        TieMatrices {
+            m: vec![vec![0b001; len2]; len1],
+            x: vec![vec![0b010; len2]; len1],
+            y: vec![vec![0b100; len2]; len1],
+        }
+    }
+}
+
+/// Number of residues of the gap that ends in every cell of the gap states, following the stored
+/// directions. Only kept for gap functions that are not affine. A cell only knows the length of
+/// the gap it chose, which makes the concave gap costs a greedy approximation: a longer gap
+/// that is more expensive at the cell but cheaper to extend is dropped. Kept in two rows like
+/// the scores.
+pub(super) struct GapLengths {
+    pub(super) x: Vec<Vec<u32>>,
+    pub(super) y: Vec<Vec<u32>>,
+}
+
+impl GapLengths {
+    pub(super) fn new(len1: usize, len2: usize, two_rows: bool) -> GapLengths {
+        GapLengths {
+            x: matrix_rows(len1, len2, two_rows, 0),
+            y: matrix_rows(len1, len2, two_rows, 0),
+        }
+    }
+}
+
 pub(crate) struct ParsimonyAlignmentMatrices {
     pub(crate) rows: usize,
     pub(crate) cols: usize,
     pub(super) score: ScoreMatrices,
     pub(super) trace: TracebackMatrices,
+    pub(super) ties: TieMatrices,
     pub(super) direction_picker: [&'static [Direction]; 8],
-    pub(crate) rng: fn(usize) -> usize,
+    pub(crate) rng: Mutex<Box<dyn FnMut(usize) -> usize + Send>>,
+    pub(crate) tie_break: TieBreak,
+    pub(crate) wavefront: bool,
+    pub(crate) two_score_rows: bool,
+    pub(crate) band: Option<usize>,
+    pub(crate) x_drop: Option<f64>,
+    pub(crate) free_end_gaps: bool,
+    pub(crate) gap_function: GapFunction,
+    pub(super) gap_lengths: Option<GapLengths>,
+    /// Site category of the match of every cell, with site categories only.
+    pub(super) match_categories: Option<Vec<Vec<u8>>>,
+    /// Number of cells whose scores were computed by the fill.
+    pub(crate) cells_filled: u64,
+    /// Number of choices between several optimal directions.
+    pub(crate) tie_breaks: AtomicU64,
 }
 
 impl fmt::Display for ParsimonyAlignmentMatrices {
@@ -83,16 +177,87 @@ impl fmt::Display for ParsimonyAlignmentMatrices {
     }
 }
 
+/// Costs of a branch with the gap costs scaled by the gap weight of the site they are charged
+/// against.
+struct WeightedGapCosts<'a> {
+    costs: &'a dyn BranchCosts,
+    weight: f64,
+}
+
+impl<'a> WeightedGapCosts<'a> {
+    fn new(costs: &'a dyn BranchCosts, weight: f64) -> Self {
+        WeightedGapCosts { costs, weight }
+    }
+}
+
+impl BranchCosts for WeightedGapCosts<'_> {
+    fn match_cost(&self, i: u8, j: u8) -> f64 {
+        self.costs.match_cost(i, j)
+    }
+
+    fn gap_open_cost(&self) -> f64 {
+        self.weight * self.costs.gap_open_cost()
+    }
+
+    fn gap_ext_cost(&self) -> f64 {
+        self.weight * self.costs.gap_ext_cost()
+    }
+
+    fn avg_cost(&self) -> f64 {
+        self.costs.avg_cost()
+    }
+
+    fn min_match_costs(&self, ancestors: &ParsimonySet, set: &ParsimonySet) -> Vec<f64> {
+        self.costs.min_match_costs(ancestors, set)
+    }
+
+    fn site_categories(&self) -> usize {
+        self.costs.site_categories()
+    }
+
+    fn category_min_match_costs(
+        &self,
+        category: usize,
+        ancestors: &ParsimonySet,
+        set: &ParsimonySet,
+        costs: &mut Vec<f64>,
+    ) {
+        self.costs
+            .category_min_match_costs(category, ancestors, set, costs)
+    }
+}
+
+/// Minimum costs of the ancestors to the set written into `costs`, with the costs of the site
+/// category if the site has one.
+fn category_min_match_costs(
+    a_set: &ParsimonySet,
+    c_set: &ParsimonySet,
+    c_scor: &dyn BranchCosts,
+    category: Option<u8>,
+    costs: &mut Vec<f64>,
+) {
+    match category {
+        Some(category) => c_scor.category_min_match_costs(category as usize, a_set, c_set, costs),
+        None => *costs = c_scor.min_match_costs(a_set, c_set),
+    }
+}
+
 fn score_match_one_branch(
     a_set: &ParsimonySet,
     c_set: &ParsimonySet,
     c_scor: &dyn BranchCosts,
+    category: Option<u8>,
 ) -> f64 {
-    a_set
-        .iter()
-        .map(|&ancestor| min_score(c_set, c_scor, ancestor))
-        .min_by(cmp_f64())
-        .unwrap()
+    let mut costs = Vec::new();
+    category_min_match_costs(a_set, c_set, c_scor, category, &mut costs);
+    costs.into_iter().min_by(cmp_f64()).unwrap()
+}
+
+/// Buffers of the costs of the two branches, kept over the site categories of a cell.
+#[derive(Default)]
+struct MatchCostBuffers {
+    x: Vec<f64>,
+    y: Vec<f64>,
 }
 
 fn score_match_both_branches(
@@ -101,32 +266,102 @@ fn score_match_both_branches(
     x_scor: &dyn BranchCosts,
     y_set: &ParsimonySet,
     y_scor: &dyn BranchCosts,
+    category: Option<u8>,
+    buffers: &mut MatchCostBuffers,
 ) -> f64 {
-    a_set
-        .iter()
-        .map(|&ancestor| min_score(x_set, x_scor, ancestor) + min_score(y_set, y_scor, ancestor))
+    category_min_match_costs(a_set, x_set, x_scor, category, &mut buffers.x);
+    category_min_match_costs(a_set, y_set, y_scor, category, &mut buffers.y);
+    zip(&buffers.x, &buffers.y)
+        .map(|(x_cost, y_cost)| x_cost + y_cost)
         .min_by(cmp_f64())
         .unwrap()
 }
 
-fn min_score(set: &ParsimonySet, scor: &dyn BranchCosts, ancestor: u8) -> f64 {
-    set.iter()
-        .map(|&child| scor.match_cost(ancestor, child))
-        .min_by(cmp_f64())
-        .unwrap()
+/// Cost of matching the sites of x and y to the ancestral set and the site category of the
+/// match, none without site categories. A site with a category keeps it, two sites of
+/// different categories take the cheaper one and two sites without a category, which were not
+/// matched before, the cheapest of all.
+fn score_match_sites(
+    a_set: &ParsimonySet,
+    x_site: &SiteInfo,
+    x_scor: &dyn BranchCosts,
+    y_site: &SiteInfo,
+    y_scor: &dyn BranchCosts,
+) -> (f64, Option<u8>) {
+    let categories = x_scor.site_categories().min(u8::MAX as usize + 1);
+    let mut buffers = MatchCostBuffers::default();
+    if categories == 0 {
+        let score = score_match_both_branches(
+            a_set,
+            &x_site.set,
+            x_scor,
+            &y_site.set,
+            y_scor,
+            None,
+            &mut buffers,
+        );
+        return (score, None);
+    }
+    let mut score_in = |category: u8| {
+        let score = score_match_both_branches(
+            a_set,
+            &x_site.set,
+            x_scor,
+            &y_site.set,
+            y_scor,
+            Some(category),
+            &mut buffers,
+        );
+        (score, Some(category))
+    };
+    let cheapest = |a: &(f64, Option<u8>), b: &(f64, Option<u8>)| a.0.total_cmp(&b.0);
+    match (x_site.site_category, y_site.site_category) {
+        (None, None) => (0..categories)
+            .map(|category| score_in(category as u8))
+            .min_by(cheapest),
+        (Some(category), None) | (None, Some(category)) => Some(score_in(category)),
+        (Some(x_category), Some(y_category)) => [x_category, y_category]
+            .into_iter()
+            .map(score_in)
+            .min_by(cheapest),
+    }
+    .unwrap()
+}
+
+/// Ancestral site and cost of matching a site of x with a site of y outside of the DP, with the
+/// same set and gap weight as a match column of the traceback.
+pub(crate) fn match_sites(
+    x_site: &SiteInfo,
+    x_scor: &dyn BranchCosts,
+    y_site: &SiteInfo,
+    y_scor: &dyn BranchCosts,
+) -> (SiteInfo, f64) {
+    let mut set = &x_site.set & &y_site.set;
+    if set.is_empty() {
+        set = &x_site.set | &y_site.set;
+    }
+    let (score, site_category) = score_match_sites(&set, x_site, x_scor, y_site, y_scor);
+    let gap_weight = x_site.gap_weight.max(y_site.gap_weight);
+    (
+        SiteInfo::from_set(set, NoGap)
+            .with_gap_weight(gap_weight)
+            .with_site_category(site_category),
+        score,
+    )
 }
 
 impl ParsimonyAlignmentMatrices {
     pub(crate) fn new(
         rows: usize,
         cols: usize,
-        rng: fn(usize) -> usize,
+        rng: impl FnMut(usize) -> usize + Send + 'static,
     ) -> ParsimonyAlignmentMatrices {
         ParsimonyAlignmentMatrices {
             rows,
             cols,
-            score: ScoreMatrices::new(rows, cols),
+            score: ScoreMatrices::default(),
             trace: TracebackMatrices::new(rows, cols),
+            ties: TieMatrices::new(rows, cols),
             direction_picker: [
                 /* 000 */ &[][..],
                 /* 001 */ &[Matc][..],
@@ -137,11 +372,163 @@ impl ParsimonyAlignmentMatrices {
                 /* 110 */ &[GapInY, GapInX][..],
                 /* 111 */ &[Matc, GapInX, GapInY][..],
             ],
-            rng,
+            rng: Mutex::new(Box::new(rng)),
+            tie_break: TieBreak::Random,
+            wavefront: false,
+            two_score_rows: false,
+            band: None,
+            x_drop: None,
+            free_end_gaps: false,
+            gap_function: GapFunction::Affine,
+            gap_lengths: None,
+            match_categories: None,
+            cells_filled: 0,
+            tie_breaks: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    /// Fills the matrices one anti-diagonal at a time with the cells of a diagonal computed in
+    /// parallel, see `fill_matrices_wavefront`.
+    pub(crate) fn with_wavefront(mut self, wavefront: bool) -> Self {
+        self.wavefront = wavefront;
+        self
+    }
+
+    /// Allocates only two rows of the score matrices and of the gap lengths for the row by row
+    /// fill, a row is reused as soon as the fill no longer needs it and only the last row is
+    /// left afterwards. Only the traceback that follows the stored directions can be used
+    /// afterwards. The wavefront fill, also used for the X-drop, always keeps all the scores.
+    /// The directions and tie masks are kept for every cell, so the memory stays quadratic.
+    pub(crate) fn with_two_score_rows(mut self, two_score_rows: bool) -> Self {
+        self.two_score_rows = two_score_rows;
+        self
+    }
+
+    /// Restricts the fill to the cells within `band` diagonals of the main diagonal, widened by
+    /// the length difference of the sequences so that the band always holds the last cell. The
+    /// cells outside of the band are not computed and get an infinite score. A banded fill is
+    /// always done row by row.
+    pub(crate) fn with_band(mut self, band: Option<usize>) -> Self {
+        self.band = band;
+        self
+    }
+
+    /// Cuts off the cells of an anti-diagonal whose score is more than `x_drop` above the best
+    /// score of that diagonal. The cut off cells get an infinite score and the cells that can
+    /// only be reached through them are not computed. Pruning needs the matrices to be filled
+    /// one diagonal at a time, so it is only done by the wavefront fill, a band or constraints
+    /// take precedence over it.
+    pub(crate) fn with_x_drop(mut self, x_drop: Option<f64>) -> Self {
+        self.x_drop = x_drop;
+        self
+    }
+
+    /// Makes the leading and trailing gaps against the sites of the longer sequence free, so
+    /// that a shorter fragment is aligned within it. The end gaps of the shorter sequence keep
+    /// their costs, otherwise leaving the two sequences unaligned would cost nothing.
+    pub(crate) fn with_free_end_gaps(mut self, free_end_gaps: bool) -> Self {
+        self.free_end_gaps = free_end_gaps;
+        self
+    }
+
+    /// Penalizes gaps with `gap_function` instead of the affine costs. The length of the gap
+    /// ending in every cell is tracked along the stored directions, so the extension costs
+    /// follow the chosen path in the same way as the choice between opening and extending. This
+    /// is not the exact DP for concave costs, see `GapLengths`.
+    pub(crate) fn with_gap_function(mut self, gap_function: GapFunction) -> Self {
+        self.gap_function = gap_function;
+        self
+    }
+
+    /// Allocates the score matrices and the gap lengths for a fill, see `with_two_score_rows`.
+    fn allocate_scores(&mut self, two_rows: bool) {
+        self.score = ScoreMatrices::new(self.rows, self.cols, two_rows);
+        self.gap_lengths = (self.gap_function != GapFunction::Affine)
+            .then(|| GapLengths::new(self.rows, self.cols, two_rows));
+    }
+
+    /// Whether `fill_matrices` fills the matrices one anti-diagonal at a time, which keeps all
+    /// the scores.
+    pub(crate) fn fills_by_diagonals(&self) -> bool {
+        self.band.is_none() && (self.wavefront || self.x_drop.is_some())
+    }
+
+    /// Multiplier of the costs of a gap against a site of x, nothing for the end gaps when
+    /// they are free and x is the longer sequence.
+    fn x_gap_weight(&self, site: &SiteInfo, end_gap: bool) -> f64 {
+        if self.free_end_gaps && end_gap && self.rows >= self.cols {
+            0.0
+        } else {
+            site.gap_weight
         }
     }
 
+    /// Multiplier of the costs of a gap against a site of y, see `x_gap_weight`.
+    fn y_gap_weight(&self, site: &SiteInfo, end_gap: bool) -> f64 {
+        if self.free_end_gaps && end_gap && self.cols > self.rows {
+            0.0
+        } else {
+            site.gap_weight
+        }
+    }
+
+    /// Lowest and highest `j - i` of the cells within the band.
+    fn band_limits(&self, band: usize) -> (isize, isize) {
+        let diff = self.cols as isize - self.rows as isize;
+        (diff.min(0) - band as isize, diff.max(0) + band as isize)
+    }
+
+    /// Whether the alignment passes through a cell on the edge of the band, in which case a
+    /// better alignment might have been cut off by it.
+    pub(crate) fn touches_band_edge(&self, alignment: &Alignment) -> bool {
+        let Some(band) = self.band else {
+            return false;
+        };
+        let (low, high) = self.band_limits(band);
+        let low = (low > 1 - self.rows as isize).then_some(low);
+        let high = (high < self.cols as isize - 1).then_some(high);
+        let (mut i, mut j) = (0, 0);
+        zip(&alignment.map_x, &alignment.map_y).any(|(x, y)| {
+            if let Some(x) = x {
+                i = *x as isize + 1;
+            }
+            if let Some(y) = y {
+                j = *y as isize + 1;
+            }
+            Some(j - i) == low || Some(j - i) == high
+        })
+    }
+
+    fn pick_direction(&self, options: &[Direction]) -> Direction {
+        if options.len() > 1 {
+            self.tie_breaks.fetch_add(1, Relaxed);
+        }
+        let preference = match self.tie_break {
+            TieBreak::Random => {
+                let mut rng = self.rng.lock().unwrap();
+                return options[(*rng)(options.len())];
+            }
+            TieBreak::Match => [Matc, GapInX, GapInY],
+            TieBreak::GapInX => [GapInX, GapInY, Matc],
+            TieBreak::GapInY => [GapInY, GapInX, Matc],
+        };
+        *preference
+            .iter()
+            .find(|&direction| options.contains(direction))
+            .unwrap()
+    }
+
     fn select_direction(&self, sm: f64, sx: f64, sy: f64) -> (f64, Direction) {
+        let (min_val, direction, _) = self.select_direction_w_ties(sm, sx, sy);
+        (min_val, direction)
+    }
+
+    fn select_direction_w_ties(&self, sm: f64, sx: f64, sy: f64) -> (f64, Direction, u8) {
         let (mut min_val, mut sel_mat) = (sm, 0b001);
         if sx < min_val {
             (min_val, sel_mat) = (sx, 0b010);
@@ -155,7 +542,8 @@ impl ParsimonyAlignmentMatrices {
         }
         (
             min_val,
-            self.direction_picker[sel_mat][(self.rng)(self.direction_picker[sel_mat].len())],
+            self.pick_direction(self.direction_picker[sel_mat]),
+            sel_mat as u8,
         )
     }
 
@@ -166,9 +554,208 @@ impl ParsimonyAlignmentMatrices {
         y_info: &[SiteInfo],
         y_scor: &dyn BranchCosts,
     ) {
-        self.init_x(x_info, x_scor, y_scor);
+        if let Some(band) = self.band {
+            let (low, high) = self.band_limits(band);
+            let in_band = |_: Direction, i: usize, j: usize| {
+                (low..=high).contains(&(j as isize - i as isize))
+            };
+            self.fill_matrices_constrained(x_info, x_scor, y_info, y_scor, Some(&in_band));
+        } else if self.fills_by_diagonals() {
+            self.fill_matrices_wavefront(x_info, x_scor, y_info, y_scor);
+        } else {
+            self.fill_matrices_constrained(x_info, x_scor, y_info, y_scor, None);
+        }
+    }
+
+    /// Fills the matrices so that every alignment matches the sites of the forced cells and none
+    /// of the sites of the forbidden cells, the cell (i, j) stands for the sites i - 1 of x and
+    /// j - 1 of y. The forced cells must be increasing in both coordinates. An alignment through
+    /// such a cell has all its other cells either above and to the left of it or below and to
+    /// the right, so all the other cells are masked. The forbidden cells are masked in the match
+    /// state only. With constraints the matrices are always filled row by row.
+    pub(crate) fn fill_matrices_w_matches(
+        &mut self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+        constraints: &NodeConstraints,
+    ) {
+        if constraints.is_empty() {
+            return self.fill_matrices(x_info, x_scor, y_info, y_scor);
+        }
+        let forced = &constraints.forced;
+        let band = self.band.map(|band| self.band_limits(band));
+        let allowed = |state: Direction, i: usize, j: usize| {
+            if band.is_some_and(|(low, high)| !(low..=high).contains(&(j as isize - i as isize))) {
+                return false;
+            }
+            if state == Matc && constraints.is_forbidden(i, j) {
+                return false;
+            }
+            let passed = forced.partition_point(|&(p, _)| p <= i);
+            let before_next = forced.get(passed).map_or(true, |&(_, q)| j < q);
+            let after_last = passed == 0 || {
+                let (p, q) = forced[passed - 1];
+                j > q || (j == q && (i > p || state == Matc))
+            };
+            before_next && after_last
+        };
+        self.fill_matrices_constrained(x_info, x_scor, y_info, y_scor, Some(&allowed));
+    }
+
+    /// Fills the matrices one anti-diagonal at a time. The cells of a diagonal only depend on the
+    /// cells of the previous diagonals, so their candidate scores are computed in parallel. The
+    /// directions are then picked in order along the diagonal, which keeps the result
+    /// reproducible, but with random tie breaking the choices differ from the row by row fill.
+    /// With an X-drop only the cells next to the surviving cells of the previous two diagonals
+    /// are computed, the others get an infinite score.
+    fn fill_matrices_wavefront(
+        &mut self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+    ) {
+        self.allocate_scores(false);
         self.init_y(y_info, x_scor, y_scor);
         for i in 1..self.rows {
+            self.init_x_row(i, x_info, x_scor, y_scor);
+        }
+        self.init_match_categories(x_scor);
+        // first and last row of the surviving cells of the previous two diagonals
+        let mut live = [(0, 0), (0, 0)];
+        if let Some(x_drop) = self.x_drop {
+            live[1] = self.prune_diagonal(1, x_drop);
+        }
+        for diag in 2..(self.rows + self.cols).saturating_sub(1) {
+            let cell_first = diag.saturating_sub(self.cols - 1).max(1);
+            let cell_last = (diag - 1).min(self.rows - 1);
+            let (first, last) = match self.x_drop {
+                Some(_) => (
+                    cell_first.max(live[1].0.min(live[0].0.saturating_add(1))),
+                    cell_last.min(live[1].1.max(live[0].1) + 1),
+                ),
+                None => (cell_first, cell_last),
+            };
+            let candidates: Vec<Option<([[f64; 3]; 3], Option<u8>)>> = (first..=last)
+                .into_par_iter()
+                .map(|i| {
+                    let j = diag - i;
+                    if x_info[i - 1].is_fixed() || y_info[j - 1].is_fixed() {
+                        None
+                    } else {
+                        let (m, category) =
+                            self.s_m_candidates(i - 1, j - 1, x_info, x_scor, y_info, y_scor);
+                        Some((
+                            [
+                                m,
+                                self.s_x_candidates(i - 1, j, x_info, x_scor, y_info, y_scor),
+                                self.s_y_candidates(i, j - 1, x_info, x_scor, y_info, y_scor),
+                            ],
+                            category,
+                        ))
+                    }
+                })
+                .collect();
+            for i in (cell_first..=cell_last).filter(|i| !(first..=last).contains(i)) {
+                let j = diag - i;
+                self.score.m[i][j] = INF;
+                self.score.x[i][j] = INF;
+                self.score.y[i][j] = INF;
+            }
+            for (i, cell) in zip(first..=last, candidates) {
+                let j = diag - i;
+                match cell {
+                    None => {
+                        let ni = i - x_info[i - 1].is_fixed() as usize;
+                        let nj = j - y_info[j - 1].is_fixed() as usize;
+                        self.score.m[i][j] = self.score.m[ni][nj];
+                        self.score.x[i][j] = self.score.x[ni][nj];
+                        self.score.y[i][j] = self.score.y[ni][nj];
+                        self.copy_gap_lengths(i, j, ni, nj);
+                    }
+                    Some(([[mm, mx, my], [xm, xx, xy], [ym, yx, yy]], category)) => {
+                        self.cells_filled += 1;
+                        self.set_match_category(i, j, category);
+                        (self.score.m[i][j], self.trace.m[i][j], self.ties.m[i][j]) =
+                            self.select_direction_w_ties(mm, mx, my);
+                        (self.score.x[i][j], self.trace.x[i][j], self.ties.x[i][j]) =
+                            self.select_direction_w_ties(xm, xx, xy);
+                        (self.score.y[i][j], self.trace.y[i][j], self.ties.y[i][j]) =
+                            self.select_direction_w_ties(ym, yx, yy);
+                        self.update_gap_lengths(i, j, x_info, y_info);
+                    }
+                }
+            }
+            if let Some(x_drop) = self.x_drop {
+                live = [live[1], self.prune_diagonal(diag, x_drop)];
+            }
+        }
+        debug!("{}", self);
+    }
+
+    /// Gives an infinite score to the cells of the anti-diagonal whose best state scores more
+    /// than `x_drop` above the best cell of the diagonal, and returns the first and last row of
+    /// the cells that are left. The best cell always survives, so the last cell stays reachable.
+    fn prune_diagonal(&mut self, diag: usize, x_drop: f64) -> (usize, usize) {
+        let cell_score = |score: &ScoreMatrices, i: usize, j: usize| {
+            score.m[i][j].min(score.x[i][j]).min(score.y[i][j])
+        };
+        let rows = diag.saturating_sub(self.cols - 1)..=diag.min(self.rows - 1);
+        let best = rows
+            .clone()
+            .map(|i| cell_score(&self.score, i, diag - i))
+            .fold(INF, f64::min);
+        let mut live = (usize::MAX, 0);
+        for i in rows {
+            let j = diag - i;
+            let score = cell_score(&self.score, i, j);
+            if score > best + x_drop {
+                self.score.m[i][j] = INF;
+                self.score.x[i][j] = INF;
+                self.score.y[i][j] = INF;
+            } else if score < INF {
+                live = (live.0.min(i), live.1.max(i));
+            }
+        }
+        live
+    }
+
+    /// Fills the matrices, the states of the cells for which `allowed(state, i, j)` is false
+    /// get an infinite score so that no alignment can pass through them.
+    pub(crate) fn fill_matrices_constrained(
+        &mut self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+        allowed: Option<&dyn Fn(Direction, usize, usize) -> bool>,
+    ) {
+        self.allocate_scores(self.two_score_rows);
+        self.init_y(y_info, x_scor, y_scor);
+        self.init_match_categories(x_scor);
+        if let Some(allowed) = allowed {
+            // fixed sites do not change the score, so they carry over the masked scores
+            for j in 1..self.cols {
+                if y_info[j - 1].is_fixed() {
+                    self.score.y[0][j] = self.score.y[0][j - 1];
+                } else if !allowed(GapInX, 0, j) {
+                    self.score.y[0][j] = INF;
+                }
+            }
+        }
+        let band = self.band.map(|band| self.band_limits(band));
+        for i in 1..self.rows {
+            // the first column is set up row by row, the rows above may be gone already
+            self.init_x_row(i, x_info, x_scor, y_scor);
+            if let Some(allowed) = allowed {
+                if x_info[i - 1].is_fixed() {
+                    self.score.x[i][0] = self.score.x[i - 1][0];
+                } else if !allowed(GapInY, i, 0) {
+                    self.score.x[i][0] = INF;
+                }
+            }
             for j in 1..self.cols {
                 if x_info[i - 1].is_fixed() || y_info[j - 1].is_fixed() {
                     let ni = i - x_info[i - 1].is_fixed() as usize;
@@ -176,39 +763,147 @@ impl ParsimonyAlignmentMatrices {
                     self.score.m[i][j] = self.score.m[ni][nj];
                     self.score.x[i][j] = self.score.x[ni][nj];
                     self.score.y[i][j] = self.score.y[ni][nj];
+                    self.copy_gap_lengths(i, j, ni, nj);
+                } else if band
+                    .is_some_and(|(low, high)| !(low..=high).contains(&(j as isize - i as isize)))
+                {
+                    self.score.m[i][j] = INF;
+                    self.score.x[i][j] = INF;
+                    self.score.y[i][j] = INF;
                 } else {
-                    (self.score.m[i][j], self.trace.m[i][j]) =
+                    self.cells_filled += 1;
+                    let (score, direction, ties, category) =
                         self.fill_s_m(i - 1, j - 1, x_info, x_scor, y_info, y_scor);
-                    (self.score.x[i][j], self.trace.x[i][j]) =
+                    (self.score.m[i][j], self.trace.m[i][j], self.ties.m[i][j]) =
+                        (score, direction, ties);
+                    self.set_match_category(i, j, category);
+                    (self.score.x[i][j], self.trace.x[i][j], self.ties.x[i][j]) =
                         self.fill_s_x(i - 1, j, x_info, x_scor, y_info, y_scor);
-                    (self.score.y[i][j], self.trace.y[i][j]) =
+                    (self.score.y[i][j], self.trace.y[i][j], self.ties.y[i][j]) =
                         self.fill_s_y(i, j - 1, x_info, x_scor, y_info, y_scor);
+                    self.update_gap_lengths(i, j, x_info, y_info);
+                    if let Some(allowed) = allowed {
+                        self.mask_cell(i, j, allowed);
+                    }
+                }
+            }
+            // the next row only reads the scores of this one
+            if self.two_score_rows {
+                self.score.reuse_row(i);
+                if let Some(lengths) = &mut self.gap_lengths {
+                    reuse_row(&mut lengths.x, i, 0);
+                    reuse_row(&mut lengths.y, i, 0);
                 }
             }
         }
         debug!("{}", self);
     }
 
-    fn init_x(&mut self, x_info: &[SiteInfo], x_scor: &dyn BranchCosts, y_scor: &dyn BranchCosts) {
-        for i in 1..self.rows {
-            self.score.x[i][0] = self.score.x[i - 1][0]
-                + if x_info[i - 1].no_gap() {
-                    score_match_one_branch(&x_info[i - 1].set, &x_info[i - 1].set, x_scor)
-                        + if self.score.x[i - 1][0] == 0.0 {
-                            y_scor.gap_open_cost()
-                        } else {
-                            y_scor.gap_ext_cost()
-                        }
-                } else {
-                    0.0
-                };
-            if !x_info[i - 1].is_fixed() {
-                self.trace.m[i][0] = GapInY;
-                self.trace.x[i][0] = GapInY;
-                self.trace.y[i][0] = GapInY;
-            }
-            self.score.y[i][0] = INF;
-            self.score.m[i][0] = INF;
+    /// Multiplier of the extension cost of the gap in y that continues from the x state of
+    /// cell (i, j).
+    fn x_ext_multiplier(&self, i: usize, j: usize) -> f64 {
+        self.gap_lengths.as_ref().map_or(1.0, |lengths| {
+            self.gap_function.ext_multiplier(lengths.x[i][j].max(1) + 1)
+        })
+    }
+
+    /// Multiplier of the extension cost of the gap in x that continues from the y state of
+    /// cell (i, j).
+    fn y_ext_multiplier(&self, i: usize, j: usize) -> f64 {
+        self.gap_lengths.as_ref().map_or(1.0, |lengths| {
+            self.gap_function.ext_multiplier(lengths.y[i][j].max(1) + 1)
+        })
+    }
+
+    /// Sets the gap lengths of a filled cell from the directions stored for it, the gap sites
+    /// of the profiles do not add to the length.
+    fn update_gap_lengths(&mut self, i: usize, j: usize, x_info: &[SiteInfo], y_info: &[SiteInfo]) {
+        let Some(lengths) = &mut self.gap_lengths else {
+            return;
+        };
+        lengths.x[i][j] = x_info[i - 1].no_gap() as u32
+            + if self.trace.x[i][j] == GapInY {
+                lengths.x[i - 1][j]
+            } else {
+                0
+            };
+        lengths.y[i][j] = y_info[j - 1].no_gap() as u32
+            + if self.trace.y[i][j] == GapInX {
+                lengths.y[i][j - 1]
+            } else {
+                0
+            };
+    }
+
+    /// Carries the gap lengths over a cell with a fixed gap site from the cell (ni, nj).
+    fn copy_gap_lengths(&mut self, i: usize, j: usize, ni: usize, nj: usize) {
+        if let Some(lengths) = &mut self.gap_lengths {
+            lengths.x[i][j] = lengths.x[ni][nj];
+            lengths.y[i][j] = lengths.y[ni][nj];
+        }
+    }
+
+    /// Keeps the site category of the match of every cell for the traceback, if the scoring has
+    /// site categories.
+    fn init_match_categories(&mut self, x_scor: &dyn BranchCosts) {
+        self.match_categories =
+            (x_scor.site_categories() > 0).then(|| vec![vec![0; self.cols]; self.rows]);
+    }
+
+    fn set_match_category(&mut self, i: usize, j: usize, category: Option<u8>) {
+        if let (Some(categories), Some(category)) = (&mut self.match_categories, category) {
+            categories[i][j] = category;
+        }
+    }
+
+    fn mask_cell(&mut self, i: usize, j: usize, allowed: &dyn Fn(Direction, usize, usize) -> bool) {
+        if !allowed(Matc, i, j) {
+            self.score.m[i][j] = INF;
+        }
+        if !allowed(GapInY, i, j) {
+            self.score.x[i][j] = INF;
+        }
+        if !allowed(GapInX, i, j) {
+            self.score.y[i][j] = INF;
+        }
+    }
+
+    /// Sets up the first cell of row `i`, a gap in y against the first `i` sites of x.
+    fn init_x_row(
+        &mut self,
+        i: usize,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_scor: &dyn BranchCosts,
+    ) {
+        self.score.x[i][0] = self.score.x[i - 1][0]
+            + if x_info[i - 1].no_gap() {
+                score_match_one_branch(
+                    &x_info[i - 1].set,
+                    &x_info[i - 1].set,
+                    x_scor,
+                    x_info[i - 1].site_category,
+                ) + self.x_gap_weight(&x_info[i - 1], true)
+                    * if self.score.x[i - 1][0] == 0.0 {
+                        y_scor.gap_open_cost()
+                    } else {
+                        y_scor.gap_ext_cost() * self.x_ext_multiplier(i - 1, 0)
+                    }
+            } else {
+                0.0
+            };
+        if !x_info[i - 1].is_fixed() {
+            self.trace.m[i][0] = GapInY;
+            self.trace.x[i][0] = GapInY;
+            self.trace.y[i][0] = GapInY;
+            self.ties.m[i][0] = 0b010;
+            self.ties.x[i][0] = 0b010;
+            self.ties.y[i][0] = 0b010;
+        }
+        self.score.y[i][0] = INF;
+        self.score.m[i][0] = INF;
+        if let Some(lengths) = &mut self.gap_lengths {
+            lengths.x[i][0] = lengths.x[i - 1][0] + x_info[i - 1].no_gap() as u32;
         }
     }
 
@@ -216,11 +911,16 @@ impl ParsimonyAlignmentMatrices {
         for j in 1..self.cols {
             self.score.y[0][j] = self.score.y[0][j - 1]
                 + if y_info[j - 1].no_gap() {
-                    score_match_one_branch(&y_info[j - 1].set, &y_info[j - 1].set, y_scor)
-                        + if self.score.y[0][j - 1] == 0.0 {
+                    score_match_one_branch(
+                        &y_info[j - 1].set,
+                        &y_info[j - 1].set,
+                        y_scor,
+                        y_info[j - 1].site_category,
+                    ) + self.y_gap_weight(&y_info[j - 1], true)
+                        * if self.score.y[0][j - 1] == 0.0 {
                             x_scor.gap_open_cost()
                         } else {
-                            x_scor.gap_ext_cost()
+                            x_scor.gap_ext_cost() * self.y_ext_multiplier(0, j - 1)
                         }
                 } else {
                     0.0
@@ -229,9 +929,15 @@ impl ParsimonyAlignmentMatrices {
                 self.trace.m[0][j] = GapInX;
                 self.trace.x[0][j] = GapInX;
                 self.trace.y[0][j] = GapInX;
+                self.ties.m[0][j] = 0b100;
+                self.ties.x[0][j] = 0b100;
+                self.ties.y[0][j] = 0b100;
             }
             self.score.x[0][j] = INF;
             self.score.m[0][j] = INF;
+            if let Some(lengths) = &mut self.gap_lengths {
+                lengths.y[0][j] = lengths.y[0][j - 1] + y_info[j - 1].no_gap() as u32;
+            }
         }
     }
 
@@ -243,26 +949,52 @@ impl ParsimonyAlignmentMatrices {
         x_scor: &dyn BranchCosts,
         y_info: &[SiteInfo],
         y_scor: &dyn BranchCosts,
-    ) -> (f64, Direction) {
+    ) -> (f64, Direction, u8, Option<u8>) {
+        let ([sm, sx, sy], category) = self.s_m_candidates(i, j, x_info, x_scor, y_info, y_scor);
+        let (score, direction, ties) = self.select_direction_w_ties(sm, sx, sy);
+        (score, direction, ties, category)
+    }
+
+    /// Candidate scores of the match state from the three states of the cell (i, j), and the
+    /// site category of the match.
+    fn s_m_candidates(
+        &self,
+        i: usize,
+        j: usize,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+    ) -> ([f64; 3], Option<u8>) {
         let anc_set = if !(&x_info[i].set & &y_info[j].set).is_empty() {
             &x_info[i].set & &y_info[j].set
         } else {
             &x_info[i].set | &y_info[j].set
         };
-        let match_score =
-            score_match_both_branches(&anc_set, &x_info[i].set, x_scor, &y_info[j].set, y_scor);
+        let (match_score, category) =
+            score_match_sites(&anc_set, &x_info[i], x_scor, &y_info[j], y_scor);
         debug!(
             "Match score for {} and {}: {}",
             print_parsimony_set(&x_info[i].set),
             print_parsimony_set(&y_info[j].set),
             match_score
         );
-        let (x_gap_adj, y_gap_adj) =
-            self.score_match_gap_cost_adjustment(i, j, x_info, x_scor, y_info, y_scor);
-        self.select_direction(
-            self.score.m[i][j] + match_score,
-            self.score.x[i][j] + x_gap_adj + match_score,
-            self.score.y[i][j] + y_gap_adj + match_score,
+        // the adjustments close the gaps against the matched sites
+        let (x_gap_adj, y_gap_adj) = self.score_match_gap_cost_adjustment(
+            i,
+            j,
+            x_info,
+            &WeightedGapCosts::new(x_scor, self.y_gap_weight(&y_info[j], i == 0)),
+            y_info,
+            &WeightedGapCosts::new(y_scor, self.x_gap_weight(&x_info[i], j == 0)),
+        );
+        (
+            [
+                self.score.m[i][j] + match_score,
+                self.score.x[i][j] + x_gap_adj + match_score,
+                self.score.y[i][j] + y_gap_adj + match_score,
+            ],
+            category,
         )
     }
 
@@ -310,24 +1042,44 @@ impl ParsimonyAlignmentMatrices {
         x_scor: &dyn BranchCosts,
         y_info: &[SiteInfo],
         y_scor: &dyn BranchCosts,
-    ) -> (f64, Direction) {
-        let (sm, sx, sy) = match x_info[i].flag {
-            GapOpen | GapFixed => (self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]),
-            GapExt => (
+    ) -> (f64, Direction, u8) {
+        let [sm, sx, sy] = self.s_x_candidates(i, j, x_info, x_scor, y_info, y_scor);
+        self.select_direction_w_ties(sm, sx, sy)
+    }
+
+    fn s_x_candidates(
+        &self,
+        i: usize,
+        j: usize,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+    ) -> [f64; 3] {
+        let gap_weight = self.x_gap_weight(&x_info[i], j == self.cols - 1);
+        let x_scor = &WeightedGapCosts::new(x_scor, gap_weight);
+        let y_scor = &WeightedGapCosts::new(y_scor, gap_weight);
+        match x_info[i].flag {
+            GapOpen | GapFixed => [self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]],
+            GapExt => [
                 self.score.m[i][j] + x_scor.gap_open_cost() - x_scor.gap_ext_cost(),
                 self.score.x[i][j],
                 self.score.y[i][j] + self.gap_y_cost_adjustment(i, j, x_info, x_scor, y_info),
-            ),
+            ],
             NoGap => {
-                let match_score = score_match_one_branch(&x_info[i].set, &x_info[i].set, x_scor);
-                (
+                let match_score = score_match_one_branch(
+                    &x_info[i].set,
+                    &x_info[i].set,
+                    x_scor,
+                    x_info[i].site_category,
+                );
+                [
                     self.score.m[i][j] + match_score + y_scor.gap_open_cost(),
                     self.score.x[i][j] + match_score + self.new_gap_y_score(i, j, x_info, y_scor),
                     self.score.y[i][j] + match_score + y_scor.gap_open_cost(),
-                )
+                ]
             }
-        };
-        self.select_direction(sm, sx, sy)
+        }
     }
 
     fn gap_y_cost_adjustment(
@@ -370,7 +1122,9 @@ impl ParsimonyAlignmentMatrices {
         .rev()
         .find(|(dir, info)| !(dir[j] == GapInY && info.is_possible()))
         .filter(|(dir, info)| !(dir[j] != GapInY && info.is_possible()))
-        .map_or(y_scor.gap_open_cost(), |_| y_scor.gap_ext_cost())
+        .map_or(y_scor.gap_open_cost(), |_| {
+            y_scor.gap_ext_cost() * self.x_ext_multiplier(i, j)
+        })
     }
 
     fn fill_s_y(
@@ -381,24 +1135,44 @@ impl ParsimonyAlignmentMatrices {
         x_scor: &dyn BranchCosts,
         y_info: &[SiteInfo],
         y_scor: &dyn BranchCosts,
-    ) -> (f64, Direction) {
-        let (sm, sx, sy) = match y_info[j].flag {
-            GapFixed | GapOpen => (self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]),
-            GapExt => (
+    ) -> (f64, Direction, u8) {
+        let [sm, sx, sy] = self.s_y_candidates(i, j, x_info, x_scor, y_info, y_scor);
+        self.select_direction_w_ties(sm, sx, sy)
+    }
+
+    fn s_y_candidates(
+        &self,
+        i: usize,
+        j: usize,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+    ) -> [f64; 3] {
+        let gap_weight = self.y_gap_weight(&y_info[j], i == self.rows - 1);
+        let x_scor = &WeightedGapCosts::new(x_scor, gap_weight);
+        let y_scor = &WeightedGapCosts::new(y_scor, gap_weight);
+        match y_info[j].flag {
+            GapFixed | GapOpen => [self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]],
+            GapExt => [
                 self.score.m[i][j] + y_scor.gap_open_cost() - y_scor.gap_ext_cost(),
                 self.score.x[i][j] + self.gap_x_cost_adjustment(i, j, x_info, y_info, y_scor),
                 self.score.y[i][j],
-            ),
+            ],
             NoGap => {
-                let match_score = score_match_one_branch(&y_info[j].set, &y_info[j].set, y_scor);
-                (
+                let match_score = score_match_one_branch(
+                    &y_info[j].set,
+                    &y_info[j].set,
+                    y_scor,
+                    y_info[j].site_category,
+                );
+                [
                     self.score.m[i][j] + match_score + x_scor.gap_open_cost(),
                     self.score.x[i][j] + match_score + x_scor.gap_open_cost(),
                     self.score.y[i][j] + match_score + self.new_x_gap_score(i, j, y_info, x_scor),
-                )
+                ]
             }
-        };
-        self.select_direction(sm, sx, sy)
+        }
     }
 
     fn gap_x_cost_adjustment(
@@ -441,18 +1215,346 @@ impl ParsimonyAlignmentMatrices {
         .rev()
         .find(|(&dir, info)| !(dir == GapInX && info.is_possible()))
         .filter(|(&dir, info)| !(dir != GapInY && info.is_possible()))
-        .map_or(x_scor.gap_open_cost(), |_| x_scor.gap_ext_cost())
+        .map_or(x_scor.gap_open_cost(), |_| {
+            x_scor.gap_ext_cost() * self.y_ext_multiplier(i, j)
+        })
     }
 
     pub(crate) fn traceback(
         &self,
         x_info: &[SiteInfo],
         y_info: &[SiteInfo],
+    ) -> (Vec<SiteInfo>, Alignment, f64) {
+        let (i, j) = (self.rows - 1, self.cols - 1);
+        let (pars_score, action) =
+            self.select_direction(self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]);
+        self.traceback_along(x_info, y_info, pars_score, action, &mut |state, i, j| {
+            self.stored_direction(state, i, j)
+        })
+    }
+
+    /// Cost of every column of an alignment found by the traceback, the difference between the
+    /// score of its cell and the score of the cell it was reached from. Columns with fixed gap
+    /// sites cost nothing, the costs sum up to the score of the alignment. Needs the scores of
+    /// all the cells, so the matrices cannot be filled with two score rows.
+    pub(crate) fn site_costs(
+        &self,
+        x_info: &[SiteInfo],
+        y_info: &[SiteInfo],
+        alignment: &Alignment,
+    ) -> Vec<f64> {
+        debug_assert!(!self.two_score_rows);
+        let (mut i, mut j) = (0, 0);
+        zip(&alignment.map_x, &alignment.map_y)
+            .map(|(x, y)| {
+                let (state, pi, pj) = match (x, y) {
+                    (Some(_), Some(_)) => {
+                        (i, j) = (i + 1, j + 1);
+                        (Matc, i - 1, j - 1)
+                    }
+                    (Some(_), None) => {
+                        i += 1;
+                        if x_info[i - 1].is_fixed() {
+                            return 0.0;
+                        }
+                        (GapInY, i - 1, j)
+                    }
+                    (None, Some(_)) => {
+                        j += 1;
+                        if y_info[j - 1].is_fixed() {
+                            return 0.0;
+                        }
+                        (GapInX, i, j - 1)
+                    }
+                    (None, None) => unreachable!(),
+                };
+                let previous = self.stored_direction(state, i, j);
+                self.state_score(state, i, j) - self.state_score(previous, pi, pj)
+            })
+            .collect()
+    }
+
+    /// Traceback that picks uniformly at random between all the optimal directions at every
+    /// step instead of following the choices stored during the fill, so repeated calls yield
+    /// different co-optimal alignments from a single fill. The gap costs of the fill follow the
+    /// stored directions, so a path that leaves them can cost more than the optimum and is
+    /// scored again with `alignment_score`.
+    pub(crate) fn sample_traceback(
+        &self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+        rng: &mut dyn FnMut(usize) -> usize,
+    ) -> (Vec<SiteInfo>, Alignment, f64) {
+        let (i, j) = (self.rows - 1, self.cols - 1);
+        let (pars_score, _, ties) = self.select_direction_w_ties(
+            self.score.m[i][j],
+            self.score.x[i][j],
+            self.score.y[i][j],
+        );
+        let options = self.direction_picker[ties as usize];
+        let action = options[rng(options.len())];
+        // without a tie along the way the path is the stored one and keeps the fill score
+        let mut tied = options.len() > 1;
+        // the choices are memoised so that looking at a cell twice gives a consistent path
+        let mut chosen = HashMap::<(u8, usize, usize), Direction>::new();
+        let (node_info, alignment, score) =
+            self.traceback_along(x_info, y_info, pars_score, action, &mut |state, i, j| {
+                *chosen.entry((state as u8, i, j)).or_insert_with(|| {
+                    let options = self.tied_directions(state, i, j);
+                    tied |= options.len() > 1;
+                    options[rng(options.len())]
+                })
+            });
+        if !tied {
+            return (node_info, alignment, score);
+        }
+        let score = self.alignment_score(x_info, x_scor, y_info, y_scor, &alignment);
+        (node_info, alignment, score)
+    }
+
+    /// Score of a given alignment of the two children, from a fill restricted to the cells of
+    /// the alignment with the settings of these matrices, as the MSAs are scored by
+    /// `pars_score_msa`.
+    pub(crate) fn alignment_score(
+        &self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+        alignment: &Alignment,
+    ) -> f64 {
+        // the fixed gap sites are skipped, the matrices handle them the same way for every path
+        let (mut i, mut j) = (0, 0);
+        let mut path = HashSet::with_capacity(alignment.map_x.len());
+        for (x, y) in zip(&alignment.map_x, &alignment.map_y) {
+            let state = match (x, y) {
+                (Some(_), Some(_)) => {
+                    (i, j) = (i + 1, j + 1);
+                    Matc
+                }
+                (Some(_), None) => {
+                    i += 1;
+                    if x_info[i - 1].is_fixed() {
+                        continue;
+                    }
+                    GapInY
+                }
+                (None, Some(_)) => {
+                    j += 1;
+                    if y_info[j - 1].is_fixed() {
+                        continue;
+                    }
+                    GapInX
+                }
+                (None, None) => unreachable!(),
+            };
+            path.insert((state as u8, i, j));
+        }
+        let mut path_mats = ParsimonyAlignmentMatrices::new(self.rows, self.cols, |_| 0)
+            .with_free_end_gaps(self.free_end_gaps)
+            .with_gap_function(self.gap_function);
+        path_mats.fill_matrices_constrained(
+            x_info,
+            x_scor,
+            y_info,
+            y_scor,
+            Some(&|state: Direction, i: usize, j: usize| path.contains(&(state as u8, i, j))),
+        );
+        path_mats.traceback(x_info, y_info).2
+    }
+
+    /// The `k` best scoring distinct alignments allowed by the filled matrices, in order of
+    /// increasing score. The paths are enumerated best first from the bottom right cell, the
+    /// filled scores give the exact cost of the best remaining prefix of every partial path.
+    /// The gap costs of a path are the ones used in the fill, so the scores follow the same
+    /// gap open/extension approximations as the optimal alignment.
+    pub(crate) fn k_best_traceback(
+        &self,
+        x_info: &[SiteInfo],
+        x_scor: &dyn BranchCosts,
+        y_info: &[SiteInfo],
+        y_scor: &dyn BranchCosts,
+        k: usize,
+    ) -> Vec<(Vec<SiteInfo>, Alignment, f64)> {
+        let mut steps = Vec::<PathStep>::new();
+        let mut queue = BinaryHeap::<QueuedPath>::new();
+        let (i, j) = self.skip_fixed(x_info, y_info, self.rows - 1, self.cols - 1);
+        let states = if i == 0 && j == 0 {
+            &[Matc][..]
+        } else {
+            &[Matc, GapInY, GapInX][..]
+        };
+        for &state in states {
+            self.push_path(&mut steps, &mut queue, state, i, j, 0.0, None);
+        }
+
+        let mut alignments = Vec::with_capacity(k);
+        while alignments.len() < k {
+            let Some(QueuedPath {
+                priority,
+                cost,
+                step,
+            }) = queue.pop()
+            else {
+                break;
+            };
+            let PathStep { state, i, j, .. } = steps[step];
+            if i == 0 && j == 0 {
+                alignments.push(self.path_traceback(x_info, y_info, &steps, step, priority));
+                continue;
+            }
+            let (pi, pj) = match state {
+                Matc => (i - 1, j - 1),
+                GapInY => (i - 1, j),
+                GapInX => (i, j - 1),
+            };
+            let candidates = if i == 0 || j == 0 {
+                // along the borders only the gap continues
+                let mut candidates = [INF; 3];
+                candidates[state_index(state)] = self.state_score(state, i, j);
+                candidates
+            } else {
+                match state {
+                    Matc => {
+                        self.s_m_candidates(pi, pj, x_info, x_scor, y_info, y_scor)
+                            .0
+                    }
+                    GapInY => self.s_x_candidates(pi, pj, x_info, x_scor, y_info, y_scor),
+                    GapInX => self.s_y_candidates(pi, pj, x_info, x_scor, y_info, y_scor),
+                }
+            };
+            let (ni, nj) = self.skip_fixed(x_info, y_info, pi, pj);
+            let previous =
+                [Matc, GapInY, GapInX]
+                    .into_iter()
+                    .zip(candidates)
+                    .filter(|(prev, candidate)| {
+                        candidate.is_finite() && self.state_score(*prev, pi, pj).is_finite()
+                    });
+            if ni == 0 && nj == 0 {
+                // the state at the top left cell does not change the alignment
+                if let Some((prev, candidate)) = previous.min_by(|a, b| a.1.total_cmp(&b.1)) {
+                    let prev_cost = cost + candidate - self.state_score(prev, pi, pj);
+                    self.push_path(&mut steps, &mut queue, prev, 0, 0, prev_cost, Some(step));
+                }
+            } else {
+                for (prev, candidate) in previous {
+                    let prev_cost = cost + candidate - self.state_score(prev, pi, pj);
+                    self.push_path(&mut steps, &mut queue, prev, ni, nj, prev_cost, Some(step));
+                }
+            }
+        }
+        alignments
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_path(
+        &self,
+        steps: &mut Vec<PathStep>,
+        queue: &mut BinaryHeap<QueuedPath>,
+        state: Direction,
+        i: usize,
+        j: usize,
+        cost: f64,
+        next: Option<usize>,
+    ) {
+        let priority = cost + self.state_score(state, i, j);
+        if !priority.is_finite() {
+            return;
+        }
+        steps.push(PathStep { state, i, j, next });
+        queue.push(QueuedPath {
+            priority,
+            cost,
+            step: steps.len() - 1,
+        });
+    }
+
+    fn path_traceback(
+        &self,
+        x_info: &[SiteInfo],
+        y_info: &[SiteInfo],
+        steps: &[PathStep],
+        first: usize,
+        score: f64,
+    ) -> (Vec<SiteInfo>, Alignment, f64) {
+        let mut chosen = HashMap::<(u8, usize, usize), Direction>::new();
+        let mut step = first;
+        while let Some(next) = steps[step].next {
+            let PathStep { state, i, j, .. } = steps[next];
+            chosen.insert((state as u8, i, j), steps[step].state);
+            step = next;
+        }
+        self.traceback_along(
+            x_info,
+            y_info,
+            score,
+            steps[step].state,
+            &mut |state, i, j| {
+                chosen
+                    .get(&(state as u8, i, j))
+                    .copied()
+                    .unwrap_or_else(|| self.stored_direction(state, i, j))
+            },
+        )
+    }
+
+    fn skip_fixed(
+        &self,
+        x_info: &[SiteInfo],
+        y_info: &[SiteInfo],
+        mut i: usize,
+        mut j: usize,
+    ) -> (usize, usize) {
+        while (i > 0 && x_info[i - 1].is_fixed()) || (j > 0 && y_info[j - 1].is_fixed()) {
+            i -= (i > 0 && x_info[i - 1].is_fixed()) as usize;
+            j -= (j > 0 && y_info[j - 1].is_fixed()) as usize;
+        }
+        (i, j)
+    }
+
+    pub(super) fn state_score(&self, state: Direction, i: usize, j: usize) -> f64 {
+        match state {
+            Matc => self.score.m[i][j],
+            GapInY => self.score.x[i][j],
+            GapInX => self.score.y[i][j],
+        }
+    }
+
+    pub(super) fn stored_direction(&self, state: Direction, i: usize, j: usize) -> Direction {
+        match state {
+            Matc => self.trace.m[i][j],
+            GapInY => self.trace.x[i][j],
+            GapInX => self.trace.y[i][j],
+        }
+    }
+
+    pub(super) fn tied_directions(
+        &self,
+        state: Direction,
+        i: usize,
+        j: usize,
+    ) -> &'static [Direction] {
+        let ties = match state {
+            Matc => self.ties.m[i][j],
+            GapInY => self.ties.x[i][j],
+            GapInX => self.ties.y[i][j],
+        };
+        self.direction_picker[ties as usize]
+    }
+
+    fn traceback_along(
+        &self,
+        x_info: &[SiteInfo],
+        y_info: &[SiteInfo],
+        pars_score: f64,
+        mut action: Direction,
+        next: &mut dyn FnMut(Direction, usize, usize) -> Direction,
     ) -> (Vec<SiteInfo>, Alignment, f64) {
         let mut i = self.rows - 1;
         let mut j = self.cols - 1;
-        let (pars_score, mut action) =
-            self.select_direction(self.score.m[i][j], self.score.x[i][j], self.score.y[i][j]);
         let max_alignment_length = x_info.len() + y_info.len();
         let mut node_info = Vec::<SiteInfo>::with_capacity(max_alignment_length);
         let mut alignment = Alignment::new(
@@ -465,48 +1567,62 @@ impl ParsimonyAlignmentMatrices {
                     i -= 1;
                     alignment.map_x.push(Some(i));
                     alignment.map_y.push(None);
-                    node_info.push(SiteInfo::new(gap_set(), GapFixed));
+                    node_info.push(SiteInfo::from_set(gap_set(), GapFixed));
                 }
                 if j > 0 && y_info[j - 1].is_fixed() {
                     j -= 1;
                     alignment.map_x.push(None);
                     alignment.map_y.push(Some(j));
-                    node_info.push(SiteInfo::new(gap_set(), GapFixed));
+                    node_info.push(SiteInfo::from_set(gap_set(), GapFixed));
                 }
             } else {
-                let (map_x, map_y, set, flag) = match action {
+                let (map_x, map_y, set, flag, gap_weight, category) = match action {
                     Matc => {
-                        action = self.trace.m[i][j];
+                        action = next(Matc, i, j);
+                        let category = self.match_categories.as_ref().map(|c| c[i][j]);
                         i -= 1;
                         j -= 1;
                         let mut set = &x_info[i].set & &y_info[j].set;
                         if set.is_empty() {
                             set = &x_info[i].set | &y_info[j].set;
                         }
-                        (Some(i), Some(j), set, NoGap)
+                        let gap_weight = x_info[i].gap_weight.max(y_info[j].gap_weight);
+                        (Some(i), Some(j), set, NoGap, gap_weight, category)
                     }
                     GapInY => {
-                        action = self.trace.x[i][j];
+                        action = next(GapInY, i, j);
                         i -= 1;
                         let (set, flag) = match x_info[i].flag {
                             GapOpen | GapExt => (gap_set(), GapFixed),
-                            NoGap => (x_info[i].set.clone(), self.gap_x_open_or_ext(i, j, x_info)),
+                            NoGap => (
+                                x_info[i].set.clone(),
+                                self.gap_x_open_or_ext(i, j, x_info, next),
+                            ),
                             GapFixed => unreachable!(),
                         };
-                        (Some(i), None, set, flag)
+                        let category = x_info[i].site_category.filter(|_| flag != GapFixed);
+                        (Some(i), None, set, flag, x_info[i].gap_weight, category)
                     }
                     GapInX => {
-                        action = self.trace.y[i][j];
+                        action = next(GapInX, i, j);
                         j -= 1;
                         let (set, flag) = match y_info[j].flag {
                             GapOpen | GapExt => (gap_set(), GapFixed),
-                            NoGap => (y_info[j].set.clone(), self.gap_y_open_or_ext(i, j, y_info)),
+                            NoGap => (
+                                y_info[j].set.clone(),
+                                self.gap_y_open_or_ext(i, j, y_info, next),
+                            ),
                             GapFixed => unreachable!(),
                         };
-                        (None, Some(j), set, flag)
+                        let category = y_info[j].site_category.filter(|_| flag != GapFixed);
+                        (None, Some(j), set, flag, y_info[j].gap_weight, category)
                     }
                 };
-                node_info.push(SiteInfo::new(set, flag));
+                node_info.push(
+                    SiteInfo::from_set(set, flag)
+                        .with_gap_weight(gap_weight)
+                        .with_site_category(category),
+                );
                 alignment.map_x.push(map_x);
                 alignment.map_y.push(map_y);
             }
@@ -517,10 +1633,16 @@ impl ParsimonyAlignmentMatrices {
         (node_info, alignment, pars_score)
     }
 
-    fn gap_x_open_or_ext(&self, i: usize, j: usize, x_info: &[SiteInfo]) -> SiteFlag {
-        if self.trace.x[i + 1][j] != GapInY
+    fn gap_x_open_or_ext(
+        &self,
+        i: usize,
+        j: usize,
+        x_info: &[SiteInfo],
+        next: &mut dyn FnMut(Direction, usize, usize) -> Direction,
+    ) -> SiteFlag {
+        if next(GapInY, i + 1, j) != GapInY
             || i == 0
-            || (x_info[i - 1].is_possible() && self.trace.x[i][j] != GapInX)
+            || (x_info[i - 1].is_possible() && next(GapInY, i, j) != GapInX)
         {
             GapOpen
         } else {
@@ -528,10 +1650,16 @@ impl ParsimonyAlignmentMatrices {
         }
     }
 
-    fn gap_y_open_or_ext(&self, i: usize, j: usize, y_info: &[SiteInfo]) -> SiteFlag {
-        if self.trace.y[i][j + 1] != GapInX
+    fn gap_y_open_or_ext(
+        &self,
+        i: usize,
+        j: usize,
+        y_info: &[SiteInfo],
+        next: &mut dyn FnMut(Direction, usize, usize) -> Direction,
+    ) -> SiteFlag {
+        if next(GapInX, i, j + 1) != GapInX
             || j == 0
-            || (y_info[j - 1].is_possible() && self.trace.y[i][j] != GapInY)
+            || (y_info[j - 1].is_possible() && next(GapInX, i, j) != GapInY)
         {
             GapOpen
         } else {
@@ -540,5 +1668,52 @@ impl ParsimonyAlignmentMatrices {
     }
 }
 
+fn state_index(state: Direction) -> usize {
+    match state {
+        Matc => 0,
+        GapInY => 1,
+        GapInX => 2,
+    }
+}
+
+/// Step of a partial path in the k-best traceback, `next` is the step that follows it in the
+/// alignment.
+struct PathStep {
+    state: Direction,
+    i: usize,
+    j: usize,
+    next: Option<usize>,
+}
+
+struct QueuedPath {
+    priority: f64,
+    cost: f64,
+    step: usize,
+}
+
+impl PartialEq for QueuedPath {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedPath {}
+
+impl PartialOrd for QueuedPath {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedPath {
+    // reversed so that the heap pops the lowest priority first, and the oldest path among ties
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.step.cmp(&self.step))
+    }
+}
+
 #[cfg(test)]
 mod parsimony_matrices_tests;
@@ -1,5 +1,7 @@
 use crate::assert_float_relative_slice_eq;
-use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
+use crate::parsimony_alignment::parsimony_costs::parsimony_costs_model::{
+    GammaRates, GapMultipliers,
+};
 use crate::parsimony_alignment::parsimony_costs::{
     parsimony_costs_model::DNAParsCosts, parsimony_costs_simple::ParsimonyCostsSimple,
     ParsimonyCosts,
@@ -12,9 +14,12 @@ use crate::parsimony_alignment::parsimony_matrices::{
     Direction::{GapInX, GapInY, Matc},
     ParsimonyAlignmentMatrices as PAM,
 };
+use crate::parsimony_alignment::{GapFunction, TieBreak};
 use approx::assert_relative_eq;
 use phylo::Rounding;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::f64::INFINITY as INF;
+use std::iter::zip;
 
 macro_rules! align {
     (@collect -) => { None };
@@ -159,6 +164,283 @@ fn fill_matrix_other_outcome() {
     );
 }
 
+#[test]
+fn deterministic_tie_breaking() {
+    let pars_mats = PAM::new(1, 1, |_| unreachable!()).with_tie_break(TieBreak::Match);
+    assert_eq!(pars_mats.select_direction(1.0, 1.0, 1.0), (1.0, Matc));
+    assert_eq!(pars_mats.select_direction(2.0, 1.0, 1.0), (1.0, GapInX));
+    assert_eq!(pars_mats.select_direction(1.0, 2.0, 3.0), (1.0, Matc));
+    let pars_mats = PAM::new(1, 1, |_| unreachable!()).with_tie_break(TieBreak::GapInX);
+    assert_eq!(pars_mats.select_direction(1.0, 1.0, 1.0), (1.0, GapInX));
+    assert_eq!(pars_mats.select_direction(1.0, 1.0, 2.0), (1.0, GapInY));
+    assert_eq!(pars_mats.select_direction(1.0, 2.0, 2.0), (1.0, Matc));
+    let pars_mats = PAM::new(1, 1, |_| unreachable!()).with_tie_break(TieBreak::GapInY);
+    assert_eq!(pars_mats.select_direction(1.0, 1.0, 1.0), (1.0, GapInY));
+    assert_eq!(pars_mats.select_direction(1.0, 2.0, 1.0), (1.0, GapInX));
+    assert_eq!(pars_mats.select_direction(3.0, 2.0, 2.0), (2.0, GapInY));
+}
+
+#[test]
+fn wavefront_fill_matches_row_fill() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let x_info = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], GapOpen),
+        PSI::new([b'C'], GapExt),
+        PSI::new([b'-'], GapFixed),
+        PSI::new([b'G', b'T'], NoGap),
+        PSI::new([b'A'], NoGap),
+    ];
+    let y_info = vec![
+        PSI::new([b'A', b'C'], NoGap),
+        PSI::new([b'-'], GapFixed),
+        PSI::new([b'T'], GapOpen),
+        PSI::new([b'G'], NoGap),
+    ];
+    for tie_break in [TieBreak::Match, TieBreak::GapInX, TieBreak::Random] {
+        let mut row_mats = PAM::new(7, 5, |_| 0).with_tie_break(tie_break);
+        row_mats.fill_matrices(
+            &x_info,
+            scoring.get_branch_costs(1.0),
+            &y_info,
+            scoring.get_branch_costs(1.0),
+        );
+        let mut wave_mats = PAM::new(7, 5, |_| 0)
+            .with_tie_break(tie_break)
+            .with_wavefront(true);
+        wave_mats.fill_matrices(
+            &x_info,
+            scoring.get_branch_costs(1.0),
+            &y_info,
+            scoring.get_branch_costs(1.0),
+        );
+        assert_eq!(wave_mats.score.m, row_mats.score.m);
+        assert_eq!(wave_mats.score.x, row_mats.score.x);
+        assert_eq!(wave_mats.score.y, row_mats.score.y);
+        assert_eq!(wave_mats.trace.m, row_mats.trace.m);
+        assert_eq!(wave_mats.trace.x, row_mats.trace.x);
+        assert_eq!(wave_mats.trace.y, row_mats.trace.y);
+    }
+}
+
+#[test]
+fn two_score_rows_traceback() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let x_info = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], GapOpen),
+        PSI::new([b'C'], GapExt),
+        PSI::new([b'-'], GapFixed),
+        PSI::new([b'G', b'T'], NoGap),
+    ];
+    let y_info = vec![
+        PSI::new([b'A', b'C'], NoGap),
+        PSI::new([b'T'], NoGap),
+        PSI::new([b'G'], NoGap),
+    ];
+    let mut full_mats = PAM::new(6, 4, |_| 0);
+    full_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    let mut two_row_mats = PAM::new(6, 4, |_| 0).with_two_score_rows(true);
+    two_row_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    assert!(two_row_mats
+        .score
+        .m
+        .iter()
+        .take(5)
+        .all(|row| row.is_empty()));
+    assert_eq!(two_row_mats.score.m[5], full_mats.score.m[5]);
+    let (full_info, full_alignment, full_score) = full_mats.traceback(&x_info, &y_info);
+    let (low_info, low_alignment, low_score) = two_row_mats.traceback(&x_info, &y_info);
+    assert_eq!(low_info, full_info);
+    assert_eq!(low_alignment.map_x, full_alignment.map_x);
+    assert_eq!(low_alignment.map_y, full_alignment.map_y);
+    assert_eq!(low_score, full_score);
+}
+
+#[test]
+fn two_score_rows_banded_log_gaps() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let x_info = info(b"ACGTTTTACGT");
+    let y_info = info(b"ACGTACGT");
+    let fill = |two_rows: bool| {
+        let mut mats = PAM::new(12, 9, |_| 0)
+            .with_band(Some(4))
+            .with_gap_function(GapFunction::Logarithmic(1.0))
+            .with_two_score_rows(two_rows);
+        mats.fill_matrices(
+            &x_info,
+            scoring.get_branch_costs(1.0),
+            &y_info,
+            scoring.get_branch_costs(1.0),
+        );
+        mats
+    };
+    let (full_mats, two_row_mats) = (fill(false), fill(true));
+    // only the last row of the scores and the gap lengths is left
+    let allocated = |rows: &[Vec<f64>]| rows.iter().filter(|row| !row.is_empty()).count();
+    assert_eq!(allocated(&two_row_mats.score.x), 1);
+    let lengths = two_row_mats.gap_lengths.as_ref().unwrap();
+    assert_eq!(lengths.x.iter().filter(|row| !row.is_empty()).count(), 1);
+    assert_eq!(two_row_mats.score.x[11], full_mats.score.x[11]);
+    assert_eq!(two_row_mats.score.y[11], full_mats.score.y[11]);
+    let (full_info, full_alignment, full_score) = full_mats.traceback(&x_info, &y_info);
+    let (low_info, low_alignment, low_score) = two_row_mats.traceback(&x_info, &y_info);
+    assert_eq!(low_info, full_info);
+    assert_eq!(low_alignment.map_x, full_alignment.map_x);
+    assert_eq!(low_score, full_score);
+}
+
+#[test]
+fn banded_fill() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let x_info = info(b"ACGTACGT");
+    let y_info = info(b"ACGTACGT");
+    let mut full_mats = PAM::new(9, 9, |_| 0);
+    full_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, full_alignment, full_score) = full_mats.traceback(&x_info, &y_info);
+    assert!(!full_mats.touches_band_edge(&full_alignment));
+
+    let mut banded_mats = PAM::new(9, 9, |_| 0).with_band(Some(1));
+    banded_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    assert_eq!(banded_mats.score.m[8][1..7], [INF; 6]);
+    assert_eq!(banded_mats.score.x[1][3..], [INF; 6]);
+    let (_, alignment, score) = banded_mats.traceback(&x_info, &y_info);
+    assert_eq!(score, full_score);
+    assert_eq!(alignment.map_x, full_alignment.map_x);
+    assert_eq!(alignment.map_y, full_alignment.map_y);
+    assert!(!banded_mats.touches_band_edge(&alignment));
+
+    let x_info = info(b"ACGTTACG");
+    let y_info = info(b"ACGTACG");
+    let mut narrow_mats = PAM::new(9, 8, |_| 0).with_band(Some(0));
+    narrow_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, alignment, score) = narrow_mats.traceback(&x_info, &y_info);
+    assert!(score.is_finite());
+    assert!(narrow_mats.touches_band_edge(&alignment));
+}
+
+#[test]
+fn x_drop_fill() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let x_info = info(b"ACGTACGT");
+    let y_info = info(b"ACGTACGT");
+    let mut full_mats = PAM::new(9, 9, |_| 0);
+    full_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, full_alignment, full_score) = full_mats.traceback(&x_info, &y_info);
+    assert_eq!(full_mats.cells_filled, 64);
+
+    let mut wide_mats = PAM::new(9, 9, |_| 0).with_x_drop(Some(100.0));
+    wide_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, _, score) = wide_mats.traceback(&x_info, &y_info);
+    assert_eq!(score, full_score);
+    assert_eq!(wide_mats.cells_filled, 64);
+
+    let mut pruned_mats = PAM::new(9, 9, |_| 0).with_x_drop(Some(0.0));
+    pruned_mats.fill_matrices(
+        &x_info,
+        scoring.get_branch_costs(1.0),
+        &y_info,
+        scoring.get_branch_costs(1.0),
+    );
+    assert_eq!(pruned_mats.score.m[8][1..7], [INF; 6]);
+    let (_, alignment, score) = pruned_mats.traceback(&x_info, &y_info);
+    assert_eq!(score, full_score);
+    assert_eq!(alignment.map_x, full_alignment.map_x);
+    assert_eq!(alignment.map_y, full_alignment.map_y);
+    assert!(pruned_mats.cells_filled < 64);
+}
+
+#[test]
+fn site_categories_fill() {
+    let scoring = |gamma: &GammaRates| {
+        DNAParsCosts::new(
+            "k80",
+            &[],
+            &GapMultipliers::new(2.0, 0.5),
+            &[0.5],
+            false,
+            &Rounding::none(),
+        )
+        .unwrap()
+        .with_site_rates(gamma)
+    };
+    let plain = scoring(&GammaRates::none());
+    let site_rates = scoring(&GammaRates::new(0.5, 4).unwrap());
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let x_info = info(b"ACGTTA");
+    let y_info = info(b"ACCTA");
+
+    let mut plain_mats = PAM::new(7, 6, |_| 0);
+    plain_mats.fill_matrices(
+        &x_info,
+        plain.get_branch_costs(0.5),
+        &y_info,
+        plain.get_branch_costs(0.5),
+    );
+    let (plain_info, _, plain_score) = plain_mats.traceback(&x_info, &y_info);
+    assert!(plain_mats.match_categories.is_none());
+    assert!(plain_info.iter().all(|site| site.site_category().is_none()));
+
+    for wavefront in [false, true] {
+        let mut mats = PAM::new(7, 6, |_| 0).with_wavefront(wavefront);
+        mats.fill_matrices(
+            &x_info,
+            site_rates.get_branch_costs(0.5),
+            &y_info,
+            site_rates.get_branch_costs(0.5),
+        );
+        let (node_info, alignment, score) = mats.traceback(&x_info, &y_info);
+        // every column takes its cheapest category, which is cheaper than the average rate
+        assert!(score.is_finite());
+        assert!(score < plain_score);
+        for (site, (x, y)) in zip(&node_info, zip(&alignment.map_x, &alignment.map_y)) {
+            let category = site.site_category();
+            assert_eq!(x.is_some() && y.is_some(), category.is_some());
+            if let Some(category) = category {
+                assert!(category < 4);
+            }
+        }
+    }
+}
+
 #[test]
 fn traceback_correct() {
     let mismatch_cost = 1.0;
@@ -199,6 +481,177 @@ fn traceback_correct() {
     assert_eq!(score, 1.0);
 }
 
+#[test]
+fn site_costs_sum_to_score() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let node_info_1 = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], NoGap),
+        PSI::new([b'G'], NoGap),
+        PSI::new([b'T'], NoGap),
+    ];
+    let node_info_2 = vec![PSI::new([b'A'], NoGap), PSI::new([b'T'], NoGap)];
+    let mut pars_mats = PAM::new(5, 3, |_| 0);
+    pars_mats.fill_matrices(
+        &node_info_1,
+        scoring.get_branch_costs(1.0),
+        &node_info_2,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, alignment, score) = pars_mats.traceback(&node_info_1, &node_info_2);
+    let costs = pars_mats.site_costs(&node_info_1, &node_info_2, &alignment);
+    assert_eq!(alignment.map_x, align!(0 1 2 3));
+    assert_eq!(alignment.map_y, align!(0 - -1));
+    assert_eq!(costs, vec![0.0, 2.0, 0.5, 0.0]);
+    assert_eq!(costs.iter().sum::<f64>(), score);
+}
+
+#[test]
+fn sampled_traceback_co_optimal() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let node_info_1 = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], NoGap),
+        PSI::new([b'T'], NoGap),
+    ];
+    let node_info_2 = vec![PSI::new([b'A'], NoGap), PSI::new([b'C'], NoGap)];
+    let mut pars_mats = PAM::new(5, 3, |_| 0);
+    pars_mats.fill_matrices(
+        &node_info_1,
+        scoring.get_branch_costs(1.0),
+        &node_info_2,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, _, score) = pars_mats.traceback(&node_info_1, &node_info_2);
+    let mut generator = StdRng::seed_from_u64(1);
+    let mut rng = |l: usize| generator.gen_range(0..l);
+    let mut alignments = Vec::new();
+    for _ in 0..200 {
+        let (node_info, alignment, sampled_score) = pars_mats.sample_traceback(
+            &node_info_1,
+            scoring.get_branch_costs(1.0),
+            &node_info_2,
+            scoring.get_branch_costs(1.0),
+            &mut rng,
+        );
+        assert_eq!(sampled_score, score);
+        assert_eq!(node_info.len(), alignment.map_x.len());
+        assert_eq!(alignment.map_x.iter().flatten().count(), 4);
+        assert_eq!(alignment.map_y.iter().flatten().count(), 2);
+        alignments.push(alignment.map_y);
+    }
+    // both co-optimal alignments are drawn with a fair share of the samples
+    for optimal in [align!(0 - -1), align!(0 1 - -)] {
+        let count = alignments.iter().filter(|&map_y| map_y == &optimal).count();
+        assert!(
+            (50..=150).contains(&count),
+            "{:?} sampled {} times",
+            optimal,
+            count
+        );
+    }
+}
+
+#[test]
+fn sampled_traceback_scored_along_path() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let node_info_1 = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], NoGap),
+        PSI::new([b'C'], GapExt),
+        PSI::new([b'G'], NoGap),
+        PSI::new([b'T'], NoGap),
+    ];
+    let node_info_2 = vec![PSI::new([b'A'], NoGap), PSI::new([b'T'], NoGap)];
+    let mut pars_mats = PAM::new(6, 3, |_| 0);
+    pars_mats.fill_matrices(
+        &node_info_1,
+        scoring.get_branch_costs(1.0),
+        &node_info_2,
+        scoring.get_branch_costs(1.0),
+    );
+    let mut generator = StdRng::seed_from_u64(3);
+    let mut rng = |l: usize| generator.gen_range(0..l);
+    for _ in 0..50 {
+        let (_, alignment, sampled_score) = pars_mats.sample_traceback(
+            &node_info_1,
+            scoring.get_branch_costs(1.0),
+            &node_info_2,
+            scoring.get_branch_costs(1.0),
+            &mut rng,
+        );
+        // the score is the one of the sampled path, not the optimum of the fill
+        let mut path_mats = PAM::new(6, 3, |_| 0);
+        let path_score = path_mats.alignment_score(
+            &node_info_1,
+            scoring.get_branch_costs(1.0),
+            &node_info_2,
+            scoring.get_branch_costs(1.0),
+            &alignment,
+        );
+        assert_eq!(sampled_score, path_score);
+        path_mats.fill_matrices(
+            &node_info_1,
+            scoring.get_branch_costs(1.0),
+            &node_info_2,
+            scoring.get_branch_costs(1.0),
+        );
+        assert!(sampled_score >= path_mats.traceback(&node_info_1, &node_info_2).2);
+    }
+}
+
+#[test]
+fn k_best_traceback() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+    let node_info_1 = vec![
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'A'], NoGap),
+        PSI::new([b'C'], NoGap),
+        PSI::new([b'T'], NoGap),
+    ];
+    let node_info_2 = vec![PSI::new([b'A'], NoGap), PSI::new([b'C'], NoGap)];
+    let mut pars_mats = PAM::new(5, 3, |_| 0);
+    pars_mats.fill_matrices(
+        &node_info_1,
+        scoring.get_branch_costs(1.0),
+        &node_info_2,
+        scoring.get_branch_costs(1.0),
+    );
+    let (_, _, score) = pars_mats.traceback(&node_info_1, &node_info_2);
+    let k_best = pars_mats.k_best_traceback(
+        &node_info_1,
+        scoring.get_branch_costs(1.0),
+        &node_info_2,
+        scoring.get_branch_costs(1.0),
+        6,
+    );
+    assert_eq!(k_best.len(), 6);
+    assert_eq!(k_best[0].2, score);
+    assert_eq!(k_best[1].2, score);
+    let mut optimal = vec![k_best[0].1.map_y.clone(), k_best[1].1.map_y.clone()];
+    optimal.sort();
+    assert_eq!(optimal, vec![align!(0 - -1), align!(0 1 - -)]);
+    for (rank, (node_info, alignment, alignment_score)) in k_best.iter().enumerate() {
+        assert_eq!(node_info.len(), alignment.map_x.len());
+        assert_eq!(alignment.map_x.iter().flatten().count(), 4);
+        assert_eq!(alignment.map_y.iter().flatten().count(), 2);
+        for (_, other, other_score) in &k_best[..rank] {
+            assert!(other_score <= alignment_score);
+            assert!(other.map_x != alignment.map_x || other.map_y != alignment.map_y);
+        }
+    }
+    assert!(pars_mats
+        .k_best_traceback(
+            &node_info_1,
+            scoring.get_branch_costs(1.0),
+            &node_info_2,
+            scoring.get_branch_costs(1.0),
+            0,
+        )
+        .is_empty());
+}
+
 #[cfg(test)]
 fn setup_gap_adjustment_1() -> (Vec<PSI>, Vec<PSI>, PAM) {
     // Sequence file: sequences_fill_matrix_gap_adjustment_1.fasta
@@ -1022,3 +1475,78 @@ fn traceback_diff_branch_models_3() {
     assert_eq!(alignment.map_y, align!(- - - - 0 1));
     assert_relative_eq!(score + 7.686975 + 8.619275, 20.16745, epsilon = 0.0001);
 }
+
+#[test]
+fn free_end_gaps() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let fill = |x_info: &[PSI], y_info: &[PSI], free_end_gaps: bool| {
+        let mut pars_mats =
+            PAM::new(x_info.len() + 1, y_info.len() + 1, |_| 0).with_free_end_gaps(free_end_gaps);
+        pars_mats.fill_matrices(
+            x_info,
+            scoring.get_branch_costs(1.0),
+            y_info,
+            scoring.get_branch_costs(1.0),
+        );
+        pars_mats.traceback(x_info, y_info)
+    };
+    let fragment = info(b"ACGT");
+    for (seq, global_score, map) in [
+        (&b"GGACGT"[..], 3.0, align!(- - 0 1 2 3)),
+        (&b"ACGTGG"[..], 3.0, align!(0 1 2 3 - -)),
+        (&b"GACGTG"[..], 5.0, align!(- 0 1 2 3 -)),
+    ] {
+        let longer = info(seq);
+        assert_eq!(fill(&longer, &fragment, false).2, global_score);
+        let (_, alignment, score) = fill(&longer, &fragment, true);
+        assert_eq!(score, 0.0);
+        assert_eq!(alignment.map_y, map);
+        let (_, alignment, score) = fill(&fragment, &longer, true);
+        assert_eq!(score, 0.0);
+        assert_eq!(alignment.map_x, map);
+    }
+
+    // the gaps inside the fragment and at the ends of the longer sequence keep their costs
+    let (_, _, score) = fill(&info(b"ACGGT"), &fragment, true);
+    assert_eq!(score, 1.0);
+    let (_, _, score) = fill(&info(b"TTACGTAA"), &info(b"CCACGTCC"), true);
+    assert_eq!(score, 4.0);
+}
+
+#[test]
+fn logarithmic_gaps() {
+    let scoring = ParsimonyCostsSimple::new(1.0, 2.5, 0.5);
+    let info = |seq: &[u8]| -> Vec<PSI> { seq.iter().map(|&c| PSI::new([c], NoGap)).collect() };
+    let fill = |x_info: &[PSI], y_info: &[PSI], gap_function: GapFunction, wavefront: bool| {
+        let mut pars_mats = PAM::new(x_info.len() + 1, y_info.len() + 1, |_| 0)
+            .with_wavefront(wavefront)
+            .with_gap_function(gap_function);
+        pars_mats.fill_matrices(
+            x_info,
+            scoring.get_branch_costs(1.0),
+            y_info,
+            scoring.get_branch_costs(1.0),
+        );
+        pars_mats.traceback(x_info, y_info)
+    };
+    let short = info(b"ACGT");
+    for (seq, map) in [
+        (&b"TTTTACGT"[..], align!(- - - - 0 1 2 3)),
+        (&b"ACTTTTGT"[..], align!(0 1 - - - - 2 3)),
+        (&b"ACGTCCCC"[..], align!(0 1 2 3 - - - -)),
+    ] {
+        let long = info(seq);
+        assert_eq!(fill(&long, &short, GapFunction::Affine, false).2, 4.0);
+        for wavefront in [false, true] {
+            let (_, alignment, score) =
+                fill(&long, &short, GapFunction::Logarithmic(1.0), wavefront);
+            assert_relative_eq!(score, 2.5 + 0.5 * 4.0_f64.ln());
+            assert_eq!(alignment.map_y, map);
+            let (_, alignment, score) =
+                fill(&short, &long, GapFunction::Logarithmic(1.0), wavefront);
+            assert_relative_eq!(score, 2.5 + 0.5 * 4.0_f64.ln());
+            assert_eq!(alignment.map_x, map);
+        }
+    }
+}
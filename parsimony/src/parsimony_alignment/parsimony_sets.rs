@@ -1,12 +1,269 @@
 use bio::io::fasta;
 use itertools::join;
 use phylo::sequences::{charify, SequenceType, AMINOACIDS_STR, NUCLEOTIDES_STR};
-use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{BitAnd, BitOr};
 
-pub(crate) type ParsimonySet = HashSet<u8>;
+/// Set of the characters a site can have. Sets of nucleotides and the gap, which are all the
+/// sets of DNA data, take a single byte with one bit per character, the sets of protein data a
+/// u32 with one bit per amino acid and any other set has one bit for every byte value.
+/// Intersections and unions are bitwise operations without allocations, which keeps the
+/// profiles of the internal nodes small and the DP loop free of hashing, and the masks index the
+/// precomputed minimum costs of the substitution models.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Vec<u8>", from = "Vec<u8>")
+)]
+pub struct ParsimonySet(Bits);
+
+/// Representation of a set. A set of nucleotides can also be stored as amino acids or bytes,
+/// sets are compared by their characters.
+#[derive(Clone)]
+pub(crate) enum Bits {
+    /// One bit per character of `NUCLEOTIDE_BITS`.
+    Nucleotides(u8),
+    /// One bit per character of `AMINOACID_BITS`.
+    Aminoacids(u32),
+    /// Bit `c % 64` of word `c / 64` for every character `c`.
+    Bytes(Box<[u64; 4]>),
+}
+
+/// Characters of the bits of `Bits::Nucleotides`, from the lowest bit.
+pub(crate) const NUCLEOTIDE_BITS: [u8; 5] = *b"ACGT-";
+
+/// Characters of the bits of `Bits::Aminoacids`, from the lowest bit.
+pub(crate) const AMINOACID_BITS: [u8; 21] = *b"ARNDCQEGHILKMFPSTWYV-";
+
+fn word_bit(char: u8) -> (usize, u64) {
+    (char as usize / 64, 1 << (char % 64))
+}
+
+/// Mask of the characters of `words` with one bit per character of `chars`, `None` if `words`
+/// has other characters.
+fn pack(words: [u64; 4], chars: &[u8]) -> Option<u32> {
+    let mut mask = 0;
+    let mut rest = words;
+    for (bit, &char) in chars.iter().enumerate() {
+        let (word, char_bit) = word_bit(char);
+        if rest[word] & char_bit != 0 {
+            mask |= 1 << bit;
+            rest[word] &= !char_bit;
+        }
+    }
+    (rest == [0; 4]).then_some(mask)
+}
+
+fn unpack(mask: u32, chars: &[u8]) -> [u64; 4] {
+    let mut words = [0; 4];
+    for (bit, &char) in chars.iter().enumerate() {
+        if mask & (1 << bit) != 0 {
+            let (word, char_bit) = word_bit(char);
+            words[word] |= char_bit;
+        }
+    }
+    words
+}
+
+impl ParsimonySet {
+    fn from_words(words: [u64; 4]) -> Self {
+        if let Some(mask) = pack(words, &NUCLEOTIDE_BITS) {
+            ParsimonySet(Bits::Nucleotides(mask as u8))
+        } else if let Some(mask) = pack(words, &AMINOACID_BITS) {
+            ParsimonySet(Bits::Aminoacids(mask))
+        } else {
+            ParsimonySet(Bits::Bytes(Box::new(words)))
+        }
+    }
+
+    /// Set of amino acids, stored as an amino acid mask even if all the characters are also
+    /// nucleotides so that the sets of protein data combine without conversions.
+    pub(crate) fn aminoacids(chars: impl IntoIterator<Item = u8>) -> Self {
+        let set = Self::from_iter(chars);
+        match pack(set.words(), &AMINOACID_BITS) {
+            Some(mask) => ParsimonySet(Bits::Aminoacids(mask)),
+            None => set,
+        }
+    }
+
+    pub(crate) fn bits(&self) -> &Bits {
+        &self.0
+    }
+
+    fn words(&self) -> [u64; 4] {
+        match &self.0 {
+            Bits::Nucleotides(mask) => unpack(*mask as u32, &NUCLEOTIDE_BITS),
+            Bits::Aminoacids(mask) => unpack(*mask, &AMINOACID_BITS),
+            Bits::Bytes(words) => **words,
+        }
+    }
+
+    fn combine(
+        &self,
+        other: &ParsimonySet,
+        mask_op: fn(u32, u32) -> u32,
+        word_op: fn(u64, u64) -> u64,
+    ) -> ParsimonySet {
+        match (&self.0, &other.0) {
+            (Bits::Nucleotides(x), Bits::Nucleotides(y)) => {
+                ParsimonySet(Bits::Nucleotides(mask_op(*x as u32, *y as u32) as u8))
+            }
+            (Bits::Aminoacids(x), Bits::Aminoacids(y)) => {
+                ParsimonySet(Bits::Aminoacids(mask_op(*x, *y)))
+            }
+            (x_bits, y_bits) => {
+                let (x, y) = (self.words(), other.words());
+                let words = std::array::from_fn(|word| word_op(x[word], y[word]));
+                // amino acids stay amino acids, e.g. in a union with the gap set
+                let aminoacids =
+                    matches!(x_bits, Bits::Aminoacids(_)) || matches!(y_bits, Bits::Aminoacids(_));
+                match pack(words, &AMINOACID_BITS).filter(|_| aminoacids) {
+                    Some(mask) => ParsimonySet(Bits::Aminoacids(mask)),
+                    None => Self::from_words(words),
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Bits::Nucleotides(mask) => mask.count_ones() as usize,
+            Bits::Aminoacids(mask) => mask.count_ones() as usize,
+            Bits::Bytes(words) => words.iter().map(|word| word.count_ones() as usize).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, char: u8) -> bool {
+        let (word, char_bit) = word_bit(char);
+        self.words()[word] & char_bit != 0
+    }
+
+    pub fn is_disjoint(&self, other: &ParsimonySet) -> bool {
+        (self & other).is_empty()
+    }
+
+    /// Characters of the set in increasing order.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            words: self.words(),
+            word: 0,
+        }
+    }
+}
+
+impl PartialEq for ParsimonySet {
+    fn eq(&self, other: &ParsimonySet) -> bool {
+        match (&self.0, &other.0) {
+            (Bits::Nucleotides(x), Bits::Nucleotides(y)) => x == y,
+            (Bits::Aminoacids(x), Bits::Aminoacids(y)) => x == y,
+            _ => self.words() == other.words(),
+        }
+    }
+}
+
+impl Eq for ParsimonySet {}
+
+impl Hash for ParsimonySet {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.words().hash(state);
+    }
+}
+
+/// Iterator over the characters of a `ParsimonySet` in increasing order.
+pub struct Iter {
+    words: [u64; 4],
+    word: usize,
+}
+
+impl Iterator for Iter {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        while self.word < self.words.len() {
+            let bits = self.words[self.word];
+            if bits != 0 {
+                self.words[self.word] &= bits - 1;
+                return Some((self.word * 64 + bits.trailing_zeros() as usize) as u8);
+            }
+            self.word += 1;
+        }
+        None
+    }
+}
+
+impl FromIterator<u8> for ParsimonySet {
+    fn from_iter<I: IntoIterator<Item = u8>>(chars: I) -> Self {
+        let mut words = [0; 4];
+        for char in chars {
+            let (word, char_bit) = word_bit(char);
+            words[word] |= char_bit;
+        }
+        Self::from_words(words)
+    }
+}
+
+impl IntoIterator for ParsimonySet {
+    type Item = u8;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &ParsimonySet {
+    type Item = u8;
+    type IntoIter = Iter;
+
+    fn into_iter(self) -> Iter {
+        self.iter()
+    }
+}
+
+impl BitAnd for &ParsimonySet {
+    type Output = ParsimonySet;
+
+    fn bitand(self, other: &ParsimonySet) -> ParsimonySet {
+        self.combine(other, |x, y| x & y, |x, y| x & y)
+    }
+}
+
+impl BitOr for &ParsimonySet {
+    type Output = ParsimonySet;
+
+    fn bitor(self, other: &ParsimonySet) -> ParsimonySet {
+        self.combine(other, |x, y| x | y, |x, y| x | y)
+    }
+}
+
+impl From<Vec<u8>> for ParsimonySet {
+    fn from(chars: Vec<u8>) -> Self {
+        chars.into_iter().collect()
+    }
+}
+
+impl From<ParsimonySet> for Vec<u8> {
+    fn from(set: ParsimonySet) -> Self {
+        set.iter().collect()
+    }
+}
+
+impl fmt::Debug for ParsimonySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.iter().map(|char| char as char))
+            .finish()
+    }
+}
 
 pub(crate) fn print_parsimony_set(set: &ParsimonySet) -> String {
-    let mut chars: Vec<char> = set.iter().map(|&a| a as char).collect();
+    let mut chars: Vec<char> = set.iter().map(|a| a as char).collect();
     chars.sort_by_key(|c| AMINOACIDS_STR.find(*c));
     join(chars, " ")
 }
@@ -15,6 +272,11 @@ pub(crate) fn make_parsimony_set(chars: impl IntoIterator<Item = u8>) -> Parsimo
     ParsimonySet::from_iter(chars)
 }
 
+/// Set of nucleotides a DNA or RNA character stands for, following the IUPAC codes:
+/// R = AG, Y = CT, S = CG, W = AT, K = GT, M = AC, B = CGT, D = AGT, H = ACT, V = ACG and
+/// N = X = ACGT. U is read as T and any other character is treated like N.
+/// An ambiguous site is scored as the whole set, its match cost to a residue is the minimum
+/// over the members of the set, see `BranchParsimonyCosts::min_match_costs`.
 pub(crate) fn get_dna_set(char: &u8) -> ParsimonySet {
     let nucleotides = charify(NUCLEOTIDES_STR);
     if nucleotides.contains(char) {
@@ -22,6 +284,8 @@ pub(crate) fn get_dna_set(char: &u8) -> ParsimonySet {
     } else {
         match *char {
             b'-' => gap_set(),
+            // uracil in RNA sequences
+            b'U' => ParsimonySet::from_iter([b'T']),
             b'V' => ParsimonySet::from_iter(nucleotides.into_iter().filter(|c| *c != b'T')),
             b'D' => ParsimonySet::from_iter(nucleotides.into_iter().filter(|c| *c != b'C')),
             b'B' => ParsimonySet::from_iter(nucleotides.into_iter().filter(|c| *c != b'A')),
@@ -44,22 +308,27 @@ pub(crate) fn get_dna_set(char: &u8) -> ParsimonySet {
             b'K' => ParsimonySet::from_iter(
                 nucleotides.into_iter().filter(|c| *c == b'G' || *c == b'T'),
             ),
+            // N, X and unknown characters
             _ => ParsimonySet::from_iter(nucleotides),
         }
     }
 }
 
+/// Set of amino acids a protein character stands for: B = DN, Z = EQ, J = IL and
+/// X = any amino acid. Any other character, e.g. U for selenocysteine or O for pyrrolysine, is
+/// treated like X. Ambiguous sites are scored like in `get_dna_set`.
 pub(crate) fn get_protein_set(char: &u8) -> ParsimonySet {
     let aminoacids = charify(AMINOACIDS_STR);
     if aminoacids.contains(char) {
-        ParsimonySet::from_iter(aminoacids.into_iter().filter(|c| c == char))
+        ParsimonySet::aminoacids(aminoacids.into_iter().filter(|c| c == char))
     } else {
         match *char {
-            b'-' => gap_set(),
-            b'B' => ParsimonySet::from_iter([b'D', b'N']),
-            b'Z' => ParsimonySet::from_iter([b'E', b'Q']),
-            b'J' => ParsimonySet::from_iter([b'I', b'L']),
-            _ => ParsimonySet::from_iter(aminoacids),
+            b'-' => ParsimonySet::aminoacids(gap_set()),
+            b'B' => ParsimonySet::aminoacids([b'D', b'N']),
+            b'Z' => ParsimonySet::aminoacids([b'E', b'Q']),
+            b'J' => ParsimonySet::aminoacids([b'I', b'L']),
+            // X and unknown characters
+            _ => ParsimonySet::aminoacids(aminoacids),
         }
     }
 }
@@ -86,7 +355,9 @@ pub(crate) fn gap_set() -> ParsimonySet {
 
 #[cfg(test)]
 mod parsimony_sets_tests {
-    use super::{make_parsimony_set, print_parsimony_set};
+    use super::{make_parsimony_set, print_parsimony_set, Bits, ParsimonySet};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::parsimony_costs::ParsimonyCosts;
     use crate::parsimony_alignment::parsimony_sets::{
         gap_set, get_dna_set, get_parsimony_sets, get_protein_set,
     };
@@ -109,6 +380,37 @@ mod parsimony_sets_tests {
         assert_eq!(&(&sets[0] | &sets[2]) | &(&sets[4] | &sets[6]), sets[8]);
     }
 
+    #[test]
+    fn packed_sets() {
+        let dna = make_parsimony_set(*b"AG");
+        let protein = make_parsimony_set(*b"AW");
+        assert_eq!(dna.len(), 2);
+        assert!(dna.contains(b'G') && !dna.contains(b'W'));
+        assert!(protein.contains(b'W') && !protein.contains(b'G'));
+        assert_eq!(&dna & &protein, make_parsimony_set(*b"A"));
+        assert_eq!(&dna | &protein, make_parsimony_set(*b"AGW"));
+        assert!((&dna & &make_parsimony_set(*b"CT")).is_empty());
+        assert_eq!(
+            (&dna | &gap_set()).iter().collect::<Vec<_>>(),
+            b"-AG".to_vec()
+        );
+        let aminoacids = ParsimonySet::aminoacids(*b"AC");
+        assert_eq!(aminoacids, make_parsimony_set(*b"CA"));
+        assert_eq!(&aminoacids | &dna, make_parsimony_set(*b"ACG"));
+        assert_eq!(&protein & &aminoacids, make_parsimony_set(*b"A"));
+        assert!(matches!(
+            (&protein & &aminoacids).bits(),
+            Bits::Aminoacids(_)
+        ));
+        assert!(matches!(get_protein_set(&b'-').bits(), Bits::Aminoacids(_)));
+        assert_eq!(
+            make_parsimony_set([255, 0, 64, 63])
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![0, 63, 64, 255]
+        );
+    }
+
     #[test]
     fn protein_sets() {
         let record = Record::with_attrs("", None, b"rRlLeEqQxO-");
@@ -135,6 +437,7 @@ mod parsimony_sets_tests {
         assert!((&get_dna_set(&b'-') & &get_dna_set(&b'X')).is_empty());
         assert_eq!(get_dna_set(&b'E'), get_dna_set(&b'E'));
         assert_eq!(get_dna_set(&b'T'), get_dna_set(&b'T'));
+        assert_eq!(get_dna_set(&b'U'), get_dna_set(&b'T'));
         assert!((&get_dna_set(&b'V') & &get_dna_set(&b'T')).is_empty());
         assert!((&get_dna_set(&b'D') & &get_dna_set(&b'C')).is_empty());
         assert!((&get_dna_set(&b'B') & &get_dna_set(&b'A')).is_empty());
@@ -172,6 +475,82 @@ mod parsimony_sets_tests {
         );
     }
 
+    #[rstest]
+    #[case(b'A', "A")]
+    #[case(b'C', "C")]
+    #[case(b'G', "G")]
+    #[case(b'T', "T")]
+    #[case(b'U', "T")]
+    #[case(b'R', "AG")]
+    #[case(b'Y', "CT")]
+    #[case(b'S', "CG")]
+    #[case(b'W', "AT")]
+    #[case(b'K', "GT")]
+    #[case(b'M', "AC")]
+    #[case(b'B', "CGT")]
+    #[case(b'D', "AGT")]
+    #[case(b'H', "ACT")]
+    #[case(b'V', "ACG")]
+    #[case(b'N', "ACGT")]
+    #[case(b'X', "ACGT")]
+    #[case(b'?', "ACGT")]
+    #[case(b'-', "-")]
+    fn iupac_dna_codes(#[case] code: u8, #[case] residues: &str) {
+        assert_eq!(get_dna_set(&code), make_parsimony_set(residues.bytes()));
+        let record = Record::with_attrs("", None, &[code.to_ascii_lowercase()]);
+        assert_eq!(
+            get_parsimony_sets(&record, &SequenceType::DNA)[0],
+            get_dna_set(&code)
+        );
+    }
+
+    #[rstest]
+    #[case(b'B', "DN")]
+    #[case(b'Z', "EQ")]
+    #[case(b'J', "IL")]
+    #[case(b'X', AMINOACIDS_STR)]
+    #[case(b'U', AMINOACIDS_STR)]
+    #[case(b'O', AMINOACIDS_STR)]
+    #[case(b'-', "-")]
+    fn iupac_protein_codes(#[case] code: u8, #[case] residues: &str) {
+        assert_eq!(get_protein_set(&code), make_parsimony_set(residues.bytes()));
+    }
+
+    #[test]
+    fn standard_amino_acids() {
+        for residue in AMINOACIDS_STR.bytes() {
+            assert_eq!(get_protein_set(&residue), make_parsimony_set([residue]));
+        }
+    }
+
+    #[test]
+    fn ambiguous_match_costs() {
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let costs = scoring.get_branch_costs(1.0);
+        let ancestors = make_parsimony_set(*b"A");
+        assert_eq!(
+            costs.min_match_costs(&ancestors, &get_dna_set(&b'R')),
+            vec![0.0]
+        );
+        assert_eq!(
+            costs.min_match_costs(&ancestors, &get_dna_set(&b'Y')),
+            vec![1.0]
+        );
+        assert_eq!(
+            costs.min_match_costs(&ancestors, &get_dna_set(&b'N')),
+            vec![0.0]
+        );
+        let ancestors = make_parsimony_set(*b"Q");
+        assert_eq!(
+            costs.min_match_costs(&ancestors, &get_protein_set(&b'Z')),
+            vec![0.0]
+        );
+        assert_eq!(
+            costs.min_match_costs(&ancestors, &get_protein_set(&b'B')),
+            vec![1.0]
+        );
+    }
+
     #[rstest]
     #[case(vec![b'A', b'C', b'G', b'T'], "ACGT")]
     #[case(vec![b'T', b'G', b'A', b'C'], "ACGT")]
@@ -0,0 +1,475 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::iter::zip;
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use bio::io::fasta::Record;
+use log::info;
+use phylo::alignment::{Alignment, Mapping};
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::sequences::SequenceType;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf};
+
+use super::{AlignmentResult, DataType};
+use crate::{ParsimonyError, Result};
+
+/// Region of the sequences that is scored with its own substitution model, e.g. the first and
+/// second or the third codon positions of a gene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub name: String,
+    /// Data type of the residues of the partition, inferred from them unless it is declared.
+    pub data_type: DataType,
+    pub model: String,
+    pub params: Vec<f64>,
+    /// 0-based, end-exclusive ranges of residues, each with the step between its residues.
+    pub ranges: Vec<(Range<usize>, usize)>,
+}
+
+impl Partition {
+    /// Residues of the partition of every sequence, in the order of their positions.
+    pub fn sequences(&self, sequences: &[Record]) -> Vec<Record> {
+        let mut positions: Vec<usize> = sites(self).collect();
+        positions.sort_unstable();
+        sequences
+            .iter()
+            .map(|record| {
+                let seq: Vec<u8> = positions
+                    .iter()
+                    .filter_map(|&position| record.seq().get(position).copied())
+                    .collect();
+                Record::with_attrs(record.id(), record.desc(), &seq)
+            })
+            .collect()
+    }
+
+    /// Type of the residues of the partition of `sequences`.
+    pub fn sequence_type(&self, sequences: &[Record]) -> SequenceType {
+        self.data_type.sequence_type(&self.sequences(sequences))
+    }
+}
+
+/// Partitions of the sequences, every residue of a sequence is scored with the model of the
+/// partition that covers its position. Residues outside of the partitions are scored with the
+/// model of the run. Partitions of different data types, e.g. the genes and the proteins of a
+/// supermatrix, are aligned separately, see `concatenate_alignments`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Partitions {
+    partitions: Vec<Partition>,
+}
+
+impl Partitions {
+    pub fn new(partitions: Vec<Partition>) -> Result<Self> {
+        if partitions.len() >= u8::MAX as usize {
+            return Err(ParsimonyError::InvalidInput(format!(
+                "At most {} partitions are supported, got {}.",
+                u8::MAX - 1,
+                partitions.len()
+            )));
+        }
+        for (idx, partition) in partitions.iter().enumerate() {
+            if partitions[..idx]
+                .iter()
+                .any(|other| other.name == partition.name)
+            {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "There are several partitions named {}.",
+                    partition.name
+                )));
+            }
+            if let Some((range, step)) = partition
+                .ranges
+                .iter()
+                .find(|(range, step)| range.is_empty() || *step == 0)
+            {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The range {}-{}\\{} of the partition {} is empty.",
+                    range.start + 1,
+                    range.end,
+                    step,
+                    partition.name
+                )));
+            }
+        }
+        let partitions = Partitions { partitions };
+        let mut owners: Vec<Option<usize>> = vec![None; partitions.len_sites()];
+        for (idx, partition) in partitions.partitions.iter().enumerate() {
+            for site in sites(partition) {
+                if let Some(other) = owners[site].replace(idx) {
+                    return Err(ParsimonyError::InvalidInput(format!(
+                        "Position {} is in both the partitions {} and {}.",
+                        site + 1,
+                        partitions.partitions[other].name,
+                        partition.name
+                    )));
+                }
+            }
+        }
+        Ok(partitions)
+    }
+
+    /// Reads the partitions from a file with a line per partition in the style of RAxML,
+    /// `[TYPE] MODEL [PARAMS...], NAME = RANGES`: the optional data type DNA, RNA or PROT, the
+    /// model with its parameters separated by whitespace, the name of the partition and the
+    /// comma-separated 1-based, inclusive ranges of positions of the sequences, e.g.
+    /// `HKY85 2.0, gene1_pos12 = 1-300\3, 2-300\3`. A range can be a single position and `\k`
+    /// takes every k-th position of the range. Empty lines and lines starting with # are
+    /// ignored. Not available on wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path) -> Result<Self> {
+        info!("Reading the partitions from {}.", path.display());
+        let partitions = Self::parse(&fs::read_to_string(path)?)?;
+        info!(
+            "Scoring the sequences in the partitions {:?}.",
+            partitions.iter().map(|p| &p.name).collect::<Vec<_>>()
+        );
+        Ok(partitions)
+    }
+
+    fn parse(text: &str) -> Result<Self> {
+        let mut partitions = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                ParsimonyError::InvalidInput(format!(
+                    "Line {} of the partition file is not of the form [TYPE] MODEL [PARAMS...], NAME = RANGES.",
+                    line_no + 1
+                ))
+            };
+            let (model, rest) = line.split_once(',').ok_or_else(invalid)?;
+            let (name, ranges) = rest.split_once('=').ok_or_else(invalid)?;
+            let mut model = model.split_whitespace().peekable();
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            let data_type = match model.peek().map(|token| token.to_ascii_uppercase()) {
+                Some(token) if token == "DNA" => DataType::Dna,
+                Some(token) if token == "RNA" => DataType::Rna,
+                Some(token) if token == "PROT" || token == "PROTEIN" => DataType::Protein,
+                _ => DataType::Auto,
+            };
+            if data_type != DataType::Auto {
+                model.next();
+            }
+            partitions.push(Partition {
+                name: name.to_string(),
+                data_type,
+                model: model.next().ok_or_else(invalid)?.to_string(),
+                params: model
+                    .map(|param| param.parse().map_err(|_| invalid()))
+                    .collect::<Result<_>>()?,
+                ranges: ranges
+                    .split(',')
+                    .map(|range| parse_range(range.trim()).ok_or_else(invalid))
+                    .collect::<Result<_>>()?,
+            });
+        }
+        Self::new(partitions)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Partition> {
+        self.partitions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.partitions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.is_empty()
+    }
+
+    /// Partition of each of the `len` residues of a sequence, the number of partitions for the
+    /// residues outside of them.
+    pub fn site_partitions(&self, len: usize) -> Vec<u8> {
+        let mut site_partitions = vec![self.len() as u8; len];
+        for (idx, partition) in self.partitions.iter().enumerate() {
+            for site in sites(partition).filter(|&site| site < len) {
+                site_partitions[site] = idx as u8;
+            }
+        }
+        site_partitions
+    }
+
+    /// Whether the residues of a partition are of another type than `sequence_type`, so that
+    /// the partitions cannot be scored in a single alignment.
+    pub fn mixed_types(&self, sequences: &[Record], sequence_type: &SequenceType) -> bool {
+        self.iter().any(|partition| {
+            !matches!(
+                (partition.sequence_type(sequences), sequence_type),
+                (SequenceType::DNA, SequenceType::DNA)
+                    | (SequenceType::Protein, SequenceType::Protein)
+            )
+        })
+    }
+
+    /// Residues outside of the partitions of every sequence, in the order of their positions.
+    pub fn unpartitioned_sequences(&self, sequences: &[Record]) -> Vec<Record> {
+        sequences
+            .iter()
+            .map(|record| {
+                let seq: Vec<u8> = zip(record.seq(), self.site_partitions(record.seq().len()))
+                    .filter(|(_, partition)| *partition as usize == self.len())
+                    .map(|(&residue, _)| residue)
+                    .collect();
+                Record::with_attrs(record.id(), record.desc(), &seq)
+            })
+            .collect()
+    }
+
+    /// Whether the positions of the partitions, taken one partition after the other, are in
+    /// increasing order, so that concatenating the partitions keeps the order of the sequences.
+    pub fn keeps_positions(&self) -> bool {
+        let positions: Vec<usize> = self
+            .partitions
+            .iter()
+            .flat_map(|partition| {
+                let mut positions: Vec<usize> = sites(partition).collect();
+                positions.sort_unstable();
+                positions
+            })
+            .collect();
+        positions.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    /// Number of positions up to the end of the last range.
+    fn len_sites(&self) -> usize {
+        self.partitions
+            .iter()
+            .flat_map(|partition| &partition.ranges)
+            .map(|(range, _)| range.end)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+fn sites(partition: &Partition) -> impl Iterator<Item = usize> + '_ {
+    partition
+        .ranges
+        .iter()
+        .flat_map(|(range, step)| range.clone().step_by(*step))
+}
+
+/// Alignment of the blocks of the sequences that were aligned separately on the same tree, e.g.
+/// partitions of different data types, as one alignment of the blocks concatenated in the
+/// given order. The alignment of a node joins the alignments of the node in the blocks and its
+/// score is their sum. Returns the concatenated sequences with the tree, the MSA of the result
+/// for them is the concatenation of the MSAs of the blocks.
+pub fn concatenate_alignments(
+    blocks: &[(PhyloInfo, AlignmentResult)],
+) -> Result<(PhyloInfo, AlignmentResult)> {
+    let Some((first_info, first_result)) = blocks.first() else {
+        return Err(ParsimonyError::InvalidInput(
+            "There are no alignments to concatenate.".to_string(),
+        ));
+    };
+    let tree = &first_info.tree;
+    let sequences: Vec<Record> = first_info
+        .sequences
+        .iter()
+        .enumerate()
+        .map(|(leaf, record)| {
+            let seq: Vec<u8> = blocks
+                .iter()
+                .flat_map(|(info, _)| info.sequences[leaf].seq().iter().copied())
+                .collect();
+            Record::with_attrs(record.id(), record.desc(), &seq)
+        })
+        .collect();
+    let mut alignments = vec![Alignment::new(Mapping::new(), Mapping::new()); tree.internals.len()];
+    let mut scores = vec![0.0; tree.internals.len()];
+    // length of the profile of every node in the blocks concatenated so far
+    let mut internal_lengths = vec![0; tree.internals.len()];
+    let mut leaf_lengths = vec![0; tree.leaves.len()];
+    for (info, result) in blocks {
+        let offset = |node: &NodeIdx| match node {
+            Int(idx) => internal_lengths[*idx],
+            Leaf(idx) => leaf_lengths[*idx],
+        };
+        let shift = |mapping: &Mapping, offset: usize| -> Mapping {
+            mapping
+                .iter()
+                .map(|site| site.map(|idx| idx + offset))
+                .collect()
+        };
+        for (idx, alignment) in result.alignments.iter().enumerate() {
+            let children = &tree.internals[idx].children;
+            let map_x = shift(&alignment.map_x, offset(&children[0]));
+            let map_y = shift(&alignment.map_y, offset(&children[1]));
+            alignments[idx].map_x.extend(map_x);
+            alignments[idx].map_y.extend(map_y);
+            scores[idx] += result.scores[idx];
+        }
+        for (length, alignment) in internal_lengths.iter_mut().zip(&result.alignments) {
+            *length += alignment.map_x.len();
+        }
+        for (length, record) in leaf_lengths.iter_mut().zip(&info.sequences) {
+            *length += record.seq().len();
+        }
+    }
+    Ok((
+        phyloinfo_from_sequences_tree(&sequences, tree.clone())?,
+        AlignmentResult::new(alignments, scores, first_result.order.clone()),
+    ))
+}
+
+/// Range of the form START[-END][\STEP] with 1-based, inclusive positions.
+fn parse_range(range: &str) -> Option<(Range<usize>, usize)> {
+    let (range, step) = match range.split_once('\\') {
+        Some((range, step)) => (range, step.trim().parse().ok()?),
+        None => (range, 1),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+        None => {
+            let position = range.trim().parse().ok()?;
+            (position, position)
+        }
+    };
+    if start == 0 {
+        return None;
+    }
+    Some((start - 1..end, step))
+}
+
+#[cfg(test)]
+mod partitions_tests {
+    use super::{concatenate_alignments, Partition, Partitions};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree, DataType};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::sequences::SequenceType;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+    use std::iter::zip;
+
+    #[test]
+    fn partitions_from_text() {
+        let partitions = Partitions::parse(
+            "# gene 1 by codon position\n\
+             HKY85 2.0, gene1_pos12 = 1-6\\3, 2-6\\3\n\
+             \n\
+             HKY85 4.0, gene1_pos3 = 3-6\\3\n\
+             JC69, gene2 = 8-9\n",
+        )
+        .unwrap();
+        assert_eq!(partitions.len(), 3);
+        let names: Vec<&str> = partitions.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["gene1_pos12", "gene1_pos3", "gene2"]);
+        let first = partitions.iter().next().unwrap();
+        assert_eq!(first.model, "HKY85");
+        assert_eq!(first.params, vec![2.0]);
+        assert_eq!(first.ranges, vec![(0..6, 3), (1..6, 3)]);
+        assert!(partitions.iter().last().unwrap().params.is_empty());
+        assert!(partitions.iter().all(|p| p.data_type == DataType::Auto));
+        // position 7 and the positions past the last range are outside of the partitions
+        assert_eq!(
+            partitions.site_partitions(11),
+            vec![0, 0, 1, 0, 0, 1, 3, 2, 2, 3, 3]
+        );
+        assert_eq!(partitions.site_partitions(2), vec![0, 0]);
+    }
+
+    #[test]
+    fn invalid_partitions() {
+        assert!(Partitions::parse("HKY85 2.0 gene1 = 1-6").is_err());
+        assert!(Partitions::parse("HKY85 x, gene1 = 1-6").is_err());
+        assert!(Partitions::parse("HKY85, gene1 = 0-6").is_err());
+        assert!(Partitions::parse("HKY85, gene1 = 1-6\\0").is_err());
+        assert!(Partitions::parse("HKY85, gene1 = 4-3").is_err());
+        assert!(Partitions::parse("HKY85, gene1 = 1-6\nJC69, gene1 = 7-9").is_err());
+        // overlapping ranges
+        assert!(Partitions::parse("HKY85, gene1 = 1-6\nJC69, gene2 = 6-9").is_err());
+        assert!(Partitions::parse("HKY85, gene1 = 1-6\\2\nJC69, gene2 = 2-6\\2").is_ok());
+        let partition = |idx: usize| Partition {
+            name: idx.to_string(),
+            data_type: DataType::Auto,
+            model: "JC69".to_string(),
+            params: Vec::new(),
+            ranges: vec![(idx..idx + 1, 1)],
+        };
+        assert!(Partitions::new((0..255).map(partition).collect()).is_err());
+        assert!(Partitions::new((0..254).map(partition).collect()).is_ok());
+    }
+
+    #[test]
+    fn mixed_partitions() {
+        let partitions = Partitions::parse(
+            "DNA HKY85 2.0, gene = 1-6\nprot WAG, protein = 7-10\nPROT LG, short = 12\n",
+        )
+        .unwrap();
+        let types: Vec<DataType> = partitions.iter().map(|p| p.data_type).collect();
+        assert_eq!(types, [DataType::Dna, DataType::Protein, DataType::Protein]);
+        assert_eq!(partitions.iter().nth(1).unwrap().model, "WAG");
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGTACMKLWCR"),
+            Record::with_attrs("B", None, b"ACGAACMKLWC"),
+        ];
+        let protein = partitions.iter().nth(1).unwrap();
+        assert_eq!(protein.sequences(&sequences)[1].seq(), &b"MKLW"[..]);
+        assert!(matches!(
+            protein.sequence_type(&sequences),
+            SequenceType::Protein
+        ));
+        assert_eq!(
+            partitions.iter().nth(2).unwrap().sequences(&sequences)[1].seq(),
+            &b""[..]
+        );
+        let unpartitioned = partitions.unpartitioned_sequences(&sequences);
+        assert_eq!(unpartitioned[0].seq(), &b"C"[..]);
+        assert_eq!(unpartitioned[1].seq(), &b"C"[..]);
+        assert!(partitions.mixed_types(&sequences, &SequenceType::DNA));
+        assert!(partitions.mixed_types(&sequences, &SequenceType::Protein));
+        let dna = Partitions::parse("HKY85, gene = 1-6\n").unwrap();
+        assert!(!dna.mixed_types(&sequences, &SequenceType::DNA));
+        assert!(partitions.keeps_positions());
+        let codons = Partitions::parse("HKY85, pos12 = 1-6\\3, 2-6\\3\nJC69, pos3 = 3-6\\3\n");
+        assert!(!codons.unwrap().keeps_positions());
+    }
+
+    #[test]
+    fn concatenated_alignments() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTMKLW"),
+            Record::with_attrs("B", None, b"ACMKW"),
+            Record::with_attrs("C", None, b"AACMKLW"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), I(0), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let block = |seqs: [&[u8]; 3]| {
+            let records: Vec<Record> = zip(&sequences, seqs)
+                .map(|(record, seq)| Record::with_attrs(record.id(), None, seq))
+                .collect();
+            let info = phyloinfo_from_sequences_tree(&records, tree.clone()).unwrap();
+            let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+            let result = pars_align_on_tree(&scoring, &info).unwrap();
+            (info, result)
+        };
+        let blocks = [
+            block([b"AACT", b"AC", b"AAC"]),
+            block([b"MKLW", b"MKW", b"MKLW"]),
+        ];
+        let (info, result) = concatenate_alignments(&blocks).unwrap();
+        let seqs: Vec<&[u8]> = info.sequences.iter().map(|record| record.seq()).collect();
+        assert_eq!(seqs, [&b"AACTMKLW"[..], &b"ACMKW"[..], &b"AACMKLW"[..]]);
+        assert_eq!(result.score(), blocks[0].1.score() + blocks[1].1.score());
+        let msa = result.msa(&info);
+        for (record, (block_a, block_b)) in zip(
+            &msa,
+            zip(blocks[0].1.msa(&blocks[0].0), blocks[1].1.msa(&blocks[1].0)),
+        ) {
+            assert_eq!(record.id(), block_a.id());
+            assert_eq!(record.seq(), [block_a.seq(), block_b.seq()].concat());
+        }
+        assert!(concatenate_alignments(&[]).is_err());
+    }
+}
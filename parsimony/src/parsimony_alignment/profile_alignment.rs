@@ -0,0 +1,651 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::iter::once;
+
+use super::msa_scoring::{msa_on_tree, MsaOnTree, GAP};
+use super::parsimony_costs::ParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::{leaf_site_info, node_rng, pars_align_w_options, AlignmentOptions};
+use crate::{ParsimonyError, Result};
+
+/// Tree of some of the sequences cut out of a tree that also holds others.
+struct PrunedTree {
+    tree: Tree,
+    next_internal: usize,
+    /// Index among the kept sequences of every leaf of the full tree.
+    kept_idx: Vec<Option<usize>>,
+    /// Node of the pruned tree for every internal node of the full tree with kept sequences
+    /// below it, with the length of the path to it from the parent of the full node.
+    internals: Vec<Option<(NodeIdx, f64)>>,
+}
+
+impl PrunedTree {
+    /// Prunes the sequences for which `keep` is false from the tree of `info`, and returns the
+    /// pruned tree with the info of the kept sequences on it.
+    fn new(info: &PhyloInfo, keep: &[bool]) -> Result<(Self, PhyloInfo)> {
+        let tree = &info.tree;
+        let kept: Vec<Record> = info
+            .sequences
+            .iter()
+            .zip(keep)
+            .filter(|(_, &keep)| keep)
+            .map(|(record, _)| record.clone())
+            .collect();
+        let mut next_kept = 0;
+        let kept_idx = keep
+            .iter()
+            .map(|&keep| {
+                keep.then(|| {
+                    next_kept += 1;
+                    next_kept - 1
+                })
+            })
+            .collect();
+        let mut pruned = PrunedTree {
+            tree: Tree::new(&kept)?,
+            next_internal: 0,
+            kept_idx,
+            internals: vec![None; tree.internals.len()],
+        };
+        let root = *tree.postorder.last().unwrap();
+        prune_subtree(tree, root, &mut pruned);
+        pruned.tree.complete = true;
+        pruned.tree.create_postorder();
+        let pruned_info = phyloinfo_from_sequences_tree(&kept, pruned.tree.clone())?;
+        Ok((pruned, pruned_info))
+    }
+
+    /// Node of the pruned tree for a node of the full tree, see `internals`.
+    fn node(&self, tree: &Tree, node: NodeIdx) -> Option<(NodeIdx, f64)> {
+        match node {
+            Int(idx) => self.internals[idx],
+            Leaf(idx) => self.kept_idx[idx].map(|kept| (Leaf(kept), tree.leaves[idx].blen)),
+        }
+    }
+}
+
+/// Copies the subtree of `node` into the pruned tree without the dropped sequences, the nodes
+/// left with a single child are replaced by the child with the two branches joined. Nodes with
+/// more than two children are split by joining the children from the left under fresh nodes
+/// with zero length branches.
+fn prune_subtree(tree: &Tree, node: NodeIdx, pruned: &mut PrunedTree) -> Option<(NodeIdx, f64)> {
+    let Int(idx) = node else {
+        return pruned.node(tree, node);
+    };
+    let children: Vec<(NodeIdx, f64)> = tree.internals[idx]
+        .children
+        .iter()
+        .filter_map(|&child| prune_subtree(tree, child, pruned))
+        .collect();
+    let blen = tree.internals[idx].blen;
+    pruned.internals[idx] = match children[..] {
+        [] => None,
+        [(child, child_blen)] => Some((child, child_blen + blen)),
+        [first, ref rest @ ..] => {
+            let mut joined = first;
+            for &(child, child_blen) in rest {
+                let pruned_idx = pruned.next_internal;
+                pruned.next_internal += 1;
+                pruned
+                    .tree
+                    .add_parent(pruned_idx, joined.0, child, joined.1, child_blen);
+                joined = (Int(pruned_idx), 0.0);
+            }
+            Some((joined.0, blen))
+        }
+    };
+    pruned.internals[idx]
+}
+
+/// Parents of the leaves and of the internal nodes of the tree.
+pub(crate) fn node_parents(tree: &Tree) -> [Vec<Option<usize>>; 2] {
+    let mut parents = [
+        vec![None; tree.leaves.len()],
+        vec![None; tree.internals.len()],
+    ];
+    for (idx, node) in tree.internals.iter().enumerate() {
+        for child in &node.children {
+            match child {
+                Leaf(child) => parents[0][*child] = Some(idx),
+                Int(child) => parents[1][*child] = Some(idx),
+            }
+        }
+    }
+    parents
+}
+
+/// Node of the reference tree that a new sequence is aligned to: the sibling of the first node
+/// on the path to the root whose sibling holds reference sequences. Returns the node and the
+/// lengths of the branches from their common ancestor to the new sequence and to the node.
+fn placement(
+    tree: &Tree,
+    leaf: usize,
+    parents: &[Vec<Option<usize>>; 2],
+    pruned: &PrunedTree,
+) -> Option<(NodeIdx, f64, f64)> {
+    let mut node = Leaf(leaf);
+    let mut x_branch = tree.leaves[leaf].blen;
+    loop {
+        let parent = match node {
+            Leaf(idx) => parents[0][idx],
+            Int(idx) => parents[1][idx],
+        }?;
+        let children = &tree.internals[parent].children;
+        let sibling = if children[0] == node {
+            children[1]
+        } else {
+            children[0]
+        };
+        if let Some((reference, y_branch)) = pruned.node(tree, sibling) {
+            return Some((reference, x_branch, y_branch));
+        }
+        x_branch += tree.internals[parent].blen;
+        node = Int(parent);
+    }
+}
+
+/// Residues of a new sequence in the reference columns, and the residues inserted after every
+/// reference column, or before the first one for `None`.
+#[derive(Default)]
+struct AddedSequence {
+    columns: HashMap<usize, u8>,
+    insertions: HashMap<Option<usize>, Vec<u8>>,
+}
+
+/// Aligns a new sequence to the sites of a node of the reference MSA on the tree.
+fn add_sequence(
+    scoring: &dyn ParsimonyCosts,
+    record: &Record,
+    leaf: usize,
+    (node, x_branch, y_branch): (NodeIdx, f64, f64),
+    msa: &MsaOnTree,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<AddedSequence> {
+    let residues: Vec<u8> = record.seq().iter().copied().filter(|&c| c != GAP).collect();
+    let sequence_type = options.data_type.sequence_type(&info.sequences);
+    let x_info = leaf_site_info(
+        &Record::with_attrs(record.id(), record.desc(), &residues),
+        &sequence_type,
+        options,
+    );
+    let (y_info, y_columns) = match node {
+        Int(idx) => (&msa.internal_info[idx], &msa.internal_columns[idx]),
+        Leaf(idx) => (&msa.leaf_info[idx], &msa.leaf_columns[idx]),
+    };
+    let (_, alignment, score) = pars_align_w_options(
+        &x_info,
+        scoring.get_branch_costs(x_branch),
+        y_info,
+        scoring.get_branch_costs(y_branch),
+        node_rng(options, leaf),
+        options,
+    )?;
+    info!("Added sequence {} with score {}.", record.id(), score);
+    // only the sites without a fixed gap can be matched, and their columns are in MSA order
+    let mut added = AddedSequence::default();
+    let mut anchor = None;
+    for (x, y) in alignment.map_x.iter().zip(alignment.map_y.iter()) {
+        match (x, y) {
+            (Some(i), Some(j)) => {
+                anchor = Some(y_columns[*j]);
+                added.columns.insert(y_columns[*j], residues[*i]);
+            }
+            (Some(i), None) => added
+                .insertions
+                .entry(anchor)
+                .or_default()
+                .push(residues[*i]),
+            (None, Some(j)) if !y_info[*j].is_fixed() => anchor = Some(y_columns[*j]),
+            _ => {}
+        }
+    }
+    Ok(added)
+}
+
+/// Aligns new sequences to a fixed reference MSA without changing its columns. The sequences
+/// in `info` are the rows of the MSA and the new sequences with the ids in `new_ids`, the tree
+/// places the new sequences among the reference ones. Every new sequence is aligned to the
+/// reference MSA at the node of the tree it is attached to, its residues are either put in the
+/// MSA columns or inserted into new columns of their own, so the new sequences are not aligned
+/// to each other. Returns the rows of the extended MSA in the order of the sequences in `info`.
+pub fn pars_add_to_msa(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    new_ids: &HashSet<&str>,
+    options: &AlignmentOptions,
+) -> Result<Vec<Record>> {
+    let tree = &info.tree;
+    let sequences = &info.sequences;
+    let added_count = sequences
+        .iter()
+        .filter(|record| new_ids.contains(record.id()))
+        .count();
+    if sequences.len() - added_count < 2 {
+        return Err(ParsimonyError::TooFewSequences(
+            "The reference MSA needs at least two sequences that are in the tree.".to_string(),
+        ));
+    }
+    if added_count == 0 {
+        return Err(ParsimonyError::TreeSequenceMismatch(
+            "None of the new sequences are in the tree.".to_string(),
+        ));
+    }
+    info!(
+        "Adding {} sequences to the MSA of {} sequences.",
+        added_count,
+        sequences.len() - added_count
+    );
+    let reference: Vec<bool> = sequences
+        .iter()
+        .map(|record| !new_ids.contains(record.id()))
+        .collect();
+    let (pruned, reference_info) = PrunedTree::new(info, &reference)?;
+    let msa = msa_on_tree(scoring, &reference_info, options, false)?;
+
+    let parents = node_parents(tree);
+    let mut added = BTreeMap::new();
+    for (leaf, record) in sequences.iter().enumerate() {
+        if pruned.kept_idx[leaf].is_some() {
+            continue;
+        }
+        let node = placement(tree, leaf, &parents, &pruned).unwrap();
+        let sequence = add_sequence(scoring, record, leaf, node, &msa, &reference_info, options)?;
+        added.insert(leaf, sequence);
+    }
+
+    let mut rows = vec![Vec::<u8>::new(); sequences.len()];
+    for anchor in once(None).chain((0..msa.columns).map(Some)) {
+        if let Some(column) = anchor {
+            for (leaf, row) in rows.iter_mut().enumerate() {
+                row.push(match added.get(&leaf) {
+                    Some(sequence) => *sequence.columns.get(&column).unwrap_or(&GAP),
+                    None => sequences[leaf].seq()[column],
+                });
+            }
+        }
+        for (leaf, sequence) in &added {
+            let Some(insertion) = sequence.insertions.get(&anchor) else {
+                continue;
+            };
+            for &residue in insertion {
+                for (other, row) in rows.iter_mut().enumerate() {
+                    row.push(if other == *leaf { residue } else { GAP });
+                }
+            }
+        }
+    }
+    Ok(sequences
+        .iter()
+        .zip(rows)
+        .map(|(record, row)| Record::with_attrs(record.id(), record.desc(), &row))
+        .collect())
+}
+
+/// Profile at the root of an MSA scored on its tree, with the MSA column of every site.
+pub(crate) struct Profile {
+    pub(crate) sites: Vec<ParsimonySiteInfo>,
+    pub(crate) columns: Vec<usize>,
+    /// Score of the MSA on its tree.
+    pub(crate) score: f64,
+}
+
+/// Profile of the MSA of the rows of `info` for which `keep` is true, scored on the tree of
+/// `info` with the other sequences pruned.
+pub(crate) fn msa_profile(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    keep: &[bool],
+    options: &AlignmentOptions,
+) -> Result<Profile> {
+    let kept: Vec<&Record> = info
+        .sequences
+        .iter()
+        .zip(keep)
+        .filter(|(_, &keep)| keep)
+        .map(|(record, _)| record)
+        .collect();
+    match kept[..] {
+        [] => Err(ParsimonyError::TooFewSequences(
+            "The profile has no sequences.".to_string(),
+        )),
+        [record] => {
+            let (columns, residues): (Vec<usize>, Vec<u8>) = record
+                .seq()
+                .iter()
+                .enumerate()
+                .filter(|(_, &c)| c != GAP)
+                .map(|(column, &c)| (column, c))
+                .unzip();
+            let sequence_type = options.data_type.sequence_type(&info.sequences);
+            let sites = leaf_site_info(
+                &Record::with_attrs(record.id(), record.desc(), &residues),
+                &sequence_type,
+                options,
+            );
+            Ok(Profile {
+                sites,
+                columns,
+                score: 0.0,
+            })
+        }
+        _ => {
+            let (_, kept_info) = PrunedTree::new(info, keep)?;
+            let mut msa = msa_on_tree(scoring, &kept_info, options, false)?;
+            let Int(root) = *kept_info.tree.postorder.last().unwrap() else {
+                unreachable!();
+            };
+            Ok(Profile {
+                sites: msa.internal_info.swap_remove(root),
+                columns: msa.internal_columns.swap_remove(root),
+                score: msa.scores.iter().sum(),
+            })
+        }
+    }
+}
+
+/// Aligns the profiles of two disjoint parts of an MSA, the rows of `info` for which `in_x` is
+/// true and the others, and returns the rows of the new MSA in the order of `info` with its
+/// score, the score of the profile alignment plus the scores of the two profiles.
+pub(crate) fn align_profiles(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    in_x: &[bool],
+    (x, x_branch): (&Profile, f64),
+    (y, y_branch): (&Profile, f64),
+    node_idx: usize,
+    options: &AlignmentOptions,
+) -> Result<(Vec<Record>, f64)> {
+    let (_, alignment, score) = pars_align_w_options(
+        &x.sites,
+        scoring.get_branch_costs(x_branch),
+        &y.sites,
+        scoring.get_branch_costs(y_branch),
+        node_rng(options, node_idx),
+        options,
+    )?;
+    let rows = info
+        .sequences
+        .iter()
+        .zip(in_x)
+        .map(|(record, &in_x)| {
+            let (map, columns) = if in_x {
+                (&alignment.map_x, &x.columns)
+            } else {
+                (&alignment.map_y, &y.columns)
+            };
+            let row: Vec<u8> = map
+                .iter()
+                .map(|site| match site {
+                    Some(site) => record.seq()[columns[*site]],
+                    None => GAP,
+                })
+                .collect();
+            Record::with_attrs(record.id(), record.desc(), &row)
+        })
+        .collect();
+    Ok((rows, score + x.score + y.score))
+}
+
+/// Aligns two MSAs to each other as profiles without changing the columns of either, e.g. to
+/// combine the alignments of two clades. The sequences in `info` are the rows of both MSAs, the
+/// ones with the ids in `y_ids` form the second MSA, and the root of the tree splits the two.
+/// Both MSAs are first scored on their subtrees, then the profiles at their roots are aligned
+/// with the indel-aware parsimony score. Columns with only gaps are dropped. Returns the rows of
+/// the merged MSA in the order of the sequences in `info` and its total score.
+pub fn pars_merge_msas(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    y_ids: &HashSet<&str>,
+    options: &AlignmentOptions,
+) -> Result<(Vec<Record>, f64)> {
+    let tree = &info.tree;
+    let sequences = &info.sequences;
+    let in_x: Vec<bool> = sequences
+        .iter()
+        .map(|record| !y_ids.contains(record.id()))
+        .collect();
+    let in_y: Vec<bool> = in_x.iter().map(|&in_x| !in_x).collect();
+    let y_count = in_y.iter().filter(|&&in_y| in_y).count();
+    if y_count < 2 || sequences.len() - y_count < 2 {
+        return Err(ParsimonyError::TooFewSequences(
+            "Both MSAs need at least two sequences that are in the tree.".to_string(),
+        ));
+    }
+    let (x_pruned, _) = PrunedTree::new(info, &in_x)?;
+    let (y_pruned, _) = PrunedTree::new(info, &in_y)?;
+    let Int(root_idx) = *tree.postorder.last().unwrap() else {
+        return Err(ParsimonyError::InvalidTree(
+            "The tree has no internal nodes.".to_string(),
+        ));
+    };
+    let children = &tree.internals[root_idx].children;
+    let side = |child: NodeIdx| (x_pruned.node(tree, child), y_pruned.node(tree, child));
+    let (x_branch, y_branch) = match (side(children[0]), side(children[1])) {
+        ((Some((_, x_branch)), None), (None, Some((_, y_branch))))
+        | ((None, Some((_, y_branch))), (Some((_, x_branch)), None)) => (x_branch, y_branch),
+        _ => {
+            return Err(ParsimonyError::TreeSequenceMismatch(
+                "The root of the tree does not split the sequences into the two MSAs.".to_string(),
+            ))
+        }
+    };
+    info!(
+        "Merging the MSAs of {} and {} sequences.",
+        sequences.len() - y_count,
+        y_count
+    );
+    let x = msa_profile(scoring, info, &in_x, options)?;
+    let y = msa_profile(scoring, info, &in_y, options)?;
+    let (rows, score) = align_profiles(
+        scoring,
+        info,
+        &in_x,
+        (&x, x_branch),
+        (&y, y_branch),
+        root_idx,
+        options,
+    )?;
+    info!("Merged the MSAs with total score {}.", score);
+    Ok((rows, score))
+}
+
+#[cfg(test)]
+mod profile_alignment_tests {
+    use super::{pars_add_to_msa, pars_merge_msas, PrunedTree};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+    use std::collections::HashSet;
+
+    fn add_to_msa(sequences: &[Record], tree: Tree, new_ids: &[&str]) -> Vec<Vec<u8>> {
+        let info = phyloinfo_from_sequences_tree(sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let new_ids: HashSet<&str> = new_ids.iter().copied().collect();
+        pars_add_to_msa(&scoring, &info, &new_ids, &AlignmentOptions::default())
+            .unwrap()
+            .iter()
+            .map(|record| record.seq().to_vec())
+            .collect()
+    }
+
+    fn cherry_tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    fn balanced_tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    #[test]
+    fn prune_multifurcating_node() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"ACGT"),
+            Record::with_attrs("C", None, b"ACGT"),
+            Record::with_attrs("D", None, b"ACGT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.internals[1].children.push(L(3));
+        tree.leaves[3].blen = 1.0;
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let (pruned, pruned_info) = PrunedTree::new(&info, &[true, false, true, true]).unwrap();
+        assert_eq!(pruned.tree.internals.len(), 2);
+        assert_eq!(pruned_info.sequences.len(), 3);
+        assert_eq!(pruned.tree.postorder.len(), 5);
+        for leaf in [L(0), L(1), L(2)] {
+            assert!(pruned.tree.postorder.contains(&leaf));
+        }
+    }
+
+    #[test]
+    fn add_into_existing_columns() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AC-GT"),
+            Record::with_attrs("N", None, b"ACGT"),
+            Record::with_attrs("B", None, b"ACTGT"),
+        ];
+        let rows = add_to_msa(&sequences, cherry_tree(&sequences), &["N"]);
+        assert_eq!(
+            rows,
+            vec![b"AC-GT".to_vec(), b"AC-GT".to_vec(), b"ACTGT".to_vec()]
+        );
+    }
+
+    #[test]
+    fn add_w_insertion() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AC-GT"),
+            Record::with_attrs("N", None, b"ACGTAA"),
+            Record::with_attrs("B", None, b"ACTGT"),
+        ];
+        let rows = add_to_msa(&sequences, cherry_tree(&sequences), &["N"]);
+        assert_eq!(
+            rows,
+            vec![
+                b"AC-GT--".to_vec(),
+                b"AC-GTAA".to_vec(),
+                b"ACTGT--".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn add_clade_of_new_sequences() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACTGT"),
+            Record::with_attrs("B", None, b"ACTGT"),
+            Record::with_attrs("M", None, b"GGACTGT"),
+            Record::with_attrs("N", None, b"ACTGTCC"),
+        ];
+        let rows = add_to_msa(&sequences, balanced_tree(&sequences), &["M", "N"]);
+        assert_eq!(
+            rows,
+            vec![
+                b"--ACTGT--".to_vec(),
+                b"--ACTGT--".to_vec(),
+                b"GGACTGT--".to_vec(),
+                b"--ACTGTCC".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn add_nothing() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AC-GT"),
+            Record::with_attrs("N", None, b"ACGT"),
+            Record::with_attrs("B", None, b"ACTGT"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&sequences, cherry_tree(&sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        assert!(pars_add_to_msa(&scoring, &info, &HashSet::new(), &options).is_err());
+        let new_ids = HashSet::from(["N", "B"]);
+        assert!(pars_add_to_msa(&scoring, &info, &new_ids, &options).is_err());
+    }
+
+    fn merge_msas(sequences: &[Record], y_ids: &[&str]) -> crate::Result<(Vec<Vec<u8>>, f64)> {
+        let info = phyloinfo_from_sequences_tree(sequences, balanced_tree(sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let y_ids: HashSet<&str> = y_ids.iter().copied().collect();
+        let (msa, score) = pars_merge_msas(&scoring, &info, &y_ids, &AlignmentOptions::default())?;
+        Ok((
+            msa.iter().map(|record| record.seq().to_vec()).collect(),
+            score,
+        ))
+    }
+
+    #[test]
+    fn merge_into_shared_columns() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AC-GT"),
+            Record::with_attrs("B", None, b"ACTGT"),
+            Record::with_attrs("C", None, b"ACTGT"),
+            Record::with_attrs("D", None, b"ACTGT"),
+        ];
+        let (rows, _) = merge_msas(&sequences, &["C", "D"]).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                b"AC-GT".to_vec(),
+                b"ACTGT".to_vec(),
+                b"ACTGT".to_vec(),
+                b"ACTGT".to_vec()
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_w_gaps() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"ACGT"),
+            Record::with_attrs("C", None, b"ACGTAA"),
+            Record::with_attrs("D", None, b"ACGTAA"),
+        ];
+        let (rows, score) = merge_msas(&sequences, &["C", "D"]).unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                b"ACGT--".to_vec(),
+                b"ACGT--".to_vec(),
+                b"ACGTAA".to_vec(),
+                b"ACGTAA".to_vec()
+            ]
+        );
+        assert!(score > 0.0);
+    }
+
+    #[test]
+    fn merge_not_split_by_root() {
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGT"),
+            Record::with_attrs("B", None, b"ACGTAA"),
+            Record::with_attrs("C", None, b"ACGT"),
+            Record::with_attrs("D", None, b"ACGTAA"),
+        ];
+        assert!(merge_msas(&sequences, &["B", "D"]).is_err());
+        assert!(merge_msas(&sequences, &["D"]).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use bio::io::fasta::Record;
+use phylo::sequences::SequenceType;
+
+/// Character-state recoding schemes that are applied to the sequences before the leaf
+/// parsimony sets are built. Every class of characters is represented by a single residue
+/// so the model-derived costs are used between the class representatives. The recoding only
+/// affects scoring, the alignment itself refers to the original sequences.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Recoding {
+    #[default]
+    None,
+    /// Purine/pyrimidine coding for DNA: A, G -> A and C, T -> C.
+    RY,
+    /// Dayhoff 6-state coding for proteins: AGPST -> A, DENQ -> D, HKR -> H, ILMV -> I,
+    /// FWY -> F and C -> C.
+    Dayhoff6,
+}
+
+impl Recoding {
+    pub fn supports(&self, sequence_type: &SequenceType) -> bool {
+        match self {
+            Recoding::None => true,
+            Recoding::RY => matches!(sequence_type, SequenceType::DNA),
+            Recoding::Dayhoff6 => matches!(sequence_type, SequenceType::Protein),
+        }
+    }
+
+    pub fn recode(&self, char: u8) -> u8 {
+        let char = char.to_ascii_uppercase();
+        match self {
+            Recoding::None => char,
+            Recoding::RY => ry_class(char),
+            Recoding::Dayhoff6 => dayhoff6_class(char),
+        }
+    }
+
+    pub fn recode_record(&self, record: &Record) -> Record {
+        let seq: Vec<u8> = record.seq().iter().map(|&c| self.recode(c)).collect();
+        Record::with_attrs(record.id(), record.desc(), &seq)
+    }
+}
+
+fn ry_class(char: u8) -> u8 {
+    match char {
+        b'A' | b'G' | b'R' => b'A',
+        b'C' | b'T' | b'U' | b'Y' => b'C',
+        b'-' => b'-',
+        _ => b'N',
+    }
+}
+
+fn dayhoff6_class(char: u8) -> u8 {
+    match char {
+        b'A' | b'G' | b'P' | b'S' | b'T' => b'A',
+        b'D' | b'E' | b'N' | b'Q' | b'B' | b'Z' => b'D',
+        b'H' | b'K' | b'R' => b'H',
+        b'I' | b'L' | b'M' | b'V' | b'J' => b'I',
+        b'F' | b'W' | b'Y' => b'F',
+        b'C' => b'C',
+        b'-' => b'-',
+        _ => b'X',
+    }
+}
+
+#[cfg(test)]
+mod recoding_tests {
+    use super::Recoding;
+    use bio::io::fasta::Record;
+    use phylo::sequences::SequenceType;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Recoding::None, b"AaCcGgTtRy-", b"AACCGGTTRY-")]
+    #[case(Recoding::RY, b"AaCcGgTtRy-", b"AACCAACCAC-")]
+    #[case(Recoding::RY, b"NSWKMBDHV", b"NNNNNNNNN")]
+    #[case(Recoding::Dayhoff6, b"AGPSTdenqHKRilmvFWYc-", b"AAAAADDDDHHHIIIIFFFC-")]
+    #[case(Recoding::Dayhoff6, b"BZJXO", b"DDIXX")]
+    fn recode_sequence(#[case] recoding: Recoding, #[case] input: &[u8], #[case] expected: &[u8]) {
+        let record = Record::with_attrs("A", Some("desc"), input);
+        let recoded = recoding.recode_record(&record);
+        assert_eq!(recoded.id(), "A");
+        assert_eq!(recoded.desc(), Some("desc"));
+        assert_eq!(recoded.seq(), expected);
+    }
+
+    #[test]
+    fn recoding_sequence_types() {
+        assert!(Recoding::None.supports(&SequenceType::DNA));
+        assert!(Recoding::None.supports(&SequenceType::Protein));
+        assert!(Recoding::RY.supports(&SequenceType::DNA));
+        assert!(!Recoding::RY.supports(&SequenceType::Protein));
+        assert!(!Recoding::Dayhoff6.supports(&SequenceType::DNA));
+        assert!(Recoding::Dayhoff6.supports(&SequenceType::Protein));
+    }
+}
@@ -0,0 +1,171 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+
+use super::msa_scoring::pars_score_msa;
+use super::parsimony_costs::ParsimonyCosts;
+use super::profile_alignment::{align_profiles, msa_profile, node_parents};
+use super::AlignmentOptions;
+use crate::Result;
+
+/// Smallest decrease of the total score that is kept as an improvement, so that rounding errors
+/// do not make the refinement go back and forth between equally good MSAs.
+const MIN_IMPROVEMENT: f64 = 1e-9;
+
+fn mark_subtree(tree: &Tree, node: NodeIdx, in_subtree: &mut [bool]) {
+    match node {
+        Leaf(idx) => in_subtree[idx] = true,
+        Int(idx) => {
+            for &child in &tree.internals[idx].children {
+                mark_subtree(tree, child, in_subtree);
+            }
+        }
+    }
+}
+
+fn msa_score(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+) -> Result<f64> {
+    Ok(pars_score_msa(scoring, info, options)?.1.iter().sum())
+}
+
+/// Refines an MSA on the tree by realigning one subtree at a time. The rows of the subtree and
+/// the rows of the rest of the tree are scored as two separate MSAs, and the profile at the root
+/// of the subtree is aligned to the profile at the root of the rest over the path between them.
+/// The new MSA is kept if it lowers the total parsimony score on the tree. Every iteration
+/// visits all the subtrees, and the refinement stops early once an iteration keeps no change.
+/// The sequences in `info` are the rows of the MSA. Returns the rows of the refined MSA in the
+/// same order and its total score.
+pub fn pars_refine_msa(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    iterations: usize,
+    options: &AlignmentOptions,
+) -> Result<(Vec<Record>, f64)> {
+    let tree = &info.tree;
+    let parents = node_parents(tree);
+    let mut current = phyloinfo_from_sequences_tree(&info.sequences, tree.clone())?;
+    let mut score = msa_score(scoring, &current, options)?;
+    info!("Refining the MSA with score {}.", score);
+    for iteration in 0..iterations {
+        let mut improved = false;
+        for &node in &tree.postorder {
+            let (parent, node_idx, mut x_branch) = match node {
+                Leaf(idx) => (parents[0][idx], idx, tree.leaves[idx].blen),
+                Int(idx) => (
+                    parents[1][idx],
+                    tree.leaves.len() + idx,
+                    tree.internals[idx].blen,
+                ),
+            };
+            let Some(mut ancestor) = parent else {
+                continue;
+            };
+            while let Some(parent) = parents[1][ancestor] {
+                x_branch += tree.internals[ancestor].blen;
+                ancestor = parent;
+            }
+            let mut in_x = vec![false; tree.leaves.len()];
+            mark_subtree(tree, node, &mut in_x);
+            let in_y: Vec<bool> = in_x.iter().map(|&in_x| !in_x).collect();
+            let x = msa_profile(scoring, &current, &in_x, options)?;
+            let y = msa_profile(scoring, &current, &in_y, options)?;
+            let (rows, _) = align_profiles(
+                scoring,
+                &current,
+                &in_x,
+                (&x, x_branch),
+                (&y, 0.0),
+                node_idx,
+                options,
+            )?;
+            let candidate = phyloinfo_from_sequences_tree(&rows, tree.clone())?;
+            let candidate_score = msa_score(scoring, &candidate, options)?;
+            if candidate_score < score - MIN_IMPROVEMENT {
+                current = candidate;
+                score = candidate_score;
+                improved = true;
+            }
+        }
+        info!(
+            "Refinement iteration {} gives score {}.",
+            iteration + 1,
+            score
+        );
+        if !improved {
+            break;
+        }
+    }
+    Ok((current.sequences, score))
+}
+
+#[cfg(test)]
+mod refinement_tests {
+    use super::{msa_score, pars_refine_msa};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::AlignmentOptions;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    fn ungapped(record: &Record) -> Vec<u8> {
+        record
+            .seq()
+            .iter()
+            .copied()
+            .filter(|&c| c != b'-')
+            .collect()
+    }
+
+    #[test]
+    fn refine_shifted_msa() {
+        let shifted = [
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"-AAC-"),
+            Record::with_attrs("C", None, b"AACT-"),
+            Record::with_attrs("D", None, b"-AAC-"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&shifted, tree(&shifted)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let shifted_score = msa_score(&scoring, &info, &options).unwrap();
+        let (refined, score) = pars_refine_msa(&scoring, &info, 5, &options).unwrap();
+        assert!(score < shifted_score);
+        for (record, refined) in shifted.iter().zip(refined.iter()) {
+            assert_eq!(record.id(), refined.id());
+            assert_eq!(ungapped(record), ungapped(refined));
+        }
+        let refined_info = phyloinfo_from_sequences_tree(&refined, tree(&refined)).unwrap();
+        assert_eq!(msa_score(&scoring, &refined_info, &options).unwrap(), score);
+    }
+
+    #[test]
+    fn refine_no_iterations() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACT-"),
+            Record::with_attrs("B", None, b"-AAC-"),
+            Record::with_attrs("C", None, b"AACT-"),
+            Record::with_attrs("D", None, b"-AAC-"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&sequences, tree(&sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let (refined, _) =
+            pars_refine_msa(&scoring, &info, 0, &AlignmentOptions::default()).unwrap();
+        for (record, refined) in sequences.iter().zip(refined.iter()) {
+            assert_eq!(record.seq(), refined.seq());
+        }
+    }
+}
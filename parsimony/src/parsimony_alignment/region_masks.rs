@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use bio::io::fasta::Record;
+use log::info;
+
+use crate::{ParsimonyError, Result};
+
+/// Regions of the input sequences that are excluded from the scoring, e.g. low quality or
+/// repeat segments. A masked residue stands for any character, so matching it costs nothing, and
+/// gaps against it cost `gap_weight` times the usual penalty, so such segments neither attract
+/// nor repel the gaps of the well aligned parts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegionMasks {
+    regions: HashMap<String, Vec<Range<usize>>>,
+    gap_weight: f64,
+}
+
+impl RegionMasks {
+    /// Masks from 0-based, end-exclusive ranges of residues per sequence id.
+    pub fn new(regions: HashMap<String, Vec<Range<usize>>>, gap_weight: f64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&gap_weight) {
+            return Err(ParsimonyError::InvalidParameters(format!(
+                "The gap penalty multiplier of masked regions must be between 0 and 1, got {}.",
+                gap_weight
+            )));
+        }
+        for (id, ranges) in &regions {
+            if let Some(range) = ranges.iter().find(|range| range.is_empty()) {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "The masked region {}-{} of {} is empty.",
+                    range.start, range.end, id
+                )));
+            }
+        }
+        Ok(RegionMasks {
+            regions,
+            gap_weight,
+        })
+    }
+
+    /// Reads the masks from a BED-like file with a line per region: the sequence id, the 0-based
+    /// start and the end of the region, which is not included, separated by whitespace. Further
+    /// fields are ignored, as are empty lines, lines starting with # and track or browser lines.
+    /// Not available on wasm32, which has no file system.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_bed_file(path: &Path, gap_weight: f64) -> Result<Self> {
+        info!("Reading the masked regions from {}.", path.display());
+        let mut regions = HashMap::<String, Vec<Range<usize>>>::new();
+        for (line_no, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty()
+                || fields[0].starts_with('#')
+                || fields[0] == "track"
+                || fields[0] == "browser"
+            {
+                continue;
+            }
+            let (Some(start), Some(end)) = (
+                fields.get(1).and_then(|field| field.parse::<usize>().ok()),
+                fields.get(2).and_then(|field| field.parse::<usize>().ok()),
+            ) else {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "Line {} of the mask file does not have the fields SEQ START END.",
+                    line_no + 1
+                )));
+            };
+            regions
+                .entry(fields[0].to_string())
+                .or_default()
+                .push(start..end);
+        }
+        Self::new(regions, gap_weight)
+    }
+
+    pub fn regions(&self, id: &str) -> Option<&[Range<usize>]> {
+        self.regions.get(id).map(Vec::as_slice)
+    }
+
+    pub fn gap_weight(&self) -> f64 {
+        self.gap_weight
+    }
+
+    /// Whether each of the `len` residues of the sequence `id` is masked.
+    pub fn masked_sites(&self, id: &str, len: usize) -> Vec<bool> {
+        let mut masked = vec![false; len];
+        for range in self.regions(id).unwrap_or_default() {
+            masked[range.start.min(len)..range.end.min(len)].fill(true);
+        }
+        masked
+    }
+
+    /// Fails if a region belongs to a sequence that does not exist or reaches past its end.
+    pub fn check_sequences(&self, sequences: &[Record]) -> Result<()> {
+        for (id, ranges) in &self.regions {
+            let Some(record) = sequences.iter().find(|record| record.id() == id) else {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "There is a masked region for {} but no such sequence.",
+                    id
+                )));
+            };
+            if let Some(range) = ranges.iter().find(|range| range.end > record.seq().len()) {
+                return Err(ParsimonyError::SequenceMismatch(format!(
+                    "The masked region {}-{} of {} reaches past the end of the sequence with {} residues.",
+                    range.start,
+                    range.end,
+                    id,
+                    record.seq().len()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod region_masks_tests {
+    use super::RegionMasks;
+    use bio::io::fasta::Record;
+    use std::collections::HashMap;
+    use std::fs;
+
+    #[test]
+    fn masks_from_bed_file() {
+        let path = std::env::temp_dir().join("indelMaP_masks_from_bed_file.bed");
+        fs::write(
+            &path,
+            "track name=repeats\n# low quality\nA\t0\t2\nA 5 7 repeat\n\nB 1 3\n",
+        )
+        .unwrap();
+        let masks = RegionMasks::from_bed_file(&path, 0.5).unwrap();
+        assert_eq!(masks.regions("A"), Some(&[0..2, 5..7][..]));
+        assert_eq!(masks.regions("B"), Some(&[1..3][..]));
+        assert_eq!(masks.regions("C"), None);
+        assert_eq!(masks.gap_weight(), 0.5);
+        assert_eq!(
+            masks.masked_sites("A", 7),
+            vec![true, true, false, false, false, true, true]
+        );
+        assert_eq!(masks.masked_sites("C", 2), vec![false, false]);
+
+        let sequences = [
+            Record::with_attrs("A", None, b"ACGTACG"),
+            Record::with_attrs("B", None, b"ACG"),
+        ];
+        assert!(masks.check_sequences(&sequences).is_ok());
+        assert!(masks.check_sequences(&sequences[..1]).is_err());
+        assert!(masks
+            .check_sequences(&[sequences[0].clone(), Record::with_attrs("B", None, b"AC")])
+            .is_err());
+
+        fs::write(&path, "A 2\n").unwrap();
+        assert!(RegionMasks::from_bed_file(&path, 0.5).is_err());
+        fs::write(&path, "A 3 3\n").unwrap();
+        assert!(RegionMasks::from_bed_file(&path, 0.5).is_err());
+        fs::remove_file(&path).unwrap();
+        assert!(RegionMasks::new(HashMap::new(), 1.5).is_err());
+    }
+}
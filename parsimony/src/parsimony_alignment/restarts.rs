@@ -0,0 +1,155 @@
+use log::{info, warn};
+use phylo::phylo_info::PhyloInfo;
+use rayon::prelude::*;
+
+use super::parsimony_costs::ParsimonyCosts;
+use super::{pars_align_on_tree_w_options, AlignmentOptions, AlignmentResult, TieBreak};
+use crate::Result;
+
+/// Seed of the traceback choices of a restart, the first restart uses the seed of the options.
+pub fn restart_seed(seed: u64, restart: usize) -> u64 {
+    seed.wrapping_add(restart as u64)
+}
+
+/// Aligns the sequences on the tree `restarts` times with different seeds for the random
+/// choices between equally scoring traceback directions, running the restarts concurrently on
+/// a thread pool. With `threads` set to 0 the number of threads is chosen automatically.
+/// Restart r uses the seed `restart_seed(seed, r)` if the options have a seed, and unseeded
+/// choices otherwise. Returns the alignments and scores of every restart in order, the choices
+/// at one node change the profiles aligned above it, so the total scores can differ.
+pub fn pars_restarts_on_tree(
+    scoring: &(dyn ParsimonyCosts + Sync),
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    restarts: usize,
+    threads: usize,
+) -> Result<Vec<AlignmentResult>> {
+    if options.tie_break != TieBreak::Random {
+        warn!(
+            "The {:?} tie-breaking is deterministic, all the restarts give the same alignment.",
+            options.tie_break
+        );
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    info!(
+        "Running {} restarts of the IndelMAP alignment on {} threads.",
+        restarts,
+        pool.current_num_threads()
+    );
+    pool.install(|| {
+        (0..restarts)
+            .into_par_iter()
+            .map(|restart| {
+                let restart_options = AlignmentOptions {
+                    seed: options.seed.map(|seed| restart_seed(seed, restart)),
+                    progress: None,
+                    observer: None,
+                    ..options.clone()
+                };
+                let result = pars_align_on_tree_w_options(scoring, info, &restart_options)?;
+                info!(
+                    "Restart {} finished with score {}.",
+                    restart,
+                    result.score()
+                );
+                Ok(result)
+            })
+            .collect()
+    })
+}
+
+/// Spread of the total scores of the restarts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScoreSummary {
+    /// Index of the first restart with the lowest score, the one that is kept.
+    pub best: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Sample standard deviation, 0 for a single restart.
+    pub sd: f64,
+}
+
+/// Summary of the scores of the restarts, `None` if there are none.
+pub fn summarize_scores(scores: &[f64]) -> Option<ScoreSummary> {
+    let best = (0..scores.len()).min_by(|&a, &b| scores[a].total_cmp(&scores[b]))?;
+    let mean = scores.iter().sum::<f64>() / scores.len() as f64;
+    let sd = if scores.len() > 1 {
+        (scores
+            .iter()
+            .map(|score| (score - mean).powi(2))
+            .sum::<f64>()
+            / (scores.len() - 1) as f64)
+            .sqrt()
+    } else {
+        0.0
+    };
+    Some(ScoreSummary {
+        best,
+        min: scores[best],
+        max: scores.iter().copied().fold(f64::MIN, f64::max),
+        mean,
+        sd,
+    })
+}
+
+#[cfg(test)]
+mod restarts_tests {
+    use super::{pars_restarts_on_tree, restart_seed, summarize_scores};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn restarts_match_seeded_runs() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACG"),
+            Record::with_attrs("C", None, b"AGTA"),
+            Record::with_attrs("D", None, b"GACTT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(5),
+            ..Default::default()
+        };
+
+        let restarts = pars_restarts_on_tree(&scoring, &info, &options, 4, 2).unwrap();
+        assert_eq!(restarts.len(), 4);
+        for (restart, result) in restarts.iter().enumerate() {
+            let seeded = AlignmentOptions {
+                seed: Some(restart_seed(5, restart)),
+                ..Default::default()
+            };
+            let seeded = pars_align_on_tree_w_options(&scoring, &info, &seeded).unwrap();
+            assert_eq!(result.scores, seeded.scores);
+            for (alignment, seeded) in result.alignments.iter().zip(seeded.alignments.iter()) {
+                assert_eq!(alignment.map_x, seeded.map_x);
+                assert_eq!(alignment.map_y, seeded.map_y);
+            }
+        }
+    }
+
+    #[test]
+    fn summary_of_scores() {
+        let summary = summarize_scores(&[12.0, 10.0, 14.0, 10.0]).unwrap();
+        assert_eq!(summary.best, 1);
+        assert_eq!(summary.min, 10.0);
+        assert_eq!(summary.max, 14.0);
+        assert_eq!(summary.mean, 11.5);
+        assert!((summary.sd - 3.0_f64.sqrt()).abs() < 1e-12);
+        assert_eq!(summarize_scores(&[3.0]).unwrap().sd, 0.0);
+        assert!(summarize_scores(&[]).is_none());
+    }
+}
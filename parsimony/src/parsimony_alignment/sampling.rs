@@ -0,0 +1,271 @@
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx::Internal as Int, NodeIdx::Leaf};
+use web_time::Instant;
+
+use super::constraints::{NodeConstraints, TreeConstraints};
+use super::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
+use super::parsimony_info::ParsimonySiteInfo;
+use super::parsimony_matrices::ParsimonyAlignmentMatrices;
+use super::sequence_weights::{child_cost_factors, WeightedBranchCosts};
+use super::{
+    check_dp_memory, check_single_fill, child_info, format_bytes, leaf_site_info, node_dp_memory,
+    node_matrices, node_rng, node_seed, record_cost_lookup, record_node_dp, report_run_finished,
+    rng_len, seeded_rng_len, AlignmentOptions, AlignmentResult, DpExtraMatrices, TieBreak,
+};
+use crate::{ParsimonyError, Result};
+
+fn sample_rng(
+    options: &AlignmentOptions,
+    sample: usize,
+    node_idx: usize,
+) -> Box<dyn FnMut(usize) -> usize + Send> {
+    match (sample, options.seed) {
+        (0, _) => node_rng(options, node_idx),
+        (_, Some(seed)) => Box::new(seeded_rng_len(node_seed(
+            seed.wrapping_add(sample as u64),
+            node_idx,
+        ))),
+        (_, None) => Box::new(rng_len),
+    }
+}
+
+/// Aligns the sequences on the tree and returns `samples` co-optimal alignments. The first
+/// sample is the alignment given by the usual traceback, for the other samples the traceback
+/// picks randomly between all the optimal directions at every step. The matrices are filled
+/// with the settings and constraints of `options` as in `pars_align_on_tree_w_options`, except
+/// that all the scores are kept and the anchored alignment is not supported.
+/// The DP matrices of every internal node are kept from the first sample, so a node is only
+/// refilled if the profile of one of its children or its constraints differ from the first
+/// sample. This requires memory proportional to the sizes of the DP matrices summed over all the
+/// internal nodes, which is checked against `options.max_memory`.
+pub fn pars_sample_on_tree(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    samples: usize,
+) -> Result<Vec<AlignmentResult>> {
+    check_single_fill(options, "Sampling co-optimal alignments")?;
+    info!("Sampling {} co-optimal IndelMAP alignments.", samples);
+    let tree = &info.tree;
+    let sequence_type = options.data_type.sequence_type(&info.sequences);
+    let leaf_info: Vec<Vec<ParsimonySiteInfo>> = info
+        .sequences
+        .iter()
+        .map(|record| leaf_site_info(record, &sequence_type, options))
+        .collect();
+    let constraints = options
+        .constraints
+        .as_ref()
+        .map(|constraints| TreeConstraints::new(constraints, info))
+        .transpose()?;
+    let mut reference_info = Vec::<Vec<ParsimonySiteInfo>>::new();
+    let mut reference_constraints = vec![NodeConstraints::default(); tree.internals.len()];
+    let mut matrices: Vec<Option<ParsimonyAlignmentMatrices>> =
+        (0..tree.internals.len()).map(|_| None).collect();
+    let mut kept_memory = 0usize;
+    let mut results = Vec::with_capacity(samples);
+    let factors = child_cost_factors(tree, options.sequence_weighting);
+
+    for sample in 0..samples {
+        let start = Instant::now();
+        let mut internal_info = vec![Vec::<ParsimonySiteInfo>::new(); tree.internals.len()];
+        let mut alignments = vec![Alignment::empty(); tree.internals.len()];
+        let mut scores = vec![0.0; tree.internals.len()];
+        let mut refilled = 0;
+        for (processed, &node_idx) in tree.postorder.iter().enumerate() {
+            if let Int(idx) = node_idx {
+                let children = &tree.internals[idx].children;
+                let (x_info, x_branch) = child_info(tree, children[0], &internal_info, &leaf_info);
+                let (y_info, y_branch) = child_info(tree, children[1], &internal_info, &leaf_info);
+                let node_constraints = match &constraints {
+                    Some(constraints) => {
+                        constraints.node_constraints(info, idx, x_info, y_info, &|node| {
+                            &alignments[node]
+                        })?
+                    }
+                    None => NodeConstraints::default(),
+                };
+                let unchanged = sample > 0
+                    && node_constraints == reference_constraints[idx]
+                    && children.iter().all(|child| match child {
+                        Int(child) => internal_info[*child] == reference_info[*child],
+                        Leaf(_) => true,
+                    });
+                let x_scor =
+                    WeightedBranchCosts::new(scoring.get_branch_costs(x_branch), factors[idx].0);
+                let y_scor =
+                    WeightedBranchCosts::new(scoring.get_branch_costs(y_branch), factors[idx].1);
+                let node_start = Instant::now();
+                if let Some(observer) = &options.observer {
+                    observer.0.node_started(node_idx);
+                }
+                let (node_info, alignment, score) = if unchanged && matrices[idx].is_some() {
+                    let pars_mats = matrices[idx].as_ref().unwrap();
+                    let mut rng = sample_rng(options, sample, idx);
+                    pars_mats.sample_traceback(x_info, &x_scor, y_info, &y_scor, &mut *rng)
+                } else {
+                    refilled += 1;
+                    record_cost_lookup(scoring, x_branch, options);
+                    record_cost_lookup(scoring, y_branch, options);
+                    let extra = DpExtraMatrices::new(options, x_scor.site_categories());
+                    check_dp_memory(x_info.len(), y_info.len(), options, extra, true)?;
+                    let tie_break = if sample == 0 {
+                        options.tie_break
+                    } else {
+                        TieBreak::Random
+                    };
+                    let mut pars_mats = node_matrices(
+                        x_info.len(),
+                        y_info.len(),
+                        sample_rng(options, sample, idx),
+                        options,
+                        false,
+                        options.free_end_gaps,
+                    )
+                    .with_tie_break(tie_break);
+                    pars_mats.fill_matrices_w_matches(
+                        x_info,
+                        &x_scor,
+                        y_info,
+                        &y_scor,
+                        &node_constraints,
+                    );
+                    let result = pars_mats.traceback(x_info, y_info);
+                    let (memory, _) =
+                        node_dp_memory(x_info.len(), y_info.len(), options, extra, true);
+                    record_node_dp(options, &mut pars_mats, memory);
+                    if sample == 0 {
+                        kept_memory = kept_memory.saturating_add(memory);
+                        if let Some(max_memory) =
+                            options.max_memory.filter(|&max| kept_memory > max)
+                        {
+                            return Err(ParsimonyError::MemoryLimit(format!(
+                                "Keeping the DP matrices of every node for sampling needs more than {}, the limit of {}.",
+                                format_bytes(kept_memory),
+                                format_bytes(max_memory)
+                            )));
+                        }
+                        matrices[idx] = Some(pars_mats);
+                        reference_constraints[idx] = node_constraints;
+                    }
+                    result
+                };
+                if let Some(observer) = &options.observer {
+                    observer
+                        .0
+                        .node_finished(node_idx, score, node_start.elapsed());
+                }
+                internal_info[idx] = node_info;
+                alignments[idx] = alignment;
+                scores[idx] = score;
+            }
+            if let Some(progress) = &options.progress {
+                progress.report(processed + 1, tree.postorder.len());
+            }
+        }
+        let result = AlignmentResult::new(alignments, scores, tree.postorder.clone());
+        info!(
+            "Sample {} has score {}, {} of {} nodes were refilled.",
+            sample + 1,
+            result.score(),
+            refilled,
+            tree.internals.len()
+        );
+        report_run_finished(options, result.score(), start.elapsed());
+        if sample == 0 {
+            reference_info = internal_info;
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod sampling_tests {
+    use super::pars_sample_on_tree;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{
+        pars_align_on_tree_w_options, AlignmentOptions, AlignmentResult,
+    };
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn samples_are_co_optimal() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"ACGT"),
+            Record::with_attrs("D", None, b"AGT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(3),
+            ..Default::default()
+        };
+
+        let samples = pars_sample_on_tree(&scoring, &info, &options, 20).unwrap();
+        assert_eq!(samples.len(), 20);
+        let AlignmentResult {
+            alignments, scores, ..
+        } = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(samples[0].scores, scores);
+        for (alignment, sampled) in alignments.iter().zip(samples[0].alignments.iter()) {
+            assert_eq!(alignment.map_x, sampled.map_x);
+            assert_eq!(alignment.map_y, sampled.map_y);
+        }
+        // cherries always have the same leaf profiles, so their samples are co-optimal
+        for sample in &samples {
+            assert_eq!(sample.scores[0], scores[0]);
+            assert_eq!(sample.scores[1], scores[1]);
+            for alignment in &sample.alignments {
+                assert_eq!(alignment.map_x.len(), alignment.map_y.len());
+            }
+        }
+        assert_eq!(
+            pars_sample_on_tree(&scoring, &info, &options, 20).unwrap()[7].scores,
+            samples[7].scores
+        );
+    }
+
+    #[test]
+    fn samples_follow_the_band() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGA"),
+            Record::with_attrs("B", None, b"ACTG"),
+            Record::with_attrs("C", None, b"ACGTTA"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(5),
+            band: Some(1),
+            ..Default::default()
+        };
+
+        let samples = pars_sample_on_tree(&scoring, &info, &options, 5).unwrap();
+        let aligned = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(samples[0].scores, aligned.scores);
+
+        let anchored = AlignmentOptions {
+            anchor_length: Some(3),
+            ..Default::default()
+        };
+        assert!(pars_sample_on_tree(&scoring, &info, &anchored, 5).is_err());
+    }
+}
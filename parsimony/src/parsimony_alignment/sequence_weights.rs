@@ -0,0 +1,184 @@
+use phylo::tree::{NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+
+use super::parsimony_costs::BranchParsimonyCosts;
+use super::parsimony_sets::ParsimonySet;
+
+/// Weighting of the input sequences when the profiles of two children are combined.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SequenceWeighting {
+    /// Every child profile counts the same regardless of the number of sequences below it.
+    #[default]
+    None,
+    /// Tree-derived weights of Thompson, Higgins and Gibson. The costs on the branch to a child
+    /// are scaled by the share of its subtree in the weight of the sequences below the node, so
+    /// clades of many similar sequences do not dominate the gap placement.
+    Thg,
+}
+
+/// Weights of the leaves of Thompson, Higgins and Gibson (1994): the length of every branch is
+/// shared equally among the leaves below it and a leaf gets the sum of its shares along the path
+/// from the root. The weights are scaled to a mean of one, a tree without branch lengths gives
+/// every leaf weight one.
+pub fn thg_weights(tree: &Tree) -> Vec<f64> {
+    let mut internal_leaves = vec![0usize; tree.internals.len()];
+    for &node in &tree.postorder {
+        if let Int(idx) = node {
+            internal_leaves[idx] = tree.internals[idx]
+                .children
+                .iter()
+                .map(|&child| match child {
+                    Int(child) => internal_leaves[child],
+                    Leaf(_) => 1,
+                })
+                .sum();
+        }
+    }
+
+    let mut internal_shares = vec![0.0; tree.internals.len()];
+    let mut weights = vec![0.0; tree.leaves.len()];
+    for &node in tree.postorder.iter().rev() {
+        let Int(idx) = node else {
+            continue;
+        };
+        let share = internal_shares[idx];
+        for &child in &tree.internals[idx].children {
+            match child {
+                Int(child) => {
+                    internal_shares[child] =
+                        share + tree.internals[child].blen / internal_leaves[child].max(1) as f64;
+                }
+                Leaf(child) => weights[child] = share + tree.leaves[child].blen,
+            }
+        }
+    }
+
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 || !total.is_finite() {
+        return vec![1.0; tree.leaves.len()];
+    }
+    let scale = weights.len() as f64 / total;
+    weights.iter().map(|weight| weight * scale).collect()
+}
+
+/// Multipliers of the costs on the branches to the two children of every internal node, indexed
+/// like the internal nodes. A child gets twice its share of the leaf weights below the node, so
+/// equally weighted children keep the plain costs.
+pub fn child_cost_factors(tree: &Tree, weighting: SequenceWeighting) -> Vec<(f64, f64)> {
+    if weighting == SequenceWeighting::None {
+        return vec![(1.0, 1.0); tree.internals.len()];
+    }
+    let leaf_weights = thg_weights(tree);
+    let mut subtree_weights = vec![0.0; tree.internals.len()];
+    let mut factors = vec![(1.0, 1.0); tree.internals.len()];
+    for &node in &tree.postorder {
+        let Int(idx) = node else {
+            continue;
+        };
+        let [x, y] = [0, 1].map(|child| match tree.internals[idx].children[child] {
+            Int(child) => subtree_weights[child],
+            Leaf(child) => leaf_weights[child],
+        });
+        subtree_weights[idx] = x + y;
+        if x + y > 0.0 {
+            factors[idx] = (2.0 * x / (x + y), 2.0 * y / (x + y));
+        }
+    }
+    factors
+}
+
+/// Costs of a branch multiplied by the weight factor of the child below it.
+pub(crate) struct WeightedBranchCosts<'a> {
+    costs: &'a dyn BranchParsimonyCosts,
+    factor: f64,
+}
+
+impl<'a> WeightedBranchCosts<'a> {
+    pub(crate) fn new(costs: &'a dyn BranchParsimonyCosts, factor: f64) -> Self {
+        WeightedBranchCosts { costs, factor }
+    }
+}
+
+impl BranchParsimonyCosts for WeightedBranchCosts<'_> {
+    fn match_cost(&self, i: u8, j: u8) -> f64 {
+        self.factor * self.costs.match_cost(i, j)
+    }
+
+    fn gap_open_cost(&self) -> f64 {
+        self.factor * self.costs.gap_open_cost()
+    }
+
+    fn gap_ext_cost(&self) -> f64 {
+        self.factor * self.costs.gap_ext_cost()
+    }
+
+    fn avg_cost(&self) -> f64 {
+        self.factor * self.costs.avg_cost()
+    }
+
+    fn min_match_costs(&self, ancestors: &ParsimonySet, set: &ParsimonySet) -> Vec<f64> {
+        let costs = self.costs.min_match_costs(ancestors, set);
+        if self.factor == 1.0 {
+            return costs;
+        }
+        costs.into_iter().map(|cost| self.factor * cost).collect()
+    }
+
+    fn site_categories(&self) -> usize {
+        self.costs.site_categories()
+    }
+
+    fn category_min_match_costs(
+        &self,
+        category: usize,
+        ancestors: &ParsimonySet,
+        set: &ParsimonySet,
+        costs: &mut Vec<f64>,
+    ) {
+        self.costs
+            .category_min_match_costs(category, ancestors, set, costs);
+        if self.factor != 1.0 {
+            costs.iter_mut().for_each(|cost| *cost *= self.factor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod sequence_weights_tests {
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    use super::{child_cost_factors, thg_weights, SequenceWeighting};
+
+    fn tree(blens: [f64; 4]) -> Tree {
+        let sequences = ["A", "B", "C"].map(|id| Record::with_attrs(id, None, b"ACGT"));
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), blens[0], blens[1]);
+        tree.add_parent(1, I(0), L(2), blens[2], blens[3]);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    #[test]
+    fn thg() {
+        // Raw weights 0.1 + 0.5, 0.1 + 0.5 and 1.8, which sum to 3.
+        let weights = thg_weights(&tree([0.1, 0.1, 1.0, 1.8]));
+        assert_relative_eq!(weights[0], 0.6);
+        assert_relative_eq!(weights[1], 0.6);
+        assert_relative_eq!(weights[2], 1.8);
+
+        let factors = child_cost_factors(&tree([0.1, 0.1, 1.0, 1.8]), SequenceWeighting::Thg);
+        assert_relative_eq!(factors[0].0, 1.0);
+        assert_relative_eq!(factors[0].1, 1.0);
+        assert_relative_eq!(factors[1].0, 0.8);
+        assert_relative_eq!(factors[1].1, 1.2);
+
+        assert_eq!(thg_weights(&tree([0.0; 4])), vec![1.0; 3]);
+        assert_eq!(
+            child_cost_factors(&tree([0.1, 0.1, 1.0, 1.8]), SequenceWeighting::None),
+            vec![(1.0, 1.0); 2]
+        );
+    }
+}
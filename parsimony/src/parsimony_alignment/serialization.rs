@@ -0,0 +1,247 @@
+//! Serde support for the types of the `phylo` crate that appear in the results, for use with
+//! `#[serde(with = "...")]` on fields that hold them. The types of this crate derive
+//! `Serialize` and `Deserialize` directly when the `serde` feature is enabled, the sites of the
+//! profiles through `SiteInfoRepr`.
+
+use phylo::alignment::{Alignment, Mapping};
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::parsimony_info::{ParsimonySiteInfo, SiteFlag};
+use super::parsimony_sets::ParsimonySet;
+
+/// Gap state of a serialised site.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GapRepr {
+    /// The site is a gap that later alignments keep.
+    Fixed,
+    /// A gap against the site opens a gap.
+    Open,
+    /// A gap against the site extends a gap.
+    Ext,
+    /// The site holds a character.
+    NoGap,
+}
+
+/// Serialised form of a `ParsimonySiteInfo`, `{"set": [...], "gap": "NoGap", "gap_weight": 1.0,
+/// "site_category": null}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SiteInfoRepr {
+    /// Possible characters of the site.
+    pub set: Vec<u8>,
+    pub gap: GapRepr,
+    pub gap_weight: f64,
+    #[serde(default)]
+    pub site_category: Option<u8>,
+}
+
+impl From<ParsimonySiteInfo> for SiteInfoRepr {
+    fn from(site: ParsimonySiteInfo) -> Self {
+        SiteInfoRepr {
+            set: site.set.into(),
+            gap: match site.flag {
+                SiteFlag::GapFixed => GapRepr::Fixed,
+                SiteFlag::GapOpen => GapRepr::Open,
+                SiteFlag::GapExt => GapRepr::Ext,
+                SiteFlag::NoGap => GapRepr::NoGap,
+            },
+            gap_weight: site.gap_weight,
+            site_category: site.site_category,
+        }
+    }
+}
+
+impl From<SiteInfoRepr> for ParsimonySiteInfo {
+    fn from(repr: SiteInfoRepr) -> Self {
+        let flag = match repr.gap {
+            GapRepr::Fixed => SiteFlag::GapFixed,
+            GapRepr::Open => SiteFlag::GapOpen,
+            GapRepr::Ext => SiteFlag::GapExt,
+            GapRepr::NoGap => SiteFlag::NoGap,
+        };
+        ParsimonySiteInfo::from_set(ParsimonySet::from(repr.set), flag)
+            .with_gap_weight(repr.gap_weight)
+            .with_site_category(repr.site_category)
+    }
+}
+
+#[derive(Serialize)]
+struct AlignmentRef<'a> {
+    map_x: &'a Mapping,
+    map_y: &'a Mapping,
+}
+
+#[derive(Deserialize)]
+struct AlignmentRepr {
+    map_x: Mapping,
+    map_y: Mapping,
+}
+
+impl<'a> From<&'a Alignment> for AlignmentRef<'a> {
+    fn from(alignment: &'a Alignment) -> Self {
+        AlignmentRef {
+            map_x: &alignment.map_x,
+            map_y: &alignment.map_y,
+        }
+    }
+}
+
+impl From<AlignmentRepr> for Alignment {
+    fn from(repr: AlignmentRepr) -> Self {
+        Alignment::new(repr.map_x, repr.map_y)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum NodeRepr {
+    Internal(usize),
+    Leaf(usize),
+}
+
+impl From<&NodeIdx> for NodeRepr {
+    fn from(node: &NodeIdx) -> Self {
+        match *node {
+            Int(idx) => NodeRepr::Internal(idx),
+            Leaf(idx) => NodeRepr::Leaf(idx),
+        }
+    }
+}
+
+impl From<NodeRepr> for NodeIdx {
+    fn from(node: NodeRepr) -> Self {
+        match node {
+            NodeRepr::Internal(idx) => Int(idx),
+            NodeRepr::Leaf(idx) => Leaf(idx),
+        }
+    }
+}
+
+/// An alignment as its two mappings, `{"map_x": [...], "map_y": [...]}`.
+pub mod alignment {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        alignment: &Alignment,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        AlignmentRef::from(alignment).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Alignment, D::Error> {
+        Ok(AlignmentRepr::deserialize(deserializer)?.into())
+    }
+}
+
+/// The alignments of all the internal nodes as a sequence of `alignment`s.
+pub mod alignments {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        alignments: &[Alignment],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(alignments.iter().map(AlignmentRef::from))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Alignment>, D::Error> {
+        Ok(Vec::<AlignmentRepr>::deserialize(deserializer)?
+            .into_iter()
+            .map(Alignment::from)
+            .collect())
+    }
+}
+
+/// Tree nodes as `{"Internal": idx}` or `{"Leaf": idx}`.
+pub mod nodes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(nodes: &[NodeIdx], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(nodes.iter().map(NodeRepr::from))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<NodeIdx>, D::Error> {
+        Ok(Vec::<NodeRepr>::deserialize(deserializer)?
+            .into_iter()
+            .map(NodeIdx::from)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod serialization_tests {
+    use crate::parsimony_alignment::bootstrap::BootstrapSupport;
+    use crate::parsimony_alignment::parsimony_info::{ParsimonySiteInfo, SiteFlag::GapOpen};
+    use crate::parsimony_alignment::{AlignmentResult, TieBreak};
+    use phylo::alignment::Alignment;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Alignments(#[serde(with = "super::alignments")] Vec<Alignment>);
+
+    #[test]
+    fn alignments_round_trip() {
+        let alignments = Alignments(vec![
+            Alignment::new(vec![Some(0), None, Some(1)], vec![Some(0), Some(1), None]),
+            Alignment::empty(),
+        ]);
+        let json = serde_json::to_string(&alignments).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"map_x":[0,null,1],"map_y":[0,1,null]},{"map_x":[],"map_y":[]}]"#
+        );
+        let Alignments(parsed) = serde_json::from_str(&json).unwrap();
+        for (alignment, parsed) in alignments.0.iter().zip(parsed.iter()) {
+            assert_eq!(alignment.map_x, parsed.map_x);
+            assert_eq!(alignment.map_y, parsed.map_y);
+        }
+    }
+
+    #[test]
+    fn results_round_trip() {
+        let site = ParsimonySiteInfo::new([b'A', b'C'], GapOpen).with_gap_weight(0.5);
+        let json = serde_json::to_string(&site).unwrap();
+        assert_eq!(
+            json,
+            r#"{"set":[65,67],"gap":"Open","gap_weight":0.5,"site_category":null}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ParsimonySiteInfo>(&json).unwrap(),
+            site
+        );
+
+        let bootstrap = BootstrapSupport {
+            score: 8.0,
+            replicate_scores: vec![7.5, 8.0],
+            support: vec![Some(1.0), None],
+        };
+        let json = serde_json::to_string(&bootstrap).unwrap();
+        assert_eq!(
+            serde_json::from_str::<BootstrapSupport>(&json).unwrap(),
+            bootstrap
+        );
+        assert_eq!(
+            serde_json::to_string(&TieBreak::GapInX).unwrap(),
+            r#""GapInX""#
+        );
+
+        let result = AlignmentResult {
+            alignments: vec![Alignment::new(vec![Some(0), None], vec![Some(0), Some(1)])],
+            scores: vec![2.0],
+            order: vec![L(0), L(1), I(0)],
+        };
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            json,
+            r#"{"alignments":[{"map_x":[0,null],"map_y":[0,1]}],"scores":[2.0],"order":[{"Leaf":0},{"Leaf":1},{"Internal":0}]}"#
+        );
+        let parsed: AlignmentResult = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.scores, result.scores);
+        assert_eq!(parsed.order, result.order);
+        assert_eq!(parsed.alignments[0].map_x, result.alignments[0].map_x);
+    }
+}
@@ -0,0 +1,127 @@
+use bio::io::fasta::Record;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+/// Length of the k-mers of the strand test, long enough that they are rarely shared by chance.
+const STRAND_KMER: usize = 8;
+
+fn complement(nucleotide: u8) -> u8 {
+    let complement = match nucleotide.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        other => other,
+    };
+    if nucleotide.is_ascii_lowercase() {
+        complement.to_ascii_lowercase()
+    } else {
+        complement
+    }
+}
+
+/// Reverse complement of a DNA sequence. Ambiguity codes are complemented, gaps and the case of
+/// the residues are kept.
+pub fn reverse_complement(record: &Record) -> Record {
+    let seq: Vec<u8> = record.seq().iter().rev().map(|&c| complement(c)).collect();
+    Record::with_attrs(record.id(), record.desc(), &seq)
+}
+
+fn residues(sequence: &[u8]) -> Vec<u8> {
+    sequence
+        .iter()
+        .filter(|&&c| c != b'-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn shared_kmers(sequence: &[u8], reference: &HashSet<&[u8]>) -> usize {
+    sequence
+        .windows(STRAND_KMER)
+        .filter(|kmer| reference.contains(kmer))
+        .count()
+}
+
+/// Indices of the DNA sequences that were most likely read from the opposite strand than the
+/// rest. Every sequence is compared with the longest one, the reference, and is on the
+/// opposite strand if its reverse complement shares more k-mers with the reference. If that
+/// holds for most of the sequences, the reference and the sequences on its strand are the ones
+/// reported instead. Sequences that share no k-mers with the reference on either strand are kept
+/// as they are. Linear in the total length of the sequences.
+pub fn reverse_strand_sequences(sequences: &[Record]) -> Vec<usize> {
+    let Some(reference) =
+        (0..sequences.len()).max_by_key(|&idx| residues(sequences[idx].seq()).len())
+    else {
+        return Vec::new();
+    };
+    let reference_seq = residues(sequences[reference].seq());
+    let reference_kmers: HashSet<&[u8]> = reference_seq.windows(STRAND_KMER).collect();
+    let mut same = vec![reference];
+    let mut opposite = Vec::new();
+    for (idx, record) in sequences.iter().enumerate() {
+        if idx == reference {
+            continue;
+        }
+        let forward = shared_kmers(&residues(record.seq()), &reference_kmers);
+        let reverse = shared_kmers(
+            &residues(reverse_complement(record).seq()),
+            &reference_kmers,
+        );
+        match reverse.cmp(&forward) {
+            Ordering::Greater => opposite.push(idx),
+            Ordering::Less => same.push(idx),
+            Ordering::Equal => {}
+        }
+    }
+    if opposite.len() > same.len() {
+        same.sort_unstable();
+        same
+    } else {
+        opposite
+    }
+}
+
+#[cfg(test)]
+mod strand_tests {
+    use super::{reverse_complement, reverse_strand_sequences};
+    use bio::io::fasta::Record;
+
+    #[test]
+    fn complement_sequence() {
+        let record = reverse_complement(&Record::with_attrs("A", Some("d"), b"AC-gtRN"));
+        assert_eq!(record.seq(), b"NYac-GT");
+        assert_eq!(record.desc(), Some("d"));
+    }
+
+    #[test]
+    fn detect_reverse_strand() {
+        let gene = b"ATGGCGTACGATTCCGGAAGTCTTACCGGATAGCAATTCGGCATCAG";
+        let mut sequences = vec![
+            Record::with_attrs("A", None, gene),
+            Record::with_attrs("B", None, &gene[2..]),
+            Record::with_attrs("C", None, &gene[..40]),
+        ];
+        assert!(reverse_strand_sequences(&sequences).is_empty());
+        sequences[1] = reverse_complement(&sequences[1]);
+        assert_eq!(reverse_strand_sequences(&sequences), [1]);
+        sequences.push(Record::with_attrs("D", None, b"CCCCCCCCCCCC"));
+        assert_eq!(reverse_strand_sequences(&sequences), [1]);
+
+        // the longest sequence is the one on the opposite strand
+        let sequences = vec![
+            Record::with_attrs("A", None, &gene[..40]),
+            reverse_complement(&Record::with_attrs("B", None, gene)),
+            Record::with_attrs("C", None, &gene[3..]),
+        ];
+        assert_eq!(reverse_strand_sequences(&sequences), [1]);
+        assert!(reverse_strand_sequences(&[]).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use bio::io::fasta::{self, Record};
+use log::info;
+use phylo::sequences::SequenceType;
+use phylo::tree::Tree;
+use std::collections::HashSet;
+use std::io::Read;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use super::parsimony_costs::ParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::{align_on_tree, leaf_site_info, AlignmentOptions, AlignmentResult};
+use crate::{ParsimonyError, Result};
+
+/// Leaf profiles read from a FASTA stream one record at a time, each record is dropped as soon
+/// as its parsimony sets are built so that only the profiles are kept in memory. The leaves are
+/// numbered in the order of the records, like the leaves of a tree built from the sequences.
+#[derive(Debug)]
+pub struct LeafProfiles {
+    ids: Vec<String>,
+    sequence_type: SequenceType,
+    profiles: Vec<Vec<ParsimonySiteInfo>>,
+}
+
+impl LeafProfiles {
+    /// Reads the profiles with the data type, alphabet, recoding and gap profiles of `options`.
+    /// The data type `Auto` is decided by the first record, the streamed sequences cannot be
+    /// looked at together.
+    pub fn read(reader: impl Read, options: &AlignmentOptions) -> Result<Self> {
+        let mut ids = Vec::new();
+        let mut seen = HashSet::new();
+        let mut sequence_type = None;
+        let mut profiles = Vec::new();
+        for record in fasta::Reader::new(reader).records() {
+            let record = record?;
+            if !seen.insert(record.id().to_string()) {
+                return Err(ParsimonyError::InvalidInput(format!(
+                    "Sequence {} appears more than once in the FASTA input.",
+                    record.id()
+                )));
+            }
+            let sequence_type = sequence_type.get_or_insert_with(|| {
+                options
+                    .data_type
+                    .sequence_type(std::slice::from_ref(&record))
+            });
+            profiles.push(leaf_site_info(&record, sequence_type, options));
+            ids.push(record.id().to_string());
+        }
+        let Some(sequence_type) = sequence_type else {
+            return Err(ParsimonyError::InvalidInput(
+                "There are no sequences in the FASTA input.".to_string(),
+            ));
+        };
+        info!("Read the profiles of {} sequences.", ids.len());
+        Ok(LeafProfiles {
+            ids,
+            sequence_type,
+            profiles,
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &Path, options: &AlignmentOptions) -> Result<Self> {
+        Self::read(std::fs::File::open(path)?, options)
+    }
+
+    /// Ids of the sequences in the order of the leaves.
+    pub fn ids(&self) -> &[String] {
+        &self.ids
+    }
+
+    pub fn sequence_type(&self) -> &SequenceType {
+        &self.sequence_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.profiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+
+    pub fn profile(&self, leaf: usize) -> &[ParsimonySiteInfo] {
+        &self.profiles[leaf]
+    }
+
+    /// Records with the ids of the sequences and no residues, enough for building the tree of
+    /// the leaves e.g. with `tree_from_newick`.
+    pub fn id_records(&self) -> Vec<Record> {
+        self.ids
+            .iter()
+            .map(|id| Record::with_attrs(id, None, b""))
+            .collect()
+    }
+}
+
+/// Aligns the streamed leaf profiles on the tree, whose leaves must be numbered like the
+/// profiles. The profiles are moved into the alignment leaf by leaf. The aligned sequences are
+/// compiled from the result with `AlignmentResult::msa` once the sequences are read again.
+/// Alignment constraints refer to the residues of the sequences and cannot be used here.
+pub fn pars_align_on_tree_w_profiles(
+    scoring: &dyn ParsimonyCosts,
+    tree: &Tree,
+    profiles: LeafProfiles,
+    options: &AlignmentOptions,
+) -> Result<AlignmentResult> {
+    if tree.leaves.len() != profiles.len() {
+        return Err(ParsimonyError::TreeSequenceMismatch(format!(
+            "The tree has {} leaves but {} sequences were read.",
+            tree.leaves.len(),
+            profiles.len()
+        )));
+    }
+    if options.constraints.is_some() {
+        return Err(ParsimonyError::InvalidParameters(
+            "Alignment constraints cannot be used with streamed leaf profiles.".to_string(),
+        ));
+    }
+    let mut profiles = profiles.profiles;
+    align_on_tree(
+        scoring,
+        tree,
+        |idx| std::mem::take(&mut profiles[idx]),
+        None,
+        options,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod streaming_tests {
+    use super::{pars_align_on_tree_w_profiles, LeafProfiles};
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use crate::ParsimonyError;
+    use assert_matches::assert_matches;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::sequences::SequenceType;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    const FASTA: &[u8] = b">A\nAACTGAC\n>B\nACG\nTA\n>C\nAACTTAC\n";
+
+    fn tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, I(0), L(2), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    #[test]
+    fn streamed_profiles_align_as_records() {
+        let options = AlignmentOptions {
+            seed: Some(5),
+            ..Default::default()
+        };
+        let profiles = LeafProfiles::read(FASTA, &options).unwrap();
+        assert_eq!(profiles.ids(), ["A", "B", "C"]);
+        assert!(matches!(profiles.sequence_type(), SequenceType::DNA));
+        assert_eq!(profiles.profile(1).len(), 5);
+
+        let sequences = [
+            Record::with_attrs("A", None, b"AACTGAC"),
+            Record::with_attrs("B", None, b"ACGTA"),
+            Record::with_attrs("C", None, b"AACTTAC"),
+        ];
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let tree = tree(&profiles.id_records());
+        let streamed = pars_align_on_tree_w_profiles(&scoring, &tree, profiles, &options).unwrap();
+
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let eager = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(streamed.score(), eager.score());
+        assert_eq!(streamed.msa(&info), eager.msa(&info));
+    }
+
+    #[test]
+    fn reject_bad_input() {
+        let options = AlignmentOptions::default();
+        assert_matches!(
+            LeafProfiles::read(&b">A\nAC\n>A\nAG\n"[..], &options),
+            Err(ParsimonyError::InvalidInput(_))
+        );
+        assert_matches!(
+            LeafProfiles::read(&b""[..], &options),
+            Err(ParsimonyError::InvalidInput(_))
+        );
+        let profiles = LeafProfiles::read(&b">A\nAC\n>B\nAG\n"[..], &options).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let sequences = [
+            Record::with_attrs("A", None, b""),
+            Record::with_attrs("B", None, b""),
+            Record::with_attrs("C", None, b""),
+        ];
+        assert_matches!(
+            pars_align_on_tree_w_profiles(&scoring, &tree(&sequences), profiles, &options),
+            Err(ParsimonyError::TreeSequenceMismatch(_))
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx::Internal as Int, NodeIdx::Leaf};
+use web_time::Instant;
+
+use super::constraints::{NodeConstraints, TreeConstraints};
+use super::parsimony_costs::{BranchParsimonyCosts, ParsimonyCosts};
+use super::parsimony_info::ParsimonySiteInfo;
+use super::sequence_weights::{child_cost_factors, WeightedBranchCosts};
+use super::{
+    check_dp_memory, check_single_fill, child_info, leaf_site_info, node_dp_memory, node_matrices,
+    node_rng, pars_align_w_matches, record_cost_lookup, record_node_dp, report_run_finished,
+    AlignmentOptions, AlignmentResult, DpExtraMatrices,
+};
+use crate::Result;
+
+/// The best scoring alternative alignments of the children of an internal node, in order of
+/// increasing score.
+pub type NodeAlternatives = Vec<(Alignment, f64)>;
+
+/// Aligns the sequences on the tree with the settings and constraints of `options` and
+/// additionally enumerates the `k` best scoring distinct alignments at the root. The
+/// alternatives are computed for the profiles of the children of the root in the alignment on
+/// the tree, and the first alternative is an optimal alignment, though not necessarily the one
+/// picked by the traceback. The DP matrices of the root keep all their scores for this and the
+/// anchored alignment is not supported.
+pub fn pars_suboptimal_on_tree(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    options: &AlignmentOptions,
+    k: usize,
+) -> Result<(AlignmentResult, NodeAlternatives)> {
+    check_single_fill(options, "Enumerating suboptimal alignments")?;
+    info!("Enumerating the {} best alignments at the root.", k);
+    let start = Instant::now();
+    let tree = &info.tree;
+    let sequence_type = options.data_type.sequence_type(&info.sequences);
+    let leaf_info: Vec<Vec<ParsimonySiteInfo>> = info
+        .sequences
+        .iter()
+        .map(|record| leaf_site_info(record, &sequence_type, options))
+        .collect();
+    let constraints = options
+        .constraints
+        .as_ref()
+        .map(|constraints| TreeConstraints::new(constraints, info))
+        .transpose()?;
+    let mut internal_info = vec![Vec::<ParsimonySiteInfo>::new(); tree.internals.len()];
+    let mut alignments = vec![Alignment::empty(); tree.internals.len()];
+    let mut scores = vec![0.0; tree.internals.len()];
+    let mut alternatives = NodeAlternatives::new();
+    let factors = child_cost_factors(tree, options.sequence_weighting);
+
+    for (processed, &node_idx) in tree.postorder.iter().enumerate() {
+        if let Int(idx) = node_idx {
+            let children = &tree.internals[idx].children;
+            let (x_info, x_branch) = child_info(tree, children[0], &internal_info, &leaf_info);
+            let (y_info, y_branch) = child_info(tree, children[1], &internal_info, &leaf_info);
+            let (x_scoring, y_scoring) = (
+                &WeightedBranchCosts::new(scoring.get_branch_costs(x_branch), factors[idx].0),
+                &WeightedBranchCosts::new(scoring.get_branch_costs(y_branch), factors[idx].1),
+            );
+            record_cost_lookup(scoring, x_branch, options);
+            record_cost_lookup(scoring, y_branch, options);
+            let node_constraints = match &constraints {
+                Some(constraints) => {
+                    constraints
+                        .node_constraints(info, idx, x_info, y_info, &|node| &alignments[node])?
+                }
+                None => NodeConstraints::default(),
+            };
+            let node_start = Instant::now();
+            if let Some(observer) = &options.observer {
+                observer.0.node_started(node_idx);
+            }
+            let (node_info, alignment, score) = if node_idx != tree.root {
+                pars_align_w_matches(
+                    x_info,
+                    x_scoring,
+                    y_info,
+                    y_scoring,
+                    node_rng(options, idx),
+                    options,
+                    &node_constraints,
+                )?
+            } else {
+                let extra = DpExtraMatrices::new(options, x_scoring.site_categories());
+                check_dp_memory(x_info.len(), y_info.len(), options, extra, true)?;
+                let mut pars_mats = node_matrices(
+                    x_info.len(),
+                    y_info.len(),
+                    node_rng(options, idx),
+                    options,
+                    false,
+                    options.free_end_gaps,
+                );
+                pars_mats.fill_matrices_w_matches(
+                    x_info,
+                    x_scoring,
+                    y_info,
+                    y_scoring,
+                    &node_constraints,
+                );
+                alternatives = pars_mats
+                    .k_best_traceback(x_info, x_scoring, y_info, y_scoring, k)
+                    .into_iter()
+                    .map(|(_, alignment, score)| (alignment, score))
+                    .collect();
+                let result = pars_mats.traceback(x_info, y_info);
+                let (memory, _) = node_dp_memory(x_info.len(), y_info.len(), options, extra, true);
+                record_node_dp(options, &mut pars_mats, memory);
+                if let Some((_, worst)) = alternatives.last() {
+                    info!(
+                        "The root has {} alternative(s) with scores from {} to {}.",
+                        alternatives.len(),
+                        result.2,
+                        worst
+                    );
+                }
+                result
+            };
+            if let Some(observer) = &options.observer {
+                observer
+                    .0
+                    .node_finished(node_idx, score, node_start.elapsed());
+            }
+            internal_info[idx] = node_info;
+            alignments[idx] = alignment;
+            scores[idx] = score;
+        }
+        if let Some(progress) = &options.progress {
+            progress.report(processed + 1, tree.postorder.len());
+        }
+    }
+    let result = AlignmentResult::new(alignments, scores, tree.postorder.clone());
+    report_run_finished(options, result.score(), start.elapsed());
+    Ok((result, alternatives))
+}
+
+#[cfg(test)]
+mod suboptimal_tests {
+    use super::pars_suboptimal_on_tree;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    #[test]
+    fn root_alternatives() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AACT"),
+            Record::with_attrs("B", None, b"AC"),
+            Record::with_attrs("C", None, b"ACGT"),
+            Record::with_attrs("D", None, b"AGT"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions {
+            seed: Some(5),
+            ..Default::default()
+        };
+
+        let (result, alternatives) = pars_suboptimal_on_tree(&scoring, &info, &options, 4).unwrap();
+        let expected = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        assert_eq!(result.scores, expected.scores);
+        for (alignment, expected) in result.alignments.iter().zip(expected.alignments.iter()) {
+            assert_eq!(alignment.map_x, expected.map_x);
+            assert_eq!(alignment.map_y, expected.map_y);
+        }
+        assert_eq!(alternatives.len(), 4);
+        assert_relative_eq!(
+            alternatives[0].1,
+            result.scores[Into::<usize>::into(info.tree.root)]
+        );
+        assert!(alternatives.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+}
@@ -0,0 +1,832 @@
+use bio::io::fasta::Record;
+use log::{info, warn};
+use phylo::tree::Tree;
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+use super::guide_tree::{midpoint_branch, rooted_tree, Adjacency};
+use crate::{ParsimonyError, Result};
+
+/// Where an unrooted input tree is rooted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RootingMethod {
+    /// Midpoint of the longest leaf to leaf path.
+    #[default]
+    Midpoint,
+    /// Middle of the longest branch.
+    LongestBranch,
+}
+
+impl fmt::Display for RootingMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootingMethod::Midpoint => write!(f, "midpoint"),
+            RootingMethod::LongestBranch => write!(f, "longest branch"),
+        }
+    }
+}
+
+/// Rules for pairing the leaf labels of the tree with the sequence ids, both are turned into a
+/// key and a label matches the sequence with the same key. The sequence ids end at the first
+/// space of the FASTA header, with `ignore_whitespace` the whole header is used instead.
+#[derive(Clone, Debug, Default)]
+pub struct NameMatching {
+    /// Compare the names case-insensitively.
+    pub ignore_case: bool,
+    /// Remove all whitespace from the names.
+    pub ignore_whitespace: bool,
+    /// Cut the leaf labels at their first space, like the FASTA reader does with the headers.
+    pub truncate_at_space: bool,
+    /// Pattern whose matches are removed from the names before any other rule is applied.
+    pub strip: Option<Regex>,
+}
+
+impl NameMatching {
+    /// Sets the pattern that is removed from the names, fails on an invalid regular expression.
+    pub fn with_strip_pattern(mut self, pattern: &str) -> Result<Self> {
+        self.strip = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// Whether the names have to match exactly.
+    pub fn is_exact(&self) -> bool {
+        !self.ignore_case
+            && !self.ignore_whitespace
+            && !self.truncate_at_space
+            && self.strip.is_none()
+    }
+
+    /// Key of a leaf label or a sequence id under these rules.
+    pub fn key(&self, name: &str) -> String {
+        let mut key = match &self.strip {
+            Some(pattern) => pattern.replace_all(name, "").into_owned(),
+            None => name.to_string(),
+        };
+        if self.truncate_at_space {
+            if let Some(end) = key.trim_start().find(char::is_whitespace) {
+                key = key.trim_start()[..end].to_string();
+            }
+        }
+        if self.ignore_whitespace {
+            key.retain(|c| !c.is_whitespace());
+        }
+        if self.ignore_case {
+            key = key.to_lowercase();
+        }
+        key
+    }
+
+    fn sequence_key(&self, record: &Record) -> String {
+        match record.desc() {
+            Some(desc) if self.ignore_whitespace => self.key(&format!("{} {}", record.id(), desc)),
+            _ => self.key(record.id()),
+        }
+    }
+}
+
+/// Node of a parsed newick tree.
+#[derive(Debug)]
+struct NewickNode {
+    name: String,
+    blen: f64,
+    children: Vec<NewickNode>,
+}
+
+impl NewickNode {
+    fn leaf_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        if self.children.is_empty() {
+            names.push(&self.name);
+        }
+        for child in &self.children {
+            child.leaf_names(names);
+        }
+    }
+
+    /// Replaces the leaf labels by the ids of the sequences they match, the labels without a
+    /// matching sequence are kept.
+    fn rename_leaves(&mut self, ids: &HashMap<String, &str>, matching: &NameMatching) {
+        if self.children.is_empty() {
+            if let Some(id) = ids.get(&matching.key(&self.name)) {
+                self.name = id.to_string();
+            }
+        }
+        for child in self.children.iter_mut() {
+            child.rename_leaves(ids, matching);
+        }
+    }
+
+    fn to_newick(&self) -> String {
+        let mut newick = String::new();
+        if !self.children.is_empty() {
+            let children: Vec<String> = self.children.iter().map(|c| c.to_newick()).collect();
+            newick = format!("({})", children.join(","));
+        }
+        if self
+            .name
+            .contains(|c: char| c.is_whitespace() || ":,();[]'".contains(c))
+        {
+            newick.push_str(&format!("'{}'", self.name));
+        } else {
+            newick.push_str(&self.name);
+        }
+        format!("{}:{}", newick, self.blen)
+    }
+}
+
+struct NewickParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl NewickParser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        loop {
+            match self.text.get(self.pos) {
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                // comments, e.g. support values written as [100]
+                Some(b'[') => {
+                    while self.pos < self.text.len() && self.text[self.pos] != b']' {
+                        self.pos += 1;
+                    }
+                    self.pos += 1;
+                }
+                c => return c.copied(),
+            }
+        }
+    }
+
+    fn label(&mut self) -> String {
+        if self.peek() == Some(b'\'') {
+            let start = self.pos + 1;
+            self.pos = start;
+            while self.pos < self.text.len() && self.text[self.pos] != b'\'' {
+                self.pos += 1;
+            }
+            let label = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+            self.pos += 1;
+            return label;
+        }
+        let start = self.pos;
+        while self.pos < self.text.len() && !b":,();[".contains(&self.text[self.pos]) {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.text[start..self.pos])
+            .trim()
+            .to_string()
+    }
+
+    fn node(&mut self) -> Result<NewickNode> {
+        let mut children = Vec::new();
+        if self.peek() == Some(b'(') {
+            self.pos += 1;
+            loop {
+                children.push(self.node()?);
+                match self.peek() {
+                    Some(b',') => self.pos += 1,
+                    Some(b')') => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => {
+                        return Err(ParsimonyError::InvalidInput(format!(
+                            "Malformed newick tree at position {}.",
+                            self.pos
+                        )))
+                    }
+                }
+            }
+        }
+        let name = self.label();
+        let mut blen = 0.0;
+        if self.peek() == Some(b':') {
+            self.pos += 1;
+            let length = self.label();
+            blen = match length.parse() {
+                Ok(blen) => blen,
+                Err(_) => {
+                    return Err(ParsimonyError::InvalidInput(format!(
+                        "Invalid branch length \"{}\" in the newick tree.",
+                        length
+                    )))
+                }
+            };
+        }
+        Ok(NewickNode {
+            name,
+            blen,
+            children,
+        })
+    }
+}
+
+/// Parses the first tree of a newick string.
+fn parse_newick(newick: &str) -> Result<NewickNode> {
+    let mut parser = NewickParser {
+        text: newick.as_bytes(),
+        pos: 0,
+    };
+    let root = parser.node()?;
+    if parser.peek() != Some(b';') {
+        return Err(ParsimonyError::InvalidInput(format!(
+            "Malformed newick tree at position {}.",
+            parser.pos
+        )));
+    }
+    Ok(root)
+}
+
+/// Parses the first tree of a newick string and renames its leaves to the ids of the sequences
+/// they match under the name matching rules.
+fn parse_matched_newick(
+    newick: &str,
+    sequences: &[Record],
+    matching: &NameMatching,
+) -> Result<NewickNode> {
+    let mut top = parse_newick(newick)?;
+    if matching.is_exact() {
+        return Ok(top);
+    }
+    let mut ids = HashMap::new();
+    for record in sequences {
+        if let Some(other) = ids.insert(matching.sequence_key(record), record.id()) {
+            return Err(ParsimonyError::TreeSequenceMismatch(format!(
+                "The sequences {} and {} cannot be told apart with the name matching rules.",
+                other,
+                record.id()
+            )));
+        }
+    }
+    top.rename_leaves(&ids, matching);
+    Ok(top)
+}
+
+/// Whether the first tree of a newick string is unrooted, i.e. has three subtrees at the top
+/// level. The alignment needs a rooted binary tree.
+pub fn is_unrooted_newick(newick: &str) -> Result<bool> {
+    Ok(parse_newick(newick)?.children.len() == 3)
+}
+
+fn has_polytomy(node: &NewickNode) -> bool {
+    node.children.len() > 2 || node.children.iter().any(has_polytomy)
+}
+
+/// Adds the subtree below `node` to the adjacency and returns the index of its top node.
+fn add_to_adjacency(
+    adjacency: &mut Adjacency,
+    leaf_ids: &HashMap<&str, usize>,
+    node: &NewickNode,
+) -> Result<usize> {
+    let idx = if node.children.is_empty() {
+        match leaf_ids.get(node.name.as_str()) {
+            Some(&idx) if adjacency[idx].is_empty() => idx,
+            Some(_) => {
+                return Err(ParsimonyError::TreeSequenceMismatch(format!(
+                    "The leaf {} appears twice in the tree.",
+                    node.name
+                )))
+            }
+            None => {
+                return Err(ParsimonyError::TreeSequenceMismatch(format!(
+                    "The leaf {} of the tree has no sequence.",
+                    node.name
+                )))
+            }
+        }
+    } else {
+        adjacency.push(Vec::new());
+        adjacency.len() - 1
+    };
+    for child in &node.children {
+        let child_idx = add_to_adjacency(adjacency, leaf_ids, child)?;
+        adjacency[idx].push((child_idx, child.blen));
+        adjacency[child_idx].push((idx, child.blen));
+    }
+    Ok(idx)
+}
+
+fn longest_branch(adjacency: &Adjacency) -> ((usize, f64), (usize, f64)) {
+    let (mut from, mut to, mut longest) = (0, adjacency[0][0].0, -1.0);
+    for (node, neighbours) in adjacency.iter().enumerate() {
+        for &(other, blen) in neighbours {
+            if node < other && blen > longest {
+                (from, to, longest) = (node, other, blen);
+            }
+        }
+    }
+    ((from, longest / 2.0), (to, longest / 2.0))
+}
+
+/// How the input tree is matched to the sequences and rooted before the alignment.
+#[derive(Clone, Debug, Default)]
+pub struct TreeInputOptions {
+    /// Where an unrooted tree is rooted.
+    pub rooting: RootingMethod,
+    /// Ids of the sequences the tree is rerooted on, the tree is used as rooted if empty.
+    pub outgroup: Vec<String>,
+    /// Whether to drop the leaves without a sequence and the sequences that are not in the tree
+    /// with `prune_missing_taxa` instead of failing.
+    pub prune_missing: bool,
+    /// Rules for pairing the leaf labels with the sequence ids.
+    pub matching: NameMatching,
+}
+
+impl TreeInputOptions {
+    /// Whether the tree has to be prepared with `tree_from_newick` before it can be used, i.e.
+    /// it is rerooted, unrooted or not binary.
+    pub fn needs_preparing(&self, newick: &str) -> Result<bool> {
+        let top = parse_newick(newick)?;
+        Ok(!self.outgroup.is_empty()
+            || self.prune_missing
+            || !self.matching.is_exact()
+            || top.children.len() != 2
+            || top.children.iter().any(has_polytomy))
+    }
+}
+
+/// Splits the nodes with more than three neighbours by joining two of their children at a time
+/// under a fresh node with a zero length branch, until the node has two children left. The link
+/// towards the root, or towards the other side of the root branch, stays where it is. Returns
+/// the number of polytomies.
+fn resolve_polytomies(adjacency: &mut Adjacency, leaves: usize) -> usize {
+    let mut polytomies = 0;
+    for node in leaves..adjacency.len() {
+        if adjacency[node].len() <= 3 {
+            continue;
+        }
+        polytomies += 1;
+        // the children come first, the link towards the root is added last
+        let parent = adjacency[node].pop().unwrap();
+        let mut children: VecDeque<(usize, f64)> = adjacency[node].drain(..).collect();
+        while children.len() > 2 {
+            let (a, a_len) = children.pop_front().unwrap();
+            let (b, b_len) = children.pop_front().unwrap();
+            let new_node = adjacency.len();
+            adjacency.push(vec![(a, a_len), (b, b_len), (node, 0.0)]);
+            for child in [a, b] {
+                for link in adjacency[child].iter_mut() {
+                    if link.0 == node {
+                        link.0 = new_node;
+                    }
+                }
+            }
+            children.push_back((new_node, 0.0));
+        }
+        adjacency[node].extend(children);
+        adjacency[node].push(parent);
+    }
+    polytomies
+}
+
+/// Unrooted binary tree of the first tree of a newick string, the root of a rooted tree is
+/// removed and its two branches are joined and polytomies are resolved with zero length
+/// branches. The leaves are matched to the sequences by their ids. Also returns the branch of
+/// the original root for a rooted tree.
+fn newick_adjacency(
+    newick: &str,
+    sequences: &[Record],
+    matching: &NameMatching,
+) -> Result<(Adjacency, Option<((usize, f64), (usize, f64))>)> {
+    let top = parse_matched_newick(newick, sequences, matching)?;
+    let leaf_ids: HashMap<&str, usize> = sequences
+        .iter()
+        .enumerate()
+        .map(|(idx, record)| (record.id(), idx))
+        .collect();
+    let mut adjacency: Adjacency = vec![Vec::new(); sequences.len()];
+    let root_branch = match top.children.len() {
+        2 => {
+            let (left, right) = (&top.children[0], &top.children[1]);
+            let left_idx = add_to_adjacency(&mut adjacency, &leaf_ids, left)?;
+            let right_idx = add_to_adjacency(&mut adjacency, &leaf_ids, right)?;
+            adjacency[left_idx].push((right_idx, left.blen + right.blen));
+            adjacency[right_idx].push((left_idx, left.blen + right.blen));
+            Some(((left_idx, left.blen), (right_idx, right.blen)))
+        }
+        0 | 1 => {
+            return Err(ParsimonyError::InvalidTree(
+                "The tree must have at least two subtrees at the top level.".to_string(),
+            ))
+        }
+        _ => {
+            add_to_adjacency(&mut adjacency, &leaf_ids, &top)?;
+            None
+        }
+    };
+    if let Some(missing) = (0..sequences.len()).find(|&idx| adjacency[idx].is_empty()) {
+        return Err(ParsimonyError::TreeSequenceMismatch(format!(
+            "The sequence {} is not in the tree.",
+            sequences[missing].id()
+        )));
+    }
+    if adjacency[sequences.len()..]
+        .iter()
+        .any(|node| node.len() < 3)
+    {
+        return Err(ParsimonyError::InvalidTree(
+            "The tree has an internal node with a single child.".to_string(),
+        ));
+    }
+    let polytomies = resolve_polytomies(&mut adjacency, sequences.len());
+    if polytomies > 0 {
+        info!(
+            "Resolved {} polytomies of the tree with zero length branches.",
+            polytomies
+        );
+    }
+    Ok((adjacency, root_branch))
+}
+
+/// Removes the leaves that are not in `keep`, the internal nodes left with a single child are
+/// replaced by the child with the two branches joined.
+fn prune(node: NewickNode, keep: &HashSet<&str>) -> Option<NewickNode> {
+    if node.children.is_empty() {
+        return keep.contains(node.name.as_str()).then_some(node);
+    }
+    let mut children: Vec<NewickNode> = node
+        .children
+        .into_iter()
+        .filter_map(|child| prune(child, keep))
+        .collect();
+    match children.len() {
+        0 => None,
+        1 => {
+            let mut child = children.pop().unwrap();
+            child.blen += node.blen;
+            Some(child)
+        }
+        _ => Some(NewickNode { children, ..node }),
+    }
+}
+
+/// Restricts the first tree of a newick string and the sequences to the taxa they have in
+/// common, reporting the dropped leaves and sequences. Returns the pruned tree in newick format
+/// and the remaining sequences, the leaves are renamed to the ids of the sequences they match.
+pub fn prune_missing_taxa(
+    newick: &str,
+    sequences: &[Record],
+    matching: &NameMatching,
+) -> Result<(String, Vec<Record>)> {
+    let top = parse_matched_newick(newick, sequences, matching)?;
+    let mut leaves = Vec::new();
+    top.leaf_names(&mut leaves);
+    let ids: HashSet<&str> = sequences.iter().map(|record| record.id()).collect();
+    let tree_only: Vec<&str> = leaves
+        .iter()
+        .copied()
+        .filter(|leaf| !ids.contains(leaf))
+        .collect();
+    let leaves: HashSet<&str> = leaves.into_iter().collect();
+    let kept: Vec<Record> = sequences
+        .iter()
+        .filter(|record| leaves.contains(record.id()))
+        .cloned()
+        .collect();
+    if !tree_only.is_empty() {
+        warn!(
+            "Pruned {} leaves without a sequence from the tree: {}.",
+            tree_only.len(),
+            tree_only.join(",")
+        );
+    }
+    if kept.len() < sequences.len() {
+        let dropped: Vec<&str> = sequences
+            .iter()
+            .map(|record| record.id())
+            .filter(|id| !leaves.contains(id))
+            .collect();
+        warn!(
+            "Dropped {} sequences that are not in the tree: {}.",
+            dropped.len(),
+            dropped.join(",")
+        );
+    }
+    let common: HashSet<&str> = kept.iter().map(|record| record.id()).collect();
+    if common.len() < 2 {
+        return Err(ParsimonyError::TreeSequenceMismatch(
+            "The tree and the sequences have fewer than two taxa in common.".to_string(),
+        ));
+    }
+    let Some(top) = prune(top, &common) else {
+        return Err(ParsimonyError::TreeSequenceMismatch(
+            "The tree and the sequences have no taxa in common.".to_string(),
+        ));
+    };
+    Ok((format!("{};", top.to_newick()), kept))
+}
+
+/// Branch that separates the outgroup from the other leaves, with its length split in half.
+fn outgroup_branch(
+    adjacency: &Adjacency,
+    sequences: &[Record],
+    outgroup: &[String],
+    matching: &NameMatching,
+) -> Result<((usize, f64), (usize, f64))> {
+    let mut in_outgroup = vec![false; sequences.len()];
+    for id in outgroup {
+        let key = matching.key(id);
+        match sequences
+            .iter()
+            .position(|record| matching.sequence_key(record) == key)
+        {
+            Some(idx) => in_outgroup[idx] = true,
+            None => {
+                return Err(ParsimonyError::TreeSequenceMismatch(format!(
+                    "The outgroup taxon {} is not in the sequences.",
+                    id
+                )))
+            }
+        }
+    }
+    let outgroup_size = in_outgroup.iter().filter(|&&taxon| taxon).count();
+    let Some(start) = in_outgroup.iter().position(|&taxon| !taxon) else {
+        return Err(ParsimonyError::InvalidParameters(
+            "The outgroup cannot contain all the sequences.".to_string(),
+        ));
+    };
+    // leaves below every node when the tree hangs from a leaf that is not in the outgroup
+    let mut order = vec![(start, start)];
+    let mut visited = 0;
+    while visited < order.len() {
+        let (node, parent) = order[visited];
+        for &(next, _) in &adjacency[node] {
+            if next != parent {
+                order.push((next, node));
+            }
+        }
+        visited += 1;
+    }
+    let mut below = vec![(0, 0); adjacency.len()];
+    for &(node, parent) in order.iter().skip(1).rev() {
+        if node < sequences.len() {
+            below[node] = (1, in_outgroup[node] as usize);
+        }
+        below[parent].0 += below[node].0;
+        below[parent].1 += below[node].1;
+        if below[node] == (outgroup_size, outgroup_size) {
+            let blen = adjacency[node]
+                .iter()
+                .find(|(next, _)| *next == parent)
+                .unwrap()
+                .1;
+            return Ok(((parent, blen / 2.0), (node, blen / 2.0)));
+        }
+    }
+    Err(ParsimonyError::InvalidParameters(
+        "The outgroup taxa do not form a clade in the tree.".to_string(),
+    ))
+}
+
+/// Rooted binary tree of the first tree of a newick string. The tree is rooted on the outgroup
+/// if one is given and otherwise an unrooted tree is rooted with the rooting method, polytomies
+/// are resolved with zero length branches. The leaves are matched to the sequences with the name
+/// matching rules.
+pub fn tree_from_newick(
+    newick: &str,
+    sequences: &[Record],
+    options: &TreeInputOptions,
+) -> Result<Tree> {
+    let (adjacency, original_root) = newick_adjacency(newick, sequences, &options.matching)?;
+    let describe = |node: usize| match sequences.get(node) {
+        Some(record) => format!("leaf {}", record.id()),
+        None => "an internal node".to_string(),
+    };
+    let root_branch = if !options.outgroup.is_empty() {
+        let root_branch =
+            outgroup_branch(&adjacency, sequences, &options.outgroup, &options.matching)?;
+        info!(
+            "Rerooting the tree on the outgroup {}.",
+            options.outgroup.join(",")
+        );
+        root_branch
+    } else if let Some(root_branch) = original_root {
+        root_branch
+    } else {
+        let root_branch = match options.rooting {
+            RootingMethod::Midpoint => midpoint_branch(&adjacency, sequences.len()),
+            RootingMethod::LongestBranch => longest_branch(&adjacency),
+        };
+        info!(
+            "The tree is unrooted, rooting it at the {} on the branch between {} and {}.",
+            options.rooting,
+            describe((root_branch.0).0),
+            describe((root_branch.1).0)
+        );
+        root_branch
+    };
+    rooted_tree(sequences, &adjacency, root_branch)
+}
+
+#[cfg(test)]
+mod tree_input_tests {
+    use super::{
+        is_unrooted_newick, prune_missing_taxa, tree_from_newick, NameMatching, RootingMethod,
+        TreeInputOptions,
+    };
+    use bio::io::fasta::Record;
+    use phylo::tree::NodeIdx::Leaf as L;
+
+    fn sequences() -> Vec<Record> {
+        ["A", "B", "C", "D"]
+            .iter()
+            .map(|id| Record::with_attrs(id, None, b"ACGT"))
+            .collect()
+    }
+
+    fn rooting(rooting: RootingMethod) -> TreeInputOptions {
+        TreeInputOptions {
+            rooting,
+            ..Default::default()
+        }
+    }
+
+    fn outgroup(taxa: &[&str]) -> TreeInputOptions {
+        TreeInputOptions {
+            outgroup: taxa.iter().map(|id| id.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rooting_unrooted_trees() {
+        let newick = "(A:0.1,B:0.2,(C:0.3,D:5.0)[90]:0.4);";
+        assert!(is_unrooted_newick(newick).unwrap());
+        assert!(!is_unrooted_newick("((A:1,B:1):1,(C:1,D:1):1);").unwrap());
+        assert!(is_unrooted_newick("(A:1,B:1").is_err());
+
+        let sequences = sequences();
+        let tree =
+            tree_from_newick(newick, &sequences, &rooting(RootingMethod::LongestBranch)).unwrap();
+        let root = Into::<usize>::into(tree.root);
+        assert_eq!(tree.internals.len(), 3);
+        assert_eq!(tree.postorder.len(), 7);
+        assert!(tree.internals[root].children.contains(&L(3)));
+        assert_eq!(tree.leaves[3].blen, 2.5);
+
+        // the longest path is D to B, its midpoint is on the branch of D as well
+        let tree = tree_from_newick(newick, &sequences, &rooting(RootingMethod::Midpoint)).unwrap();
+        let root = Into::<usize>::into(tree.root);
+        assert!(tree.internals[root].children.contains(&L(3)));
+        assert!((tree.leaves[3].blen - (5.0 + 0.4 + 0.2) / 2.0).abs() < 1e-10);
+
+        assert!(tree_from_newick(newick, &sequences[..3], &Default::default()).is_err());
+        assert!(tree_from_newick(
+            "(A:1,B:1,(C:1,(D:1):1):1);",
+            &sequences,
+            &Default::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rooting_on_outgroup() {
+        let sequences = sequences();
+        let newick = "((A:1,B:1):1,(C:1,D:1):2);";
+        let options = TreeInputOptions::default();
+        assert!(!options.needs_preparing(newick).unwrap());
+        let tree = tree_from_newick(newick, &sequences, &options).unwrap();
+        let root = Into::<usize>::into(tree.root);
+        let blens: Vec<f64> = tree.internals[root]
+            .children
+            .iter()
+            .map(|child| tree.internals[Into::<usize>::into(*child)].blen)
+            .collect();
+        assert_eq!(blens, vec![1.0, 2.0]);
+
+        let options = outgroup(&["A"]);
+        assert!(options.needs_preparing(newick).unwrap());
+        let tree = tree_from_newick(newick, &sequences, &options).unwrap();
+        let root = Into::<usize>::into(tree.root);
+        assert!(tree.internals[root].children.contains(&L(0)));
+        assert_eq!(tree.leaves[0].blen, 0.5);
+
+        let tree = tree_from_newick(newick, &sequences, &outgroup(&["D", "C"])).unwrap();
+        let root = Into::<usize>::into(tree.root);
+        assert!(!tree.internals[root]
+            .children
+            .iter()
+            .any(|child| matches!(child, L(_))));
+
+        assert!(tree_from_newick(newick, &sequences, &outgroup(&["A", "C"])).is_err());
+        assert!(tree_from_newick(newick, &sequences, &outgroup(&["E"])).is_err());
+        assert!(tree_from_newick(newick, &sequences, &outgroup(&["A", "B", "C", "D"])).is_err());
+    }
+
+    #[test]
+    fn resolving_polytomies() {
+        let sequences = sequences();
+        let options = TreeInputOptions::default();
+        let newick = "((A:1,B:2,C:3):1,D:1);";
+        assert!(options.needs_preparing(newick).unwrap());
+        let tree = tree_from_newick(newick, &sequences, &options).unwrap();
+        assert_eq!(tree.internals.len(), 3);
+        assert_eq!(tree.postorder.len(), 7);
+        let root = Into::<usize>::into(tree.root);
+        assert!(tree.internals[root].children.contains(&L(3)));
+        let blens: Vec<f64> = tree.leaves.iter().map(|leaf| leaf.blen).collect();
+        assert_eq!(blens, vec![1.0, 2.0, 3.0, 1.0]);
+        let zero_branches = (0..tree.internals.len())
+            .filter(|&idx| idx != root && tree.internals[idx].blen == 0.0)
+            .count();
+        assert_eq!(zero_branches, 1);
+
+        let tree = tree_from_newick("(A:1,B:1,C:1,D:1);", &sequences, &options).unwrap();
+        assert_eq!(tree.internals.len(), 3);
+
+        let sequences: Vec<Record> = ["A", "B", "C", "D", "E", "F"]
+            .iter()
+            .map(|id| Record::with_attrs(id, None, b"ACGT"))
+            .collect();
+        let tree =
+            tree_from_newick("((A:1,B:1,C:1,D:1,E:1):1,F:1);", &sequences, &options).unwrap();
+        assert_eq!(tree.internals.len(), 5);
+        assert_eq!(tree.postorder.len(), 11);
+        let root = Into::<usize>::into(tree.root);
+        assert!(tree.internals[root].children.contains(&L(5)));
+        assert_eq!(tree.leaves[5].blen, 1.0);
+        let blens: Vec<f64> = tree.leaves.iter().map(|leaf| leaf.blen).collect();
+        assert_eq!(blens, vec![1.0; 6]);
+        let zero_branches = (0..tree.internals.len())
+            .filter(|&idx| idx != root && tree.internals[idx].blen == 0.0)
+            .count();
+        assert_eq!(zero_branches, 3);
+        assert!(!options
+            .needs_preparing("((A:1,B:1):1,(C:1,D:1):1);")
+            .unwrap());
+    }
+
+    #[test]
+    fn pruning_missing_taxa() {
+        let sequences: Vec<Record> = ["A", "C", "D", "E"]
+            .iter()
+            .map(|id| Record::with_attrs(id, None, b"ACGT"))
+            .collect();
+        let newick = "(((A:1,B:1):1,C:1):1,'D x':1,(D:1,F:2):0.5);";
+        let (pruned, kept) = prune_missing_taxa(newick, &sequences, &Default::default()).unwrap();
+        assert_eq!(pruned, "((A:2,C:1):1,D:1.5):0;");
+        let ids: Vec<&str> = kept.iter().map(|record| record.id()).collect();
+        assert_eq!(ids, vec!["A", "C", "D"]);
+        let options = TreeInputOptions {
+            prune_missing: true,
+            ..Default::default()
+        };
+        assert!(options.needs_preparing(&pruned).unwrap());
+        let tree = tree_from_newick(&pruned, &kept, &options).unwrap();
+        assert_eq!(tree.internals.len(), 2);
+
+        assert!(prune_missing_taxa("(A:1,(B:1,F:1):1);", &sequences, &Default::default()).is_err());
+    }
+
+    #[test]
+    fn matching_names() {
+        let matching = NameMatching {
+            ignore_case: true,
+            truncate_at_space: true,
+            ..Default::default()
+        }
+        .with_strip_pattern(r"^\w+\|")
+        .unwrap();
+        assert_eq!(matching.key("sp|Human_P53 tumour"), "human_p53");
+        assert!(NameMatching::default().is_exact());
+        assert!(NameMatching::default().with_strip_pattern("(").is_err());
+
+        let sequences: Vec<Record> = ["human", "mouse", "rat", "fly"]
+            .iter()
+            .map(|id| Record::with_attrs(id, Some("protein"), b"ACGT"))
+            .collect();
+        let newick = "((Human extra:1,'MOUSE':1):1,(RAT:1,fly:1):1);";
+        assert!(tree_from_newick(newick, &sequences, &Default::default()).is_err());
+        let options = TreeInputOptions {
+            matching: NameMatching {
+                ignore_case: true,
+                truncate_at_space: true,
+                ..Default::default()
+            },
+            outgroup: vec!["FLY".to_string(), "rat".to_string()],
+            ..Default::default()
+        };
+        assert!(options.needs_preparing(newick).unwrap());
+        let tree = tree_from_newick(newick, &sequences, &options).unwrap();
+        assert_eq!(tree.internals.len(), 3);
+
+        let whitespace = NameMatching {
+            ignore_whitespace: true,
+            ..Default::default()
+        };
+        let (pruned, kept) =
+            prune_missing_taxa("(humanprotein:1,mouseprotein:1);", &sequences, &whitespace)
+                .unwrap();
+        assert_eq!(pruned, "(human:1,mouse:1):0;");
+        assert_eq!(kept.len(), 2);
+
+        let duplicates = [
+            Record::with_attrs("a", None, b"A"),
+            Record::with_attrs("A", None, b"A"),
+        ];
+        let ignore_case = NameMatching {
+            ignore_case: true,
+            ..Default::default()
+        };
+        assert!(prune_missing_taxa("(a:1,A:1);", &duplicates, &ignore_case).is_err());
+    }
+}
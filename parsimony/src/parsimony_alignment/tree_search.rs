@@ -0,0 +1,383 @@
+use bio::io::fasta::Record;
+use log::info;
+use phylo::alignment::Alignment;
+use phylo::phylo_info::PhyloInfo;
+use phylo::tree::{NodeIdx, NodeIdx::Internal as Int, NodeIdx::Leaf, Tree};
+use std::collections::HashMap;
+
+use super::parsimony_costs::ParsimonyCosts;
+use super::parsimony_info::ParsimonySiteInfo;
+use super::{leaf_site_info, node_rng, pars_align_w_options, AlignmentOptions};
+use crate::{ParsimonyError, Result};
+
+/// Smallest decrease of the total score that is kept as an improvement, so that rounding errors
+/// do not make the search swap back and forth between equally good trees.
+const MIN_IMPROVEMENT: f64 = 1e-9;
+
+/// Rooted binary tree that can be rearranged by exchanging subtrees, the subtrees keep the
+/// lengths of the branches above them.
+#[derive(Clone)]
+pub(crate) struct Topology {
+    children: Vec<[NodeIdx; 2]>,
+    parents: [Vec<Option<usize>>; 2],
+    leaf_blens: Vec<f64>,
+    internal_blens: Vec<f64>,
+}
+
+impl Topology {
+    pub(crate) fn new(tree: &Tree) -> Self {
+        let mut topology = Topology {
+            children: tree
+                .internals
+                .iter()
+                .map(|node| [node.children[0], node.children[1]])
+                .collect(),
+            parents: [
+                vec![None; tree.leaves.len()],
+                vec![None; tree.internals.len()],
+            ],
+            leaf_blens: tree.leaves.iter().map(|node| node.blen).collect(),
+            internal_blens: tree.internals.iter().map(|node| node.blen).collect(),
+        };
+        for idx in 0..topology.children.len() {
+            for child in topology.children[idx] {
+                topology.set_parent(child, idx);
+            }
+        }
+        topology
+    }
+
+    fn parent(&self, node: NodeIdx) -> Option<usize> {
+        match node {
+            Leaf(idx) => self.parents[0][idx],
+            Int(idx) => self.parents[1][idx],
+        }
+    }
+
+    fn set_parent(&mut self, node: NodeIdx, parent: usize) {
+        match node {
+            Leaf(idx) => self.parents[0][idx] = Some(parent),
+            Int(idx) => self.parents[1][idx] = Some(parent),
+        }
+    }
+
+    fn blen(&self, node: NodeIdx) -> f64 {
+        match node {
+            Leaf(idx) => self.leaf_blens[idx],
+            Int(idx) => self.internal_blens[idx],
+        }
+    }
+
+    fn root(&self) -> usize {
+        (0..self.children.len())
+            .find(|&idx| self.parents[1][idx].is_none())
+            .unwrap()
+    }
+
+    fn depth(&self, mut idx: usize) -> usize {
+        let mut depth = 0;
+        while let Some(parent) = self.parents[1][idx] {
+            depth += 1;
+            idx = parent;
+        }
+        depth
+    }
+
+    /// Exchanges the places of two nodes, neither may be an ancestor of the other.
+    pub(crate) fn swap(&mut self, a: NodeIdx, b: NodeIdx) {
+        let (a_parent, b_parent) = (self.parent(a).unwrap(), self.parent(b).unwrap());
+        let a_pos = usize::from(self.children[a_parent][1] == a);
+        let b_pos = usize::from(self.children[b_parent][1] == b);
+        self.children[a_parent][a_pos] = b;
+        self.children[b_parent][b_pos] = a;
+        self.set_parent(a, b_parent);
+        self.set_parent(b, a_parent);
+    }
+
+    /// Pairs of nodes whose exchange is a nearest-neighbour interchange around the branch above
+    /// the internal node: a child of the node with the sibling of the node. The two branches
+    /// below the root form a single branch of the unrooted tree, so the interchanges of the
+    /// root exchange a child of one child of the root with a child of the other, and the
+    /// children of the root have none of their own.
+    pub(crate) fn interchanges(&self, idx: usize) -> Vec<(NodeIdx, NodeIdx)> {
+        let Some(parent) = self.parents[1][idx] else {
+            return match self.children[idx] {
+                [Int(x), Int(y)] => (0..2)
+                    .map(|which| (self.children[x][0], self.children[y][which]))
+                    .collect(),
+                _ => Vec::new(),
+            };
+        };
+        if self.parents[1][parent].is_none() {
+            return Vec::new();
+        }
+        let sibling = self.children[parent][usize::from(self.children[parent][0] == Int(idx))];
+        (0..2)
+            .map(|which| (self.children[idx][which], sibling))
+            .collect()
+    }
+
+    /// Builds the tree with the leaves in the order of the sequences.
+    pub(crate) fn build_tree(&self, sequences: &[Record]) -> Result<Tree> {
+        fn add_subtree(
+            topology: &Topology,
+            tree: &mut Tree,
+            node: NodeIdx,
+            next_internal: &mut usize,
+        ) -> NodeIdx {
+            let Int(idx) = node else {
+                return node;
+            };
+            let [x, y] = topology.children[idx]
+                .map(|child| add_subtree(topology, tree, child, next_internal));
+            let new_idx = *next_internal;
+            *next_internal += 1;
+            let [x_blen, y_blen] = topology.children[idx].map(|child| topology.blen(child));
+            tree.add_parent(new_idx, x, y, x_blen, y_blen);
+            Int(new_idx)
+        }
+        let mut tree = Tree::new(sequences)?;
+        add_subtree(self, &mut tree, Int(self.root()), &mut 0);
+        tree.complete = true;
+        tree.create_postorder();
+        Ok(tree)
+    }
+}
+
+/// Topology that is rearranged during the search, with the profiles and scores of the alignment
+/// of every internal node on it.
+struct SearchTree<'a> {
+    scoring: &'a dyn ParsimonyCosts,
+    options: &'a AlignmentOptions,
+    topology: Topology,
+    leaf_info: Vec<Vec<ParsimonySiteInfo>>,
+    internal_info: Vec<Vec<ParsimonySiteInfo>>,
+    scores: Vec<f64>,
+}
+
+impl<'a> SearchTree<'a> {
+    fn new(
+        scoring: &'a dyn ParsimonyCosts,
+        info: &PhyloInfo,
+        options: &'a AlignmentOptions,
+    ) -> Result<Self> {
+        let tree = &info.tree;
+        let sequence_type = options.data_type.sequence_type(&info.sequences);
+        let mut search = SearchTree {
+            scoring,
+            options,
+            topology: Topology::new(tree),
+            leaf_info: info
+                .sequences
+                .iter()
+                .map(|record| leaf_site_info(record, &sequence_type, options))
+                .collect(),
+            internal_info: vec![Vec::new(); tree.internals.len()],
+            scores: vec![0.0; tree.internals.len()],
+        };
+        for &node in &tree.postorder {
+            if let Int(idx) = node {
+                let (node_info, _, score) = search.align_node(idx, &HashMap::new())?;
+                search.internal_info[idx] = node_info;
+                search.scores[idx] = score;
+            }
+        }
+        Ok(search)
+    }
+
+    fn total_score(&self) -> f64 {
+        self.scores.iter().sum()
+    }
+
+    /// Aligns the profiles of the children of a node, taking the profiles in `updated` over the
+    /// stored ones.
+    fn align_node(
+        &self,
+        idx: usize,
+        updated: &HashMap<usize, Vec<ParsimonySiteInfo>>,
+    ) -> Result<(Vec<ParsimonySiteInfo>, Alignment, f64)> {
+        let profile = |child: NodeIdx| match child {
+            Leaf(child) => &self.leaf_info[child],
+            Int(child) => updated.get(&child).unwrap_or(&self.internal_info[child]),
+        };
+        let [x, y] = self.topology.children[idx];
+        pars_align_w_options(
+            profile(x),
+            self.scoring.get_branch_costs(self.topology.blen(x)),
+            profile(y),
+            self.scoring.get_branch_costs(self.topology.blen(y)),
+            node_rng(self.options, idx),
+            self.options,
+        )
+    }
+
+    /// Realigns the given nodes and all their ancestors, returns their new profiles and scores,
+    /// the deeper nodes first.
+    fn realign(&self, nodes: &[usize]) -> Result<Vec<(usize, Vec<ParsimonySiteInfo>, f64)>> {
+        let mut affected = Vec::new();
+        for &node in nodes {
+            let mut node = Some(node);
+            while let Some(idx) = node {
+                if !affected.contains(&idx) {
+                    affected.push(idx);
+                }
+                node = self.topology.parents[1][idx];
+            }
+        }
+        affected.sort_by_key(|&idx| std::cmp::Reverse(self.topology.depth(idx)));
+        let mut updated = HashMap::new();
+        let mut realigned = Vec::new();
+        for idx in affected {
+            let (node_info, _, score) = self.align_node(idx, &updated)?;
+            updated.insert(idx, node_info.clone());
+            realigned.push((idx, node_info, score));
+        }
+        Ok(realigned)
+    }
+
+    /// Exchanges two nodes if that lowers the total score.
+    fn try_swap(&mut self, (a, b): (NodeIdx, NodeIdx)) -> Result<bool> {
+        self.topology.swap(a, b);
+        let realigned = self.realign(&[
+            self.topology.parent(a).unwrap(),
+            self.topology.parent(b).unwrap(),
+        ])?;
+        let change: f64 = realigned
+            .iter()
+            .map(|(idx, _, score)| score - self.scores[*idx])
+            .sum();
+        if change < -MIN_IMPROVEMENT {
+            for (idx, node_info, score) in realigned {
+                self.internal_info[idx] = node_info;
+                self.scores[idx] = score;
+            }
+            return Ok(true);
+        }
+        self.topology.swap(a, b);
+        Ok(false)
+    }
+
+    /// Tries the interchanges around the branch above the node and keeps the first one that
+    /// lowers the total score.
+    fn try_interchanges(&mut self, idx: usize) -> Result<bool> {
+        for pair in self.topology.interchanges(idx) {
+            if self.try_swap(pair)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Searches for a tree with a lower indel-aware parsimony score by nearest-neighbour
+/// interchanges. Every round tries to exchange a child of every internal node with the sibling
+/// of the node, or at the root a child of one of its children with a child of the other, and
+/// keeps the interchanges that lower the total score of the progressive alignment on the tree.
+/// Only the nodes above the exchanged subtrees are realigned for an interchange, the subtrees
+/// keep their branch lengths. The search stops after `max_rounds` rounds or when a round keeps
+/// no interchange. Returns the improved tree and its score.
+pub fn pars_nni_search(
+    scoring: &dyn ParsimonyCosts,
+    info: &PhyloInfo,
+    max_rounds: usize,
+    options: &AlignmentOptions,
+) -> Result<(Tree, f64)> {
+    if info.tree.internals.len() < 2 {
+        return Err(ParsimonyError::TooFewSequences(
+            "The tree search needs at least three sequences.".to_string(),
+        ));
+    }
+    let mut search = SearchTree::new(scoring, info, options)?;
+    info!(
+        "Starting the tree search from the tree with score {}.",
+        search.total_score()
+    );
+    for round in 0..max_rounds {
+        let mut interchanges = 0;
+        for idx in 0..search.topology.children.len() {
+            if search.try_interchanges(idx)? {
+                interchanges += 1;
+            }
+        }
+        info!(
+            "Tree search round {} kept {} interchange(s), score {}.",
+            round + 1,
+            interchanges,
+            search.total_score()
+        );
+        if interchanges == 0 {
+            break;
+        }
+    }
+    Ok((
+        search.topology.build_tree(&info.sequences)?,
+        search.total_score(),
+    ))
+}
+
+#[cfg(test)]
+mod tree_search_tests {
+    use super::pars_nni_search;
+    use crate::parsimony_alignment::parsimony_costs::parsimony_costs_simple::ParsimonyCostsSimple;
+    use crate::parsimony_alignment::{pars_align_on_tree_w_options, AlignmentOptions};
+    use approx::assert_relative_eq;
+    use bio::io::fasta::Record;
+    use phylo::phylo_info::phyloinfo_from_sequences_tree;
+    use phylo::tree::{NodeIdx::Internal as I, NodeIdx::Leaf as L, Tree};
+
+    fn tree(sequences: &[Record]) -> Tree {
+        let mut tree = Tree::new(sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.add_parent(1, L(2), L(3), 1.0, 1.0);
+        tree.add_parent(2, I(0), I(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        tree
+    }
+
+    #[test]
+    fn nni_groups_similar_sequences() {
+        // the starting tree pairs each sequence with the dissimilar one
+        let sequences = [
+            Record::with_attrs("A1", None, b"AAAAAAAA"),
+            Record::with_attrs("B1", None, b"CCGGTTCC"),
+            Record::with_attrs("A2", None, b"AAAAAAAA"),
+            Record::with_attrs("B2", None, b"CCGGTTCC"),
+        ];
+        let info = phyloinfo_from_sequences_tree(&sequences, tree(&sequences)).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        let options = AlignmentOptions::default();
+        let start = pars_align_on_tree_w_options(&scoring, &info, &options).unwrap();
+        let (searched, score) = pars_nni_search(&scoring, &info, 5, &options).unwrap();
+        assert!(score < start.score());
+
+        let searched_info = phyloinfo_from_sequences_tree(&sequences, searched).unwrap();
+        let result = pars_align_on_tree_w_options(&scoring, &searched_info, &options).unwrap();
+        assert_relative_eq!(result.score(), score);
+        let tree = &searched_info.tree;
+        let cherries: Vec<[usize; 2]> = tree
+            .internals
+            .iter()
+            .filter_map(|node| match node.children[..] {
+                [L(x), L(y)] => Some([x.min(y), x.max(y)]),
+                _ => None,
+            })
+            .collect();
+        assert!(cherries.contains(&[0, 2]) || cherries.contains(&[1, 3]));
+    }
+
+    #[test]
+    fn nni_too_few_sequences() {
+        let sequences = [
+            Record::with_attrs("A", None, b"AAAA"),
+            Record::with_attrs("B", None, b"AAAA"),
+        ];
+        let mut tree = Tree::new(&sequences).unwrap();
+        tree.add_parent(0, L(0), L(1), 1.0, 1.0);
+        tree.complete = true;
+        tree.create_postorder();
+        let info = phyloinfo_from_sequences_tree(&sequences, tree).unwrap();
+        let scoring = ParsimonyCostsSimple::new(1.0, 2.0, 0.5);
+        assert!(pars_nni_search(&scoring, &info, 5, &AlignmentOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,60 @@
+use bio::io::fasta::Record;
+
+use super::msa_scoring::GAP;
+
+/// Removes the columns of the MSA in which more than `max_gap_fraction` of the rows have a gap.
+/// Returns the trimmed MSA and, for every column of it, the index of that column in the
+/// original MSA.
+pub fn trim_gappy_columns(msa: &[Record], max_gap_fraction: f64) -> (Vec<Record>, Vec<usize>) {
+    let columns = msa.first().map_or(0, |record| record.seq().len());
+    let kept: Vec<usize> = (0..columns)
+        .filter(|&column| {
+            let gaps = msa
+                .iter()
+                .filter(|record| record.seq().get(column) == Some(&GAP))
+                .count();
+            gaps as f64 <= max_gap_fraction * msa.len() as f64
+        })
+        .collect();
+    let trimmed = msa
+        .iter()
+        .map(|record| {
+            let row: Vec<u8> = kept.iter().map(|&column| record.seq()[column]).collect();
+            Record::with_attrs(record.id(), record.desc(), &row)
+        })
+        .collect();
+    (trimmed, kept)
+}
+
+#[cfg(test)]
+mod trimming_tests {
+    use super::trim_gappy_columns;
+    use bio::io::fasta::Record;
+
+    fn msa() -> Vec<Record> {
+        vec![
+            Record::with_attrs("A", None, b"A-CT-"),
+            Record::with_attrs("B", None, b"A-C--"),
+            Record::with_attrs("C", None, b"AGC--"),
+            Record::with_attrs("D", None, b"A-CTG"),
+        ]
+    }
+
+    #[test]
+    fn trim_gappy_columns_above_fraction() {
+        let (trimmed, kept) = trim_gappy_columns(&msa(), 0.5);
+        assert_eq!(kept, vec![0, 2, 3]);
+        let rows: Vec<&[u8]> = trimmed.iter().map(|record| record.seq()).collect();
+        assert_eq!(rows, vec![&b"ACT"[..], b"AC-", b"AC-", b"ACT"]);
+        assert_eq!(trimmed[2].id(), "C");
+    }
+
+    #[test]
+    fn trim_nothing() {
+        let (trimmed, kept) = trim_gappy_columns(&msa(), 1.0);
+        assert_eq!(kept, vec![0, 1, 2, 3, 4]);
+        for (record, trimmed) in msa().iter().zip(trimmed.iter()) {
+            assert_eq!(record.seq(), trimmed.seq());
+        }
+    }
+}
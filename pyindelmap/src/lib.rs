@@ -0,0 +1,174 @@
+//! Python bindings of the IndelMaP alignment. Sequences and MSAs are passed as lists of
+//! `(id, sequence)` pairs and trees as newick strings, scores are returned as numpy arrays.
+
+use bio::io::fasta::Record;
+use numpy::{IntoPyArray, PyArray1};
+use parsimony::parsimony_alignment::aligner::IndelMapAligner;
+use parsimony::parsimony_alignment::msa_scoring::{
+    pars_ancestors_msa, pars_column_scores, pars_score_msa,
+};
+use parsimony::parsimony_alignment::parsimony_costs::parsimony_costs_model::GapMultipliers;
+use parsimony::parsimony_alignment::tree_input::{tree_from_newick, TreeInputOptions};
+use phylo::phylo_info::{phyloinfo_from_sequences_tree, PhyloInfo};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+type Rows = Vec<(String, String)>;
+
+fn value_error(error: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(error.to_string())
+}
+
+fn records(rows: &[(String, String)]) -> Vec<Record> {
+    rows.iter()
+        .map(|(id, seq)| Record::with_attrs(id, None, seq.as_bytes()))
+        .collect()
+}
+
+fn rows(records: &[Record]) -> Rows {
+    records
+        .iter()
+        .map(|record| {
+            (
+                record.id().to_string(),
+                String::from_utf8_lossy(record.seq()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+fn phylo_info(rows: &[(String, String)], newick: &str) -> PyResult<PhyloInfo> {
+    let sequences = records(rows);
+    let tree =
+        tree_from_newick(newick, &sequences, &TreeInputOptions::default()).map_err(value_error)?;
+    phyloinfo_from_sequences_tree(&sequences, tree).map_err(value_error)
+}
+
+/// Substitution model and gap multipliers of the scoring. The cost matrices are created for
+/// `categories` branch length categories of the tree, 0 uses every distinct branch length.
+#[pyclass]
+#[derive(Clone)]
+struct Model {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    params: Vec<f64>,
+    #[pyo3(get)]
+    gap_open: f64,
+    #[pyo3(get)]
+    gap_ext: f64,
+    #[pyo3(get)]
+    categories: u32,
+}
+
+#[pymethods]
+impl Model {
+    #[new]
+    #[pyo3(signature = (name, params = Vec::new(), gap_open = 2.5, gap_ext = 0.5, categories = 4))]
+    fn new(name: String, params: Vec<f64>, gap_open: f64, gap_ext: f64, categories: u32) -> Self {
+        Model {
+            name,
+            params,
+            gap_open,
+            gap_ext,
+            categories,
+        }
+    }
+}
+
+impl Model {
+    fn aligner(&self, seed: Option<u64>, threads: usize) -> PyResult<IndelMapAligner> {
+        let mut builder = IndelMapAligner::builder()
+            .model(&self.name, self.params.clone())
+            .gap_multipliers(GapMultipliers::new(self.gap_open, self.gap_ext))
+            .categories(self.categories)
+            .threads(threads);
+        if let Some(seed) = seed {
+            builder = builder.seed(seed);
+        }
+        builder.build().map_err(value_error)
+    }
+}
+
+/// Aligns the sequences on the tree, returns the MSA and the scores of the internal nodes.
+#[pyfunction]
+#[pyo3(signature = (sequences, newick, model, seed = None, threads = 1))]
+fn align<'py>(
+    py: Python<'py>,
+    sequences: Rows,
+    newick: &str,
+    model: Model,
+    seed: Option<u64>,
+    threads: usize,
+) -> PyResult<(Rows, &'py PyArray1<f64>)> {
+    let info = phylo_info(&sequences, newick)?;
+    let aligner = model.aligner(seed, threads)?;
+    let result = py
+        .allow_threads(|| aligner.align(&info))
+        .map_err(value_error)?;
+    Ok((rows(&result.msa(&info)), result.scores.into_pyarray(py)))
+}
+
+/// Aligns two sequences that are `distance` apart, returns both aligned sequences and the score.
+#[pyfunction]
+#[pyo3(signature = (seq_x, seq_y, model, distance = 1.0, seed = None))]
+fn align_pair(
+    seq_x: &str,
+    seq_y: &str,
+    model: Model,
+    distance: f64,
+    seed: Option<u64>,
+) -> PyResult<(String, String, f64)> {
+    let sequences = vec![
+        ("x".to_string(), seq_x.to_string()),
+        ("y".to_string(), seq_y.to_string()),
+    ];
+    let newick = format!("(x:{},y:{});", distance / 2.0, distance / 2.0);
+    let info = phylo_info(&sequences, &newick)?;
+    let result = model.aligner(seed, 1)?.align(&info).map_err(value_error)?;
+    let mut msa = rows(&result.msa(&info)).into_iter();
+    let (_, aligned_x) = msa.next().unwrap();
+    let (_, aligned_y) = msa.next().unwrap();
+    Ok((aligned_x, aligned_y, result.score()))
+}
+
+/// Scores an existing MSA on the tree, returns the scores of the internal nodes and of the
+/// columns.
+#[pyfunction]
+fn score_msa<'py>(
+    py: Python<'py>,
+    msa: Rows,
+    newick: &str,
+    model: Model,
+) -> PyResult<(&'py PyArray1<f64>, &'py PyArray1<f64>)> {
+    let info = phylo_info(&msa, newick)?;
+    let aligner = model.aligner(None, 1)?;
+    let scoring = aligner.scoring(&info).map_err(value_error)?;
+    let (_, node_scores) =
+        pars_score_msa(scoring.as_ref(), &info, aligner.options()).map_err(value_error)?;
+    let column_scores =
+        pars_column_scores(scoring.as_ref(), &info, aligner.options()).map_err(value_error)?;
+    Ok((node_scores.into_pyarray(py), column_scores.into_pyarray(py)))
+}
+
+/// Reconstructs the ancestral sequences of the internal nodes of the tree for an existing MSA,
+/// aligned to the MSA.
+#[pyfunction]
+fn ancestors(msa: Rows, newick: &str, model: Model) -> PyResult<Rows> {
+    let info = phylo_info(&msa, newick)?;
+    let aligner = model.aligner(None, 1)?;
+    let scoring = aligner.scoring(&info).map_err(value_error)?;
+    let ancestors =
+        pars_ancestors_msa(scoring.as_ref(), &info, aligner.options()).map_err(value_error)?;
+    Ok(rows(&ancestors))
+}
+
+#[pymodule]
+fn pyindelmap(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Model>()?;
+    m.add_function(wrap_pyfunction!(align, m)?)?;
+    m.add_function(wrap_pyfunction!(align_pair, m)?)?;
+    m.add_function(wrap_pyfunction!(score_msa, m)?)?;
+    m.add_function(wrap_pyfunction!(ancestors, m)?)?;
+    Ok(())
+}
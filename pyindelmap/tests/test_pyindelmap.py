@@ -0,0 +1,31 @@
+import pyindelmap
+
+MODEL = pyindelmap.Model("WAG", gap_open=2.5, gap_ext=0.5)
+SEQUENCES = [("A", "MKVLAAG"), ("B", "MKLAG"), ("C", "MRVLAAGW")]
+NEWICK = "((A:0.1,B:0.2):0.1,C:0.3);"
+
+
+def test_align_round_trip():
+    msa, node_scores = pyindelmap.align(SEQUENCES, NEWICK, MODEL, seed=1)
+
+    assert [row_id for row_id, _ in msa] == [seq_id for seq_id, _ in SEQUENCES]
+    assert len({len(row) for _, row in msa}) == 1
+    assert [row.replace("-", "") for _, row in msa] == [seq for _, seq in SEQUENCES]
+    assert len(node_scores) == 2
+
+    # scoring the MSA on the same tree follows the alignments it was built from
+    msa_scores, column_scores = pyindelmap.score_msa(msa, NEWICK, MODEL)
+    assert all(abs(a - b) < 1e-9 for a, b in zip(msa_scores, node_scores))
+    assert len(column_scores) == len(msa[0][1])
+    assert abs(column_scores.sum() - node_scores.sum()) < 1e-9
+
+    ancestors = pyindelmap.ancestors(msa, NEWICK, MODEL)
+    assert len(ancestors) == 2
+    assert all(len(row) == len(msa[0][1]) for _, row in ancestors)
+
+
+def test_align_is_reproducible():
+    first, first_scores = pyindelmap.align(SEQUENCES, NEWICK, MODEL, seed=7)
+    second, second_scores = pyindelmap.align(SEQUENCES, NEWICK, MODEL, seed=7, threads=2)
+    assert first == second
+    assert first_scores.tolist() == second_scores.tolist()